@@ -46,7 +46,9 @@ fn ossl_bindings(header: &str, args: &[&str]) {
         .allowlist_item("EVP_.*")
         .allowlist_item("evp_.*")
         .allowlist_item("BN_.*")
+        .allowlist_item("EC_.*")
         .allowlist_item("LN_aes.*")
+        .allowlist_item("LN_chacha20.*")
         .generate()
         .expect("Unable to generate bindings")
         .write_to_file("src/ossl/bindings.rs")
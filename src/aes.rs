@@ -11,6 +11,7 @@ use crate::object::*;
 use crate::ossl::aes::*;
 use crate::{attr_element, cast_params};
 
+use constant_time_eq::constant_time_eq;
 use once_cell::sync::Lazy;
 
 use zeroize::Zeroize;
@@ -19,6 +20,8 @@ pub const MIN_AES_SIZE_BYTES: usize = 16; /* 128 bits */
 pub const MID_AES_SIZE_BYTES: usize = 24; /* 192 bits */
 pub const MAX_AES_SIZE_BYTES: usize = 32; /* 256 bits */
 pub const AES_BLOCK_SIZE: usize = 16;
+pub const MIN_AES_XTS_SIZE_BYTES: usize = 32; /* 2 x 128 bits */
+pub const MAX_AES_XTS_SIZE_BYTES: usize = 64; /* 2 x 256 bits */
 
 pub(crate) fn check_key_len(len: usize) -> Result<()> {
     match len {
@@ -27,15 +30,44 @@ pub(crate) fn check_key_len(len: usize) -> Result<()> {
     }
 }
 
+pub(crate) fn check_xts_key_len(len: usize) -> Result<()> {
+    match len {
+        32 | 64 => Ok(()),
+        _ => Err(CKR_KEY_SIZE_RANGE)?,
+    }
+}
+
+/* XTS keys are the concatenation of a data key and a tweak key,
+ * FIPS 140-3 IG C.I requires that the two halves are not identical */
+pub(crate) fn check_xts_key(key: &[u8]) -> Result<()> {
+    check_xts_key_len(key.len())?;
+    let (key1, key2) = key.split_at(key.len() / 2);
+    if constant_time_eq(key1, key2) {
+        Err(CKR_ATTRIBUTE_VALUE_INVALID)?;
+    }
+    Ok(())
+}
+
+const AES_KEY_SIZES: [usize; 3] =
+    [MIN_AES_SIZE_BYTES, MID_AES_SIZE_BYTES, MAX_AES_SIZE_BYTES];
+const AES_XTS_KEY_SIZES: [usize; 2] =
+    [MIN_AES_XTS_SIZE_BYTES, MAX_AES_XTS_SIZE_BYTES];
+
+/* Serves both plain AES and AES-XTS keys, the latter are made of two AES
+ * keys of the same size */
 #[derive(Debug)]
 pub struct AesKeyFactory {
     attributes: Vec<ObjectAttr>,
+    key_type: CK_KEY_TYPE,
+    sizes: &'static [usize],
 }
 
 impl AesKeyFactory {
-    fn new() -> AesKeyFactory {
+    fn new(key_type: CK_KEY_TYPE, sizes: &'static [usize]) -> AesKeyFactory {
         let mut data: AesKeyFactory = AesKeyFactory {
             attributes: Vec::new(),
+            key_type,
+            sizes,
         };
         data.attributes.append(&mut data.init_common_object_attrs());
         data.attributes
@@ -64,13 +96,28 @@ impl AesKeyFactory {
 
         data
     }
+
+    fn check_len(&self, len: usize) -> Result<()> {
+        if self.sizes.contains(&len) {
+            Ok(())
+        } else {
+            Err(CKR_KEY_SIZE_RANGE)?
+        }
+    }
+
+    fn check_key(&self, key: &[u8]) -> Result<()> {
+        match self.key_type {
+            CKK_AES_XTS => check_xts_key(key),
+            _ => self.check_len(key.len()),
+        }
+    }
 }
 
 impl ObjectFactory for AesKeyFactory {
     fn create(&self, template: &[CK_ATTRIBUTE]) -> Result<Object> {
         let mut obj = self.default_object_create(template)?;
         let len = self.get_key_buffer_len(&obj)?;
-        check_key_len(len)?;
+        self.check_key(obj.get_attr_as_bytes(CKA_VALUE)?)?;
         if !obj.check_or_set_attr(Attribute::from_ulong(
             CKA_VALUE_LEN,
             CK_ULONG::try_from(len)?,
@@ -94,27 +141,24 @@ impl ObjectFactory for AesKeyFactory {
         mut data: Vec<u8>,
         template: &[CK_ATTRIBUTE],
     ) -> Result<Object> {
-        /* AES keys can only be 16, 24, 32 bytes long,
-         * ensure we allow only these sizes */
-        match template.iter().position(|x| x.type_ == CKA_VALUE_LEN) {
-            Some(idx) => {
-                let len = usize::try_from(template[idx].to_ulong()?)?;
-                if len > data.len() {
-                    data.zeroize();
-                    return Err(CKR_KEY_SIZE_RANGE)?;
-                }
-                if len < data.len() {
-                    unsafe { data.set_len(len) };
-                }
-            }
-            None => (),
-        }
-        match check_key_len(data.len()) {
-            Ok(_) => (),
-            Err(e) => {
+        /* padded wrapping modes may return more data than the key,
+         * ensure we allow only the sizes valid for the key type */
+        if let Some(idx) =
+            template.iter().position(|x| x.type_ == CKA_VALUE_LEN)
+        {
+            let len = usize::try_from(template[idx].to_ulong()?)?;
+            if len > data.len() {
                 data.zeroize();
-                return Err(e);
+                return Err(CKR_KEY_SIZE_RANGE)?;
             }
+            if len < data.len() {
+                data[len..].zeroize();
+                data.truncate(len);
+            }
+        }
+        if let Err(e) = self.check_len(data.len()) {
+            data.zeroize();
+            return Err(e);
         }
         SecretKeyFactory::import_from_wrapped(self, data, template)
     }
@@ -127,10 +171,8 @@ impl ObjectFactory for AesKeyFactory {
         let obj = self.internal_object_derive(template, origin)?;
 
         let key_len = self.get_key_len(&obj);
-        if key_len != 0 {
-            if check_key_len(key_len).is_err() {
-                return Err(CKR_TEMPLATE_INCONSISTENT)?;
-            }
+        if key_len != 0 && self.check_len(key_len).is_err() {
+            return Err(CKR_TEMPLATE_INCONSISTENT)?;
         }
         Ok(obj)
     }
@@ -150,29 +192,60 @@ impl SecretKeyFactory for AesKeyFactory {
         ObjectFactory::default_object_unwrap(self, template)
     }
 
-    fn set_key(&self, obj: &mut Object, key: Vec<u8>) -> Result<()> {
+    fn set_key(&self, obj: &mut Object, mut key: Vec<u8>) -> Result<()> {
+        if let Err(e) = self.check_key(&key) {
+            key.zeroize();
+            return Err(e);
+        }
         let keylen = key.len();
-        check_key_len(keylen)?;
         obj.set_attr(Attribute::from_bytes(CKA_VALUE, key))?;
         self.set_key_len(obj, keylen)?;
         Ok(())
     }
 
+    /* pkcs11-spec-v3.1 6.10.2: the first three bytes of the ECB
+     * encryption of a block of zeros, XTS keys use their data key */
+    fn check_value(&self, obj: &Object) -> Result<Vec<u8>> {
+        let mech = CK_MECHANISM {
+            mechanism: CKM_AES_ECB,
+            pParameter: std::ptr::null_mut(),
+            ulParameterLen: 0,
+        };
+        let mut op = match self.key_type {
+            CKK_AES_XTS => {
+                let value = obj.get_attr_as_bytes(CKA_VALUE)?;
+                let mut key = Object::new();
+                key.set_attr(Attribute::from_ulong(CKA_KEY_TYPE, CKK_AES))?;
+                key.set_attr(Attribute::from_bytes(
+                    CKA_VALUE,
+                    value[..(value.len() / 2)].to_vec(),
+                ))?;
+                key.set_zeroize();
+                AesOperation::encrypt_new(&mech, &key)?
+            }
+            _ => AesOperation::encrypt_new(&mech, obj)?,
+        };
+        let mut block = [0u8; AES_BLOCK_SIZE];
+        let outlen = op.encrypt(&[0u8; AES_BLOCK_SIZE], &mut block)?;
+        if outlen != AES_BLOCK_SIZE {
+            return Err(CKR_GENERAL_ERROR)?;
+        }
+        Ok(block[..CHECK_VALUE_LEN].to_vec())
+    }
+
     fn recommend_key_size(&self, max: usize) -> Result<usize> {
-        if max >= MAX_AES_SIZE_BYTES {
-            Ok(MAX_AES_SIZE_BYTES)
-        } else if max > MID_AES_SIZE_BYTES {
-            Ok(MID_AES_SIZE_BYTES)
-        } else if max > MIN_AES_SIZE_BYTES {
-            Ok(MIN_AES_SIZE_BYTES)
-        } else {
-            Err(CKR_KEY_SIZE_RANGE)?
+        match self.sizes.iter().rev().find(|s| **s <= max) {
+            Some(s) => Ok(*s),
+            None => Err(CKR_KEY_SIZE_RANGE)?,
         }
     }
 }
 
 static AES_KEY_FACTORY: Lazy<Box<dyn ObjectFactory>> =
-    Lazy::new(|| Box::new(AesKeyFactory::new()));
+    Lazy::new(|| Box::new(AesKeyFactory::new(CKK_AES, &AES_KEY_SIZES)));
+
+static AES_XTS_KEY_FACTORY: Lazy<Box<dyn ObjectFactory>> =
+    Lazy::new(|| Box::new(AesKeyFactory::new(CKK_AES_XTS, &AES_XTS_KEY_SIZES)));
 
 #[derive(Debug)]
 pub(crate) struct AesMechanism {
@@ -204,7 +277,11 @@ impl Mechanism for AesMechanism {
         if self.info.flags & CKF_ENCRYPT != CKF_ENCRYPT {
             return Err(CKR_MECHANISM_INVALID)?;
         }
-        match key.check_key_ops(CKO_SECRET_KEY, CKK_AES, CKA_ENCRYPT) {
+        let key_type = match mech.mechanism {
+            CKM_AES_XTS => CKK_AES_XTS,
+            _ => CKK_AES,
+        };
+        match key.check_key_ops(CKO_SECRET_KEY, key_type, CKA_ENCRYPT) {
             Ok(_) => (),
             Err(e) => return Err(e),
         }
@@ -219,7 +296,11 @@ impl Mechanism for AesMechanism {
         if self.info.flags & CKF_DECRYPT != CKF_DECRYPT {
             return Err(CKR_MECHANISM_INVALID)?;
         }
-        match key.check_key_ops(CKO_SECRET_KEY, CKK_AES, CKA_DECRYPT) {
+        let key_type = match mech.mechanism {
+            CKM_AES_XTS => CKK_AES_XTS,
+            _ => CKK_AES,
+        };
+        match key.check_key_ops(CKO_SECRET_KEY, key_type, CKA_DECRYPT) {
             Ok(_) => (),
             Err(e) => return Err(e),
         }
@@ -233,10 +314,12 @@ impl Mechanism for AesMechanism {
         _: &Mechanisms,
         _: &ObjectFactories,
     ) -> Result<Object> {
-        if mech.mechanism != CKM_AES_KEY_GEN {
-            return Err(CKR_MECHANISM_INVALID)?;
-        }
-        let mut key = AES_KEY_FACTORY.default_object_generate(template)?;
+        let (factory, key_type) = match mech.mechanism {
+            CKM_AES_KEY_GEN => (&AES_KEY_FACTORY, CKK_AES),
+            CKM_AES_XTS_KEY_GEN => (&AES_XTS_KEY_FACTORY, CKK_AES_XTS),
+            _ => return Err(CKR_MECHANISM_INVALID)?,
+        };
+        let mut key = factory.default_object_generate(template)?;
         if !key.check_or_set_attr(Attribute::from_ulong(
             CKA_CLASS,
             CKO_SECRET_KEY,
@@ -244,12 +327,26 @@ impl Mechanism for AesMechanism {
             return Err(CKR_TEMPLATE_INCONSISTENT)?;
         }
         if !key
-            .check_or_set_attr(Attribute::from_ulong(CKA_KEY_TYPE, CKK_AES))?
+            .check_or_set_attr(Attribute::from_ulong(CKA_KEY_TYPE, key_type))?
         {
             return Err(CKR_TEMPLATE_INCONSISTENT)?;
         }
 
+        if key_type == CKK_AES_XTS {
+            let len = usize::try_from(key.get_attr_as_ulong(CKA_VALUE_LEN)?)?;
+            if check_xts_key_len(len).is_err() {
+                return Err(CKR_TEMPLATE_INCONSISTENT)?;
+            }
+        }
+
         default_secret_key_generate(&mut key)?;
+
+        /* two identical halves can only come from a broken RNG */
+        if key_type == CKK_AES_XTS
+            && check_xts_key(key.get_attr_as_bytes(CKA_VALUE)?).is_err()
+        {
+            return Err(CKR_FUNCTION_FAILED)?;
+        }
         default_key_attributes(&mut key, mech.mechanism)?;
         Ok(key)
     }
@@ -289,6 +386,45 @@ impl Mechanism for AesMechanism {
         key_template.import_from_wrapped(keydata, template)
     }
 
+    fn wrap_key_authenticated(
+        &self,
+        mech: &CK_MECHANISM,
+        wrapping_key: &Object,
+        key: &Object,
+        aad: &[u8],
+        data: &mut [u8],
+        key_template: &dyn ObjectFactory,
+    ) -> Result<usize> {
+        if self.info.flags & CKF_WRAP != CKF_WRAP {
+            return Err(CKR_MECHANISM_INVALID)?;
+        }
+
+        AesOperation::wrap_authenticated(
+            mech,
+            wrapping_key,
+            key_template.export_for_wrapping(key)?,
+            aad,
+            data,
+        )
+    }
+
+    fn unwrap_key_authenticated(
+        &self,
+        mech: &CK_MECHANISM,
+        wrapping_key: &Object,
+        data: &[u8],
+        aad: &[u8],
+        template: &[CK_ATTRIBUTE],
+        key_template: &dyn ObjectFactory,
+    ) -> Result<Object> {
+        if self.info.flags & CKF_UNWRAP != CKF_UNWRAP {
+            return Err(CKR_MECHANISM_INVALID)?;
+        }
+        let keydata =
+            AesOperation::unwrap_authenticated(mech, wrapping_key, data, aad)?;
+        key_template.import_from_wrapped(keydata, template)
+    }
+
     fn derive_operation(&self, mech: &CK_MECHANISM) -> Result<Operation> {
         if self.info.flags & CKF_DERIVE != CKF_DERIVE {
             return Err(CKR_MECHANISM_INVALID)?;
@@ -322,7 +458,7 @@ impl Mechanism for AesMechanism {
         }
         match mech.mechanism {
             CKM_AES_CMAC | CKM_AES_CMAC_GENERAL => {
-                Ok(Box::new(AesCmacOperation::init(mech, key)?))
+                Ok(Box::new(AesEvpMacOperation::cmac_init(mech, key)?))
             }
             _ => Err(CKR_MECHANISM_INVALID)?,
         }
@@ -346,7 +482,10 @@ impl Mechanism for AesMechanism {
                 Ok(Box::new(AesMacOperation::init(mech, key)?))
             }
             CKM_AES_CMAC | CKM_AES_CMAC_GENERAL => {
-                Ok(Box::new(AesCmacOperation::init(mech, key)?))
+                Ok(Box::new(AesEvpMacOperation::cmac_init(mech, key)?))
+            }
+            CKM_AES_GMAC => {
+                Ok(Box::new(AesEvpMacOperation::gmac_init(mech, key)?))
             }
             _ => Err(CKR_MECHANISM_INVALID)?,
         }
@@ -370,7 +509,10 @@ impl Mechanism for AesMechanism {
                 Ok(Box::new(AesMacOperation::init(mech, key)?))
             }
             CKM_AES_CMAC | CKM_AES_CMAC_GENERAL => {
-                Ok(Box::new(AesCmacOperation::init(mech, key)?))
+                Ok(Box::new(AesEvpMacOperation::cmac_init(mech, key)?))
+            }
+            CKM_AES_GMAC => {
+                Ok(Box::new(AesEvpMacOperation::gmac_init(mech, key)?))
             }
             _ => Err(CKR_MECHANISM_INVALID)?,
         }
@@ -553,7 +695,11 @@ pub fn register(mechs: &mut Mechanisms, ot: &mut ObjectFactories) {
     AesKDFOperation::register_mechanisms(mechs);
     #[cfg(not(feature = "fips"))]
     AesMacOperation::register_mechanisms(mechs);
-    AesCmacOperation::register_mechanisms(mechs);
+    AesEvpMacOperation::register_mechanisms(mechs);
 
     ot.add_factory(ObjectType::new(CKO_SECRET_KEY, CKK_AES), &AES_KEY_FACTORY);
+    ot.add_factory(
+        ObjectType::new(CKO_SECRET_KEY, CKK_AES_XTS),
+        &AES_XTS_KEY_FACTORY,
+    );
 }
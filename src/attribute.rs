@@ -16,6 +16,7 @@ pub enum AttrType {
     StringType,
     BytesType,
     DateType,
+    ArrayType,
     DenyType,
     IgnoreType,
 }
@@ -37,7 +38,7 @@ macro_rules! attrmap_element {
     };
 }
 
-static ATTRMAP: [Attrmap<'_>; 147] = [
+static ATTRMAP: [Attrmap<'_>; 153] = [
     attrmap_element!(CKA_CLASS; as NumType),
     attrmap_element!(CKA_TOKEN; as BoolType),
     attrmap_element!(CKA_PRIVATE; as BoolType),
@@ -59,7 +60,7 @@ static ATTRMAP: [Attrmap<'_>; 147] = [
     attrmap_element!(CKA_HASH_OF_SUBJECT_PUBLIC_KEY; as BytesType),
     attrmap_element!(CKA_HASH_OF_ISSUER_PUBLIC_KEY; as BytesType),
     attrmap_element!(CKA_NAME_HASH_ALGORITHM; as NumType),
-    attrmap_element!(CKA_CHECK_VALUE; as IgnoreType),
+    attrmap_element!(CKA_CHECK_VALUE; as BytesType),
     attrmap_element!(CKA_KEY_TYPE; as NumType),
     attrmap_element!(CKA_SUBJECT; as BytesType),
     attrmap_element!(CKA_ID; as BytesType),
@@ -104,9 +105,9 @@ static ATTRMAP: [Attrmap<'_>; 147] = [
     attrmap_element!(CKA_EC_POINT; as BytesType),
     attrmap_element!(CKA_ALWAYS_AUTHENTICATE; as BoolType),
     attrmap_element!(CKA_WRAP_WITH_TRUSTED; as BoolType),
-    attrmap_element!(CKA_WRAP_TEMPLATE; as BytesType),
-    attrmap_element!(CKA_UNWRAP_TEMPLATE; as BytesType),
-    attrmap_element!(CKA_DERIVE_TEMPLATE; as BytesType),
+    attrmap_element!(CKA_WRAP_TEMPLATE; as ArrayType),
+    attrmap_element!(CKA_UNWRAP_TEMPLATE; as ArrayType),
+    attrmap_element!(CKA_DERIVE_TEMPLATE; as ArrayType),
     attrmap_element!(CKA_OTP_FORMAT; as NumType),
     attrmap_element!(CKA_OTP_LENGTH; as NumType),
     attrmap_element!(CKA_OTP_TIME_INTERVAL; as NumType),
@@ -167,6 +168,12 @@ static ATTRMAP: [Attrmap<'_>; 147] = [
     attrmap_element!(CKA_HSS_LMS_TYPES; as BytesType),
     attrmap_element!(CKA_HSS_LMOTS_TYPES; as BytesType),
     attrmap_element!(CKA_HSS_KEYS_REMAINING; as NumType),
+    attrmap_element!(CKA_PARAMETER_SET; as NumType),
+    attrmap_element!(CKA_ENCAPSULATE_TEMPLATE; as ArrayType),
+    attrmap_element!(CKA_DECAPSULATE_TEMPLATE; as ArrayType),
+    attrmap_element!(CKA_ENCAPSULATE; as BoolType),
+    attrmap_element!(CKA_DECAPSULATE; as BoolType),
+    attrmap_element!(CKA_SEED; as BytesType),
     attrmap_element!(KRA_MAX_LOGIN_ATTEMPTS; as NumType),
     attrmap_element!(KRA_LOGIN_ATTEMPTS; as NumType),
     attrmap_element!(KRA_FLAGS; as NumType),
@@ -211,6 +218,12 @@ impl Attribute {
         if self.ck_type != attr.type_ {
             return false;
         }
+        if self.attrtype == AttrType::ArrayType {
+            return match attr.to_attribute() {
+                Ok(a) => a.value == self.value,
+                Err(_) => false,
+            };
+        }
         match attr.to_buf() {
             Ok(buf) => buf == self.value,
             Err(_) => false,
@@ -255,6 +268,40 @@ impl Attribute {
         Ok(&self.value)
     }
 
+    pub fn to_attr_array(&self) -> Result<Vec<Attribute>> {
+        let mut attrs = Vec::new();
+        let mut data = self.value.as_slice();
+        while !data.is_empty() {
+            let ck_type = CK_ULONG::try_from(read_u64(data)?)?;
+            data = &data[U64_SIZE..];
+            let len = usize::try_from(read_u64(data)?)?;
+            data = &data[U64_SIZE..];
+            if data.len() < len {
+                return Err(CKR_ATTRIBUTE_VALUE_INVALID)?;
+            }
+            let atype = AttrType::attr_id_to_attrtype(ck_type)?;
+            match atype {
+                AttrType::ArrayType => {
+                    return Err(CKR_ATTRIBUTE_VALUE_INVALID)?;
+                }
+                AttrType::NumType => {
+                    if len != U64_SIZE {
+                        return Err(CKR_ATTRIBUTE_VALUE_INVALID)?;
+                    }
+                    let val = CK_ULONG::try_from(read_u64(data)?)?;
+                    attrs.push(Attribute::from_ulong(ck_type, val));
+                }
+                _ => attrs.push(Attribute::from_attr_slice(
+                    ck_type,
+                    atype,
+                    &data[..len],
+                )),
+            }
+            data = &data[len..];
+        }
+        Ok(attrs)
+    }
+
     pub fn to_date_string(&self) -> Result<String> {
         if self.value.len() != 8 {
             return Err(CKR_ATTRIBUTE_VALUE_INVALID)?;
@@ -408,6 +455,43 @@ fn date_to_vec(val: CK_DATE) -> Vec<u8> {
 
 conversion_from_type! {make from_date; from_type_date; from_string_date; from CK_DATE; as DateType; via date_to_vec}
 
+const U64_SIZE: usize = std::mem::size_of::<u64>();
+
+fn read_u64(data: &[u8]) -> Result<u64> {
+    match data.get(..U64_SIZE) {
+        Some(b) => Ok(u64::from_be_bytes(b.try_into().unwrap())),
+        None => Err(CKR_ATTRIBUTE_VALUE_INVALID)?,
+    }
+}
+
+/* CK_ULONG is not 64 bit wide on all platforms */
+#[allow(clippy::useless_conversion)]
+fn ulong_to_u64(val: CK_ULONG) -> u64 {
+    u64::try_from(val).unwrap()
+}
+
+/* Attribute arrays (templates) are serialized as a sequence of attribute
+ * type and value length, both as big endian u64s, each followed by the
+ * attribute value. Numeric values are stored as big endian u64s as well,
+ * so that the stored form does not depend on the platform CK_ULONG */
+fn attr_array_to_vec(val: Vec<Attribute>) -> Vec<u8> {
+    let mut v = Vec::new();
+    for a in &val {
+        let value = match (a.attrtype, a.to_ulong()) {
+            (AttrType::NumType, Ok(n)) => {
+                ulong_to_u64(n).to_be_bytes().to_vec()
+            }
+            _ => a.value.clone(),
+        };
+        v.extend_from_slice(&ulong_to_u64(a.ck_type).to_be_bytes());
+        v.extend_from_slice(&(value.len() as u64).to_be_bytes());
+        v.extend_from_slice(&value);
+    }
+    v
+}
+
+conversion_from_type! {make from_attr_array; from_type_attr_array; from_string_attr_array; from Vec<Attribute>; as ArrayType; via attr_array_to_vec}
+
 fn vec_to_date(val: Vec<u8>) -> CK_DATE {
     CK_DATE {
         year: [val[0], val[1], val[2], val[3]],
@@ -520,6 +604,31 @@ impl CK_ATTRIBUTE {
         vec_to_date_validate(bytes_to_vec!(self.pValue, self.ulValueLen))
     }
 
+    pub fn to_attr_array(self) -> Result<Vec<Attribute>> {
+        if !self.ulValueLen.is_multiple_of(sizeof!(CK_ATTRIBUTE)) {
+            return Err(CKR_ATTRIBUTE_VALUE_INVALID)?;
+        }
+        let cnt = usize::try_from(self.ulValueLen / sizeof!(CK_ATTRIBUTE))?;
+        if cnt == 0 {
+            return Ok(Vec::new());
+        }
+        if self.pValue.is_null() {
+            return Err(CKR_ATTRIBUTE_VALUE_INVALID)?;
+        }
+        let ck_attrs: &[CK_ATTRIBUTE] =
+            unsafe { std::slice::from_raw_parts(self.pValue as *const _, cnt) };
+        let mut attrs = Vec::with_capacity(cnt);
+        for a in ck_attrs {
+            let attr = a.to_attribute()?;
+            /* no nested templates */
+            if attr.get_attrtype() == AttrType::ArrayType {
+                return Err(CKR_ATTRIBUTE_VALUE_INVALID)?;
+            }
+            attrs.push(attr);
+        }
+        Ok(attrs)
+    }
+
     pub fn to_attribute(&self) -> Result<Attribute> {
         let mut atype = AttrType::DenyType;
         for amap in &ATTRMAP {
@@ -544,6 +653,10 @@ impl CK_ATTRIBUTE {
             AttrType::DateType => {
                 Ok(Attribute::from_date(self.type_, self.to_date()?))
             }
+            AttrType::ArrayType => Ok(Attribute::from_attr_array(
+                self.type_,
+                self.to_attr_array()?,
+            )),
             AttrType::DenyType => Err(CKR_ATTRIBUTE_TYPE_INVALID)?,
             AttrType::IgnoreType => {
                 Ok(Attribute::from_ignore(self.type_, None))
@@ -725,6 +838,22 @@ impl<'a> CkAttrs<'a> {
         }
     }
 
+    /// Merges the attributes of a stored template in, attributes that are
+    /// already present must have the same value.
+    pub fn merge_template(&mut self, attrs: &[Attribute]) -> Result<()> {
+        for a in attrs {
+            match self.p.as_ref().iter().find(|c| c.type_ == a.get_type()) {
+                Some(c) => {
+                    if !a.match_ck_attr(c) {
+                        return Err(CKR_TEMPLATE_INCONSISTENT)?;
+                    }
+                }
+                None => self.add_owned_slice(a.get_type(), a.get_value())?,
+            }
+        }
+        Ok(())
+    }
+
     pub fn remove_ulong(
         &mut self,
         typ: CK_ATTRIBUTE_TYPE,
@@ -0,0 +1,331 @@
+// Copyright 2024 Simo Sorce
+// See LICENSE.txt file for terms
+
+use std::fmt::Debug;
+
+use crate::attr_element;
+use crate::attribute::Attribute;
+use crate::error::Result;
+use crate::interface::*;
+use crate::mechanism::*;
+use crate::object::*;
+use crate::ossl::chacha20::*;
+
+use once_cell::sync::Lazy;
+
+use zeroize::Zeroize;
+
+pub const CHACHA20_KEY_SIZE: usize = 32; /* 256 bits */
+pub const POLY1305_KEY_SIZE: usize = 32; /* 256 bits */
+pub const POLY1305_TAG_SIZE: usize = 16;
+
+/* ChaCha20 and Poly1305 keys have the same fixed size and the same
+ * attributes, so a single factory type serves both key types */
+#[derive(Debug)]
+pub struct ChaCha20KeyFactory {
+    keysize: usize,
+    attributes: Vec<ObjectAttr>,
+}
+
+impl ChaCha20KeyFactory {
+    fn new(keysize: usize) -> ChaCha20KeyFactory {
+        let mut data: ChaCha20KeyFactory = ChaCha20KeyFactory {
+            keysize,
+            attributes: Vec::new(),
+        };
+        data.attributes.append(&mut data.init_common_object_attrs());
+        data.attributes
+            .append(&mut data.init_common_storage_attrs());
+        data.attributes.append(&mut data.init_common_key_attrs());
+        data.attributes
+            .append(&mut data.init_common_secret_key_attrs());
+        data.attributes.push(attr_element!(
+            CKA_VALUE; OAFlags::Defval | OAFlags::Sensitive
+            | OAFlags::RequiredOnCreate | OAFlags::SettableOnlyOnCreate;
+            Attribute::from_bytes; val Vec::new()));
+        /* the key size is fixed, so it is not required on generation */
+        data.attributes.push(attr_element!(
+            CKA_VALUE_LEN; OAFlags::empty(); Attribute::from_bytes;
+            val Vec::new()));
+
+        /* default to private */
+        let private = attr_element!(CKA_PRIVATE; OAFlags::Defval | OAFlags::ChangeOnCopy; Attribute::from_bool; val true);
+        match data
+            .attributes
+            .iter()
+            .position(|x| x.get_type() == CKA_PRIVATE)
+        {
+            Some(idx) => data.attributes[idx] = private,
+            None => data.attributes.push(private),
+        }
+
+        data
+    }
+
+    fn check_key_len(&self, len: usize) -> Result<()> {
+        if len != self.keysize {
+            return Err(CKR_KEY_SIZE_RANGE)?;
+        }
+        Ok(())
+    }
+}
+
+impl ObjectFactory for ChaCha20KeyFactory {
+    fn create(&self, template: &[CK_ATTRIBUTE]) -> Result<Object> {
+        let mut obj = self.default_object_create(template)?;
+        let len = self.get_key_buffer_len(&obj)?;
+        self.check_key_len(len)?;
+        if !obj.check_or_set_attr(Attribute::from_ulong(
+            CKA_VALUE_LEN,
+            CK_ULONG::try_from(len)?,
+        ))? {
+            return Err(CKR_ATTRIBUTE_VALUE_INVALID)?;
+        }
+
+        Ok(obj)
+    }
+
+    fn get_attributes(&self) -> &Vec<ObjectAttr> {
+        &self.attributes
+    }
+
+    fn export_for_wrapping(&self, key: &Object) -> Result<Vec<u8>> {
+        SecretKeyFactory::export_for_wrapping(self, key)
+    }
+
+    fn import_from_wrapped(
+        &self,
+        mut data: Vec<u8>,
+        template: &[CK_ATTRIBUTE],
+    ) -> Result<Object> {
+        /* padded wrapping modes may return more data than the key */
+        if data.len() > self.keysize {
+            data[self.keysize..].zeroize();
+            data.truncate(self.keysize);
+        }
+        match self.check_key_len(data.len()) {
+            Ok(_) => (),
+            Err(e) => {
+                data.zeroize();
+                return Err(e);
+            }
+        }
+        SecretKeyFactory::import_from_wrapped(self, data, template)
+    }
+
+    fn default_object_derive(
+        &self,
+        template: &[CK_ATTRIBUTE],
+        origin: &Object,
+    ) -> Result<Object> {
+        let mut obj = self.internal_object_derive(template, origin)?;
+
+        match self.get_key_len(&obj) {
+            0 => {
+                obj.set_attr(Attribute::from_ulong(
+                    CKA_VALUE_LEN,
+                    CK_ULONG::try_from(self.keysize)?,
+                ))?;
+            }
+            len => {
+                if len != self.keysize {
+                    return Err(CKR_TEMPLATE_INCONSISTENT)?;
+                }
+            }
+        }
+        Ok(obj)
+    }
+
+    fn as_secret_key_factory(&self) -> Result<&dyn SecretKeyFactory> {
+        Ok(self)
+    }
+}
+
+impl CommonKeyFactory for ChaCha20KeyFactory {}
+
+impl SecretKeyFactory for ChaCha20KeyFactory {
+    fn default_object_unwrap(
+        &self,
+        template: &[CK_ATTRIBUTE],
+    ) -> Result<Object> {
+        ObjectFactory::default_object_unwrap(self, template)
+    }
+
+    fn set_key(&self, obj: &mut Object, key: Vec<u8>) -> Result<()> {
+        let keylen = key.len();
+        self.check_key_len(keylen)?;
+        obj.set_attr(Attribute::from_bytes(CKA_VALUE, key))?;
+        self.set_key_len(obj, keylen)?;
+        Ok(())
+    }
+
+    fn recommend_key_size(&self, max: usize) -> Result<usize> {
+        if max >= self.keysize {
+            Ok(self.keysize)
+        } else {
+            Err(CKR_KEY_SIZE_RANGE)?
+        }
+    }
+}
+
+static CHACHA20_KEY_FACTORY: Lazy<Box<dyn ObjectFactory>> =
+    Lazy::new(|| Box::new(ChaCha20KeyFactory::new(CHACHA20_KEY_SIZE)));
+
+static POLY1305_KEY_FACTORY: Lazy<Box<dyn ObjectFactory>> =
+    Lazy::new(|| Box::new(ChaCha20KeyFactory::new(POLY1305_KEY_SIZE)));
+
+#[derive(Debug)]
+pub(crate) struct ChaCha20Mechanism {
+    info: CK_MECHANISM_INFO,
+}
+
+impl ChaCha20Mechanism {
+    pub fn new(flags: CK_FLAGS) -> ChaCha20Mechanism {
+        ChaCha20Mechanism {
+            info: CK_MECHANISM_INFO {
+                ulMinKeySize: CK_ULONG::try_from(CHACHA20_KEY_SIZE).unwrap(),
+                ulMaxKeySize: CK_ULONG::try_from(CHACHA20_KEY_SIZE).unwrap(),
+                flags,
+            },
+        }
+    }
+}
+
+impl Mechanism for ChaCha20Mechanism {
+    fn info(&self) -> &CK_MECHANISM_INFO {
+        &self.info
+    }
+
+    fn encryption_new(
+        &self,
+        mech: &CK_MECHANISM,
+        key: &Object,
+    ) -> Result<Box<dyn Encryption>> {
+        if self.info.flags & CKF_ENCRYPT != CKF_ENCRYPT {
+            return Err(CKR_MECHANISM_INVALID)?;
+        }
+        key.check_key_ops(CKO_SECRET_KEY, CKK_CHACHA20, CKA_ENCRYPT)?;
+        Ok(Box::new(ChaCha20Operation::encrypt_new(mech, key)?))
+    }
+
+    fn decryption_new(
+        &self,
+        mech: &CK_MECHANISM,
+        key: &Object,
+    ) -> Result<Box<dyn Decryption>> {
+        if self.info.flags & CKF_DECRYPT != CKF_DECRYPT {
+            return Err(CKR_MECHANISM_INVALID)?;
+        }
+        key.check_key_ops(CKO_SECRET_KEY, CKK_CHACHA20, CKA_DECRYPT)?;
+        Ok(Box::new(ChaCha20Operation::decrypt_new(mech, key)?))
+    }
+
+    fn generate_key(
+        &self,
+        mech: &CK_MECHANISM,
+        template: &[CK_ATTRIBUTE],
+        _: &Mechanisms,
+        _: &ObjectFactories,
+    ) -> Result<Object> {
+        let (factory, key_type, key_size) = match mech.mechanism {
+            CKM_CHACHA20_KEY_GEN => {
+                (&CHACHA20_KEY_FACTORY, CKK_CHACHA20, CHACHA20_KEY_SIZE)
+            }
+            CKM_POLY1305_KEY_GEN => {
+                (&POLY1305_KEY_FACTORY, CKK_POLY1305, POLY1305_KEY_SIZE)
+            }
+            _ => return Err(CKR_MECHANISM_INVALID)?,
+        };
+        let mut key = factory.default_object_generate(template)?;
+        if !key.check_or_set_attr(Attribute::from_ulong(
+            CKA_CLASS,
+            CKO_SECRET_KEY,
+        ))? {
+            return Err(CKR_TEMPLATE_INCONSISTENT)?;
+        }
+        if !key
+            .check_or_set_attr(Attribute::from_ulong(CKA_KEY_TYPE, key_type))?
+        {
+            return Err(CKR_TEMPLATE_INCONSISTENT)?;
+        }
+        if !key.check_or_set_attr(Attribute::from_ulong(
+            CKA_VALUE_LEN,
+            CK_ULONG::try_from(key_size)?,
+        ))? {
+            return Err(CKR_TEMPLATE_INCONSISTENT)?;
+        }
+
+        default_secret_key_generate(&mut key)?;
+        default_key_attributes(&mut key, mech.mechanism)?;
+        Ok(key)
+    }
+
+    fn sign_new(
+        &self,
+        mech: &CK_MECHANISM,
+        key: &Object,
+    ) -> Result<Box<dyn Sign>> {
+        if self.info.flags & CKF_SIGN != CKF_SIGN {
+            return Err(CKR_MECHANISM_INVALID)?;
+        }
+        key.check_key_ops(CKO_SECRET_KEY, CKK_POLY1305, CKA_SIGN)?;
+        Ok(Box::new(Poly1305Operation::init(mech, key)?))
+    }
+
+    fn verify_new(
+        &self,
+        mech: &CK_MECHANISM,
+        key: &Object,
+    ) -> Result<Box<dyn Verify>> {
+        if self.info.flags & CKF_VERIFY != CKF_VERIFY {
+            return Err(CKR_MECHANISM_INVALID)?;
+        }
+        key.check_key_ops(CKO_SECRET_KEY, CKK_POLY1305, CKA_VERIFY)?;
+        Ok(Box::new(Poly1305Operation::init(mech, key)?))
+    }
+
+    fn msg_encryption_op(
+        &self,
+        mech: &CK_MECHANISM,
+        key: &Object,
+    ) -> Result<Box<dyn MsgEncryption>> {
+        if self.info.flags & CKF_MESSAGE_ENCRYPT == 0 {
+            return Err(CKR_MECHANISM_INVALID)?;
+        }
+        key.check_key_ops(CKO_SECRET_KEY, CKK_CHACHA20, CKA_ENCRYPT)?;
+        Ok(Box::new(ChaCha20Operation::msg_encrypt_init(mech, key)?))
+    }
+
+    fn msg_decryption_op(
+        &self,
+        mech: &CK_MECHANISM,
+        key: &Object,
+    ) -> Result<Box<dyn MsgDecryption>> {
+        if self.info.flags & CKF_MESSAGE_DECRYPT == 0 {
+            return Err(CKR_MECHANISM_INVALID)?;
+        }
+        key.check_key_ops(CKO_SECRET_KEY, CKK_CHACHA20, CKA_DECRYPT)?;
+        Ok(Box::new(ChaCha20Operation::msg_decrypt_init(mech, key)?))
+    }
+}
+
+pub fn register(mechs: &mut Mechanisms, ot: &mut ObjectFactories) {
+    ChaCha20Operation::register_mechanisms(mechs);
+    Poly1305Operation::register_mechanisms(mechs);
+
+    for ckm in &[CKM_CHACHA20_KEY_GEN, CKM_POLY1305_KEY_GEN] {
+        mechs.add_mechanism(
+            *ckm,
+            Box::new(ChaCha20Mechanism::new(CKF_GENERATE)),
+        );
+    }
+
+    ot.add_factory(
+        ObjectType::new(CKO_SECRET_KEY, CKK_CHACHA20),
+        &CHACHA20_KEY_FACTORY,
+    );
+    ot.add_factory(
+        ObjectType::new(CKO_SECRET_KEY, CKK_POLY1305),
+        &POLY1305_KEY_FACTORY,
+    );
+}
@@ -4,6 +4,7 @@
 use std::env;
 use std::fs;
 use std::path::Path;
+use std::time::SystemTime;
 
 use crate::error::{Error, Result};
 use crate::interface;
@@ -27,7 +28,7 @@ const DEFAULT_CONF_DIR: &str = "test";
 
 pub const DEFAULT_CONF_NAME: &str = "token.conf";
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Slot {
     pub slot: u32,
     pub description: Option<String>,
@@ -102,17 +103,16 @@ impl Config {
         Ok(())
     }
 
-    fn find_conf() -> Result<String> {
+    fn conf_path() -> String {
         /* First check for our own env var,
          * this has the highest precedence */
-        match env::var("KRYOPTIC_CONF") {
-            Ok(var) => return Ok(var),
-            Err(_) => (),
+        if let Ok(var) = env::var("KRYOPTIC_CONF") {
+            return var;
         }
         /* Freedesktop specification for data dirs first
          * then fallback to use $HOME/.local/share, if that is also not
          * available see if we have access to a system store */
-        let datafile = match env::var("XDG_CONFIG_HOME") {
+        match env::var("XDG_CONFIG_HOME") {
             Ok(xdg) => format!("{}/kryoptic/{}", xdg, DEFAULT_CONF_NAME),
             Err(_) => match env::var("HOME") {
                 Ok(home) => {
@@ -123,7 +123,11 @@ impl Config {
                     DEFAULT_CONF_DIR, DEFAULT_CONF_NAME
                 ),
             },
-        };
+        }
+    }
+
+    fn find_conf() -> Result<String> {
+        let datafile = Self::conf_path();
         if Path::new(&datafile).is_file() {
             Ok(datafile)
         } else {
@@ -297,3 +301,107 @@ impl Config {
         Ok(())
     }
 }
+
+/* Used to detect changes to the configuration file or its directory
+ * without relying on platform specific notification mechanisms */
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct FileStamp {
+    modified: Option<SystemTime>,
+    len: u64,
+}
+
+impl FileStamp {
+    fn from_path(path: &Path) -> Option<FileStamp> {
+        match fs::metadata(path) {
+            Ok(meta) => Some(FileStamp {
+                modified: meta.modified().ok(),
+                len: meta.len(),
+            }),
+            Err(_) => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct SlotChanges {
+    pub removed: Vec<Slot>,
+    pub added: Vec<Slot>,
+}
+
+/// Watches the configuration file for `[[slots]]` entries being added
+/// or removed while the module is in use
+#[derive(Debug)]
+pub struct ConfigWatcher {
+    filename: String,
+    file_stamp: Option<FileStamp>,
+    dir_stamp: Option<FileStamp>,
+    slots: Vec<Slot>,
+}
+
+impl ConfigWatcher {
+    pub fn new(filename: &str) -> ConfigWatcher {
+        let mut watcher = ConfigWatcher {
+            filename: filename.to_string(),
+            file_stamp: None,
+            dir_stamp: None,
+            slots: Vec::new(),
+        };
+        /* establish the baseline, changes are reported against it */
+        let _ = watcher.poll();
+        watcher
+    }
+
+    /// Watches the configuration file in use, if any, a configuration
+    /// file that does not exist yet is only watched when explicitly
+    /// requested with new()
+    pub fn default_config() -> Option<ConfigWatcher> {
+        match Config::find_conf() {
+            Ok(filename) => Some(Self::new(&filename)),
+            Err(_) => None,
+        }
+    }
+
+    /// Returns the slots added or removed since the last call, if any
+    pub fn poll(&mut self) -> Option<SlotChanges> {
+        let path = Path::new(&self.filename);
+        let file_stamp = FileStamp::from_path(path);
+        let dir_stamp = match path.parent() {
+            Some(dir) => FileStamp::from_path(dir),
+            None => None,
+        };
+        if file_stamp == self.file_stamp && dir_stamp == self.dir_stamp {
+            return None;
+        }
+        self.file_stamp = file_stamp;
+        self.dir_stamp = dir_stamp;
+
+        let slots = if file_stamp.is_some() {
+            match Config::from_file(&self.filename) {
+                Ok(conf) => conf.slots,
+                /* the file may be in the middle of being rewritten,
+                 * keep the current slots until it parses again */
+                Err(_) => return None,
+            }
+        } else {
+            Vec::new()
+        };
+
+        let removed: Vec<Slot> = self
+            .slots
+            .iter()
+            .filter(|s| !slots.contains(s))
+            .cloned()
+            .collect();
+        let added: Vec<Slot> = slots
+            .iter()
+            .filter(|s| !self.slots.contains(s))
+            .cloned()
+            .collect();
+        self.slots = slots;
+
+        if removed.is_empty() && added.is_empty() {
+            return None;
+        }
+        Some(SlotChanges { removed, added })
+    }
+}
@@ -0,0 +1,413 @@
+// Copyright 2024 Simo Sorce
+// See LICENSE.txt file for terms
+
+use std::fmt::Debug;
+
+use crate::attribute::Attribute;
+use crate::error::Result;
+use crate::interface::*;
+use crate::mechanism::*;
+use crate::misc::value_bits;
+use crate::object::*;
+use crate::ossl::dsa::{dsa_generate_domain, DSAOperation};
+use crate::{attr_element, bytes_attr_not_empty};
+
+use once_cell::sync::Lazy;
+
+pub const MIN_DSA_SIZE_BITS: usize = 1024;
+pub const MAX_DSA_SIZE_BITS: usize = 3072;
+
+/* Checks the domain parameters of an object, the size of the prime is
+ * the size of the key */
+fn dsa_check_domain(obj: &Object) -> Result<()> {
+    let prime = match obj.get_attr_as_bytes(CKA_PRIME) {
+        Ok(p) => p,
+        Err(_) => return Err(CKR_TEMPLATE_INCOMPLETE)?,
+    };
+    let bits = usize::try_from(value_bits(prime))?;
+    if !(MIN_DSA_SIZE_BITS..=MAX_DSA_SIZE_BITS).contains(&bits) {
+        Err(CKR_ATTRIBUTE_VALUE_INVALID)?;
+    }
+    bytes_attr_not_empty!(obj; CKA_SUBPRIME);
+    bytes_attr_not_empty!(obj; CKA_BASE);
+    Ok(())
+}
+
+#[derive(Debug)]
+pub struct DSAPubFactory {
+    attributes: Vec<ObjectAttr>,
+}
+
+impl DSAPubFactory {
+    pub fn new() -> DSAPubFactory {
+        let mut data: DSAPubFactory = DSAPubFactory {
+            attributes: Vec::new(),
+        };
+        data.attributes.append(&mut data.init_common_object_attrs());
+        data.attributes
+            .append(&mut data.init_common_storage_attrs());
+        data.attributes.append(&mut data.init_common_key_attrs());
+        data.attributes
+            .append(&mut data.init_common_public_key_attrs());
+        data.attributes.push(attr_element!(
+            CKA_PRIME; OAFlags::RequiredOnCreate | OAFlags::RequiredOnGenerate
+            | OAFlags::Unchangeable; Attribute::from_bytes; val Vec::new()));
+        data.attributes.push(attr_element!(
+            CKA_SUBPRIME; OAFlags::RequiredOnCreate
+            | OAFlags::RequiredOnGenerate | OAFlags::Unchangeable;
+            Attribute::from_bytes; val Vec::new()));
+        data.attributes.push(attr_element!(
+            CKA_BASE; OAFlags::RequiredOnCreate | OAFlags::RequiredOnGenerate
+            | OAFlags::Unchangeable; Attribute::from_bytes; val Vec::new()));
+        data.attributes.push(attr_element!(
+            CKA_VALUE; OAFlags::RequiredOnCreate
+            | OAFlags::SettableOnlyOnCreate | OAFlags::Unchangeable;
+            Attribute::from_bytes; val Vec::new()));
+        data
+    }
+}
+
+impl ObjectFactory for DSAPubFactory {
+    fn create(&self, template: &[CK_ATTRIBUTE]) -> Result<Object> {
+        let obj = self.default_object_create(template)?;
+
+        dsa_check_domain(&obj)?;
+        bytes_attr_not_empty!(obj; CKA_VALUE);
+
+        Ok(obj)
+    }
+
+    fn get_attributes(&self) -> &Vec<ObjectAttr> {
+        &self.attributes
+    }
+}
+
+impl CommonKeyFactory for DSAPubFactory {}
+
+impl PubKeyFactory for DSAPubFactory {}
+
+#[derive(Debug)]
+pub struct DSAPrivFactory {
+    attributes: Vec<ObjectAttr>,
+}
+
+impl DSAPrivFactory {
+    pub fn new() -> DSAPrivFactory {
+        let mut data: DSAPrivFactory = DSAPrivFactory {
+            attributes: Vec::new(),
+        };
+        data.attributes.append(&mut data.init_common_object_attrs());
+        data.attributes
+            .append(&mut data.init_common_storage_attrs());
+        data.attributes.append(&mut data.init_common_key_attrs());
+        data.attributes
+            .append(&mut data.init_common_private_key_attrs());
+        data.attributes.push(attr_element!(
+            CKA_PRIME; OAFlags::RequiredOnCreate | OAFlags::Unchangeable;
+            Attribute::from_bytes; val Vec::new()));
+        data.attributes.push(attr_element!(
+            CKA_SUBPRIME; OAFlags::RequiredOnCreate | OAFlags::Unchangeable;
+            Attribute::from_bytes; val Vec::new()));
+        data.attributes.push(attr_element!(
+            CKA_BASE; OAFlags::RequiredOnCreate | OAFlags::Unchangeable;
+            Attribute::from_bytes; val Vec::new()));
+        data.attributes.push(attr_element!(
+            CKA_VALUE; OAFlags::Sensitive | OAFlags::RequiredOnCreate
+            | OAFlags::SettableOnlyOnCreate | OAFlags::Unchangeable;
+            Attribute::from_bytes; val Vec::new()));
+
+        /* default to private */
+        let private = attr_element!(
+            CKA_PRIVATE; OAFlags::Defval | OAFlags::ChangeOnCopy;
+            Attribute::from_bool; val true);
+        match data
+            .attributes
+            .iter()
+            .position(|x| x.get_type() == CKA_PRIVATE)
+        {
+            Some(idx) => data.attributes[idx] = private,
+            None => data.attributes.push(private),
+        }
+
+        data
+    }
+}
+
+impl ObjectFactory for DSAPrivFactory {
+    fn create(&self, template: &[CK_ATTRIBUTE]) -> Result<Object> {
+        let obj = self.default_object_create(template)?;
+
+        dsa_check_domain(&obj)?;
+        bytes_attr_not_empty!(obj; CKA_VALUE);
+
+        Ok(obj)
+    }
+
+    fn get_attributes(&self) -> &Vec<ObjectAttr> {
+        &self.attributes
+    }
+}
+
+impl CommonKeyFactory for DSAPrivFactory {}
+
+impl PrivKeyFactory for DSAPrivFactory {}
+
+#[derive(Debug)]
+pub struct DSADomainParamsFactory {
+    attributes: Vec<ObjectAttr>,
+}
+
+impl DSADomainParamsFactory {
+    pub fn new() -> DSADomainParamsFactory {
+        let mut data: DSADomainParamsFactory = DSADomainParamsFactory {
+            attributes: Vec::new(),
+        };
+        data.attributes.append(&mut data.init_common_object_attrs());
+        data.attributes
+            .append(&mut data.init_common_storage_attrs());
+        data.attributes
+            .append(&mut data.init_common_domain_params_attrs());
+        data.attributes.push(attr_element!(
+            CKA_PRIME; OAFlags::RequiredOnCreate
+            | OAFlags::SettableOnlyOnCreate | OAFlags::Unchangeable;
+            Attribute::from_bytes; val Vec::new()));
+        data.attributes.push(attr_element!(
+            CKA_SUBPRIME; OAFlags::RequiredOnCreate
+            | OAFlags::SettableOnlyOnCreate | OAFlags::Unchangeable;
+            Attribute::from_bytes; val Vec::new()));
+        data.attributes.push(attr_element!(
+            CKA_BASE; OAFlags::RequiredOnCreate
+            | OAFlags::SettableOnlyOnCreate | OAFlags::Unchangeable;
+            Attribute::from_bytes; val Vec::new()));
+        data.attributes.push(attr_element!(
+            CKA_PRIME_BITS; OAFlags::RequiredOnGenerate
+            | OAFlags::Unchangeable; Attribute::from_bytes; val Vec::new()));
+        data.attributes.push(attr_element!(
+            CKA_SUBPRIME_BITS; OAFlags::Unchangeable;
+            Attribute::from_ulong; val 0));
+        data
+    }
+}
+
+impl ObjectFactory for DSADomainParamsFactory {
+    fn create(&self, template: &[CK_ATTRIBUTE]) -> Result<Object> {
+        let mut obj = self.default_object_create(template)?;
+
+        dsa_check_domain(&obj)?;
+
+        /* The sizes are always computed from the parameters */
+        for attr in [CKA_PRIME_BITS, CKA_SUBPRIME_BITS] {
+            if obj.get_attr(attr).is_some() {
+                return Err(CKR_ATTRIBUTE_VALUE_INVALID)?;
+            }
+        }
+        let bits = value_bits(obj.get_attr_as_bytes(CKA_PRIME)?);
+        obj.set_attr(prime_bits_attribute(bits))?;
+        let bits = value_bits(obj.get_attr_as_bytes(CKA_SUBPRIME)?);
+        obj.set_attr(Attribute::from_ulong(CKA_SUBPRIME_BITS, bits))?;
+
+        Ok(obj)
+    }
+
+    fn get_attributes(&self) -> &Vec<ObjectAttr> {
+        &self.attributes
+    }
+}
+
+impl DomainParamsFactory for DSADomainParamsFactory {}
+
+static PUBLIC_KEY_FACTORY: Lazy<Box<dyn ObjectFactory>> =
+    Lazy::new(|| Box::new(DSAPubFactory::new()));
+
+static PRIVATE_KEY_FACTORY: Lazy<Box<dyn ObjectFactory>> =
+    Lazy::new(|| Box::new(DSAPrivFactory::new()));
+
+static DOMAIN_PARAMS_FACTORY: Lazy<Box<dyn ObjectFactory>> =
+    Lazy::new(|| Box::new(DSADomainParamsFactory::new()));
+
+/* DSA is retained for the verification of legacy signatures only in the
+ * FIPS build, FIPS 186-5 does not allow generating new signatures */
+#[cfg(not(feature = "fips"))]
+const DSA_SIG_FLAGS: CK_FLAGS = CKF_SIGN | CKF_VERIFY;
+#[cfg(feature = "fips")]
+const DSA_SIG_FLAGS: CK_FLAGS = CKF_VERIFY;
+
+#[derive(Debug)]
+struct DSAMechanism {
+    info: CK_MECHANISM_INFO,
+}
+
+impl DSAMechanism {
+    fn new_mechanism(flags: CK_FLAGS) -> Box<dyn Mechanism> {
+        Box::new(DSAMechanism {
+            info: CK_MECHANISM_INFO {
+                ulMinKeySize: CK_ULONG::try_from(MIN_DSA_SIZE_BITS).unwrap(),
+                ulMaxKeySize: CK_ULONG::try_from(MAX_DSA_SIZE_BITS).unwrap(),
+                flags,
+            },
+        })
+    }
+
+    fn register_mechanisms(mechs: &mut Mechanisms) {
+        #[cfg(not(feature = "fips"))]
+        mechs.add_mechanism(
+            CKM_DSA_PARAMETER_GEN,
+            Self::new_mechanism(CKF_GENERATE),
+        );
+        #[cfg(not(feature = "fips"))]
+        mechs.add_mechanism(
+            CKM_DSA_KEY_PAIR_GEN,
+            Self::new_mechanism(CKF_GENERATE_KEY_PAIR),
+        );
+        for ckm in &[
+            CKM_DSA,
+            CKM_DSA_SHA1,
+            CKM_DSA_SHA224,
+            CKM_DSA_SHA256,
+            CKM_DSA_SHA384,
+            CKM_DSA_SHA512,
+            CKM_DSA_SHA3_224,
+            CKM_DSA_SHA3_256,
+            CKM_DSA_SHA3_384,
+            CKM_DSA_SHA3_512,
+        ] {
+            mechs.add_mechanism(*ckm, Self::new_mechanism(DSA_SIG_FLAGS));
+        }
+    }
+}
+
+impl Mechanism for DSAMechanism {
+    fn info(&self) -> &CK_MECHANISM_INFO {
+        &self.info
+    }
+
+    fn generate_key(
+        &self,
+        mech: &CK_MECHANISM,
+        template: &[CK_ATTRIBUTE],
+        _: &Mechanisms,
+        _: &ObjectFactories,
+    ) -> Result<Object> {
+        if mech.mechanism != CKM_DSA_PARAMETER_GEN {
+            return Err(CKR_MECHANISM_INVALID)?;
+        }
+
+        let mut params =
+            DOMAIN_PARAMS_FACTORY.default_object_generate(template)?;
+        if !params.check_or_set_attr(Attribute::from_ulong(
+            CKA_CLASS,
+            CKO_DOMAIN_PARAMETERS,
+        ))? {
+            return Err(CKR_TEMPLATE_INCONSISTENT)?;
+        }
+        if !params
+            .check_or_set_attr(Attribute::from_ulong(CKA_KEY_TYPE, CKK_DSA))?
+        {
+            return Err(CKR_TEMPLATE_INCONSISTENT)?;
+        }
+        let bits = get_prime_bits(&params)?;
+        if !(MIN_DSA_SIZE_BITS..=MAX_DSA_SIZE_BITS).contains(&bits) {
+            return Err(CKR_KEY_SIZE_RANGE)?;
+        }
+
+        dsa_generate_domain(&mut params)?;
+        params.set_attr(Attribute::from_bool(CKA_LOCAL, true))?;
+
+        Ok(params)
+    }
+
+    fn generate_keypair(
+        &self,
+        mech: &CK_MECHANISM,
+        pubkey_template: &[CK_ATTRIBUTE],
+        prikey_template: &[CK_ATTRIBUTE],
+    ) -> Result<(Object, Object)> {
+        if mech.mechanism != CKM_DSA_KEY_PAIR_GEN {
+            return Err(CKR_MECHANISM_INVALID)?;
+        }
+
+        let mut pubkey =
+            PUBLIC_KEY_FACTORY.default_object_generate(pubkey_template)?;
+        if !pubkey.check_or_set_attr(Attribute::from_ulong(
+            CKA_CLASS,
+            CKO_PUBLIC_KEY,
+        ))? {
+            return Err(CKR_TEMPLATE_INCONSISTENT)?;
+        }
+        if !pubkey
+            .check_or_set_attr(Attribute::from_ulong(CKA_KEY_TYPE, CKK_DSA))?
+        {
+            return Err(CKR_TEMPLATE_INCONSISTENT)?;
+        }
+
+        let mut privkey =
+            PRIVATE_KEY_FACTORY.default_object_generate(prikey_template)?;
+        if !privkey.check_or_set_attr(Attribute::from_ulong(
+            CKA_CLASS,
+            CKO_PRIVATE_KEY,
+        ))? {
+            return Err(CKR_TEMPLATE_INCONSISTENT)?;
+        }
+        if !privkey
+            .check_or_set_attr(Attribute::from_ulong(CKA_KEY_TYPE, CKK_DSA))?
+        {
+            return Err(CKR_TEMPLATE_INCONSISTENT)?;
+        }
+
+        /* the domain parameters come from the public key template */
+        dsa_check_domain(&pubkey)?;
+        for attr in [CKA_PRIME, CKA_SUBPRIME, CKA_BASE] {
+            let value = pubkey.get_attr_as_bytes(attr)?.clone();
+            if !privkey.check_or_set_attr(Attribute::from_bytes(attr, value))? {
+                return Err(CKR_TEMPLATE_INCONSISTENT)?;
+            }
+        }
+
+        DSAOperation::generate_keypair(&mut pubkey, &mut privkey)?;
+        default_key_attributes(&mut privkey, mech.mechanism)?;
+        default_key_attributes(&mut pubkey, mech.mechanism)?;
+
+        Ok((pubkey, privkey))
+    }
+
+    fn sign_new(
+        &self,
+        mech: &CK_MECHANISM,
+        key: &Object,
+    ) -> Result<Box<dyn Sign>> {
+        if self.info.flags & CKF_SIGN != CKF_SIGN {
+            return Err(CKR_MECHANISM_INVALID)?;
+        }
+        key.check_key_ops(CKO_PRIVATE_KEY, CKK_DSA, CKA_SIGN)?;
+        Ok(Box::new(DSAOperation::sign_new(mech, key)?))
+    }
+
+    fn verify_new(
+        &self,
+        mech: &CK_MECHANISM,
+        key: &Object,
+    ) -> Result<Box<dyn Verify>> {
+        if self.info.flags & CKF_VERIFY != CKF_VERIFY {
+            return Err(CKR_MECHANISM_INVALID)?;
+        }
+        key.check_key_ops(CKO_PUBLIC_KEY, CKK_DSA, CKA_VERIFY)?;
+        Ok(Box::new(DSAOperation::verify_new(mech, key)?))
+    }
+}
+
+pub fn register(mechs: &mut Mechanisms, ot: &mut ObjectFactories) {
+    DSAMechanism::register_mechanisms(mechs);
+
+    ot.add_factory(
+        ObjectType::new(CKO_PUBLIC_KEY, CKK_DSA),
+        &PUBLIC_KEY_FACTORY,
+    );
+    ot.add_factory(
+        ObjectType::new(CKO_PRIVATE_KEY, CKK_DSA),
+        &PRIVATE_KEY_FACTORY,
+    );
+    ot.add_factory(
+        ObjectType::new(CKO_DOMAIN_PARAMETERS, CKK_DSA),
+        &DOMAIN_PARAMS_FACTORY,
+    );
+}
@@ -7,9 +7,10 @@ use crate::attr_element;
 use crate::attribute::Attribute;
 use crate::ec::*;
 use crate::error::{general_error, Error, Result};
-use crate::kasn1::{oid, PrivateKeyInfo};
+use crate::kasn1::PrivateKeyInfo;
 use crate::mechanism::*;
 use crate::object::*;
+use crate::ossl::common::ec_explicit_params_to_curve_name;
 use crate::ossl::ecdsa::EccOperation;
 
 use asn1;
@@ -18,6 +19,54 @@ use once_cell::sync::Lazy;
 pub const MIN_EC_SIZE_BITS: usize = BITS_SECP256R1;
 pub const MAX_EC_SIZE_BITS: usize = BITS_SECP521R1;
 
+fn explicit_params_to_oid(
+    domain: &SpecifiedECDomain,
+) -> Result<asn1::ObjectIdentifier> {
+    if !(1..=3).contains(&domain.version) {
+        Err(CKR_ATTRIBUTE_VALUE_INVALID)?;
+    }
+    if domain.field_id.field_type != EC_PRIME_FIELD_OID {
+        Err(CKR_CURVE_NOT_SUPPORTED)?;
+    }
+    let prime = match domain.field_id.parameters.parse::<asn1::BigUint>() {
+        Ok(p) => p,
+        Err(_) => return Err(CKR_ATTRIBUTE_VALUE_INVALID)?,
+    };
+    let name = match ec_explicit_params_to_curve_name(prime.as_bytes(), domain)
+    {
+        Ok(n) => n,
+        Err(_) => return Err(CKR_CURVE_NOT_SUPPORTED)?,
+    };
+    match curvename_to_oid(&name) {
+        Ok(oid) => {
+            if !curve_supported(&oid, CKK_EC) {
+                Err(CKR_CURVE_NOT_SUPPORTED)?;
+            }
+            Ok(oid)
+        }
+        Err(_) => Err(CKR_CURVE_NOT_SUPPORTED)?,
+    }
+}
+
+/* Explicit domain parameters are accepted only when they describe one
+ * of the supported named curves, the parameters are then replaced with
+ * the OID of that curve so they are resolved only once */
+fn use_named_curve(obj: &mut Object) -> Result<()> {
+    let params = match obj.get_attr_as_bytes(CKA_EC_PARAMS) {
+        Ok(p) => p,
+        Err(_) => return Ok(()),
+    };
+    let oid = match asn1::parse_single::<ECParameters>(params) {
+        Ok(ECParameters::EcParameters(domain)) => {
+            explicit_params_to_oid(&domain)?
+        }
+        _ => return Ok(()),
+    };
+    let params =
+        asn1::write_single(&ECParameters::OId(oid)).map_err(general_error)?;
+    obj.set_attr(Attribute::from_bytes(CKA_EC_PARAMS, params))
+}
+
 #[derive(Debug)]
 pub struct ECCPubFactory {
     attributes: Vec<ObjectAttr>,
@@ -43,6 +92,7 @@ impl ECCPubFactory {
 impl ObjectFactory for ECCPubFactory {
     fn create(&self, template: &[CK_ATTRIBUTE]) -> Result<Object> {
         let mut obj = self.default_object_create(template)?;
+        use_named_curve(&mut obj)?;
 
         /* According to PKCS#11 v3.1 6.3.3:
          * CKA_EC_PARAMS, Byte array,
@@ -50,15 +100,16 @@ impl ObjectFactory for ECCPubFactory {
         let oid = get_oid_from_obj(&obj).map_err(|e| {
             if e.attr_not_found() {
                 Error::ck_rv_from_error(CKR_TEMPLATE_INCOMPLETE, e)
+            } else if e.rv() == CKR_CURVE_NOT_SUPPORTED {
+                e
             } else if e.rv() != CKR_ATTRIBUTE_VALUE_INVALID {
                 Error::ck_rv_from_error(CKR_ATTRIBUTE_VALUE_INVALID, e)
             } else {
                 general_error(e)
             }
         })?;
-        match oid {
-            oid::EC_SECP256R1 | oid::EC_SECP384R1 | oid::EC_SECP521R1 => (),
-            _ => return Err(CKR_ATTRIBUTE_VALUE_INVALID)?,
+        if !curve_supported(&oid, CKK_EC) {
+            return Err(CKR_ATTRIBUTE_VALUE_INVALID)?;
         }
 
         /* According to PKCS#11 v3.1 6.3.3:
@@ -127,7 +178,8 @@ impl ECCPrivFactory {
 
 impl ObjectFactory for ECCPrivFactory {
     fn create(&self, template: &[CK_ATTRIBUTE]) -> Result<Object> {
-        let obj = self.default_object_create(template)?;
+        let mut obj = self.default_object_create(template)?;
+        use_named_curve(&mut obj)?;
 
         /* According to PKCS#11 v3.1 6.3.4:
          * CKA_EC_PARAMS, Byte array,
@@ -135,15 +187,16 @@ impl ObjectFactory for ECCPrivFactory {
         let oid = get_oid_from_obj(&obj).map_err(|e| {
             if e.attr_not_found() {
                 Error::ck_rv_from_error(CKR_TEMPLATE_INCOMPLETE, e)
+            } else if e.rv() == CKR_CURVE_NOT_SUPPORTED {
+                e
             } else if e.rv() != CKR_ATTRIBUTE_VALUE_INVALID {
                 Error::ck_rv_from_error(CKR_ATTRIBUTE_VALUE_INVALID, e)
             } else {
                 general_error(e)
             }
         })?;
-        match oid {
-            oid::EC_SECP256R1 | oid::EC_SECP384R1 | oid::EC_SECP521R1 => (),
-            _ => return Err(CKR_ATTRIBUTE_VALUE_INVALID)?,
+        if !curve_supported(&oid, CKK_EC) {
+            return Err(CKR_ATTRIBUTE_VALUE_INVALID)?;
         }
 
         /* According to PKCS#11 v3.1 6.3.4:
@@ -241,10 +294,9 @@ impl PrivKeyFactory for ECCPrivFactory {
         };
         let oid = pkeyinfo.get_oid();
         /* filter out unknown OIDs */
-        match oid {
-            &EC_SECP521R1 | &EC_SECP384R1 | &EC_SECP256R1 => (),
-            _ => return Err(CKR_WRAPPED_KEY_INVALID)?,
-        };
+        if !curve_supported(oid, CKK_EC) {
+            return Err(CKR_WRAPPED_KEY_INVALID)?;
+        }
         let oid_encoded = match asn1::write_single(&oid) {
             Ok(b) => b,
             Err(_) => return Err(CKR_WRAPPED_KEY_INVALID)?,
@@ -275,12 +327,65 @@ impl PrivKeyFactory for ECCPrivFactory {
     }
 }
 
+#[derive(Debug)]
+pub struct ECCDomainParamsFactory {
+    attributes: Vec<ObjectAttr>,
+}
+
+impl ECCDomainParamsFactory {
+    pub fn new() -> ECCDomainParamsFactory {
+        let mut data: ECCDomainParamsFactory = ECCDomainParamsFactory {
+            attributes: Vec::new(),
+        };
+        data.attributes.append(&mut data.init_common_object_attrs());
+        data.attributes
+            .append(&mut data.init_common_storage_attrs());
+        data.attributes
+            .append(&mut data.init_common_domain_params_attrs());
+        data.attributes.push(attr_element!(
+            CKA_EC_PARAMS; OAFlags::RequiredOnCreate | OAFlags::Unchangeable;
+            Attribute::from_bytes; val Vec::new()));
+        data
+    }
+}
+
+impl ObjectFactory for ECCDomainParamsFactory {
+    fn create(&self, template: &[CK_ATTRIBUTE]) -> Result<Object> {
+        let mut obj = self.default_object_create(template)?;
+        use_named_curve(&mut obj)?;
+
+        let oid = get_oid_from_obj(&obj).map_err(|e| {
+            if e.attr_not_found() {
+                Error::ck_rv_from_error(CKR_TEMPLATE_INCOMPLETE, e)
+            } else if e.rv() == CKR_CURVE_NOT_SUPPORTED {
+                e
+            } else {
+                Error::ck_rv_from_error(CKR_ATTRIBUTE_VALUE_INVALID, e)
+            }
+        })?;
+        if !curve_supported(&oid, CKK_EC) {
+            return Err(CKR_ATTRIBUTE_VALUE_INVALID)?;
+        }
+
+        Ok(obj)
+    }
+
+    fn get_attributes(&self) -> &Vec<ObjectAttr> {
+        &self.attributes
+    }
+}
+
+impl DomainParamsFactory for ECCDomainParamsFactory {}
+
 static PUBLIC_KEY_FACTORY: Lazy<Box<dyn ObjectFactory>> =
     Lazy::new(|| Box::new(ECCPubFactory::new()));
 
 static PRIVATE_KEY_FACTORY: Lazy<Box<dyn ObjectFactory>> =
     Lazy::new(|| Box::new(ECCPrivFactory::new()));
 
+static DOMAIN_PARAMS_FACTORY: Lazy<Box<dyn ObjectFactory>> =
+    Lazy::new(|| Box::new(ECCDomainParamsFactory::new()));
+
 #[derive(Debug)]
 pub struct EccMechanism {
     info: CK_MECHANISM_INFO,
@@ -331,6 +436,28 @@ impl Mechanism for EccMechanism {
         }
         Ok(Box::new(EccOperation::verify_new(mech, key, &self.info)?))
     }
+    fn msg_sign_op(
+        &self,
+        mech: &CK_MECHANISM,
+        key: &Object,
+    ) -> Result<Box<dyn MsgSign>> {
+        if self.info.flags & CKF_MESSAGE_SIGN != CKF_MESSAGE_SIGN {
+            return Err(CKR_MECHANISM_INVALID)?;
+        }
+        key.check_key_ops(CKO_PRIVATE_KEY, CKK_EC, CKA_SIGN)?;
+        Ok(Box::new(EccOperation::sign_new(mech, key, &self.info)?))
+    }
+    fn msg_verify_op(
+        &self,
+        mech: &CK_MECHANISM,
+        key: &Object,
+    ) -> Result<Box<dyn MsgVerify>> {
+        if self.info.flags & CKF_MESSAGE_VERIFY != CKF_MESSAGE_VERIFY {
+            return Err(CKR_MECHANISM_INVALID)?;
+        }
+        key.check_key_ops(CKO_PUBLIC_KEY, CKK_EC, CKA_VERIFY)?;
+        Ok(Box::new(EccOperation::verify_new(mech, key, &self.info)?))
+    }
 
     fn generate_keypair(
         &self,
@@ -366,6 +493,8 @@ impl Mechanism for EccMechanism {
             return Err(CKR_TEMPLATE_INCONSISTENT)?;
         }
 
+        use_named_curve(&mut pubkey)?;
+        use_named_curve(&mut privkey)?;
         let ec_params = match pubkey.get_attr_as_bytes(CKA_EC_PARAMS) {
             Ok(a) => a.clone(),
             Err(_) => {
@@ -398,4 +527,8 @@ pub fn register(mechs: &mut Mechanisms, ot: &mut ObjectFactories) {
         ObjectType::new(CKO_PRIVATE_KEY, CKK_EC),
         &PRIVATE_KEY_FACTORY,
     );
+    ot.add_factory(
+        ObjectType::new(CKO_DOMAIN_PARAMETERS, CKK_EC),
+        &DOMAIN_PARAMS_FACTORY,
+    );
 }
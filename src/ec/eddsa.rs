@@ -190,7 +190,37 @@ impl ObjectFactory for EDDSAPrivFactory {
 
 impl CommonKeyFactory for EDDSAPrivFactory {}
 
-impl PrivKeyFactory for EDDSAPrivFactory {}
+impl PrivKeyFactory for EDDSAPrivFactory {
+    fn export_for_wrapping(&self, key: &Object) -> Result<Vec<u8>> {
+        key.check_key_ops(CKO_PRIVATE_KEY, CKK_EC_EDWARDS, CKA_EXTRACTABLE)?;
+        export_curve_private_key(key, CKK_EC_EDWARDS)
+    }
+
+    fn import_from_wrapped(
+        &self,
+        data: Vec<u8>,
+        template: &[CK_ATTRIBUTE],
+    ) -> Result<Object> {
+        let mut key = self.default_object_unwrap(template)?;
+
+        if !key.check_or_set_attr(Attribute::from_ulong(
+            CKA_CLASS,
+            CKO_PRIVATE_KEY,
+        ))? {
+            return Err(CKR_TEMPLATE_INCONSISTENT)?;
+        }
+        if !key.check_or_set_attr(Attribute::from_ulong(
+            CKA_KEY_TYPE,
+            CKK_EC_EDWARDS,
+        ))? {
+            return Err(CKR_TEMPLATE_INCONSISTENT)?;
+        }
+
+        import_curve_private_key(&mut key, CKK_EC_EDWARDS, &data)?;
+
+        Ok(key)
+    }
+}
 
 static PUBLIC_KEY_FACTORY: Lazy<Box<dyn ObjectFactory>> =
     Lazy::new(|| Box::new(EDDSAPubFactory::new()));
@@ -213,7 +243,10 @@ impl EddsaMechanism {
                         .unwrap(),
                     ulMaxKeySize: CK_ULONG::try_from(MAX_EDDSA_SIZE_BITS)
                         .unwrap(),
-                    flags: CKF_SIGN | CKF_VERIFY,
+                    flags: CKF_SIGN
+                        | CKF_VERIFY
+                        | CKF_MESSAGE_SIGN
+                        | CKF_MESSAGE_VERIFY,
                 },
             }),
         );
@@ -266,6 +299,28 @@ impl Mechanism for EddsaMechanism {
         }
         Ok(Box::new(EddsaOperation::verify_new(mech, key, &self.info)?))
     }
+    fn msg_sign_op(
+        &self,
+        mech: &CK_MECHANISM,
+        key: &Object,
+    ) -> Result<Box<dyn MsgSign>> {
+        if self.info.flags & CKF_MESSAGE_SIGN != CKF_MESSAGE_SIGN {
+            return Err(CKR_MECHANISM_INVALID)?;
+        }
+        key.check_key_ops(CKO_PRIVATE_KEY, CKK_EC_EDWARDS, CKA_SIGN)?;
+        Ok(Box::new(EddsaOperation::sign_new(mech, key, &self.info)?))
+    }
+    fn msg_verify_op(
+        &self,
+        mech: &CK_MECHANISM,
+        key: &Object,
+    ) -> Result<Box<dyn MsgVerify>> {
+        if self.info.flags & CKF_MESSAGE_VERIFY != CKF_MESSAGE_VERIFY {
+            return Err(CKR_MECHANISM_INVALID)?;
+        }
+        key.check_key_ops(CKO_PUBLIC_KEY, CKK_EC_EDWARDS, CKA_VERIFY)?;
+        Ok(Box::new(EddsaOperation::verify_new(mech, key, &self.info)?))
+    }
 
     fn generate_keypair(
         &self,
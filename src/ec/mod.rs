@@ -9,6 +9,8 @@ use crate::kasn1::pkcs::*;
 use crate::object::Object;
 
 use asn1;
+#[cfg(any(feature = "eddsa", feature = "ec_montgomery"))]
+use zeroize::Zeroize;
 
 #[cfg(feature = "ecdh")]
 pub mod ecdh;
@@ -24,89 +26,194 @@ pub mod montgomery;
 
 // Bit sized for curves
 pub const BITS_SECP256R1: usize = 256;
-#[allow(dead_code)]
 pub const BITS_SECP384R1: usize = 384;
 pub const BITS_SECP521R1: usize = 521;
+pub const BITS_SECP256K1: usize = 256;
+pub const BITS_BRAINPOOLP256R1: usize = 256;
+pub const BITS_BRAINPOOLP384R1: usize = 384;
+pub const BITS_BRAINPOOLP512R1: usize = 512;
 pub const BITS_ED25519: usize = 256;
 pub const BITS_ED448: usize = 456;
 pub const BITS_X25519: usize = 256;
 pub const BITS_X448: usize = 448;
 
-const EC_POINT_BYTES_SECP256R1: usize = 2 * ((BITS_SECP256R1 + 7) / 8) + 1;
-const EC_POINT_BYTES_SECP384R1: usize = 2 * ((BITS_SECP384R1 + 7) / 8) + 1;
-const EC_POINT_BYTES_SECP521R1: usize = 2 * ((BITS_SECP521R1 + 7) / 8) + 1;
+#[cfg(any(feature = "eddsa", feature = "ec_montgomery"))]
 const EC_POINT_BYTES_ED25519: usize = (BITS_ED25519 + 7) / 8;
+#[cfg(any(feature = "eddsa", feature = "ec_montgomery"))]
 const EC_POINT_BYTES_ED448: usize = (BITS_ED448 + 7) / 8;
-const EC_POINT_BYTES_X25519: usize = (BITS_X25519 + 7) / 8;
+#[cfg(any(feature = "eddsa", feature = "ec_montgomery"))]
 const EC_POINT_BYTES_X448: usize = (BITS_X448 + 7) / 8;
 
-const EC_KEY_BYTES_SECP256R1: usize = (BITS_SECP256R1 + 7) / 8;
-const EC_KEY_BYTES_SECP384R1: usize = (BITS_SECP384R1 + 7) / 8;
-const EC_KEY_BYTES_SECP521R1: usize = (BITS_SECP521R1 + 7) / 8;
-const EC_KEY_BYTES_ED25519: usize = (BITS_ED25519 + 7) / 8;
-const EC_KEY_BYTES_ED448: usize = (BITS_ED448 + 7) / 8;
-const EC_KEY_BYTES_X25519: usize = (BITS_X25519 + 7) / 8;
-const EC_KEY_BYTES_X448: usize = (BITS_X448 + 7) / 8;
-
 /* Curve names as used in CurveName PrinableString */
 pub const PRIME256V1: &str = "prime256v1";
 pub const SECP384R1: &str = "secp384r1";
 pub const SECP521R1: &str = "secp521r1";
+pub const SECP256K1: &str = "secp256k1";
+pub const BRAINPOOLP256R1: &str = "brainpoolP256r1";
+pub const BRAINPOOLP384R1: &str = "brainpoolP384r1";
+pub const BRAINPOOLP512R1: &str = "brainpoolP512r1";
 pub const EDWARDS25519: &str = "edwards25519";
 pub const EDWARDS448: &str = "edwards448";
 pub const CURVE25519: &str = "curve25519";
 pub const CURVE448: &str = "curve448";
 
+/// Describes a curve supported by the token
+pub struct EcCurve {
+    /// The OID used in CKA_EC_PARAMS
+    pub oid: asn1::ObjectIdentifier,
+    /// The name used in the CurveName form of CKA_EC_PARAMS
+    pub name: &'static str,
+    /// The name of the curve in OpenSSL
+    pub ossl_name: &'static [u8],
+    /// The key type this curve is used with
+    pub key_type: CK_KEY_TYPE,
+    /// The size of the curve in bits
+    pub bits: usize,
+    /// Whether the curve is available in the FIPS build
+    pub fips: bool,
+}
+
+/* Adding a curve only requires a new entry here */
+const EC_CURVES: [EcCurve; 11] = [
+    EcCurve {
+        oid: EC_SECP256R1,
+        name: PRIME256V1,
+        ossl_name: b"prime256v1\0",
+        key_type: CKK_EC,
+        bits: BITS_SECP256R1,
+        fips: true,
+    },
+    EcCurve {
+        oid: EC_SECP384R1,
+        name: SECP384R1,
+        ossl_name: b"secp384r1\0",
+        key_type: CKK_EC,
+        bits: BITS_SECP384R1,
+        fips: true,
+    },
+    EcCurve {
+        oid: EC_SECP521R1,
+        name: SECP521R1,
+        ossl_name: b"secp521r1\0",
+        key_type: CKK_EC,
+        bits: BITS_SECP521R1,
+        fips: true,
+    },
+    EcCurve {
+        oid: EC_SECP256K1,
+        name: SECP256K1,
+        ossl_name: b"secp256k1\0",
+        key_type: CKK_EC,
+        bits: BITS_SECP256K1,
+        fips: false,
+    },
+    EcCurve {
+        oid: EC_BRAINPOOLP256R1,
+        name: BRAINPOOLP256R1,
+        ossl_name: b"brainpoolP256r1\0",
+        key_type: CKK_EC,
+        bits: BITS_BRAINPOOLP256R1,
+        fips: false,
+    },
+    EcCurve {
+        oid: EC_BRAINPOOLP384R1,
+        name: BRAINPOOLP384R1,
+        ossl_name: b"brainpoolP384r1\0",
+        key_type: CKK_EC,
+        bits: BITS_BRAINPOOLP384R1,
+        fips: false,
+    },
+    EcCurve {
+        oid: EC_BRAINPOOLP512R1,
+        name: BRAINPOOLP512R1,
+        ossl_name: b"brainpoolP512r1\0",
+        key_type: CKK_EC,
+        bits: BITS_BRAINPOOLP512R1,
+        fips: false,
+    },
+    EcCurve {
+        oid: ED25519_OID,
+        name: EDWARDS25519,
+        ossl_name: b"ED25519\0",
+        key_type: CKK_EC_EDWARDS,
+        bits: BITS_ED25519,
+        fips: true,
+    },
+    EcCurve {
+        oid: ED448_OID,
+        name: EDWARDS448,
+        ossl_name: b"ED448\0",
+        key_type: CKK_EC_EDWARDS,
+        bits: BITS_ED448,
+        fips: true,
+    },
+    EcCurve {
+        oid: X25519_OID,
+        name: CURVE25519,
+        ossl_name: b"X25519\0",
+        key_type: CKK_EC_MONTGOMERY,
+        bits: BITS_X25519,
+        fips: true,
+    },
+    EcCurve {
+        oid: X448_OID,
+        name: CURVE448,
+        ossl_name: b"X448\0",
+        key_type: CKK_EC_MONTGOMERY,
+        bits: BITS_X448,
+        fips: true,
+    },
+];
+
+/* Curves not approved in FIPS mode are not available at all in the
+ * FIPS build */
+fn available_curves() -> impl Iterator<Item = &'static EcCurve> {
+    EC_CURVES
+        .iter()
+        .filter(|c| c.fips || cfg!(not(feature = "fips")))
+}
+
+pub fn get_curve(oid: &asn1::ObjectIdentifier) -> Result<&'static EcCurve> {
+    match available_curves().find(|c| c.oid == *oid) {
+        Some(c) => Ok(c),
+        None => Err(CKR_GENERAL_ERROR)?,
+    }
+}
+
+/// Checks that the curve is known and can be used with the key type
+pub fn curve_supported(
+    oid: &asn1::ObjectIdentifier,
+    key_type: CK_KEY_TYPE,
+) -> bool {
+    match get_curve(oid) {
+        Ok(c) => c.key_type == key_type,
+        Err(_) => false,
+    }
+}
+
 pub fn ec_point_size(oid: &asn1::ObjectIdentifier) -> Result<usize> {
-    match oid {
-        &EC_SECP256R1 => Ok(EC_POINT_BYTES_SECP256R1),
-        &EC_SECP384R1 => Ok(EC_POINT_BYTES_SECP384R1),
-        &EC_SECP521R1 => Ok(EC_POINT_BYTES_SECP521R1),
-        &ED25519_OID => Ok(EC_POINT_BYTES_ED25519),
-        &ED448_OID => Ok(EC_POINT_BYTES_ED448),
-        &X25519_OID => Ok(EC_POINT_BYTES_X25519),
-        &X448_OID => Ok(EC_POINT_BYTES_X448),
-        _ => Err(CKR_GENERAL_ERROR)?,
+    let curve = get_curve(oid)?;
+    let bytes = curve.bits.div_ceil(8);
+    match curve.key_type {
+        /* uncompressed points: 0x04 || x || y */
+        CKK_EC => Ok(2 * bytes + 1),
+        _ => Ok(bytes),
     }
 }
 
 pub fn ec_key_size(oid: &asn1::ObjectIdentifier) -> Result<usize> {
-    match oid {
-        &EC_SECP256R1 => Ok(EC_KEY_BYTES_SECP256R1),
-        &EC_SECP384R1 => Ok(EC_KEY_BYTES_SECP384R1),
-        &EC_SECP521R1 => Ok(EC_KEY_BYTES_SECP521R1),
-        &ED25519_OID => Ok(EC_KEY_BYTES_ED25519),
-        &ED448_OID => Ok(EC_KEY_BYTES_ED448),
-        &X25519_OID => Ok(EC_KEY_BYTES_X25519),
-        &X448_OID => Ok(EC_KEY_BYTES_X448),
-        _ => Err(CKR_GENERAL_ERROR)?,
-    }
+    Ok(get_curve(oid)?.bits.div_ceil(8))
 }
 
 #[cfg(feature = "fips")]
 pub fn oid_to_bits(oid: asn1::ObjectIdentifier) -> Result<usize> {
-    match oid {
-        EC_SECP256R1 => Ok(BITS_SECP256R1),
-        EC_SECP384R1 => Ok(BITS_SECP384R1),
-        EC_SECP521R1 => Ok(BITS_SECP521R1),
-        ED25519_OID => Ok(BITS_ED25519),
-        ED448_OID => Ok(BITS_ED448),
-        X25519_OID => Ok(BITS_X25519),
-        X448_OID => Ok(BITS_X448),
-        _ => Err(CKR_GENERAL_ERROR)?,
-    }
+    Ok(get_curve(&oid)?.bits)
 }
 
-fn curvename_to_oid(name: &str) -> Result<asn1::ObjectIdentifier> {
-    match name {
-        PRIME256V1 => Ok(EC_SECP256R1),
-        SECP384R1 => Ok(EC_SECP384R1),
-        SECP521R1 => Ok(EC_SECP521R1),
-        EDWARDS25519 => Ok(ED25519_OID),
-        EDWARDS448 => Ok(ED448_OID),
-        CURVE25519 => Ok(X25519_OID),
-        CURVE448 => Ok(X448_OID),
-        _ => Err(CKR_GENERAL_ERROR)?,
+pub fn curvename_to_oid(name: &str) -> Result<asn1::ObjectIdentifier> {
+    match available_curves().find(|c| c.name == name) {
+        Some(c) => Ok(c.oid.clone()),
+        None => Err(CKR_GENERAL_ERROR)?,
     }
 }
 
@@ -134,6 +241,90 @@ pub fn get_ec_point_from_obj(key: &Object) -> Result<Vec<u8>> {
     Ok(octet.to_vec())
 }
 
+/* RFC 8410 encoding of the Edwards and Montgomery private keys, used
+ * to wrap them */
+#[cfg(any(feature = "eddsa", feature = "ec_montgomery"))]
+pub fn export_curve_private_key(
+    key: &Object,
+    key_type: CK_KEY_TYPE,
+) -> Result<Vec<u8>> {
+    let oid = get_oid_from_obj(key).map_err(general_error)?;
+    if !curve_supported(&oid, key_type) {
+        Err(CKR_GENERAL_ERROR)?;
+    }
+    let params = match oid {
+        ED25519_OID => AlgorithmParameters::Ed25519,
+        ED448_OID => AlgorithmParameters::Ed448,
+        X25519_OID => AlgorithmParameters::X25519,
+        X448_OID => AlgorithmParameters::X448,
+        _ => return Err(CKR_GENERAL_ERROR)?,
+    };
+    let mut cpkey = asn1::write_single(&CurvePrivateKey::new(
+        key.get_attr_as_bytes(CKA_VALUE)?,
+    )?)
+    .map_err(general_error)?;
+    let result = asn1::write_single(&OneAsymmetricKey::new(params, &cpkey)?)
+        .map_err(general_error);
+    cpkey.zeroize();
+    result
+}
+
+#[cfg(any(feature = "eddsa", feature = "ec_montgomery"))]
+pub fn import_curve_private_key(
+    key: &mut Object,
+    key_type: CK_KEY_TYPE,
+    data: &[u8],
+) -> Result<()> {
+    let (tlv, extra) = match asn1::strip_tlv(data) {
+        Ok(x) => x,
+        Err(_) => return Err(CKR_WRAPPED_KEY_INVALID)?,
+    };
+    /* Some Key Wrapping algorithms may 0 pad to match block size */
+    if !extra.iter().all(|b| *b == 0) {
+        Err(CKR_WRAPPED_KEY_INVALID)?;
+    }
+    let pkey = match tlv.parse::<OneAsymmetricKey>() {
+        Ok(k) => k,
+        Err(_) => return Err(CKR_WRAPPED_KEY_INVALID)?,
+    };
+    /* v2 only adds the optional public key, which is ignored */
+    if pkey.version > 1 {
+        Err(CKR_WRAPPED_KEY_INVALID)?;
+    }
+    let oid = match pkey.private_key_algorithm.params {
+        AlgorithmParameters::Ed25519 => ED25519_OID,
+        AlgorithmParameters::Ed448 => ED448_OID,
+        AlgorithmParameters::X25519 => X25519_OID,
+        AlgorithmParameters::X448 => X448_OID,
+        _ => return Err(CKR_WRAPPED_KEY_INVALID)?,
+    };
+    if !curve_supported(&oid, key_type) {
+        Err(CKR_WRAPPED_KEY_INVALID)?;
+    }
+    let cpkey = match asn1::parse_single::<CurvePrivateKey>(
+        pkey.private_key.as_bytes(),
+    ) {
+        Ok(k) => k,
+        Err(_) => return Err(CKR_WRAPPED_KEY_INVALID)?,
+    };
+    if cpkey.as_bytes().len() != ec_key_size(&oid)? {
+        Err(CKR_WRAPPED_KEY_INVALID)?;
+    }
+
+    let params =
+        asn1::write_single(&ECParameters::OId(oid)).map_err(general_error)?;
+    if !key.check_or_set_attr(Attribute::from_bytes(CKA_EC_PARAMS, params))? {
+        Err(CKR_TEMPLATE_INCONSISTENT)?;
+    }
+    if !key.check_or_set_attr(Attribute::from_bytes(
+        CKA_VALUE,
+        cpkey.as_bytes().to_vec(),
+    ))? {
+        Err(CKR_TEMPLATE_INCONSISTENT)?;
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 pub fn curvename_to_key_size(name: &str) -> Result<usize> {
     ec_key_size(&curvename_to_oid(name)?)
@@ -184,16 +375,15 @@ pub fn check_ec_point_from_obj(
 
     let octet: &[u8];
     let compat: bool;
-    match oid {
-        &EC_SECP256R1 | &EC_SECP384R1 | &EC_SECP521R1 => {
+    match get_curve(oid)?.key_type {
+        CKK_EC => {
             octet = asn1::parse_single::<&[u8]>(point).map_err(device_error)?;
             compat = false;
         }
-        &ED25519_OID | &ED448_OID | &X25519_OID | &X448_OID => {
+        _ => {
             octet = point.as_slice();
             compat = true;
         }
-        _ => return Err(CKR_GENERAL_ERROR)?,
     }
 
     if octet.len() == size {
@@ -12,6 +12,8 @@ use crate::kasn1::oid;
 use crate::mechanism::*;
 use crate::object::*;
 use crate::ossl::montgomery;
+#[cfg(not(feature = "fips"))]
+use crate::ossl::xeddsa::XEddsaOperation;
 
 use once_cell::sync::Lazy;
 
@@ -191,7 +193,37 @@ impl ObjectFactory for ECMontgomeryPrivFactory {
 
 impl CommonKeyFactory for ECMontgomeryPrivFactory {}
 
-impl PrivKeyFactory for ECMontgomeryPrivFactory {}
+impl PrivKeyFactory for ECMontgomeryPrivFactory {
+    fn export_for_wrapping(&self, key: &Object) -> Result<Vec<u8>> {
+        key.check_key_ops(CKO_PRIVATE_KEY, CKK_EC_MONTGOMERY, CKA_EXTRACTABLE)?;
+        export_curve_private_key(key, CKK_EC_MONTGOMERY)
+    }
+
+    fn import_from_wrapped(
+        &self,
+        data: Vec<u8>,
+        template: &[CK_ATTRIBUTE],
+    ) -> Result<Object> {
+        let mut key = self.default_object_unwrap(template)?;
+
+        if !key.check_or_set_attr(Attribute::from_ulong(
+            CKA_CLASS,
+            CKO_PRIVATE_KEY,
+        ))? {
+            return Err(CKR_TEMPLATE_INCONSISTENT)?;
+        }
+        if !key.check_or_set_attr(Attribute::from_ulong(
+            CKA_KEY_TYPE,
+            CKK_EC_MONTGOMERY,
+        ))? {
+            return Err(CKR_TEMPLATE_INCONSISTENT)?;
+        }
+
+        import_curve_private_key(&mut key, CKK_EC_MONTGOMERY, &data)?;
+
+        Ok(key)
+    }
+}
 
 static PUBLIC_KEY_FACTORY: Lazy<Box<dyn ObjectFactory>> =
     Lazy::new(|| Box::new(ECMontgomeryPubFactory::new()));
@@ -206,8 +238,6 @@ struct ECMontgomeryMechanism {
 
 impl ECMontgomeryMechanism {
     fn register_mechanisms(mechs: &mut Mechanisms) {
-        /* TODO PKCS #11 defines also CKM_XEDDSA for signatures, but it is not implemented by
-         * OpenSSL */
         mechs.add_mechanism(
             CKM_EC_MONTGOMERY_KEY_PAIR_GEN,
             Box::new(ECMontgomeryMechanism {
@@ -224,6 +254,26 @@ impl ECMontgomeryMechanism {
                 },
             }),
         );
+
+        /* XEdDSA is not offered by OpenSSL as such, it is built on
+         * top of the OpenSSL elliptic curve arithmetic */
+        #[cfg(not(feature = "fips"))]
+        mechs.add_mechanism(
+            CKM_XEDDSA,
+            Box::new(ECMontgomeryMechanism {
+                info: CK_MECHANISM_INFO {
+                    ulMinKeySize: CK_ULONG::try_from(
+                        MIN_EC_MONTGOMERY_SIZE_BITS,
+                    )
+                    .unwrap(),
+                    ulMaxKeySize: CK_ULONG::try_from(
+                        MAX_EC_MONTGOMERY_SIZE_BITS,
+                    )
+                    .unwrap(),
+                    flags: CKF_SIGN | CKF_VERIFY,
+                },
+            }),
+        );
     }
 }
 
@@ -232,6 +282,32 @@ impl Mechanism for ECMontgomeryMechanism {
         &self.info
     }
 
+    #[cfg(not(feature = "fips"))]
+    fn sign_new(
+        &self,
+        mech: &CK_MECHANISM,
+        key: &Object,
+    ) -> Result<Box<dyn Sign>> {
+        if self.info.flags & CKF_SIGN != CKF_SIGN {
+            return Err(CKR_MECHANISM_INVALID)?;
+        }
+        key.check_key_ops(CKO_PRIVATE_KEY, CKK_EC_MONTGOMERY, CKA_SIGN)?;
+        Ok(Box::new(XEddsaOperation::sign_new(mech, key)?))
+    }
+
+    #[cfg(not(feature = "fips"))]
+    fn verify_new(
+        &self,
+        mech: &CK_MECHANISM,
+        key: &Object,
+    ) -> Result<Box<dyn Verify>> {
+        if self.info.flags & CKF_VERIFY != CKF_VERIFY {
+            return Err(CKR_MECHANISM_INVALID)?;
+        }
+        key.check_key_ops(CKO_PUBLIC_KEY, CKK_EC_MONTGOMERY, CKA_VERIFY)?;
+        Ok(Box::new(XEddsaOperation::verify_new(mech, key)?))
+    }
+
     fn generate_keypair(
         &self,
         mech: &CK_MECHANISM,
@@ -13,9 +13,18 @@ compile_error!("Feature 'ecdh' requires either 'ecdsa' or 'ec_montgomery'");
 #[cfg(feature = "aes")]
 mod aes;
 
+#[cfg(feature = "chacha20")]
+mod chacha20;
+
+#[cfg(feature = "dsa")]
+mod dsa;
+
 #[cfg(feature = "ecc")]
 mod ec;
 
+#[cfg(feature = "ffdh")]
+mod ffdh;
+
 #[cfg(feature = "hash")]
 mod hash;
 
@@ -25,12 +34,24 @@ mod hkdf;
 #[cfg(feature = "hmac")]
 mod hmac;
 
+#[cfg(feature = "hss")]
+mod hss;
+
+#[cfg(feature = "mldsa")]
+mod mldsa;
+
+#[cfg(feature = "mlkem")]
+mod mlkem;
+
 #[cfg(feature = "pbkdf2")]
 mod pbkdf2;
 
 #[cfg(feature = "rsa")]
 mod rsa;
 
+#[cfg(feature = "slhdsa")]
+mod slhdsa;
+
 #[cfg(feature = "sp800_108")]
 mod sp800_108;
 
@@ -49,6 +70,12 @@ pub fn register_all(mechs: &mut Mechanisms, ot: &mut ObjectFactories) {
     #[cfg(feature = "aes")]
     aes::register(mechs, ot);
 
+    #[cfg(feature = "chacha20")]
+    chacha20::register(mechs, ot);
+
+    #[cfg(feature = "dsa")]
+    dsa::register(mechs, ot);
+
     #[cfg(feature = "ecdsa")]
     ec::ecdsa::register(mechs, ot);
 
@@ -61,6 +88,9 @@ pub fn register_all(mechs: &mut Mechanisms, ot: &mut ObjectFactories) {
     #[cfg(feature = "eddsa")]
     ec::eddsa::register(mechs, ot);
 
+    #[cfg(feature = "ffdh")]
+    ffdh::register(mechs, ot);
+
     #[cfg(feature = "hash")]
     hash::register(mechs, ot);
 
@@ -70,12 +100,24 @@ pub fn register_all(mechs: &mut Mechanisms, ot: &mut ObjectFactories) {
     #[cfg(feature = "hmac")]
     hmac::register(mechs, ot);
 
+    #[cfg(feature = "hss")]
+    hss::register(mechs, ot);
+
+    #[cfg(feature = "mldsa")]
+    mldsa::register(mechs, ot);
+
+    #[cfg(feature = "mlkem")]
+    mlkem::register(mechs, ot);
+
     #[cfg(feature = "pbkdf2")]
     pbkdf2::register(mechs, ot);
 
     #[cfg(feature = "rsa")]
     rsa::register(mechs, ot);
 
+    #[cfg(feature = "slhdsa")]
+    slhdsa::register(mechs, ot);
+
     #[cfg(feature = "sp800_108")]
     sp800_108::register(mechs, ot);
 
@@ -0,0 +1,477 @@
+// Copyright 2024 Simo Sorce
+// See LICENSE.txt file for terms
+
+use std::fmt::Debug;
+
+use crate::attribute::Attribute;
+use crate::error::Result;
+use crate::interface::*;
+use crate::mechanism::*;
+use crate::misc::value_bits;
+use crate::object::*;
+use crate::ossl::ffdh::{ffdh_generate_domain, FFDHOperation};
+use crate::{attr_element, bytes_attr_not_empty, bytes_to_vec, cast_params};
+
+use once_cell::sync::Lazy;
+
+pub const MIN_DH_SIZE_BITS: usize = 1024;
+pub const MAX_DH_SIZE_BITS: usize = 8192;
+
+/* Checks the domain parameters of a key, the size of the prime is the
+ * size of the key */
+fn ffdh_check_domain(obj: &Object) -> Result<()> {
+    let prime = match obj.get_attr_as_bytes(CKA_PRIME) {
+        Ok(p) => p,
+        Err(_) => return Err(CKR_TEMPLATE_INCOMPLETE)?,
+    };
+    let bits = usize::try_from(value_bits(prime))?;
+    if !(MIN_DH_SIZE_BITS..=MAX_DH_SIZE_BITS).contains(&bits) {
+        Err(CKR_ATTRIBUTE_VALUE_INVALID)?;
+    }
+    bytes_attr_not_empty!(obj; CKA_BASE);
+    if obj.get_attr_as_ulong(CKA_KEY_TYPE)? == CKK_X9_42_DH {
+        bytes_attr_not_empty!(obj; CKA_SUBPRIME);
+    }
+    Ok(())
+}
+
+fn ffdh_check_import(obj: &Object) -> Result<()> {
+    ffdh_check_domain(obj)?;
+    bytes_attr_not_empty!(obj; CKA_VALUE);
+    Ok(())
+}
+
+#[derive(Debug)]
+pub struct FFDHPubFactory {
+    attributes: Vec<ObjectAttr>,
+}
+
+impl FFDHPubFactory {
+    pub fn new(key_type: CK_KEY_TYPE) -> FFDHPubFactory {
+        let mut data: FFDHPubFactory = FFDHPubFactory {
+            attributes: Vec::new(),
+        };
+        data.attributes.append(&mut data.init_common_object_attrs());
+        data.attributes
+            .append(&mut data.init_common_storage_attrs());
+        data.attributes.append(&mut data.init_common_key_attrs());
+        data.attributes
+            .append(&mut data.init_common_public_key_attrs());
+        data.attributes.push(attr_element!(
+            CKA_PRIME; OAFlags::RequiredOnCreate | OAFlags::RequiredOnGenerate
+            | OAFlags::Unchangeable; Attribute::from_bytes; val Vec::new()));
+        data.attributes.push(attr_element!(
+            CKA_BASE; OAFlags::RequiredOnCreate | OAFlags::RequiredOnGenerate
+            | OAFlags::Unchangeable; Attribute::from_bytes; val Vec::new()));
+        if key_type == CKK_X9_42_DH {
+            data.attributes.push(attr_element!(
+                CKA_SUBPRIME; OAFlags::RequiredOnCreate
+                | OAFlags::RequiredOnGenerate | OAFlags::Unchangeable;
+                Attribute::from_bytes; val Vec::new()));
+        }
+        data.attributes.push(attr_element!(
+            CKA_VALUE; OAFlags::RequiredOnCreate
+            | OAFlags::SettableOnlyOnCreate | OAFlags::Unchangeable;
+            Attribute::from_bytes; val Vec::new()));
+        data
+    }
+}
+
+impl ObjectFactory for FFDHPubFactory {
+    fn create(&self, template: &[CK_ATTRIBUTE]) -> Result<Object> {
+        let obj = self.default_object_create(template)?;
+
+        ffdh_check_import(&obj)?;
+
+        Ok(obj)
+    }
+
+    fn get_attributes(&self) -> &Vec<ObjectAttr> {
+        &self.attributes
+    }
+}
+
+impl CommonKeyFactory for FFDHPubFactory {}
+
+impl PubKeyFactory for FFDHPubFactory {}
+
+#[derive(Debug)]
+pub struct FFDHPrivFactory {
+    attributes: Vec<ObjectAttr>,
+}
+
+impl FFDHPrivFactory {
+    pub fn new(key_type: CK_KEY_TYPE) -> FFDHPrivFactory {
+        let mut data: FFDHPrivFactory = FFDHPrivFactory {
+            attributes: Vec::new(),
+        };
+        data.attributes.append(&mut data.init_common_object_attrs());
+        data.attributes
+            .append(&mut data.init_common_storage_attrs());
+        data.attributes.append(&mut data.init_common_key_attrs());
+        data.attributes
+            .append(&mut data.init_common_private_key_attrs());
+        data.attributes.push(attr_element!(
+            CKA_PRIME; OAFlags::RequiredOnCreate | OAFlags::Unchangeable;
+            Attribute::from_bytes; val Vec::new()));
+        data.attributes.push(attr_element!(
+            CKA_BASE; OAFlags::RequiredOnCreate | OAFlags::Unchangeable;
+            Attribute::from_bytes; val Vec::new()));
+        if key_type == CKK_X9_42_DH {
+            data.attributes.push(attr_element!(
+                CKA_SUBPRIME; OAFlags::RequiredOnCreate
+                | OAFlags::Unchangeable; Attribute::from_bytes;
+                val Vec::new()));
+        } else {
+            /* Can be requested on generation, and is always computed
+             * from the private value on import */
+            data.attributes.push(attr_element!(
+                CKA_VALUE_BITS; OAFlags::Unchangeable;
+                Attribute::from_ulong; val 0));
+        }
+        data.attributes.push(attr_element!(
+            CKA_VALUE; OAFlags::Sensitive | OAFlags::RequiredOnCreate
+            | OAFlags::SettableOnlyOnCreate | OAFlags::Unchangeable;
+            Attribute::from_bytes; val Vec::new()));
+
+        /* default to private */
+        let private = attr_element!(
+            CKA_PRIVATE; OAFlags::Defval | OAFlags::ChangeOnCopy;
+            Attribute::from_bool; val true);
+        match data
+            .attributes
+            .iter()
+            .position(|x| x.get_type() == CKA_PRIVATE)
+        {
+            Some(idx) => data.attributes[idx] = private,
+            None => data.attributes.push(private),
+        }
+
+        data
+    }
+}
+
+impl ObjectFactory for FFDHPrivFactory {
+    fn create(&self, template: &[CK_ATTRIBUTE]) -> Result<Object> {
+        let mut obj = self.default_object_create(template)?;
+
+        ffdh_check_import(&obj)?;
+
+        if obj.get_attr_as_ulong(CKA_KEY_TYPE)? == CKK_DH {
+            match obj.get_attr_as_ulong(CKA_VALUE_BITS) {
+                Ok(_) => return Err(CKR_ATTRIBUTE_VALUE_INVALID)?,
+                Err(e) => {
+                    if !e.attr_not_found() {
+                        return Err(e);
+                    }
+                }
+            }
+            let bits = value_bits(obj.get_attr_as_bytes(CKA_VALUE)?);
+            obj.set_attr(Attribute::from_ulong(CKA_VALUE_BITS, bits))?;
+        }
+
+        Ok(obj)
+    }
+
+    fn get_attributes(&self) -> &Vec<ObjectAttr> {
+        &self.attributes
+    }
+}
+
+impl CommonKeyFactory for FFDHPrivFactory {}
+
+impl PrivKeyFactory for FFDHPrivFactory {}
+
+#[derive(Debug)]
+pub struct FFDHDomainParamsFactory {
+    attributes: Vec<ObjectAttr>,
+}
+
+impl FFDHDomainParamsFactory {
+    pub fn new(key_type: CK_KEY_TYPE) -> FFDHDomainParamsFactory {
+        let mut data: FFDHDomainParamsFactory = FFDHDomainParamsFactory {
+            attributes: Vec::new(),
+        };
+        data.attributes.append(&mut data.init_common_object_attrs());
+        data.attributes
+            .append(&mut data.init_common_storage_attrs());
+        data.attributes
+            .append(&mut data.init_common_domain_params_attrs());
+        data.attributes.push(attr_element!(
+            CKA_PRIME; OAFlags::RequiredOnCreate
+            | OAFlags::SettableOnlyOnCreate | OAFlags::Unchangeable;
+            Attribute::from_bytes; val Vec::new()));
+        data.attributes.push(attr_element!(
+            CKA_BASE; OAFlags::RequiredOnCreate
+            | OAFlags::SettableOnlyOnCreate | OAFlags::Unchangeable;
+            Attribute::from_bytes; val Vec::new()));
+        data.attributes.push(attr_element!(
+            CKA_PRIME_BITS; OAFlags::RequiredOnGenerate
+            | OAFlags::Unchangeable; Attribute::from_bytes; val Vec::new()));
+        if key_type == CKK_X9_42_DH {
+            data.attributes.push(attr_element!(
+                CKA_SUBPRIME; OAFlags::RequiredOnCreate
+                | OAFlags::SettableOnlyOnCreate | OAFlags::Unchangeable;
+                Attribute::from_bytes; val Vec::new()));
+            data.attributes.push(attr_element!(
+                CKA_SUBPRIME_BITS; OAFlags::Unchangeable;
+                Attribute::from_ulong; val 0));
+        }
+        data
+    }
+}
+
+impl ObjectFactory for FFDHDomainParamsFactory {
+    fn create(&self, template: &[CK_ATTRIBUTE]) -> Result<Object> {
+        let mut obj = self.default_object_create(template)?;
+
+        ffdh_check_domain(&obj)?;
+
+        /* The sizes are always computed from the parameters */
+        if obj.get_attr(CKA_PRIME_BITS).is_some() {
+            return Err(CKR_ATTRIBUTE_VALUE_INVALID)?;
+        }
+        let bits = value_bits(obj.get_attr_as_bytes(CKA_PRIME)?);
+        obj.set_attr(prime_bits_attribute(bits))?;
+        if obj.get_attr_as_ulong(CKA_KEY_TYPE)? == CKK_X9_42_DH {
+            if obj.get_attr(CKA_SUBPRIME_BITS).is_some() {
+                return Err(CKR_ATTRIBUTE_VALUE_INVALID)?;
+            }
+            let bits = value_bits(obj.get_attr_as_bytes(CKA_SUBPRIME)?);
+            obj.set_attr(Attribute::from_ulong(CKA_SUBPRIME_BITS, bits))?;
+        }
+
+        Ok(obj)
+    }
+
+    fn get_attributes(&self) -> &Vec<ObjectAttr> {
+        &self.attributes
+    }
+}
+
+impl DomainParamsFactory for FFDHDomainParamsFactory {}
+
+static DH_PUBLIC_KEY_FACTORY: Lazy<Box<dyn ObjectFactory>> =
+    Lazy::new(|| Box::new(FFDHPubFactory::new(CKK_DH)));
+
+static DH_PRIVATE_KEY_FACTORY: Lazy<Box<dyn ObjectFactory>> =
+    Lazy::new(|| Box::new(FFDHPrivFactory::new(CKK_DH)));
+
+static X9_42_DH_PUBLIC_KEY_FACTORY: Lazy<Box<dyn ObjectFactory>> =
+    Lazy::new(|| Box::new(FFDHPubFactory::new(CKK_X9_42_DH)));
+
+static X9_42_DH_PRIVATE_KEY_FACTORY: Lazy<Box<dyn ObjectFactory>> =
+    Lazy::new(|| Box::new(FFDHPrivFactory::new(CKK_X9_42_DH)));
+
+static DH_DOMAIN_PARAMS_FACTORY: Lazy<Box<dyn ObjectFactory>> =
+    Lazy::new(|| Box::new(FFDHDomainParamsFactory::new(CKK_DH)));
+
+static X9_42_DH_DOMAIN_PARAMS_FACTORY: Lazy<Box<dyn ObjectFactory>> =
+    Lazy::new(|| Box::new(FFDHDomainParamsFactory::new(CKK_X9_42_DH)));
+
+#[derive(Debug)]
+struct FFDHMechanism {
+    info: CK_MECHANISM_INFO,
+}
+
+impl FFDHMechanism {
+    fn new_mechanism(flags: CK_FLAGS) -> Box<dyn Mechanism> {
+        Box::new(FFDHMechanism {
+            info: CK_MECHANISM_INFO {
+                ulMinKeySize: CK_ULONG::try_from(MIN_DH_SIZE_BITS).unwrap(),
+                ulMaxKeySize: CK_ULONG::try_from(MAX_DH_SIZE_BITS).unwrap(),
+                flags,
+            },
+        })
+    }
+
+    fn register_mechanisms(mechs: &mut Mechanisms) {
+        for ckm in &[CKM_DH_PKCS_PARAMETER_GEN, CKM_X9_42_DH_PARAMETER_GEN] {
+            mechs.add_mechanism(*ckm, Self::new_mechanism(CKF_GENERATE));
+        }
+        for ckm in &[CKM_DH_PKCS_KEY_PAIR_GEN, CKM_X9_42_DH_KEY_PAIR_GEN] {
+            mechs.add_mechanism(
+                *ckm,
+                Self::new_mechanism(CKF_GENERATE_KEY_PAIR),
+            );
+        }
+        for ckm in &[CKM_DH_PKCS_DERIVE, CKM_X9_42_DH_DERIVE] {
+            mechs.add_mechanism(*ckm, Self::new_mechanism(CKF_DERIVE));
+        }
+    }
+}
+
+impl Mechanism for FFDHMechanism {
+    fn info(&self) -> &CK_MECHANISM_INFO {
+        &self.info
+    }
+
+    fn generate_key(
+        &self,
+        mech: &CK_MECHANISM,
+        template: &[CK_ATTRIBUTE],
+        _: &Mechanisms,
+        _: &ObjectFactories,
+    ) -> Result<Object> {
+        let (key_type, factory) = match mech.mechanism {
+            CKM_DH_PKCS_PARAMETER_GEN => (CKK_DH, &DH_DOMAIN_PARAMS_FACTORY),
+            CKM_X9_42_DH_PARAMETER_GEN => {
+                (CKK_X9_42_DH, &X9_42_DH_DOMAIN_PARAMS_FACTORY)
+            }
+            _ => return Err(CKR_MECHANISM_INVALID)?,
+        };
+
+        let mut params = factory.default_object_generate(template)?;
+        if !params.check_or_set_attr(Attribute::from_ulong(
+            CKA_CLASS,
+            CKO_DOMAIN_PARAMETERS,
+        ))? {
+            return Err(CKR_TEMPLATE_INCONSISTENT)?;
+        }
+        if !params
+            .check_or_set_attr(Attribute::from_ulong(CKA_KEY_TYPE, key_type))?
+        {
+            return Err(CKR_TEMPLATE_INCONSISTENT)?;
+        }
+        let bits = get_prime_bits(&params)?;
+        if !(MIN_DH_SIZE_BITS..=MAX_DH_SIZE_BITS).contains(&bits) {
+            return Err(CKR_KEY_SIZE_RANGE)?;
+        }
+
+        ffdh_generate_domain(&mut params)?;
+        params.set_attr(Attribute::from_bool(CKA_LOCAL, true))?;
+
+        Ok(params)
+    }
+
+    fn generate_keypair(
+        &self,
+        mech: &CK_MECHANISM,
+        pubkey_template: &[CK_ATTRIBUTE],
+        prikey_template: &[CK_ATTRIBUTE],
+    ) -> Result<(Object, Object)> {
+        let (key_type, pub_factory, priv_factory) = match mech.mechanism {
+            CKM_DH_PKCS_KEY_PAIR_GEN => {
+                (CKK_DH, &DH_PUBLIC_KEY_FACTORY, &DH_PRIVATE_KEY_FACTORY)
+            }
+            CKM_X9_42_DH_KEY_PAIR_GEN => (
+                CKK_X9_42_DH,
+                &X9_42_DH_PUBLIC_KEY_FACTORY,
+                &X9_42_DH_PRIVATE_KEY_FACTORY,
+            ),
+            _ => return Err(CKR_MECHANISM_INVALID)?,
+        };
+
+        let mut pubkey =
+            pub_factory.default_object_generate(pubkey_template)?;
+        if !pubkey.check_or_set_attr(Attribute::from_ulong(
+            CKA_CLASS,
+            CKO_PUBLIC_KEY,
+        ))? {
+            return Err(CKR_TEMPLATE_INCONSISTENT)?;
+        }
+        if !pubkey
+            .check_or_set_attr(Attribute::from_ulong(CKA_KEY_TYPE, key_type))?
+        {
+            return Err(CKR_TEMPLATE_INCONSISTENT)?;
+        }
+
+        let mut privkey =
+            priv_factory.default_object_generate(prikey_template)?;
+        if !privkey.check_or_set_attr(Attribute::from_ulong(
+            CKA_CLASS,
+            CKO_PRIVATE_KEY,
+        ))? {
+            return Err(CKR_TEMPLATE_INCONSISTENT)?;
+        }
+        if !privkey
+            .check_or_set_attr(Attribute::from_ulong(CKA_KEY_TYPE, key_type))?
+        {
+            return Err(CKR_TEMPLATE_INCONSISTENT)?;
+        }
+
+        /* the domain parameters come from the public key template */
+        ffdh_check_domain(&pubkey)?;
+        let mut domain = vec![CKA_PRIME, CKA_BASE];
+        if key_type == CKK_X9_42_DH {
+            domain.push(CKA_SUBPRIME);
+        }
+        for attr in domain {
+            let value = pubkey.get_attr_as_bytes(attr)?.clone();
+            if !privkey.check_or_set_attr(Attribute::from_bytes(attr, value))? {
+                return Err(CKR_TEMPLATE_INCONSISTENT)?;
+            }
+        }
+
+        FFDHOperation::generate_keypair(&mut pubkey, &mut privkey)?;
+        default_key_attributes(&mut privkey, mech.mechanism)?;
+        default_key_attributes(&mut pubkey, mech.mechanism)?;
+
+        /* Marks private keys generated on an approved group, so they can
+         * be used in approved key agreements */
+        #[cfg(feature = "fips")]
+        crate::fips::indicators::is_approved(
+            mech.mechanism,
+            CKF_GENERATE_KEY_PAIR,
+            None,
+            Some(&mut privkey),
+        );
+
+        Ok((pubkey, privkey))
+    }
+
+    fn derive_operation(&self, mech: &CK_MECHANISM) -> Result<Operation> {
+        if self.info.flags & CKF_DERIVE != CKF_DERIVE {
+            return Err(CKR_MECHANISM_INVALID)?;
+        }
+        let kdf = match mech.mechanism {
+            /* The parameter is the public value of the other party */
+            CKM_DH_PKCS_DERIVE => FFDHOperation::derive_new(
+                mech.mechanism,
+                CKD_NULL,
+                bytes_to_vec!(mech.pParameter, mech.ulParameterLen),
+                Vec::new(),
+            )?,
+            CKM_X9_42_DH_DERIVE => {
+                let params = cast_params!(mech, CK_X9_42_DH1_DERIVE_PARAMS);
+                FFDHOperation::derive_new(
+                    mech.mechanism,
+                    params.kdf,
+                    bytes_to_vec!(params.pPublicData, params.ulPublicDataLen),
+                    bytes_to_vec!(params.pOtherInfo, params.ulOtherInfoLen),
+                )?
+            }
+            _ => return Err(CKR_MECHANISM_INVALID)?,
+        };
+        Ok(Operation::Derive(Box::new(kdf)))
+    }
+}
+
+pub fn register(mechs: &mut Mechanisms, ot: &mut ObjectFactories) {
+    FFDHMechanism::register_mechanisms(mechs);
+
+    ot.add_factory(
+        ObjectType::new(CKO_PUBLIC_KEY, CKK_DH),
+        &DH_PUBLIC_KEY_FACTORY,
+    );
+    ot.add_factory(
+        ObjectType::new(CKO_PRIVATE_KEY, CKK_DH),
+        &DH_PRIVATE_KEY_FACTORY,
+    );
+    ot.add_factory(
+        ObjectType::new(CKO_PUBLIC_KEY, CKK_X9_42_DH),
+        &X9_42_DH_PUBLIC_KEY_FACTORY,
+    );
+    ot.add_factory(
+        ObjectType::new(CKO_PRIVATE_KEY, CKK_X9_42_DH),
+        &X9_42_DH_PRIVATE_KEY_FACTORY,
+    );
+    ot.add_factory(
+        ObjectType::new(CKO_DOMAIN_PARAMETERS, CKK_DH),
+        &DH_DOMAIN_PARAMS_FACTORY,
+    );
+    ot.add_factory(
+        ObjectType::new(CKO_DOMAIN_PARAMETERS, CKK_X9_42_DH),
+        &X9_42_DH_DOMAIN_PARAMS_FACTORY,
+    );
+}
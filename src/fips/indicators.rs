@@ -6,7 +6,10 @@ use crate::attribute::Attribute;
 use crate::ec::{get_oid_from_obj, oid_to_bits};
 use crate::error::Result;
 use crate::interface::*;
+use crate::misc::value_bits;
 use crate::object::{OAFlags, Object, ObjectAttr, ObjectFactory};
+#[cfg(feature = "ffdh")]
+use crate::ossl::ffdh::ffdh_approved_group_bits;
 use crate::Token;
 
 use once_cell::sync::Lazy;
@@ -244,8 +247,8 @@ struct FipsMechanism {
 }
 
 struct FipsChecks {
-    keys: [FipsKeyType; 15],
-    mechs: [FipsMechanism; 67],
+    keys: [FipsKeyType; 19],
+    mechs: [FipsMechanism; 93],
 }
 
 /* TODO: double check the values, this is just an initial
@@ -273,6 +276,18 @@ const FIPS_CHECKS: FipsChecks = FipsChecks {
             operations: CKF_SIGN | CKF_VERIFY | CKF_DERIVE,
             sizes: step!(256, 384, 521),
         },
+        /* DSA is only allowed to verify legacy signatures */
+        FipsKeyType {
+            keytype: CKK_DSA,
+            operations: CKF_VERIFY,
+            sizes: step!(1024, 2048, 3072),
+        },
+        /* Only safe-prime groups are approved, see check_key() */
+        FipsKeyType {
+            keytype: CKK_DH,
+            operations: CKF_DERIVE,
+            sizes: range!(2048, 8192),
+        },
         FipsKeyType {
             keytype: CKK_AES,
             operations: CKF_SIGN
@@ -283,6 +298,12 @@ const FIPS_CHECKS: FipsChecks = FipsChecks {
                 | CKF_UNWRAP,
             sizes: step!(128, 192, 256),
         },
+        /* SP 800-38E, XTS-AES is approved only for storage encryption */
+        FipsKeyType {
+            keytype: CKK_AES_XTS,
+            operations: CKF_ENCRYPT | CKF_DECRYPT,
+            sizes: step!(256, 512),
+        },
         FipsKeyType {
             keytype: CKK_GENERIC_SECRET,
             operations: CKF_SIGN | CKF_VERIFY | CKF_DERIVE,
@@ -338,6 +359,20 @@ const FIPS_CHECKS: FipsChecks = FipsChecks {
             operations: CKF_DERIVE,
             sizes: range!(112, 512),
         },
+        /* ML-DSA keys are checked by parameter set, not size */
+        FipsKeyType {
+            keytype: CKK_ML_DSA,
+            operations: CKF_SIGN | CKF_VERIFY,
+            sizes: (
+                [
+                    CKP_ML_DSA_44 as usize,
+                    CKP_ML_DSA_65 as usize,
+                    CKP_ML_DSA_87 as usize,
+                    0,
+                ],
+                (0, 0),
+            ),
+        },
     ],
     mechs: [
         /* RSA */
@@ -503,6 +538,156 @@ const FIPS_CHECKS: FipsChecks = FipsChecks {
             restrictions: [restrict!(CKK_RSA), restrict!()],
             genflags: 0,
         },
+        /* DSA, legacy signature verification only */
+        FipsMechanism {
+            mechanism: CKM_DSA,
+            operations: CKF_VERIFY,
+            restrictions: [restrict!(CKK_DSA), restrict!()],
+            genflags: 0,
+        },
+        FipsMechanism {
+            mechanism: CKM_DSA_SHA1,
+            operations: CKF_VERIFY,
+            restrictions: [restrict!(CKK_DSA), restrict!()],
+            genflags: 0,
+        },
+        FipsMechanism {
+            mechanism: CKM_DSA_SHA224,
+            operations: CKF_VERIFY,
+            restrictions: [restrict!(CKK_DSA), restrict!()],
+            genflags: 0,
+        },
+        FipsMechanism {
+            mechanism: CKM_DSA_SHA256,
+            operations: CKF_VERIFY,
+            restrictions: [restrict!(CKK_DSA), restrict!()],
+            genflags: 0,
+        },
+        FipsMechanism {
+            mechanism: CKM_DSA_SHA384,
+            operations: CKF_VERIFY,
+            restrictions: [restrict!(CKK_DSA), restrict!()],
+            genflags: 0,
+        },
+        FipsMechanism {
+            mechanism: CKM_DSA_SHA512,
+            operations: CKF_VERIFY,
+            restrictions: [restrict!(CKK_DSA), restrict!()],
+            genflags: 0,
+        },
+        FipsMechanism {
+            mechanism: CKM_DSA_SHA3_224,
+            operations: CKF_VERIFY,
+            restrictions: [restrict!(CKK_DSA), restrict!()],
+            genflags: 0,
+        },
+        FipsMechanism {
+            mechanism: CKM_DSA_SHA3_256,
+            operations: CKF_VERIFY,
+            restrictions: [restrict!(CKK_DSA), restrict!()],
+            genflags: 0,
+        },
+        FipsMechanism {
+            mechanism: CKM_DSA_SHA3_384,
+            operations: CKF_VERIFY,
+            restrictions: [restrict!(CKK_DSA), restrict!()],
+            genflags: 0,
+        },
+        FipsMechanism {
+            mechanism: CKM_DSA_SHA3_512,
+            operations: CKF_VERIFY,
+            restrictions: [restrict!(CKK_DSA), restrict!()],
+            genflags: 0,
+        },
+        /* FFDH */
+        FipsMechanism {
+            mechanism: CKM_DH_PKCS_KEY_PAIR_GEN,
+            operations: CKF_GENERATE_KEY_PAIR,
+            restrictions: [restrict!(CKK_DH), restrict!()],
+            genflags: CKF_DERIVE,
+        },
+        FipsMechanism {
+            mechanism: CKM_DH_PKCS_DERIVE,
+            operations: CKF_DERIVE,
+            restrictions: [
+                restrict!(CKK_GENERIC_SECRET, range!(112, 8192)),
+                restrict!(CKK_AES),
+            ],
+            genflags: CKF_SIGN
+                | CKF_VERIFY
+                | CKF_ENCRYPT
+                | CKF_DECRYPT
+                | CKF_WRAP
+                | CKF_UNWRAP
+                | CKF_DERIVE,
+        },
+        /* ML-DSA */
+        FipsMechanism {
+            mechanism: CKM_ML_DSA_KEY_PAIR_GEN,
+            operations: CKF_GENERATE_KEY_PAIR,
+            restrictions: [restrict!(CKK_ML_DSA), restrict!()],
+            genflags: CKF_SIGN | CKF_VERIFY,
+        },
+        FipsMechanism {
+            mechanism: CKM_ML_DSA,
+            operations: CKF_SIGN | CKF_VERIFY,
+            restrictions: [restrict!(CKK_ML_DSA), restrict!()],
+            genflags: 0,
+        },
+        FipsMechanism {
+            mechanism: CKM_HASH_ML_DSA,
+            operations: CKF_SIGN | CKF_VERIFY,
+            restrictions: [restrict!(CKK_ML_DSA), restrict!()],
+            genflags: 0,
+        },
+        FipsMechanism {
+            mechanism: CKM_HASH_ML_DSA_SHA224,
+            operations: CKF_SIGN | CKF_VERIFY,
+            restrictions: [restrict!(CKK_ML_DSA), restrict!()],
+            genflags: 0,
+        },
+        FipsMechanism {
+            mechanism: CKM_HASH_ML_DSA_SHA256,
+            operations: CKF_SIGN | CKF_VERIFY,
+            restrictions: [restrict!(CKK_ML_DSA), restrict!()],
+            genflags: 0,
+        },
+        FipsMechanism {
+            mechanism: CKM_HASH_ML_DSA_SHA384,
+            operations: CKF_SIGN | CKF_VERIFY,
+            restrictions: [restrict!(CKK_ML_DSA), restrict!()],
+            genflags: 0,
+        },
+        FipsMechanism {
+            mechanism: CKM_HASH_ML_DSA_SHA512,
+            operations: CKF_SIGN | CKF_VERIFY,
+            restrictions: [restrict!(CKK_ML_DSA), restrict!()],
+            genflags: 0,
+        },
+        FipsMechanism {
+            mechanism: CKM_HASH_ML_DSA_SHA3_224,
+            operations: CKF_SIGN | CKF_VERIFY,
+            restrictions: [restrict!(CKK_ML_DSA), restrict!()],
+            genflags: 0,
+        },
+        FipsMechanism {
+            mechanism: CKM_HASH_ML_DSA_SHA3_256,
+            operations: CKF_SIGN | CKF_VERIFY,
+            restrictions: [restrict!(CKK_ML_DSA), restrict!()],
+            genflags: 0,
+        },
+        FipsMechanism {
+            mechanism: CKM_HASH_ML_DSA_SHA3_384,
+            operations: CKF_SIGN | CKF_VERIFY,
+            restrictions: [restrict!(CKK_ML_DSA), restrict!()],
+            genflags: 0,
+        },
+        FipsMechanism {
+            mechanism: CKM_HASH_ML_DSA_SHA3_512,
+            operations: CKF_SIGN | CKF_VERIFY,
+            restrictions: [restrict!(CKK_ML_DSA), restrict!()],
+            genflags: 0,
+        },
         /* AES */
         FipsMechanism {
             mechanism: CKM_AES_KEY_GEN,
@@ -562,6 +747,12 @@ const FIPS_CHECKS: FipsChecks = FipsChecks {
             restrictions: [restrict!(CKK_AES), restrict!()],
             genflags: 0,
         },
+        FipsMechanism {
+            mechanism: CKM_AES_GMAC,
+            operations: CKF_SIGN | CKF_VERIFY,
+            restrictions: [restrict!(CKK_AES), restrict!()],
+            genflags: 0,
+        },
         FipsMechanism {
             mechanism: CKM_AES_KEY_WRAP,
             operations: CKF_ENCRYPT | CKF_DECRYPT | CKF_WRAP | CKF_UNWRAP,
@@ -574,6 +765,18 @@ const FIPS_CHECKS: FipsChecks = FipsChecks {
             restrictions: [restrict!(CKK_AES), restrict!()],
             genflags: 0,
         },
+        FipsMechanism {
+            mechanism: CKM_AES_XTS_KEY_GEN,
+            operations: CKF_GENERATE,
+            restrictions: [restrict!(CKK_AES_XTS), restrict!()],
+            genflags: CKF_ENCRYPT | CKF_DECRYPT,
+        },
+        FipsMechanism {
+            mechanism: CKM_AES_XTS,
+            operations: CKF_ENCRYPT | CKF_DECRYPT,
+            restrictions: [restrict!(CKK_AES_XTS), restrict!()],
+            genflags: 0,
+        },
         /* SHA */
         FipsMechanism {
             mechanism: CKM_SHA224,
@@ -907,6 +1110,19 @@ fn check_key(
             },
             Err(_) => return false,
         },
+        CKK_DSA => match obj.get_attr_as_bytes(CKA_PRIME) {
+            Ok(p) => usize::try_from(value_bits(p)).unwrap(),
+            Err(_) => return false,
+        },
+        #[cfg(feature = "ffdh")]
+        CKK_DH => match ffdh_approved_group_bits(obj) {
+            Some(l) => l,
+            None => return false,
+        },
+        CKK_ML_DSA => match obj.get_attr_as_ulong(CKA_PARAMETER_SET) {
+            Ok(p) => usize::try_from(p).unwrap(),
+            Err(_) => return false,
+        },
         _ => {
             /* assume everything else is a symmetric key */
             match obj.get_attr_as_ulong(CKA_VALUE_LEN) {
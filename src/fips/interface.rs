@@ -1,15 +1,6 @@
 // Copyright 2024 Simo Sorce
 // See LICENSE.txt file for terms
 
-#[allow(non_camel_case_types)]
-pub type CK_C_GetSessionValidationFlags = ::std::option::Option<
-    unsafe extern "C" fn(
-        arg1: CK_SESSION_HANDLE,
-        arg2: CK_SESSION_VALIDATION_FLAGS_TYPE,
-        arg3: CK_FLAGS_PTR,
-    ) -> CK_RV,
->;
-
 #[repr(C)]
 #[derive(Debug, Default, Copy, Clone)]
 #[allow(non_snake_case)]
@@ -89,7 +89,10 @@ impl HMACMechanism {
                     info: CK_MECHANISM_INFO {
                         ulMinKeySize: 0,
                         ulMaxKeySize: 0,
-                        flags: CKF_SIGN | CKF_VERIFY,
+                        flags: CKF_SIGN
+                            | CKF_VERIFY
+                            | CKF_MESSAGE_SIGN
+                            | CKF_MESSAGE_VERIFY,
                     },
                     keytype: hs.key_type,
                     minlen: hs.hash_size,
@@ -102,7 +105,10 @@ impl HMACMechanism {
                     info: CK_MECHANISM_INFO {
                         ulMinKeySize: 0,
                         ulMaxKeySize: 0,
-                        flags: CKF_SIGN | CKF_VERIFY,
+                        flags: CKF_SIGN
+                            | CKF_VERIFY
+                            | CKF_MESSAGE_SIGN
+                            | CKF_MESSAGE_VERIFY,
                     },
                     keytype: hs.key_type,
                     minlen: 1,
@@ -213,6 +219,28 @@ impl Mechanism for HMACMechanism {
     ) -> Result<Box<dyn Verify>> {
         Ok(Box::new(self.new_op(mech, keyobj, CKF_VERIFY)?))
     }
+
+    fn msg_sign_op(
+        &self,
+        mech: &CK_MECHANISM,
+        keyobj: &Object,
+    ) -> Result<Box<dyn MsgSign>> {
+        if self.info.flags & CKF_MESSAGE_SIGN != CKF_MESSAGE_SIGN {
+            return Err(CKR_MECHANISM_INVALID)?;
+        }
+        Ok(Box::new(self.new_op(mech, keyobj, CKF_SIGN)?))
+    }
+
+    fn msg_verify_op(
+        &self,
+        mech: &CK_MECHANISM,
+        keyobj: &Object,
+    ) -> Result<Box<dyn MsgVerify>> {
+        if self.info.flags & CKF_MESSAGE_VERIFY != CKF_MESSAGE_VERIFY {
+            return Err(CKR_MECHANISM_INVALID)?;
+        }
+        Ok(Box::new(self.new_op(mech, keyobj, CKF_VERIFY)?))
+    }
 }
 
 static HMAC_SECRET_KEY_FACTORIES: Lazy<
@@ -0,0 +1,471 @@
+// Copyright 2024 Simo Sorce
+// See LICENSE.txt file for terms
+
+use std::fmt::Debug;
+
+use crate::attr_element;
+use crate::attribute::Attribute;
+use crate::error::Result;
+use crate::interface::*;
+use crate::mechanism::*;
+use crate::object::*;
+use crate::ossl::hss::HssOperation;
+
+use once_cell::sync::Lazy;
+
+pub const MIN_HSS_LEVELS: usize = 1;
+pub const MAX_HSS_LEVELS: usize = 8;
+
+/* RFC 8554 Section 8.1 and 8.2 registered types, only the SHA-256
+ * based types are supported */
+pub const LMS_SHA256_M32_H5: CK_LMS_TYPE = 5;
+pub const LMS_SHA256_M32_H10: CK_LMS_TYPE = 6;
+pub const LMS_SHA256_M32_H15: CK_LMS_TYPE = 7;
+pub const LMS_SHA256_M32_H20: CK_LMS_TYPE = 8;
+pub const LMS_SHA256_M32_H25: CK_LMS_TYPE = 9;
+
+pub const LMOTS_SHA256_N32_W1: CK_LMOTS_TYPE = 1;
+pub const LMOTS_SHA256_N32_W2: CK_LMOTS_TYPE = 2;
+pub const LMOTS_SHA256_N32_W4: CK_LMOTS_TYPE = 3;
+pub const LMOTS_SHA256_N32_W8: CK_LMOTS_TYPE = 4;
+
+/* Size of the hash outputs (n and m) and of the tree identifier */
+pub const HSS_N: usize = 32;
+pub const HSS_I_LEN: usize = 16;
+
+/* u32 levels || u32 lms type || u32 lmots type || I || T[1] */
+pub const HSS_PUBLIC_KEY_LEN: usize = 12 + HSS_I_LEN + HSS_N;
+
+/* The private key format is vendor defined, Kryoptic stores the index
+ * of the next signature followed by the identifier and the secret seed
+ * of the top level tree: u64 q || I || SEED, lower level trees are
+ * derived from their parent seed when needed */
+pub const HSS_PRIVATE_KEY_LEN: usize = 8 + HSS_I_LEN + HSS_N;
+
+/* Trees are recomputed for each signature, the taller trees would take
+ * hours to compute so they are only supported for verification */
+const MAX_SIGNING_HEIGHT: usize = 15;
+
+/* RFC 8554 Section 5.1: the height of the tree */
+pub fn lms_height(lms_type: CK_LMS_TYPE) -> Result<usize> {
+    match lms_type {
+        LMS_SHA256_M32_H5 => Ok(5),
+        LMS_SHA256_M32_H10 => Ok(10),
+        LMS_SHA256_M32_H15 => Ok(15),
+        LMS_SHA256_M32_H20 => Ok(20),
+        LMS_SHA256_M32_H25 => Ok(25),
+        _ => Err(CKR_ATTRIBUTE_VALUE_INVALID)?,
+    }
+}
+
+/* RFC 8554 Section 4.1: the Winternitz parameter w, the number of n-byte
+ * string elements p, and the left shift ls used for the checksum */
+pub fn lmots_params(
+    lmots_type: CK_LMOTS_TYPE,
+) -> Result<(usize, usize, usize)> {
+    match lmots_type {
+        LMOTS_SHA256_N32_W1 => Ok((1, 265, 7)),
+        LMOTS_SHA256_N32_W2 => Ok((2, 133, 6)),
+        LMOTS_SHA256_N32_W4 => Ok((4, 67, 4)),
+        LMOTS_SHA256_N32_W8 => Ok((8, 34, 0)),
+        _ => Err(CKR_ATTRIBUTE_VALUE_INVALID)?,
+    }
+}
+
+/* RFC 8554 Section 5.4: the size of a LMS signature */
+pub fn lms_signature_len(
+    lms_type: CK_LMS_TYPE,
+    lmots_type: CK_LMOTS_TYPE,
+) -> Result<usize> {
+    let h = lms_height(lms_type)?;
+    let (_, p, _) = lmots_params(lmots_type)?;
+    Ok(4 + (4 + HSS_N * (p + 1)) + 4 + h * HSS_N)
+}
+
+/* RFC 8554 Section 6.2: the size of a HSS signature, each level but the
+ * last carries the public key of the next level */
+pub fn hss_signature_len(
+    lms_types: &[CK_LMS_TYPE],
+    lmots_types: &[CK_LMOTS_TYPE],
+) -> Result<usize> {
+    let mut len = 4;
+    for (lms_type, lmots_type) in lms_types.iter().zip(lmots_types.iter()) {
+        len += lms_signature_len(*lms_type, *lmots_type)?;
+    }
+    len += (lms_types.len() - 1) * (HSS_PUBLIC_KEY_LEN - 4);
+    Ok(len)
+}
+
+/* The number of signatures left given the index of the next one */
+pub fn hss_keys_remaining(
+    lms_types: &[CK_LMS_TYPE],
+    index: u64,
+) -> Result<CK_ULONG> {
+    let mut bits = 0;
+    for lms_type in lms_types {
+        bits += lms_height(*lms_type)?;
+    }
+    let total = 1u128 << bits;
+    let index = u128::from(index);
+    if index >= total {
+        return Ok(0);
+    }
+    Ok(CK_ULONG::try_from(total - index).unwrap_or(CK_ULONG::MAX))
+}
+
+fn ulong_array(v: &[u8]) -> Result<Vec<CK_ULONG>> {
+    let size = std::mem::size_of::<CK_ULONG>();
+    if !v.len().is_multiple_of(size) {
+        Err(CKR_ATTRIBUTE_VALUE_INVALID)?;
+    }
+    Ok(v.chunks(size)
+        .map(|c| CK_ULONG::from_ne_bytes(c.try_into().unwrap()))
+        .collect())
+}
+
+/* Returns the LMS and LM-OTS types of each level of a private key */
+pub fn get_hss_types(
+    key: &Object,
+) -> Result<(Vec<CK_LMS_TYPE>, Vec<CK_LMOTS_TYPE>)> {
+    let levels = match key.get_attr_as_ulong(CKA_HSS_LEVELS) {
+        Ok(l) => usize::try_from(l)?,
+        Err(e) => {
+            if e.attr_not_found() {
+                Err(CKR_TEMPLATE_INCOMPLETE)?;
+            }
+            return Err(e);
+        }
+    };
+    if !(MIN_HSS_LEVELS..=MAX_HSS_LEVELS).contains(&levels) {
+        Err(CKR_ATTRIBUTE_VALUE_INVALID)?;
+    }
+    let lms_types = ulong_array(key.get_attr_as_bytes(CKA_HSS_LMS_TYPES)?)?;
+    let lmots_types = ulong_array(key.get_attr_as_bytes(CKA_HSS_LMOTS_TYPES)?)?;
+    if lms_types.len() != levels || lmots_types.len() != levels {
+        Err(CKR_ATTRIBUTE_VALUE_INVALID)?;
+    }
+    for (lms_type, lmots_type) in lms_types.iter().zip(lmots_types.iter()) {
+        if lms_height(*lms_type)? > MAX_SIGNING_HEIGHT {
+            Err(CKR_ATTRIBUTE_VALUE_INVALID)?;
+        }
+        lmots_params(*lmots_type)?;
+    }
+    Ok((lms_types, lmots_types))
+}
+
+#[derive(Debug)]
+pub struct HssPubFactory {
+    attributes: Vec<ObjectAttr>,
+}
+
+impl HssPubFactory {
+    pub fn new() -> HssPubFactory {
+        let mut data: HssPubFactory = HssPubFactory {
+            attributes: Vec::new(),
+        };
+        data.attributes.append(&mut data.init_common_object_attrs());
+        data.attributes
+            .append(&mut data.init_common_storage_attrs());
+        data.attributes.append(&mut data.init_common_key_attrs());
+        data.attributes
+            .append(&mut data.init_common_public_key_attrs());
+        data.attributes.push(attr_element!(
+            CKA_HSS_LEVELS; OAFlags::NeverSettable | OAFlags::Unchangeable;
+            Attribute::from_ulong; val 0));
+        data.attributes.push(attr_element!(
+            CKA_HSS_LMS_TYPE; OAFlags::NeverSettable | OAFlags::Unchangeable;
+            Attribute::from_ulong; val 0));
+        data.attributes.push(attr_element!(
+            CKA_HSS_LMOTS_TYPE; OAFlags::NeverSettable
+            | OAFlags::Unchangeable; Attribute::from_ulong; val 0));
+        data.attributes.push(attr_element!(
+            CKA_VALUE; OAFlags::RequiredOnCreate
+            | OAFlags::SettableOnlyOnCreate | OAFlags::Unchangeable;
+            Attribute::from_bytes; val Vec::new()));
+        data
+    }
+}
+
+impl ObjectFactory for HssPubFactory {
+    fn create(&self, template: &[CK_ATTRIBUTE]) -> Result<Object> {
+        let mut obj = self.default_object_create(template)?;
+
+        /* PKCS#11 3.1 6.14.3 HSS public key objects:
+         * CKA_VALUE, Byte array,
+         * XDR-encoded public key as defined in RFC 8554 */
+        let value = match obj.get_attr_as_bytes(CKA_VALUE) {
+            Ok(v) => v,
+            Err(e) => {
+                if e.attr_not_found() {
+                    return Err(CKR_TEMPLATE_INCOMPLETE)?;
+                } else {
+                    return Err(e);
+                }
+            }
+        };
+        if value.len() != HSS_PUBLIC_KEY_LEN {
+            return Err(CKR_ATTRIBUTE_VALUE_INVALID)?;
+        }
+        let levels =
+            CK_ULONG::from(u32::from_be_bytes(value[0..4].try_into().unwrap()));
+        let lms_type =
+            CK_ULONG::from(u32::from_be_bytes(value[4..8].try_into().unwrap()));
+        let lmots_type = CK_ULONG::from(u32::from_be_bytes(
+            value[8..12].try_into().unwrap(),
+        ));
+        if levels < CK_ULONG::try_from(MIN_HSS_LEVELS)?
+            || levels > CK_ULONG::try_from(MAX_HSS_LEVELS)?
+        {
+            return Err(CKR_ATTRIBUTE_VALUE_INVALID)?;
+        }
+        lms_height(lms_type)?;
+        lmots_params(lmots_type)?;
+
+        obj.set_attr(Attribute::from_ulong(CKA_HSS_LEVELS, levels))?;
+        obj.set_attr(Attribute::from_ulong(CKA_HSS_LMS_TYPE, lms_type))?;
+        obj.set_attr(Attribute::from_ulong(CKA_HSS_LMOTS_TYPE, lmots_type))?;
+
+        Ok(obj)
+    }
+
+    fn get_attributes(&self) -> &Vec<ObjectAttr> {
+        &self.attributes
+    }
+}
+
+impl CommonKeyFactory for HssPubFactory {}
+
+impl PubKeyFactory for HssPubFactory {}
+
+#[derive(Debug)]
+pub struct HssPrivFactory {
+    attributes: Vec<ObjectAttr>,
+}
+
+impl HssPrivFactory {
+    pub fn new() -> HssPrivFactory {
+        let mut data: HssPrivFactory = HssPrivFactory {
+            attributes: Vec::new(),
+        };
+        data.attributes.append(&mut data.init_common_object_attrs());
+        data.attributes
+            .append(&mut data.init_common_storage_attrs());
+        data.attributes.append(&mut data.init_common_key_attrs());
+        data.attributes
+            .append(&mut data.init_common_private_key_attrs());
+        data.attributes.push(attr_element!(
+            CKA_HSS_LEVELS; OAFlags::RequiredOnCreate
+            | OAFlags::RequiredOnGenerate | OAFlags::Unchangeable;
+            Attribute::from_ulong; val 0));
+        data.attributes.push(attr_element!(
+            CKA_HSS_LMS_TYPES; OAFlags::RequiredOnCreate
+            | OAFlags::RequiredOnGenerate | OAFlags::Unchangeable;
+            Attribute::from_bytes; val Vec::new()));
+        data.attributes.push(attr_element!(
+            CKA_HSS_LMOTS_TYPES; OAFlags::RequiredOnCreate
+            | OAFlags::RequiredOnGenerate | OAFlags::Unchangeable;
+            Attribute::from_bytes; val Vec::new()));
+        data.attributes.push(attr_element!(
+            CKA_VALUE; OAFlags::Sensitive | OAFlags::RequiredOnCreate
+            | OAFlags::SettableOnlyOnCreate | OAFlags::Unchangeable;
+            Attribute::from_bytes; val Vec::new()));
+        data.attributes.push(attr_element!(
+            CKA_HSS_KEYS_REMAINING; OAFlags::NeverSettable
+            | OAFlags::Unchangeable; Attribute::from_ulong; val 0));
+
+        /* default to private */
+        let private = attr_element!(
+            CKA_PRIVATE; OAFlags::Defval | OAFlags::ChangeOnCopy;
+            Attribute::from_bool; val true);
+        match data
+            .attributes
+            .iter()
+            .position(|x| x.get_type() == CKA_PRIVATE)
+        {
+            Some(idx) => data.attributes[idx] = private,
+            None => data.attributes.push(private),
+        }
+
+        /* a copy would share the signing state and reuse one time keys */
+        let copyable = attr_element!(
+            CKA_COPYABLE; OAFlags::Defval | OAFlags::ChangeToFalse;
+            Attribute::from_bool; val false);
+        match data
+            .attributes
+            .iter()
+            .position(|x| x.get_type() == CKA_COPYABLE)
+        {
+            Some(idx) => data.attributes[idx] = copyable,
+            None => data.attributes.push(copyable),
+        }
+
+        data
+    }
+}
+
+impl ObjectFactory for HssPrivFactory {
+    fn create(&self, template: &[CK_ATTRIBUTE]) -> Result<Object> {
+        let mut obj = self.default_object_create(template)?;
+        if !obj.check_or_set_attr(Attribute::from_bool(CKA_COPYABLE, false))? {
+            return Err(CKR_TEMPLATE_INCONSISTENT)?;
+        }
+
+        /* PKCS#11 3.1 6.14.4 HSS private key objects:
+         * CKA_VALUE, Byte array,
+         * Vendor defined, must include state information */
+        let (lms_types, _) = get_hss_types(&obj)?;
+        let value = match obj.get_attr_as_bytes(CKA_VALUE) {
+            Ok(v) => v,
+            Err(e) => {
+                if e.attr_not_found() {
+                    return Err(CKR_TEMPLATE_INCOMPLETE)?;
+                } else {
+                    return Err(e);
+                }
+            }
+        };
+        if value.len() != HSS_PRIVATE_KEY_LEN {
+            return Err(CKR_ATTRIBUTE_VALUE_INVALID)?;
+        }
+        let index = u64::from_be_bytes(value[0..8].try_into().unwrap());
+        let remaining = hss_keys_remaining(&lms_types, index)?;
+        obj.set_attr(Attribute::from_ulong(CKA_HSS_KEYS_REMAINING, remaining))?;
+
+        Ok(obj)
+    }
+
+    fn get_attributes(&self) -> &Vec<ObjectAttr> {
+        &self.attributes
+    }
+}
+
+impl CommonKeyFactory for HssPrivFactory {}
+
+impl PrivKeyFactory for HssPrivFactory {}
+
+static PUBLIC_KEY_FACTORY: Lazy<Box<dyn ObjectFactory>> =
+    Lazy::new(|| Box::new(HssPubFactory::new()));
+
+static PRIVATE_KEY_FACTORY: Lazy<Box<dyn ObjectFactory>> =
+    Lazy::new(|| Box::new(HssPrivFactory::new()));
+
+#[derive(Debug)]
+struct HssMechanism {
+    info: CK_MECHANISM_INFO,
+}
+
+impl HssMechanism {
+    fn new_mechanism(flags: CK_FLAGS) -> Box<dyn Mechanism> {
+        Box::new(HssMechanism {
+            info: CK_MECHANISM_INFO {
+                ulMinKeySize: CK_ULONG::try_from(MIN_HSS_LEVELS).unwrap(),
+                ulMaxKeySize: CK_ULONG::try_from(MAX_HSS_LEVELS).unwrap(),
+                flags,
+            },
+        })
+    }
+
+    fn register_mechanisms(mechs: &mut Mechanisms) {
+        mechs.add_mechanism(
+            CKM_HSS_KEY_PAIR_GEN,
+            Self::new_mechanism(CKF_GENERATE_KEY_PAIR),
+        );
+        mechs
+            .add_mechanism(CKM_HSS, Self::new_mechanism(CKF_SIGN | CKF_VERIFY));
+    }
+}
+
+impl Mechanism for HssMechanism {
+    fn info(&self) -> &CK_MECHANISM_INFO {
+        &self.info
+    }
+
+    fn sign_new(
+        &self,
+        mech: &CK_MECHANISM,
+        key: &Object,
+    ) -> Result<Box<dyn Sign>> {
+        if self.info.flags & CKF_SIGN != CKF_SIGN {
+            return Err(CKR_MECHANISM_INVALID)?;
+        }
+        key.check_key_ops(CKO_PRIVATE_KEY, CKK_HSS, CKA_SIGN)?;
+        Ok(Box::new(HssOperation::sign_new(mech, key)?))
+    }
+
+    fn verify_new(
+        &self,
+        mech: &CK_MECHANISM,
+        key: &Object,
+    ) -> Result<Box<dyn Verify>> {
+        if self.info.flags & CKF_VERIFY != CKF_VERIFY {
+            return Err(CKR_MECHANISM_INVALID)?;
+        }
+        key.check_key_ops(CKO_PUBLIC_KEY, CKK_HSS, CKA_VERIFY)?;
+        Ok(Box::new(HssOperation::verify_new(mech, key)?))
+    }
+
+    fn generate_keypair(
+        &self,
+        mech: &CK_MECHANISM,
+        pubkey_template: &[CK_ATTRIBUTE],
+        prikey_template: &[CK_ATTRIBUTE],
+    ) -> Result<(Object, Object)> {
+        let mut pubkey =
+            PUBLIC_KEY_FACTORY.default_object_generate(pubkey_template)?;
+        if !pubkey.check_or_set_attr(Attribute::from_ulong(
+            CKA_CLASS,
+            CKO_PUBLIC_KEY,
+        ))? {
+            return Err(CKR_TEMPLATE_INCONSISTENT)?;
+        }
+        if !pubkey
+            .check_or_set_attr(Attribute::from_ulong(CKA_KEY_TYPE, CKK_HSS))?
+        {
+            return Err(CKR_TEMPLATE_INCONSISTENT)?;
+        }
+
+        let mut privkey =
+            PRIVATE_KEY_FACTORY.default_object_generate(prikey_template)?;
+        if !privkey.check_or_set_attr(Attribute::from_ulong(
+            CKA_CLASS,
+            CKO_PRIVATE_KEY,
+        ))? {
+            return Err(CKR_TEMPLATE_INCONSISTENT)?;
+        }
+        if !privkey
+            .check_or_set_attr(Attribute::from_ulong(CKA_KEY_TYPE, CKK_HSS))?
+        {
+            return Err(CKR_TEMPLATE_INCONSISTENT)?;
+        }
+        if !privkey
+            .check_or_set_attr(Attribute::from_bool(CKA_COPYABLE, false))?
+        {
+            return Err(CKR_TEMPLATE_INCONSISTENT)?;
+        }
+
+        let (lms_types, lmots_types) = get_hss_types(&privkey)?;
+        HssOperation::generate_keypair(
+            &lms_types,
+            &lmots_types,
+            &mut pubkey,
+            &mut privkey,
+        )?;
+        default_key_attributes(&mut privkey, mech.mechanism)?;
+        default_key_attributes(&mut pubkey, mech.mechanism)?;
+
+        Ok((pubkey, privkey))
+    }
+}
+
+pub fn register(mechs: &mut Mechanisms, ot: &mut ObjectFactories) {
+    HssMechanism::register_mechanisms(mechs);
+
+    ot.add_factory(
+        ObjectType::new(CKO_PUBLIC_KEY, CKK_HSS),
+        &PUBLIC_KEY_FACTORY,
+    );
+    ot.add_factory(
+        ObjectType::new(CKO_PRIVATE_KEY, CKK_HSS),
+        &PRIVATE_KEY_FACTORY,
+    );
+}
@@ -4,3 +4,26 @@ include! {"pyca/oid.rs"}
 
 pub const PBMAC1_OID: asn1::ObjectIdentifier =
     asn1::oid!(1, 2, 840, 113549, 1, 5, 14);
+
+/* ANSI X9.62 field type of the explicit EC domain parameters */
+pub const EC_PRIME_FIELD_OID: asn1::ObjectIdentifier =
+    asn1::oid!(1, 2, 840, 10045, 1, 1);
+
+/* FIPS 204 ML-DSA, as assigned in the NIST CSOR registry */
+pub const ML_DSA_44_OID: asn1::ObjectIdentifier =
+    asn1::oid!(2, 16, 840, 1, 101, 3, 4, 3, 17);
+pub const ML_DSA_65_OID: asn1::ObjectIdentifier =
+    asn1::oid!(2, 16, 840, 1, 101, 3, 4, 3, 18);
+pub const ML_DSA_87_OID: asn1::ObjectIdentifier =
+    asn1::oid!(2, 16, 840, 1, 101, 3, 4, 3, 19);
+
+/* NIST hash algorithm identifiers, used to encode the pre-hash in
+ * HashML-DSA (FIPS 204, Algorithm 4) */
+pub const NIST_SHA3_224_OID: asn1::ObjectIdentifier =
+    asn1::oid!(2, 16, 840, 1, 101, 3, 4, 2, 7);
+pub const NIST_SHA3_256_OID: asn1::ObjectIdentifier =
+    asn1::oid!(2, 16, 840, 1, 101, 3, 4, 2, 8);
+pub const NIST_SHA3_384_OID: asn1::ObjectIdentifier =
+    asn1::oid!(2, 16, 840, 1, 101, 3, 4, 2, 9);
+pub const NIST_SHA3_512_OID: asn1::ObjectIdentifier =
+    asn1::oid!(2, 16, 840, 1, 101, 3, 4, 2, 10);
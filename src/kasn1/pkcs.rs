@@ -10,9 +10,34 @@ use asn1;
 
 include! {"pyca/pkcs.rs"}
 
+// Defined in SECG SEC 1, C.2
+#[derive(asn1::Asn1Read, asn1::Asn1Write)]
+pub struct FieldID<'a> {
+    pub field_type: asn1::ObjectIdentifier,
+    pub parameters: asn1::Tlv<'a>,
+}
+
+#[derive(asn1::Asn1Read, asn1::Asn1Write)]
+pub struct Curve<'a> {
+    pub a: &'a [u8],
+    pub b: &'a [u8],
+    pub seed: Option<asn1::BitString<'a>>,
+}
+
+#[derive(asn1::Asn1Read, asn1::Asn1Write)]
+pub struct SpecifiedECDomain<'a> {
+    pub version: u8,
+    pub field_id: FieldID<'a>,
+    pub curve: Curve<'a>,
+    pub base: &'a [u8],
+    pub order: asn1::BigUint<'a>,
+    pub cofactor: Option<asn1::BigUint<'a>>,
+    pub hash: Option<asn1::Tlv<'a>>,
+}
+
 #[derive(asn1::Asn1Read, asn1::Asn1Write)]
 pub enum ECParameters<'a> {
-    //EcParameters(EcParameters<'a>),
+    EcParameters(SpecifiedECDomain<'a>),
     OId(asn1::ObjectIdentifier),
     ImplicitlyCA(asn1::Null),
     CurveName(asn1::PrintableString<'a>),
@@ -40,6 +65,39 @@ impl ECPrivateKey<'_> {
     }
 }
 
+// Defined in RFC 5958, 2
+#[derive(asn1::Asn1Read, asn1::Asn1Write)]
+pub struct OneAsymmetricKey<'a> {
+    pub version: Version,
+    pub private_key_algorithm: AlgorithmIdentifier<'a>,
+    pub private_key: DerEncOctetString<'a>,
+    #[implicit(0)]
+    attributes: Option<asn1::SetOf<'a, asn1::Tlv<'a>>>,
+    #[implicit(1)]
+    public_key: Option<asn1::BitString<'a>>,
+}
+
+impl OneAsymmetricKey<'_> {
+    pub fn new<'a>(
+        params: AlgorithmParameters<'a>,
+        private_key_asn1: &'a [u8],
+    ) -> Result<OneAsymmetricKey<'a>> {
+        Ok(OneAsymmetricKey {
+            version: 0,
+            private_key_algorithm: AlgorithmIdentifier {
+                oid: asn1::DefinedByMarker::marker(),
+                params,
+            },
+            private_key: DerEncOctetString::new(private_key_asn1)?,
+            attributes: None,
+            public_key: None,
+        })
+    }
+}
+
+// Defined in RFC 8410, 7
+pub type CurvePrivateKey<'a> = DerEncOctetString<'a>;
+
 #[derive(
     asn1::Asn1Read, asn1::Asn1Write, PartialEq, Eq, Hash, Clone, Debug,
 )]
@@ -52,3 +110,33 @@ pub const HMAC_SHA_256_ALG: AlgorithmIdentifier<'_> = AlgorithmIdentifier {
     oid: asn1::DefinedByMarker::marker(),
     params: AlgorithmParameters::HmacWithSha256(Some(())),
 };
+
+#[derive(asn1::Asn1Read, asn1::Asn1Write)]
+pub struct MlDsaBothKeys<'a> {
+    pub seed: DerEncOctetString<'a>,
+    pub expanded_key: DerEncOctetString<'a>,
+}
+
+// Defined in draft-ietf-lamps-dilithium-certificates
+#[derive(asn1::Asn1Read, asn1::Asn1Write)]
+pub enum MlDsaPrivateKey<'a> {
+    #[implicit(0)]
+    Seed(DerEncOctetString<'a>),
+    ExpandedKey(DerEncOctetString<'a>),
+    Both(MlDsaBothKeys<'a>),
+}
+
+// Defined in RFC 2631, 2.1.2
+#[derive(asn1::Asn1Read, asn1::Asn1Write)]
+pub struct KeySpecificInfo<'a> {
+    pub algorithm: asn1::ObjectIdentifier,
+    pub counter: &'a [u8],
+}
+
+#[derive(asn1::Asn1Read, asn1::Asn1Write)]
+pub struct X942OtherInfo<'a> {
+    pub key_info: KeySpecificInfo<'a>,
+    #[explicit(0)]
+    pub party_a_info: Option<&'a [u8]>,
+    pub supp_pub_info: asn1::Explicit<&'a [u8], 2>,
+}
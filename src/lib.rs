@@ -5,6 +5,7 @@ use std::cell::RefCell;
 use std::collections::HashMap;
 use std::ffi::{c_char, CStr};
 use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::Duration;
 
 use once_cell::sync::Lazy;
 
@@ -27,10 +28,11 @@ mod slot;
 mod storage;
 mod token;
 
-use config::Config;
+use attribute::CkAttrs;
+use config::{Config, ConfigWatcher};
 use error::Result;
 use interface::*;
-use mechanism::Operation;
+use mechanism::{MechOperation, Operation, VerifySignatureOperation};
 use rng::RNG;
 use session::Session;
 use slot::Slot;
@@ -47,6 +49,7 @@ include!("enabled.rs");
 
 /* Helper code */
 mod kasn1;
+mod lock;
 mod misc;
 
 macro_rules! ret_to_rv {
@@ -105,6 +108,7 @@ struct State {
     slots: HashMap<CK_SLOT_ID, Slot>,
     sessionmap: HashMap<CK_SESSION_HANDLE, CK_SLOT_ID>,
     next_handle: CK_ULONG,
+    slot_events: Vec<CK_SLOT_ID>,
 }
 
 impl State {
@@ -115,6 +119,7 @@ impl State {
         self.slots.clear();
         self.sessionmap.clear();
         self.next_handle = 1;
+        self.slot_events.clear();
     }
 
     fn finalize(&mut self) -> CK_RV {
@@ -132,6 +137,7 @@ impl State {
         self.slots.clear();
         self.sessionmap.clear();
         self.next_handle = 0;
+        self.slot_events.clear();
         ret
     }
 
@@ -176,6 +182,49 @@ impl State {
         Ok(())
     }
 
+    fn insert_token(
+        &mut self,
+        slot_id: CK_SLOT_ID,
+        config: &config::Slot,
+    ) -> Result<()> {
+        if let Some(slot) = self.slots.get(&slot_id) {
+            if slot.is_token_present() {
+                return Ok(());
+            }
+        }
+        self.slots.insert(slot_id, Slot::new(config)?);
+        self.queue_slot_event(slot_id);
+        Ok(())
+    }
+
+    fn remove_token(&mut self, slot_id: CK_SLOT_ID) -> Result<()> {
+        match self.slots.get(&slot_id) {
+            Some(slot) => {
+                if !slot.is_token_present() {
+                    return Ok(());
+                }
+            }
+            None => return Ok(()),
+        }
+        let _ = self.drop_all_sessions_slot(slot_id)?;
+        let ret = self.get_slot_mut(slot_id)?.remove_token();
+        self.queue_slot_event(slot_id);
+        ret
+    }
+
+    fn queue_slot_event(&mut self, slot_id: CK_SLOT_ID) {
+        if !self.slot_events.contains(&slot_id) {
+            self.slot_events.push(slot_id);
+        }
+    }
+
+    fn pop_slot_event(&mut self) -> Option<CK_SLOT_ID> {
+        if self.slot_events.is_empty() {
+            return None;
+        }
+        Some(self.slot_events.remove(0))
+    }
+
     fn get_session(
         &self,
         handle: CK_SESSION_HANDLE,
@@ -300,24 +349,29 @@ static STATE: Lazy<RwLock<State>> = Lazy::new(|| {
         slots: HashMap::new(),
         sessionmap: HashMap::new(),
         next_handle: 0,
+        slot_events: Vec::new(),
     })
 });
 
+/* Global locks are always paired with the application mutex when the
+ * application asked us to use its own locking callbacks */
 macro_rules! global_rlock {
-    ($GLOBAL:expr) => {
+    ($GLOBAL:expr) => {{
+        let applock = res_or_ret!(lock::AppLock::acquire());
         match $GLOBAL.read() {
             Ok(r) => {
                 if (!r.is_initialized()) {
                     return CKR_CRYPTOKI_NOT_INITIALIZED;
                 }
-                r
+                lock::Locked::new(r, applock)
             }
             Err(_) => return CKR_GENERAL_ERROR,
         }
-    };
+    }};
     (noinitcheck $GLOBAL:expr) => {{
+        let applock = res_or_ret!(lock::AppLock::acquire());
         match $GLOBAL.read() {
-            Ok(r) => r,
+            Ok(r) => lock::Locked::new(r, applock),
             Err(_) => return CKR_GENERAL_ERROR,
         }
     }};
@@ -325,19 +379,21 @@ macro_rules! global_rlock {
 
 macro_rules! global_wlock {
     ($GLOBAL:expr) => {{
+        let applock = res_or_ret!(lock::AppLock::acquire());
         match $GLOBAL.write() {
             Ok(w) => {
                 if (!w.is_initialized()) {
                     return CKR_CRYPTOKI_NOT_INITIALIZED;
                 }
-                w
+                lock::Locked::new(w, applock)
             }
             Err(_) => return CKR_GENERAL_ERROR,
         }
     }};
     (noinitcheck $GLOBAL:expr) => {{
+        let applock = res_or_ret!(lock::AppLock::acquire());
         match $GLOBAL.write() {
-            Ok(w) => w,
+            Ok(w) => lock::Locked::new(w, applock),
             Err(_) => return CKR_GENERAL_ERROR,
         }
     }};
@@ -394,6 +450,34 @@ fn finalize_fips_approval(
 
 struct GlobalConfig {
     conf: Config,
+    watcher: Option<ConfigWatcher>,
+}
+
+impl GlobalConfig {
+    fn poll_slot_changes(&mut self) -> Option<config::SlotChanges> {
+        let mut changes = self.watcher.as_mut()?.poll()?;
+        let slots = &mut self.conf.slots;
+        /* only slots that are still loaded as configured can go away */
+        changes.removed.retain(|slot| {
+            match slots.iter().position(|s| s == slot) {
+                Some(idx) => {
+                    slots.remove(idx);
+                    true
+                }
+                None => false,
+            }
+        });
+        /* slot numbers in use, eg via init args, can't be reassigned */
+        changes.added.retain(|slot| {
+            if slots.iter().any(|s| s.slot == slot.slot) {
+                false
+            } else {
+                slots.push(slot.clone());
+                true
+            }
+        });
+        Some(changes)
+    }
 }
 
 static CONFIG: Lazy<RwLock<GlobalConfig>> = Lazy::new(|| {
@@ -405,6 +489,7 @@ static CONFIG: Lazy<RwLock<GlobalConfig>> = Lazy::new(|| {
             Ok(conf) => conf,
             Err(_) => Config::new(),
         },
+        watcher: ConfigWatcher::default_config(),
     };
     global_conf.conf.load_env_vars_overrides();
     RwLock::new(global_conf)
@@ -420,11 +505,23 @@ pub fn add_slot(slot: config::Slot) -> CK_RV {
 }
 
 extern "C" fn fn_initialize(_init_args: CK_VOID_PTR) -> CK_RV {
-    let mut gconf = global_wlock!(noinitcheck CONFIG);
+    let init_args = if _init_args.is_null() {
+        None
+    } else {
+        Some(unsafe { *(_init_args as *const CK_C_INITIALIZE_ARGS) })
+    };
+    if let Some(args) = &init_args {
+        /* later calls only add slots and must not change the locking */
+        let initialized = match STATE.read() {
+            Ok(s) => s.is_initialized(),
+            Err(_) => return CKR_GENERAL_ERROR,
+        };
+        res_or_ret!(lock::configure(args, initialized));
+    }
 
-    if !_init_args.is_null() {
-        let args = unsafe { *(_init_args as *const CK_C_INITIALIZE_ARGS) };
+    let mut gconf = global_wlock!(noinitcheck CONFIG);
 
+    if let Some(args) = init_args {
         if !args.pReserved.is_null() {
             let reserved =
                 unsafe { CStr::from_ptr(args.pReserved as *const _) };
@@ -467,6 +564,7 @@ fn force_load_config() -> CK_RV {
             Ok(conf) => conf,
             Err(e) => return e.rv(),
         },
+        watcher: ConfigWatcher::default_config(),
     };
     if testconf.conf.slots.len() == 0 {
         return CKR_GENERAL_ERROR;
@@ -478,6 +576,36 @@ fn force_load_config() -> CK_RV {
     return CKR_OK;
 }
 
+#[cfg(test)]
+fn set_config_watch(filename: &str) -> CK_RV {
+    let mut gconf = global_wlock!(noinitcheck CONFIG);
+    gconf.watcher = Some(ConfigWatcher::new(filename));
+    CKR_OK
+}
+
+/* Applies slots added to or removed from the configuration file since
+ * the last check, each slot whose token appears or disappears gets a
+ * slot event queued. The config and state locks are never held at the
+ * same time as other functions acquire them in the opposite order */
+fn refresh_slots() -> CK_RV {
+    let changes = match global_wlock!(noinitcheck CONFIG).poll_slot_changes() {
+        Some(c) => c,
+        None => return CKR_OK,
+    };
+    let mut wstate = global_wlock!(STATE);
+    for slot in &changes.removed {
+        let slotnum = cast_or_ret!(CK_SLOT_ID from slot.slot);
+        res_or_ret!(wstate.remove_token(slotnum));
+    }
+    for slot in &changes.added {
+        let slotnum = cast_or_ret!(CK_SLOT_ID from slot.slot);
+        /* a broken entry must not prevent other slots from being
+         * updated, it will be retried once the file changes again */
+        let _ = wstate.insert_token(slotnum, slot);
+    }
+    CKR_OK
+}
+
 #[cfg(test)]
 fn get_ec_point_encoding(save: &mut config::EcPointEncoding) -> CK_RV {
     let gconf = global_rlock!(noinitcheck CONFIG);
@@ -493,7 +621,11 @@ fn set_ec_point_encoding(val: config::EcPointEncoding) -> CK_RV {
 }
 
 extern "C" fn fn_finalize(_reserved: CK_VOID_PTR) -> CK_RV {
-    global_wlock!(STATE).finalize()
+    let ret = global_wlock!(STATE).finalize();
+    if ret == CKR_OK {
+        lock::release();
+    }
+    ret
 }
 
 extern "C" fn fn_get_mechanism_list(
@@ -678,26 +810,81 @@ extern "C" fn fn_get_session_info(
     CKR_OK
 }
 extern "C" fn fn_get_operation_state(
-    _session: CK_SESSION_HANDLE,
-    _operation_state: CK_BYTE_PTR,
-    _pul_operation_state_len: CK_ULONG_PTR,
+    s_handle: CK_SESSION_HANDLE,
+    operation_state: CK_BYTE_PTR,
+    pul_operation_state_len: CK_ULONG_PTR,
 ) -> CK_RV {
-    CKR_FUNCTION_NOT_SUPPORTED
+    if pul_operation_state_len.is_null() {
+        return CKR_ARGUMENTS_BAD;
+    }
+    let rstate = global_rlock!(STATE);
+    let session = res_or_ret!(rstate.get_session(s_handle));
+    let state_len = res_or_ret!(session.get_operation_state_len());
+    let state_len = cast_or_ret!(CK_ULONG from state_len);
+    if operation_state.is_null() {
+        unsafe {
+            *pul_operation_state_len = state_len;
+        }
+        return CKR_OK;
+    }
+    if unsafe { *pul_operation_state_len } < state_len {
+        unsafe {
+            *pul_operation_state_len = state_len;
+        }
+        return CKR_BUFFER_TOO_SMALL;
+    }
+    let slot_id = session.get_slot_id();
+    let mut token = res_or_ret!(rstate.get_token_from_slot_mut(slot_id));
+    let state = res_or_ret!(session.get_operation_state(&mut token));
+    unsafe {
+        std::ptr::copy_nonoverlapping(
+            state.as_ptr(),
+            operation_state,
+            state.len(),
+        );
+        *pul_operation_state_len = state_len;
+    }
+    CKR_OK
 }
 extern "C" fn fn_set_operation_state(
-    _session: CK_SESSION_HANDLE,
-    _operation_state: CK_BYTE_PTR,
-    _operation_state_len: CK_ULONG,
-    _encryption_key: CK_OBJECT_HANDLE,
-    _authentication_key: CK_OBJECT_HANDLE,
+    s_handle: CK_SESSION_HANDLE,
+    operation_state: CK_BYTE_PTR,
+    operation_state_len: CK_ULONG,
+    encryption_key: CK_OBJECT_HANDLE,
+    authentication_key: CK_OBJECT_HANDLE,
 ) -> CK_RV {
-    CKR_FUNCTION_NOT_SUPPORTED
+    if operation_state.is_null() {
+        return CKR_ARGUMENTS_BAD;
+    }
+    let rstate = global_rlock!(STATE);
+    let mut session = res_or_ret!(rstate.get_session_mut(s_handle));
+    let slot_id = session.get_slot_id();
+    let mut token = res_or_ret!(rstate.get_token_from_slot_mut(slot_id));
+    let state_len = cast_or_ret!(usize from operation_state_len);
+    let state: &[u8] =
+        unsafe { std::slice::from_raw_parts(operation_state, state_len) };
+    ret_to_rv!(session.set_operation_state(
+        &mut token,
+        state,
+        encryption_key,
+        authentication_key
+    ))
 }
 extern "C" fn fn_login(
     s_handle: CK_SESSION_HANDLE,
     user_type: CK_USER_TYPE,
     pin: CK_UTF8CHAR_PTR,
     pin_len: CK_ULONG,
+) -> CK_RV {
+    let vpin = bytes_to_slice!(pin, pin_len, u8);
+    login(s_handle, user_type, vpin, None)
+}
+
+fn login(
+    s_handle: CK_SESSION_HANDLE,
+    user_type: CK_USER_TYPE,
+    vpin: &[u8],
+    username: Option<&str>,
 ) -> CK_RV {
     let rstate = global_rlock!(STATE);
     let session = res_or_ret!(rstate.get_session(s_handle));
@@ -709,20 +896,15 @@ extern "C" fn fn_login(
             return CKR_SESSION_READ_ONLY_EXISTS;
         }
     }
-    let vpin = bytes_to_slice!(pin, pin_len, u8);
     let mut token = res_or_ret!(rstate.get_token_from_slot_mut(slot_id));
     if user_type == CKU_CONTEXT_SPECIFIC {
         let session = res_or_ret!(rstate.get_session_mut(s_handle));
-        match session.get_operation() {
-            Err(e) => match e.rv() {
-                CKR_USER_NOT_LOGGED_IN => (),
-                _ => return CKR_OPERATION_NOT_INITIALIZED,
-            },
-            Ok(_) => return CKR_OPERATION_NOT_INITIALIZED,
+        if !session.login_required() {
+            return CKR_OPERATION_NOT_INITIALIZED;
         }
     }
 
-    let result = token.login(user_type, &vpin);
+    let result = token.login_user(user_type, username, vpin);
 
     if user_type == CKU_CONTEXT_SPECIFIC {
         match result {
@@ -819,6 +1001,7 @@ extern "C" fn fn_create_object(
             Ok(key_type) => {
                 let mechanism = match key_type {
                     CKK_AES => CKM_AES_KEY_GEN,
+                    CKK_AES_XTS => CKM_AES_XTS_KEY_GEN,
                     CKK_GENERIC_SECRET => CKM_GENERIC_SECRET_KEY_GEN,
                     CKK_HKDF => CKM_HKDF_KEY_GEN,
                     CKK_RSA => CKM_RSA_PKCS_KEY_PAIR_GEN,
@@ -1067,18 +1250,44 @@ extern "C" fn fn_find_objects_final(s_handle: CK_SESSION_HANDLE) -> CK_RV {
 
 macro_rules! check_op_empty_or_fail {
     ($sess:expr; $optype:ident; $ptr:expr) => {
-        let op = $sess.get_operation_nocheck();
-        if !op.finalized() {
+        if $sess.has_active_operation() {
+            let is_op: fn(&Operation) -> bool =
+                |op| matches!(op, Operation::$optype(_));
             if $ptr.is_null() {
-                match op {
-                    Operation::$optype(_) => {
-                        $sess.set_operation(Operation::Empty, false);
-                        return CKR_OK;
-                    }
-                    _ => (),
+                if $sess.cancel_operation(is_op) {
+                    return CKR_OK;
                 }
+                return CKR_OPERATION_ACTIVE;
+            }
+            if !$sess.can_start_operation(is_op) {
+                return CKR_OPERATION_ACTIVE;
             }
-            return CKR_OPERATION_ACTIVE;
+        }
+    };
+}
+
+/* Finds the operation of the requested type, which may be one of the
+ * two operations started for dual-function calls */
+macro_rules! get_op_or_ret {
+    ($sess:expr; $optype:ident) => {
+        match res_or_ret!($sess
+            .get_operation_mut_by(|op| matches!(op, Operation::$optype(_))))
+        {
+            Operation::$optype(op) => op,
+            _ => return CKR_OPERATION_NOT_INITIALIZED,
+        }
+    };
+}
+
+/* Returns both operations needed by a dual-function call */
+macro_rules! get_dual_ops_or_ret {
+    ($sess:expr; $first:ident; $second:ident) => {
+        match res_or_ret!($sess.get_dual_operations_mut(
+            |op| matches!(op, Operation::$first(_)),
+            |op| matches!(op, Operation::$second(_)),
+        )) {
+            (Operation::$first(a), Operation::$second(b)) => (a, b),
+            _ => return CKR_OPERATION_NOT_INITIALIZED,
         }
     };
 }
@@ -1130,7 +1339,11 @@ extern "C" fn fn_encrypt_init(
     if mech.info().flags & CKF_ENCRYPT == CKF_ENCRYPT {
         let operation = res_or_ret!(mech.encryption_new(mechanism, &key));
 
-        session.set_operation(Operation::Encryption(operation), false);
+        res_or_ret!(session.start_operation(
+            Operation::Encryption(operation),
+            key_handle,
+            false
+        ));
 
         #[cfg(feature = "fips")]
         init_fips_approval(session, mechanism.mechanism, CKF_ENCRYPT, &key);
@@ -1153,10 +1366,7 @@ extern "C" fn fn_encrypt(
     }
     let rstate = global_rlock!(STATE);
     let mut session = res_or_ret!(rstate.get_session_mut(s_handle));
-    let operation = match res_or_ret!(session.get_operation_mut()) {
-        Operation::Encryption(op) => op,
-        _ => return CKR_OPERATION_NOT_INITIALIZED,
-    };
+    let operation = get_op_or_ret!(session; Encryption);
     if operation.finalized() {
         return CKR_OPERATION_NOT_INITIALIZED;
     }
@@ -1198,10 +1408,7 @@ extern "C" fn fn_encrypt_update(
     }
     let rstate = global_rlock!(STATE);
     let mut session = res_or_ret!(rstate.get_session_mut(s_handle));
-    let operation = match res_or_ret!(session.get_operation_mut()) {
-        Operation::Encryption(op) => op,
-        _ => return CKR_OPERATION_NOT_INITIALIZED,
-    };
+    let operation = get_op_or_ret!(session; Encryption);
     if operation.finalized() {
         return CKR_OPERATION_NOT_INITIALIZED;
     }
@@ -1235,10 +1442,7 @@ extern "C" fn fn_encrypt_final(
         return CKR_ARGUMENTS_BAD;
     }
     let mut session = res_or_ret!(rstate.get_session_mut(s_handle));
-    let operation = match res_or_ret!(session.get_operation_mut()) {
-        Operation::Encryption(op) => op,
-        _ => return CKR_OPERATION_NOT_INITIALIZED,
-    };
+    let operation = get_op_or_ret!(session; Encryption);
     if operation.finalized() {
         return CKR_OPERATION_NOT_INITIALIZED;
     }
@@ -1283,8 +1487,11 @@ extern "C" fn fn_decrypt_init(
     let mech = res_or_ret!(token.get_mechanisms().get(mechanism.mechanism));
     if mech.info().flags & CKF_DECRYPT == CKF_DECRYPT {
         let operation = res_or_ret!(mech.decryption_new(mechanism, &key));
-        session
-            .set_operation(Operation::Decryption(operation), key.always_auth());
+        res_or_ret!(session.start_operation(
+            Operation::Decryption(operation),
+            key_handle,
+            key.always_auth()
+        ));
 
         #[cfg(feature = "fips")]
         init_fips_approval(session, mechanism.mechanism, CKF_DECRYPT, &key);
@@ -1306,10 +1513,7 @@ extern "C" fn fn_decrypt(
     }
     let rstate = global_rlock!(STATE);
     let mut session = res_or_ret!(rstate.get_session_mut(s_handle));
-    let operation = match res_or_ret!(session.get_operation_mut()) {
-        Operation::Decryption(op) => op,
-        _ => return CKR_OPERATION_NOT_INITIALIZED,
-    };
+    let operation = get_op_or_ret!(session; Decryption);
     if operation.finalized() {
         return CKR_OPERATION_NOT_INITIALIZED;
     }
@@ -1352,10 +1556,7 @@ extern "C" fn fn_decrypt_update(
     }
     let rstate = global_rlock!(STATE);
     let mut session = res_or_ret!(rstate.get_session_mut(s_handle));
-    let operation = match res_or_ret!(session.get_operation_mut()) {
-        Operation::Decryption(op) => op,
-        _ => return CKR_OPERATION_NOT_INITIALIZED,
-    };
+    let operation = get_op_or_ret!(session; Decryption);
     if operation.finalized() {
         return CKR_OPERATION_NOT_INITIALIZED;
     }
@@ -1390,10 +1591,7 @@ extern "C" fn fn_decrypt_final(
     }
     let rstate = global_rlock!(STATE);
     let mut session = res_or_ret!(rstate.get_session_mut(s_handle));
-    let operation = match res_or_ret!(session.get_operation_mut()) {
-        Operation::Decryption(op) => op,
-        _ => return CKR_OPERATION_NOT_INITIALIZED,
-    };
+    let operation = get_op_or_ret!(session; Decryption);
     if operation.finalized() {
         return CKR_OPERATION_NOT_INITIALIZED;
     }
@@ -1434,7 +1632,11 @@ extern "C" fn fn_digest_init(
     let mech = res_or_ret!(token.get_mechanisms().get(mechanism.mechanism));
     if mech.info().flags & CKF_DIGEST == CKF_DIGEST {
         let operation = res_or_ret!(mech.digest_new(mechanism));
-        session.set_operation(Operation::Digest(operation), false);
+        res_or_ret!(session.start_operation(
+            Operation::Digest(operation),
+            CK_INVALID_HANDLE,
+            false
+        ));
 
         CKR_OK
     } else {
@@ -1454,10 +1656,7 @@ extern "C" fn fn_digest(
     }
     let rstate = global_rlock!(STATE);
     let mut session = res_or_ret!(rstate.get_session_mut(s_handle));
-    let operation = match res_or_ret!(session.get_operation_mut()) {
-        Operation::Digest(op) => op,
-        _ => return CKR_OPERATION_NOT_INITIALIZED,
-    };
+    let operation = get_op_or_ret!(session; Digest);
     if operation.finalized() {
         return CKR_OPERATION_NOT_INITIALIZED;
     }
@@ -1502,10 +1701,7 @@ extern "C" fn fn_digest_update(
     }
     let rstate = global_rlock!(STATE);
     let mut session = res_or_ret!(rstate.get_session_mut(s_handle));
-    let operation = match res_or_ret!(session.get_operation_mut()) {
-        Operation::Digest(op) => op,
-        _ => return CKR_OPERATION_NOT_INITIALIZED,
-    };
+    let operation = get_op_or_ret!(session; Digest);
     if operation.finalized() {
         return CKR_OPERATION_NOT_INITIALIZED;
     }
@@ -1520,10 +1716,7 @@ extern "C" fn fn_digest_key(
     let rstate = global_rlock!(STATE);
     let mut session = res_or_ret!(rstate.get_session_mut(s_handle));
     let slot_id = session.get_slot_id();
-    let operation = match res_or_ret!(session.get_operation_mut()) {
-        Operation::Digest(op) => op,
-        _ => return CKR_OPERATION_NOT_INITIALIZED,
-    };
+    let operation = get_op_or_ret!(session; Digest);
     if operation.finalized() {
         return CKR_OPERATION_NOT_INITIALIZED;
     }
@@ -1559,10 +1752,7 @@ extern "C" fn fn_digest_final(
     }
     let rstate = global_rlock!(STATE);
     let mut session = res_or_ret!(rstate.get_session_mut(s_handle));
-    let operation = match res_or_ret!(session.get_operation_mut()) {
-        Operation::Digest(op) => op,
-        _ => return CKR_OPERATION_NOT_INITIALIZED,
-    };
+    let operation = get_op_or_ret!(session; Digest);
     if operation.finalized() {
         return CKR_OPERATION_NOT_INITIALIZED;
     }
@@ -1612,7 +1802,11 @@ extern "C" fn fn_sign_init(
     let mech = res_or_ret!(token.get_mechanisms().get(mechanism.mechanism));
     if mech.info().flags & CKF_SIGN == CKF_SIGN {
         let operation = res_or_ret!(mech.sign_new(mechanism, &key));
-        session.set_operation(Operation::Sign(operation), key.always_auth());
+        res_or_ret!(session.start_operation(
+            Operation::Sign(operation),
+            key_handle,
+            key.always_auth()
+        ));
 
         #[cfg(feature = "fips")]
         init_fips_approval(session, mechanism.mechanism, CKF_SIGN, &key);
@@ -1634,10 +1828,8 @@ extern "C" fn fn_sign(
     }
     let rstate = global_rlock!(STATE);
     let mut session = res_or_ret!(rstate.get_session_mut(s_handle));
-    let operation = match res_or_ret!(session.get_operation_mut()) {
-        Operation::Sign(op) => op,
-        _ => return CKR_OPERATION_NOT_INITIALIZED,
-    };
+    let slot_id = session.get_slot_id();
+    let operation = get_op_or_ret!(session; Sign);
     if operation.finalized() {
         return CKR_OPERATION_NOT_INITIALIZED;
     }
@@ -1659,6 +1851,10 @@ extern "C" fn fn_sign(
     let signature: &mut [u8] =
         unsafe { std::slice::from_raw_parts_mut(psignature, signature_len) };
 
+    if operation.state_key().is_some() {
+        let mut token = res_or_ret!(rstate.get_token_from_slot_mut(slot_id));
+        res_or_ret!(token.reserve_sign_state(operation.as_mut()));
+    }
     let ret = ret_to_rv!(operation.sign(data, signature));
     if ret == CKR_OK {
         unsafe {
@@ -1683,10 +1879,7 @@ extern "C" fn fn_sign_update(
     }
     let rstate = global_rlock!(STATE);
     let mut session = res_or_ret!(rstate.get_session_mut(s_handle));
-    let operation = match res_or_ret!(session.get_operation_mut()) {
-        Operation::Sign(op) => op,
-        _ => return CKR_OPERATION_NOT_INITIALIZED,
-    };
+    let operation = get_op_or_ret!(session; Sign);
     if operation.finalized() {
         return CKR_OPERATION_NOT_INITIALIZED;
     }
@@ -1704,10 +1897,8 @@ extern "C" fn fn_sign_final(
     }
     let rstate = global_rlock!(STATE);
     let mut session = res_or_ret!(rstate.get_session_mut(s_handle));
-    let operation = match res_or_ret!(session.get_operation_mut()) {
-        Operation::Sign(op) => op,
-        _ => return CKR_OPERATION_NOT_INITIALIZED,
-    };
+    let slot_id = session.get_slot_id();
+    let operation = get_op_or_ret!(session; Sign);
     if operation.finalized() {
         return CKR_OPERATION_NOT_INITIALIZED;
     }
@@ -1726,6 +1917,10 @@ extern "C" fn fn_sign_final(
     }
     let signature: &mut [u8] =
         unsafe { std::slice::from_raw_parts_mut(psignature, signature_len) };
+    if operation.state_key().is_some() {
+        let mut token = res_or_ret!(rstate.get_token_from_slot_mut(slot_id));
+        res_or_ret!(token.reserve_sign_state(operation.as_mut()));
+    }
     let ret = ret_to_rv!(operation.sign_final(signature));
     if ret == CKR_OK {
         unsafe {
@@ -1741,20 +1936,87 @@ extern "C" fn fn_sign_final(
     ret
 }
 extern "C" fn fn_sign_recover_init(
-    _session: CK_SESSION_HANDLE,
-    _mechanism: CK_MECHANISM_PTR,
-    _key: CK_OBJECT_HANDLE,
+    s_handle: CK_SESSION_HANDLE,
+    mechptr: CK_MECHANISM_PTR,
+    key_handle: CK_OBJECT_HANDLE,
 ) -> CK_RV {
-    CKR_FUNCTION_NOT_SUPPORTED
+    let rstate = global_rlock!(STATE);
+    let mut session = res_or_ret!(rstate.get_session_mut(s_handle));
+    check_op_empty_or_fail!(session; SignRecover; mechptr);
+    let mechanism: &CK_MECHANISM = unsafe { &*mechptr };
+    let slot_id = session.get_slot_id();
+    let mut token = res_or_ret!(rstate.get_token_from_slot_mut(slot_id));
+    let key = res_or_ret!(token.get_object_by_handle(key_handle));
+    ok_or_ret!(check_allowed_mechs(mechanism, &key));
+    let mech = res_or_ret!(token.get_mechanisms().get(mechanism.mechanism));
+    if mech.info().flags & CKF_SIGN_RECOVER == CKF_SIGN_RECOVER {
+        let operation = res_or_ret!(mech.sign_recover_new(mechanism, &key));
+        res_or_ret!(session.start_operation(
+            Operation::SignRecover(operation),
+            key_handle,
+            key.always_auth()
+        ));
+
+        #[cfg(feature = "fips")]
+        init_fips_approval(
+            session,
+            mechanism.mechanism,
+            CKF_SIGN_RECOVER,
+            &key,
+        );
+
+        CKR_OK
+    } else {
+        CKR_MECHANISM_INVALID
+    }
 }
 extern "C" fn fn_sign_recover(
-    _session: CK_SESSION_HANDLE,
-    _data: CK_BYTE_PTR,
-    _data_len: CK_ULONG,
-    _signature: CK_BYTE_PTR,
-    _pul_signature_len: CK_ULONG_PTR,
+    s_handle: CK_SESSION_HANDLE,
+    pdata: CK_BYTE_PTR,
+    data_len: CK_ULONG,
+    psignature: CK_BYTE_PTR,
+    pul_signature_len: CK_ULONG_PTR,
 ) -> CK_RV {
-    CKR_FUNCTION_NOT_SUPPORTED
+    if pdata.is_null() || pul_signature_len.is_null() {
+        return CKR_ARGUMENTS_BAD;
+    }
+    let rstate = global_rlock!(STATE);
+    let mut session = res_or_ret!(rstate.get_session_mut(s_handle));
+    let operation = match res_or_ret!(session.get_operation_mut()) {
+        Operation::SignRecover(op) => op,
+        _ => return CKR_OPERATION_NOT_INITIALIZED,
+    };
+    if operation.finalized() {
+        return CKR_OPERATION_NOT_INITIALIZED;
+    }
+    let signature_len = res_or_ret!(operation.sign_recover_len());
+    let sig_len = cast_or_ret!(CK_ULONG from signature_len);
+    if psignature.is_null() {
+        unsafe {
+            *pul_signature_len = sig_len;
+        }
+        return CKR_OK;
+    }
+    unsafe {
+        if *pul_signature_len < sig_len {
+            return CKR_BUFFER_TOO_SMALL;
+        }
+    }
+    let dlen = cast_or_ret!(usize from data_len => CKR_ARGUMENTS_BAD);
+    let data: &[u8] = unsafe { std::slice::from_raw_parts(pdata, dlen) };
+    let signature: &mut [u8] =
+        unsafe { std::slice::from_raw_parts_mut(psignature, signature_len) };
+
+    let outlen = res_or_ret!(operation.sign_recover(data, signature));
+    let retlen = cast_or_ret!(CK_ULONG from outlen);
+    unsafe { *pul_signature_len = retlen };
+
+    #[cfg(feature = "fips")]
+    {
+        let approved = operation.fips_approved();
+        finalize_fips_approval(session, approved);
+    }
+    CKR_OK
 }
 extern "C" fn fn_verify_init(
     s_handle: CK_SESSION_HANDLE,
@@ -1772,7 +2034,11 @@ extern "C" fn fn_verify_init(
     let mech = res_or_ret!(token.get_mechanisms().get(mechanism.mechanism));
     if mech.info().flags & CKF_VERIFY == CKF_VERIFY {
         let operation = res_or_ret!(mech.verify_new(mechanism, &key));
-        session.set_operation(Operation::Verify(operation), false);
+        res_or_ret!(session.start_operation(
+            Operation::Verify(operation),
+            key_handle,
+            false
+        ));
 
         #[cfg(feature = "fips")]
         init_fips_approval(session, mechanism.mechanism, CKF_VERIFY, &key);
@@ -1794,16 +2060,15 @@ extern "C" fn fn_verify(
     }
     let rstate = global_rlock!(STATE);
     let mut session = res_or_ret!(rstate.get_session_mut(s_handle));
-    let operation = match res_or_ret!(session.get_operation_mut()) {
-        Operation::Verify(op) => op,
-        _ => return CKR_OPERATION_NOT_INITIALIZED,
-    };
+    let operation = get_op_or_ret!(session; Verify);
     if operation.finalized() {
         return CKR_OPERATION_NOT_INITIALIZED;
     }
-    let signature_len = res_or_ret!(operation.signature_len());
-    let sig_len = cast_or_ret!(CK_ULONG from signature_len);
-    if psignature_len != sig_len {
+    let signature_len =
+        cast_or_ret!(usize from psignature_len => CKR_SIGNATURE_LEN_RANGE);
+    if operation.fixed_signature_len()
+        && signature_len != res_or_ret!(operation.signature_len())
+    {
         return CKR_SIGNATURE_LEN_RANGE;
     }
     let dlen = cast_or_ret!(usize from data_len);
@@ -1830,10 +2095,7 @@ extern "C" fn fn_verify_update(
     }
     let rstate = global_rlock!(STATE);
     let mut session = res_or_ret!(rstate.get_session_mut(s_handle));
-    let operation = match res_or_ret!(session.get_operation_mut()) {
-        Operation::Verify(op) => op,
-        _ => return CKR_OPERATION_NOT_INITIALIZED,
-    };
+    let operation = get_op_or_ret!(session; Verify);
     if operation.finalized() {
         return CKR_OPERATION_NOT_INITIALIZED;
     }
@@ -1851,16 +2113,15 @@ extern "C" fn fn_verify_final(
     }
     let rstate = global_rlock!(STATE);
     let mut session = res_or_ret!(rstate.get_session_mut(s_handle));
-    let operation = match res_or_ret!(session.get_operation_mut()) {
-        Operation::Verify(op) => op,
-        _ => return CKR_OPERATION_NOT_INITIALIZED,
-    };
+    let operation = get_op_or_ret!(session; Verify);
     if operation.finalized() {
         return CKR_OPERATION_NOT_INITIALIZED;
     }
-    let signature_len = res_or_ret!(operation.signature_len());
-    let sig_len = cast_or_ret!(CK_ULONG from signature_len);
-    if psignature_len != sig_len {
+    let signature_len =
+        cast_or_ret!(usize from psignature_len => CKR_SIGNATURE_LEN_RANGE);
+    if operation.fixed_signature_len()
+        && signature_len != res_or_ret!(operation.signature_len())
+    {
         return CKR_SIGNATURE_LEN_RANGE;
     }
     let signature: &mut [u8] =
@@ -1876,56 +2137,302 @@ extern "C" fn fn_verify_final(
     ret
 }
 extern "C" fn fn_verify_recover_init(
-    _session: CK_SESSION_HANDLE,
-    _mechanism: CK_MECHANISM_PTR,
-    _key: CK_OBJECT_HANDLE,
+    s_handle: CK_SESSION_HANDLE,
+    mechptr: CK_MECHANISM_PTR,
+    key_handle: CK_OBJECT_HANDLE,
 ) -> CK_RV {
-    CKR_FUNCTION_NOT_SUPPORTED
+    let rstate = global_rlock!(STATE);
+    let mut session = res_or_ret!(rstate.get_session_mut(s_handle));
+    check_op_empty_or_fail!(session; VerifyRecover; mechptr);
+    let mechanism: &CK_MECHANISM = unsafe { &*mechptr };
+    let slot_id = session.get_slot_id();
+    let mut token = res_or_ret!(rstate.get_token_from_slot_mut(slot_id));
+    let key = res_or_ret!(token.get_object_by_handle(key_handle));
+    ok_or_ret!(check_allowed_mechs(mechanism, &key));
+    let mech = res_or_ret!(token.get_mechanisms().get(mechanism.mechanism));
+    if mech.info().flags & CKF_VERIFY_RECOVER == CKF_VERIFY_RECOVER {
+        let operation = res_or_ret!(mech.verify_recover_new(mechanism, &key));
+        res_or_ret!(session.start_operation(
+            Operation::VerifyRecover(operation),
+            key_handle,
+            false
+        ));
+
+        #[cfg(feature = "fips")]
+        init_fips_approval(
+            session,
+            mechanism.mechanism,
+            CKF_VERIFY_RECOVER,
+            &key,
+        );
+
+        CKR_OK
+    } else {
+        CKR_MECHANISM_INVALID
+    }
 }
 extern "C" fn fn_verify_recover(
-    _session: CK_SESSION_HANDLE,
-    _signature: CK_BYTE_PTR,
-    _signature_len: CK_ULONG,
-    _data: CK_BYTE_PTR,
-    _pul_data_len: CK_ULONG_PTR,
-) -> CK_RV {
-    CKR_FUNCTION_NOT_SUPPORTED
+    s_handle: CK_SESSION_HANDLE,
+    psignature: CK_BYTE_PTR,
+    signature_len: CK_ULONG,
+    pdata: CK_BYTE_PTR,
+    pul_data_len: CK_ULONG_PTR,
+) -> CK_RV {
+    if psignature.is_null() || pul_data_len.is_null() {
+        return CKR_ARGUMENTS_BAD;
+    }
+    let rstate = global_rlock!(STATE);
+    let mut session = res_or_ret!(rstate.get_session_mut(s_handle));
+    let operation = match res_or_ret!(session.get_operation_mut()) {
+        Operation::VerifyRecover(op) => op,
+        _ => return CKR_OPERATION_NOT_INITIALIZED,
+    };
+    if operation.finalized() {
+        return CKR_OPERATION_NOT_INITIALIZED;
+    }
+    if pdata.is_null() {
+        let data_len = cast_or_ret!(
+            CK_ULONG from res_or_ret!(operation.verify_recover_len())
+        );
+        unsafe {
+            *pul_data_len = data_len;
+        }
+        return CKR_OK;
+    }
+    let slen = cast_or_ret!(usize from signature_len => CKR_ARGUMENTS_BAD);
+    let signature: &[u8] =
+        unsafe { std::slice::from_raw_parts(psignature, slen) };
+    let pdlen = unsafe { *pul_data_len as CK_ULONG };
+    let dlen = cast_or_ret!(usize from pdlen => CKR_ARGUMENTS_BAD);
+    let data: &mut [u8] =
+        unsafe { std::slice::from_raw_parts_mut(pdata, dlen) };
+    let outlen = match operation.verify_recover(signature, data) {
+        Ok(len) => len,
+        Err(e) => {
+            /* the actual length of the recovered data is only
+             * known after the signature has been processed */
+            if e.rv() == CKR_BUFFER_TOO_SMALL {
+                let reqlen = cast_or_ret!(CK_ULONG from e.reqsize());
+                unsafe { *pul_data_len = reqlen };
+            }
+            return e.rv();
+        }
+    };
+    let retlen = cast_or_ret!(CK_ULONG from outlen);
+    unsafe { *pul_data_len = retlen };
+
+    #[cfg(feature = "fips")]
+    {
+        let approved = operation.fips_approved();
+        finalize_fips_approval(session, approved);
+    }
+    CKR_OK
+}
+/* Completes a dual-function call, any failure on either side
+ * terminates both operations */
+fn dual_function_result(
+    session: &mut Session,
+    result: Result<usize>,
+    pul_len: CK_ULONG_PTR,
+) -> CK_RV {
+    match result {
+        Ok(outlen) => {
+            let retlen = cast_or_ret!(CK_ULONG from outlen);
+            unsafe { *pul_len = retlen };
+            CKR_OK
+        }
+        Err(e) => {
+            session.set_operation(Operation::Empty, false);
+            e.rv()
+        }
+    }
 }
 extern "C" fn fn_digest_encrypt_update(
-    _session: CK_SESSION_HANDLE,
-    _part: CK_BYTE_PTR,
-    _part_len: CK_ULONG,
-    _encrypted_part: CK_BYTE_PTR,
-    _pul_encrypted_part_len: CK_ULONG_PTR,
+    s_handle: CK_SESSION_HANDLE,
+    part: CK_BYTE_PTR,
+    part_len: CK_ULONG,
+    encrypted_part: CK_BYTE_PTR,
+    pul_encrypted_part_len: CK_ULONG_PTR,
 ) -> CK_RV {
-    CKR_FUNCTION_NOT_SUPPORTED
+    if part.is_null() || pul_encrypted_part_len.is_null() {
+        return CKR_ARGUMENTS_BAD;
+    }
+    let rstate = global_rlock!(STATE);
+    let mut session = res_or_ret!(rstate.get_session_mut(s_handle));
+    let (digest, encryption) =
+        get_dual_ops_or_ret!(session; Digest; Encryption);
+    if digest.finalized() || encryption.finalized() {
+        return CKR_OPERATION_NOT_INITIALIZED;
+    }
+    let plen = cast_or_ret!(usize from part_len => CKR_ARGUMENTS_BAD);
+    let encryption_len = res_or_ret!(encryption.encryption_len(plen, false));
+    let enc_len = cast_or_ret!(CK_ULONG from encryption_len);
+    if encrypted_part.is_null() {
+        unsafe {
+            *pul_encrypted_part_len = enc_len;
+        }
+        return CKR_OK;
+    }
+    /* check the buffer before any data is processed, so that the
+     * application can call again with a larger buffer */
+    unsafe {
+        if *pul_encrypted_part_len < enc_len {
+            *pul_encrypted_part_len = enc_len;
+            return CKR_BUFFER_TOO_SMALL;
+        }
+    }
+    let data: &[u8] = unsafe { std::slice::from_raw_parts(part, plen) };
+    let penclen = unsafe { *pul_encrypted_part_len as CK_ULONG };
+    let enclen = cast_or_ret!(usize from penclen => CKR_ARGUMENTS_BAD);
+    let encpart: &mut [u8] =
+        unsafe { std::slice::from_raw_parts_mut(encrypted_part, enclen) };
+    let result = match digest.digest_update(data) {
+        Ok(()) => encryption.encrypt_update(data, encpart),
+        Err(e) => Err(e),
+    };
+    dual_function_result(&mut session, result, pul_encrypted_part_len)
 }
 extern "C" fn fn_decrypt_digest_update(
-    _session: CK_SESSION_HANDLE,
-    _encrypted_part: CK_BYTE_PTR,
-    _encrypted_part_len: CK_ULONG,
-    _part: CK_BYTE_PTR,
-    _pul_part_len: CK_ULONG_PTR,
+    s_handle: CK_SESSION_HANDLE,
+    encrypted_part: CK_BYTE_PTR,
+    encrypted_part_len: CK_ULONG,
+    part: CK_BYTE_PTR,
+    pul_part_len: CK_ULONG_PTR,
 ) -> CK_RV {
-    CKR_FUNCTION_NOT_SUPPORTED
+    if encrypted_part.is_null() || pul_part_len.is_null() {
+        return CKR_ARGUMENTS_BAD;
+    }
+    let rstate = global_rlock!(STATE);
+    let mut session = res_or_ret!(rstate.get_session_mut(s_handle));
+    let (decryption, digest) =
+        get_dual_ops_or_ret!(session; Decryption; Digest);
+    if decryption.finalized() || digest.finalized() {
+        return CKR_OPERATION_NOT_INITIALIZED;
+    }
+    let elen = cast_or_ret!(usize from encrypted_part_len => CKR_ARGUMENTS_BAD);
+    let decryption_len = res_or_ret!(decryption.decryption_len(elen, false));
+    let dec_len = cast_or_ret!(CK_ULONG from decryption_len);
+    if part.is_null() {
+        unsafe {
+            *pul_part_len = dec_len;
+        }
+        return CKR_OK;
+    }
+    /* check the buffer before any data is processed, so that the
+     * application can call again with a larger buffer */
+    unsafe {
+        if *pul_part_len < dec_len {
+            *pul_part_len = dec_len;
+            return CKR_BUFFER_TOO_SMALL;
+        }
+    }
+    let enc: &[u8] =
+        unsafe { std::slice::from_raw_parts(encrypted_part, elen) };
+    let pplen = unsafe { *pul_part_len as CK_ULONG };
+    let plen = cast_or_ret!(usize from pplen => CKR_ARGUMENTS_BAD);
+    let dpart: &mut [u8] =
+        unsafe { std::slice::from_raw_parts_mut(part, plen) };
+    let result = match decryption.decrypt_update(enc, dpart) {
+        Ok(outlen) => match digest.digest_update(&dpart[..outlen]) {
+            Ok(()) => Ok(outlen),
+            Err(e) => Err(e),
+        },
+        Err(e) => Err(e),
+    };
+    dual_function_result(&mut session, result, pul_part_len)
 }
 extern "C" fn fn_sign_encrypt_update(
-    _session: CK_SESSION_HANDLE,
-    _part: CK_BYTE_PTR,
-    _part_len: CK_ULONG,
-    _encrypted_part: CK_BYTE_PTR,
-    _pul_encrypted_part_len: CK_ULONG_PTR,
+    s_handle: CK_SESSION_HANDLE,
+    part: CK_BYTE_PTR,
+    part_len: CK_ULONG,
+    encrypted_part: CK_BYTE_PTR,
+    pul_encrypted_part_len: CK_ULONG_PTR,
 ) -> CK_RV {
-    CKR_FUNCTION_NOT_SUPPORTED
+    if part.is_null() || pul_encrypted_part_len.is_null() {
+        return CKR_ARGUMENTS_BAD;
+    }
+    let rstate = global_rlock!(STATE);
+    let mut session = res_or_ret!(rstate.get_session_mut(s_handle));
+    let (sign, encryption) = get_dual_ops_or_ret!(session; Sign; Encryption);
+    if sign.finalized() || encryption.finalized() {
+        return CKR_OPERATION_NOT_INITIALIZED;
+    }
+    let plen = cast_or_ret!(usize from part_len => CKR_ARGUMENTS_BAD);
+    let encryption_len = res_or_ret!(encryption.encryption_len(plen, false));
+    let enc_len = cast_or_ret!(CK_ULONG from encryption_len);
+    if encrypted_part.is_null() {
+        unsafe {
+            *pul_encrypted_part_len = enc_len;
+        }
+        return CKR_OK;
+    }
+    /* check the buffer before any data is processed, so that the
+     * application can call again with a larger buffer */
+    unsafe {
+        if *pul_encrypted_part_len < enc_len {
+            *pul_encrypted_part_len = enc_len;
+            return CKR_BUFFER_TOO_SMALL;
+        }
+    }
+    let data: &[u8] = unsafe { std::slice::from_raw_parts(part, plen) };
+    let penclen = unsafe { *pul_encrypted_part_len as CK_ULONG };
+    let enclen = cast_or_ret!(usize from penclen => CKR_ARGUMENTS_BAD);
+    let encpart: &mut [u8] =
+        unsafe { std::slice::from_raw_parts_mut(encrypted_part, enclen) };
+    let result = match sign.sign_update(data) {
+        Ok(()) => encryption.encrypt_update(data, encpart),
+        Err(e) => Err(e),
+    };
+    dual_function_result(&mut session, result, pul_encrypted_part_len)
 }
 extern "C" fn fn_decrypt_verify_update(
-    _session: CK_SESSION_HANDLE,
-    _encrypted_part: CK_BYTE_PTR,
-    _encrypted_part_len: CK_ULONG,
-    _part: CK_BYTE_PTR,
-    _pul_part_len: CK_ULONG_PTR,
+    s_handle: CK_SESSION_HANDLE,
+    encrypted_part: CK_BYTE_PTR,
+    encrypted_part_len: CK_ULONG,
+    part: CK_BYTE_PTR,
+    pul_part_len: CK_ULONG_PTR,
 ) -> CK_RV {
-    CKR_FUNCTION_NOT_SUPPORTED
+    if encrypted_part.is_null() || pul_part_len.is_null() {
+        return CKR_ARGUMENTS_BAD;
+    }
+    let rstate = global_rlock!(STATE);
+    let mut session = res_or_ret!(rstate.get_session_mut(s_handle));
+    let (decryption, verify) =
+        get_dual_ops_or_ret!(session; Decryption; Verify);
+    if decryption.finalized() || verify.finalized() {
+        return CKR_OPERATION_NOT_INITIALIZED;
+    }
+    let elen = cast_or_ret!(usize from encrypted_part_len => CKR_ARGUMENTS_BAD);
+    let decryption_len = res_or_ret!(decryption.decryption_len(elen, false));
+    let dec_len = cast_or_ret!(CK_ULONG from decryption_len);
+    if part.is_null() {
+        unsafe {
+            *pul_part_len = dec_len;
+        }
+        return CKR_OK;
+    }
+    /* check the buffer before any data is processed, so that the
+     * application can call again with a larger buffer */
+    unsafe {
+        if *pul_part_len < dec_len {
+            *pul_part_len = dec_len;
+            return CKR_BUFFER_TOO_SMALL;
+        }
+    }
+    let enc: &[u8] =
+        unsafe { std::slice::from_raw_parts(encrypted_part, elen) };
+    let pplen = unsafe { *pul_part_len as CK_ULONG };
+    let plen = cast_or_ret!(usize from pplen => CKR_ARGUMENTS_BAD);
+    let dpart: &mut [u8] =
+        unsafe { std::slice::from_raw_parts_mut(part, plen) };
+    let result = match decryption.decrypt_update(enc, dpart) {
+        Ok(outlen) => match verify.verify_update(&dpart[..outlen]) {
+            Ok(()) => Ok(outlen),
+            Err(e) => Err(e),
+        },
+        Err(e) => Err(e),
+    };
+    dual_function_result(&mut session, result, pul_part_len)
 }
 
 extern "C" fn fn_generate_key(
@@ -2047,14 +2554,40 @@ extern "C" fn fn_generate_key_pair(
     }
 }
 
-extern "C" fn fn_wrap_key(
+/* Builds the template of a key created with the help of another key,
+ * the template stored in the typ attribute of that key (for example
+ * CKA_UNWRAP_TEMPLATE) is merged with the application's template */
+fn new_key_template(
+    key: &object::Object,
+    typ: CK_ATTRIBUTE_TYPE,
+    template: CK_ATTRIBUTE_PTR,
+    attribute_count: CK_ULONG,
+) -> Result<CkAttrs<'static>> {
+    let mut tmpl = if template.is_null() && attribute_count == 0 {
+        CkAttrs::new()
+    } else {
+        CkAttrs::from_ptr(template, attribute_count)?
+    };
+    if let Some(a) = key.get_attr(typ) {
+        tmpl.merge_template(&a.to_attr_array()?)?;
+    }
+    Ok(tmpl)
+}
+
+/* Common implementation of C_WrapKey and C_WrapKeyAuthenticated, the
+ * associated data is available only for the latter */
+fn wrap_key_common(
     s_handle: CK_SESSION_HANDLE,
     mechptr: CK_MECHANISM_PTR,
     wrapping_key_handle: CK_OBJECT_HANDLE,
     key_handle: CK_OBJECT_HANDLE,
+    aad: Option<&[u8]>,
     wrapped_key: CK_BYTE_PTR,
     pul_wrapped_key_len: CK_ULONG_PTR,
 ) -> CK_RV {
+    if mechptr.is_null() || pul_wrapped_key_len.is_null() {
+        return CKR_ARGUMENTS_BAD;
+    }
     let rstate = global_rlock!(STATE);
     let session = res_or_ret!(rstate.get_session(s_handle));
 
@@ -2082,19 +2615,71 @@ extern "C" fn fn_wrap_key(
             return CKR_WRAPPING_KEY_HANDLE_INVALID;
         }
     }
+    if let Some(a) = wkey.get_attr(CKA_WRAP_TEMPLATE) {
+        if !key.match_attributes(&res_or_ret!(a.to_attr_array())) {
+            return CKR_KEY_NOT_WRAPPABLE;
+        }
+    }
 
-    let pwraplen = unsafe { *pul_wrapped_key_len as CK_ULONG };
-    let wraplen = cast_or_ret!(usize from pwraplen => CKR_ARGUMENTS_BAD);
-    let wrapped: &mut [u8] =
-        unsafe { std::slice::from_raw_parts_mut(wrapped_key, wraplen) };
-    let outlen =
-        res_or_ret!(mech.wrap_key(mechanism, &wkey, &key, wrapped, factory,));
+    let wrapped: &mut [u8] = if wrapped_key.is_null() {
+        &mut []
+    } else {
+        let pwraplen = unsafe { *pul_wrapped_key_len as CK_ULONG };
+        let wraplen = cast_or_ret!(usize from pwraplen => CKR_ARGUMENTS_BAD);
+        unsafe { std::slice::from_raw_parts_mut(wrapped_key, wraplen) }
+    };
+    let result = match aad {
+        None => mech.wrap_key(mechanism, &wkey, &key, wrapped, factory),
+        Some(adata) => mech.wrap_key_authenticated(
+            mechanism,
+            &wkey,
+            &key,
+            adata,
+            wrapped,
+            factory.as_ref(),
+        ),
+    };
+    let outlen = match result {
+        Ok(len) => len,
+        Err(e) => {
+            if e.rv() == CKR_BUFFER_TOO_SMALL {
+                let reqlen = cast_or_ret!(CK_ULONG from e.reqsize());
+                unsafe { *pul_wrapped_key_len = reqlen };
+                if wrapped_key.is_null() {
+                    return CKR_OK;
+                }
+            }
+            return e.rv();
+        }
+    };
     let retlen = cast_or_ret!(CK_ULONG from outlen);
     unsafe { *pul_wrapped_key_len = retlen };
     CKR_OK
 }
 
-extern "C" fn fn_unwrap_key(
+extern "C" fn fn_wrap_key(
+    s_handle: CK_SESSION_HANDLE,
+    mechptr: CK_MECHANISM_PTR,
+    wrapping_key_handle: CK_OBJECT_HANDLE,
+    key_handle: CK_OBJECT_HANDLE,
+    wrapped_key: CK_BYTE_PTR,
+    pul_wrapped_key_len: CK_ULONG_PTR,
+) -> CK_RV {
+    wrap_key_common(
+        s_handle,
+        mechptr,
+        wrapping_key_handle,
+        key_handle,
+        None,
+        wrapped_key,
+        pul_wrapped_key_len,
+    )
+}
+
+/* Common implementation of C_UnwrapKey and C_UnwrapKeyAuthenticated,
+ * the associated data is available only for the latter */
+#[allow(clippy::too_many_arguments)]
+fn unwrap_key_common(
     s_handle: CK_SESSION_HANDLE,
     mechptr: CK_MECHANISM_PTR,
     unwrapping_key_handle: CK_OBJECT_HANDLE,
@@ -2102,29 +2687,39 @@ extern "C" fn fn_unwrap_key(
     wrapped_key_len: CK_ULONG,
     template: CK_ATTRIBUTE_PTR,
     attribute_count: CK_ULONG,
+    aad: Option<&[u8]>,
     key_handle: CK_OBJECT_HANDLE_PTR,
 ) -> CK_RV {
+    if mechptr.is_null() || wrapped_key.is_null() || key_handle.is_null() {
+        return CKR_ARGUMENTS_BAD;
+    }
     let rstate = global_rlock!(STATE);
     let session = res_or_ret!(rstate.get_session(s_handle));
 
     let mechanism: &CK_MECHANISM = unsafe { &*mechptr };
-    let cnt = cast_or_ret!(usize from attribute_count);
-    let tmpl: &mut [CK_ATTRIBUTE] =
-        unsafe { std::slice::from_raw_parts_mut(template, cnt) };
-    if !session.is_writable() {
-        fail_if_cka_token_true!(&*tmpl);
-    }
     let slot_id = session.get_slot_id();
     let mut token = res_or_ret!(rstate.get_token_from_slot_mut(slot_id));
     let key = res_or_ret!(token.get_object_by_handle(unwrapping_key_handle));
     ok_or_ret!(check_allowed_mechs(mechanism, &key));
+
+    /* the unwrapping key template is applied to the unwrapped key */
+    let tmpl = res_or_ret!(new_key_template(
+        &key,
+        CKA_UNWRAP_TEMPLATE,
+        template,
+        attribute_count
+    ));
+    let tmpl = tmpl.as_slice();
+    if !session.is_writable() {
+        fail_if_cka_token_true!(tmpl);
+    }
     let factories = token.get_object_factories();
     let factory =
         res_or_ret!(factories.get_obj_factory_from_key_template(tmpl));
     let wklen = cast_or_ret!(usize from wrapped_key_len);
     let data: &[u8] = unsafe { std::slice::from_raw_parts(wrapped_key, wklen) };
     let mech = res_or_ret!(token.get_mechanisms().get(mechanism.mechanism));
-    if mech.info().flags & CKF_WRAP != CKF_WRAP {
+    if mech.info().flags & CKF_UNWRAP != CKF_UNWRAP {
         return CKR_MECHANISM_INVALID;
     }
 
@@ -2133,7 +2728,17 @@ extern "C" fn fn_unwrap_key(
         return CKR_WRAPPING_KEY_HANDLE_INVALID;
     }
 
-    let result = mech.unwrap_key(mechanism, &key, data, tmpl, factory);
+    let result = match aad {
+        None => mech.unwrap_key(mechanism, &key, data, tmpl, factory),
+        Some(adata) => mech.unwrap_key_authenticated(
+            mechanism,
+            &key,
+            data,
+            adata,
+            tmpl,
+            factory.as_ref(),
+        ),
+    };
     match result {
         Ok(obj) => {
             let kh = res_or_ret!(token.insert_object(s_handle, obj));
@@ -2146,6 +2751,29 @@ extern "C" fn fn_unwrap_key(
     }
 }
 
+extern "C" fn fn_unwrap_key(
+    s_handle: CK_SESSION_HANDLE,
+    mechptr: CK_MECHANISM_PTR,
+    unwrapping_key_handle: CK_OBJECT_HANDLE,
+    wrapped_key: CK_BYTE_PTR,
+    wrapped_key_len: CK_ULONG,
+    template: CK_ATTRIBUTE_PTR,
+    attribute_count: CK_ULONG,
+    key_handle: CK_OBJECT_HANDLE_PTR,
+) -> CK_RV {
+    unwrap_key_common(
+        s_handle,
+        mechptr,
+        unwrapping_key_handle,
+        wrapped_key,
+        wrapped_key_len,
+        template,
+        attribute_count,
+        None,
+        key_handle,
+    )
+}
+
 extern "C" fn fn_derive_key(
     s_handle: CK_SESSION_HANDLE,
     mechptr: CK_MECHANISM_PTR,
@@ -2379,14 +3007,38 @@ extern "C" fn fn_get_function_status(_session: CK_SESSION_HANDLE) -> CK_RV {
     CKR_FUNCTION_NOT_SUPPORTED
 }
 extern "C" fn fn_cancel_function(_session: CK_SESSION_HANDLE) -> CK_RV {
-    CKR_FUNCTION_NOT_SUPPORTED
+    /* legacy function, C_SessionCancel replaces it */
+    CKR_FUNCTION_NOT_PARALLEL
 }
+/* how often the configuration is checked while blocked in
+ * C_WaitForSlotEvent */
+const SLOT_EVENT_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
 extern "C" fn fn_wait_for_slot_event(
-    _flags: CK_FLAGS,
-    _slot: CK_SLOT_ID_PTR,
+    flags: CK_FLAGS,
+    slot: CK_SLOT_ID_PTR,
     _rserved: CK_VOID_PTR,
 ) -> CK_RV {
-    CKR_FUNCTION_NOT_SUPPORTED
+    if slot.is_null() {
+        return CKR_ARGUMENTS_BAD;
+    }
+    loop {
+        let ret = refresh_slots();
+        if ret != CKR_OK {
+            return ret;
+        }
+        /* this also catches C_Finalize being called while blocked */
+        if let Some(slot_id) = global_wlock!(STATE).pop_slot_event() {
+            unsafe {
+                *slot = slot_id;
+            }
+            return CKR_OK;
+        }
+        if flags & CKF_DONT_BLOCK == CKF_DONT_BLOCK {
+            return CKR_NO_EVENT;
+        }
+        std::thread::sleep(SLOT_EVENT_POLL_INTERVAL);
+    }
 }
 
 pub static FNLIST_240: CK_FUNCTION_LIST = CK_FUNCTION_LIST {
@@ -2465,14 +3117,26 @@ pub static FNLIST_240: CK_FUNCTION_LIST = CK_FUNCTION_LIST {
 };
 
 extern "C" fn fn_get_slot_list(
-    _token_present: CK_BBOOL,
+    token_present: CK_BBOOL,
     slot_list: CK_SLOT_ID_PTR,
     count: CK_ULONG_PTR,
 ) -> CK_RV {
     if count.is_null() {
         return CKR_ARGUMENTS_BAD;
     }
-    let slotids = global_rlock!(STATE).get_slots_ids();
+    let ret = refresh_slots();
+    if ret != CKR_OK {
+        return ret;
+    }
+    let rstate = global_rlock!(STATE);
+    let mut slotids = rstate.get_slots_ids();
+    if token_present == CK_TRUE {
+        slotids.retain(|id| match rstate.get_slot(*id) {
+            Ok(slot) => slot.is_token_present(),
+            Err(_) => false,
+        });
+    }
+    drop(rstate);
     let silen = cast_or_ret!(CK_ULONG from slotids.len());
     if slot_list.is_null() {
         unsafe {
@@ -2523,6 +3187,9 @@ extern "C" fn fn_get_token_info(
         Ok(s) => s,
         Err(e) => return e.rv(),
     };
+    if !slot.is_token_present() {
+        return CKR_TOKEN_NOT_PRESENT;
+    }
     let tokinfo = slot.get_token_info();
     unsafe {
         core::ptr::write(info as *mut _, tokinfo);
@@ -2530,7 +3197,7 @@ extern "C" fn fn_get_token_info(
     CKR_OK
 }
 
-static IMPLEMENTED_VERSION: CK_VERSION = CK_VERSION { major: 3, minor: 0 };
+static IMPLEMENTED_VERSION: CK_VERSION = CK_VERSION { major: 3, minor: 2 };
 static MANUFACTURER_ID: [CK_UTF8CHAR; 32usize] =
     *b"Kryoptic                        ";
 static LIBRARY_DESCRIPTION: [CK_UTF8CHAR; 32usize] =
@@ -2562,24 +3229,80 @@ pub extern "C" fn C_GetFunctionList(fnlist: CK_FUNCTION_LIST_PTR_PTR) -> CK_RV {
 
 // Additional 3.0 functions
 
-extern "C" fn fn_login_user(
-    _session: CK_SESSION_HANDLE,
-    _user_type: CK_USER_TYPE,
-    _pin: CK_UTF8CHAR_PTR,
-    _pin_len: CK_ULONG,
-    _username: CK_UTF8CHAR_PTR,
-    _username_len: CK_ULONG,
-) -> CK_RV {
-    CKR_FUNCTION_NOT_SUPPORTED
-}
-extern "C" fn fn_session_cancel(
-    _session: CK_SESSION_HANDLE,
-    _flags: CK_FLAGS,
-) -> CK_RV {
-    CKR_FUNCTION_NOT_SUPPORTED
+macro_rules! username_or_ret {
+    ($username:expr, $username_len:expr) => {{
+        if $username.is_null() || $username_len == 0 {
+            return CKR_ARGUMENTS_BAD;
+        }
+        let name = bytes_to_slice!($username, $username_len, u8);
+        match std::str::from_utf8(name) {
+            Ok(n) => n,
+            Err(_) => return CKR_ARGUMENTS_BAD,
+        }
+    }};
 }
 
-extern "C" fn fn_message_encrypt_init(
+extern "C" fn fn_login_user(
+    s_handle: CK_SESSION_HANDLE,
+    user_type: CK_USER_TYPE,
+    pin: CK_UTF8CHAR_PTR,
+    pin_len: CK_ULONG,
+    username: CK_UTF8CHAR_PTR,
+    username_len: CK_ULONG,
+) -> CK_RV {
+    let vpin = bytes_to_slice!(pin, pin_len, u8);
+    /* the username is meaningless for context specific logins,
+     * those always apply to the user already logged in */
+    if user_type == CKU_CONTEXT_SPECIFIC {
+        return login(s_handle, user_type, vpin, None);
+    }
+    let name = username_or_ret!(username, username_len);
+    login(s_handle, user_type, vpin, Some(name))
+}
+
+/// Vendor extension to let the SO create a named user for use with
+/// C_LoginUser, or reset the pin of an existing one
+#[no_mangle]
+pub extern "C" fn KR_CreateUser(
+    s_handle: CK_SESSION_HANDLE,
+    username: CK_UTF8CHAR_PTR,
+    username_len: CK_ULONG,
+    pin: CK_UTF8CHAR_PTR,
+    pin_len: CK_ULONG,
+) -> CK_RV {
+    let rstate = global_rlock!(STATE);
+    let mut token = res_or_ret!(rstate.get_token_from_session_mut(s_handle));
+    let name = username_or_ret!(username, username_len);
+    let vpin = bytes_to_slice!(pin, pin_len, u8);
+    if vpin.is_empty() {
+        return CKR_PIN_INVALID;
+    }
+    ret_to_rv!(token.add_user(name, vpin))
+}
+
+/// Vendor extension to let the SO remove a named user
+#[no_mangle]
+pub extern "C" fn KR_RemoveUser(
+    s_handle: CK_SESSION_HANDLE,
+    username: CK_UTF8CHAR_PTR,
+    username_len: CK_ULONG,
+) -> CK_RV {
+    let rstate = global_rlock!(STATE);
+    let mut token = res_or_ret!(rstate.get_token_from_session_mut(s_handle));
+    let name = username_or_ret!(username, username_len);
+    ret_to_rv!(token.remove_user(name))
+}
+extern "C" fn fn_session_cancel(
+    s_handle: CK_SESSION_HANDLE,
+    flags: CK_FLAGS,
+) -> CK_RV {
+    let rstate = global_rlock!(STATE);
+    let mut session = res_or_ret!(rstate.get_session_mut(s_handle));
+    session.cancel_operations(flags);
+    CKR_OK
+}
+
+extern "C" fn fn_message_encrypt_init(
     s_handle: CK_SESSION_HANDLE,
     mechptr: CK_MECHANISM_PTR,
     key_handle: CK_OBJECT_HANDLE,
@@ -2595,7 +3318,11 @@ extern "C" fn fn_message_encrypt_init(
     let mech = res_or_ret!(token.get_mechanisms().get(mechanism.mechanism));
     if mech.info().flags & CKF_MESSAGE_ENCRYPT != 0 {
         let operation = res_or_ret!(mech.msg_encryption_op(mechanism, &key));
-        session.set_operation(Operation::MsgEncryption(operation), false);
+        res_or_ret!(session.start_operation(
+            Operation::MsgEncryption(operation),
+            key_handle,
+            false
+        ));
         #[cfg(feature = "fips")]
         init_fips_approval(session, mechanism.mechanism, CKF_ENCRYPT, &key);
 
@@ -2833,7 +3560,11 @@ extern "C" fn fn_message_decrypt_init(
     let mech = res_or_ret!(token.get_mechanisms().get(mechanism.mechanism));
     if mech.info().flags & CKF_MESSAGE_DECRYPT != 0 {
         let operation = res_or_ret!(mech.msg_decryption_op(mechanism, &key));
-        session.set_operation(Operation::MsgDecryption(operation), false);
+        res_or_ret!(session.start_operation(
+            Operation::MsgDecryption(operation),
+            key_handle,
+            false
+        ));
         #[cfg(feature = "fips")]
         init_fips_approval(session, mechanism.mechanism, CKF_DECRYPT, &key);
 
@@ -3057,94 +3788,832 @@ extern "C" fn fn_message_decrypt_final(s_handle: CK_SESSION_HANDLE) -> CK_RV {
 }
 
 extern "C" fn fn_message_sign_init(
-    _session: CK_SESSION_HANDLE,
-    _mechanism: CK_MECHANISM_PTR,
-    _key: CK_OBJECT_HANDLE,
+    s_handle: CK_SESSION_HANDLE,
+    mechptr: CK_MECHANISM_PTR,
+    key_handle: CK_OBJECT_HANDLE,
 ) -> CK_RV {
-    CKR_FUNCTION_NOT_SUPPORTED
+    let rstate = global_rlock!(STATE);
+    let mut session = res_or_ret!(rstate.get_session_mut(s_handle));
+    check_op_empty_or_fail!(session; MsgSign; mechptr);
+    let mechanism: &CK_MECHANISM = unsafe { &*mechptr };
+    let slot_id = session.get_slot_id();
+    let mut token = res_or_ret!(rstate.get_token_from_slot_mut(slot_id));
+    let key = res_or_ret!(token.get_object_by_handle(key_handle));
+    ok_or_ret!(check_allowed_mechs(mechanism, &key));
+    let mech = res_or_ret!(token.get_mechanisms().get(mechanism.mechanism));
+    if mech.info().flags & CKF_MESSAGE_SIGN != 0 {
+        let operation = res_or_ret!(mech.msg_sign_op(mechanism, &key));
+        res_or_ret!(session.start_operation(
+            Operation::MsgSign(operation),
+            key_handle,
+            key.always_auth()
+        ));
+        #[cfg(feature = "fips")]
+        init_fips_approval(session, mechanism.mechanism, CKF_SIGN, &key);
+
+        CKR_OK
+    } else {
+        CKR_MECHANISM_INVALID
+    }
 }
+
 extern "C" fn fn_sign_message(
-    _session: CK_SESSION_HANDLE,
-    _parameter: CK_VOID_PTR,
-    _parameter_len: CK_ULONG,
-    _data: CK_BYTE_PTR,
-    _data_len: CK_ULONG,
-    _signature: CK_BYTE_PTR,
-    _pul_signature_len: CK_ULONG_PTR,
+    s_handle: CK_SESSION_HANDLE,
+    parameter: CK_VOID_PTR,
+    parameter_len: CK_ULONG,
+    data: CK_BYTE_PTR,
+    data_len: CK_ULONG,
+    signature: CK_BYTE_PTR,
+    pul_signature_len: CK_ULONG_PTR,
 ) -> CK_RV {
-    CKR_FUNCTION_NOT_SUPPORTED
+    if data.is_null() || pul_signature_len.is_null() {
+        return CKR_ARGUMENTS_BAD;
+    }
+
+    let dlen = cast_or_ret!(usize from data_len => CKR_ARGUMENTS_BAD);
+
+    let rstate = global_rlock!(STATE);
+    let mut session = res_or_ret!(rstate.get_session_mut(s_handle));
+    let operation = match res_or_ret!(session.get_operation_mut()) {
+        Operation::MsgSign(op) => op,
+        _ => return CKR_OPERATION_NOT_INITIALIZED,
+    };
+    if operation.finalized() {
+        return CKR_OPERATION_NOT_INITIALIZED;
+    }
+    if operation.busy() {
+        return CKR_OPERATION_ACTIVE;
+    }
+
+    let signature_len = res_or_ret!(operation.msg_signature_len());
+    let sig_len = cast_or_ret!(CK_ULONG from signature_len);
+    if signature.is_null() {
+        unsafe {
+            *pul_signature_len = sig_len;
+        }
+        return CKR_OK;
+    }
+    unsafe {
+        if *pul_signature_len < sig_len {
+            return CKR_BUFFER_TOO_SMALL;
+        }
+    }
+
+    let data: &[u8] = unsafe { std::slice::from_raw_parts(data, dlen) };
+    let signature: &mut [u8] =
+        unsafe { std::slice::from_raw_parts_mut(signature, signature_len) };
+
+    res_or_ret!(operation.msg_sign(parameter, parameter_len, data, signature));
+    unsafe { *pul_signature_len = sig_len };
+
+    #[cfg(feature = "fips")]
+    {
+        let approved = operation.fips_approved();
+        finalize_fips_approval(session, approved);
+    }
+    CKR_OK
 }
+
 extern "C" fn fn_sign_message_begin(
-    _session: CK_SESSION_HANDLE,
-    _parameter: CK_VOID_PTR,
-    _parameter_len: CK_ULONG,
+    s_handle: CK_SESSION_HANDLE,
+    parameter: CK_VOID_PTR,
+    parameter_len: CK_ULONG,
 ) -> CK_RV {
-    CKR_FUNCTION_NOT_SUPPORTED
+    let rstate = global_rlock!(STATE);
+    let mut session = res_or_ret!(rstate.get_session_mut(s_handle));
+    let operation = match res_or_ret!(session.get_operation_mut()) {
+        Operation::MsgSign(op) => op,
+        _ => return CKR_OPERATION_NOT_INITIALIZED,
+    };
+    if operation.finalized() {
+        return CKR_OPERATION_NOT_INITIALIZED;
+    }
+    if operation.busy() {
+        return CKR_OPERATION_ACTIVE;
+    }
+
+    ret_to_rv!(operation.msg_sign_begin(parameter, parameter_len))
 }
+
 extern "C" fn fn_sign_message_next(
-    _session: CK_SESSION_HANDLE,
-    _parameter: CK_VOID_PTR,
-    _parameter_len: CK_ULONG,
-    _data: CK_BYTE_PTR,
-    _data_len: CK_ULONG,
-    _signature: CK_BYTE_PTR,
-    _pul_signature_len: CK_ULONG_PTR,
+    s_handle: CK_SESSION_HANDLE,
+    parameter: CK_VOID_PTR,
+    parameter_len: CK_ULONG,
+    data: CK_BYTE_PTR,
+    data_len: CK_ULONG,
+    signature: CK_BYTE_PTR,
+    pul_signature_len: CK_ULONG_PTR,
 ) -> CK_RV {
-    CKR_FUNCTION_NOT_SUPPORTED
+    if data.is_null() && data_len != 0 {
+        return CKR_ARGUMENTS_BAD;
+    }
+
+    let dlen = cast_or_ret!(usize from data_len => CKR_ARGUMENTS_BAD);
+
+    let rstate = global_rlock!(STATE);
+    let mut session = res_or_ret!(rstate.get_session_mut(s_handle));
+    let operation = match res_or_ret!(session.get_operation_mut()) {
+        Operation::MsgSign(op) => op,
+        _ => return CKR_OPERATION_NOT_INITIALIZED,
+    };
+    if operation.finalized() {
+        return CKR_OPERATION_NOT_INITIALIZED;
+    }
+    if !operation.busy() {
+        return CKR_OPERATION_NOT_INITIALIZED;
+    }
+
+    let data: &[u8] = if data.is_null() {
+        &[]
+    } else {
+        unsafe { std::slice::from_raw_parts(data, dlen) }
+    };
+
+    /* a null signature length marks a part that is not the last one */
+    if pul_signature_len.is_null() {
+        return ret_to_rv!(operation.msg_sign_next(
+            parameter,
+            parameter_len,
+            data
+        ));
+    }
+
+    let signature_len = res_or_ret!(operation.msg_signature_len());
+    let sig_len = cast_or_ret!(CK_ULONG from signature_len);
+    if signature.is_null() {
+        unsafe {
+            *pul_signature_len = sig_len;
+        }
+        return CKR_OK;
+    }
+    unsafe {
+        if *pul_signature_len < sig_len {
+            return CKR_BUFFER_TOO_SMALL;
+        }
+    }
+
+    let signature: &mut [u8] =
+        unsafe { std::slice::from_raw_parts_mut(signature, signature_len) };
+
+    res_or_ret!(operation.msg_sign_final(
+        parameter,
+        parameter_len,
+        data,
+        signature
+    ));
+    unsafe { *pul_signature_len = sig_len };
+
+    #[cfg(feature = "fips")]
+    {
+        let approved = operation.fips_approved();
+        finalize_fips_approval(session, approved);
+    }
+    CKR_OK
 }
-extern "C" fn fn_message_sign_final(_session: CK_SESSION_HANDLE) -> CK_RV {
-    CKR_FUNCTION_NOT_SUPPORTED
+
+extern "C" fn fn_message_sign_final(s_handle: CK_SESSION_HANDLE) -> CK_RV {
+    let rstate = global_rlock!(STATE);
+    let mut session = res_or_ret!(rstate.get_session_mut(s_handle));
+    let operation = match res_or_ret!(session.get_operation_mut()) {
+        Operation::MsgSign(op) => op,
+        _ => return CKR_OPERATION_NOT_INITIALIZED,
+    };
+    if operation.finalized() {
+        return CKR_OPERATION_NOT_INITIALIZED;
+    }
+    ret_to_rv!(operation.finalize())
 }
+
 extern "C" fn fn_message_verify_init(
-    _session: CK_SESSION_HANDLE,
-    _mechanism: CK_MECHANISM_PTR,
-    _key: CK_OBJECT_HANDLE,
+    s_handle: CK_SESSION_HANDLE,
+    mechptr: CK_MECHANISM_PTR,
+    key_handle: CK_OBJECT_HANDLE,
 ) -> CK_RV {
-    CKR_FUNCTION_NOT_SUPPORTED
+    let rstate = global_rlock!(STATE);
+    let mut session = res_or_ret!(rstate.get_session_mut(s_handle));
+    check_op_empty_or_fail!(session; MsgVerify; mechptr);
+    let mechanism: &CK_MECHANISM = unsafe { &*mechptr };
+    let slot_id = session.get_slot_id();
+    let mut token = res_or_ret!(rstate.get_token_from_slot_mut(slot_id));
+    let key = res_or_ret!(token.get_object_by_handle(key_handle));
+    ok_or_ret!(check_allowed_mechs(mechanism, &key));
+    let mech = res_or_ret!(token.get_mechanisms().get(mechanism.mechanism));
+    if mech.info().flags & CKF_MESSAGE_VERIFY != 0 {
+        let operation = res_or_ret!(mech.msg_verify_op(mechanism, &key));
+        res_or_ret!(session.start_operation(
+            Operation::MsgVerify(operation),
+            key_handle,
+            false
+        ));
+        #[cfg(feature = "fips")]
+        init_fips_approval(session, mechanism.mechanism, CKF_VERIFY, &key);
+
+        CKR_OK
+    } else {
+        CKR_MECHANISM_INVALID
+    }
 }
+
 extern "C" fn fn_verify_message(
-    _session: CK_SESSION_HANDLE,
-    _parameter: CK_VOID_PTR,
-    _parameter_len: CK_ULONG,
-    _data: CK_BYTE_PTR,
-    _data_len: CK_ULONG,
-    _signature: CK_BYTE_PTR,
-    _signature_len: CK_ULONG,
-) -> CK_RV {
-    CKR_FUNCTION_NOT_SUPPORTED
-}
-extern "C" fn fn_verify_message_begin(
-    _session: CK_SESSION_HANDLE,
-    _parameter: CK_VOID_PTR,
-    _parameter_len: CK_ULONG,
-) -> CK_RV {
-    CKR_FUNCTION_NOT_SUPPORTED
-}
-extern "C" fn fn_verify_message_next(
-    _session: CK_SESSION_HANDLE,
-    _parameter: CK_VOID_PTR,
-    _parameter_len: CK_ULONG,
-    _data: CK_BYTE_PTR,
-    _data_len: CK_ULONG,
-    _signature: CK_BYTE_PTR,
-    _signature_len: CK_ULONG,
+    s_handle: CK_SESSION_HANDLE,
+    parameter: CK_VOID_PTR,
+    parameter_len: CK_ULONG,
+    data: CK_BYTE_PTR,
+    data_len: CK_ULONG,
+    signature: CK_BYTE_PTR,
+    signature_len: CK_ULONG,
 ) -> CK_RV {
-    CKR_FUNCTION_NOT_SUPPORTED
-}
-extern "C" fn fn_message_verify_final(_session: CK_SESSION_HANDLE) -> CK_RV {
-    CKR_FUNCTION_NOT_SUPPORTED
-}
+    if data.is_null() || signature.is_null() {
+        return CKR_ARGUMENTS_BAD;
+    }
 
-pub static FNLIST_300: CK_FUNCTION_LIST_3_0 = CK_FUNCTION_LIST_3_0 {
-    version: CK_VERSION { major: 3, minor: 0 },
-    C_Initialize: Some(fn_initialize),
-    C_Finalize: Some(fn_finalize),
-    C_GetInfo: Some(fn_get_info),
-    C_GetFunctionList: Some(C_GetFunctionList),
-    C_GetSlotList: Some(fn_get_slot_list),
-    C_GetSlotInfo: Some(fn_get_slot_info),
-    C_GetTokenInfo: Some(fn_get_token_info),
-    C_GetMechanismList: Some(fn_get_mechanism_list),
+    let dlen = cast_or_ret!(usize from data_len => CKR_ARGUMENTS_BAD);
+    let slen = cast_or_ret!(usize from signature_len => CKR_ARGUMENTS_BAD);
+
+    let rstate = global_rlock!(STATE);
+    let mut session = res_or_ret!(rstate.get_session_mut(s_handle));
+    let operation = match res_or_ret!(session.get_operation_mut()) {
+        Operation::MsgVerify(op) => op,
+        _ => return CKR_OPERATION_NOT_INITIALIZED,
+    };
+    if operation.finalized() {
+        return CKR_OPERATION_NOT_INITIALIZED;
+    }
+    if operation.busy() {
+        return CKR_OPERATION_ACTIVE;
+    }
+    if slen != res_or_ret!(operation.msg_signature_len()) {
+        return CKR_SIGNATURE_LEN_RANGE;
+    }
+
+    let data: &[u8] = unsafe { std::slice::from_raw_parts(data, dlen) };
+    let signature: &[u8] =
+        unsafe { std::slice::from_raw_parts(signature, slen) };
+
+    res_or_ret!(operation.msg_verify(
+        parameter,
+        parameter_len,
+        data,
+        signature
+    ));
+
+    #[cfg(feature = "fips")]
+    {
+        let approved = operation.fips_approved();
+        finalize_fips_approval(session, approved);
+    }
+    CKR_OK
+}
+
+extern "C" fn fn_verify_message_begin(
+    s_handle: CK_SESSION_HANDLE,
+    parameter: CK_VOID_PTR,
+    parameter_len: CK_ULONG,
+) -> CK_RV {
+    let rstate = global_rlock!(STATE);
+    let mut session = res_or_ret!(rstate.get_session_mut(s_handle));
+    let operation = match res_or_ret!(session.get_operation_mut()) {
+        Operation::MsgVerify(op) => op,
+        _ => return CKR_OPERATION_NOT_INITIALIZED,
+    };
+    if operation.finalized() {
+        return CKR_OPERATION_NOT_INITIALIZED;
+    }
+    if operation.busy() {
+        return CKR_OPERATION_ACTIVE;
+    }
+
+    ret_to_rv!(operation.msg_verify_begin(parameter, parameter_len))
+}
+
+extern "C" fn fn_verify_message_next(
+    s_handle: CK_SESSION_HANDLE,
+    parameter: CK_VOID_PTR,
+    parameter_len: CK_ULONG,
+    data: CK_BYTE_PTR,
+    data_len: CK_ULONG,
+    signature: CK_BYTE_PTR,
+    signature_len: CK_ULONG,
+) -> CK_RV {
+    if data.is_null() && data_len != 0 {
+        return CKR_ARGUMENTS_BAD;
+    }
+
+    let dlen = cast_or_ret!(usize from data_len => CKR_ARGUMENTS_BAD);
+    let slen = cast_or_ret!(usize from signature_len => CKR_ARGUMENTS_BAD);
+
+    let rstate = global_rlock!(STATE);
+    let mut session = res_or_ret!(rstate.get_session_mut(s_handle));
+    let operation = match res_or_ret!(session.get_operation_mut()) {
+        Operation::MsgVerify(op) => op,
+        _ => return CKR_OPERATION_NOT_INITIALIZED,
+    };
+    if operation.finalized() {
+        return CKR_OPERATION_NOT_INITIALIZED;
+    }
+    if !operation.busy() {
+        return CKR_OPERATION_NOT_INITIALIZED;
+    }
+
+    let data: &[u8] = if data.is_null() {
+        &[]
+    } else {
+        unsafe { std::slice::from_raw_parts(data, dlen) }
+    };
+
+    /* a null signature marks a part that is not the last one */
+    if signature.is_null() {
+        return ret_to_rv!(operation.msg_verify_next(
+            parameter,
+            parameter_len,
+            data
+        ));
+    }
+
+    if slen != res_or_ret!(operation.msg_signature_len()) {
+        return CKR_SIGNATURE_LEN_RANGE;
+    }
+    let signature: &[u8] =
+        unsafe { std::slice::from_raw_parts(signature, slen) };
+
+    res_or_ret!(operation.msg_verify_final(
+        parameter,
+        parameter_len,
+        data,
+        signature
+    ));
+
+    #[cfg(feature = "fips")]
+    {
+        let approved = operation.fips_approved();
+        finalize_fips_approval(session, approved);
+    }
+    CKR_OK
+}
+
+extern "C" fn fn_message_verify_final(s_handle: CK_SESSION_HANDLE) -> CK_RV {
+    let rstate = global_rlock!(STATE);
+    let mut session = res_or_ret!(rstate.get_session_mut(s_handle));
+    let operation = match res_or_ret!(session.get_operation_mut()) {
+        Operation::MsgVerify(op) => op,
+        _ => return CKR_OPERATION_NOT_INITIALIZED,
+    };
+    if operation.finalized() {
+        return CKR_OPERATION_NOT_INITIALIZED;
+    }
+    ret_to_rv!(operation.finalize())
+}
+
+extern "C" fn fn_encapsulate_key(
+    s_handle: CK_SESSION_HANDLE,
+    mechptr: CK_MECHANISM_PTR,
+    pubkey_handle: CK_OBJECT_HANDLE,
+    template: CK_ATTRIBUTE_PTR,
+    attribute_count: CK_ULONG,
+    ciphertext: CK_BYTE_PTR,
+    pul_ciphertext_len: CK_ULONG_PTR,
+    key_handle: CK_OBJECT_HANDLE_PTR,
+) -> CK_RV {
+    if pul_ciphertext_len.is_null() {
+        return CKR_ARGUMENTS_BAD;
+    }
+    let rstate = global_rlock!(STATE);
+    let session = res_or_ret!(rstate.get_session(s_handle));
+
+    let mechanism: &CK_MECHANISM = unsafe { &*mechptr };
+    let slot_id = session.get_slot_id();
+    let mut token = res_or_ret!(rstate.get_token_from_slot_mut(slot_id));
+    let key = res_or_ret!(token.get_object_by_handle(pubkey_handle));
+
+    /* key checks */
+    if !res_or_ret!(key.get_attr_as_bool(CKA_ENCAPSULATE)) {
+        return CKR_KEY_FUNCTION_NOT_PERMITTED;
+    }
+    ok_or_ret!(check_allowed_mechs(mechanism, &key));
+
+    let mech = res_or_ret!(token.get_mechanisms().get(mechanism.mechanism));
+    if mech.info().flags & CKF_ENCAPSULATE != CKF_ENCAPSULATE {
+        return CKR_MECHANISM_INVALID;
+    }
+
+    let ctlen = res_or_ret!(mech.encapsulate_ciphertext_len(&key));
+    let retlen = cast_or_ret!(CK_ULONG from ctlen);
+    if ciphertext.is_null() {
+        unsafe { *pul_ciphertext_len = retlen };
+        return CKR_OK;
+    }
+    let pctlen = unsafe { *pul_ciphertext_len };
+    if pctlen < retlen {
+        unsafe { *pul_ciphertext_len = retlen };
+        return CKR_BUFFER_TOO_SMALL;
+    }
+
+    /* the encapsulating key template is applied to the new key */
+    let tmpl = res_or_ret!(new_key_template(
+        &key,
+        CKA_ENCAPSULATE_TEMPLATE,
+        template,
+        attribute_count
+    ));
+    let tmpl = tmpl.as_slice();
+    if !session.is_writable() {
+        fail_if_cka_token_true!(tmpl);
+    }
+    let factories = token.get_object_factories();
+    let factory =
+        res_or_ret!(factories.get_obj_factory_from_key_template(tmpl));
+
+    let ct: &mut [u8] =
+        unsafe { std::slice::from_raw_parts_mut(ciphertext, ctlen) };
+    let (obj, outlen) = res_or_ret!(mech.encapsulate(
+        mechanism,
+        &key,
+        factory.as_ref(),
+        tmpl,
+        ct
+    ));
+    let retlen = cast_or_ret!(CK_ULONG from outlen);
+    let kh = res_or_ret!(token.insert_object(s_handle, obj));
+    unsafe {
+        *pul_ciphertext_len = retlen;
+        core::ptr::write(key_handle as *mut _, kh);
+    }
+    CKR_OK
+}
+
+extern "C" fn fn_decapsulate_key(
+    s_handle: CK_SESSION_HANDLE,
+    mechptr: CK_MECHANISM_PTR,
+    privkey_handle: CK_OBJECT_HANDLE,
+    template: CK_ATTRIBUTE_PTR,
+    attribute_count: CK_ULONG,
+    ciphertext: CK_BYTE_PTR,
+    ciphertext_len: CK_ULONG,
+    key_handle: CK_OBJECT_HANDLE_PTR,
+) -> CK_RV {
+    if ciphertext.is_null() {
+        return CKR_ARGUMENTS_BAD;
+    }
+    let rstate = global_rlock!(STATE);
+    let session = res_or_ret!(rstate.get_session(s_handle));
+
+    let mechanism: &CK_MECHANISM = unsafe { &*mechptr };
+    let slot_id = session.get_slot_id();
+    let mut token = res_or_ret!(rstate.get_token_from_slot_mut(slot_id));
+    let key = res_or_ret!(token.get_object_by_handle(privkey_handle));
+
+    /* key checks */
+    if !res_or_ret!(key.get_attr_as_bool(CKA_DECAPSULATE)) {
+        return CKR_KEY_FUNCTION_NOT_PERMITTED;
+    }
+    ok_or_ret!(check_allowed_mechs(mechanism, &key));
+
+    let mech = res_or_ret!(token.get_mechanisms().get(mechanism.mechanism));
+    if mech.info().flags & CKF_DECAPSULATE != CKF_DECAPSULATE {
+        return CKR_MECHANISM_INVALID;
+    }
+
+    /* the decapsulating key template is applied to the new key */
+    let tmpl = res_or_ret!(new_key_template(
+        &key,
+        CKA_DECAPSULATE_TEMPLATE,
+        template,
+        attribute_count
+    ));
+    let tmpl = tmpl.as_slice();
+    if !session.is_writable() {
+        fail_if_cka_token_true!(tmpl);
+    }
+    let factories = token.get_object_factories();
+    let factory =
+        res_or_ret!(factories.get_obj_factory_from_key_template(tmpl));
+
+    let ctlen = cast_or_ret!(usize from ciphertext_len);
+    let ct: &[u8] = unsafe { std::slice::from_raw_parts(ciphertext, ctlen) };
+    let obj = res_or_ret!(mech.decapsulate(
+        mechanism,
+        &key,
+        factory.as_ref(),
+        tmpl,
+        ct
+    ));
+    let kh = res_or_ret!(token.insert_object(s_handle, obj));
+    unsafe {
+        core::ptr::write(key_handle as *mut _, kh);
+    }
+    CKR_OK
+}
+
+extern "C" fn fn_verify_signature_init(
+    s_handle: CK_SESSION_HANDLE,
+    mechptr: CK_MECHANISM_PTR,
+    key_handle: CK_OBJECT_HANDLE,
+    psignature: CK_BYTE_PTR,
+    psignature_len: CK_ULONG,
+) -> CK_RV {
+    let rstate = global_rlock!(STATE);
+    let mut session = res_or_ret!(rstate.get_session_mut(s_handle));
+    check_op_empty_or_fail!(session; VerifySignature; mechptr);
+    if psignature.is_null() {
+        return CKR_ARGUMENTS_BAD;
+    }
+    let mechanism: &CK_MECHANISM = unsafe { &*mechptr };
+    let slot_id = session.get_slot_id();
+    let mut token = res_or_ret!(rstate.get_token_from_slot_mut(slot_id));
+    let key = res_or_ret!(token.get_object_by_handle(key_handle));
+    ok_or_ret!(check_allowed_mechs(mechanism, &key));
+    let mech = res_or_ret!(token.get_mechanisms().get(mechanism.mechanism));
+    if mech.info().flags & CKF_VERIFY == CKF_VERIFY {
+        let signature_len =
+            cast_or_ret!(usize from psignature_len => CKR_SIGNATURE_LEN_RANGE);
+        let signature: &[u8] =
+            unsafe { std::slice::from_raw_parts(psignature, signature_len) };
+        let operation = res_or_ret!(VerifySignatureOperation::new(
+            res_or_ret!(mech.verify_new(mechanism, &key)),
+            signature
+        ));
+        res_or_ret!(session.start_operation(
+            Operation::VerifySignature(Box::new(operation)),
+            key_handle,
+            false
+        ));
+
+        #[cfg(feature = "fips")]
+        init_fips_approval(session, mechanism.mechanism, CKF_VERIFY, &key);
+
+        CKR_OK
+    } else {
+        CKR_MECHANISM_INVALID
+    }
+}
+
+extern "C" fn fn_verify_signature(
+    s_handle: CK_SESSION_HANDLE,
+    pdata: CK_BYTE_PTR,
+    data_len: CK_ULONG,
+) -> CK_RV {
+    if pdata.is_null() {
+        return CKR_ARGUMENTS_BAD;
+    }
+    let rstate = global_rlock!(STATE);
+    let mut session = res_or_ret!(rstate.get_session_mut(s_handle));
+    let operation = get_op_or_ret!(session; VerifySignature);
+    if operation.finalized() {
+        return CKR_OPERATION_NOT_INITIALIZED;
+    }
+    let dlen = cast_or_ret!(usize from data_len);
+    let data: &[u8] = unsafe { std::slice::from_raw_parts(pdata, dlen) };
+    let ret = ret_to_rv!(operation.verify(data));
+
+    #[cfg(feature = "fips")]
+    if ret == CKR_OK {
+        let approved = operation.fips_approved();
+        finalize_fips_approval(session, approved);
+    }
+
+    ret
+}
+
+extern "C" fn fn_verify_signature_update(
+    s_handle: CK_SESSION_HANDLE,
+    part: CK_BYTE_PTR,
+    part_len: CK_ULONG,
+) -> CK_RV {
+    if part.is_null() {
+        return CKR_ARGUMENTS_BAD;
+    }
+    let rstate = global_rlock!(STATE);
+    let mut session = res_or_ret!(rstate.get_session_mut(s_handle));
+    let operation = get_op_or_ret!(session; VerifySignature);
+    if operation.finalized() {
+        return CKR_OPERATION_NOT_INITIALIZED;
+    }
+    let plen = cast_or_ret!(usize from part_len);
+    let data: &[u8] = unsafe { std::slice::from_raw_parts(part, plen) };
+    ret_to_rv!(operation.verify_update(data))
+}
+
+extern "C" fn fn_verify_signature_final(s_handle: CK_SESSION_HANDLE) -> CK_RV {
+    let rstate = global_rlock!(STATE);
+    let mut session = res_or_ret!(rstate.get_session_mut(s_handle));
+    let operation = get_op_or_ret!(session; VerifySignature);
+    if operation.finalized() {
+        return CKR_OPERATION_NOT_INITIALIZED;
+    }
+    let ret = ret_to_rv!(operation.verify_final());
+
+    #[cfg(feature = "fips")]
+    if ret == CKR_OK {
+        let approved = operation.fips_approved();
+        finalize_fips_approval(session, approved);
+    }
+
+    ret
+}
+
+#[cfg(not(feature = "fips"))]
+extern "C" fn fn_get_session_validation_flags(
+    _session: CK_SESSION_HANDLE,
+    _flags_type: CK_SESSION_VALIDATION_FLAGS_TYPE,
+    _pflags: CK_FLAGS_PTR,
+) -> CK_RV {
+    CKR_FUNCTION_NOT_SUPPORTED
+}
+
+extern "C" fn fn_async_complete(
+    _session: CK_SESSION_HANDLE,
+    _function_name: CK_UTF8CHAR_PTR,
+    _result: CK_ASYNC_DATA_PTR,
+) -> CK_RV {
+    CKR_FUNCTION_NOT_SUPPORTED
+}
+
+extern "C" fn fn_async_get_id(
+    _session: CK_SESSION_HANDLE,
+    _function_name: CK_UTF8CHAR_PTR,
+    _id: CK_ULONG_PTR,
+) -> CK_RV {
+    CKR_FUNCTION_NOT_SUPPORTED
+}
+
+extern "C" fn fn_async_join(
+    _session: CK_SESSION_HANDLE,
+    _function_name: CK_UTF8CHAR_PTR,
+    _id: CK_ULONG,
+    _data: CK_BYTE_PTR,
+    _data_len: CK_ULONG,
+) -> CK_RV {
+    CKR_FUNCTION_NOT_SUPPORTED
+}
+
+extern "C" fn fn_wrap_key_authenticated(
+    s_handle: CK_SESSION_HANDLE,
+    mechptr: CK_MECHANISM_PTR,
+    wrapping_key_handle: CK_OBJECT_HANDLE,
+    key_handle: CK_OBJECT_HANDLE,
+    associated_data: CK_BYTE_PTR,
+    associated_data_len: CK_ULONG,
+    wrapped_key: CK_BYTE_PTR,
+    pul_wrapped_key_len: CK_ULONG_PTR,
+) -> CK_RV {
+    let alen = cast_or_ret!(
+        usize from associated_data_len => CKR_ARGUMENTS_BAD
+    );
+    let adata: &[u8] = if associated_data.is_null() {
+        &[]
+    } else {
+        unsafe { std::slice::from_raw_parts(associated_data, alen) }
+    };
+    wrap_key_common(
+        s_handle,
+        mechptr,
+        wrapping_key_handle,
+        key_handle,
+        Some(adata),
+        wrapped_key,
+        pul_wrapped_key_len,
+    )
+}
+
+extern "C" fn fn_unwrap_key_authenticated(
+    s_handle: CK_SESSION_HANDLE,
+    mechptr: CK_MECHANISM_PTR,
+    unwrapping_key_handle: CK_OBJECT_HANDLE,
+    wrapped_key: CK_BYTE_PTR,
+    wrapped_key_len: CK_ULONG,
+    template: CK_ATTRIBUTE_PTR,
+    attribute_count: CK_ULONG,
+    associated_data: CK_BYTE_PTR,
+    associated_data_len: CK_ULONG,
+    key_handle: CK_OBJECT_HANDLE_PTR,
+) -> CK_RV {
+    let alen = cast_or_ret!(
+        usize from associated_data_len => CKR_ARGUMENTS_BAD
+    );
+    let adata: &[u8] = if associated_data.is_null() {
+        &[]
+    } else {
+        unsafe { std::slice::from_raw_parts(associated_data, alen) }
+    };
+    unwrap_key_common(
+        s_handle,
+        mechptr,
+        unwrapping_key_handle,
+        wrapped_key,
+        wrapped_key_len,
+        template,
+        attribute_count,
+        Some(adata),
+        key_handle,
+    )
+}
+
+pub static FNLIST_300: CK_FUNCTION_LIST_3_0 = CK_FUNCTION_LIST_3_0 {
+    version: CK_VERSION { major: 3, minor: 0 },
+    C_Initialize: Some(fn_initialize),
+    C_Finalize: Some(fn_finalize),
+    C_GetInfo: Some(fn_get_info),
+    C_GetFunctionList: Some(C_GetFunctionList),
+    C_GetSlotList: Some(fn_get_slot_list),
+    C_GetSlotInfo: Some(fn_get_slot_info),
+    C_GetTokenInfo: Some(fn_get_token_info),
+    C_GetMechanismList: Some(fn_get_mechanism_list),
+    C_GetMechanismInfo: Some(fn_get_mechanism_info),
+    C_InitToken: Some(fn_init_token),
+    C_InitPIN: Some(fn_init_pin),
+    C_SetPIN: Some(fn_set_pin),
+    C_OpenSession: Some(fn_open_session),
+    C_CloseSession: Some(fn_close_session),
+    C_CloseAllSessions: Some(fn_close_all_sessions),
+    C_GetSessionInfo: Some(fn_get_session_info),
+    C_GetOperationState: Some(fn_get_operation_state),
+    C_SetOperationState: Some(fn_set_operation_state),
+    C_Login: Some(fn_login),
+    C_Logout: Some(fn_logout),
+    C_CreateObject: Some(fn_create_object),
+    C_CopyObject: Some(fn_copy_object),
+    C_DestroyObject: Some(fn_destroy_object),
+    C_GetObjectSize: Some(fn_get_object_size),
+    C_GetAttributeValue: Some(fn_get_attribute_value),
+    C_SetAttributeValue: Some(fn_set_attribute_value),
+    C_FindObjectsInit: Some(fn_find_objects_init),
+    C_FindObjects: Some(fn_find_objects),
+    C_FindObjectsFinal: Some(fn_find_objects_final),
+    C_EncryptInit: Some(fn_encrypt_init),
+    C_Encrypt: Some(fn_encrypt),
+    C_EncryptUpdate: Some(fn_encrypt_update),
+    C_EncryptFinal: Some(fn_encrypt_final),
+    C_DecryptInit: Some(fn_decrypt_init),
+    C_Decrypt: Some(fn_decrypt),
+    C_DecryptUpdate: Some(fn_decrypt_update),
+    C_DecryptFinal: Some(fn_decrypt_final),
+    C_DigestInit: Some(fn_digest_init),
+    C_Digest: Some(fn_digest),
+    C_DigestUpdate: Some(fn_digest_update),
+    C_DigestKey: Some(fn_digest_key),
+    C_DigestFinal: Some(fn_digest_final),
+    C_SignInit: Some(fn_sign_init),
+    C_Sign: Some(fn_sign),
+    C_SignUpdate: Some(fn_sign_update),
+    C_SignFinal: Some(fn_sign_final),
+    C_SignRecoverInit: Some(fn_sign_recover_init),
+    C_SignRecover: Some(fn_sign_recover),
+    C_VerifyInit: Some(fn_verify_init),
+    C_Verify: Some(fn_verify),
+    C_VerifyUpdate: Some(fn_verify_update),
+    C_VerifyFinal: Some(fn_verify_final),
+    C_VerifyRecoverInit: Some(fn_verify_recover_init),
+    C_VerifyRecover: Some(fn_verify_recover),
+    C_DigestEncryptUpdate: Some(fn_digest_encrypt_update),
+    C_DecryptDigestUpdate: Some(fn_decrypt_digest_update),
+    C_SignEncryptUpdate: Some(fn_sign_encrypt_update),
+    C_DecryptVerifyUpdate: Some(fn_decrypt_verify_update),
+    C_GenerateKey: Some(fn_generate_key),
+    C_GenerateKeyPair: Some(fn_generate_key_pair),
+    C_WrapKey: Some(fn_wrap_key),
+    C_UnwrapKey: Some(fn_unwrap_key),
+    C_DeriveKey: Some(fn_derive_key),
+    C_SeedRandom: Some(fn_seed_random),
+    C_GenerateRandom: Some(fn_generate_random),
+    C_GetFunctionStatus: Some(fn_get_function_status),
+    C_CancelFunction: Some(fn_cancel_function),
+    C_WaitForSlotEvent: Some(fn_wait_for_slot_event),
+    C_GetInterfaceList: Some(C_GetInterfaceList),
+    C_GetInterface: Some(C_GetInterface),
+    C_LoginUser: Some(fn_login_user),
+    C_SessionCancel: Some(fn_session_cancel),
+    C_MessageEncryptInit: Some(fn_message_encrypt_init),
+    C_EncryptMessage: Some(fn_encrypt_message),
+    C_EncryptMessageBegin: Some(fn_encrypt_message_begin),
+    C_EncryptMessageNext: Some(fn_encrypt_message_next),
+    C_MessageEncryptFinal: Some(fn_message_encrypt_final),
+    C_MessageDecryptInit: Some(fn_message_decrypt_init),
+    C_DecryptMessage: Some(fn_decrypt_message),
+    C_DecryptMessageBegin: Some(fn_decrypt_message_begin),
+    C_DecryptMessageNext: Some(fn_decrypt_message_next),
+    C_MessageDecryptFinal: Some(fn_message_decrypt_final),
+    C_MessageSignInit: Some(fn_message_sign_init),
+    C_SignMessage: Some(fn_sign_message),
+    C_SignMessageBegin: Some(fn_sign_message_begin),
+    C_SignMessageNext: Some(fn_sign_message_next),
+    C_MessageSignFinal: Some(fn_message_sign_final),
+    C_MessageVerifyInit: Some(fn_message_verify_init),
+    C_VerifyMessage: Some(fn_verify_message),
+    C_VerifyMessageBegin: Some(fn_verify_message_begin),
+    C_VerifyMessageNext: Some(fn_verify_message_next),
+    C_MessageVerifyFinal: Some(fn_message_verify_final),
+};
+
+pub static FNLIST_320: CK_FUNCTION_LIST_3_2 = CK_FUNCTION_LIST_3_2 {
+    version: CK_VERSION { major: 3, minor: 2 },
+    C_Initialize: Some(fn_initialize),
+    C_Finalize: Some(fn_finalize),
+    C_GetInfo: Some(fn_get_info),
+    C_GetFunctionList: Some(C_GetFunctionList),
+    C_GetSlotList: Some(fn_get_slot_list),
+    C_GetSlotInfo: Some(fn_get_slot_info),
+    C_GetTokenInfo: Some(fn_get_token_info),
+    C_GetMechanismList: Some(fn_get_mechanism_list),
     C_GetMechanismInfo: Some(fn_get_mechanism_info),
     C_InitToken: Some(fn_init_token),
     C_InitPIN: Some(fn_init_pin),
@@ -3229,6 +4698,18 @@ pub static FNLIST_300: CK_FUNCTION_LIST_3_0 = CK_FUNCTION_LIST_3_0 {
     C_VerifyMessageBegin: Some(fn_verify_message_begin),
     C_VerifyMessageNext: Some(fn_verify_message_next),
     C_MessageVerifyFinal: Some(fn_message_verify_final),
+    C_EncapsulateKey: Some(fn_encapsulate_key),
+    C_DecapsulateKey: Some(fn_decapsulate_key),
+    C_VerifySignatureInit: Some(fn_verify_signature_init),
+    C_VerifySignature: Some(fn_verify_signature),
+    C_VerifySignatureUpdate: Some(fn_verify_signature_update),
+    C_VerifySignatureFinal: Some(fn_verify_signature_final),
+    C_GetSessionValidationFlags: Some(fn_get_session_validation_flags),
+    C_AsyncComplete: Some(fn_async_complete),
+    C_AsyncGetID: Some(fn_async_get_id),
+    C_AsyncJoin: Some(fn_async_join),
+    C_WrapKeyAuthenticated: Some(fn_wrap_key_authenticated),
+    C_UnwrapKeyAuthenticated: Some(fn_unwrap_key_authenticated),
 };
 
 static INTERFACE_NAME_STD_NUL: &str = "PKCS 11\0";
@@ -3245,6 +4726,12 @@ static INTERFACE_300: CK_INTERFACE = CK_INTERFACE {
     flags: 0,
 };
 
+static INTERFACE_320: CK_INTERFACE = CK_INTERFACE {
+    pInterfaceName: INTERFACE_NAME_STD_NUL.as_ptr() as *mut u8,
+    pFunctionList: &FNLIST_320 as *const _ as *const ::std::os::raw::c_void,
+    flags: 0,
+};
+
 #[cfg(feature = "fips")]
 include!("fips/interface.rs");
 
@@ -3259,7 +4746,11 @@ unsafe impl Sync for InterfaceData {}
 unsafe impl Send for InterfaceData {}
 
 static INTERFACE_SET: Lazy<Vec<InterfaceData>> = Lazy::new(|| {
-    let mut v = Vec::with_capacity(3);
+    let mut v = Vec::with_capacity(4);
+    v.push(InterfaceData {
+        interface: std::ptr::addr_of!(INTERFACE_320),
+        version: FNLIST_320.version,
+    });
     v.push(InterfaceData {
         interface: std::ptr::addr_of!(INTERFACE_300),
         version: FNLIST_300.version,
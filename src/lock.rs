@@ -0,0 +1,186 @@
+// Copyright 2024 Simo Sorce
+// See LICENSE.txt file for terms
+
+/* Locking primitives used to honor the CK_C_INITIALIZE_ARGS locking
+ * model. When the application supplies mutex callbacks and does not
+ * allow native OS locking, every access to the global state is also
+ * serialized through the application provided mutex. */
+
+use std::cell::Cell;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+
+use crate::error::Result;
+use crate::interface::*;
+
+use once_cell::sync::Lazy;
+
+type MutexFn = unsafe extern "C" fn(CK_VOID_PTR) -> CK_RV;
+
+struct AppMutex {
+    mutex: CK_VOID_PTR,
+    destroy: MutexFn,
+    lock: MutexFn,
+    unlock: MutexFn,
+}
+
+/* The mutex handle is opaque and is only ever passed back to the
+ * application callbacks, which are required to be thread safe */
+unsafe impl Send for AppMutex {}
+unsafe impl Sync for AppMutex {}
+
+impl Drop for AppMutex {
+    fn drop(&mut self) {
+        let _ = unsafe { (self.destroy)(self.mutex) };
+    }
+}
+
+static APP_MUTEX: Lazy<RwLock<Option<Arc<AppMutex>>>> =
+    Lazy::new(|| RwLock::new(None));
+
+/* Set when the application passed CKF_LIBRARY_CANT_CREATE_OS_THREADS */
+static NO_OS_THREADS: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    /* Number of locks currently held by this thread, the application
+     * mutex is only acquired by the outermost one as application
+     * mutexes are not required to be recursive */
+    static LOCK_DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Validates the locking arguments passed to C_Initialize and sets up
+/// the application mutex if one must be used. The locking model is
+/// chosen by the first initialization, later calls only validate the
+/// arguments.
+pub fn configure(args: &CK_C_INITIALIZE_ARGS, initialized: bool) -> Result<()> {
+    let funcs = match (
+        args.CreateMutex,
+        args.DestroyMutex,
+        args.LockMutex,
+        args.UnlockMutex,
+    ) {
+        (None, None, None, None) => None,
+        (Some(c), Some(d), Some(l), Some(u)) => Some((c, d, l, u)),
+        _ => return Err(CKR_ARGUMENTS_BAD)?,
+    };
+    if initialized {
+        return Ok(());
+    }
+
+    NO_OS_THREADS.store(
+        args.flags & CKF_LIBRARY_CANT_CREATE_OS_THREADS != 0,
+        Ordering::Relaxed,
+    );
+
+    /* native locking is preferred whenever the application allows it,
+     * and no locking callbacks means either native locking is allowed
+     * or the application will not call us concurrently */
+    if args.flags & CKF_OS_LOCKING_OK != 0 {
+        return Ok(());
+    }
+    let (create, destroy, lock, unlock) = match funcs {
+        Some(f) => f,
+        None => return Ok(()),
+    };
+
+    let mut app = match APP_MUTEX.write() {
+        Ok(a) => a,
+        Err(_) => return Err(CKR_CANT_LOCK)?,
+    };
+    if app.is_some() {
+        return Ok(());
+    }
+    let mut mutex: CK_VOID_PTR = std::ptr::null_mut();
+    if unsafe { create(&mut mutex) } != CKR_OK || mutex.is_null() {
+        Err(CKR_CANT_LOCK)?;
+    }
+    *app = Some(Arc::new(AppMutex {
+        mutex,
+        destroy,
+        lock,
+        unlock,
+    }));
+    Ok(())
+}
+
+/// Drops the application mutex (if any) on C_Finalize, the mutex is
+/// destroyed once the last lock holding it is released.
+pub fn release() {
+    if let Ok(mut app) = APP_MUTEX.write() {
+        *app = None;
+    }
+    NO_OS_THREADS.store(false, Ordering::Relaxed);
+}
+
+/// Returns false if the application does not allow the library to
+/// create its own threads, any background work must then be carried
+/// out in the calling thread.
+#[allow(dead_code)]
+pub fn os_threads_allowed() -> bool {
+    !NO_OS_THREADS.load(Ordering::Relaxed)
+}
+
+/// Holds the application mutex (when one is configured) for as long as
+/// it lives.
+pub struct AppLock {
+    mutex: Option<Arc<AppMutex>>,
+}
+
+impl AppLock {
+    pub fn acquire() -> Result<AppLock> {
+        let depth = LOCK_DEPTH.with(|d| d.get());
+        let mutex = if depth == 0 {
+            match APP_MUTEX.read() {
+                Ok(a) => a.clone(),
+                Err(_) => return Err(CKR_GENERAL_ERROR)?,
+            }
+        } else {
+            None
+        };
+        if let Some(m) = &mutex {
+            let ret = unsafe { (m.lock)(m.mutex) };
+            if ret != CKR_OK {
+                return Err(ret)?;
+            }
+        }
+        LOCK_DEPTH.with(|d| d.set(depth + 1));
+        Ok(AppLock { mutex })
+    }
+}
+
+impl Drop for AppLock {
+    fn drop(&mut self) {
+        LOCK_DEPTH.with(|d| d.set(d.get() - 1));
+        if let Some(m) = &self.mutex {
+            let _ = unsafe { (m.unlock)(m.mutex) };
+        }
+    }
+}
+
+/// A native lock guard paired with the application lock, the native
+/// guard is always released first.
+pub struct Locked<G> {
+    guard: G,
+    _lock: AppLock,
+}
+
+impl<G> Locked<G> {
+    pub fn new(guard: G, lock: AppLock) -> Locked<G> {
+        Locked { guard, _lock: lock }
+    }
+}
+
+impl<G: Deref> Deref for Locked<G> {
+    type Target = G::Target;
+
+    fn deref(&self) -> &G::Target {
+        &self.guard
+    }
+}
+
+impl<G: DerefMut> DerefMut for Locked<G> {
+    fn deref_mut(&mut self) -> &mut G::Target {
+        &mut self.guard
+    }
+}
@@ -3,6 +3,7 @@
 
 use std::collections::BTreeMap;
 
+use crate::attribute::Attribute;
 use crate::error::Result;
 use crate::interface::*;
 use crate::object::{Object, ObjectFactories, ObjectFactory};
@@ -46,6 +47,20 @@ pub trait Mechanism: Debug + Send + Sync {
     ) -> Result<Box<dyn Verify>> {
         Err(CKR_MECHANISM_INVALID)?
     }
+    fn sign_recover_new(
+        &self,
+        _: &CK_MECHANISM,
+        _: &Object,
+    ) -> Result<Box<dyn SignRecover>> {
+        Err(CKR_MECHANISM_INVALID)?
+    }
+    fn verify_recover_new(
+        &self,
+        _: &CK_MECHANISM,
+        _: &Object,
+    ) -> Result<Box<dyn VerifyRecover>> {
+        Err(CKR_MECHANISM_INVALID)?
+    }
 
     fn generate_key(
         &self,
@@ -88,10 +103,60 @@ pub trait Mechanism: Debug + Send + Sync {
         Err(CKR_MECHANISM_INVALID)?
     }
 
+    fn wrap_key_authenticated(
+        &self,
+        _: &CK_MECHANISM,
+        _: &Object,
+        _: &Object,
+        _: &[u8],
+        _: &mut [u8],
+        _: &dyn ObjectFactory,
+    ) -> Result<usize> {
+        Err(CKR_MECHANISM_INVALID)?
+    }
+
+    fn unwrap_key_authenticated(
+        &self,
+        _: &CK_MECHANISM,
+        _: &Object,
+        _: &[u8],
+        _: &[u8],
+        _: &[CK_ATTRIBUTE],
+        _: &dyn ObjectFactory,
+    ) -> Result<Object> {
+        Err(CKR_MECHANISM_INVALID)?
+    }
+
     fn derive_operation(&self, _: &CK_MECHANISM) -> Result<Operation> {
         Err(CKR_MECHANISM_INVALID)?
     }
 
+    fn encapsulate_ciphertext_len(&self, _: &Object) -> Result<usize> {
+        Err(CKR_MECHANISM_INVALID)?
+    }
+
+    fn encapsulate(
+        &self,
+        _: &CK_MECHANISM,
+        _: &Object,
+        _: &dyn ObjectFactory,
+        _: &[CK_ATTRIBUTE],
+        _: &mut [u8],
+    ) -> Result<(Object, usize)> {
+        Err(CKR_MECHANISM_INVALID)?
+    }
+
+    fn decapsulate(
+        &self,
+        _: &CK_MECHANISM,
+        _: &Object,
+        _: &dyn ObjectFactory,
+        _: &[CK_ATTRIBUTE],
+        _: &[u8],
+    ) -> Result<Object> {
+        Err(CKR_MECHANISM_INVALID)?
+    }
+
     fn msg_encryption_op(
         &self,
         _: &CK_MECHANISM,
@@ -107,6 +172,22 @@ pub trait Mechanism: Debug + Send + Sync {
     ) -> Result<Box<dyn MsgDecryption>> {
         Err(CKR_MECHANISM_INVALID)?
     }
+
+    fn msg_sign_op(
+        &self,
+        _: &CK_MECHANISM,
+        _: &Object,
+    ) -> Result<Box<dyn MsgSign>> {
+        Err(CKR_MECHANISM_INVALID)?
+    }
+
+    fn msg_verify_op(
+        &self,
+        _: &CK_MECHANISM,
+        _: &Object,
+    ) -> Result<Box<dyn MsgVerify>> {
+        Err(CKR_MECHANISM_INVALID)?
+    }
 }
 
 #[derive(Debug)]
@@ -194,6 +275,9 @@ pub trait Encryption: MechOperation {
     ) -> Result<usize> {
         Err(CKR_GENERAL_ERROR)?
     }
+    fn try_clone(&self) -> Result<Box<dyn Encryption>> {
+        Err(CKR_STATE_UNSAVEABLE)?
+    }
 }
 
 pub trait Decryption: MechOperation {
@@ -217,6 +301,9 @@ pub trait Decryption: MechOperation {
     ) -> Result<usize> {
         Err(CKR_GENERAL_ERROR)?
     }
+    fn try_clone(&self) -> Result<Box<dyn Decryption>> {
+        Err(CKR_STATE_UNSAVEABLE)?
+    }
 }
 
 pub trait SearchOperation: Debug + Send + Sync {
@@ -239,6 +326,9 @@ pub trait Digest: MechOperation {
     fn digest_len(&self) -> Result<usize> {
         Err(CKR_GENERAL_ERROR)?
     }
+    fn try_clone(&self) -> Result<Box<dyn Digest>> {
+        Err(CKR_STATE_UNSAVEABLE)?
+    }
 }
 
 pub trait Mac: MechOperation {
@@ -271,6 +361,19 @@ pub trait Sign: MechOperation {
     fn signature_len(&self) -> Result<usize> {
         Err(CKR_GENERAL_ERROR)?
     }
+    fn try_clone(&self) -> Result<Box<dyn Sign>> {
+        Err(CKR_STATE_UNSAVEABLE)?
+    }
+    /* Stateful signature schemes return the handle of the key object
+     * holding the signing state, see Token::reserve_sign_state() */
+    fn state_key(&self) -> Option<CK_OBJECT_HANDLE> {
+        None
+    }
+    /* reserves the next signing state from the current key object and
+     * returns the attributes to be stored before signing */
+    fn reserve_state(&mut self, _key: &Object) -> Result<Vec<Attribute>> {
+        Err(CKR_GENERAL_ERROR)?
+    }
 }
 
 pub trait Verify: MechOperation {
@@ -287,6 +390,90 @@ pub trait Verify: MechOperation {
     fn signature_len(&self) -> Result<usize> {
         Err(CKR_GENERAL_ERROR)?
     }
+    /* signatures that embed their own parameters (like HSS) have no
+     * length known in advance, it is checked when parsing them */
+    fn fixed_signature_len(&self) -> bool {
+        true
+    }
+    fn try_clone(&self) -> Result<Box<dyn Verify>> {
+        Err(CKR_STATE_UNSAVEABLE)?
+    }
+}
+
+/* C_VerifySignature* receive the signature before the data, so it is
+ * kept with the verification operation until it can be checked */
+#[derive(Debug)]
+pub struct VerifySignatureOperation {
+    op: Box<dyn Verify>,
+    signature: Vec<u8>,
+}
+
+impl VerifySignatureOperation {
+    pub fn new(
+        op: Box<dyn Verify>,
+        signature: &[u8],
+    ) -> Result<VerifySignatureOperation> {
+        if op.fixed_signature_len() && signature.len() != op.signature_len()? {
+            return Err(CKR_SIGNATURE_LEN_RANGE)?;
+        }
+        Ok(VerifySignatureOperation {
+            op,
+            signature: signature.to_vec(),
+        })
+    }
+
+    pub fn verify(&mut self, data: &[u8]) -> Result<()> {
+        self.op.verify(data, &self.signature)
+    }
+
+    pub fn verify_update(&mut self, data: &[u8]) -> Result<()> {
+        self.op.verify_update(data)
+    }
+
+    pub fn verify_final(&mut self) -> Result<()> {
+        self.op.verify_final(&self.signature)
+    }
+}
+
+impl MechOperation for VerifySignatureOperation {
+    fn finalized(&self) -> bool {
+        self.op.finalized()
+    }
+    #[cfg(feature = "fips")]
+    fn fips_approved(&self) -> Option<bool> {
+        self.op.fips_approved()
+    }
+    fn mechanism(&self) -> Result<CK_MECHANISM_TYPE> {
+        self.op.mechanism()
+    }
+}
+
+pub trait SignRecover: MechOperation {
+    fn sign_recover(
+        &mut self,
+        _data: &[u8],
+        _signature: &mut [u8],
+    ) -> Result<usize> {
+        Err(CKR_GENERAL_ERROR)?
+    }
+    fn sign_recover_len(&self) -> Result<usize> {
+        Err(CKR_GENERAL_ERROR)?
+    }
+}
+
+pub trait VerifyRecover: MechOperation {
+    fn verify_recover(
+        &mut self,
+        _signature: &[u8],
+        _data: &mut [u8],
+    ) -> Result<usize> {
+        Err(CKR_GENERAL_ERROR)?
+    }
+    /* upper bound for the recovered data, the actual length
+     * is only known once the signature has been processed */
+    fn verify_recover_len(&self) -> Result<usize> {
+        Err(CKR_GENERAL_ERROR)?
+    }
 }
 
 pub trait Derive: MechOperation {
@@ -306,6 +493,23 @@ pub trait MessageOperation: MechOperation {
     fn finalize(&mut self) -> Result<()> {
         Err(CKR_OPERATION_NOT_INITIALIZED)?
     }
+    /* Applies the parameters given for a single message, by default
+     * no per-message parameters are accepted */
+    fn msg_params(
+        &mut self,
+        param: CK_VOID_PTR,
+        paramlen: CK_ULONG,
+    ) -> Result<()> {
+        if !param.is_null() || paramlen != 0 {
+            return Err(CKR_ARGUMENTS_BAD)?;
+        }
+        Ok(())
+    }
+    /* Prepares the operation for the next message, restoring any
+     * parameters replaced by msg_params() */
+    fn msg_reset(&mut self) -> Result<()> {
+        Err(CKR_GENERAL_ERROR)?
+    }
 }
 
 pub trait MsgEncryption: MessageOperation {
@@ -408,6 +612,178 @@ pub trait MsgDecryption: MessageOperation {
     }
 }
 
+/* Message based signatures are built on the Sign and Verify
+ * operations, which are reset after each message. The per-message
+ * parameters, when provided, apply to that message only, the ones
+ * given at initialization are in effect again for the following
+ * messages. On multi-part messages parameters can only be given to
+ * the begin call, the next and final calls must pass NULL */
+pub trait MsgSign: MessageOperation + Sign {
+    fn msg_sign(
+        &mut self,
+        param: CK_VOID_PTR,
+        paramlen: CK_ULONG,
+        data: &[u8],
+        signature: &mut [u8],
+    ) -> Result<()> {
+        if self.finalized() {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        if self.busy() {
+            return Err(CKR_OPERATION_ACTIVE)?;
+        }
+        self.msg_params(param, paramlen)?;
+        let ret = self.sign(data, signature);
+        self.msg_reset()?;
+        ret
+    }
+
+    fn msg_sign_begin(
+        &mut self,
+        param: CK_VOID_PTR,
+        paramlen: CK_ULONG,
+    ) -> Result<()> {
+        if self.finalized() {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        if self.busy() {
+            return Err(CKR_OPERATION_ACTIVE)?;
+        }
+        self.msg_params(param, paramlen)?;
+        let ret = self.sign_update(&[]);
+        if ret.is_err() {
+            self.msg_reset()?;
+        }
+        ret
+    }
+
+    fn msg_sign_next(
+        &mut self,
+        param: CK_VOID_PTR,
+        paramlen: CK_ULONG,
+        data: &[u8],
+    ) -> Result<()> {
+        if self.finalized() || !self.busy() {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        if !param.is_null() || paramlen != 0 {
+            return Err(CKR_ARGUMENTS_BAD)?;
+        }
+        let ret = self.sign_update(data);
+        if ret.is_err() {
+            self.msg_reset()?;
+        }
+        ret
+    }
+
+    fn msg_sign_final(
+        &mut self,
+        param: CK_VOID_PTR,
+        paramlen: CK_ULONG,
+        data: &[u8],
+        signature: &mut [u8],
+    ) -> Result<()> {
+        if self.finalized() || !self.busy() {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        if !param.is_null() || paramlen != 0 {
+            return Err(CKR_ARGUMENTS_BAD)?;
+        }
+        let ret = self
+            .sign_update(data)
+            .and_then(|_| self.sign_final(signature));
+        self.msg_reset()?;
+        ret
+    }
+
+    fn msg_signature_len(&self) -> Result<usize> {
+        Sign::signature_len(self)
+    }
+}
+
+pub trait MsgVerify: MessageOperation + Verify {
+    fn msg_verify(
+        &mut self,
+        param: CK_VOID_PTR,
+        paramlen: CK_ULONG,
+        data: &[u8],
+        signature: &[u8],
+    ) -> Result<()> {
+        if self.finalized() {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        if self.busy() {
+            return Err(CKR_OPERATION_ACTIVE)?;
+        }
+        self.msg_params(param, paramlen)?;
+        let ret = self.verify(data, signature);
+        self.msg_reset()?;
+        ret
+    }
+
+    fn msg_verify_begin(
+        &mut self,
+        param: CK_VOID_PTR,
+        paramlen: CK_ULONG,
+    ) -> Result<()> {
+        if self.finalized() {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        if self.busy() {
+            return Err(CKR_OPERATION_ACTIVE)?;
+        }
+        self.msg_params(param, paramlen)?;
+        let ret = self.verify_update(&[]);
+        if ret.is_err() {
+            self.msg_reset()?;
+        }
+        ret
+    }
+
+    fn msg_verify_next(
+        &mut self,
+        param: CK_VOID_PTR,
+        paramlen: CK_ULONG,
+        data: &[u8],
+    ) -> Result<()> {
+        if self.finalized() || !self.busy() {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        if !param.is_null() || paramlen != 0 {
+            return Err(CKR_ARGUMENTS_BAD)?;
+        }
+        let ret = self.verify_update(data);
+        if ret.is_err() {
+            self.msg_reset()?;
+        }
+        ret
+    }
+
+    fn msg_verify_final(
+        &mut self,
+        param: CK_VOID_PTR,
+        paramlen: CK_ULONG,
+        data: &[u8],
+        signature: &[u8],
+    ) -> Result<()> {
+        if self.finalized() || !self.busy() {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        if !param.is_null() || paramlen != 0 {
+            return Err(CKR_ARGUMENTS_BAD)?;
+        }
+        let ret = self
+            .verify_update(data)
+            .and_then(|_| self.verify_final(signature));
+        self.msg_reset()?;
+        ret
+    }
+
+    fn msg_signature_len(&self) -> Result<usize> {
+        Verify::signature_len(self)
+    }
+}
+
 #[derive(Debug)]
 pub enum Operation {
     Empty,
@@ -417,9 +793,14 @@ pub enum Operation {
     Digest(Box<dyn Digest>),
     Sign(Box<dyn Sign>),
     Verify(Box<dyn Verify>),
+    SignRecover(Box<dyn SignRecover>),
+    VerifyRecover(Box<dyn VerifyRecover>),
     Derive(Box<dyn Derive>),
     MsgEncryption(Box<dyn MsgEncryption>),
     MsgDecryption(Box<dyn MsgDecryption>),
+    MsgSign(Box<dyn MsgSign>),
+    MsgVerify(Box<dyn MsgVerify>),
+    VerifySignature(Box<VerifySignatureOperation>),
 }
 
 impl Operation {
@@ -432,11 +813,31 @@ impl Operation {
             Operation::Digest(op) => op.finalized(),
             Operation::Sign(op) => op.finalized(),
             Operation::Verify(op) => op.finalized(),
+            Operation::SignRecover(op) => op.finalized(),
+            Operation::VerifyRecover(op) => op.finalized(),
             Operation::Derive(op) => op.finalized(),
             Operation::MsgEncryption(op) => op.finalized(),
             Operation::MsgDecryption(op) => op.finalized(),
+            Operation::MsgSign(op) => op.finalized(),
+            Operation::MsgVerify(op) => op.finalized(),
+            Operation::VerifySignature(op) => op.finalized(),
         }
     }
+
+    /* Returns an independent copy of the current operation state, used
+     * to save and restore operations via C_GetOperationState and
+     * C_SetOperationState */
+    pub fn try_clone(&self) -> Result<Operation> {
+        Ok(match self {
+            Operation::Empty => Err(CKR_OPERATION_NOT_INITIALIZED)?,
+            Operation::Encryption(op) => Operation::Encryption(op.try_clone()?),
+            Operation::Decryption(op) => Operation::Decryption(op.try_clone()?),
+            Operation::Digest(op) => Operation::Digest(op.try_clone()?),
+            Operation::Sign(op) => Operation::Sign(op.try_clone()?),
+            Operation::Verify(op) => Operation::Verify(op.try_clone()?),
+            _ => Err(CKR_STATE_UNSAVEABLE)?,
+        })
+    }
 }
 
 pub trait DRBG: Debug + Send + Sync {
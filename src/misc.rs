@@ -191,3 +191,16 @@ pub fn copy_sized_string(s: &[u8], d: &mut [u8]) {
         d[slen..].fill(0x20); /* space in ASCII/UTF8 */
     }
 }
+
+/* Number of significant bits of a big endian integer */
+pub fn value_bits(value: &[u8]) -> CK_ULONG {
+    let mut bits = value.len() * 8;
+    for b in value {
+        if *b != 0 {
+            bits -= b.leading_zeros() as usize;
+            break;
+        }
+        bits -= 8;
+    }
+    bits as CK_ULONG
+}
@@ -0,0 +1,549 @@
+// Copyright 2024 Simo Sorce
+// See LICENSE.txt file for terms
+
+use std::fmt::Debug;
+
+use crate::attr_element;
+use crate::attribute::Attribute;
+use crate::error::Result;
+use crate::interface::*;
+use crate::kasn1::oid::*;
+use crate::kasn1::pkcs::*;
+use crate::kasn1::{DerEncOctetString, PrivateKeyInfo};
+use crate::mechanism::*;
+use crate::object::*;
+use crate::ossl::mldsa::MlDsaOperation;
+
+use once_cell::sync::Lazy;
+use zeroize::Zeroize;
+
+pub const ML_DSA_SEED_BYTES: usize = 32;
+
+pub const MIN_ML_DSA_SIZE: usize = 44;
+pub const MAX_ML_DSA_SIZE: usize = 87;
+
+/* FIPS 204, Table 2: sizes of the public key, private key and signature
+ * for each parameter set */
+pub fn mldsa_sizes(
+    param_set: CK_ML_DSA_PARAMETER_SET_TYPE,
+) -> Result<(usize, usize, usize)> {
+    match param_set {
+        CKP_ML_DSA_44 => Ok((1312, 2560, 2420)),
+        CKP_ML_DSA_65 => Ok((1952, 4032, 3309)),
+        CKP_ML_DSA_87 => Ok((2592, 4896, 4627)),
+        _ => Err(CKR_ATTRIBUTE_VALUE_INVALID)?,
+    }
+}
+
+fn mldsa_param_set_to_oid(
+    param_set: CK_ML_DSA_PARAMETER_SET_TYPE,
+) -> Result<asn1::ObjectIdentifier> {
+    match param_set {
+        CKP_ML_DSA_44 => Ok(ML_DSA_44_OID),
+        CKP_ML_DSA_65 => Ok(ML_DSA_65_OID),
+        CKP_ML_DSA_87 => Ok(ML_DSA_87_OID),
+        _ => Err(CKR_ATTRIBUTE_VALUE_INVALID)?,
+    }
+}
+
+fn mldsa_oid_to_param_set(
+    oid: &asn1::ObjectIdentifier,
+) -> Result<CK_ML_DSA_PARAMETER_SET_TYPE> {
+    match *oid {
+        ML_DSA_44_OID => Ok(CKP_ML_DSA_44),
+        ML_DSA_65_OID => Ok(CKP_ML_DSA_65),
+        ML_DSA_87_OID => Ok(CKP_ML_DSA_87),
+        _ => Err(CKR_WRAPPED_KEY_INVALID)?,
+    }
+}
+
+pub fn get_param_set(key: &Object) -> Result<CK_ML_DSA_PARAMETER_SET_TYPE> {
+    let param_set = match key.get_attr_as_ulong(CKA_PARAMETER_SET) {
+        Ok(p) => p,
+        Err(e) => {
+            if e.attr_not_found() {
+                Err(CKR_TEMPLATE_INCOMPLETE)?;
+            }
+            return Err(e);
+        }
+    };
+    match param_set {
+        CKP_ML_DSA_44 | CKP_ML_DSA_65 | CKP_ML_DSA_87 => Ok(param_set),
+        _ => Err(CKR_ATTRIBUTE_VALUE_INVALID)?,
+    }
+}
+
+/* DER encoded SubjectPublicKeyInfo, as stored in CKA_PUBLIC_KEY_INFO */
+fn mldsa_public_key_info(
+    param_set: CK_ML_DSA_PARAMETER_SET_TYPE,
+    public_key: &[u8],
+) -> Result<Vec<u8>> {
+    let spki = SubjectPublicKeyInfo {
+        algorithm: AlgorithmIdentifier {
+            oid: asn1::DefinedByMarker::marker(),
+            params: AlgorithmParameters::Other(
+                mldsa_param_set_to_oid(param_set)?,
+                None,
+            ),
+        },
+        subject_public_key: match asn1::BitString::new(public_key, 0) {
+            Some(b) => b,
+            None => return Err(CKR_GENERAL_ERROR)?,
+        },
+    };
+    match asn1::write_single(&spki) {
+        Ok(x) => Ok(x),
+        Err(_) => Err(CKR_GENERAL_ERROR)?,
+    }
+}
+
+#[derive(Debug)]
+pub struct MlDsaPubFactory {
+    attributes: Vec<ObjectAttr>,
+}
+
+impl MlDsaPubFactory {
+    pub fn new() -> MlDsaPubFactory {
+        let mut data: MlDsaPubFactory = MlDsaPubFactory {
+            attributes: Vec::new(),
+        };
+        data.attributes.append(&mut data.init_common_object_attrs());
+        data.attributes
+            .append(&mut data.init_common_storage_attrs());
+        data.attributes.append(&mut data.init_common_key_attrs());
+        data.attributes
+            .append(&mut data.init_common_public_key_attrs());
+        data.attributes.push(attr_element!(
+            CKA_PARAMETER_SET; OAFlags::AlwaysRequired
+            | OAFlags::Unchangeable; Attribute::from_ulong; val 0));
+        data.attributes.push(attr_element!(
+            CKA_VALUE; OAFlags::RequiredOnCreate
+            | OAFlags::SettableOnlyOnCreate | OAFlags::Unchangeable;
+            Attribute::from_bytes; val Vec::new()));
+        data
+    }
+}
+
+impl ObjectFactory for MlDsaPubFactory {
+    fn create(&self, template: &[CK_ATTRIBUTE]) -> Result<Object> {
+        let obj = self.default_object_create(template)?;
+
+        /* PKCS#11 3.2 6.x ML-DSA public key objects:
+         * CKA_VALUE, Byte array,
+         * Public key bytes as defined by FIPS 204 */
+        let (pklen, _, _) = mldsa_sizes(get_param_set(&obj)?)?;
+        match obj.get_attr_as_bytes(CKA_VALUE) {
+            Ok(v) => {
+                if v.len() != pklen {
+                    return Err(CKR_ATTRIBUTE_VALUE_INVALID)?;
+                }
+            }
+            Err(e) => {
+                if e.attr_not_found() {
+                    return Err(CKR_TEMPLATE_INCOMPLETE)?;
+                } else {
+                    return Err(e);
+                }
+            }
+        }
+
+        Ok(obj)
+    }
+
+    fn get_attributes(&self) -> &Vec<ObjectAttr> {
+        &self.attributes
+    }
+}
+
+impl CommonKeyFactory for MlDsaPubFactory {}
+
+impl PubKeyFactory for MlDsaPubFactory {}
+
+#[derive(Debug)]
+pub struct MlDsaPrivFactory {
+    attributes: Vec<ObjectAttr>,
+}
+
+impl MlDsaPrivFactory {
+    pub fn new() -> MlDsaPrivFactory {
+        let mut data: MlDsaPrivFactory = MlDsaPrivFactory {
+            attributes: Vec::new(),
+        };
+        data.attributes.append(&mut data.init_common_object_attrs());
+        data.attributes
+            .append(&mut data.init_common_storage_attrs());
+        data.attributes.append(&mut data.init_common_key_attrs());
+        data.attributes
+            .append(&mut data.init_common_private_key_attrs());
+        data.attributes.push(attr_element!(
+            CKA_PARAMETER_SET; OAFlags::RequiredOnCreate
+            | OAFlags::Unchangeable; Attribute::from_ulong; val 0));
+        data.attributes.push(attr_element!(
+            CKA_VALUE; OAFlags::Sensitive | OAFlags::SettableOnlyOnCreate
+            | OAFlags::Unchangeable; Attribute::from_bytes; val Vec::new()));
+        data.attributes.push(attr_element!(
+            CKA_SEED; OAFlags::Sensitive | OAFlags::SettableOnlyOnCreate
+            | OAFlags::Unchangeable; Attribute::from_bytes; val Vec::new()));
+
+        /* default to private */
+        let private = attr_element!(
+            CKA_PRIVATE; OAFlags::Defval | OAFlags::ChangeOnCopy;
+            Attribute::from_bool; val true);
+        match data
+            .attributes
+            .iter()
+            .position(|x| x.get_type() == CKA_PRIVATE)
+        {
+            Some(idx) => data.attributes[idx] = private,
+            None => data.attributes.push(private),
+        }
+
+        data
+    }
+}
+
+impl ObjectFactory for MlDsaPrivFactory {
+    fn create(&self, template: &[CK_ATTRIBUTE]) -> Result<Object> {
+        let obj = self.default_object_create(template)?;
+
+        /* PKCS#11 3.2 6.x ML-DSA private key objects:
+         * either the expanded key (CKA_VALUE) or the seed (CKA_SEED) or
+         * both need to be provided */
+        let (_, sklen, _) = mldsa_sizes(get_param_set(&obj)?)?;
+        let value = obj.get_attr(CKA_VALUE);
+        let seed = obj.get_attr(CKA_SEED);
+        if value.is_none() && seed.is_none() {
+            return Err(CKR_TEMPLATE_INCOMPLETE)?;
+        }
+        if let Some(v) = value {
+            if v.get_value().len() != sklen {
+                return Err(CKR_ATTRIBUTE_VALUE_INVALID)?;
+            }
+        }
+        if let Some(s) = seed {
+            if s.get_value().len() != ML_DSA_SEED_BYTES {
+                return Err(CKR_ATTRIBUTE_VALUE_INVALID)?;
+            }
+        }
+
+        Ok(obj)
+    }
+
+    fn get_attributes(&self) -> &Vec<ObjectAttr> {
+        &self.attributes
+    }
+
+    fn export_for_wrapping(&self, key: &Object) -> Result<Vec<u8>> {
+        PrivKeyFactory::export_for_wrapping(self, key)
+    }
+
+    fn import_from_wrapped(
+        &self,
+        data: Vec<u8>,
+        template: &[CK_ATTRIBUTE],
+    ) -> Result<Object> {
+        PrivKeyFactory::import_from_wrapped(self, data, template)
+    }
+}
+
+impl CommonKeyFactory for MlDsaPrivFactory {}
+
+impl PrivKeyFactory for MlDsaPrivFactory {
+    fn export_for_wrapping(&self, key: &Object) -> Result<Vec<u8>> {
+        key.check_key_ops(CKO_PRIVATE_KEY, CKK_ML_DSA, CKA_EXTRACTABLE)?;
+
+        let oid = mldsa_param_set_to_oid(get_param_set(key)?)?;
+        let seed = match key.get_attr(CKA_SEED) {
+            Some(s) => Some(DerEncOctetString::new(s.get_value())?),
+            None => None,
+        };
+        let value = match key.get_attr(CKA_VALUE) {
+            Some(v) => Some(DerEncOctetString::new(v.get_value())?),
+            None => None,
+        };
+        let mldsa_key = match (seed, value) {
+            (Some(s), Some(v)) => MlDsaPrivateKey::Both(MlDsaBothKeys {
+                seed: s,
+                expanded_key: v,
+            }),
+            (Some(s), None) => MlDsaPrivateKey::Seed(s),
+            (None, Some(v)) => MlDsaPrivateKey::ExpandedKey(v),
+            (None, None) => return Err(CKR_GENERAL_ERROR)?,
+        };
+        let mut mldsa_asn1 = match asn1::write_single(&mldsa_key) {
+            Ok(p) => p,
+            _ => return Err(CKR_GENERAL_ERROR)?,
+        };
+        let result = match asn1::write_single(&PrivateKeyInfo::new(
+            mldsa_asn1.as_slice(),
+            oid,
+        )?) {
+            Ok(x) => Ok(x),
+            Err(_) => Err(CKR_GENERAL_ERROR)?,
+        };
+        mldsa_asn1.zeroize();
+        result
+    }
+
+    fn import_from_wrapped(
+        &self,
+        data: Vec<u8>,
+        template: &[CK_ATTRIBUTE],
+    ) -> Result<Object> {
+        let mut key = self.default_object_unwrap(template)?;
+
+        if !key.check_or_set_attr(Attribute::from_ulong(
+            CKA_CLASS,
+            CKO_PRIVATE_KEY,
+        ))? {
+            return Err(CKR_TEMPLATE_INCONSISTENT)?;
+        }
+        if !key.check_or_set_attr(Attribute::from_ulong(
+            CKA_KEY_TYPE,
+            CKK_ML_DSA,
+        ))? {
+            return Err(CKR_TEMPLATE_INCONSISTENT)?;
+        }
+
+        let (tlv, extra) = match asn1::strip_tlv(&data) {
+            Ok(x) => x,
+            Err(_) => return Err(CKR_WRAPPED_KEY_INVALID)?,
+        };
+        /* Some Key Wrapping algorithms may 0 pad to match block size */
+        if !extra.iter().all(|b| *b == 0) {
+            return Err(CKR_WRAPPED_KEY_INVALID)?;
+        }
+        let pkeyinfo = match tlv.parse::<PrivateKeyInfo>() {
+            Ok(k) => k,
+            Err(_) => return Err(CKR_WRAPPED_KEY_INVALID)?,
+        };
+        let param_set = mldsa_oid_to_param_set(pkeyinfo.get_oid())?;
+        if !key.check_or_set_attr(Attribute::from_ulong(
+            CKA_PARAMETER_SET,
+            param_set,
+        ))? {
+            return Err(CKR_TEMPLATE_INCONSISTENT)?;
+        }
+
+        let mldsa_key = match asn1::parse_single::<MlDsaPrivateKey>(
+            pkeyinfo.get_private_key(),
+        ) {
+            Ok(k) => k,
+            Err(_) => return Err(CKR_WRAPPED_KEY_INVALID)?,
+        };
+        let (seed, value) = match &mldsa_key {
+            MlDsaPrivateKey::Seed(s) => (Some(s.as_bytes()), None),
+            MlDsaPrivateKey::ExpandedKey(v) => (None, Some(v.as_bytes())),
+            MlDsaPrivateKey::Both(b) => {
+                (Some(b.seed.as_bytes()), Some(b.expanded_key.as_bytes()))
+            }
+        };
+
+        let (_, sklen, _) = mldsa_sizes(param_set)?;
+        if let Some(s) = seed {
+            if s.len() != ML_DSA_SEED_BYTES {
+                return Err(CKR_WRAPPED_KEY_INVALID)?;
+            }
+            if !key.check_or_set_attr(Attribute::from_bytes(
+                CKA_SEED,
+                s.to_vec(),
+            ))? {
+                return Err(CKR_TEMPLATE_INCONSISTENT)?;
+            }
+        }
+        if let Some(v) = value {
+            if v.len() != sklen {
+                return Err(CKR_WRAPPED_KEY_INVALID)?;
+            }
+            if !key.check_or_set_attr(Attribute::from_bytes(
+                CKA_VALUE,
+                v.to_vec(),
+            ))? {
+                return Err(CKR_TEMPLATE_INCONSISTENT)?;
+            }
+        }
+
+        Ok(key)
+    }
+}
+
+static PUBLIC_KEY_FACTORY: Lazy<Box<dyn ObjectFactory>> =
+    Lazy::new(|| Box::new(MlDsaPubFactory::new()));
+
+static PRIVATE_KEY_FACTORY: Lazy<Box<dyn ObjectFactory>> =
+    Lazy::new(|| Box::new(MlDsaPrivFactory::new()));
+
+#[derive(Debug)]
+struct MlDsaMechanism {
+    info: CK_MECHANISM_INFO,
+}
+
+impl MlDsaMechanism {
+    fn new_mechanism(flags: CK_FLAGS) -> Box<dyn Mechanism> {
+        Box::new(MlDsaMechanism {
+            info: CK_MECHANISM_INFO {
+                ulMinKeySize: CK_ULONG::try_from(MIN_ML_DSA_SIZE).unwrap(),
+                ulMaxKeySize: CK_ULONG::try_from(MAX_ML_DSA_SIZE).unwrap(),
+                flags,
+            },
+        })
+    }
+
+    fn register_mechanisms(mechs: &mut Mechanisms) {
+        mechs.add_mechanism(
+            CKM_ML_DSA_KEY_PAIR_GEN,
+            Self::new_mechanism(CKF_GENERATE_KEY_PAIR),
+        );
+        mechs.add_mechanism(
+            CKM_ML_DSA,
+            Self::new_mechanism(
+                CKF_SIGN | CKF_VERIFY | CKF_MESSAGE_SIGN | CKF_MESSAGE_VERIFY,
+            ),
+        );
+        for mech in [
+            CKM_HASH_ML_DSA,
+            CKM_HASH_ML_DSA_SHA224,
+            CKM_HASH_ML_DSA_SHA256,
+            CKM_HASH_ML_DSA_SHA384,
+            CKM_HASH_ML_DSA_SHA512,
+            CKM_HASH_ML_DSA_SHA3_224,
+            CKM_HASH_ML_DSA_SHA3_256,
+            CKM_HASH_ML_DSA_SHA3_384,
+            CKM_HASH_ML_DSA_SHA3_512,
+        ] {
+            mechs.add_mechanism(
+                mech,
+                Self::new_mechanism(CKF_SIGN | CKF_VERIFY),
+            );
+        }
+    }
+}
+
+impl Mechanism for MlDsaMechanism {
+    fn info(&self) -> &CK_MECHANISM_INFO {
+        &self.info
+    }
+
+    fn sign_new(
+        &self,
+        mech: &CK_MECHANISM,
+        key: &Object,
+    ) -> Result<Box<dyn Sign>> {
+        if self.info.flags & CKF_SIGN != CKF_SIGN {
+            return Err(CKR_MECHANISM_INVALID)?;
+        }
+        key.check_key_ops(CKO_PRIVATE_KEY, CKK_ML_DSA, CKA_SIGN)?;
+        Ok(Box::new(MlDsaOperation::sign_new(mech, key)?))
+    }
+
+    fn verify_new(
+        &self,
+        mech: &CK_MECHANISM,
+        key: &Object,
+    ) -> Result<Box<dyn Verify>> {
+        if self.info.flags & CKF_VERIFY != CKF_VERIFY {
+            return Err(CKR_MECHANISM_INVALID)?;
+        }
+        key.check_key_ops(CKO_PUBLIC_KEY, CKK_ML_DSA, CKA_VERIFY)?;
+        Ok(Box::new(MlDsaOperation::verify_new(mech, key)?))
+    }
+
+    fn msg_sign_op(
+        &self,
+        mech: &CK_MECHANISM,
+        key: &Object,
+    ) -> Result<Box<dyn MsgSign>> {
+        if self.info.flags & CKF_MESSAGE_SIGN != CKF_MESSAGE_SIGN {
+            return Err(CKR_MECHANISM_INVALID)?;
+        }
+        key.check_key_ops(CKO_PRIVATE_KEY, CKK_ML_DSA, CKA_SIGN)?;
+        Ok(Box::new(MlDsaOperation::sign_new(mech, key)?))
+    }
+
+    fn msg_verify_op(
+        &self,
+        mech: &CK_MECHANISM,
+        key: &Object,
+    ) -> Result<Box<dyn MsgVerify>> {
+        if self.info.flags & CKF_MESSAGE_VERIFY != CKF_MESSAGE_VERIFY {
+            return Err(CKR_MECHANISM_INVALID)?;
+        }
+        key.check_key_ops(CKO_PUBLIC_KEY, CKK_ML_DSA, CKA_VERIFY)?;
+        Ok(Box::new(MlDsaOperation::verify_new(mech, key)?))
+    }
+
+    fn generate_keypair(
+        &self,
+        mech: &CK_MECHANISM,
+        pubkey_template: &[CK_ATTRIBUTE],
+        prikey_template: &[CK_ATTRIBUTE],
+    ) -> Result<(Object, Object)> {
+        let mut pubkey =
+            PUBLIC_KEY_FACTORY.default_object_generate(pubkey_template)?;
+        if !pubkey.check_or_set_attr(Attribute::from_ulong(
+            CKA_CLASS,
+            CKO_PUBLIC_KEY,
+        ))? {
+            return Err(CKR_TEMPLATE_INCONSISTENT)?;
+        }
+        if !pubkey.check_or_set_attr(Attribute::from_ulong(
+            CKA_KEY_TYPE,
+            CKK_ML_DSA,
+        ))? {
+            return Err(CKR_TEMPLATE_INCONSISTENT)?;
+        }
+
+        let mut privkey =
+            PRIVATE_KEY_FACTORY.default_object_generate(prikey_template)?;
+        if !privkey.check_or_set_attr(Attribute::from_ulong(
+            CKA_CLASS,
+            CKO_PRIVATE_KEY,
+        ))? {
+            return Err(CKR_TEMPLATE_INCONSISTENT)?;
+        }
+        if !privkey.check_or_set_attr(Attribute::from_ulong(
+            CKA_KEY_TYPE,
+            CKK_ML_DSA,
+        ))? {
+            return Err(CKR_TEMPLATE_INCONSISTENT)?;
+        }
+
+        let param_set = get_param_set(&pubkey)?;
+        if !privkey.check_or_set_attr(Attribute::from_ulong(
+            CKA_PARAMETER_SET,
+            param_set,
+        ))? {
+            return Err(CKR_TEMPLATE_INCONSISTENT)?;
+        }
+
+        MlDsaOperation::generate_keypair(param_set, &mut pubkey, &mut privkey)?;
+
+        let spki = mldsa_public_key_info(
+            param_set,
+            pubkey.get_attr_as_bytes(CKA_VALUE)?,
+        )?;
+        pubkey.set_attr(Attribute::from_bytes(
+            CKA_PUBLIC_KEY_INFO,
+            spki.clone(),
+        ))?;
+        privkey.set_attr(Attribute::from_bytes(CKA_PUBLIC_KEY_INFO, spki))?;
+
+        default_key_attributes(&mut privkey, mech.mechanism)?;
+        default_key_attributes(&mut pubkey, mech.mechanism)?;
+
+        Ok((pubkey, privkey))
+    }
+}
+
+pub fn register(mechs: &mut Mechanisms, ot: &mut ObjectFactories) {
+    MlDsaMechanism::register_mechanisms(mechs);
+
+    ot.add_factory(
+        ObjectType::new(CKO_PUBLIC_KEY, CKK_ML_DSA),
+        &PUBLIC_KEY_FACTORY,
+    );
+    ot.add_factory(
+        ObjectType::new(CKO_PRIVATE_KEY, CKK_ML_DSA),
+        &PRIVATE_KEY_FACTORY,
+    );
+}
@@ -0,0 +1,411 @@
+// Copyright 2024 Simo Sorce
+// See LICENSE.txt file for terms
+
+use std::fmt::Debug;
+
+use crate::attr_element;
+use crate::attribute::{Attribute, CkAttrs};
+use crate::error::Result;
+use crate::interface::*;
+use crate::mechanism::*;
+use crate::object::*;
+use crate::ossl::mlkem::MlKemOperation;
+
+use once_cell::sync::Lazy;
+use zeroize::Zeroize;
+
+pub const ML_KEM_SHARED_SECRET_BYTES: usize = 32;
+pub const ML_KEM_SEED_BYTES: usize = 64;
+
+pub const MIN_ML_KEM_SIZE_BITS: usize = 512;
+pub const MAX_ML_KEM_SIZE_BITS: usize = 1024;
+
+/* FIPS 203, Table 3: sizes of the encapsulation key, decapsulation key
+ * and ciphertext for each parameter set */
+fn mlkem_sizes(
+    param_set: CK_ML_KEM_PARAMETER_SET_TYPE,
+) -> Result<(usize, usize, usize)> {
+    match param_set {
+        CKP_ML_KEM_512 => Ok((800, 1632, 768)),
+        CKP_ML_KEM_768 => Ok((1184, 2400, 1088)),
+        CKP_ML_KEM_1024 => Ok((1568, 3168, 1568)),
+        _ => Err(CKR_ATTRIBUTE_VALUE_INVALID)?,
+    }
+}
+
+fn get_param_set(key: &Object) -> Result<CK_ML_KEM_PARAMETER_SET_TYPE> {
+    let param_set = match key.get_attr_as_ulong(CKA_PARAMETER_SET) {
+        Ok(p) => p,
+        Err(e) => {
+            if e.attr_not_found() {
+                Err(CKR_TEMPLATE_INCOMPLETE)?;
+            }
+            return Err(e);
+        }
+    };
+    match param_set {
+        CKP_ML_KEM_512 | CKP_ML_KEM_768 | CKP_ML_KEM_1024 => Ok(param_set),
+        _ => Err(CKR_ATTRIBUTE_VALUE_INVALID)?,
+    }
+}
+
+#[derive(Debug)]
+pub struct MlKemPubFactory {
+    attributes: Vec<ObjectAttr>,
+}
+
+impl MlKemPubFactory {
+    pub fn new() -> MlKemPubFactory {
+        let mut data: MlKemPubFactory = MlKemPubFactory {
+            attributes: Vec::new(),
+        };
+        data.attributes.append(&mut data.init_common_object_attrs());
+        data.attributes
+            .append(&mut data.init_common_storage_attrs());
+        data.attributes.append(&mut data.init_common_key_attrs());
+        data.attributes
+            .append(&mut data.init_common_public_key_attrs());
+        data.attributes.push(attr_element!(
+            CKA_ENCAPSULATE; OAFlags::Defval; Attribute::from_bool;
+            val false));
+        data.attributes.push(attr_element!(
+            CKA_ENCAPSULATE_TEMPLATE; OAFlags::empty();
+            Attribute::from_attr_array; val Vec::new()));
+        data.attributes.push(attr_element!(
+            CKA_PARAMETER_SET; OAFlags::AlwaysRequired
+            | OAFlags::Unchangeable; Attribute::from_ulong; val 0));
+        data.attributes.push(attr_element!(
+            CKA_VALUE; OAFlags::RequiredOnCreate
+            | OAFlags::SettableOnlyOnCreate | OAFlags::Unchangeable;
+            Attribute::from_bytes; val Vec::new()));
+        data
+    }
+}
+
+impl ObjectFactory for MlKemPubFactory {
+    fn create(&self, template: &[CK_ATTRIBUTE]) -> Result<Object> {
+        let obj = self.default_object_create(template)?;
+
+        /* PKCS#11 3.2 6.x ML-KEM public key objects:
+         * CKA_VALUE, Byte array,
+         * Public key bytes as defined by FIPS 203 */
+        let (eklen, _, _) = mlkem_sizes(get_param_set(&obj)?)?;
+        match obj.get_attr_as_bytes(CKA_VALUE) {
+            Ok(v) => {
+                if v.len() != eklen {
+                    return Err(CKR_ATTRIBUTE_VALUE_INVALID)?;
+                }
+            }
+            Err(e) => {
+                if e.attr_not_found() {
+                    return Err(CKR_TEMPLATE_INCOMPLETE)?;
+                } else {
+                    return Err(e);
+                }
+            }
+        }
+
+        Ok(obj)
+    }
+
+    fn get_attributes(&self) -> &Vec<ObjectAttr> {
+        &self.attributes
+    }
+}
+
+impl CommonKeyFactory for MlKemPubFactory {}
+
+impl PubKeyFactory for MlKemPubFactory {}
+
+#[derive(Debug)]
+pub struct MlKemPrivFactory {
+    attributes: Vec<ObjectAttr>,
+}
+
+impl MlKemPrivFactory {
+    pub fn new() -> MlKemPrivFactory {
+        let mut data: MlKemPrivFactory = MlKemPrivFactory {
+            attributes: Vec::new(),
+        };
+        data.attributes.append(&mut data.init_common_object_attrs());
+        data.attributes
+            .append(&mut data.init_common_storage_attrs());
+        data.attributes.append(&mut data.init_common_key_attrs());
+        data.attributes
+            .append(&mut data.init_common_private_key_attrs());
+        data.attributes.push(attr_element!(
+            CKA_DECAPSULATE; OAFlags::Defval; Attribute::from_bool;
+            val false));
+        data.attributes.push(attr_element!(
+            CKA_DECAPSULATE_TEMPLATE; OAFlags::empty();
+            Attribute::from_attr_array; val Vec::new()));
+        data.attributes.push(attr_element!(
+            CKA_PARAMETER_SET; OAFlags::RequiredOnCreate
+            | OAFlags::Unchangeable; Attribute::from_ulong; val 0));
+        data.attributes.push(attr_element!(
+            CKA_VALUE; OAFlags::Sensitive | OAFlags::SettableOnlyOnCreate
+            | OAFlags::Unchangeable; Attribute::from_bytes; val Vec::new()));
+        data.attributes.push(attr_element!(
+            CKA_SEED; OAFlags::Sensitive | OAFlags::SettableOnlyOnCreate
+            | OAFlags::Unchangeable; Attribute::from_bytes; val Vec::new()));
+
+        /* default to private */
+        let private = attr_element!(
+            CKA_PRIVATE; OAFlags::Defval | OAFlags::ChangeOnCopy;
+            Attribute::from_bool; val true);
+        match data
+            .attributes
+            .iter()
+            .position(|x| x.get_type() == CKA_PRIVATE)
+        {
+            Some(idx) => data.attributes[idx] = private,
+            None => data.attributes.push(private),
+        }
+
+        data
+    }
+}
+
+impl ObjectFactory for MlKemPrivFactory {
+    fn create(&self, template: &[CK_ATTRIBUTE]) -> Result<Object> {
+        let obj = self.default_object_create(template)?;
+
+        /* PKCS#11 3.2 6.x ML-KEM private key objects:
+         * either the expanded key (CKA_VALUE) or the seed (CKA_SEED) or
+         * both need to be provided */
+        let (_, dklen, _) = mlkem_sizes(get_param_set(&obj)?)?;
+        let value = obj.get_attr(CKA_VALUE);
+        let seed = obj.get_attr(CKA_SEED);
+        if value.is_none() && seed.is_none() {
+            return Err(CKR_TEMPLATE_INCOMPLETE)?;
+        }
+        if let Some(v) = value {
+            if v.get_value().len() != dklen {
+                return Err(CKR_ATTRIBUTE_VALUE_INVALID)?;
+            }
+        }
+        if let Some(s) = seed {
+            if s.get_value().len() != ML_KEM_SEED_BYTES {
+                return Err(CKR_ATTRIBUTE_VALUE_INVALID)?;
+            }
+        }
+
+        Ok(obj)
+    }
+
+    fn get_attributes(&self) -> &Vec<ObjectAttr> {
+        &self.attributes
+    }
+
+    fn export_for_wrapping(&self, key: &Object) -> Result<Vec<u8>> {
+        PrivKeyFactory::export_for_wrapping(self, key)
+    }
+
+    fn import_from_wrapped(
+        &self,
+        data: Vec<u8>,
+        template: &[CK_ATTRIBUTE],
+    ) -> Result<Object> {
+        PrivKeyFactory::import_from_wrapped(self, data, template)
+    }
+}
+
+impl CommonKeyFactory for MlKemPrivFactory {}
+
+impl PrivKeyFactory for MlKemPrivFactory {}
+
+static PUBLIC_KEY_FACTORY: Lazy<Box<dyn ObjectFactory>> =
+    Lazy::new(|| Box::new(MlKemPubFactory::new()));
+
+static PRIVATE_KEY_FACTORY: Lazy<Box<dyn ObjectFactory>> =
+    Lazy::new(|| Box::new(MlKemPrivFactory::new()));
+
+#[derive(Debug)]
+struct MlKemMechanism {
+    info: CK_MECHANISM_INFO,
+}
+
+impl MlKemMechanism {
+    fn new_mechanism(flags: CK_FLAGS) -> Box<dyn Mechanism> {
+        Box::new(MlKemMechanism {
+            info: CK_MECHANISM_INFO {
+                ulMinKeySize: CK_ULONG::try_from(MIN_ML_KEM_SIZE_BITS).unwrap(),
+                ulMaxKeySize: CK_ULONG::try_from(MAX_ML_KEM_SIZE_BITS).unwrap(),
+                flags,
+            },
+        })
+    }
+
+    fn register_mechanisms(mechs: &mut Mechanisms) {
+        mechs.add_mechanism(
+            CKM_ML_KEM_KEY_PAIR_GEN,
+            Self::new_mechanism(CKF_GENERATE_KEY_PAIR),
+        );
+        mechs.add_mechanism(
+            CKM_ML_KEM,
+            Self::new_mechanism(CKF_ENCAPSULATE | CKF_DECAPSULATE),
+        );
+    }
+
+    /* Creates the shared secret key object, the secret is truncated to
+     * CKA_VALUE_LEN if the template asks for a shorter key */
+    fn make_secret_key(
+        &self,
+        mech: &CK_MECHANISM,
+        factory: &dyn ObjectFactory,
+        template: &[CK_ATTRIBUTE],
+        secret: &[u8],
+    ) -> Result<Object> {
+        let keylen = match template.iter().find(|x| x.type_ == CKA_VALUE_LEN) {
+            Some(a) => {
+                let value_len = usize::try_from(a.to_ulong()?)?;
+                if value_len > secret.len() {
+                    return Err(CKR_TEMPLATE_INCONSISTENT)?;
+                }
+                value_len
+            }
+            None => match factory
+                .as_secret_key_factory()?
+                .recommend_key_size(secret.len())
+            {
+                Ok(len) => len,
+                Err(_) => return Err(CKR_TEMPLATE_INCONSISTENT)?,
+            },
+        };
+
+        let mut tmpl = CkAttrs::from(template);
+        tmpl.add_owned_slice(CKA_VALUE, &secret[..keylen])?;
+        tmpl.zeroize = true;
+        let mut obj = factory.create(tmpl.as_slice())?;
+
+        default_key_attributes(&mut obj, mech.mechanism)?;
+        Ok(obj)
+    }
+}
+
+impl Mechanism for MlKemMechanism {
+    fn info(&self) -> &CK_MECHANISM_INFO {
+        &self.info
+    }
+
+    fn generate_keypair(
+        &self,
+        mech: &CK_MECHANISM,
+        pubkey_template: &[CK_ATTRIBUTE],
+        prikey_template: &[CK_ATTRIBUTE],
+    ) -> Result<(Object, Object)> {
+        let mut pubkey =
+            PUBLIC_KEY_FACTORY.default_object_generate(pubkey_template)?;
+        if !pubkey.check_or_set_attr(Attribute::from_ulong(
+            CKA_CLASS,
+            CKO_PUBLIC_KEY,
+        ))? {
+            return Err(CKR_TEMPLATE_INCONSISTENT)?;
+        }
+        if !pubkey.check_or_set_attr(Attribute::from_ulong(
+            CKA_KEY_TYPE,
+            CKK_ML_KEM,
+        ))? {
+            return Err(CKR_TEMPLATE_INCONSISTENT)?;
+        }
+
+        let mut privkey =
+            PRIVATE_KEY_FACTORY.default_object_generate(prikey_template)?;
+        if !privkey.check_or_set_attr(Attribute::from_ulong(
+            CKA_CLASS,
+            CKO_PRIVATE_KEY,
+        ))? {
+            return Err(CKR_TEMPLATE_INCONSISTENT)?;
+        }
+        if !privkey.check_or_set_attr(Attribute::from_ulong(
+            CKA_KEY_TYPE,
+            CKK_ML_KEM,
+        ))? {
+            return Err(CKR_TEMPLATE_INCONSISTENT)?;
+        }
+
+        let param_set = get_param_set(&pubkey)?;
+        if !privkey.check_or_set_attr(Attribute::from_ulong(
+            CKA_PARAMETER_SET,
+            param_set,
+        ))? {
+            return Err(CKR_TEMPLATE_INCONSISTENT)?;
+        }
+
+        MlKemOperation::generate_keypair(param_set, &mut pubkey, &mut privkey)?;
+        default_key_attributes(&mut privkey, mech.mechanism)?;
+        default_key_attributes(&mut pubkey, mech.mechanism)?;
+
+        Ok((pubkey, privkey))
+    }
+
+    fn encapsulate_ciphertext_len(&self, key: &Object) -> Result<usize> {
+        let (_, _, ctlen) = mlkem_sizes(get_param_set(key)?)?;
+        Ok(ctlen)
+    }
+
+    fn encapsulate(
+        &self,
+        mech: &CK_MECHANISM,
+        key: &Object,
+        factory: &dyn ObjectFactory,
+        template: &[CK_ATTRIBUTE],
+        ciphertext: &mut [u8],
+    ) -> Result<(Object, usize)> {
+        if mech.mechanism != CKM_ML_KEM {
+            return Err(CKR_MECHANISM_INVALID)?;
+        }
+        if self.info.flags & CKF_ENCAPSULATE != CKF_ENCAPSULATE {
+            return Err(CKR_MECHANISM_INVALID)?;
+        }
+        key.check_key_ops(CKO_PUBLIC_KEY, CKK_ML_KEM, CKA_ENCAPSULATE)?;
+
+        let (mut secret, outlen) =
+            MlKemOperation::encapsulate(key, ciphertext)?;
+        let result =
+            self.make_secret_key(mech, factory, template, &secret);
+        secret.zeroize();
+        Ok((result?, outlen))
+    }
+
+    fn decapsulate(
+        &self,
+        mech: &CK_MECHANISM,
+        key: &Object,
+        factory: &dyn ObjectFactory,
+        template: &[CK_ATTRIBUTE],
+        ciphertext: &[u8],
+    ) -> Result<Object> {
+        if mech.mechanism != CKM_ML_KEM {
+            return Err(CKR_MECHANISM_INVALID)?;
+        }
+        if self.info.flags & CKF_DECAPSULATE != CKF_DECAPSULATE {
+            return Err(CKR_MECHANISM_INVALID)?;
+        }
+        key.check_key_ops(CKO_PRIVATE_KEY, CKK_ML_KEM, CKA_DECAPSULATE)?;
+
+        let (_, _, ctlen) = mlkem_sizes(get_param_set(key)?)?;
+        if ciphertext.len() != ctlen {
+            return Err(CKR_ENCRYPTED_DATA_LEN_RANGE)?;
+        }
+
+        let mut secret = MlKemOperation::decapsulate(key, ciphertext)?;
+        let result =
+            self.make_secret_key(mech, factory, template, &secret);
+        secret.zeroize();
+        result
+    }
+}
+
+pub fn register(mechs: &mut Mechanisms, ot: &mut ObjectFactories) {
+    MlKemMechanism::register_mechanisms(mechs);
+
+    ot.add_factory(
+        ObjectType::new(CKO_PUBLIC_KEY, CKK_ML_KEM),
+        &PUBLIC_KEY_FACTORY,
+    );
+    ot.add_factory(
+        ObjectType::new(CKO_PRIVATE_KEY, CKK_ML_KEM),
+        &PRIVATE_KEY_FACTORY,
+    );
+}
@@ -0,0 +1,54 @@
+// Copyright 2024 Simo Sorce
+// See LICENSE.txt file for terms
+
+use zeroize::Zeroize;
+
+pub const HCHACHA20_NONCE_SIZE: usize = 16;
+
+/* "expand 32-byte k" */
+const SIGMA: [u32; 4] = [0x61707865, 0x3320646e, 0x79622d32, 0x6b206574];
+
+fn quarter_round(s: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    s[a] = s[a].wrapping_add(s[b]);
+    s[d] = (s[d] ^ s[a]).rotate_left(16);
+    s[c] = s[c].wrapping_add(s[d]);
+    s[b] = (s[b] ^ s[c]).rotate_left(12);
+    s[a] = s[a].wrapping_add(s[b]);
+    s[d] = (s[d] ^ s[a]).rotate_left(8);
+    s[c] = s[c].wrapping_add(s[d]);
+    s[b] = (s[b] ^ s[c]).rotate_left(7);
+}
+
+/* HChaCha20 as defined in draft-irtf-cfrg-xchacha, 2.2
+ *
+ * OpenSSL does not provide XChaCha20, but it is just ChaCha20 keyed
+ * with a subkey derived via HChaCha20 from the first 16 bytes of the
+ * extended nonce, so only this function needs to be implemented here */
+pub fn hchacha20(key: &[u8], nonce: &[u8]) -> Vec<u8> {
+    let mut state = [0u32; 16];
+    state[..4].copy_from_slice(&SIGMA);
+    for (i, c) in key.chunks_exact(4).take(8).enumerate() {
+        state[4 + i] = u32::from_le_bytes([c[0], c[1], c[2], c[3]]);
+    }
+    for (i, c) in nonce.chunks_exact(4).take(4).enumerate() {
+        state[12 + i] = u32::from_le_bytes([c[0], c[1], c[2], c[3]]);
+    }
+
+    for _ in 0..10 {
+        quarter_round(&mut state, 0, 4, 8, 12);
+        quarter_round(&mut state, 1, 5, 9, 13);
+        quarter_round(&mut state, 2, 6, 10, 14);
+        quarter_round(&mut state, 3, 7, 11, 15);
+        quarter_round(&mut state, 0, 5, 10, 15);
+        quarter_round(&mut state, 1, 6, 11, 12);
+        quarter_round(&mut state, 2, 7, 8, 13);
+        quarter_round(&mut state, 3, 4, 9, 14);
+    }
+
+    let mut subkey = Vec::with_capacity(32);
+    for w in state[..4].iter().chain(state[12..].iter()) {
+        subkey.extend_from_slice(&w.to_le_bytes());
+    }
+    state.zeroize();
+    subkey
+}
@@ -177,6 +177,25 @@ impl HMACOperation {
         self.in_use = false;
         self.init()
     }
+
+    fn duplicate(&self) -> Result<HMACOperation> {
+        Ok(HMACOperation {
+            mech: self.mech,
+            key: HmacKey {
+                raw: self.key.raw.clone(),
+            },
+            hash: self.hash,
+            hashlen: self.hashlen,
+            blocklen: self.blocklen,
+            outputlen: self.outputlen,
+            state: self.state.clone(),
+            ipad: self.ipad.clone(),
+            opad: self.opad.clone(),
+            inner: self.inner.try_clone()?,
+            finalized: self.finalized,
+            in_use: self.in_use,
+        })
+    }
 }
 
 impl MechOperation for HMACOperation {
@@ -234,6 +253,10 @@ impl Sign for HMACOperation {
     fn signature_len(&self) -> Result<usize> {
         Ok(self.outputlen)
     }
+
+    fn try_clone(&self) -> Result<Box<dyn Sign>> {
+        Ok(Box::new(self.duplicate()?))
+    }
 }
 
 impl Verify for HMACOperation {
@@ -259,4 +282,29 @@ impl Verify for HMACOperation {
     fn signature_len(&self) -> Result<usize> {
         Ok(self.outputlen)
     }
+
+    fn try_clone(&self) -> Result<Box<dyn Verify>> {
+        Ok(Box::new(self.duplicate()?))
+    }
 }
+
+impl MessageOperation for HMACOperation {
+    fn busy(&self) -> bool {
+        self.in_use
+    }
+    fn finalize(&mut self) -> Result<()> {
+        if self.in_use {
+            return Err(CKR_OPERATION_ACTIVE)?;
+        }
+        self.finalized = true;
+        Ok(())
+    }
+
+    fn msg_reset(&mut self) -> Result<()> {
+        self.reinit()
+    }
+}
+
+impl MsgSign for HMACOperation {}
+
+impl MsgVerify for HMACOperation {}
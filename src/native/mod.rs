@@ -1,6 +1,8 @@
 // Copyright 2024 Simo Sorce
 // See LICENSE.txt file for terms
 
+#[cfg(feature = "chacha20")]
+pub mod chacha20;
 #[cfg(all(feature = "hmac", not(feature = "fips")))]
 pub mod hmac;
 #[cfg(all(feature = "pbkdf2", not(feature = "fips")))]
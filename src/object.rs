@@ -4,11 +4,13 @@
 use std::collections::HashMap;
 use std::fmt::Debug;
 
-use crate::attribute::{AttrType, Attribute};
+use crate::attribute::{AttrType, Attribute, CkAttrs};
 use crate::error::{Error, Result};
+#[cfg(feature = "hash")]
+use crate::hash;
 use crate::interface::*;
 use crate::mechanism::{Mechanism, Mechanisms};
-use crate::CSPRNG;
+use crate::{sizeof, CSPRNG};
 
 use bitflags::bitflags;
 use once_cell::sync::Lazy;
@@ -175,6 +177,7 @@ impl Object {
     attr_as_type! {make get_attr_as_ulong; with CK_ULONG; NumType; via to_ulong}
     attr_as_type! {make get_attr_as_string; with String; StringType; via to_string}
     attr_as_type! {make get_attr_as_bytes; with &Vec<u8>; BytesType; via to_bytes}
+    attr_as_type! {make get_attr_as_attr_array; with Vec<Attribute>; ArrayType; via to_attr_array}
 
     pub fn match_template(&self, template: &[CK_ATTRIBUTE]) -> bool {
         for ck_attr in template {
@@ -186,6 +189,20 @@ impl Object {
         true
     }
 
+    pub fn match_attributes(&self, attrs: &[Attribute]) -> bool {
+        for a in attrs {
+            match self.get_attr(a.get_type()) {
+                Some(r) => {
+                    if r.get_value() != a.get_value() {
+                        return false;
+                    }
+                }
+                None => return false,
+            }
+        }
+        true
+    }
+
     pub fn check_key_ops(
         &self,
         class: CK_OBJECT_CLASS,
@@ -412,10 +429,16 @@ pub trait ObjectFactory: Debug + Send + Sync {
         template: &[CK_ATTRIBUTE],
         origin: &Object,
     ) -> Result<Object> {
-        /* FIXME: handle CKA_DERIVE_TEMPLATE */
+        /* the base key derive template is applied to all derived keys */
+        let derive_template = match origin.get_attr(CKA_DERIVE_TEMPLATE) {
+            Some(a) => a.to_attr_array()?,
+            None => Vec::new(),
+        };
+        let mut tmpl = CkAttrs::from(template);
+        tmpl.merge_template(&derive_template)?;
 
         let mut obj = self.internal_object_create(
-            template,
+            tmpl.as_slice(),
             OAFlags::SettableOnlyOnCreate,
             OAFlags::AlwaysRequired,
         )?;
@@ -941,8 +964,8 @@ pub trait PubKeyFactory {
                 CKA_TRUSTED; OAFlags::NeverSettable | OAFlags::Defval;
                 Attribute::from_bool; val false),
             attr_element!(
-                CKA_WRAP_TEMPLATE; OAFlags::empty(); Attribute::from_bytes;
-                val Vec::new()),
+                CKA_WRAP_TEMPLATE; OAFlags::empty();
+                Attribute::from_attr_array; val Vec::new()),
             attr_element!(
                 CKA_PUBLIC_KEY_INFO; OAFlags::empty(); Attribute::from_bytes;
                 val Vec::new()),
@@ -983,8 +1006,8 @@ pub trait PrivKeyFactory {
                 CKA_WRAP_WITH_TRUSTED; OAFlags::Defval | OAFlags::ChangeToTrue;
                 Attribute::from_bool; val false),
             attr_element!(
-                CKA_UNWRAP_TEMPLATE; OAFlags::empty(); Attribute::from_bytes;
-                val Vec::new()),
+                CKA_UNWRAP_TEMPLATE; OAFlags::empty();
+                Attribute::from_attr_array; val Vec::new()),
             attr_element!(
                 CKA_ALWAYS_AUTHENTICATE; OAFlags::Defval; Attribute::from_bool;
                 val false),
@@ -992,8 +1015,8 @@ pub trait PrivKeyFactory {
                 CKA_PUBLIC_KEY_INFO; OAFlags::empty(); Attribute::from_bytes;
                 val Vec::new()),
             attr_element!(
-                CKA_DERIVE_TEMPLATE; OAFlags::empty(); Attribute::from_bytes;
-                val Vec::new()),
+                CKA_DERIVE_TEMPLATE; OAFlags::empty();
+                Attribute::from_attr_array; val Vec::new()),
         ]
     }
 
@@ -1051,8 +1074,8 @@ pub trait SecretKeyFactory {
                 CKA_NEVER_EXTRACTABLE; OAFlags::NeverSettable;
                 Attribute::from_bool; val false),
             attr_element!(
-                CKA_CHECK_VALUE; OAFlags::Ignored; Attribute::from_ignore;
-                val None),
+                CKA_CHECK_VALUE; OAFlags::Unchangeable; Attribute::from_bytes;
+                val Vec::new()),
             attr_element!(
                 CKA_WRAP_WITH_TRUSTED; OAFlags::Defval | OAFlags::ChangeToTrue;
                 Attribute::from_bool; val false),
@@ -1060,14 +1083,14 @@ pub trait SecretKeyFactory {
                 CKA_TRUSTED; OAFlags::NeverSettable | OAFlags::Defval;
                 Attribute::from_bool; val false),
             attr_element!(
-                CKA_WRAP_TEMPLATE; OAFlags::empty(); Attribute::from_bytes;
-                val Vec::new()),
+                CKA_WRAP_TEMPLATE; OAFlags::empty();
+                Attribute::from_attr_array; val Vec::new()),
             attr_element!(
-                CKA_UNWRAP_TEMPLATE; OAFlags::empty(); Attribute::from_bytes;
-                val Vec::new()),
+                CKA_UNWRAP_TEMPLATE; OAFlags::empty();
+                Attribute::from_attr_array; val Vec::new()),
             attr_element!(
-                CKA_DERIVE_TEMPLATE; OAFlags::empty(); Attribute::from_bytes;
-                val Vec::new()),
+                CKA_DERIVE_TEMPLATE; OAFlags::empty();
+                Attribute::from_attr_array; val Vec::new()),
         ]
     }
 
@@ -1146,6 +1169,68 @@ pub trait SecretKeyFactory {
     fn recommend_key_size(&self, _: usize) -> Result<usize> {
         return Err(CKR_GENERAL_ERROR)?;
     }
+
+    /* pkcs11-spec-v3.1 6.8.1: the first three bytes of the SHA-1 hash
+     * of the key value */
+    #[cfg(feature = "hash")]
+    fn check_value(&self, obj: &Object) -> Result<Vec<u8>> {
+        let value = obj.get_attr_as_bytes(CKA_VALUE).map_err(incomplete)?;
+        let mut op = hash::internal_hash_op(CKM_SHA_1)?;
+        let mut digest = vec![0u8; op.digest_len()?];
+        op.digest(value, &mut digest)?;
+        digest.truncate(CHECK_VALUE_LEN);
+        Ok(digest)
+    }
+
+    /* an empty check value means none is available */
+    #[cfg(not(feature = "hash"))]
+    fn check_value(&self, _: &Object) -> Result<Vec<u8>> {
+        Ok(Vec::new())
+    }
+
+    fn set_check_value(&self, obj: &mut Object) -> Result<()> {
+        let value = self.check_value(obj)?;
+        /* a check value provided by the caller must match, an empty one
+         * means no check value is wanted */
+        if let Some(a) = obj.get_attr(CKA_CHECK_VALUE) {
+            if a.get_value().is_empty() {
+                return Ok(());
+            }
+            if *a.get_value() != value {
+                return Err(CKR_ATTRIBUTE_VALUE_INVALID)?;
+            }
+        }
+        obj.set_attr(Attribute::from_bytes(CKA_CHECK_VALUE, value))
+    }
+}
+
+pub const CHECK_VALUE_LEN: usize = 3;
+
+/* pkcs11-spec-v3.1 4.11 Domain parameter objects */
+pub trait DomainParamsFactory {
+    fn init_common_domain_params_attrs(&self) -> Vec<ObjectAttr> {
+        vec![
+            attr_element!(
+                CKA_KEY_TYPE; OAFlags::RequiredOnCreate; Attribute::from_ulong;
+                val CK_UNAVAILABLE_INFORMATION),
+            attr_element!(
+                CKA_LOCAL; OAFlags::Defval | OAFlags::NeverSettable;
+                Attribute::from_bool; val false),
+        ]
+    }
+}
+
+/* CKA_PRIME_BITS is mapped as a byte array, so its value is kept as the
+ * bytes of a native CK_ULONG */
+pub fn prime_bits_attribute(bits: CK_ULONG) -> Attribute {
+    Attribute::from_bytes(CKA_PRIME_BITS, bits.to_ne_bytes().to_vec())
+}
+
+pub fn get_prime_bits(obj: &Object) -> Result<usize> {
+    match obj.get_attr(CKA_PRIME_BITS) {
+        Some(a) => Ok(usize::try_from(a.to_ulong()?)?),
+        None => Err(Error::not_found(CKA_PRIME_BITS.to_string())),
+    }
 }
 
 /* pkcs11-spec-v3.1 6.8 Generic secret key */
@@ -1424,8 +1509,14 @@ impl ObjectFactories {
                     None => return Err(CKR_TEMPLATE_INCOMPLETE)?,
                 }
             }
+            CKO_DOMAIN_PARAMETERS => {
+                match template.iter().find(|a| a.type_ == CKA_KEY_TYPE) {
+                    Some(k) => k.to_ulong()?,
+                    None => return Err(CKR_TEMPLATE_INCOMPLETE)?,
+                }
+            }
             /* TODO:
-             *  CKO_HW_FEATURE, CKO_DOMAIN_PARAMETERS,
+             *  CKO_HW_FEATURE,
              *  CKO_MECHANISM, CKO_OTP_KEY, CKO_PROFILE,
              *  CKO_VENDOR_DEFINED
              */
@@ -1442,9 +1533,10 @@ impl ObjectFactories {
         let class = obj.get_attr_as_ulong(CKA_CLASS)?;
         let type_ = match class {
             CKO_CERTIFICATE => obj.get_attr_as_ulong(CKA_CERTIFICATE_TYPE)?,
-            CKO_PUBLIC_KEY | CKO_PRIVATE_KEY | CKO_SECRET_KEY => {
-                obj.get_attr_as_ulong(CKA_KEY_TYPE)?
-            }
+            CKO_PUBLIC_KEY
+            | CKO_PRIVATE_KEY
+            | CKO_SECRET_KEY
+            | CKO_DOMAIN_PARAMETERS => obj.get_attr_as_ulong(CKA_KEY_TYPE)?,
             _ => 0,
         };
         self.get_factory(ObjectType::new(class, type_))
@@ -1516,26 +1608,9 @@ impl ObjectFactories {
                     continue;
                 }
                 Some(attr) => {
-                    let attr_val = attr.get_value();
-                    let attr_len = CK_ULONG::try_from(attr_val.len())?;
-                    if ck_attr.pValue.is_null() {
-                        ck_attr.ulValueLen = attr_len;
-                    } else {
-                        if ck_attr.ulValueLen < attr_len {
-                            ck_attr.ulValueLen = CK_UNAVAILABLE_INFORMATION;
-                            if result == CKR_OK {
-                                result = CKR_BUFFER_TOO_SMALL;
-                            }
-                        } else {
-                            ck_attr.ulValueLen = attr_len;
-                            unsafe {
-                                std::ptr::copy_nonoverlapping(
-                                    attr_val.as_ptr(),
-                                    ck_attr.pValue as *mut _,
-                                    attr_val.len(),
-                                );
-                            }
-                        }
+                    let ret = copy_attr_value(ck_attr, attr)?;
+                    if result == CKR_OK {
+                        result = ret;
                     }
                 }
             }
@@ -1584,6 +1659,63 @@ impl ObjectFactories {
     }
 }
 
+/* Copies an attribute value in the buffer provided by the caller, for
+ * attribute arrays the caller provides an array of CK_ATTRIBUTEs that is
+ * filled element by element */
+fn copy_attr_value(
+    ck_attr: &mut CK_ATTRIBUTE,
+    attr: &Attribute,
+) -> Result<CK_RV> {
+    if attr.get_attrtype() == AttrType::ArrayType {
+        let attrs = attr.to_attr_array()?;
+        let attrs_len =
+            CK_ULONG::try_from(attrs.len())? * sizeof!(CK_ATTRIBUTE);
+        if ck_attr.pValue.is_null() {
+            ck_attr.ulValueLen = attrs_len;
+            return Ok(CKR_OK);
+        }
+        if ck_attr.ulValueLen < attrs_len {
+            ck_attr.ulValueLen = CK_UNAVAILABLE_INFORMATION;
+            return Ok(CKR_BUFFER_TOO_SMALL);
+        }
+        ck_attr.ulValueLen = attrs_len;
+        let ck_attrs: &mut [CK_ATTRIBUTE] = unsafe {
+            std::slice::from_raw_parts_mut(
+                ck_attr.pValue as *mut CK_ATTRIBUTE,
+                attrs.len(),
+            )
+        };
+        let mut result = CKR_OK;
+        for (c, a) in ck_attrs.iter_mut().zip(attrs.iter()) {
+            c.type_ = a.get_type();
+            let ret = copy_attr_value(c, a)?;
+            if result == CKR_OK {
+                result = ret;
+            }
+        }
+        return Ok(result);
+    }
+
+    let attr_val = attr.get_value();
+    let attr_len = CK_ULONG::try_from(attr_val.len())?;
+    if ck_attr.pValue.is_null() {
+        ck_attr.ulValueLen = attr_len;
+    } else if ck_attr.ulValueLen < attr_len {
+        ck_attr.ulValueLen = CK_UNAVAILABLE_INFORMATION;
+        return Ok(CKR_BUFFER_TOO_SMALL);
+    } else {
+        ck_attr.ulValueLen = attr_len;
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                attr_val.as_ptr(),
+                ck_attr.pValue as *mut _,
+                attr_val.len(),
+            );
+        }
+    }
+    Ok(CKR_OK)
+}
+
 static DATA_OBJECT_FACTORY: Lazy<Box<dyn ObjectFactory>> =
     Lazy::new(|| Box::new(DataFactory::new()));
 
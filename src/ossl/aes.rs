@@ -22,6 +22,8 @@ use once_cell::sync::Lazy;
 use zeroize::Zeroize;
 
 const MAX_CCM_BUF: usize = 1 << 20; /* 1MiB */
+/* IEEE 1619: a data unit can't be longer than 2^20 blocks */
+const MAX_XTS_DATA_UNIT: usize = AES_BLOCK_SIZE << 20;
 const MIN_RANDOM_IV_BITS: usize = 64;
 
 const AES_128_CBC_CTS: &[u8; 16] = b"AES-128-CBC-CTS\0";
@@ -33,6 +35,9 @@ const AES_256_WRAP_NAME: &[u8; 13] = b"AES-256-WRAP\0";
 const AES_128_WRAP_PAD_NAME: &[u8; 17] = b"AES-128-WRAP-PAD\0";
 const AES_192_WRAP_PAD_NAME: &[u8; 17] = b"AES-192-WRAP-PAD\0";
 const AES_256_WRAP_PAD_NAME: &[u8; 17] = b"AES-256-WRAP-PAD\0";
+const AES_128_GCM_NAME: &[u8; 12] = b"AES-128-GCM\0";
+const AES_192_GCM_NAME: &[u8; 12] = b"AES-192-GCM\0";
+const AES_256_GCM_NAME: &[u8; 12] = b"AES-256-GCM\0";
 
 /* It is safe to share const ciphers as they do not change once they have been
  * created, and reference static function pointers and other data that is
@@ -88,6 +93,8 @@ aes_cipher!(AES_256_CBC; LN_aes_256_cbc);
 aes_cipher!(AES_128_ECB; LN_aes_128_ecb);
 aes_cipher!(AES_192_ECB; LN_aes_192_ecb);
 aes_cipher!(AES_256_ECB; LN_aes_256_ecb);
+aes_cipher!(AES_128_XTS; LN_aes_128_xts);
+aes_cipher!(AES_256_XTS; LN_aes_256_xts);
 #[cfg(not(feature = "fips"))]
 aes_cipher!(AES_128_CFB8; LN_aes_128_cfb8);
 #[cfg(not(feature = "fips"))]
@@ -132,7 +139,10 @@ impl Drop for AesKey {
 
 fn object_to_raw_key(key: &Object) -> Result<AesKey> {
     let val = key.get_attr_as_bytes(CKA_VALUE)?;
-    check_key_len(val.len())?;
+    match key.get_attr_as_ulong(CKA_KEY_TYPE)? {
+        CKK_AES_XTS => check_xts_key(val)?,
+        _ => check_key_len(val.len())?,
+    }
     Ok(AesKey { raw: val.clone() })
 }
 
@@ -144,6 +154,14 @@ fn new_mechanism(flags: CK_FLAGS) -> Box<dyn Mechanism> {
     ))
 }
 
+fn new_xts_mechanism(flags: CK_FLAGS) -> Box<dyn Mechanism> {
+    Box::new(AesMechanism::new(
+        CK_ULONG::try_from(MIN_AES_XTS_SIZE_BYTES).unwrap(),
+        CK_ULONG::try_from(MAX_AES_XTS_SIZE_BYTES).unwrap(),
+        flags,
+    ))
+}
+
 #[derive(Debug)]
 struct AesIvData {
     buf: Vec<u8>,
@@ -264,6 +282,15 @@ impl AesOperation {
         }
 
         mechs.add_mechanism(CKM_AES_KEY_GEN, new_mechanism(CKF_GENERATE));
+
+        mechs.add_mechanism(
+            CKM_AES_XTS,
+            new_xts_mechanism(CKF_ENCRYPT | CKF_DECRYPT),
+        );
+        mechs.add_mechanism(
+            CKM_AES_XTS_KEY_GEN,
+            new_xts_mechanism(CKF_GENERATE),
+        );
     }
 
     fn init_params(mech: &CK_MECHANISM) -> Result<AesParams> {
@@ -344,38 +371,19 @@ impl AesOperation {
                     usize::try_from(params.ulCounterBits),
                     CKR_MECHANISM_PARAM_INVALID
                 )?;
-                let mut maxblocks = 0u128;
-                if ctrbits < (AES_BLOCK_SIZE * 8) {
-                    /* FIXME: support arbitrary counterbits wrapping.
-                     * OpenSSL CTR mode is built to handle the whole IV
-                     * as a 128bit counter unconditionally.
-                     * For callers that want a smaller counterbit size all
-                     * we can do is to set a maximum number of blocks so
-                     * that the counter space does *not* wrap (because
-                     * openssl won't wrap it but proceed to increment the
-                     * octets part of the IV/Nonce). This means that for
-                     * applications that initialize the counter to a value
-                     * like 1 all will be fine, but application that
-                     * initialize the counter to a random value and expect
-                     * wrapping will see a failure instead of wrapping */
-                    maxblocks = (1 << ctrbits) - 1;
-                    let fulloctects = ctrbits / 8;
-                    let mut idx = 0;
-                    while fulloctects > idx {
-                        maxblocks -= u128::try_from(iv[15 - idx])? << (idx * 8);
-                        idx += 1;
-                    }
-                    let part = u128::try_from(ctrbits % 8)?;
-                    if part > 0 {
-                        maxblocks -=
-                            (u128::try_from(iv[15 - idx])? & part) << (idx * 8);
-                    }
-                    if maxblocks == 0 {
-                        return Err(CKR_MECHANISM_PARAM_INVALID)?;
-                    }
-                } else if ctrbits > (AES_BLOCK_SIZE * 8) {
+                if ctrbits == 0 || ctrbits > (AES_BLOCK_SIZE * 8) {
                     return Err(CKR_MECHANISM_PARAM_INVALID)?;
                 }
+                /* The whole counter space can be used exactly once,
+                 * starting from the initial counter value and wrapping
+                 * around within the counter bits, see ctr_update().
+                 * A full 128 bit counter can't practically be exhausted
+                 * so no limit is set in that case */
+                let maxblocks = if ctrbits < (AES_BLOCK_SIZE * 8) {
+                    1u128 << ctrbits
+                } else {
+                    0
+                };
 
                 Ok(AesParams {
                     iv: AesIvData::simple(iv)?,
@@ -406,6 +414,24 @@ impl AesOperation {
                     taglen: 0,
                 })
             }
+            CKM_AES_XTS => {
+                /* the parameter is the 128 bit tweak (the data unit
+                 * sequence number in IEEE 1619) */
+                if mech.ulParameterLen != CK_ULONG::try_from(AES_BLOCK_SIZE)? {
+                    return Err(CKR_ARGUMENTS_BAD)?;
+                }
+                Ok(AesParams {
+                    iv: AesIvData::simple(bytes_to_vec!(
+                        mech.pParameter,
+                        mech.ulParameterLen
+                    ))?,
+                    maxblocks: 0,
+                    ctsmode: 0,
+                    datalen: 0,
+                    aad: Vec::new(),
+                    taglen: 0,
+                })
+            }
             CKM_AES_ECB => Ok(AesParams {
                 iv: AesIvData::none()?,
                 maxblocks: 0,
@@ -534,6 +560,11 @@ impl AesOperation {
                 32 => AES_256_ECB.get_cipher()?,
                 _ => return Err(CKR_MECHANISM_INVALID)?,
             },
+            CKM_AES_XTS => match keylen {
+                32 => AES_128_XTS.get_cipher()?,
+                64 => AES_256_XTS.get_cipher()?,
+                _ => return Err(CKR_MECHANISM_INVALID)?,
+            },
             #[cfg(not(feature = "fips"))]
             CKM_AES_CFB8 => match keylen {
                 16 => AES_128_CFB8.get_cipher()?,
@@ -935,6 +966,49 @@ impl AesOperation {
         })
     }
 
+    /* Only modes that keep all of their running state in the cipher
+     * context can be duplicated, AEAD modes, CTS and Key Wrapping are
+     * either one-shot or buffer data on the side */
+    fn duplicate(&self) -> Result<AesOperation> {
+        match self.mech {
+            CKM_AES_ECB | CKM_AES_CBC | CKM_AES_CBC_PAD | CKM_AES_CTR => (),
+            CKM_AES_CFB8 | CKM_AES_CFB1 | CKM_AES_CFB128 | CKM_AES_OFB => (),
+            _ => return Err(CKR_STATE_UNSAVEABLE)?,
+        }
+        Ok(AesOperation {
+            mech: self.mech,
+            op: self.op,
+            key: AesKey {
+                raw: self.key.raw.clone(),
+            },
+            params: AesParams {
+                iv: AesIvData {
+                    buf: self.params.iv.buf.clone(),
+                    fixedbits: self.params.iv.fixedbits,
+                    gen: self.params.iv.gen,
+                    counter: self.params.iv.counter,
+                    maxcount: self.params.iv.maxcount,
+                },
+                maxblocks: self.params.maxblocks,
+                ctsmode: self.params.ctsmode,
+                datalen: self.params.datalen,
+                aad: self.params.aad.clone(),
+                taglen: self.params.taglen,
+            },
+            finalized: self.finalized,
+            in_use: self.in_use,
+            ctx: if self.in_use {
+                self.ctx.try_clone()?
+            } else {
+                EvpCipherCtx::new()?
+            },
+            finalbuf: self.finalbuf.clone(),
+            blockctr: self.blockctr,
+            #[cfg(feature = "fips")]
+            fips_approved: self.fips_approved,
+        })
+    }
+
     pub fn wrap(
         mech: &CK_MECHANISM,
         wrapping_key: &Object,
@@ -983,11 +1057,189 @@ impl AesOperation {
         Ok(result)
     }
 
+    /* Authenticated wrapping uses the message based AEAD interface,
+     * so that the IV can be generated by the token and returned in
+     * the mechanism parameters together with the tag */
+    pub fn wrap_authenticated(
+        mech: &CK_MECHANISM,
+        wrapping_key: &Object,
+        mut keydata: Vec<u8>,
+        aad: &[u8],
+        output: &mut [u8],
+    ) -> Result<usize> {
+        if mech.mechanism != CKM_AES_GCM {
+            keydata.zeroize();
+            return Err(CKR_MECHANISM_INVALID)?;
+        }
+        /* check early so that no IV is consumed on a length query */
+        if output.len() < keydata.len() {
+            let len = keydata.len();
+            keydata.zeroize();
+            return Err(error::Error::buf_too_small(len));
+        }
+        let result = match Self::msg_encrypt_init(mech, wrapping_key) {
+            Ok(mut op) => op.msg_encrypt(
+                mech.pParameter,
+                mech.ulParameterLen,
+                aad,
+                &keydata,
+                output,
+            ),
+            Err(e) => Err(e),
+        };
+        keydata.zeroize();
+        result
+    }
+
+    pub fn unwrap_authenticated(
+        mech: &CK_MECHANISM,
+        wrapping_key: &Object,
+        data: &[u8],
+        aad: &[u8],
+    ) -> Result<Vec<u8>> {
+        if mech.mechanism != CKM_AES_GCM {
+            return Err(CKR_MECHANISM_INVALID)?;
+        }
+        let mut op = Self::msg_decrypt_init(mech, wrapping_key)?;
+        let mut result = vec![0u8; data.len()];
+        match op.msg_decrypt(
+            mech.pParameter,
+            mech.ulParameterLen,
+            aad,
+            data,
+            result.as_mut_slice(),
+        ) {
+            Ok(outlen) => {
+                result.resize(outlen, 0);
+                Ok(result)
+            }
+            Err(e) => {
+                result.zeroize();
+                Err(e)
+            }
+        }
+    }
+
     fn op_err(&mut self, err: CK_RV) -> error::Error {
         self.finalized = true;
         error::Error::ck_rv(err)
     }
 
+    /* XTS needs the whole data unit in order to apply ciphertext
+     * stealing to the last two blocks, and OpenSSL does not carry the
+     * tweak over from one update to the next, so the data is
+     * accumulated in finalbuf and processed in one go at the end */
+    fn xts_final(
+        &mut self,
+        output: &mut [u8],
+        len_err: CK_RV,
+    ) -> Result<usize> {
+        let len = self.finalbuf.len();
+        if len < AES_BLOCK_SIZE {
+            return Err(self.op_err(len_err));
+        }
+        if output.len() < len {
+            /* This is the only, non-fatal error */
+            return Err(error::Error::buf_too_small(len));
+        }
+        let mut outl: c_int = 0;
+        let res = unsafe {
+            EVP_CipherUpdate(
+                self.ctx.as_mut_ptr(),
+                output.as_mut_ptr(),
+                &mut outl,
+                self.finalbuf.as_ptr(),
+                c_int::try_from(len)?,
+            )
+        };
+        self.finalbuf.zeroize();
+        self.finalbuf.clear();
+        if res != 1 {
+            return Err(self.op_err(CKR_DEVICE_ERROR));
+        }
+        Ok(usize::try_from(outl)?)
+    }
+
+    fn cipher_update(&mut self, input: &[u8], output: &mut [u8]) -> Result<()> {
+        if input.is_empty() {
+            return Ok(());
+        }
+        let mut outl: c_int = 0;
+        let res = unsafe {
+            EVP_CipherUpdate(
+                self.ctx.as_mut_ptr(),
+                output.as_mut_ptr(),
+                &mut outl,
+                input.as_ptr(),
+                c_int::try_from(input.len())?,
+            )
+        };
+        if res != 1 || usize::try_from(outl)? != input.len() {
+            return Err(self.op_err(CKR_DEVICE_ERROR));
+        }
+        Ok(())
+    }
+
+    /* OpenSSL CTR mode always increments the whole IV as a 128 bit
+     * counter, so counters with fewer bits need to be wrapped here:
+     * the data is split where the counter rolls over and the context
+     * is restarted with the counter bits zeroed.
+     * For CTR blockctr counts the bytes processed so far, as updates
+     * can end in the middle of a block */
+    fn ctr_update(&mut self, input: &[u8], output: &mut [u8]) -> Result<usize> {
+        if output.len() < input.len() {
+            /* This is the only, non-fatal error */
+            return Err(error::Error::buf_too_small(input.len()));
+        }
+        let total = self.blockctr.saturating_add(u128::try_from(input.len())?);
+        if self.params.maxblocks == 0 {
+            self.cipher_update(input, output)?;
+            self.blockctr = total;
+            return Ok(input.len());
+        }
+
+        let blocksize = u128::try_from(AES_BLOCK_SIZE)?;
+        if total.div_ceil(blocksize) > self.params.maxblocks {
+            return Err(self.op_err(CKR_DATA_LEN_RANGE));
+        }
+
+        let mask = self.params.maxblocks - 1;
+        let iv = u128::from_be_bytes(
+            match self.params.iv.buf.as_slice().try_into() {
+                Ok(a) => a,
+                Err(_) => return Err(self.op_err(CKR_GENERAL_ERROR)),
+            },
+        );
+        /* offset of the first byte encrypted with a zero counter, if the
+         * initial counter is zero this is the end of the counter space,
+         * and an offset that does not fit can never be reached */
+        let wrap =
+            (self.params.maxblocks - (iv & mask)).saturating_mul(blocksize);
+        let mut done = 0;
+        if self.blockctr <= wrap && wrap < total {
+            done = usize::try_from(wrap - self.blockctr)?;
+            self.cipher_update(&input[..done], output)?;
+
+            let restart = (iv & !mask).to_be_bytes();
+            let res = unsafe {
+                EVP_CipherInit_ex2(
+                    self.ctx.as_mut_ptr(),
+                    std::ptr::null(),
+                    std::ptr::null(),
+                    restart.as_ptr(),
+                    -1,
+                    std::ptr::null(),
+                )
+            };
+            if res != 1 {
+                return Err(self.op_err(CKR_DEVICE_ERROR));
+            }
+        }
+        self.cipher_update(&input[done..], &mut output[done..])?;
+        self.blockctr = total;
+        Ok(input.len())
+    }
+
     /* returns pointer to IV */
     fn init_msg_params(
         &mut self,
@@ -1469,22 +1721,18 @@ impl Encryption for AesOperation {
                     outlen = 0;
                 }
             }
-            CKM_AES_CTR => {
-                if self.params.maxblocks != 0 {
-                    let reqblocks = ((plain.len() + AES_BLOCK_SIZE - 1)
-                        / AES_BLOCK_SIZE)
-                        as u128;
-                    if self.blockctr + reqblocks > self.params.maxblocks {
-                        return Err(self.op_err(CKR_DATA_LEN_RANGE));
-                    }
-                    self.blockctr += reqblocks;
-                }
-            }
+            CKM_AES_CTR => return self.ctr_update(plain, cipher),
             CKM_AES_KEY_WRAP => {
                 if plain.len() % 8 != 0 {
                     return Err(self.op_err(CKR_DATA_LEN_RANGE));
                 }
             }
+            CKM_AES_XTS => {
+                if plain.len() + self.finalbuf.len() > MAX_XTS_DATA_UNIT {
+                    return Err(self.op_err(CKR_DATA_LEN_RANGE));
+                }
+                outlen = 0;
+            }
             _ => (),
         }
         if cipher.len() < outlen {
@@ -1494,6 +1742,10 @@ impl Encryption for AesOperation {
 
         let mut plain_buf = plain.as_ptr();
         let mut plain_len = plain.len();
+        if self.mech == CKM_AES_XTS {
+            self.finalbuf.extend_from_slice(plain);
+            plain_len = 0;
+        }
         if self.mech == CKM_AES_CCM {
             if plain.len() < self.params.datalen {
                 self.finalbuf.extend_from_slice(plain);
@@ -1575,16 +1827,12 @@ impl Encryption for AesOperation {
                 }
                 outlen = self.params.taglen;
             }
-            CKM_AES_CTR => {
-                if self.params.maxblocks > 0 {
-                    if self.blockctr >= self.params.maxblocks {
-                        return Err(self.op_err(CKR_DATA_LEN_RANGE));
-                    }
-                }
-            }
-            CKM_AES_CTS | CKM_AES_CBC | CKM_AES_ECB => (),
+            CKM_AES_CTR | CKM_AES_CTS | CKM_AES_CBC | CKM_AES_ECB => (),
             #[cfg(not(feature = "fips"))]
             CKM_AES_CFB8 | CKM_AES_CFB1 | CKM_AES_CFB128 | CKM_AES_OFB => (),
+            CKM_AES_XTS => {
+                outlen = self.xts_final(cipher, CKR_DATA_LEN_RANGE)?;
+            }
             CKM_AES_CBC_PAD => {
                 /* check if this is a second call after
                  * we saved the final buffer */
@@ -1666,6 +1914,7 @@ impl Encryption for AesOperation {
                 CKM_AES_CTR | CKM_AES_CTS | CKM_AES_CBC | CKM_AES_ECB => 0,
                 #[cfg(not(feature = "fips"))]
                 CKM_AES_CFB8 | CKM_AES_CFB1 | CKM_AES_CFB128 | CKM_AES_OFB => 0,
+                CKM_AES_XTS => self.finalbuf.len(),
                 CKM_AES_CBC_PAD => {
                     if self.finalbuf.len() > 0 {
                         self.finalbuf.len()
@@ -1680,7 +1929,7 @@ impl Encryption for AesOperation {
             match self.mech {
                 CKM_AES_CCM => self.params.datalen + self.params.taglen,
                 CKM_AES_GCM => data_len + self.params.taglen,
-                CKM_AES_CTR | CKM_AES_CTS => data_len,
+                CKM_AES_CTR | CKM_AES_CTS | CKM_AES_XTS => data_len,
                 CKM_AES_CBC | CKM_AES_ECB => {
                     ((data_len + AES_BLOCK_SIZE - 1) / AES_BLOCK_SIZE)
                         * AES_BLOCK_SIZE
@@ -1707,6 +1956,10 @@ impl Encryption for AesOperation {
         };
         Ok(outlen)
     }
+
+    fn try_clone(&self) -> Result<Box<dyn Encryption>> {
+        Ok(Box::new(self.duplicate()?))
+    }
 }
 
 impl Decryption for AesOperation {
@@ -1718,6 +1971,7 @@ impl Decryption for AesOperation {
             match self.mech {
                 CKM_AES_CCM => return Ok(self.params.datalen),
                 CKM_AES_GCM => return Ok(cipher.len() - self.params.taglen),
+                CKM_AES_XTS => return Ok(cipher.len()),
                 _ => return self.decrypt_update(cipher, plain),
             }
         }
@@ -1763,6 +2017,11 @@ impl Decryption for AesOperation {
                     return Err(self.op_err(CKR_DATA_LEN_RANGE));
                 }
             }
+            CKM_AES_XTS
+                if cipher.len() + self.finalbuf.len() > MAX_XTS_DATA_UNIT =>
+            {
+                return Err(self.op_err(CKR_ENCRYPTED_DATA_LEN_RANGE));
+            }
             _ => (),
         }
         if !self.in_use {
@@ -1790,18 +2049,7 @@ impl Decryption for AesOperation {
                  * ultimately needed */
                 self.params.datalen
             }
-            CKM_AES_CTR => {
-                if self.params.maxblocks != 0 {
-                    let reqblocks = ((cipher.len() + AES_BLOCK_SIZE - 1)
-                        / AES_BLOCK_SIZE)
-                        as u128;
-                    if self.blockctr + reqblocks > self.params.maxblocks {
-                        return Err(self.op_err(CKR_DATA_LEN_RANGE));
-                    }
-                    self.blockctr += reqblocks;
-                }
-                cipher.len()
-            }
+            CKM_AES_CTR => return self.ctr_update(cipher, plain),
             CKM_AES_GCM => {
                 let tlen = cipher.len() + self.finalbuf.len();
                 if tlen > self.params.taglen {
@@ -1811,6 +2059,7 @@ impl Decryption for AesOperation {
                 }
             }
             CKM_AES_KEY_WRAP | CKM_AES_KEY_WRAP_KWP => cipher.len(),
+            CKM_AES_XTS => 0,
             _ => cipher.len(),
         };
         if plain.len() < outlen {
@@ -1894,6 +2143,10 @@ impl Decryption for AesOperation {
                     cipher_len = 0;
                 }
             }
+            CKM_AES_XTS => {
+                self.finalbuf.extend_from_slice(cipher);
+                cipher_len = 0;
+            }
             _ => (),
         }
 
@@ -1972,16 +2225,12 @@ impl Decryption for AesOperation {
                     return Err(self.op_err(CKR_DEVICE_ERROR));
                 }
             }
-            CKM_AES_CTR => {
-                if self.params.maxblocks > 0 {
-                    if self.blockctr >= self.params.maxblocks {
-                        return Err(self.op_err(CKR_DATA_LEN_RANGE));
-                    }
-                }
-            }
-            CKM_AES_CTS | CKM_AES_CBC | CKM_AES_ECB => (),
+            CKM_AES_CTR | CKM_AES_CTS | CKM_AES_CBC | CKM_AES_ECB => (),
             #[cfg(not(feature = "fips"))]
             CKM_AES_CFB8 | CKM_AES_CFB1 | CKM_AES_CFB128 | CKM_AES_OFB => (),
+            CKM_AES_XTS => {
+                outlen = self.xts_final(plain, CKR_ENCRYPTED_DATA_LEN_RANGE)?;
+            }
             CKM_AES_CBC_PAD => {
                 /* check if this is a second call after
                  * we saved the final buffer */
@@ -2063,6 +2312,7 @@ impl Decryption for AesOperation {
                 | CKM_AES_ECB => 0,
                 #[cfg(not(feature = "fips"))]
                 CKM_AES_CFB8 | CKM_AES_CFB1 | CKM_AES_CFB128 | CKM_AES_OFB => 0,
+                CKM_AES_XTS => self.finalbuf.len(),
                 CKM_AES_CBC_PAD => {
                     if self.finalbuf.len() > 0 {
                         self.finalbuf.len()
@@ -2084,7 +2334,7 @@ impl Decryption for AesOperation {
                         0
                     }
                 }
-                CKM_AES_CTR | CKM_AES_CTS => data_len,
+                CKM_AES_CTR | CKM_AES_CTS | CKM_AES_XTS => data_len,
                 CKM_AES_CBC | CKM_AES_CBC_PAD | CKM_AES_ECB => {
                     (data_len / AES_BLOCK_SIZE) * AES_BLOCK_SIZE
                 }
@@ -2113,6 +2363,10 @@ impl Decryption for AesOperation {
         };
         Ok(outlen)
     }
+
+    fn try_clone(&self) -> Result<Box<dyn Decryption>> {
+        Ok(Box::new(self.duplicate()?))
+    }
 }
 
 impl MessageOperation for AesOperation {
@@ -2505,9 +2759,11 @@ impl MsgDecryption for AesOperation {
 /* _key and _mac as stored in order to make sure the pointers they
  * hold survive for as long as the operations are going on, as we
  * can't be sure openssl is not holding live pointers to the
- * parameters passed into the init functions */
+ * parameters passed into the init functions.
+ * CMAC and GMAC are both provided by OpenSSL as EVP_MACs and differ
+ * only in the initialization, so they share this operation */
 #[derive(Debug)]
-pub struct AesCmacOperation {
+pub struct AesEvpMacOperation {
     mech: CK_MECHANISM_TYPE,
     finalized: bool,
     in_use: bool,
@@ -2518,14 +2774,47 @@ pub struct AesCmacOperation {
     fips_approved: Option<bool>,
 }
 
-impl AesCmacOperation {
+impl AesEvpMacOperation {
     pub fn register_mechanisms(mechs: &mut Mechanisms) {
-        for ckm in &[CKM_AES_CMAC, CKM_AES_CMAC_GENERAL] {
+        for ckm in &[CKM_AES_CMAC, CKM_AES_CMAC_GENERAL, CKM_AES_GMAC] {
             mechs.add_mechanism(*ckm, new_mechanism(CKF_SIGN | CKF_VERIFY));
         }
     }
 
-    pub fn init(mech: &CK_MECHANISM, key: &Object) -> Result<AesCmacOperation> {
+    fn init(
+        mech: &CK_MECHANISM,
+        mackey: AesKey,
+        mut ctx: EvpMacCtx,
+        params: &OsslParam,
+        maclen: usize,
+    ) -> Result<AesEvpMacOperation> {
+        if unsafe {
+            EVP_MAC_init(
+                ctx.as_mut_ptr(),
+                mackey.raw.as_ptr(),
+                mackey.raw.len(),
+                params.as_ptr(),
+            )
+        } != 1
+        {
+            return Err(CKR_DEVICE_ERROR)?;
+        }
+        Ok(AesEvpMacOperation {
+            mech: mech.mechanism,
+            finalized: false,
+            in_use: false,
+            _key: mackey,
+            ctx,
+            maclen,
+            #[cfg(feature = "fips")]
+            fips_approved: None,
+        })
+    }
+
+    pub fn cmac_init(
+        mech: &CK_MECHANISM,
+        key: &Object,
+    ) -> Result<AesEvpMacOperation> {
         let maclen = match mech.mechanism {
             CKM_AES_CMAC_GENERAL => {
                 let params = cast_params!(mech, CK_MAC_GENERAL_PARAMS);
@@ -2544,7 +2833,6 @@ impl AesCmacOperation {
             _ => return Err(CKR_MECHANISM_INVALID)?,
         };
         let mackey = object_to_raw_key(key)?;
-        let mut ctx = EvpMacCtx::new(name_as_char(OSSL_MAC_NAME_CMAC))?;
         let mut params = OsslParam::with_capacity(1);
         params.add_const_c_string(
             name_as_char(OSSL_MAC_PARAM_CIPHER),
@@ -2556,28 +2844,43 @@ impl AesCmacOperation {
             },
         )?;
         params.finalize();
+        let ctx = EvpMacCtx::new(name_as_char(OSSL_MAC_NAME_CMAC))?;
+        Self::init(mech, mackey, ctx, &params, maclen)
+    }
 
-        if unsafe {
-            EVP_MAC_init(
-                ctx.as_mut_ptr(),
-                mackey.raw.as_ptr(),
-                mackey.raw.len(),
-                params.as_ptr(),
-            )
-        } != 1
-        {
-            return Err(CKR_DEVICE_ERROR)?;
+    pub fn gmac_init(
+        mech: &CK_MECHANISM,
+        key: &Object,
+    ) -> Result<AesEvpMacOperation> {
+        if mech.mechanism != CKM_AES_GMAC {
+            return Err(CKR_MECHANISM_INVALID)?;
         }
-        Ok(AesCmacOperation {
-            mech: mech.mechanism,
-            finalized: false,
-            in_use: false,
-            _key: mackey,
-            ctx: ctx,
-            maclen: maclen,
-            #[cfg(feature = "fips")]
-            fips_approved: None,
-        })
+        /* the parameter is the IV */
+        if mech.pParameter.is_null() || mech.ulParameterLen == 0 {
+            return Err(CKR_MECHANISM_PARAM_INVALID)?;
+        }
+        let ivlen = map_err!(
+            usize::try_from(mech.ulParameterLen),
+            CKR_MECHANISM_PARAM_INVALID
+        )?;
+        let mackey = object_to_raw_key(key)?;
+        let mut params = OsslParam::with_capacity(2);
+        params.add_const_c_string(
+            name_as_char(OSSL_MAC_PARAM_CIPHER),
+            match mackey.raw.len() {
+                16 => name_as_char(AES_128_GCM_NAME),
+                24 => name_as_char(AES_192_GCM_NAME),
+                32 => name_as_char(AES_256_GCM_NAME),
+                _ => return Err(CKR_KEY_INDIGESTIBLE)?,
+            },
+        )?;
+        params.add_owned_octet_string(
+            name_as_char(OSSL_MAC_PARAM_IV),
+            bytes_to_vec!(mech.pParameter, ivlen),
+        )?;
+        params.finalize();
+        let ctx = EvpMacCtx::new(name_as_char(OSSL_MAC_NAME_GMAC))?;
+        Self::init(mech, mackey, ctx, &params, AES_BLOCK_SIZE)
     }
 
     fn begin(&mut self) -> Result<()> {
@@ -2639,7 +2942,7 @@ impl AesCmacOperation {
     }
 }
 
-impl MechOperation for AesCmacOperation {
+impl MechOperation for AesEvpMacOperation {
     fn mechanism(&self) -> Result<CK_MECHANISM_TYPE> {
         Ok(self.mech)
     }
@@ -2653,7 +2956,7 @@ impl MechOperation for AesCmacOperation {
     }
 }
 
-impl Mac for AesCmacOperation {
+impl Mac for AesEvpMacOperation {
     fn mac(&mut self, data: &[u8], mac: &mut [u8]) -> Result<()> {
         self.begin()?;
         if data.len() > 0 {
@@ -2675,7 +2978,7 @@ impl Mac for AesCmacOperation {
     }
 }
 
-impl Sign for AesCmacOperation {
+impl Sign for AesEvpMacOperation {
     fn sign(&mut self, data: &[u8], signature: &mut [u8]) -> Result<()> {
         self.begin()?;
         if data.len() > 0 {
@@ -2697,7 +3000,7 @@ impl Sign for AesCmacOperation {
     }
 }
 
-impl Verify for AesCmacOperation {
+impl Verify for AesEvpMacOperation {
     fn verify(&mut self, data: &[u8], signature: &[u8]) -> Result<()> {
         self.begin()?;
         if data.len() > 0 {
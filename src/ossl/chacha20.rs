@@ -0,0 +1,1064 @@
+// Copyright 2024 Simo Sorce
+// See LICENSE.txt file for terms
+
+use std::ffi::{c_char, c_int, c_void};
+
+use crate::chacha20::*;
+use crate::error;
+use crate::error::Result;
+use crate::interface::*;
+use crate::mechanism::*;
+use crate::native::chacha20::{hchacha20, HCHACHA20_NONCE_SIZE};
+use crate::object::Object;
+use crate::ossl::bindings::*;
+use crate::ossl::common::*;
+use crate::{bytes_to_vec, cast_params, map_err};
+
+use constant_time_eq::constant_time_eq;
+use once_cell::sync::Lazy;
+use zeroize::Zeroize;
+
+const CHACHA20_BLOCK_SIZE: usize = 64;
+/* OpenSSL takes the block counter and the nonce as a single 16 byte IV,
+ * laid out exactly as the last four words of the ChaCha20 state */
+const CHACHA20_IV_SIZE: usize = 16;
+const CHACHA20_POLY1305_NONCE_SIZE: usize = 12;
+const XCHACHA20_POLY1305_NONCE_SIZE: usize = 24;
+/* RFC 8439, 2.8: the AEAD uses block 0 for the Poly1305 key */
+const CHACHA20_POLY1305_MAX_BLOCKS: u128 = (1 << 32) - 1;
+
+/* It is safe to share const ciphers as they do not change once they have been
+ * created, and reference static function pointers and other data that is
+ * always valid */
+struct ChaCha20Cipher {
+    cipher: Option<EvpCipher>,
+}
+
+impl ChaCha20Cipher {
+    pub fn new(name: *const u8) -> ChaCha20Cipher {
+        ChaCha20Cipher {
+            cipher: EvpCipher::new(name as *const c_char).ok(),
+        }
+    }
+
+    pub fn get_cipher(&self) -> Result<&EvpCipher> {
+        match self.cipher {
+            Some(ref ec) => Ok(ec),
+            None => Err(CKR_MECHANISM_INVALID)?,
+        }
+    }
+}
+
+static CHACHA20: Lazy<ChaCha20Cipher> =
+    Lazy::new(|| ChaCha20Cipher::new(LN_chacha20.as_ptr()));
+static CHACHA20_POLY1305: Lazy<ChaCha20Cipher> =
+    Lazy::new(|| ChaCha20Cipher::new(LN_chacha20_poly1305.as_ptr()));
+
+#[derive(Debug)]
+struct ChaCha20Key {
+    raw: Vec<u8>,
+}
+
+impl Drop for ChaCha20Key {
+    fn drop(&mut self) {
+        self.raw.zeroize()
+    }
+}
+
+fn object_to_raw_key(key: &Object, size: usize) -> Result<ChaCha20Key> {
+    let val = key.get_attr_as_bytes(CKA_VALUE)?;
+    if val.len() != size {
+        Err(CKR_KEY_INDIGESTIBLE)?;
+    }
+    Ok(ChaCha20Key { raw: val.clone() })
+}
+
+#[derive(Debug)]
+struct ChaCha20Params {
+    /* the nonce as provided by the caller */
+    nonce: Vec<u8>,
+    /* the XChaCha20 subkey, when an extended nonce is used */
+    subkey: Option<ChaCha20Key>,
+    iv: Vec<u8>,
+    aad: Vec<u8>,
+    maxblocks: u128,
+}
+
+impl ChaCha20Params {
+    fn none() -> ChaCha20Params {
+        ChaCha20Params {
+            nonce: Vec::new(),
+            subkey: None,
+            iv: Vec::new(),
+            aad: Vec::new(),
+            maxblocks: 0,
+        }
+    }
+
+    /* Splits an extended nonce into the HChaCha20 input, that is used
+     * to derive the subkey, and the remaining 8 bytes which are used as
+     * the (64 bit) nonce of the actual ChaCha20 operation */
+    fn set_nonce(&mut self, key: &ChaCha20Key, nonce: Vec<u8>) {
+        self.subkey = None;
+        if nonce.len() > CHACHA20_POLY1305_NONCE_SIZE {
+            self.subkey = Some(ChaCha20Key {
+                raw: hchacha20(&key.raw, &nonce[..HCHACHA20_NONCE_SIZE]),
+            });
+        }
+        self.nonce = nonce;
+    }
+
+    fn chacha20_nonce(&self) -> &[u8] {
+        match self.subkey {
+            Some(_) => &self.nonce[HCHACHA20_NONCE_SIZE..],
+            None => &self.nonce,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ChaCha20Operation {
+    mech: CK_MECHANISM_TYPE,
+    op: CK_FLAGS,
+    key: ChaCha20Key,
+    params: ChaCha20Params,
+    finalized: bool,
+    in_use: bool,
+    ctx: EvpCipherCtx,
+    finalbuf: Vec<u8>,
+    blockctr: u128,
+}
+
+impl ChaCha20Operation {
+    pub fn register_mechanisms(mechs: &mut Mechanisms) {
+        mechs.add_mechanism(
+            CKM_CHACHA20,
+            Box::new(ChaCha20Mechanism::new(CKF_ENCRYPT | CKF_DECRYPT)),
+        );
+        mechs.add_mechanism(
+            CKM_CHACHA20_POLY1305,
+            Box::new(ChaCha20Mechanism::new(
+                CKF_ENCRYPT
+                    | CKF_DECRYPT
+                    | CKF_MESSAGE_ENCRYPT
+                    | CKF_MESSAGE_DECRYPT,
+            )),
+        );
+    }
+
+    fn init_params(
+        mech: &CK_MECHANISM,
+        key: &ChaCha20Key,
+    ) -> Result<ChaCha20Params> {
+        let mut cp = ChaCha20Params::none();
+        match mech.mechanism {
+            CKM_CHACHA20 => {
+                let params = cast_params!(mech, CK_CHACHA20_PARAMS);
+                if params.pNonce.is_null() {
+                    return Err(CKR_MECHANISM_PARAM_INVALID)?;
+                }
+                /* The counter and the nonce must fill the last four
+                 * words of the state, unless an extended nonce is used,
+                 * in which case 64 bits are left for the counter */
+                match (params.ulNonceBits, params.blockCounterBits) {
+                    (64, 64) | (96, 32) | (192, 32) | (192, 64) => (),
+                    _ => return Err(CKR_MECHANISM_PARAM_INVALID)?,
+                }
+                let ctrlen = usize::try_from(params.blockCounterBits / 8)?;
+                let noncelen = usize::try_from(params.ulNonceBits / 8)?;
+
+                /* The counter is copied verbatim in the state, so it is
+                 * little endian, a NULL pointer means a zero counter */
+                let mut counter = [0u8; 8];
+                if !params.pBlockCounter.is_null() {
+                    counter[..ctrlen].copy_from_slice(&bytes_to_vec!(
+                        params.pBlockCounter,
+                        ctrlen
+                    ));
+                }
+                let start = u128::from(u64::from_le_bytes(counter));
+                cp.maxblocks = (1u128 << params.blockCounterBits) - start;
+
+                cp.set_nonce(key, bytes_to_vec!(params.pNonce, noncelen));
+                let nonce = cp.chacha20_nonce();
+                let mut iv = Vec::with_capacity(CHACHA20_IV_SIZE);
+                iv.extend_from_slice(&counter[..ctrlen]);
+                iv.resize(CHACHA20_IV_SIZE - nonce.len(), 0);
+                iv.extend_from_slice(nonce);
+                cp.iv = iv;
+            }
+            CKM_CHACHA20_POLY1305 => {
+                let params =
+                    cast_params!(mech, CK_SALSA20_CHACHA20_POLY1305_PARAMS);
+                if params.pNonce.is_null() {
+                    return Err(CKR_MECHANISM_PARAM_INVALID)?;
+                }
+                let noncelen = usize::try_from(params.ulNonceLen)?;
+                match noncelen {
+                    CHACHA20_POLY1305_NONCE_SIZE
+                    | XCHACHA20_POLY1305_NONCE_SIZE => (),
+                    _ => return Err(CKR_MECHANISM_PARAM_INVALID)?,
+                }
+                if params.ulAADLen > CK_ULONG::from(u32::MAX) {
+                    return Err(CKR_MECHANISM_PARAM_INVALID)?;
+                }
+                cp.set_nonce(key, bytes_to_vec!(params.pNonce, noncelen));
+                cp.iv = Self::aead_iv(&cp);
+                cp.aad = bytes_to_vec!(params.pAAD, params.ulAADLen);
+                cp.maxblocks = CHACHA20_POLY1305_MAX_BLOCKS;
+            }
+            /* MessageEncrypt/Decrypt uses this at init */
+            CK_UNAVAILABLE_INFORMATION => {
+                if !mech.pParameter.is_null() || mech.ulParameterLen != 0 {
+                    return Err(CKR_MECHANISM_PARAM_INVALID)?;
+                }
+            }
+            _ => return Err(CKR_MECHANISM_INVALID)?,
+        }
+        Ok(cp)
+    }
+
+    /* draft-irtf-cfrg-xchacha, 2.3: the remaining 8 bytes of an
+     * extended nonce are prefixed with 4 zero bytes */
+    fn aead_iv(cp: &ChaCha20Params) -> Vec<u8> {
+        let nonce = cp.chacha20_nonce();
+        let mut iv = vec![0u8; CHACHA20_POLY1305_NONCE_SIZE - nonce.len()];
+        iv.extend_from_slice(nonce);
+        iv
+    }
+
+    fn new_op(
+        mech: &CK_MECHANISM,
+        key: &Object,
+        op: CK_FLAGS,
+    ) -> Result<ChaCha20Operation> {
+        let key = object_to_raw_key(key, CHACHA20_KEY_SIZE)?;
+        let params = Self::init_params(mech, &key)?;
+        Ok(ChaCha20Operation {
+            mech: mech.mechanism,
+            op,
+            key,
+            params,
+            finalized: false,
+            in_use: false,
+            ctx: EvpCipherCtx::new()?,
+            finalbuf: Vec::new(),
+            blockctr: 0,
+        })
+    }
+
+    pub fn encrypt_new(
+        mech: &CK_MECHANISM,
+        key: &Object,
+    ) -> Result<ChaCha20Operation> {
+        Self::new_op(mech, key, CKF_ENCRYPT)
+    }
+
+    pub fn decrypt_new(
+        mech: &CK_MECHANISM,
+        key: &Object,
+    ) -> Result<ChaCha20Operation> {
+        Self::new_op(mech, key, CKF_DECRYPT)
+    }
+
+    fn op_err(&mut self, err: CK_RV) -> error::Error {
+        self.finalized = true;
+        error::Error::ck_rv(err)
+    }
+
+    fn initialize(&mut self) -> Result<()> {
+        let evpcipher = match self.mech {
+            CKM_CHACHA20 => CHACHA20.get_cipher(),
+            CKM_CHACHA20_POLY1305 => CHACHA20_POLY1305.get_cipher(),
+            _ => Err(CKR_MECHANISM_INVALID)?,
+        };
+        let evpcipher = match evpcipher {
+            Ok(c) => c,
+            Err(e) => return Err(self.op_err(e.rv())),
+        };
+        let enc = match self.op {
+            CKF_ENCRYPT | CKF_MESSAGE_ENCRYPT => 1,
+            _ => 0,
+        };
+        let key = match self.params.subkey {
+            Some(ref k) => k.raw.as_ptr(),
+            None => self.key.raw.as_ptr(),
+        };
+
+        let res = unsafe {
+            EVP_CipherInit_ex2(
+                self.ctx.as_mut_ptr(),
+                evpcipher.as_ptr(),
+                key,
+                self.params.iv.as_ptr(),
+                enc,
+                std::ptr::null(),
+            )
+        };
+        if res != 1 {
+            return Err(self.op_err(CKR_DEVICE_ERROR));
+        }
+
+        if !self.params.aad.is_empty() {
+            let mut outl: c_int = 0;
+            let res = unsafe {
+                EVP_CipherUpdate(
+                    self.ctx.as_mut_ptr(),
+                    std::ptr::null_mut(),
+                    &mut outl,
+                    self.params.aad.as_ptr(),
+                    c_int::try_from(self.params.aad.len())?,
+                )
+            };
+            if res != 1 {
+                return Err(self.op_err(CKR_DEVICE_ERROR));
+            }
+        }
+        Ok(())
+    }
+
+    /* Neither OpenSSL nor the IETF variant allow the block counter to
+     * wrap, so fail instead of silently reusing the key stream */
+    fn count_blocks(&mut self, len: usize) -> Result<()> {
+        let total = self.blockctr + u128::try_from(len)?;
+        if total.div_ceil(CHACHA20_BLOCK_SIZE as u128) > self.params.maxblocks {
+            return Err(self.op_err(CKR_DATA_LEN_RANGE));
+        }
+        self.blockctr = total;
+        Ok(())
+    }
+
+    fn update(&mut self, input: &[u8], output: &mut [u8]) -> Result<usize> {
+        if input.is_empty() {
+            return Ok(0);
+        }
+        self.count_blocks(input.len())?;
+        let mut outl: c_int = 0;
+        let res = unsafe {
+            EVP_CipherUpdate(
+                self.ctx.as_mut_ptr(),
+                output.as_mut_ptr(),
+                &mut outl,
+                input.as_ptr(),
+                c_int::try_from(input.len())?,
+            )
+        };
+        if res != 1 {
+            return Err(self.op_err(CKR_DEVICE_ERROR));
+        }
+        Ok(usize::try_from(outl)?)
+    }
+
+    fn get_tag(&mut self, tag: &mut [u8]) -> Result<()> {
+        let mut outl: c_int = 0;
+        let res = unsafe {
+            /* This will always return 0 bytes */
+            EVP_EncryptFinal_ex(
+                self.ctx.as_mut_ptr(),
+                std::ptr::null_mut(),
+                &mut outl,
+            )
+        };
+        if res != 1 || outl != 0 {
+            return Err(self.op_err(CKR_DEVICE_ERROR));
+        }
+        let res = unsafe {
+            EVP_CIPHER_CTX_ctrl(
+                self.ctx.as_mut_ptr(),
+                c_int::try_from(EVP_CTRL_AEAD_GET_TAG)?,
+                c_int::try_from(POLY1305_TAG_SIZE)?,
+                tag.as_mut_ptr() as *mut c_void,
+            )
+        };
+        if res != 1 {
+            return Err(self.op_err(CKR_DEVICE_ERROR));
+        }
+        Ok(())
+    }
+
+    fn check_tag(&mut self, tag: &[u8]) -> Result<()> {
+        let res = unsafe {
+            EVP_CIPHER_CTX_ctrl(
+                self.ctx.as_mut_ptr(),
+                c_int::try_from(EVP_CTRL_AEAD_SET_TAG)?,
+                c_int::try_from(POLY1305_TAG_SIZE)?,
+                tag.as_ptr() as *mut c_void,
+            )
+        };
+        if res != 1 {
+            return Err(self.op_err(CKR_DEVICE_ERROR));
+        }
+        let mut outl: c_int = 0;
+        let res = unsafe {
+            EVP_DecryptFinal_ex(
+                self.ctx.as_mut_ptr(),
+                std::ptr::null_mut(),
+                &mut outl,
+            )
+        };
+        if res != 1 {
+            return Err(self.op_err(CKR_ENCRYPTED_DATA_INVALID));
+        }
+        if outl != 0 {
+            return Err(self.op_err(CKR_DEVICE_ERROR));
+        }
+        Ok(())
+    }
+
+    /* Only the stream cipher keeps all of its running state in the
+     * cipher context, the AEAD buffers the tag on the side */
+    fn duplicate(&self) -> Result<ChaCha20Operation> {
+        if self.mech != CKM_CHACHA20 {
+            return Err(CKR_STATE_UNSAVEABLE)?;
+        }
+        Ok(ChaCha20Operation {
+            mech: self.mech,
+            op: self.op,
+            key: ChaCha20Key {
+                raw: self.key.raw.clone(),
+            },
+            params: ChaCha20Params {
+                nonce: self.params.nonce.clone(),
+                subkey: self
+                    .params
+                    .subkey
+                    .as_ref()
+                    .map(|k| ChaCha20Key { raw: k.raw.clone() }),
+                iv: self.params.iv.clone(),
+                aad: self.params.aad.clone(),
+                maxblocks: self.params.maxblocks,
+            },
+            finalized: self.finalized,
+            in_use: self.in_use,
+            ctx: if self.in_use {
+                self.ctx.try_clone()?
+            } else {
+                EvpCipherCtx::new()?
+            },
+            finalbuf: self.finalbuf.clone(),
+            blockctr: self.blockctr,
+        })
+    }
+
+    /* returns pointer to tag */
+    fn init_msg_params(
+        &mut self,
+        parameter: CK_VOID_PTR,
+        parameter_len: CK_ULONG,
+        aad: &[u8],
+    ) -> Result<CK_BYTE_PTR> {
+        let params = cast_params!(
+            parameter,
+            parameter_len,
+            CK_SALSA20_CHACHA20_POLY1305_MSG_PARAMS
+        );
+        if params.pNonce.is_null() || params.pTag.is_null() {
+            return Err(CKR_ARGUMENTS_BAD)?;
+        }
+        let noncelen =
+            map_err!(usize::try_from(params.ulNonceLen), CKR_ARGUMENTS_BAD)?;
+        match noncelen {
+            CHACHA20_POLY1305_NONCE_SIZE | XCHACHA20_POLY1305_NONCE_SIZE => (),
+            _ => return Err(CKR_ARGUMENTS_BAD)?,
+        }
+        if aad.len() > usize::try_from(u32::MAX)? {
+            return Err(CKR_ARGUMENTS_BAD)?;
+        }
+        let mut cp = ChaCha20Params::none();
+        cp.set_nonce(&self.key, bytes_to_vec!(params.pNonce, noncelen));
+        cp.iv = Self::aead_iv(&cp);
+        cp.aad = aad.to_vec();
+        cp.maxblocks = CHACHA20_POLY1305_MAX_BLOCKS;
+        self.params = cp;
+        self.blockctr = 0;
+        Ok(params.pTag)
+    }
+
+    /* returns pointer to tag */
+    fn check_msg_params(
+        &mut self,
+        parameter: CK_VOID_PTR,
+        parameter_len: CK_ULONG,
+    ) -> Result<CK_BYTE_PTR> {
+        let params = cast_params!(
+            parameter,
+            parameter_len,
+            CK_SALSA20_CHACHA20_POLY1305_MSG_PARAMS
+        );
+        if params.pNonce.is_null() || params.pTag.is_null() {
+            return Err(self.op_err(CKR_ARGUMENTS_BAD));
+        }
+        let noncelen =
+            map_err!(usize::try_from(params.ulNonceLen), CKR_ARGUMENTS_BAD)?;
+        if self.params.nonce.len() != noncelen {
+            return Err(self.op_err(CKR_ARGUMENTS_BAD));
+        }
+        if self.params.nonce != bytes_to_vec!(params.pNonce, noncelen) {
+            return Err(self.op_err(CKR_ARGUMENTS_BAD));
+        }
+        Ok(params.pTag)
+    }
+
+    fn msg_init(
+        mech: &CK_MECHANISM,
+        key: &Object,
+        op: CK_FLAGS,
+    ) -> Result<ChaCha20Operation> {
+        if mech.mechanism != CKM_CHACHA20_POLY1305 {
+            return Err(CKR_MECHANISM_INVALID)?;
+        }
+        /* params are not set until later */
+        let mut msgop = Self::new_op(
+            &CK_MECHANISM {
+                mechanism: CK_UNAVAILABLE_INFORMATION,
+                pParameter: mech.pParameter,
+                ulParameterLen: mech.ulParameterLen,
+            },
+            key,
+            op,
+        )?;
+        msgop.mech = mech.mechanism;
+        Ok(msgop)
+    }
+
+    pub fn msg_encrypt_init(
+        mech: &CK_MECHANISM,
+        key: &Object,
+    ) -> Result<ChaCha20Operation> {
+        Self::msg_init(mech, key, CKF_MESSAGE_ENCRYPT)
+    }
+
+    pub fn msg_decrypt_init(
+        mech: &CK_MECHANISM,
+        key: &Object,
+    ) -> Result<ChaCha20Operation> {
+        Self::msg_init(mech, key, CKF_MESSAGE_DECRYPT)
+    }
+
+    fn msg_new(
+        &mut self,
+        op: CK_FLAGS,
+        parameter: CK_VOID_PTR,
+        parameter_len: CK_ULONG,
+        aad: &[u8],
+    ) -> Result<()> {
+        if self.op != op {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        if self.in_use {
+            return Err(CKR_OPERATION_ACTIVE)?;
+        }
+        self.finalized = false;
+        self.in_use = true;
+
+        let _ = self.init_msg_params(parameter, parameter_len, aad)?;
+
+        /* reset ctx */
+        let res = unsafe { EVP_CIPHER_CTX_reset(self.ctx.as_mut_ptr()) };
+        if res != 1 {
+            return Err(CKR_DEVICE_ERROR)?;
+        }
+
+        self.initialize()
+    }
+
+    fn msg_next(
+        &mut self,
+        param: CK_VOID_PTR,
+        paramlen: CK_ULONG,
+        input: &[u8],
+        output: &mut [u8],
+    ) -> Result<usize> {
+        if self.finalized {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        if !self.in_use {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        let _ = self.check_msg_params(param, paramlen)?;
+
+        if output.len() < input.len() {
+            /* This is the only non-fatal error */
+            return Err(error::Error::buf_too_small(input.len()));
+        }
+        self.update(input, output)
+    }
+}
+
+impl MechOperation for ChaCha20Operation {
+    fn mechanism(&self) -> Result<CK_MECHANISM_TYPE> {
+        Ok(self.mech)
+    }
+
+    fn finalized(&self) -> bool {
+        self.finalized
+    }
+}
+
+impl Encryption for ChaCha20Operation {
+    fn encrypt(&mut self, plain: &[u8], cipher: &mut [u8]) -> Result<usize> {
+        if self.finalized {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        let needlen = self.encryption_len(plain.len(), false)?;
+        if cipher.len() < needlen {
+            /* This is the only non-fatal error */
+            return Err(error::Error::buf_too_small(needlen));
+        }
+        let outl = self.encrypt_update(plain, cipher)?;
+        Ok(outl + self.encrypt_final(&mut cipher[outl..])?)
+    }
+
+    fn encrypt_update(
+        &mut self,
+        plain: &[u8],
+        cipher: &mut [u8],
+    ) -> Result<usize> {
+        if self.finalized {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        if !self.in_use {
+            self.in_use = true;
+            self.initialize()?;
+        }
+        if cipher.len() < plain.len() {
+            /* This is the only non-fatal error */
+            return Err(error::Error::buf_too_small(plain.len()));
+        }
+        self.update(plain, cipher)
+    }
+
+    fn encrypt_final(&mut self, cipher: &mut [u8]) -> Result<usize> {
+        if self.finalized {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        if !self.in_use {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+
+        let mut outlen = 0;
+        if self.mech == CKM_CHACHA20_POLY1305 {
+            if cipher.len() < POLY1305_TAG_SIZE {
+                /* This is the only non-fatal error */
+                return Err(error::Error::buf_too_small(POLY1305_TAG_SIZE));
+            }
+            self.get_tag(&mut cipher[..POLY1305_TAG_SIZE])?;
+            outlen = POLY1305_TAG_SIZE;
+        }
+        self.finalized = true;
+        Ok(outlen)
+    }
+
+    fn encryption_len(&mut self, data_len: usize, fin: bool) -> Result<usize> {
+        if self.finalized {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        let taglen = match self.mech {
+            CKM_CHACHA20_POLY1305 => POLY1305_TAG_SIZE,
+            _ => 0,
+        };
+        if fin {
+            if !self.in_use {
+                return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+            }
+            Ok(taglen)
+        } else {
+            Ok(data_len + taglen)
+        }
+    }
+
+    fn try_clone(&self) -> Result<Box<dyn Encryption>> {
+        Ok(Box::new(self.duplicate()?))
+    }
+}
+
+impl Decryption for ChaCha20Operation {
+    fn decrypt(&mut self, cipher: &[u8], plain: &mut [u8]) -> Result<usize> {
+        if self.finalized {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        let outlen = self.decrypt_update(cipher, plain)?;
+        Ok(outlen + self.decrypt_final(&mut plain[outlen..])?)
+    }
+
+    fn decrypt_update(
+        &mut self,
+        cipher: &[u8],
+        plain: &mut [u8],
+    ) -> Result<usize> {
+        if self.finalized {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        let outlen = self.decryption_len(cipher.len(), false)?;
+        if plain.len() < outlen {
+            /* This is the only non-fatal error */
+            return Err(error::Error::buf_too_small(outlen));
+        }
+        if !self.in_use {
+            self.in_use = true;
+            self.initialize()?;
+        }
+
+        if self.mech != CKM_CHACHA20_POLY1305 {
+            return self.update(cipher, plain);
+        }
+
+        /* the tag is appended at the end of the ciphertext, but we do
+         * not know where the ciphertext ends, so always hold back the
+         * last bytes seen until the operation is finalized */
+        let mut data = std::mem::take(&mut self.finalbuf);
+        data.extend_from_slice(cipher);
+        self.finalbuf = data.split_off(outlen);
+        self.update(&data, plain)
+    }
+
+    fn decrypt_final(&mut self, _plain: &mut [u8]) -> Result<usize> {
+        if self.finalized {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        if !self.in_use {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        if self.mech == CKM_CHACHA20_POLY1305 {
+            if self.finalbuf.len() != POLY1305_TAG_SIZE {
+                return Err(self.op_err(CKR_ENCRYPTED_DATA_LEN_RANGE));
+            }
+            let tag = std::mem::take(&mut self.finalbuf);
+            self.check_tag(&tag)?;
+        }
+        self.finalized = true;
+        Ok(0)
+    }
+
+    fn decryption_len(&mut self, data_len: usize, fin: bool) -> Result<usize> {
+        if self.finalized {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        if fin {
+            if !self.in_use {
+                return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+            }
+            /* all the plaintext is returned by the updates */
+            Ok(0)
+        } else if self.mech == CKM_CHACHA20_POLY1305 {
+            Ok((self.finalbuf.len() + data_len)
+                .saturating_sub(POLY1305_TAG_SIZE))
+        } else {
+            Ok(data_len)
+        }
+    }
+
+    fn try_clone(&self) -> Result<Box<dyn Decryption>> {
+        Ok(Box::new(self.duplicate()?))
+    }
+}
+
+impl MessageOperation for ChaCha20Operation {
+    fn busy(&self) -> bool {
+        self.in_use
+    }
+    fn finalize(&mut self) -> Result<()> {
+        if self.in_use {
+            return Err(CKR_OPERATION_ACTIVE)?;
+        }
+        self.finalized = true;
+        Ok(())
+    }
+}
+
+impl MsgEncryption for ChaCha20Operation {
+    fn msg_encrypt(
+        &mut self,
+        param: CK_VOID_PTR,
+        paramlen: CK_ULONG,
+        aad: &[u8],
+        plain: &[u8],
+        cipher: &mut [u8],
+    ) -> Result<usize> {
+        self.msg_encrypt_begin(param, paramlen, aad)?;
+        self.msg_encrypt_final(param, paramlen, plain, cipher)
+    }
+
+    fn msg_encrypt_begin(
+        &mut self,
+        param: CK_VOID_PTR,
+        paramlen: CK_ULONG,
+        aad: &[u8],
+    ) -> Result<()> {
+        if self.finalized {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        self.msg_new(CKF_MESSAGE_ENCRYPT, param, paramlen, aad)
+    }
+
+    fn msg_encrypt_next(
+        &mut self,
+        param: CK_VOID_PTR,
+        paramlen: CK_ULONG,
+        plain: &[u8],
+        cipher: &mut [u8],
+    ) -> Result<usize> {
+        self.msg_next(param, paramlen, plain, cipher)
+    }
+
+    fn msg_encrypt_final(
+        &mut self,
+        param: CK_VOID_PTR,
+        paramlen: CK_ULONG,
+        plain: &[u8],
+        cipher: &mut [u8],
+    ) -> Result<usize> {
+        let tag_ptr = self.check_msg_params(param, paramlen)?;
+        let outlen = self.msg_next(param, paramlen, plain, cipher)?;
+
+        self.in_use = false;
+
+        let tag = unsafe {
+            std::slice::from_raw_parts_mut(tag_ptr, POLY1305_TAG_SIZE)
+        };
+        if let Err(e) = self.get_tag(tag) {
+            cipher.zeroize();
+            return Err(e);
+        }
+        Ok(outlen)
+    }
+
+    fn msg_encryption_len(
+        &mut self,
+        data_len: usize,
+        _fin: bool,
+    ) -> Result<usize> {
+        if self.finalized {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        Ok(data_len)
+    }
+}
+
+impl MsgDecryption for ChaCha20Operation {
+    fn msg_decrypt(
+        &mut self,
+        param: CK_VOID_PTR,
+        paramlen: CK_ULONG,
+        aad: &[u8],
+        cipher: &[u8],
+        plain: &mut [u8],
+    ) -> Result<usize> {
+        self.msg_decrypt_begin(param, paramlen, aad)?;
+        self.msg_decrypt_final(param, paramlen, cipher, plain)
+    }
+
+    fn msg_decrypt_begin(
+        &mut self,
+        param: CK_VOID_PTR,
+        paramlen: CK_ULONG,
+        aad: &[u8],
+    ) -> Result<()> {
+        if self.finalized {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        self.msg_new(CKF_MESSAGE_DECRYPT, param, paramlen, aad)
+    }
+
+    fn msg_decrypt_next(
+        &mut self,
+        param: CK_VOID_PTR,
+        paramlen: CK_ULONG,
+        cipher: &[u8],
+        plain: &mut [u8],
+    ) -> Result<usize> {
+        self.msg_next(param, paramlen, cipher, plain)
+    }
+
+    fn msg_decrypt_final(
+        &mut self,
+        param: CK_VOID_PTR,
+        paramlen: CK_ULONG,
+        cipher: &[u8],
+        plain: &mut [u8],
+    ) -> Result<usize> {
+        let tag_ptr = self.check_msg_params(param, paramlen)?;
+        let outlen = self.msg_next(param, paramlen, cipher, plain)?;
+
+        self.in_use = false;
+
+        let tag =
+            unsafe { std::slice::from_raw_parts(tag_ptr, POLY1305_TAG_SIZE) };
+        if let Err(e) = self.check_tag(tag) {
+            plain.zeroize();
+            return Err(e);
+        }
+        Ok(outlen)
+    }
+
+    fn msg_decryption_len(
+        &mut self,
+        data_len: usize,
+        _fin: bool,
+    ) -> Result<usize> {
+        if self.finalized {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        Ok(data_len)
+    }
+}
+
+/* _key is stored in order to make sure the pointers it holds survive
+ * for as long as the operation is going on, as we can't be sure
+ * openssl is not holding live pointers to the parameters passed into
+ * the init functions */
+#[derive(Debug)]
+pub struct Poly1305Operation {
+    mech: CK_MECHANISM_TYPE,
+    finalized: bool,
+    in_use: bool,
+    _key: ChaCha20Key,
+    ctx: EvpMacCtx,
+}
+
+impl Poly1305Operation {
+    pub fn register_mechanisms(mechs: &mut Mechanisms) {
+        mechs.add_mechanism(
+            CKM_POLY1305,
+            Box::new(ChaCha20Mechanism::new(CKF_SIGN | CKF_VERIFY)),
+        );
+    }
+
+    pub fn init(
+        mech: &CK_MECHANISM,
+        key: &Object,
+    ) -> Result<Poly1305Operation> {
+        if mech.mechanism != CKM_POLY1305 {
+            return Err(CKR_MECHANISM_INVALID)?;
+        }
+        if mech.ulParameterLen != 0 {
+            return Err(CKR_ARGUMENTS_BAD)?;
+        }
+        let mackey = object_to_raw_key(key, POLY1305_KEY_SIZE)?;
+        let mut ctx = EvpMacCtx::new(name_as_char(OSSL_MAC_NAME_POLY1305))?;
+        if unsafe {
+            EVP_MAC_init(
+                ctx.as_mut_ptr(),
+                mackey.raw.as_ptr(),
+                mackey.raw.len(),
+                std::ptr::null(),
+            )
+        } != 1
+        {
+            return Err(CKR_DEVICE_ERROR)?;
+        }
+        Ok(Poly1305Operation {
+            mech: mech.mechanism,
+            finalized: false,
+            in_use: false,
+            _key: mackey,
+            ctx,
+        })
+    }
+
+    fn begin(&mut self) -> Result<()> {
+        if self.in_use {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        Ok(())
+    }
+
+    fn update(&mut self, data: &[u8]) -> Result<()> {
+        if self.finalized {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        self.in_use = true;
+
+        if unsafe {
+            EVP_MAC_update(self.ctx.as_mut_ptr(), data.as_ptr(), data.len())
+        } != 1
+        {
+            return Err(CKR_DEVICE_ERROR)?;
+        }
+        Ok(())
+    }
+
+    fn finalize(&mut self, output: &mut [u8]) -> Result<()> {
+        if self.finalized {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        /* It is valid to finalize without any update */
+        self.in_use = true;
+        self.finalized = true;
+
+        if output.len() != POLY1305_TAG_SIZE {
+            return Err(CKR_GENERAL_ERROR)?;
+        }
+        let mut outlen: usize = 0;
+        if unsafe {
+            EVP_MAC_final(
+                self.ctx.as_mut_ptr(),
+                output.as_mut_ptr(),
+                &mut outlen,
+                output.len(),
+            )
+        } != 1
+        {
+            return Err(CKR_DEVICE_ERROR)?;
+        }
+        if outlen != POLY1305_TAG_SIZE {
+            return Err(CKR_GENERAL_ERROR)?;
+        }
+        Ok(())
+    }
+}
+
+impl MechOperation for Poly1305Operation {
+    fn mechanism(&self) -> Result<CK_MECHANISM_TYPE> {
+        Ok(self.mech)
+    }
+
+    fn finalized(&self) -> bool {
+        self.finalized
+    }
+}
+
+impl Sign for Poly1305Operation {
+    fn sign(&mut self, data: &[u8], signature: &mut [u8]) -> Result<()> {
+        self.begin()?;
+        self.update(data)?;
+        self.finalize(signature)
+    }
+
+    fn sign_update(&mut self, data: &[u8]) -> Result<()> {
+        self.update(data)
+    }
+
+    fn sign_final(&mut self, signature: &mut [u8]) -> Result<()> {
+        self.finalize(signature)
+    }
+
+    fn signature_len(&self) -> Result<usize> {
+        Ok(POLY1305_TAG_SIZE)
+    }
+}
+
+impl Verify for Poly1305Operation {
+    fn verify(&mut self, data: &[u8], signature: &[u8]) -> Result<()> {
+        self.begin()?;
+        self.update(data)?;
+        self.verify_final(signature)
+    }
+
+    fn verify_update(&mut self, data: &[u8]) -> Result<()> {
+        self.update(data)
+    }
+
+    fn verify_final(&mut self, signature: &[u8]) -> Result<()> {
+        let mut verify = [0u8; POLY1305_TAG_SIZE];
+        self.finalize(&mut verify)?;
+        if !constant_time_eq(&verify, signature) {
+            return Err(CKR_SIGNATURE_INVALID)?;
+        }
+        Ok(())
+    }
+
+    fn signature_len(&self) -> Result<usize> {
+        Ok(POLY1305_TAG_SIZE)
+    }
+}
@@ -6,24 +6,34 @@ use std::ffi::{c_char, c_int, c_uint, c_void};
 
 use crate::error::Result;
 use crate::interface::*;
-use crate::kasn1::oid;
 use crate::object::Object;
 use crate::ossl::bindings::*;
 use crate::ossl::get_libctx;
 use crate::{byte_ptr, void_ptr};
 
 #[cfg(feature = "ecc")]
-use crate::ec::get_oid_from_obj;
+use crate::ec::{get_curve, get_oid_from_obj};
+#[cfg(feature = "ecc")]
+use crate::kasn1::pkcs::SpecifiedECDomain;
+#[cfg(feature = "dsa")]
+use crate::ossl::dsa;
 #[cfg(feature = "ecdsa")]
 use crate::ossl::ecdsa;
 #[cfg(feature = "eddsa")]
 use crate::ossl::eddsa;
+#[cfg(feature = "ffdh")]
+use crate::ossl::ffdh;
+#[cfg(feature = "mldsa")]
+use crate::ossl::mldsa;
+#[cfg(feature = "mlkem")]
+use crate::ossl::mlkem;
 #[cfg(feature = "ec_montgomery")]
 use crate::ossl::montgomery as ecm;
 #[cfg(feature = "rsa")]
 use crate::ossl::rsa;
+#[cfg(feature = "slhdsa")]
+use crate::ossl::slhdsa;
 
-use asn1;
 use zeroize::Zeroize;
 
 macro_rules! ptr_wrapper_struct {
@@ -154,6 +164,37 @@ ptr_wrapper!(ctx; CIPHER; Cipher);
 ptr_wrapper!(ctx_from_name; KDF; Kdf);
 ptr_wrapper!(ctx_from_name; MAC; Mac);
 
+impl EvpMdCtx {
+    pub fn try_clone(&self) -> Result<EvpMdCtx> {
+        let ctx = EvpMdCtx::new()?;
+        if unsafe { EVP_MD_CTX_copy_ex(ctx.ptr, self.ptr) } != 1 {
+            return Err(CKR_DEVICE_ERROR)?;
+        }
+        Ok(ctx)
+    }
+}
+
+impl EvpCipherCtx {
+    pub fn try_clone(&self) -> Result<EvpCipherCtx> {
+        let ctx = EvpCipherCtx::new()?;
+        if unsafe { EVP_CIPHER_CTX_copy(ctx.ptr, self.ptr) } != 1 {
+            return Err(CKR_DEVICE_ERROR)?;
+        }
+        Ok(ctx)
+    }
+}
+
+#[cfg(all(feature = "hmac", feature = "fips"))]
+impl EvpMacCtx {
+    pub fn try_clone(&self) -> Result<EvpMacCtx> {
+        let ptr = unsafe { EVP_MAC_CTX_dup(self.ptr) };
+        if ptr.is_null() {
+            return Err(CKR_DEVICE_ERROR)?;
+        }
+        Ok(EvpMacCtx { ptr: ptr })
+    }
+}
+
 #[derive(Debug)]
 pub struct EvpPkeyCtx {
     ptr: *mut EVP_PKEY_CTX,
@@ -234,6 +275,43 @@ impl EvpPkey {
         params: &OsslParam,
     ) -> Result<EvpPkey> {
         let mut ctx = EvpPkeyCtx::new(pkey_name)?;
+        Self::keygen(&mut ctx, params)
+    }
+
+    /* Generates a new key using the domain parameters of this one */
+    pub fn generate_from_domain(
+        &mut self,
+        params: &OsslParam,
+    ) -> Result<EvpPkey> {
+        let mut ctx = self.new_ctx()?;
+        Self::keygen(&mut ctx, params)
+    }
+
+    /* Generates new domain parameters */
+    pub fn paramgen(
+        pkey_name: *const c_char,
+        params: &OsslParam,
+    ) -> Result<EvpPkey> {
+        let mut ctx = EvpPkeyCtx::new(pkey_name)?;
+        let res = unsafe { EVP_PKEY_paramgen_init(ctx.as_mut_ptr()) };
+        if res != 1 {
+            return Err(CKR_DEVICE_ERROR)?;
+        }
+        let res = unsafe {
+            EVP_PKEY_CTX_set_params(ctx.as_mut_ptr(), params.as_ptr())
+        };
+        if res != 1 {
+            return Err(CKR_DEVICE_ERROR)?;
+        }
+        let mut pkey: *mut EVP_PKEY = std::ptr::null_mut();
+        let res = unsafe { EVP_PKEY_generate(ctx.as_mut_ptr(), &mut pkey) };
+        if res != 1 {
+            return Err(CKR_DEVICE_ERROR)?;
+        }
+        Ok(EvpPkey { ptr: pkey })
+    }
+
+    fn keygen(ctx: &mut EvpPkeyCtx, params: &OsslParam) -> Result<EvpPkey> {
         let res = unsafe { EVP_PKEY_keygen_init(ctx.as_mut_ptr()) };
         if res != 1 {
             return Err(CKR_DEVICE_ERROR)?;
@@ -284,14 +362,24 @@ impl EvpPkey {
         };
         let key_type = obj.get_attr_as_ulong(CKA_KEY_TYPE)?;
         let (name, params) = match key_type {
+            #[cfg(feature = "dsa")]
+            CKK_DSA => dsa::dsa_object_to_params(obj, class)?,
             #[cfg(feature = "ecdsa")]
             CKK_EC => ecdsa::ecc_object_to_params(obj, class)?,
             #[cfg(feature = "eddsa")]
             CKK_EC_EDWARDS => eddsa::eddsa_object_to_params(obj, class)?,
+            #[cfg(feature = "ffdh")]
+            CKK_DH | CKK_X9_42_DH => ffdh::ffdh_object_to_params(obj, class)?,
             #[cfg(feature = "ec_montgomery")]
             CKK_EC_MONTGOMERY => ecm::ecm_object_to_params(obj, class)?,
+            #[cfg(feature = "mldsa")]
+            CKK_ML_DSA => mldsa::mldsa_object_to_params(obj, class)?,
+            #[cfg(feature = "mlkem")]
+            CKK_ML_KEM => mlkem::mlkem_object_to_params(obj, class)?,
             #[cfg(feature = "rsa")]
             CKK_RSA => rsa::rsa_object_to_params(obj, class)?,
+            #[cfg(feature = "slhdsa")]
+            CKK_SLH_DSA => slhdsa::slhdsa_object_to_params(obj, class)?,
             _ => return Err(CKR_KEY_TYPE_INCONSISTENT)?,
         };
         Self::fromdata(name, key_class, &params)
@@ -805,54 +893,63 @@ pub fn mech_type_to_digest_name(mech: CK_MECHANISM_TYPE) -> *const c_char {
     (match mech {
         CKM_SHA1_RSA_PKCS
         | CKM_ECDSA_SHA1
+        | CKM_DSA_SHA1
         | CKM_SHA1_RSA_PKCS_PSS
         | CKM_SHA_1_HMAC
         | CKM_SHA_1_HMAC_GENERAL
         | CKM_SHA_1 => OSSL_DIGEST_NAME_SHA1.as_ptr(),
         CKM_SHA224_RSA_PKCS
         | CKM_ECDSA_SHA224
+        | CKM_DSA_SHA224
         | CKM_SHA224_RSA_PKCS_PSS
         | CKM_SHA224_HMAC
         | CKM_SHA224_HMAC_GENERAL
         | CKM_SHA224 => OSSL_DIGEST_NAME_SHA2_224.as_ptr(),
         CKM_SHA256_RSA_PKCS
         | CKM_ECDSA_SHA256
+        | CKM_DSA_SHA256
         | CKM_SHA256_RSA_PKCS_PSS
         | CKM_SHA256_HMAC
         | CKM_SHA256_HMAC_GENERAL
         | CKM_SHA256 => OSSL_DIGEST_NAME_SHA2_256.as_ptr(),
         CKM_SHA384_RSA_PKCS
         | CKM_ECDSA_SHA384
+        | CKM_DSA_SHA384
         | CKM_SHA384_RSA_PKCS_PSS
         | CKM_SHA384_HMAC
         | CKM_SHA384_HMAC_GENERAL
         | CKM_SHA384 => OSSL_DIGEST_NAME_SHA2_384.as_ptr(),
         CKM_SHA512_RSA_PKCS
         | CKM_ECDSA_SHA512
+        | CKM_DSA_SHA512
         | CKM_SHA512_RSA_PKCS_PSS
         | CKM_SHA512_HMAC
         | CKM_SHA512_HMAC_GENERAL
         | CKM_SHA512 => OSSL_DIGEST_NAME_SHA2_512.as_ptr(),
         CKM_SHA3_224_RSA_PKCS
         | CKM_ECDSA_SHA3_224
+        | CKM_DSA_SHA3_224
         | CKM_SHA3_224_RSA_PKCS_PSS
         | CKM_SHA3_224_HMAC
         | CKM_SHA3_224_HMAC_GENERAL
         | CKM_SHA3_224 => OSSL_DIGEST_NAME_SHA3_224.as_ptr(),
         CKM_SHA3_256_RSA_PKCS
         | CKM_ECDSA_SHA3_256
+        | CKM_DSA_SHA3_256
         | CKM_SHA3_256_RSA_PKCS_PSS
         | CKM_SHA3_256_HMAC
         | CKM_SHA3_256_HMAC_GENERAL
         | CKM_SHA3_256 => OSSL_DIGEST_NAME_SHA3_256.as_ptr(),
         CKM_SHA3_384_RSA_PKCS
         | CKM_ECDSA_SHA3_384
+        | CKM_DSA_SHA3_384
         | CKM_SHA3_384_RSA_PKCS_PSS
         | CKM_SHA3_384_HMAC
         | CKM_SHA3_384_HMAC_GENERAL
         | CKM_SHA3_384 => OSSL_DIGEST_NAME_SHA3_384.as_ptr(),
         CKM_SHA3_512_RSA_PKCS
         | CKM_ECDSA_SHA3_512
+        | CKM_DSA_SHA3_512
         | CKM_SHA3_512_RSA_PKCS_PSS
         | CKM_SHA3_512_HMAC
         | CKM_SHA3_512_HMAC_GENERAL
@@ -865,29 +962,122 @@ pub static EC_NAME: &[u8; 3] = b"EC\0";
 #[cfg(feature = "fips")]
 pub static ECDSA_NAME: &[u8; 6] = b"ECDSA\0";
 
-/* Curve names as used in OpenSSL */
-const NAME_SECP256R1: &[u8] = b"prime256v1\0";
-const NAME_SECP384R1: &[u8] = b"secp384r1\0";
-const NAME_SECP521R1: &[u8] = b"secp521r1\0";
-const NAME_ED25519: &[u8] = b"ED25519\0";
-const NAME_ED448: &[u8] = b"ED448\0";
-const NAME_X25519: &[u8] = b"X25519\0";
-const NAME_X448: &[u8] = b"X448\0";
-
-fn oid_to_ossl_name(oid: &asn1::ObjectIdentifier) -> Result<&'static [u8]> {
-    match oid {
-        &oid::EC_SECP256R1 => Ok(NAME_SECP256R1),
-        &oid::EC_SECP384R1 => Ok(NAME_SECP384R1),
-        &oid::EC_SECP521R1 => Ok(NAME_SECP521R1),
-        &oid::ED25519_OID => Ok(NAME_ED25519),
-        &oid::ED448_OID => Ok(NAME_ED448),
-        &oid::X25519_OID => Ok(NAME_X25519),
-        &oid::X448_OID => Ok(NAME_X448),
-        _ => Err(CKR_GENERAL_ERROR)?,
-    }
+#[cfg(feature = "ecc")]
+pub fn get_ossl_name_from_obj(key: &Object) -> Result<&'static [u8]> {
+    Ok(get_curve(&get_oid_from_obj(key)?)?.ossl_name)
 }
 
 #[cfg(feature = "ecc")]
-pub fn get_ossl_name_from_obj(key: &Object) -> Result<&'static [u8]> {
-    oid_to_ossl_name(&get_oid_from_obj(key)?)
+static EC_PRIME_FIELD: &[u8; 12] = b"prime-field\0";
+
+/* OpenSSL matches explicit domain parameters against the built-in
+ * curves, and reports the group name when one is found */
+#[cfg(feature = "ecc")]
+pub fn ec_explicit_params_to_curve_name(
+    prime: &[u8],
+    domain: &SpecifiedECDomain,
+) -> Result<String> {
+    let generator = domain.base.to_vec();
+    let mut params = OsslParam::with_capacity(7);
+    params.add_const_c_string(
+        name_as_char(OSSL_PKEY_PARAM_EC_FIELD_TYPE),
+        name_as_char(EC_PRIME_FIELD),
+    )?;
+    params.add_bn(name_as_char(OSSL_PKEY_PARAM_EC_P), &prime.to_vec())?;
+    params
+        .add_bn(name_as_char(OSSL_PKEY_PARAM_EC_A), &domain.curve.a.to_vec())?;
+    params
+        .add_bn(name_as_char(OSSL_PKEY_PARAM_EC_B), &domain.curve.b.to_vec())?;
+    params.add_octet_string(
+        name_as_char(OSSL_PKEY_PARAM_EC_GENERATOR),
+        &generator,
+    )?;
+    params.add_bn(
+        name_as_char(OSSL_PKEY_PARAM_EC_ORDER),
+        &domain.order.as_bytes().to_vec(),
+    )?;
+    if let Some(cofactor) = &domain.cofactor {
+        params.add_bn(
+            name_as_char(OSSL_PKEY_PARAM_EC_COFACTOR),
+            &cofactor.as_bytes().to_vec(),
+        )?;
+    }
+    params.finalize();
+
+    let pkey = EvpPkey::fromdata(
+        name_as_char(EC_NAME),
+        EVP_PKEY_KEY_PARAMETERS,
+        &params,
+    )?;
+    let mut buf = [0u8; 64];
+    let mut len = 0usize;
+    let res = unsafe {
+        EVP_PKEY_get_utf8_string_param(
+            pkey.as_ptr(),
+            name_as_char(OSSL_PKEY_PARAM_GROUP_NAME),
+            buf.as_mut_ptr() as *mut c_char,
+            buf.len(),
+            &mut len,
+        )
+    };
+    if res != 1 {
+        Err(CKR_CURVE_NOT_SUPPORTED)?;
+    }
+    Ok(String::from_utf8_lossy(&buf[..len]).into_owned())
+}
+
+/* Default subgroup sizes for the (L, N) pairs of FIPS 186-4, 4.2 */
+#[cfg(any(feature = "dsa", feature = "ffdh"))]
+pub fn ffc_default_qbits(pbits: usize) -> usize {
+    if pbits <= 1024 {
+        160
+    } else if pbits < 3072 {
+        224
+    } else {
+        256
+    }
+}
+
+/* Generates new Finite Field domain parameters, returns (p, q, g),
+ * q may be empty for PKCS#3 DH parameters */
+#[cfg(any(feature = "dsa", feature = "ffdh"))]
+pub fn ffc_paramgen(
+    pkey_name: *const c_char,
+    pbits: usize,
+    qbits: Option<usize>,
+) -> Result<(Vec<u8>, Vec<u8>, Vec<u8>)> {
+    let mut params = OsslParam::with_capacity(2);
+    params.add_owned_uint(
+        name_as_char(OSSL_PKEY_PARAM_FFC_PBITS),
+        c_uint::try_from(pbits)?,
+    )?;
+    if let Some(q) = qbits {
+        params.add_owned_uint(
+            name_as_char(OSSL_PKEY_PARAM_FFC_QBITS),
+            c_uint::try_from(q)?,
+        )?;
+    }
+    params.finalize();
+
+    let pkey = EvpPkey::paramgen(pkey_name, &params)?;
+
+    let mut params: *mut OSSL_PARAM = std::ptr::null_mut();
+    let res = unsafe {
+        EVP_PKEY_todata(
+            pkey.as_ptr(),
+            c_int::try_from(EVP_PKEY_KEY_PARAMETERS)?,
+            &mut params,
+        )
+    };
+    if res != 1 {
+        Err(CKR_DEVICE_ERROR)?;
+    }
+    let params = OsslParam::from_ptr(params)?;
+    let p = params.get_bn(name_as_char(OSSL_PKEY_PARAM_FFC_P))?;
+    let q = match qbits {
+        Some(_) => params.get_bn(name_as_char(OSSL_PKEY_PARAM_FFC_Q))?,
+        None => Vec::new(),
+    };
+    let g = params.get_bn(name_as_char(OSSL_PKEY_PARAM_FFC_G))?;
+    Ok((p, q, g))
 }
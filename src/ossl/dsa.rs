@@ -0,0 +1,561 @@
+// Copyright 2024 Simo Sorce
+// See LICENSE.txt file for terms
+
+use std::ffi::{c_char, c_int};
+
+use crate::attribute::Attribute;
+use crate::error::Result;
+use crate::interface::*;
+use crate::kasn1::DerEncBigUint;
+use crate::mechanism::*;
+use crate::misc::value_bits;
+use crate::object::{get_prime_bits, Object};
+use crate::ossl::bindings::*;
+use crate::ossl::common::*;
+use crate::some_or_err;
+
+#[cfg(feature = "fips")]
+use crate::ossl::fips::*;
+
+#[cfg(not(feature = "fips"))]
+use crate::ossl::get_libctx;
+
+use zeroize::Zeroize;
+
+static DSA_NAME: &[u8; 4] = b"DSA\0";
+
+/* Generates new domain parameters of the size indicated by the
+ * CKA_PRIME_BITS and CKA_SUBPRIME_BITS attributes */
+pub fn dsa_generate_domain(obj: &mut Object) -> Result<()> {
+    let pbits = get_prime_bits(obj)?;
+    let qbits = match obj.get_attr_as_ulong(CKA_SUBPRIME_BITS) {
+        Ok(b) => usize::try_from(b)?,
+        Err(e) => {
+            if !e.attr_not_found() {
+                return Err(e);
+            }
+            ffc_default_qbits(pbits)
+        }
+    };
+
+    let (p, q, g) = ffc_paramgen(name_as_char(DSA_NAME), pbits, Some(qbits))?;
+    obj.set_attr(Attribute::from_bytes(CKA_PRIME, p))?;
+    obj.set_attr(Attribute::from_ulong(CKA_SUBPRIME_BITS, value_bits(&q)))?;
+    obj.set_attr(Attribute::from_bytes(CKA_SUBPRIME, q))?;
+    obj.set_attr(Attribute::from_bytes(CKA_BASE, g))?;
+    Ok(())
+}
+
+fn add_domain_params(params: &mut OsslParam, key: &Object) -> Result<()> {
+    params.add_bn(
+        name_as_char(OSSL_PKEY_PARAM_FFC_P),
+        key.get_attr_as_bytes(CKA_PRIME)?,
+    )?;
+    params.add_bn(
+        name_as_char(OSSL_PKEY_PARAM_FFC_Q),
+        key.get_attr_as_bytes(CKA_SUBPRIME)?,
+    )?;
+    params.add_bn(
+        name_as_char(OSSL_PKEY_PARAM_FFC_G),
+        key.get_attr_as_bytes(CKA_BASE)?,
+    )?;
+    Ok(())
+}
+
+pub fn dsa_object_to_params(
+    key: &Object,
+    class: CK_OBJECT_CLASS,
+) -> Result<(*const c_char, OsslParam<'_>)> {
+    let kclass = key.get_attr_as_ulong(CKA_CLASS)?;
+    if kclass != class {
+        Err(CKR_KEY_TYPE_INCONSISTENT)?;
+    }
+    let mut params = OsslParam::with_capacity(4);
+    params.zeroize = true;
+
+    add_domain_params(&mut params, key)?;
+
+    match kclass {
+        CKO_PUBLIC_KEY => {
+            params.add_bn(
+                name_as_char(OSSL_PKEY_PARAM_PUB_KEY),
+                key.get_attr_as_bytes(CKA_VALUE)?,
+            )?;
+        }
+        CKO_PRIVATE_KEY => {
+            params.add_bn(
+                name_as_char(OSSL_PKEY_PARAM_PRIV_KEY),
+                key.get_attr_as_bytes(CKA_VALUE)?,
+            )?;
+        }
+        _ => return Err(CKR_KEY_TYPE_INCONSISTENT)?,
+    }
+
+    params.finalize();
+
+    Ok((name_as_char(DSA_NAME), params))
+}
+
+fn domain_from_object(key: &Object) -> Result<EvpPkey> {
+    let mut params = OsslParam::with_capacity(3);
+    add_domain_params(&mut params, key)?;
+    params.finalize();
+    EvpPkey::fromdata(name_as_char(DSA_NAME), EVP_PKEY_KEY_PARAMETERS, &params)
+}
+
+#[derive(asn1::Asn1Read, asn1::Asn1Write)]
+struct DsaSignature<'a> {
+    r: DerEncBigUint<'a>,
+    s: DerEncBigUint<'a>,
+}
+
+fn slice_to_sig_half(hin: &[u8], hout: &mut [u8]) -> Result<()> {
+    /* strip any leading zeros that do not fit the output */
+    let skip = hin.iter().take_while(|b| **b == 0).count();
+    let len = hin.len() - skip;
+    if len > hout.len() {
+        Err(CKR_GENERAL_ERROR)?;
+    }
+    let opad = hout.len() - len;
+    hout[..opad].fill(0);
+    hout[opad..].copy_from_slice(&hin[skip..]);
+    Ok(())
+}
+
+/// Convert OpenSSL DSA signature to PKCS #11 format
+///
+/// Same as for ECDSA, OpenSSL returns a DER encoded SEQUENCE of the r and
+/// s values, while PKCS #11 expects the two values concatenated, each
+/// padded with zeroes to the length of the subprime.
+fn ossl_to_pkcs11_signature(
+    ossl_sign: &Vec<u8>,
+    signature: &mut [u8],
+) -> Result<()> {
+    let sig = match asn1::parse_single::<DsaSignature>(ossl_sign.as_slice()) {
+        Ok(a) => a,
+        Err(_) => return Err(CKR_GENERAL_ERROR)?,
+    };
+    let bn_len = signature.len() / 2;
+    slice_to_sig_half(sig.r.as_bytes(), &mut signature[..bn_len])?;
+    slice_to_sig_half(sig.s.as_bytes(), &mut signature[bn_len..])
+}
+
+/// Convert PKCS #11 DSA signature to OpenSSL format
+///
+/// Splits the two fixed length values and wraps them in the DER encoding
+/// OpenSSL expects.
+fn pkcs11_to_ossl_signature(signature: &[u8]) -> Result<Vec<u8>> {
+    let bn_len = signature.len() / 2;
+    let sig = DsaSignature {
+        r: DerEncBigUint::new(&signature[..bn_len])?,
+        s: DerEncBigUint::new(&signature[bn_len..])?,
+    };
+    let ossl_sign = match asn1::write_single(&sig) {
+        Ok(b) => b,
+        Err(_) => return Err(CKR_GENERAL_ERROR)?,
+    };
+    Ok(ossl_sign)
+}
+
+/* The signature is made of two integers modulo the subprime */
+fn dsa_signature_len(key: &Object) -> Result<usize> {
+    let qbits =
+        usize::try_from(value_bits(key.get_attr_as_bytes(CKA_SUBPRIME)?))?;
+    if qbits == 0 {
+        Err(CKR_KEY_INDIGESTIBLE)?;
+    }
+    Ok(2 * qbits.div_ceil(8))
+}
+
+#[derive(Debug)]
+pub struct DSAOperation {
+    mech: CK_MECHANISM_TYPE,
+    output_len: usize,
+    public_key: Option<EvpPkey>,
+    private_key: Option<EvpPkey>,
+    finalized: bool,
+    in_use: bool,
+    #[cfg(not(feature = "fips"))]
+    sigctx: Option<EvpMdCtx>,
+    #[cfg(feature = "fips")]
+    sigctx: Option<ProviderSignatureCtx>,
+}
+
+impl DSAOperation {
+    pub fn sign_new(mech: &CK_MECHANISM, key: &Object) -> Result<DSAOperation> {
+        Ok(DSAOperation {
+            mech: mech.mechanism,
+            output_len: dsa_signature_len(key)?,
+            public_key: None,
+            private_key: Some(EvpPkey::privkey_from_object(key)?),
+            finalized: false,
+            in_use: false,
+            sigctx: match mech.mechanism {
+                CKM_DSA => None,
+                #[cfg(feature = "fips")]
+                _ => Some(ProviderSignatureCtx::new(name_as_char(DSA_NAME))?),
+                #[cfg(not(feature = "fips"))]
+                _ => Some(EvpMdCtx::new()?),
+            },
+        })
+    }
+
+    pub fn verify_new(
+        mech: &CK_MECHANISM,
+        key: &Object,
+    ) -> Result<DSAOperation> {
+        Ok(DSAOperation {
+            mech: mech.mechanism,
+            output_len: dsa_signature_len(key)?,
+            public_key: Some(EvpPkey::pubkey_from_object(key)?),
+            private_key: None,
+            finalized: false,
+            in_use: false,
+            sigctx: match mech.mechanism {
+                CKM_DSA => None,
+                #[cfg(feature = "fips")]
+                _ => Some(ProviderSignatureCtx::new(name_as_char(DSA_NAME))?),
+                #[cfg(not(feature = "fips"))]
+                _ => Some(EvpMdCtx::new()?),
+            },
+        })
+    }
+
+    pub fn generate_keypair(
+        pubkey: &mut Object,
+        privkey: &mut Object,
+    ) -> Result<()> {
+        let mut domain = domain_from_object(pubkey)?;
+        let evp_pkey = domain.generate_from_domain(&OsslParam::empty())?;
+
+        let mut params: *mut OSSL_PARAM = std::ptr::null_mut();
+        let res = unsafe {
+            EVP_PKEY_todata(
+                evp_pkey.as_ptr(),
+                c_int::try_from(EVP_PKEY_KEYPAIR)?,
+                &mut params,
+            )
+        };
+        if res != 1 {
+            return Err(CKR_DEVICE_ERROR)?;
+        }
+        let params = OsslParam::from_ptr(params)?;
+        /* Public Key */
+        pubkey.set_attr(Attribute::from_bytes(
+            CKA_VALUE,
+            params.get_bn(name_as_char(OSSL_PKEY_PARAM_PUB_KEY))?,
+        ))?;
+
+        /* Private Key */
+        privkey.set_attr(Attribute::from_bytes(
+            CKA_VALUE,
+            params.get_bn(name_as_char(OSSL_PKEY_PARAM_PRIV_KEY))?,
+        ))?;
+        Ok(())
+    }
+}
+
+impl MechOperation for DSAOperation {
+    fn mechanism(&self) -> Result<CK_MECHANISM_TYPE> {
+        Ok(self.mech)
+    }
+
+    fn finalized(&self) -> bool {
+        self.finalized
+    }
+}
+
+impl Sign for DSAOperation {
+    fn sign(&mut self, data: &[u8], signature: &mut [u8]) -> Result<()> {
+        if self.in_use {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        if self.finalized {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        if self.mech == CKM_DSA {
+            self.finalized = true;
+            if signature.len() != self.output_len {
+                return Err(CKR_GENERAL_ERROR)?;
+            }
+            let mut ctx = some_or_err!(mut self.private_key).new_ctx()?;
+            let res = unsafe { EVP_PKEY_sign_init(ctx.as_mut_ptr()) };
+            if res != 1 {
+                return Err(CKR_DEVICE_ERROR)?;
+            }
+
+            let mut siglen = 0usize;
+            let siglen_ptr: *mut usize = &mut siglen;
+            let res = unsafe {
+                EVP_PKEY_sign(
+                    ctx.as_mut_ptr(),
+                    std::ptr::null_mut(),
+                    siglen_ptr,
+                    data.as_ptr(),
+                    data.len(),
+                )
+            };
+            if res != 1 {
+                return Err(CKR_DEVICE_ERROR)?;
+            }
+
+            let mut ossl_sign = vec![0u8; siglen];
+            let res = unsafe {
+                EVP_PKEY_sign(
+                    ctx.as_mut_ptr(),
+                    ossl_sign.as_mut_ptr(),
+                    siglen_ptr,
+                    data.as_ptr(),
+                    data.len(),
+                )
+            };
+            if res != 1 {
+                return Err(CKR_DEVICE_ERROR)?;
+            }
+            ossl_sign.resize(siglen, 0);
+            let ret = ossl_to_pkcs11_signature(&ossl_sign, signature);
+            ossl_sign.zeroize();
+            return ret;
+        }
+        self.sign_update(data)?;
+        self.sign_final(signature)
+    }
+
+    fn sign_update(&mut self, data: &[u8]) -> Result<()> {
+        if self.finalized {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        if !self.in_use {
+            if self.mech == CKM_DSA {
+                return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+            }
+            self.in_use = true;
+
+            #[cfg(not(feature = "fips"))]
+            if unsafe {
+                EVP_DigestSignInit_ex(
+                    self.sigctx.as_mut().unwrap().as_mut_ptr(),
+                    std::ptr::null_mut(),
+                    mech_type_to_digest_name(self.mech),
+                    get_libctx(),
+                    std::ptr::null(),
+                    some_or_err!(mut self.private_key).as_mut_ptr(),
+                    std::ptr::null(),
+                )
+            } != 1
+            {
+                return Err(CKR_DEVICE_ERROR)?;
+            }
+            #[cfg(feature = "fips")]
+            self.sigctx.as_mut().unwrap().digest_sign_init(
+                mech_type_to_digest_name(self.mech),
+                some_or_err!(self.private_key),
+                std::ptr::null(),
+            )?;
+        }
+
+        #[cfg(not(feature = "fips"))]
+        {
+            let res = unsafe {
+                EVP_DigestSignUpdate(
+                    self.sigctx.as_mut().unwrap().as_mut_ptr(),
+                    data.as_ptr() as *const std::os::raw::c_void,
+                    data.len(),
+                )
+            };
+            if res != 1 {
+                return Err(CKR_DEVICE_ERROR)?;
+            }
+            Ok(())
+        }
+        #[cfg(feature = "fips")]
+        self.sigctx.as_mut().unwrap().digest_sign_update(data)
+    }
+
+    fn sign_final(&mut self, signature: &mut [u8]) -> Result<()> {
+        if !self.in_use {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        if self.finalized {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        self.finalized = true;
+
+        let mut siglen = signature.len() + 10;
+        let mut ossl_sign = vec![0u8; siglen];
+
+        #[cfg(not(feature = "fips"))]
+        {
+            let siglen_ptr = &mut siglen;
+            if unsafe {
+                EVP_DigestSignFinal(
+                    self.sigctx.as_mut().unwrap().as_mut_ptr(),
+                    ossl_sign.as_mut_ptr(),
+                    siglen_ptr,
+                )
+            } != 1
+            {
+                return Err(CKR_DEVICE_ERROR)?;
+            }
+        }
+
+        #[cfg(feature = "fips")]
+        {
+            siglen = self
+                .sigctx
+                .as_mut()
+                .unwrap()
+                .digest_sign_final(&mut ossl_sign)?;
+        }
+        if siglen > ossl_sign.len() {
+            return Err(CKR_DEVICE_ERROR)?;
+        }
+
+        /* can only shrink */
+        unsafe {
+            ossl_sign.set_len(siglen);
+        }
+
+        let ret = ossl_to_pkcs11_signature(&ossl_sign, signature);
+        ossl_sign.zeroize();
+        ret
+    }
+
+    fn signature_len(&self) -> Result<usize> {
+        Ok(self.output_len)
+    }
+}
+
+impl Verify for DSAOperation {
+    fn verify(&mut self, data: &[u8], signature: &[u8]) -> Result<()> {
+        if self.in_use {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        if self.finalized {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        if self.mech == CKM_DSA {
+            if signature.len() != self.output_len {
+                return Err(CKR_GENERAL_ERROR)?; // already checked in fn_verify
+            }
+            let mut ctx = some_or_err!(mut self.public_key).new_ctx()?;
+            let res = unsafe { EVP_PKEY_verify_init(ctx.as_mut_ptr()) };
+            if res != 1 {
+                return Err(CKR_DEVICE_ERROR)?;
+            }
+
+            // convert PKCS #11 signature to OpenSSL format
+            let mut ossl_sign = pkcs11_to_ossl_signature(signature)?;
+
+            self.finalized = true;
+
+            let res = unsafe {
+                EVP_PKEY_verify(
+                    ctx.as_mut_ptr(),
+                    ossl_sign.as_ptr(),
+                    ossl_sign.len(),
+                    data.as_ptr(),
+                    data.len(),
+                )
+            };
+            if res != 1 {
+                return Err(CKR_SIGNATURE_INVALID)?;
+            }
+            ossl_sign.zeroize();
+            return Ok(());
+        }
+        self.verify_update(data)?;
+        self.verify_final(signature)
+    }
+
+    fn verify_update(&mut self, data: &[u8]) -> Result<()> {
+        if self.finalized {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        if !self.in_use {
+            if self.mech == CKM_DSA {
+                return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+            }
+            self.in_use = true;
+
+            #[cfg(not(feature = "fips"))]
+            if unsafe {
+                EVP_DigestVerifyInit_ex(
+                    self.sigctx.as_mut().unwrap().as_mut_ptr(),
+                    std::ptr::null_mut(),
+                    mech_type_to_digest_name(self.mech),
+                    get_libctx(),
+                    std::ptr::null(),
+                    some_or_err!(mut self.public_key).as_mut_ptr(),
+                    std::ptr::null(),
+                )
+            } != 1
+            {
+                return Err(CKR_DEVICE_ERROR)?;
+            }
+            #[cfg(feature = "fips")]
+            self.sigctx.as_mut().unwrap().digest_verify_init(
+                mech_type_to_digest_name(self.mech),
+                some_or_err!(self.public_key),
+                std::ptr::null(),
+            )?;
+        }
+
+        #[cfg(not(feature = "fips"))]
+        {
+            let res = unsafe {
+                EVP_DigestVerifyUpdate(
+                    self.sigctx.as_mut().unwrap().as_mut_ptr(),
+                    data.as_ptr() as *const std::os::raw::c_void,
+                    data.len(),
+                )
+            };
+            if res != 1 {
+                return Err(CKR_DEVICE_ERROR)?;
+            }
+            Ok(())
+        }
+
+        #[cfg(feature = "fips")]
+        self.sigctx.as_mut().unwrap().digest_verify_update(data)
+    }
+
+    fn verify_final(&mut self, signature: &[u8]) -> Result<()> {
+        if !self.in_use {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        if self.finalized {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+
+        // convert PKCS #11 signature to OpenSSL format
+        let mut ossl_sign = pkcs11_to_ossl_signature(signature)?;
+
+        self.finalized = true;
+
+        #[cfg(not(feature = "fips"))]
+        if unsafe {
+            EVP_DigestVerifyFinal(
+                self.sigctx.as_mut().unwrap().as_mut_ptr(),
+                ossl_sign.as_ptr(),
+                ossl_sign.len(),
+            )
+        } != 1
+        {
+            return Err(CKR_SIGNATURE_INVALID)?;
+        }
+
+        #[cfg(feature = "fips")]
+        self.sigctx
+            .as_mut()
+            .unwrap()
+            .digest_verify_final(&ossl_sign)?;
+
+        ossl_sign.zeroize();
+        Ok(())
+    }
+
+    fn signature_len(&self) -> Result<usize> {
+        Ok(self.output_len)
+    }
+}
@@ -147,7 +147,7 @@ impl EccOperation {
         Box::new(EccMechanism::new(
             CK_ULONG::try_from(MIN_EC_SIZE_BITS).unwrap(),
             CK_ULONG::try_from(MAX_EC_SIZE_BITS).unwrap(),
-            CKF_SIGN | CKF_VERIFY,
+            CKF_SIGN | CKF_VERIFY | CKF_MESSAGE_SIGN | CKF_MESSAGE_VERIFY,
         ))
     }
 
@@ -573,3 +573,27 @@ impl Verify for EccOperation {
         Ok(self.output_len)
     }
 }
+
+impl MessageOperation for EccOperation {
+    fn busy(&self) -> bool {
+        self.in_use
+    }
+    fn finalize(&mut self) -> Result<()> {
+        if self.in_use {
+            return Err(CKR_OPERATION_ACTIVE)?;
+        }
+        self.finalized = true;
+        Ok(())
+    }
+
+    /* Keys and contexts are reused across messages */
+    fn msg_reset(&mut self) -> Result<()> {
+        self.finalized = false;
+        self.in_use = false;
+        Ok(())
+    }
+}
+
+impl MsgSign for EccOperation {}
+
+impl MsgVerify for EccOperation {}
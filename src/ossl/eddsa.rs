@@ -19,6 +19,8 @@ use crate::ossl::fips::*;
 #[cfg(not(feature = "fips"))]
 use crate::ossl::get_libctx;
 
+use zeroize::Zeroize;
+
 pub const OUTLEN_ED25519: usize = 64;
 pub const OUTLEN_ED448: usize = 114;
 
@@ -122,6 +124,7 @@ pub struct EddsaOperation {
     public_key: Option<EvpPkey>,
     private_key: Option<EvpPkey>,
     params: EddsaParams,
+    init_params: Option<EddsaParams>,
     data: Vec<u8>,
     finalized: bool,
     in_use: bool,
@@ -131,6 +134,12 @@ pub struct EddsaOperation {
     sigctx: Option<ProviderSignatureCtx>,
 }
 
+impl Drop for EddsaOperation {
+    fn drop(&mut self) {
+        self.data.zeroize();
+    }
+}
+
 impl EddsaOperation {
     pub fn sign_new(
         mech: &CK_MECHANISM,
@@ -145,6 +154,7 @@ impl EddsaOperation {
             public_key: None,
             private_key: Some(privkey),
             params: parse_params(mech, outlen)?,
+            init_params: None,
             data: Vec::new(),
             finalized: false,
             in_use: false,
@@ -165,6 +175,7 @@ impl EddsaOperation {
             public_key: Some(pubkey),
             private_key: None,
             params: parse_params(mech, outlen)?,
+            init_params: None,
             data: Vec::new(),
             finalized: false,
             in_use: false,
@@ -445,3 +456,54 @@ impl Verify for EddsaOperation {
         Ok(self.output_len)
     }
 }
+
+impl MessageOperation for EddsaOperation {
+    fn busy(&self) -> bool {
+        self.in_use
+    }
+    fn finalize(&mut self) -> Result<()> {
+        if self.in_use {
+            return Err(CKR_OPERATION_ACTIVE)?;
+        }
+        self.finalized = true;
+        Ok(())
+    }
+
+    /* Per-message parameters are used in place of the ones given at
+     * initialization for the current message only */
+    fn msg_params(
+        &mut self,
+        param: CK_VOID_PTR,
+        paramlen: CK_ULONG,
+    ) -> Result<()> {
+        if param.is_null() {
+            if paramlen != 0 {
+                return Err(CKR_ARGUMENTS_BAD)?;
+            }
+            return Ok(());
+        }
+        let mech = CK_MECHANISM {
+            mechanism: self.mech,
+            pParameter: param,
+            ulParameterLen: paramlen,
+        };
+        let params = parse_params(&mech, self.output_len)?;
+        self.init_params = Some(std::mem::replace(&mut self.params, params));
+        Ok(())
+    }
+
+    /* Keys and contexts are reused across messages */
+    fn msg_reset(&mut self) -> Result<()> {
+        self.finalized = false;
+        self.in_use = false;
+        self.data.zeroize();
+        if let Some(params) = self.init_params.take() {
+            self.params = params;
+        }
+        Ok(())
+    }
+}
+
+impl MsgSign for EddsaOperation {}
+
+impl MsgVerify for EddsaOperation {}
@@ -0,0 +1,572 @@
+// Copyright 2024 Simo Sorce
+// See LICENSE.txt file for terms
+
+use std::ffi::{c_char, c_int, c_uint, c_void};
+
+use crate::attribute::{Attribute, CkAttrs};
+use crate::error::Result;
+use crate::interface::*;
+use crate::kasn1::pkcs::{KeySpecificInfo, X942OtherInfo};
+use crate::mechanism::*;
+use crate::misc::value_bits;
+use crate::object::{
+    default_key_attributes, get_prime_bits, Object, ObjectFactories,
+};
+use crate::ossl::bindings::*;
+use crate::ossl::common::*;
+
+#[cfg(feature = "fips")]
+use crate::ossl::fips::*;
+
+use zeroize::Zeroize;
+
+static DH_NAME: &[u8; 3] = b"DH\0";
+static DHX_NAME: &[u8; 4] = b"DHX\0";
+
+/* SP 800-56A Rev. 3 only approves the safe-prime groups defined in
+ * RFC 3526 and RFC 7919 */
+#[cfg(feature = "fips")]
+const APPROVED_GROUPS: [&str; 10] = [
+    "ffdhe2048",
+    "ffdhe3072",
+    "ffdhe4096",
+    "ffdhe6144",
+    "ffdhe8192",
+    "modp_2048",
+    "modp_3072",
+    "modp_4096",
+    "modp_6144",
+    "modp_8192",
+];
+
+fn add_domain_params(
+    params: &mut OsslParam,
+    key: &Object,
+) -> Result<&'static [u8]> {
+    let name: &'static [u8] = match key.get_attr_as_ulong(CKA_KEY_TYPE)? {
+        CKK_DH => DH_NAME,
+        CKK_X9_42_DH => {
+            params.add_bn(
+                name_as_char(OSSL_PKEY_PARAM_FFC_Q),
+                key.get_attr_as_bytes(CKA_SUBPRIME)?,
+            )?;
+            DHX_NAME
+        }
+        _ => return Err(CKR_KEY_TYPE_INCONSISTENT)?,
+    };
+    params.add_bn(
+        name_as_char(OSSL_PKEY_PARAM_FFC_P),
+        key.get_attr_as_bytes(CKA_PRIME)?,
+    )?;
+    params.add_bn(
+        name_as_char(OSSL_PKEY_PARAM_FFC_G),
+        key.get_attr_as_bytes(CKA_BASE)?,
+    )?;
+    Ok(name)
+}
+
+pub fn ffdh_object_to_params(
+    key: &Object,
+    class: CK_OBJECT_CLASS,
+) -> Result<(*const c_char, OsslParam<'_>)> {
+    let kclass = key.get_attr_as_ulong(CKA_CLASS)?;
+    if kclass != class {
+        Err(CKR_KEY_TYPE_INCONSISTENT)?;
+    }
+    let mut params = OsslParam::with_capacity(4);
+    params.zeroize = true;
+
+    let name = add_domain_params(&mut params, key)?;
+
+    match kclass {
+        CKO_PUBLIC_KEY => {
+            params.add_bn(
+                name_as_char(OSSL_PKEY_PARAM_PUB_KEY),
+                key.get_attr_as_bytes(CKA_VALUE)?,
+            )?;
+        }
+        CKO_PRIVATE_KEY => {
+            params.add_bn(
+                name_as_char(OSSL_PKEY_PARAM_PRIV_KEY),
+                key.get_attr_as_bytes(CKA_VALUE)?,
+            )?;
+        }
+        _ => return Err(CKR_KEY_TYPE_INCONSISTENT)?,
+    }
+
+    params.finalize();
+
+    Ok((name_as_char(name), params))
+}
+
+/* Generates new domain parameters of the size indicated by the
+ * CKA_PRIME_BITS (and CKA_SUBPRIME_BITS for X9.42) attributes */
+pub fn ffdh_generate_domain(obj: &mut Object) -> Result<()> {
+    let pbits = get_prime_bits(obj)?;
+    let (name, qbits) = match obj.get_attr_as_ulong(CKA_KEY_TYPE)? {
+        CKK_DH => (DH_NAME.as_slice(), None),
+        CKK_X9_42_DH => {
+            let qbits = match obj.get_attr_as_ulong(CKA_SUBPRIME_BITS) {
+                Ok(b) => usize::try_from(b)?,
+                Err(e) => {
+                    if !e.attr_not_found() {
+                        return Err(e);
+                    }
+                    ffc_default_qbits(pbits)
+                }
+            };
+            (DHX_NAME.as_slice(), Some(qbits))
+        }
+        _ => return Err(CKR_KEY_TYPE_INCONSISTENT)?,
+    };
+
+    let (p, q, g) = ffc_paramgen(name_as_char(name), pbits, qbits)?;
+    obj.set_attr(Attribute::from_bytes(CKA_PRIME, p))?;
+    obj.set_attr(Attribute::from_bytes(CKA_BASE, g))?;
+    if qbits.is_some() {
+        obj.set_attr(Attribute::from_ulong(CKA_SUBPRIME_BITS, value_bits(&q)))?;
+        obj.set_attr(Attribute::from_bytes(CKA_SUBPRIME, q))?;
+    }
+    Ok(())
+}
+
+fn domain_from_object(key: &Object) -> Result<EvpPkey> {
+    let mut params = OsslParam::with_capacity(3);
+    let name = add_domain_params(&mut params, key)?;
+    params.finalize();
+    EvpPkey::fromdata(name_as_char(name), EVP_PKEY_KEY_PARAMETERS, &params)
+}
+
+/* OpenSSL recognizes the well known safe-prime groups from the
+ * domain parameters and uses the group properties for key
+ * generation and validation */
+#[cfg(feature = "fips")]
+fn ffdh_group_name(key: &Object) -> Result<Option<String>> {
+    let pkey = domain_from_object(key)?;
+    let mut buf = [0u8; 32];
+    let mut len = 0usize;
+    let res = unsafe {
+        EVP_PKEY_get_utf8_string_param(
+            pkey.as_ptr(),
+            name_as_char(OSSL_PKEY_PARAM_GROUP_NAME),
+            buf.as_mut_ptr() as *mut c_char,
+            buf.len(),
+            &mut len,
+        )
+    };
+    if res != 1 {
+        return Ok(None);
+    }
+    Ok(Some(String::from_utf8_lossy(&buf[..len]).into_owned()))
+}
+
+#[cfg(feature = "fips")]
+pub fn ffdh_approved_group_bits(key: &Object) -> Option<usize> {
+    match ffdh_group_name(key) {
+        Ok(Some(name)) => {
+            if !APPROVED_GROUPS.contains(&name.as_str()) {
+                return None;
+            }
+        }
+        _ => return None,
+    }
+    match key.get_attr_as_bytes(CKA_PRIME) {
+        Ok(p) => Some(p.len() * 8),
+        Err(_) => None,
+    }
+}
+
+fn kdf_type_to_hash_mech(
+    kdf: CK_X9_42_DH_KDF_TYPE,
+) -> Result<CK_MECHANISM_TYPE> {
+    match kdf {
+        CKD_SHA1_KDF_ASN1 | CKD_SHA1_KDF_CONCATENATE | CKD_SHA1_KDF_SP800 => {
+            Ok(CKM_SHA_1)
+        }
+        CKD_SHA224_KDF_SP800 => Ok(CKM_SHA224),
+        CKD_SHA256_KDF_SP800 => Ok(CKM_SHA256),
+        CKD_SHA384_KDF_SP800 => Ok(CKM_SHA384),
+        CKD_SHA512_KDF_SP800 => Ok(CKM_SHA512),
+        CKD_SHA3_224_KDF_SP800 => Ok(CKM_SHA3_224),
+        CKD_SHA3_256_KDF_SP800 => Ok(CKM_SHA3_256),
+        CKD_SHA3_384_KDF_SP800 => Ok(CKM_SHA3_384),
+        CKD_SHA3_512_KDF_SP800 => Ok(CKM_SHA3_512),
+        _ => Err(CKR_MECHANISM_PARAM_INVALID)?,
+    }
+}
+
+/* ANSI X9.42 ASN.1 KDF (RFC 2631, 2.1.2): the application provides the
+ * DER encoded OtherInfo, and the counter in it is replaced for each
+ * block of output */
+fn x942_asn1_kdf(
+    secret: &[u8],
+    other_info: &[u8],
+    keylen: usize,
+) -> Result<Vec<u8>> {
+    let info = match asn1::parse_single::<X942OtherInfo>(other_info) {
+        Ok(i) => i,
+        Err(_) => return Err(CKR_MECHANISM_PARAM_INVALID)?,
+    };
+    if info.key_info.counter.len() != 4 {
+        Err(CKR_MECHANISM_PARAM_INVALID)?;
+    }
+    let md = EvpMd::new(mech_type_to_digest_name(CKM_SHA_1))?;
+    let mut ctx = EvpMdCtx::new()?;
+    let md_size = usize::try_from(EVP_MAX_MD_SIZE)?;
+    let mut dkm = Vec::<u8>::with_capacity(keylen + md_size);
+    let mut counter: u32 = 1;
+    while dkm.len() < keylen {
+        let cnt = counter.to_be_bytes();
+        let block = asn1::write_single(&X942OtherInfo {
+            key_info: KeySpecificInfo {
+                algorithm: info.key_info.algorithm.clone(),
+                counter: &cnt,
+            },
+            party_a_info: info.party_a_info,
+            supp_pub_info: asn1::Explicit::new(*info.supp_pub_info.as_inner()),
+        })?;
+        if unsafe {
+            EVP_DigestInit_ex(
+                ctx.as_mut_ptr(),
+                md.as_ptr(),
+                std::ptr::null_mut(),
+            )
+        } != 1
+        {
+            Err(CKR_DEVICE_ERROR)?;
+        }
+        for data in [secret, block.as_slice()] {
+            if unsafe {
+                EVP_DigestUpdate(
+                    ctx.as_mut_ptr(),
+                    data.as_ptr() as *const c_void,
+                    data.len(),
+                )
+            } != 1
+            {
+                Err(CKR_DEVICE_ERROR)?;
+            }
+        }
+        let start = dkm.len();
+        let mut outlen: c_uint = 0;
+        dkm.resize(start + md_size, 0);
+        if unsafe {
+            EVP_DigestFinal_ex(
+                ctx.as_mut_ptr(),
+                dkm[start..].as_mut_ptr(),
+                &mut outlen,
+            )
+        } != 1
+        {
+            dkm.zeroize();
+            Err(CKR_DEVICE_ERROR)?;
+        }
+        dkm.truncate(start + usize::try_from(outlen)?);
+        counter += 1;
+    }
+    dkm[keylen..].zeroize();
+    dkm.truncate(keylen);
+    Ok(dkm)
+}
+
+#[derive(Debug)]
+pub struct FFDHOperation {
+    mech: CK_MECHANISM_TYPE,
+    kdf: CK_X9_42_DH_KDF_TYPE,
+    public: Vec<u8>,
+    other_info: Vec<u8>,
+    finalized: bool,
+    #[cfg(feature = "fips")]
+    fips_approved: Option<bool>,
+}
+
+impl FFDHOperation {
+    pub fn derive_new(
+        mechanism: CK_MECHANISM_TYPE,
+        kdf: CK_X9_42_DH_KDF_TYPE,
+        public: Vec<u8>,
+        other_info: Vec<u8>,
+    ) -> Result<FFDHOperation> {
+        if public.is_empty() {
+            return Err(CKR_MECHANISM_PARAM_INVALID)?;
+        }
+        if kdf == CKD_NULL {
+            if !other_info.is_empty() {
+                return Err(CKR_MECHANISM_PARAM_INVALID)?;
+            }
+        } else {
+            kdf_type_to_hash_mech(kdf)?;
+        }
+        Ok(FFDHOperation {
+            mech: mechanism,
+            kdf,
+            public,
+            other_info,
+            finalized: false,
+            #[cfg(feature = "fips")]
+            fips_approved: None,
+        })
+    }
+
+    pub fn generate_keypair(
+        pubkey: &mut Object,
+        privkey: &mut Object,
+    ) -> Result<()> {
+        let mut domain = domain_from_object(pubkey)?;
+
+        let mut params = OsslParam::with_capacity(1);
+        match privkey.get_attr_as_ulong(CKA_VALUE_BITS) {
+            Ok(bits) => params.add_owned_int(
+                name_as_char(OSSL_PKEY_PARAM_DH_PRIV_LEN),
+                c_int::try_from(bits)?,
+            )?,
+            Err(e) => {
+                if !e.attr_not_found() {
+                    return Err(e);
+                }
+            }
+        }
+        params.finalize();
+
+        let evp_pkey = domain.generate_from_domain(&params)?;
+
+        let mut params: *mut OSSL_PARAM = std::ptr::null_mut();
+        let res = unsafe {
+            EVP_PKEY_todata(
+                evp_pkey.as_ptr(),
+                c_int::try_from(EVP_PKEY_KEYPAIR)?,
+                &mut params,
+            )
+        };
+        if res != 1 {
+            return Err(CKR_DEVICE_ERROR)?;
+        }
+        let params = OsslParam::from_ptr(params)?;
+        /* Public Key */
+        pubkey.set_attr(Attribute::from_bytes(
+            CKA_VALUE,
+            params.get_bn(name_as_char(OSSL_PKEY_PARAM_PUB_KEY))?,
+        ))?;
+
+        /* Private Key */
+        let value = params.get_bn(name_as_char(OSSL_PKEY_PARAM_PRIV_KEY))?;
+        if privkey.get_attr_as_ulong(CKA_KEY_TYPE)? == CKK_DH {
+            privkey.set_attr(Attribute::from_ulong(
+                CKA_VALUE_BITS,
+                value_bits(&value),
+            ))?;
+        }
+        privkey.set_attr(Attribute::from_bytes(CKA_VALUE, value))?;
+        Ok(())
+    }
+
+    fn kdf_derive(
+        &mut self,
+        secret: &Vec<u8>,
+        keylen: usize,
+    ) -> Result<Vec<u8>> {
+        let kdf_name = match self.kdf {
+            CKD_SHA1_KDF_ASN1 => {
+                return x942_asn1_kdf(secret, &self.other_info, keylen)
+            }
+            CKD_SHA1_KDF_CONCATENATE => OSSL_KDF_NAME_X963KDF.as_ptr(),
+            _ => OSSL_KDF_NAME_SSKDF.as_ptr(),
+        };
+
+        let mut params = OsslParam::with_capacity(3);
+        params.zeroize = true;
+        params.add_octet_string(name_as_char(OSSL_KDF_PARAM_KEY), secret)?;
+        params.add_const_c_string(
+            name_as_char(OSSL_KDF_PARAM_DIGEST),
+            mech_type_to_digest_name(kdf_type_to_hash_mech(self.kdf)?),
+        )?;
+        if !self.other_info.is_empty() {
+            params.add_octet_string(
+                name_as_char(OSSL_KDF_PARAM_INFO),
+                &self.other_info,
+            )?;
+        }
+        params.finalize();
+
+        let mut kctx = EvpKdfCtx::new(kdf_name as *const c_char)?;
+        let mut dkm = vec![0u8; keylen];
+        let res = unsafe {
+            EVP_KDF_derive(
+                kctx.as_mut_ptr(),
+                dkm.as_mut_ptr(),
+                dkm.len(),
+                params.as_ptr(),
+            )
+        };
+        if res != 1 {
+            return Err(CKR_DEVICE_ERROR)?;
+        }
+
+        #[cfg(feature = "fips")]
+        {
+            self.fips_approved = match (
+                self.fips_approved,
+                check_kdf_fips_indicators(&mut kctx)?,
+            ) {
+                (Some(false), _) | (_, Some(false)) => Some(false),
+                (a, None) => a,
+                (_, b) => b,
+            };
+        }
+
+        Ok(dkm)
+    }
+}
+
+impl MechOperation for FFDHOperation {
+    fn mechanism(&self) -> Result<CK_MECHANISM_TYPE> {
+        Ok(self.mech)
+    }
+
+    fn finalized(&self) -> bool {
+        self.finalized
+    }
+
+    #[cfg(feature = "fips")]
+    fn fips_approved(&self) -> Option<bool> {
+        self.fips_approved
+    }
+}
+
+impl Derive for FFDHOperation {
+    fn derive(
+        &mut self,
+        key: &Object,
+        template: &[CK_ATTRIBUTE],
+        _mechanisms: &Mechanisms,
+        objfactories: &ObjectFactories,
+    ) -> Result<Vec<Object>> {
+        if self.finalized {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        self.finalized = true;
+
+        let key_type = match self.mech {
+            CKM_DH_PKCS_DERIVE => CKK_DH,
+            CKM_X9_42_DH_DERIVE => CKK_X9_42_DH,
+            _ => return Err(CKR_MECHANISM_INVALID)?,
+        };
+        if key.get_attr_as_ulong(CKA_KEY_TYPE)? != key_type {
+            return Err(CKR_KEY_TYPE_INCONSISTENT)?;
+        }
+
+        #[cfg(feature = "fips")]
+        {
+            self.fips_approved = Some(ffdh_approved_group_bits(key).is_some());
+        }
+
+        let mut pkey = EvpPkey::privkey_from_object(key)?;
+
+        /* Import peer key, it uses the same domain parameters */
+        let mut params = OsslParam::with_capacity(4);
+        let name = add_domain_params(&mut params, key)?;
+        params.add_bn(name_as_char(OSSL_PKEY_PARAM_PUB_KEY), &self.public)?;
+        params.finalize();
+        let mut peer = match EvpPkey::fromdata(
+            name_as_char(name),
+            EVP_PKEY_PUBLIC_KEY,
+            &params,
+        ) {
+            Ok(p) => p,
+            Err(_) => return Err(CKR_MECHANISM_PARAM_INVALID)?,
+        };
+
+        let factory =
+            objfactories.get_obj_factory_from_key_template(template)?;
+
+        /* the raw shared secret has the length of the prime */
+        let raw_max = key.get_attr_as_bytes(CKA_PRIME)?.len();
+        let keylen = match template.iter().find(|x| x.type_ == CKA_VALUE_LEN) {
+            Some(a) => {
+                let value_len = usize::try_from(a.to_ulong()?)?;
+                if self.kdf == CKD_NULL && value_len > raw_max {
+                    return Err(CKR_TEMPLATE_INCONSISTENT)?;
+                }
+                value_len
+            }
+            None => {
+                if self.kdf != CKD_NULL {
+                    return Err(CKR_TEMPLATE_INCONSISTENT)?;
+                }
+                match factory
+                    .as_secret_key_factory()?
+                    .recommend_key_size(raw_max)
+                {
+                    Ok(len) => len,
+                    Err(_) => return Err(CKR_TEMPLATE_INCONSISTENT)?,
+                }
+            }
+        };
+
+        /* keep the leading zeros, the secret is always as long as p */
+        let mut params = OsslParam::with_capacity(1);
+        params.add_owned_uint(name_as_char(OSSL_EXCHANGE_PARAM_PAD), 1)?;
+        params.finalize();
+
+        let mut ctx = pkey.new_ctx()?;
+        let res = unsafe {
+            EVP_PKEY_derive_init_ex(ctx.as_mut_ptr(), params.as_ptr())
+        };
+        if res != 1 {
+            return Err(CKR_DEVICE_ERROR)?;
+        }
+        let res = unsafe {
+            EVP_PKEY_derive_set_peer(ctx.as_mut_ptr(), peer.as_mut_ptr())
+        };
+        if res != 1 {
+            /* the peer public value failed validation */
+            return Err(CKR_MECHANISM_PARAM_INVALID)?;
+        }
+
+        let mut secret_len = 0usize;
+        let res = unsafe {
+            EVP_PKEY_derive(
+                ctx.as_mut_ptr(),
+                std::ptr::null_mut(),
+                &mut secret_len,
+            )
+        };
+        if res != 1 {
+            return Err(CKR_DEVICE_ERROR)?;
+        }
+        let mut secret = vec![0u8; secret_len];
+        let res = unsafe {
+            EVP_PKEY_derive(
+                ctx.as_mut_ptr(),
+                secret.as_mut_ptr(),
+                &mut secret_len,
+            )
+        };
+        if res != 1 {
+            secret.zeroize();
+            return Err(CKR_DEVICE_ERROR)?;
+        }
+        secret.truncate(secret_len);
+
+        let value = if self.kdf == CKD_NULL {
+            if secret_len < keylen {
+                secret.zeroize();
+                return Err(CKR_TEMPLATE_INCONSISTENT)?;
+            }
+            secret[(secret_len - keylen)..].to_vec()
+        } else {
+            let dkm = self.kdf_derive(&secret, keylen);
+            secret.zeroize();
+            dkm?
+        };
+        secret.zeroize();
+
+        let mut tmpl = CkAttrs::from(template);
+        tmpl.add_vec(CKA_VALUE, value)?;
+        tmpl.zeroize = true;
+        let mut obj = factory.create(tmpl.as_slice())?;
+
+        default_key_attributes(&mut obj, self.mech)?;
+        Ok(vec![obj])
+    }
+}
@@ -164,4 +164,14 @@ impl Digest for HashOperation {
         let len = unsafe { EVP_MD_get_size(self.state.md.as_ptr()) };
         Ok(usize::try_from(len)?)
     }
+
+    fn try_clone(&self) -> Result<Box<dyn Digest>> {
+        let mut op = HashOperation::new(self.mech)?;
+        if self.in_use {
+            op.state.ctx = self.state.ctx.try_clone()?;
+        }
+        op.finalized = self.finalized;
+        op.in_use = self.in_use;
+        Ok(Box::new(op))
+    }
 }
@@ -141,6 +141,21 @@ impl HMACOperation {
         self.fips_approved = None;
         Ok(())
     }
+
+    fn duplicate(&self) -> Result<HMACOperation> {
+        Ok(HMACOperation {
+            mech: self.mech,
+            finalized: self.finalized,
+            in_use: self.in_use,
+            outputlen: self.outputlen,
+            maclen: self.maclen,
+            key: HmacKey {
+                raw: self.key.raw.clone(),
+            },
+            ctx: self.ctx.try_clone()?,
+            fips_approved: self.fips_approved,
+        })
+    }
 }
 
 impl MechOperation for HMACOperation {
@@ -197,6 +212,10 @@ impl Sign for HMACOperation {
     fn signature_len(&self) -> Result<usize> {
         Ok(self.outputlen)
     }
+
+    fn try_clone(&self) -> Result<Box<dyn Sign>> {
+        Ok(Box::new(self.duplicate()?))
+    }
 }
 
 impl Verify for HMACOperation {
@@ -222,4 +241,34 @@ impl Verify for HMACOperation {
     fn signature_len(&self) -> Result<usize> {
         Ok(self.outputlen)
     }
+
+    fn try_clone(&self) -> Result<Box<dyn Verify>> {
+        Ok(Box::new(self.duplicate()?))
+    }
 }
+
+impl MessageOperation for HMACOperation {
+    fn busy(&self) -> bool {
+        self.in_use
+    }
+    fn finalize(&mut self) -> Result<()> {
+        if self.in_use {
+            return Err(CKR_OPERATION_ACTIVE)?;
+        }
+        self.finalized = true;
+        Ok(())
+    }
+
+    /* Prepares for the next message, the FIPS indicator of the
+     * last message is retained */
+    fn msg_reset(&mut self) -> Result<()> {
+        let approved = self.fips_approved;
+        self.reinit()?;
+        self.fips_approved = approved;
+        Ok(())
+    }
+}
+
+impl MsgSign for HMACOperation {}
+
+impl MsgVerify for HMACOperation {}
@@ -0,0 +1,726 @@
+// Copyright 2024 Simo Sorce
+// See LICENSE.txt file for terms
+
+use std::ffi::{c_uint, c_void};
+
+use crate::attribute::Attribute;
+use crate::error::Result;
+use crate::get_random_data;
+use crate::hss::*;
+use crate::interface::*;
+use crate::mechanism::*;
+use crate::object::Object;
+use crate::ossl::bindings::*;
+use crate::ossl::common::*;
+
+use zeroize::Zeroize;
+
+/* RFC 8554 Section 7.1 domain separation parameters */
+const D_PBLC: [u8; 2] = [0x80, 0x80];
+const D_MESG: [u8; 2] = [0x81, 0x81];
+const D_LEAF: [u8; 2] = [0x82, 0x82];
+const D_INTR: [u8; 2] = [0x83, 0x83];
+
+/* The LM-OTS private keys are derived from the tree seed as in RFC 8554
+ * Appendix A: H(I || u32str(q) || u16str(i) || u8str(0xff) || SEED).
+ * The seed and identifier of the lower level trees, and the randomizer
+ * used to sign their public keys, are derived the same way using values
+ * of i that can never address an LM-OTS chain. The randomizer needs to
+ * be deterministic because the signature of a lower level public key is
+ * recomputed for every message signed with that tree */
+const D_CHILD_SEED: [u8; 2] = [0xff, 0xfd];
+const D_CHILD_I: [u8; 2] = [0xff, 0xfe];
+const D_CHILD_C: [u8; 2] = [0xff, 0xff];
+const D_PRIV: [u8; 1] = [0xff];
+
+type Node = [u8; HSS_N];
+
+struct Sha256 {
+    md: EvpMd,
+    ctx: EvpMdCtx,
+}
+
+impl Sha256 {
+    fn new() -> Result<Sha256> {
+        Ok(Sha256 {
+            md: EvpMd::new(name_as_char(OSSL_DIGEST_NAME_SHA2_256))?,
+            ctx: EvpMdCtx::new()?,
+        })
+    }
+
+    fn hash(&mut self, parts: &[&[u8]]) -> Result<Node> {
+        let mut out: Node = [0u8; HSS_N];
+        let mut outlen: c_uint = 0;
+        if unsafe {
+            EVP_DigestInit_ex(
+                self.ctx.as_mut_ptr(),
+                self.md.as_ptr(),
+                std::ptr::null_mut(),
+            )
+        } != 1
+        {
+            return Err(CKR_DEVICE_ERROR)?;
+        }
+        for part in parts {
+            if unsafe {
+                EVP_DigestUpdate(
+                    self.ctx.as_mut_ptr(),
+                    part.as_ptr() as *const c_void,
+                    part.len(),
+                )
+            } != 1
+            {
+                return Err(CKR_DEVICE_ERROR)?;
+            }
+        }
+        if unsafe {
+            EVP_DigestFinal_ex(
+                self.ctx.as_mut_ptr(),
+                out.as_mut_ptr(),
+                &mut outlen,
+            )
+        } != 1
+        {
+            return Err(CKR_DEVICE_ERROR)?;
+        }
+        Ok(out)
+    }
+}
+
+fn u32str(v: CK_ULONG) -> Result<[u8; 4]> {
+    Ok(u32::try_from(v)?.to_be_bytes())
+}
+
+fn read_u32(buf: &[u8], off: usize) -> Result<CK_ULONG> {
+    match buf.get(off..off + 4) {
+        Some(b) => {
+            Ok(CK_ULONG::from(u32::from_be_bytes(b.try_into().unwrap())))
+        }
+        None => Err(CKR_SIGNATURE_INVALID)?,
+    }
+}
+
+/* RFC 8554 Section 3.1.3: the i-th w-bit element of S */
+fn coef(s: &[u8], i: usize, w: usize) -> usize {
+    let mask = (1 << w) - 1;
+    let shift = 8 - (w * (i % (8 / w)) + w);
+    (usize::from(s[i * w / 8]) >> shift) & mask
+}
+
+/* RFC 8554 Section 4.4: returns Q || Cksm(Q) */
+fn checksummed(q: &Node, w: usize, ls: usize) -> Vec<u8> {
+    let max = (1 << w) - 1;
+    let mut sum: usize = 0;
+    for i in 0..(HSS_N * 8 / w) {
+        sum += max - coef(q, i, w);
+    }
+    let mut v = q.to_vec();
+    v.extend_from_slice(&u16::try_from(sum << ls).unwrap().to_be_bytes());
+    v
+}
+
+/* RFC 8554 Section 4.3: iterates the chain i from step start to end */
+fn chain(
+    sha: &mut Sha256,
+    id: &[u8],
+    q: &[u8; 4],
+    i: usize,
+    mut tmp: Node,
+    start: usize,
+    end: usize,
+) -> Result<Node> {
+    let istr = u16::try_from(i)?.to_be_bytes();
+    for j in start..end {
+        let jstr = [u8::try_from(j)?];
+        let next = sha.hash(&[id, q, &istr, &jstr, &tmp])?;
+        tmp.zeroize();
+        tmp = next;
+    }
+    Ok(tmp)
+}
+
+/* A single LMS tree private key, RFC 8554 Section 5.2 */
+struct LmsPrivateKey {
+    lms_type: CK_LMS_TYPE,
+    lmots_type: CK_LMOTS_TYPE,
+    h: usize,
+    w: usize,
+    p: usize,
+    ls: usize,
+    id: [u8; HSS_I_LEN],
+    seed: Node,
+}
+
+impl Drop for LmsPrivateKey {
+    fn drop(&mut self) {
+        self.seed.zeroize();
+    }
+}
+
+impl LmsPrivateKey {
+    fn new(
+        lms_type: CK_LMS_TYPE,
+        lmots_type: CK_LMOTS_TYPE,
+        id: [u8; HSS_I_LEN],
+        seed: Node,
+    ) -> Result<LmsPrivateKey> {
+        let (w, p, ls) = lmots_params(lmots_type)?;
+        Ok(LmsPrivateKey {
+            lms_type,
+            lmots_type,
+            h: lms_height(lms_type)?,
+            w,
+            p,
+            ls,
+            id,
+            seed,
+        })
+    }
+
+    fn derive(&self, sha: &mut Sha256, q: &[u8; 4], d: &[u8]) -> Result<Node> {
+        sha.hash(&[&self.id, q, d, &D_PRIV, &self.seed])
+    }
+
+    /* RFC 8554 Section 4.3: the LM-OTS public key K of leaf q */
+    fn lmots_public_key(&self, sha: &mut Sha256, q: u32) -> Result<Node> {
+        let qstr = q.to_be_bytes();
+        let mut y = Vec::with_capacity(self.p * HSS_N);
+        for i in 0..self.p {
+            let istr = u16::try_from(i)?.to_be_bytes();
+            let x = self.derive(sha, &qstr, &istr)?;
+            let end = (1 << self.w) - 1;
+            y.extend_from_slice(&chain(sha, &self.id, &qstr, i, x, 0, end)?);
+        }
+        sha.hash(&[&self.id, &qstr, &D_PBLC, &y])
+    }
+
+    /* RFC 8554 Section 5.3: all the nodes of the tree, indexed by r */
+    fn tree(&self, sha: &mut Sha256) -> Result<Vec<Node>> {
+        let leaves = 1usize << self.h;
+        let mut nodes = vec![[0u8; HSS_N]; 2 * leaves];
+        for r in (1..2 * leaves).rev() {
+            let rstr = u32::try_from(r)?.to_be_bytes();
+            nodes[r] = if r >= leaves {
+                let k =
+                    self.lmots_public_key(sha, u32::try_from(r - leaves)?)?;
+                sha.hash(&[&self.id, &rstr, &D_LEAF, &k])?
+            } else {
+                sha.hash(&[
+                    &self.id,
+                    &rstr,
+                    &D_INTR,
+                    &nodes[2 * r],
+                    &nodes[2 * r + 1],
+                ])?
+            };
+        }
+        Ok(nodes)
+    }
+
+    /* RFC 8554 Section 5.3: u32str(type) || u32str(otstype) || I || T[1] */
+    fn public_key(&self, root: &Node) -> Result<Vec<u8>> {
+        let mut key = Vec::with_capacity(HSS_PUBLIC_KEY_LEN - 4);
+        key.extend_from_slice(&u32str(self.lms_type)?);
+        key.extend_from_slice(&u32str(self.lmots_type)?);
+        key.extend_from_slice(&self.id);
+        key.extend_from_slice(root);
+        Ok(key)
+    }
+
+    /* RFC 8554 Section 4.5 and 5.4.1: signs the message with leaf q */
+    fn sign(
+        &self,
+        sha: &mut Sha256,
+        tree: &[Node],
+        q: u32,
+        msg: &[u8],
+        c: &Node,
+    ) -> Result<Vec<u8>> {
+        let qstr = q.to_be_bytes();
+        let hq = sha.hash(&[&self.id, &qstr, &D_MESG, c, msg])?;
+        let v = checksummed(&hq, self.w, self.ls);
+
+        let mut sig = Vec::with_capacity(lms_signature_len(
+            self.lms_type,
+            self.lmots_type,
+        )?);
+        sig.extend_from_slice(&qstr);
+        sig.extend_from_slice(&u32str(self.lmots_type)?);
+        sig.extend_from_slice(c);
+        for i in 0..self.p {
+            let istr = u16::try_from(i)?.to_be_bytes();
+            let x = self.derive(sha, &qstr, &istr)?;
+            let a = coef(&v, i, self.w);
+            sig.extend_from_slice(&chain(sha, &self.id, &qstr, i, x, 0, a)?);
+        }
+        sig.extend_from_slice(&u32str(self.lms_type)?);
+        let mut node = (1usize << self.h) + usize::try_from(q)?;
+        for _ in 0..self.h {
+            sig.extend_from_slice(&tree[node ^ 1]);
+            node >>= 1;
+        }
+        Ok(sig)
+    }
+
+    /* The tree used by the next level below leaf q */
+    fn child(
+        &self,
+        sha: &mut Sha256,
+        q: u32,
+        lms_type: CK_LMS_TYPE,
+        lmots_type: CK_LMOTS_TYPE,
+    ) -> Result<LmsPrivateKey> {
+        let qstr = q.to_be_bytes();
+        let seed = self.derive(sha, &qstr, &D_CHILD_SEED)?;
+        let mut id = [0u8; HSS_I_LEN];
+        id.copy_from_slice(&self.derive(sha, &qstr, &D_CHILD_I)?[..HSS_I_LEN]);
+        LmsPrivateKey::new(lms_type, lmots_type, id, seed)
+    }
+}
+
+/* RFC 8554 Section 5.4.2: verifies a LMS signature that must span the
+ * whole sig buffer */
+fn lms_verify(
+    sha: &mut Sha256,
+    key: &[u8],
+    msg: &[u8],
+    sig: &[u8],
+) -> Result<()> {
+    if key.len() != HSS_PUBLIC_KEY_LEN - 4 {
+        Err(CKR_SIGNATURE_INVALID)?;
+    }
+    let lms_type = read_u32(key, 0)?;
+    let lmots_type = read_u32(key, 4)?;
+    let id = &key[8..8 + HSS_I_LEN];
+    let root = &key[8 + HSS_I_LEN..];
+
+    let h = lms_height(lms_type).or(Err(CKR_SIGNATURE_INVALID))?;
+    let (w, p, ls) = lmots_params(lmots_type).or(Err(CKR_SIGNATURE_INVALID))?;
+    if sig.len() != lms_signature_len(lms_type, lmots_type)? {
+        Err(CKR_SIGNATURE_INVALID)?;
+    }
+    let q = read_u32(sig, 0)?;
+    if read_u32(sig, 4)? != lmots_type || q >= (1 << h) {
+        Err(CKR_SIGNATURE_INVALID)?;
+    }
+    let qstr = u32str(q)?;
+    let c = &sig[8..8 + HSS_N];
+    let y = &sig[8 + HSS_N..8 + HSS_N * (p + 1)];
+    let mut off = 8 + HSS_N * (p + 1);
+    if read_u32(sig, off)? != lms_type {
+        Err(CKR_SIGNATURE_INVALID)?;
+    }
+    off += 4;
+
+    /* RFC 8554 Section 4.6: candidate LM-OTS public key */
+    let hq = sha.hash(&[id, &qstr, &D_MESG, c, msg])?;
+    let v = checksummed(&hq, w, ls);
+    let mut z = Vec::with_capacity(p * HSS_N);
+    for i in 0..p {
+        let a = coef(&v, i, w);
+        let tmp: Node = y[i * HSS_N..(i + 1) * HSS_N].try_into().unwrap();
+        z.extend_from_slice(&chain(sha, id, &qstr, i, tmp, a, (1 << w) - 1)?);
+    }
+    let kc = sha.hash(&[id, &qstr, &D_PBLC, &z])?;
+
+    let mut node = (1 << h) + q;
+    let mut tmp = sha.hash(&[id, &u32str(node)?, &D_LEAF, &kc])?;
+    while node > 1 {
+        let path = &sig[off..off + HSS_N];
+        let parent = u32str(node / 2)?;
+        tmp = if node % 2 == 1 {
+            sha.hash(&[id, &parent, &D_INTR, path, &tmp])?
+        } else {
+            sha.hash(&[id, &parent, &D_INTR, &tmp, path])?
+        };
+        node /= 2;
+        off += HSS_N;
+    }
+    if tmp != root {
+        Err(CKR_SIGNATURE_INVALID)?;
+    }
+    Ok(())
+}
+
+/* The length of the LMS signature at the start of sig */
+fn lms_signature_len_at(sig: &[u8]) -> Result<usize> {
+    let lmots_type = read_u32(sig, 4)?;
+    let (_, p, _) = lmots_params(lmots_type).or(Err(CKR_SIGNATURE_INVALID))?;
+    let lms_type = read_u32(sig, 8 + HSS_N * (p + 1))?;
+    let len = lms_signature_len(lms_type, lmots_type)
+        .or(Err(CKR_SIGNATURE_INVALID))?;
+    if sig.len() < len {
+        Err(CKR_SIGNATURE_INVALID)?;
+    }
+    Ok(len)
+}
+
+/* RFC 8554 Section 6.3: verifies a HSS signature */
+fn hss_verify(
+    sha: &mut Sha256,
+    key: &[u8],
+    msg: &[u8],
+    sig: &[u8],
+) -> Result<()> {
+    if key.len() != HSS_PUBLIC_KEY_LEN {
+        Err(CKR_SIGNATURE_INVALID)?;
+    }
+    let levels = read_u32(key, 0)?;
+    if read_u32(sig, 0)? + 1 != levels {
+        Err(CKR_SIGNATURE_INVALID)?;
+    }
+    let mut lms_key = &key[4..];
+    let mut off = 4;
+    for _ in 1..levels {
+        let len = lms_signature_len_at(&sig[off..])?;
+        let next = match sig.get(off + len..off + len + lms_key.len()) {
+            Some(k) => k,
+            None => return Err(CKR_SIGNATURE_INVALID)?,
+        };
+        lms_verify(sha, lms_key, next, &sig[off..off + len])?;
+        lms_key = next;
+        off += len + lms_key.len();
+    }
+    lms_verify(sha, lms_key, msg, &sig[off..])
+}
+
+/* The state stored in CKA_VALUE, see HSS_PRIVATE_KEY_LEN */
+struct HssPrivateKey {
+    index: u64,
+    id: [u8; HSS_I_LEN],
+    seed: Node,
+}
+
+impl Drop for HssPrivateKey {
+    fn drop(&mut self) {
+        self.seed.zeroize();
+    }
+}
+
+impl HssPrivateKey {
+    fn from_value(value: &[u8]) -> Result<HssPrivateKey> {
+        if value.len() != HSS_PRIVATE_KEY_LEN {
+            return Err(CKR_KEY_HANDLE_INVALID)?;
+        }
+        let mut hsskey = HssPrivateKey {
+            index: u64::from_be_bytes(value[0..8].try_into().unwrap()),
+            id: [0u8; HSS_I_LEN],
+            seed: [0u8; HSS_N],
+        };
+        hsskey.id.copy_from_slice(&value[8..8 + HSS_I_LEN]);
+        hsskey.seed.copy_from_slice(&value[8 + HSS_I_LEN..]);
+        Ok(hsskey)
+    }
+
+    fn to_value(&self, index: u64) -> Vec<u8> {
+        let mut value = Vec::with_capacity(HSS_PRIVATE_KEY_LEN);
+        value.extend_from_slice(&index.to_be_bytes());
+        value.extend_from_slice(&self.id);
+        value.extend_from_slice(&self.seed);
+        value
+    }
+}
+
+/* RFC 8554 Section 6.2: signs the message with the leaves selected by
+ * the key index, the tree used at each level is recomputed */
+fn hss_sign(
+    sha: &mut Sha256,
+    hsskey: &HssPrivateKey,
+    lms_types: &[CK_LMS_TYPE],
+    lmots_types: &[CK_LMOTS_TYPE],
+    msg: &[u8],
+) -> Result<Vec<u8>> {
+    let levels = lms_types.len();
+    let mut qs = vec![0u32; levels];
+    let mut index = hsskey.index;
+    for l in (0..levels).rev() {
+        let h = lms_height(lms_types[l])?;
+        qs[l] = u32::try_from(index & ((1 << h) - 1))?;
+        index >>= h;
+    }
+    if index != 0 {
+        Err(CKR_KEY_EXHAUSTED)?;
+    }
+
+    let mut keys = Vec::with_capacity(levels);
+    keys.push(LmsPrivateKey::new(
+        lms_types[0],
+        lmots_types[0],
+        hsskey.id,
+        hsskey.seed,
+    )?);
+    for l in 1..levels {
+        let child =
+            keys[l - 1].child(sha, qs[l - 1], lms_types[l], lmots_types[l])?;
+        keys.push(child);
+    }
+    let mut trees = Vec::with_capacity(levels);
+    for key in &keys {
+        trees.push(key.tree(sha)?);
+    }
+
+    let mut sig =
+        Vec::with_capacity(hss_signature_len(lms_types, lmots_types)?);
+    sig.extend_from_slice(&u32::try_from(levels - 1)?.to_be_bytes());
+    for l in 0..levels - 1 {
+        let child_key = keys[l + 1].public_key(&trees[l + 1][1])?;
+        let c = keys[l].derive(sha, &qs[l].to_be_bytes(), &D_CHILD_C)?;
+        sig.extend(keys[l].sign(sha, &trees[l], qs[l], &child_key, &c)?);
+        sig.extend(child_key);
+    }
+    let mut c: Node = [0u8; HSS_N];
+    get_random_data(&mut c)?;
+    let l = levels - 1;
+    sig.extend(keys[l].sign(sha, &trees[l], qs[l], msg, &c)?);
+    Ok(sig)
+}
+
+#[derive(Debug)]
+pub struct HssOperation {
+    mech: CK_MECHANISM_TYPE,
+    key_handle: CK_OBJECT_HANDLE,
+    lms_types: Vec<CK_LMS_TYPE>,
+    lmots_types: Vec<CK_LMOTS_TYPE>,
+    public_key: Option<Vec<u8>>,
+    reserved: Option<Vec<u8>>,
+    signature_len: usize,
+    data: Vec<u8>,
+    finalized: bool,
+    in_use: bool,
+}
+
+impl Drop for HssOperation {
+    fn drop(&mut self) {
+        if let Some(r) = &mut self.reserved {
+            r.zeroize();
+        }
+    }
+}
+
+impl HssOperation {
+    pub fn generate_keypair(
+        lms_types: &[CK_LMS_TYPE],
+        lmots_types: &[CK_LMOTS_TYPE],
+        pubkey: &mut Object,
+        privkey: &mut Object,
+    ) -> Result<()> {
+        let mut hsskey = HssPrivateKey {
+            index: 0,
+            id: [0u8; HSS_I_LEN],
+            seed: [0u8; HSS_N],
+        };
+        get_random_data(&mut hsskey.id)?;
+        get_random_data(&mut hsskey.seed)?;
+
+        let mut sha = Sha256::new()?;
+        let lms_key = LmsPrivateKey::new(
+            lms_types[0],
+            lmots_types[0],
+            hsskey.id,
+            hsskey.seed,
+        )?;
+        let tree = lms_key.tree(&mut sha)?;
+        let mut value = Vec::with_capacity(HSS_PUBLIC_KEY_LEN);
+        value.extend_from_slice(&u32::try_from(lms_types.len())?.to_be_bytes());
+        value.extend(lms_key.public_key(&tree[1])?);
+
+        pubkey.set_attr(Attribute::from_bytes(CKA_VALUE, value))?;
+        pubkey.set_attr(Attribute::from_ulong(
+            CKA_HSS_LEVELS,
+            CK_ULONG::try_from(lms_types.len())?,
+        ))?;
+        pubkey
+            .set_attr(Attribute::from_ulong(CKA_HSS_LMS_TYPE, lms_types[0]))?;
+        pubkey.set_attr(Attribute::from_ulong(
+            CKA_HSS_LMOTS_TYPE,
+            lmots_types[0],
+        ))?;
+
+        privkey
+            .set_attr(Attribute::from_bytes(CKA_VALUE, hsskey.to_value(0)))?;
+        privkey.set_attr(Attribute::from_ulong(
+            CKA_HSS_KEYS_REMAINING,
+            hss_keys_remaining(lms_types, 0)?,
+        ))?;
+        Ok(())
+    }
+
+    pub fn sign_new(mech: &CK_MECHANISM, key: &Object) -> Result<HssOperation> {
+        if mech.ulParameterLen != 0 {
+            return Err(CKR_MECHANISM_PARAM_INVALID)?;
+        }
+        let (lms_types, lmots_types) = get_hss_types(key)?;
+        let signature_len = hss_signature_len(&lms_types, &lmots_types)?;
+        Ok(HssOperation {
+            mech: mech.mechanism,
+            key_handle: key.get_handle(),
+            lms_types,
+            lmots_types,
+            public_key: None,
+            reserved: None,
+            signature_len,
+            data: Vec::new(),
+            finalized: false,
+            in_use: false,
+        })
+    }
+
+    pub fn verify_new(
+        mech: &CK_MECHANISM,
+        key: &Object,
+    ) -> Result<HssOperation> {
+        if mech.ulParameterLen != 0 {
+            return Err(CKR_MECHANISM_PARAM_INVALID)?;
+        }
+        let value = key.get_attr_as_bytes(CKA_VALUE)?;
+        if value.len() != HSS_PUBLIC_KEY_LEN {
+            return Err(CKR_KEY_TYPE_INCONSISTENT)?;
+        }
+        Ok(HssOperation {
+            mech: mech.mechanism,
+            key_handle: key.get_handle(),
+            lms_types: Vec::new(),
+            lmots_types: Vec::new(),
+            public_key: Some(value.clone()),
+            reserved: None,
+            signature_len: 0,
+            data: Vec::new(),
+            finalized: false,
+            in_use: false,
+        })
+    }
+}
+
+impl MechOperation for HssOperation {
+    fn mechanism(&self) -> Result<CK_MECHANISM_TYPE> {
+        Ok(self.mech)
+    }
+
+    fn finalized(&self) -> bool {
+        self.finalized
+    }
+}
+
+impl Sign for HssOperation {
+    fn sign(&mut self, data: &[u8], signature: &mut [u8]) -> Result<()> {
+        if self.in_use {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        if self.finalized {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        self.sign_update(data)?;
+        self.sign_final(signature)
+    }
+
+    fn sign_update(&mut self, data: &[u8]) -> Result<()> {
+        if self.finalized {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        self.in_use = true;
+        self.data.extend_from_slice(data);
+        Ok(())
+    }
+
+    fn sign_final(&mut self, signature: &mut [u8]) -> Result<()> {
+        if self.finalized {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        self.finalized = true;
+        if signature.len() != self.signature_len {
+            return Err(CKR_GENERAL_ERROR)?;
+        }
+        /* never sign without a state committed to storage first */
+        let hsskey = match &self.reserved {
+            Some(value) => HssPrivateKey::from_value(value)?,
+            None => return Err(CKR_GENERAL_ERROR)?,
+        };
+        let mut sha = Sha256::new()?;
+        let sig = hss_sign(
+            &mut sha,
+            &hsskey,
+            &self.lms_types,
+            &self.lmots_types,
+            &self.data,
+        )?;
+        if sig.len() != signature.len() {
+            return Err(CKR_GENERAL_ERROR)?;
+        }
+        signature.copy_from_slice(&sig);
+        Ok(())
+    }
+
+    fn signature_len(&self) -> Result<usize> {
+        Ok(self.signature_len)
+    }
+
+    fn state_key(&self) -> Option<CK_OBJECT_HANDLE> {
+        Some(self.key_handle)
+    }
+
+    fn reserve_state(&mut self, key: &Object) -> Result<Vec<Attribute>> {
+        if self.reserved.is_some() {
+            return Err(CKR_GENERAL_ERROR)?;
+        }
+        let hsskey =
+            HssPrivateKey::from_value(key.get_attr_as_bytes(CKA_VALUE)?)?;
+        if hss_keys_remaining(&self.lms_types, hsskey.index)? == 0 {
+            return Err(CKR_KEY_EXHAUSTED)?;
+        }
+        let next = match hsskey.index.checked_add(1) {
+            Some(n) => n,
+            None => return Err(CKR_KEY_EXHAUSTED)?,
+        };
+        self.reserved = Some(hsskey.to_value(hsskey.index));
+        Ok(vec![
+            Attribute::from_bytes(CKA_VALUE, hsskey.to_value(next)),
+            Attribute::from_ulong(
+                CKA_HSS_KEYS_REMAINING,
+                hss_keys_remaining(&self.lms_types, next)?,
+            ),
+        ])
+    }
+}
+
+impl Verify for HssOperation {
+    fn verify(&mut self, data: &[u8], signature: &[u8]) -> Result<()> {
+        if self.in_use {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        if self.finalized {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        self.verify_update(data)?;
+        self.verify_final(signature)
+    }
+
+    fn verify_update(&mut self, data: &[u8]) -> Result<()> {
+        if self.finalized {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        self.in_use = true;
+        self.data.extend_from_slice(data);
+        Ok(())
+    }
+
+    fn verify_final(&mut self, signature: &[u8]) -> Result<()> {
+        if self.finalized {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        self.finalized = true;
+        let key = match &self.public_key {
+            Some(k) => k,
+            None => return Err(CKR_GENERAL_ERROR)?,
+        };
+        let mut sha = Sha256::new()?;
+        hss_verify(&mut sha, key, &self.data, signature)
+    }
+
+    fn signature_len(&self) -> Result<usize> {
+        Err(CKR_GENERAL_ERROR)?
+    }
+
+    fn fixed_signature_len(&self) -> bool {
+        false
+    }
+}
@@ -0,0 +1,671 @@
+// Copyright 2024 Simo Sorce
+// See LICENSE.txt file for terms
+
+use std::ffi::{c_char, c_int};
+
+use crate::attribute::Attribute;
+use crate::error::Result;
+use crate::interface::*;
+use crate::kasn1::oid::*;
+use crate::mechanism::*;
+use crate::mldsa::*;
+use crate::object::Object;
+use crate::ossl::bindings::*;
+use crate::ossl::common::*;
+use crate::ossl::hash::HashOperation;
+use crate::{cast_params, some_or_err};
+
+#[cfg(feature = "fips")]
+use crate::ossl::fips::*;
+
+#[cfg(not(feature = "fips"))]
+use crate::ossl::get_libctx;
+
+use zeroize::Zeroize;
+
+/* ML-DSA algorithm names as used in OpenSSL */
+const NAME_ML_DSA_44: &[u8] = b"ML-DSA-44\0";
+const NAME_ML_DSA_65: &[u8] = b"ML-DSA-65\0";
+const NAME_ML_DSA_87: &[u8] = b"ML-DSA-87\0";
+
+/* FIPS 204 limits the context string to 255 bytes */
+const MAX_CONTEXT_LEN: usize = 255;
+
+fn mldsa_param_set_to_name(
+    param_set: CK_ML_DSA_PARAMETER_SET_TYPE,
+) -> Result<*const c_char> {
+    match param_set {
+        CKP_ML_DSA_44 => Ok(name_as_char(NAME_ML_DSA_44)),
+        CKP_ML_DSA_65 => Ok(name_as_char(NAME_ML_DSA_65)),
+        CKP_ML_DSA_87 => Ok(name_as_char(NAME_ML_DSA_87)),
+        _ => Err(CKR_KEY_TYPE_INCONSISTENT)?,
+    }
+}
+
+pub fn mldsa_object_to_params(
+    key: &Object,
+    class: CK_OBJECT_CLASS,
+) -> Result<(*const c_char, OsslParam<'_>)> {
+    let kclass = key.get_attr_as_ulong(CKA_CLASS)?;
+    if kclass != class {
+        Err(CKR_KEY_TYPE_INCONSISTENT)?;
+    }
+    let name =
+        mldsa_param_set_to_name(key.get_attr_as_ulong(CKA_PARAMETER_SET)?)?;
+
+    let mut params = OsslParam::with_capacity(2);
+    params.zeroize = true;
+
+    match kclass {
+        CKO_PUBLIC_KEY => {
+            params.add_octet_string(
+                name_as_char(OSSL_PKEY_PARAM_PUB_KEY),
+                key.get_attr_as_bytes(CKA_VALUE)?,
+            )?;
+        }
+        CKO_PRIVATE_KEY => {
+            /* the seed is preferred when available, the expanded key is
+             * checked against it by OpenSSL */
+            if let Some(seed) = key.get_attr(CKA_SEED) {
+                params.add_octet_string(
+                    name_as_char(OSSL_PKEY_PARAM_ML_DSA_SEED),
+                    seed.get_value(),
+                )?;
+            }
+            if let Some(value) = key.get_attr(CKA_VALUE) {
+                params.add_octet_string(
+                    name_as_char(OSSL_PKEY_PARAM_PRIV_KEY),
+                    value.get_value(),
+                )?;
+            }
+        }
+        _ => return Err(CKR_KEY_TYPE_INCONSISTENT)?,
+    }
+
+    params.finalize();
+
+    Ok((name, params))
+}
+
+/* The hash used by the HashML-DSA mechanisms, or
+ * CK_UNAVAILABLE_INFORMATION for pure ML-DSA */
+fn mech_to_hash(mech: CK_MECHANISM_TYPE) -> Result<CK_MECHANISM_TYPE> {
+    match mech {
+        CKM_ML_DSA => Ok(CK_UNAVAILABLE_INFORMATION),
+        CKM_HASH_ML_DSA_SHA224 => Ok(CKM_SHA224),
+        CKM_HASH_ML_DSA_SHA256 => Ok(CKM_SHA256),
+        CKM_HASH_ML_DSA_SHA384 => Ok(CKM_SHA384),
+        CKM_HASH_ML_DSA_SHA512 => Ok(CKM_SHA512),
+        CKM_HASH_ML_DSA_SHA3_224 => Ok(CKM_SHA3_224),
+        CKM_HASH_ML_DSA_SHA3_256 => Ok(CKM_SHA3_256),
+        CKM_HASH_ML_DSA_SHA3_384 => Ok(CKM_SHA3_384),
+        CKM_HASH_ML_DSA_SHA3_512 => Ok(CKM_SHA3_512),
+        _ => Err(CKR_MECHANISM_INVALID)?,
+    }
+}
+
+fn hash_to_oid(hash: CK_MECHANISM_TYPE) -> Result<asn1::ObjectIdentifier> {
+    match hash {
+        CKM_SHA224 => Ok(SHA224_OID),
+        CKM_SHA256 => Ok(SHA256_OID),
+        CKM_SHA384 => Ok(SHA384_OID),
+        CKM_SHA512 => Ok(SHA512_OID),
+        CKM_SHA3_224 => Ok(NIST_SHA3_224_OID),
+        CKM_SHA3_256 => Ok(NIST_SHA3_256_OID),
+        CKM_SHA3_384 => Ok(NIST_SHA3_384_OID),
+        CKM_SHA3_512 => Ok(NIST_SHA3_512_OID),
+        _ => Err(CKR_MECHANISM_PARAM_INVALID)?,
+    }
+}
+
+#[derive(Debug)]
+struct MlDsaParams {
+    hedge: CK_HEDGE_TYPE,
+    context: Vec<u8>,
+    hash: CK_MECHANISM_TYPE,
+}
+
+fn make_params(
+    hedge: CK_HEDGE_TYPE,
+    context: *mut CK_BYTE,
+    context_len: CK_ULONG,
+    hash: CK_MECHANISM_TYPE,
+) -> Result<MlDsaParams> {
+    match hedge {
+        CKH_HEDGE_PREFERRED
+        | CKH_HEDGE_REQUIRED
+        | CKH_DETERMINISTIC_REQUIRED => (),
+        _ => return Err(CKR_MECHANISM_PARAM_INVALID)?,
+    }
+    let len = usize::try_from(context_len)?;
+    if len > MAX_CONTEXT_LEN {
+        Err(CKR_MECHANISM_PARAM_INVALID)?;
+    }
+    let context = if len == 0 {
+        Vec::new()
+    } else {
+        if context.is_null() {
+            Err(CKR_MECHANISM_PARAM_INVALID)?;
+        }
+        unsafe { std::slice::from_raw_parts(context, len) }.to_vec()
+    };
+    Ok(MlDsaParams {
+        hedge,
+        context,
+        hash,
+    })
+}
+
+fn parse_params(mech: &CK_MECHANISM) -> Result<MlDsaParams> {
+    if mech.mechanism == CKM_HASH_ML_DSA {
+        /* the hash is mandatory for the generic pre-hash mechanism */
+        let params = cast_params!(mech, CK_HASH_SIGN_ADDITIONAL_CONTEXT);
+        hash_to_oid(params.hash)?;
+        return make_params(
+            params.hedgeVariant,
+            params.pContext,
+            params.ulContextLen,
+            params.hash,
+        );
+    }
+    let hash = mech_to_hash(mech.mechanism)?;
+    match mech.ulParameterLen {
+        0 => make_params(CKH_HEDGE_PREFERRED, std::ptr::null_mut(), 0, hash),
+        _ => {
+            let params = cast_params!(mech, CK_SIGN_ADDITIONAL_CONTEXT);
+            make_params(
+                params.hedgeVariant,
+                params.pContext,
+                params.ulContextLen,
+                hash,
+            )
+        }
+    }
+}
+
+fn new_hasher(mech: &CK_MECHANISM) -> Result<Option<HashOperation>> {
+    match mech.mechanism {
+        CKM_ML_DSA | CKM_HASH_ML_DSA => Ok(None),
+        _ => Ok(Some(HashOperation::new(mech_to_hash(mech.mechanism)?)?)),
+    }
+}
+
+macro_rules! get_sig_ctx {
+    ($key:ident) => {
+        /* needless match, but otherwise rust complains about experimental attributes on
+         * expressions */
+        match $key {
+            #[cfg(feature = "fips")]
+            _ => Some(ProviderSignatureCtx::new(mldsa_param_set_to_name(
+                get_param_set($key)?,
+            )?)?),
+            #[cfg(not(feature = "fips"))]
+            _ => Some(EvpMdCtx::new()?),
+        }
+    };
+}
+
+fn sig_params<'a>(
+    mldsa_params: &'a MlDsaParams,
+    sign: bool,
+) -> Result<OsslParam<'a>> {
+    let mut params = OsslParam::with_capacity(3);
+    if mldsa_params.hash == CK_UNAVAILABLE_INFORMATION {
+        if !mldsa_params.context.is_empty() {
+            params.add_octet_string(
+                name_as_char(OSSL_SIGNATURE_PARAM_CONTEXT_STRING),
+                &mldsa_params.context,
+            )?;
+        }
+    } else {
+        /* For HashML-DSA the encoded message M' is built here and
+         * passed to OpenSSL as is */
+        params.add_owned_int(
+            name_as_char(OSSL_SIGNATURE_PARAM_MESSAGE_ENCODING),
+            0,
+        )?;
+    }
+    if sign && mldsa_params.hedge == CKH_DETERMINISTIC_REQUIRED {
+        params.add_owned_int(
+            name_as_char(OSSL_SIGNATURE_PARAM_DETERMINISTIC),
+            1,
+        )?;
+    }
+    params.finalize();
+    Ok(params)
+}
+
+#[derive(Debug)]
+pub struct MlDsaOperation {
+    mech: CK_MECHANISM_TYPE,
+    output_len: usize,
+    public_key: Option<EvpPkey>,
+    private_key: Option<EvpPkey>,
+    params: MlDsaParams,
+    init_params: Option<MlDsaParams>,
+    data: Vec<u8>,
+    hasher: Option<HashOperation>,
+    finalized: bool,
+    in_use: bool,
+    #[cfg(not(feature = "fips"))]
+    sigctx: Option<EvpMdCtx>,
+    #[cfg(feature = "fips")]
+    sigctx: Option<ProviderSignatureCtx>,
+}
+
+impl Drop for MlDsaOperation {
+    fn drop(&mut self) {
+        self.data.zeroize();
+        self.params.context.zeroize();
+        if let Some(p) = self.init_params.as_mut() {
+            p.context.zeroize();
+        }
+    }
+}
+
+impl MlDsaOperation {
+    pub fn sign_new(
+        mech: &CK_MECHANISM,
+        key: &Object,
+    ) -> Result<MlDsaOperation> {
+        let (_, _, outlen) = mldsa_sizes(get_param_set(key)?)?;
+        Ok(MlDsaOperation {
+            mech: mech.mechanism,
+            output_len: outlen,
+            public_key: None,
+            private_key: Some(EvpPkey::privkey_from_object(key)?),
+            params: parse_params(mech)?,
+            init_params: None,
+            data: Vec::new(),
+            hasher: new_hasher(mech)?,
+            finalized: false,
+            in_use: false,
+            sigctx: get_sig_ctx!(key),
+        })
+    }
+
+    pub fn verify_new(
+        mech: &CK_MECHANISM,
+        key: &Object,
+    ) -> Result<MlDsaOperation> {
+        let (_, _, outlen) = mldsa_sizes(get_param_set(key)?)?;
+        Ok(MlDsaOperation {
+            mech: mech.mechanism,
+            output_len: outlen,
+            public_key: Some(EvpPkey::pubkey_from_object(key)?),
+            private_key: None,
+            params: parse_params(mech)?,
+            init_params: None,
+            data: Vec::new(),
+            hasher: new_hasher(mech)?,
+            finalized: false,
+            in_use: false,
+            sigctx: get_sig_ctx!(key),
+        })
+    }
+
+    pub fn generate_keypair(
+        param_set: CK_ML_DSA_PARAMETER_SET_TYPE,
+        pubkey: &mut Object,
+        privkey: &mut Object,
+    ) -> Result<()> {
+        let evp_pkey = EvpPkey::generate(
+            mldsa_param_set_to_name(param_set)?,
+            &OsslParam::empty(),
+        )?;
+
+        let mut params: *mut OSSL_PARAM = std::ptr::null_mut();
+        let res = unsafe {
+            EVP_PKEY_todata(
+                evp_pkey.as_ptr(),
+                c_int::try_from(EVP_PKEY_KEYPAIR)?,
+                &mut params,
+            )
+        };
+        if res != 1 {
+            return Err(CKR_DEVICE_ERROR)?;
+        }
+        let params = OsslParam::from_ptr(params)?;
+
+        /* Public Key */
+        let value = params
+            .get_octet_string(name_as_char(OSSL_PKEY_PARAM_PUB_KEY))?
+            .to_vec();
+        pubkey.set_attr(Attribute::from_bytes(CKA_VALUE, value))?;
+
+        /* Private Key */
+        let value = params
+            .get_octet_string(name_as_char(OSSL_PKEY_PARAM_PRIV_KEY))?
+            .to_vec();
+        privkey.set_attr(Attribute::from_bytes(CKA_VALUE, value))?;
+        /* the seed is retained by default on generation */
+        if let Ok(seed) =
+            params.get_octet_string(name_as_char(OSSL_PKEY_PARAM_ML_DSA_SEED))
+        {
+            privkey.set_attr(Attribute::from_bytes(CKA_SEED, seed.to_vec()))?;
+        }
+        Ok(())
+    }
+
+    /* FIPS 204, Algorithm 4: M' = 1 || |ctx| || ctx || OID || PH(M) */
+    fn prehash_message(&mut self) -> Result<Vec<u8>> {
+        let mut digest = match self.hasher.as_mut() {
+            Some(hasher) => {
+                let mut digest = vec![0u8; hasher.digest_len()?];
+                hasher.digest_final(&mut digest)?;
+                digest
+            }
+            None => {
+                /* CKM_HASH_ML_DSA signs a hash computed by the caller */
+                let hasher = HashOperation::new(self.params.hash)?;
+                if self.data.len() != hasher.digest_len()? {
+                    return Err(CKR_DATA_LEN_RANGE)?;
+                }
+                std::mem::take(&mut self.data)
+            }
+        };
+        let oid = match asn1::write_single(&hash_to_oid(self.params.hash)?) {
+            Ok(o) => o,
+            Err(_) => return Err(CKR_GENERAL_ERROR)?,
+        };
+        let mut message = Vec::with_capacity(
+            2 + self.params.context.len() + oid.len() + digest.len(),
+        );
+        message.push(1);
+        message.push(u8::try_from(self.params.context.len())?);
+        message.extend_from_slice(&self.params.context);
+        message.extend_from_slice(&oid);
+        message.extend_from_slice(&digest);
+        digest.zeroize();
+        Ok(message)
+    }
+
+    fn message(&mut self) -> Result<Vec<u8>> {
+        if self.params.hash == CK_UNAVAILABLE_INFORMATION {
+            Ok(std::mem::take(&mut self.data))
+        } else {
+            self.prehash_message()
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) -> Result<()> {
+        match self.hasher.as_mut() {
+            Some(hasher) => hasher.digest_update(data),
+            None => {
+                /* OpenSSL API does not support multi-part operation so we
+                 * need to emulate it as PKCS#11 supports it with this
+                 * mechanism */
+                self.data.extend_from_slice(data);
+                Ok(())
+            }
+        }
+    }
+
+    fn sign_begin(&mut self) -> Result<()> {
+        self.in_use = true;
+
+        let mut params = sig_params(&self.params, true)?;
+
+        #[cfg(not(feature = "fips"))]
+        if unsafe {
+            EVP_DigestSignInit_ex(
+                self.sigctx.as_mut().unwrap().as_mut_ptr(),
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+                get_libctx(),
+                std::ptr::null(),
+                some_or_err!(mut self.private_key).as_mut_ptr(),
+                params.as_mut_ptr(),
+            )
+        } != 1
+        {
+            return Err(CKR_DEVICE_ERROR)?;
+        }
+        #[cfg(feature = "fips")]
+        self.sigctx.as_mut().unwrap().digest_sign_init(
+            std::ptr::null_mut(),
+            some_or_err!(self.private_key),
+            params.as_mut_ptr(),
+        )?;
+        Ok(())
+    }
+
+    fn verify_begin(&mut self) -> Result<()> {
+        self.in_use = true;
+
+        let mut params = sig_params(&self.params, false)?;
+
+        #[cfg(not(feature = "fips"))]
+        if unsafe {
+            EVP_DigestVerifyInit_ex(
+                self.sigctx.as_mut().unwrap().as_mut_ptr(),
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+                get_libctx(),
+                std::ptr::null(),
+                some_or_err!(mut self.public_key).as_mut_ptr(),
+                params.as_mut_ptr(),
+            )
+        } != 1
+        {
+            return Err(CKR_DEVICE_ERROR)?;
+        }
+        #[cfg(feature = "fips")]
+        self.sigctx.as_mut().unwrap().digest_verify_init(
+            std::ptr::null_mut(),
+            some_or_err!(self.public_key),
+            params.as_mut_ptr(),
+        )?;
+        Ok(())
+    }
+}
+
+impl MechOperation for MlDsaOperation {
+    fn mechanism(&self) -> Result<CK_MECHANISM_TYPE> {
+        Ok(self.mech)
+    }
+
+    fn finalized(&self) -> bool {
+        self.finalized
+    }
+}
+
+impl Sign for MlDsaOperation {
+    fn sign(&mut self, data: &[u8], signature: &mut [u8]) -> Result<()> {
+        if self.in_use {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        if self.finalized {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        self.sign_begin()?;
+        self.update(data)?;
+        self.sign_final(signature)
+    }
+
+    fn sign_update(&mut self, data: &[u8]) -> Result<()> {
+        if self.finalized {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        if !self.in_use {
+            /* the generic pre-hash mechanism is single-part only */
+            if self.mech == CKM_HASH_ML_DSA {
+                return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+            }
+            self.sign_begin()?;
+        }
+        self.update(data)
+    }
+
+    fn sign_final(&mut self, signature: &mut [u8]) -> Result<()> {
+        if !self.in_use {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        if self.finalized {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        self.finalized = true;
+
+        let mut message = self.message()?;
+        let siglen;
+
+        #[cfg(not(feature = "fips"))]
+        {
+            let mut slen = signature.len();
+            let slen_ptr = &mut slen;
+            let ret = unsafe {
+                EVP_DigestSign(
+                    self.sigctx.as_mut().unwrap().as_mut_ptr(),
+                    signature.as_mut_ptr(),
+                    slen_ptr,
+                    message.as_ptr(),
+                    message.len(),
+                )
+            };
+            message.zeroize();
+            if ret != 1 {
+                return Err(CKR_DEVICE_ERROR)?;
+            }
+            siglen = slen;
+        }
+
+        #[cfg(feature = "fips")]
+        {
+            let ret = self
+                .sigctx
+                .as_mut()
+                .unwrap()
+                .digest_sign(signature, &mut message.as_slice());
+            message.zeroize();
+            siglen = ret?;
+        }
+        if siglen != signature.len() {
+            return Err(CKR_DEVICE_ERROR)?;
+        }
+
+        Ok(())
+    }
+
+    fn signature_len(&self) -> Result<usize> {
+        Ok(self.output_len)
+    }
+}
+
+impl Verify for MlDsaOperation {
+    fn verify(&mut self, data: &[u8], signature: &[u8]) -> Result<()> {
+        if self.in_use {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        if self.finalized {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        self.verify_begin()?;
+        self.update(data)?;
+        self.verify_final(signature)
+    }
+
+    fn verify_update(&mut self, data: &[u8]) -> Result<()> {
+        if self.finalized {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        if !self.in_use {
+            /* the generic pre-hash mechanism is single-part only */
+            if self.mech == CKM_HASH_ML_DSA {
+                return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+            }
+            self.verify_begin()?;
+        }
+        self.update(data)
+    }
+
+    fn verify_final(&mut self, signature: &[u8]) -> Result<()> {
+        if !self.in_use {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        if self.finalized {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+
+        self.finalized = true;
+
+        let message = self.message()?;
+
+        #[cfg(not(feature = "fips"))]
+        if unsafe {
+            EVP_DigestVerify(
+                self.sigctx.as_mut().unwrap().as_mut_ptr(),
+                signature.as_ptr(),
+                signature.len(),
+                message.as_ptr(),
+                message.len(),
+            )
+        } != 1
+        {
+            return Err(CKR_SIGNATURE_INVALID)?;
+        }
+
+        #[cfg(feature = "fips")]
+        self.sigctx
+            .as_mut()
+            .unwrap()
+            .digest_verify(&signature, &mut message.as_slice())?;
+
+        Ok(())
+    }
+
+    fn signature_len(&self) -> Result<usize> {
+        Ok(self.output_len)
+    }
+}
+
+impl MessageOperation for MlDsaOperation {
+    fn busy(&self) -> bool {
+        self.in_use
+    }
+    fn finalize(&mut self) -> Result<()> {
+        if self.in_use {
+            return Err(CKR_OPERATION_ACTIVE)?;
+        }
+        self.finalized = true;
+        Ok(())
+    }
+
+    /* Per-message parameters are used in place of the ones given at
+     * initialization for the current message only */
+    fn msg_params(
+        &mut self,
+        param: CK_VOID_PTR,
+        paramlen: CK_ULONG,
+    ) -> Result<()> {
+        if param.is_null() {
+            if paramlen != 0 {
+                return Err(CKR_ARGUMENTS_BAD)?;
+            }
+            return Ok(());
+        }
+        let mech = CK_MECHANISM {
+            mechanism: self.mech,
+            pParameter: param,
+            ulParameterLen: paramlen,
+        };
+        let params = parse_params(&mech)?;
+        self.init_params = Some(std::mem::replace(&mut self.params, params));
+        Ok(())
+    }
+
+    /* Keys and contexts are reused across messages */
+    fn msg_reset(&mut self) -> Result<()> {
+        self.finalized = false;
+        self.in_use = false;
+        self.data.zeroize();
+        if let Some(params) = self.init_params.take() {
+            self.params.context.zeroize();
+            self.params = params;
+        }
+        Ok(())
+    }
+}
+
+impl MsgSign for MlDsaOperation {}
+
+impl MsgVerify for MlDsaOperation {}
@@ -0,0 +1,189 @@
+// Copyright 2024 Simo Sorce
+// See LICENSE.txt file for terms
+
+use std::ffi::{c_char, c_int};
+
+use crate::attribute::Attribute;
+use crate::error::Result;
+use crate::interface::*;
+use crate::mlkem::*;
+use crate::object::Object;
+use crate::ossl::bindings::*;
+use crate::ossl::common::*;
+
+use zeroize::Zeroize;
+
+/* ML-KEM algorithm names as used in OpenSSL */
+const NAME_ML_KEM_512: &[u8] = b"ML-KEM-512\0";
+const NAME_ML_KEM_768: &[u8] = b"ML-KEM-768\0";
+const NAME_ML_KEM_1024: &[u8] = b"ML-KEM-1024\0";
+
+fn mlkem_param_set_to_name(
+    param_set: CK_ML_KEM_PARAMETER_SET_TYPE,
+) -> Result<*const c_char> {
+    match param_set {
+        CKP_ML_KEM_512 => Ok(name_as_char(NAME_ML_KEM_512)),
+        CKP_ML_KEM_768 => Ok(name_as_char(NAME_ML_KEM_768)),
+        CKP_ML_KEM_1024 => Ok(name_as_char(NAME_ML_KEM_1024)),
+        _ => Err(CKR_KEY_TYPE_INCONSISTENT)?,
+    }
+}
+
+pub fn mlkem_object_to_params(
+    key: &Object,
+    class: CK_OBJECT_CLASS,
+) -> Result<(*const c_char, OsslParam<'_>)> {
+    let kclass = key.get_attr_as_ulong(CKA_CLASS)?;
+    if kclass != class {
+        Err(CKR_KEY_TYPE_INCONSISTENT)?;
+    }
+    let name =
+        mlkem_param_set_to_name(key.get_attr_as_ulong(CKA_PARAMETER_SET)?)?;
+
+    let mut params = OsslParam::with_capacity(2);
+    params.zeroize = true;
+
+    match kclass {
+        CKO_PUBLIC_KEY => {
+            params.add_octet_string(
+                name_as_char(OSSL_PKEY_PARAM_PUB_KEY),
+                key.get_attr_as_bytes(CKA_VALUE)?,
+            )?;
+        }
+        CKO_PRIVATE_KEY => {
+            /* the seed is preferred when available, the expanded key is
+             * checked against it by OpenSSL */
+            if let Some(seed) = key.get_attr(CKA_SEED) {
+                params.add_octet_string(
+                    name_as_char(OSSL_PKEY_PARAM_ML_KEM_SEED),
+                    seed.get_value(),
+                )?;
+            }
+            if let Some(value) = key.get_attr(CKA_VALUE) {
+                params.add_octet_string(
+                    name_as_char(OSSL_PKEY_PARAM_PRIV_KEY),
+                    value.get_value(),
+                )?;
+            }
+        }
+        _ => return Err(CKR_KEY_TYPE_INCONSISTENT)?,
+    }
+
+    params.finalize();
+
+    Ok((name, params))
+}
+
+#[derive(Debug)]
+pub struct MlKemOperation {}
+
+impl MlKemOperation {
+    pub fn generate_keypair(
+        param_set: CK_ML_KEM_PARAMETER_SET_TYPE,
+        pubkey: &mut Object,
+        privkey: &mut Object,
+    ) -> Result<()> {
+        let evp_pkey = EvpPkey::generate(
+            mlkem_param_set_to_name(param_set)?,
+            &OsslParam::empty(),
+        )?;
+
+        let mut params: *mut OSSL_PARAM = std::ptr::null_mut();
+        let res = unsafe {
+            EVP_PKEY_todata(
+                evp_pkey.as_ptr(),
+                c_int::try_from(EVP_PKEY_KEYPAIR)?,
+                &mut params,
+            )
+        };
+        if res != 1 {
+            return Err(CKR_DEVICE_ERROR)?;
+        }
+        let params = OsslParam::from_ptr(params)?;
+
+        /* Public Key */
+        let value = params
+            .get_octet_string(name_as_char(OSSL_PKEY_PARAM_PUB_KEY))?
+            .to_vec();
+        pubkey.set_attr(Attribute::from_bytes(CKA_VALUE, value))?;
+
+        /* Private Key */
+        let value = params
+            .get_octet_string(name_as_char(OSSL_PKEY_PARAM_PRIV_KEY))?
+            .to_vec();
+        privkey.set_attr(Attribute::from_bytes(CKA_VALUE, value))?;
+        /* the seed is retained by default on generation */
+        if let Ok(seed) =
+            params.get_octet_string(name_as_char(OSSL_PKEY_PARAM_ML_KEM_SEED))
+        {
+            privkey.set_attr(Attribute::from_bytes(CKA_SEED, seed.to_vec()))?;
+        }
+        Ok(())
+    }
+
+    /// Encapsulates a new shared secret to the public key, the ciphertext
+    /// is written in the provided buffer and the shared secret is
+    /// returned together with the ciphertext length
+    pub fn encapsulate(
+        key: &Object,
+        ciphertext: &mut [u8],
+    ) -> Result<(Vec<u8>, usize)> {
+        let mut pkey = EvpPkey::pubkey_from_object(key)?;
+        let mut ctx = pkey.new_ctx()?;
+        let res = unsafe {
+            EVP_PKEY_encapsulate_init(ctx.as_mut_ptr(), std::ptr::null())
+        };
+        if res != 1 {
+            return Err(CKR_DEVICE_ERROR)?;
+        }
+
+        let mut outlen = ciphertext.len();
+        let mut secret = vec![0u8; ML_KEM_SHARED_SECRET_BYTES];
+        let mut secretlen = secret.len();
+        let res = unsafe {
+            EVP_PKEY_encapsulate(
+                ctx.as_mut_ptr(),
+                ciphertext.as_mut_ptr(),
+                &mut outlen,
+                secret.as_mut_ptr(),
+                &mut secretlen,
+            )
+        };
+        if res != 1 {
+            secret.zeroize();
+            return Err(CKR_DEVICE_ERROR)?;
+        }
+        secret.truncate(secretlen);
+        Ok((secret, outlen))
+    }
+
+    /// Recovers the shared secret from the ciphertext with the private key
+    pub fn decapsulate(key: &Object, ciphertext: &[u8]) -> Result<Vec<u8>> {
+        let mut pkey = EvpPkey::privkey_from_object(key)?;
+        let mut ctx = pkey.new_ctx()?;
+        let res = unsafe {
+            EVP_PKEY_decapsulate_init(ctx.as_mut_ptr(), std::ptr::null())
+        };
+        if res != 1 {
+            return Err(CKR_DEVICE_ERROR)?;
+        }
+
+        let mut secret = vec![0u8; ML_KEM_SHARED_SECRET_BYTES];
+        let mut secretlen = secret.len();
+        let res = unsafe {
+            EVP_PKEY_decapsulate(
+                ctx.as_mut_ptr(),
+                secret.as_mut_ptr(),
+                &mut secretlen,
+                ciphertext.as_ptr(),
+                ciphertext.len(),
+            )
+        };
+        if res != 1 {
+            secret.zeroize();
+            return Err(CKR_ENCRYPTED_DATA_INVALID)?;
+        }
+        secret.truncate(secretlen);
+        Ok(secret)
+    }
+}
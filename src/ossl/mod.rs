@@ -29,9 +29,16 @@ pub fn get_libctx() -> *mut bindings::OSSL_LIB_CTX {
 }
 
 pub mod aes;
+
+#[cfg(feature = "chacha20")]
+pub mod chacha20;
+
 pub mod common;
 pub mod drbg;
 
+#[cfg(feature = "dsa")]
+pub mod dsa;
+
 // the derive code for both ECDSA and Montgomery curves
 #[cfg(feature = "ecdh")]
 pub mod ecdh;
@@ -45,6 +52,9 @@ pub mod montgomery;
 #[cfg(feature = "eddsa")]
 pub mod eddsa;
 
+#[cfg(feature = "ffdh")]
+pub mod ffdh;
+
 #[cfg(feature = "fips")]
 pub mod fips;
 
@@ -54,13 +64,28 @@ pub mod hkdf;
 #[cfg(all(feature = "hmac", feature = "fips"))]
 pub mod hmac;
 
+#[cfg(feature = "hss")]
+pub mod hss;
+
 #[cfg(all(feature = "sp800_108", feature = "fips"))]
 pub mod kbkdf;
 
+#[cfg(feature = "mldsa")]
+pub mod mldsa;
+
+#[cfg(feature = "mlkem")]
+pub mod mlkem;
+
 #[cfg(all(feature = "pbkdf2", feature = "fips"))]
 pub mod pbkdf2;
 
 pub mod rsa;
 
+#[cfg(feature = "slhdsa")]
+pub mod slhdsa;
+
 #[cfg(all(feature = "sshkdf", feature = "fips"))]
 pub mod sshkdf;
+
+#[cfg(all(feature = "ec_montgomery", not(feature = "fips")))]
+pub mod xeddsa;
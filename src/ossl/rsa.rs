@@ -215,6 +215,7 @@ pub struct RsaPKCSOperation {
     #[cfg(not(feature = "fips"))]
     sigctx: Option<EvpMdCtx>,
     pss: RsaPssParams,
+    init_pss: Option<(RsaPssParams, usize)>,
     oaep: RsaOaepParams,
     #[cfg(feature = "fips")]
     fips_approved: Option<bool>,
@@ -235,6 +236,7 @@ impl RsaPKCSOperation {
     ) -> Result<usize> {
         match mech {
             CKM_RSA_PKCS => Ok(modulus - 11),
+            CKM_RSA_X_509 => Ok(modulus),
             CKM_RSA_PKCS_OAEP => {
                 let hs = Self::hash_len(hash)?;
                 Ok(modulus - 2 * hs - 2)
@@ -271,6 +273,7 @@ impl RsaPKCSOperation {
             in_use: false,
             sigctx: None,
             pss: no_pss_params(),
+            init_pss: None,
             oaep: oaep_params,
             #[cfg(feature = "fips")]
             fips_approved: None,
@@ -306,6 +309,7 @@ impl RsaPKCSOperation {
             in_use: false,
             sigctx: None,
             pss: no_pss_params(),
+            init_pss: None,
             oaep: oaep_params,
             #[cfg(feature = "fips")]
             fips_approved: None,
@@ -332,6 +336,7 @@ impl RsaPKCSOperation {
             mech: mech.mechanism,
             max_input: match mech.mechanism {
                 CKM_RSA_PKCS => modulus.len() - 11,
+                CKM_RSA_X_509 => modulus.len(),
                 CKM_RSA_PKCS_PSS => Self::hash_len(pss_params.hash)?,
                 _ => 0,
             },
@@ -341,13 +346,14 @@ impl RsaPKCSOperation {
             finalized: false,
             in_use: false,
             sigctx: match mech.mechanism {
-                CKM_RSA_PKCS => None,
+                CKM_RSA_PKCS | CKM_RSA_X_509 => None,
                 #[cfg(feature = "fips")]
                 _ => Some(ProviderSignatureCtx::new(name_as_char(RSA_NAME))?),
                 #[cfg(not(feature = "fips"))]
                 _ => Some(EvpMdCtx::new()?),
             },
             pss: pss_params,
+            init_pss: None,
             oaep: no_oaep_params(),
             #[cfg(feature = "fips")]
             fips_approved: None,
@@ -373,6 +379,7 @@ impl RsaPKCSOperation {
             mech: mech.mechanism,
             max_input: match mech.mechanism {
                 CKM_RSA_PKCS => modulus.len() - 11,
+                CKM_RSA_X_509 => modulus.len(),
                 _ => 0,
             },
             output_len: modulus.len(),
@@ -381,13 +388,14 @@ impl RsaPKCSOperation {
             finalized: false,
             in_use: false,
             sigctx: match mech.mechanism {
-                CKM_RSA_PKCS => None,
+                CKM_RSA_PKCS | CKM_RSA_X_509 => None,
                 #[cfg(feature = "fips")]
                 _ => Some(ProviderSignatureCtx::new(name_as_char(RSA_NAME))?),
                 #[cfg(not(feature = "fips"))]
                 _ => Some(EvpMdCtx::new()?),
             },
             pss: pss_params,
+            init_pss: None,
             oaep: no_oaep_params(),
             #[cfg(feature = "fips")]
             fips_approved: None,
@@ -498,6 +506,17 @@ impl RsaPKCSOperation {
         Ok(result)
     }
 
+    /* Raw RSA operates on a modulus sized integer, shorter inputs
+     * are interpreted as big endian numbers and left padded */
+    fn x509_pad(&self, data: &[u8]) -> Result<Vec<u8>> {
+        if data.len() > self.max_input {
+            return Err(CKR_DATA_LEN_RANGE)?;
+        }
+        let mut padded = vec![0u8; self.max_input - data.len()];
+        padded.extend_from_slice(data);
+        Ok(padded)
+    }
+
     fn rsa_sig_params(&self) -> Vec<OSSL_PARAM> {
         let mut params = Vec::<OSSL_PARAM>::new();
         match self.mech {
@@ -519,6 +538,15 @@ impl RsaPKCSOperation {
                     )
                 });
             }
+            CKM_RSA_X_509 => {
+                params.push(unsafe {
+                    OSSL_PARAM_construct_utf8_string(
+                        OSSL_SIGNATURE_PARAM_PAD_MODE.as_ptr() as *const c_char,
+                        OSSL_PKEY_RSA_PAD_MODE_NONE.as_ptr() as *mut c_char,
+                        OSSL_PKEY_RSA_PAD_MODE_NONE.len(),
+                    )
+                });
+            }
             CKM_RSA_PKCS_PSS
             | CKM_SHA1_RSA_PKCS_PSS
             | CKM_SHA224_RSA_PKCS_PSS
@@ -579,6 +607,15 @@ impl RsaPKCSOperation {
                     )
                 });
             }
+            CKM_RSA_X_509 => {
+                params.push(unsafe {
+                    OSSL_PARAM_construct_utf8_string(
+                        OSSL_PKEY_PARAM_PAD_MODE.as_ptr() as *const c_char,
+                        OSSL_PKEY_RSA_PAD_MODE_NONE.as_ptr() as *mut c_char,
+                        OSSL_PKEY_RSA_PAD_MODE_NONE.len(),
+                    )
+                });
+            }
             CKM_RSA_PKCS_OAEP => {
                 params.push(unsafe {
                     OSSL_PARAM_construct_utf8_string(
@@ -645,6 +682,13 @@ impl Encryption for RsaPKCSOperation {
         if self.finalized {
             return Err(CKR_OPERATION_NOT_INITIALIZED)?;
         }
+        let padded: Vec<u8>;
+        let plain = if self.mech == CKM_RSA_X_509 {
+            padded = self.x509_pad(plain)?;
+            padded.as_slice()
+        } else {
+            plain
+        };
         let mut ctx = some_or_err!(mut self.public_key).new_ctx()?;
         if unsafe { EVP_PKEY_encrypt_init(ctx.as_mut_ptr()) } != 1 {
             return Err(CKR_DEVICE_ERROR)?;
@@ -718,7 +762,9 @@ impl Encryption for RsaPKCSOperation {
             return Err(CKR_OPERATION_NOT_INITIALIZED)?;
         }
         match self.mech {
-            CKM_RSA_PKCS | CKM_RSA_PKCS_OAEP => Ok(self.output_len),
+            CKM_RSA_PKCS | CKM_RSA_X_509 | CKM_RSA_PKCS_OAEP => {
+                Ok(self.output_len)
+            }
             _ => {
                 self.finalized = true;
                 Err(CKR_GENERAL_ERROR)?
@@ -831,7 +877,9 @@ impl Decryption for RsaPKCSOperation {
             return Err(CKR_OPERATION_NOT_INITIALIZED)?;
         }
         match self.mech {
-            CKM_RSA_PKCS | CKM_RSA_PKCS_OAEP => Ok(self.output_len),
+            CKM_RSA_PKCS | CKM_RSA_X_509 | CKM_RSA_PKCS_OAEP => {
+                Ok(self.output_len)
+            }
             _ => {
                 self.finalized = true;
                 Err(CKR_GENERAL_ERROR)?
@@ -849,15 +897,22 @@ impl Sign for RsaPKCSOperation {
             return Err(CKR_OPERATION_NOT_INITIALIZED)?;
         }
         match self.mech {
-            CKM_RSA_PKCS | CKM_RSA_PKCS_PSS => {
+            CKM_RSA_PKCS | CKM_RSA_X_509 | CKM_RSA_PKCS_PSS => {
                 self.finalized = true;
                 if match self.mech {
-                    CKM_RSA_PKCS => data.len() > self.max_input,
+                    CKM_RSA_PKCS | CKM_RSA_X_509 => data.len() > self.max_input,
                     CKM_RSA_PKCS_PSS => data.len() != self.max_input,
                     _ => return Err(CKR_GENERAL_ERROR)?,
                 } {
                     return Err(CKR_DATA_LEN_RANGE)?;
                 }
+                let padded: Vec<u8>;
+                let data = if self.mech == CKM_RSA_X_509 {
+                    padded = self.x509_pad(data)?;
+                    padded.as_slice()
+                } else {
+                    data
+                };
                 if signature.len() != self.output_len {
                     return Err(CKR_GENERAL_ERROR)?;
                 }
@@ -921,8 +976,11 @@ impl Sign for RsaPKCSOperation {
             return Err(CKR_OPERATION_NOT_INITIALIZED)?;
         }
         if !self.in_use {
-            if self.mech == CKM_RSA_PKCS || self.mech == CKM_RSA_PKCS_PSS {
-                return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+            match self.mech {
+                CKM_RSA_PKCS | CKM_RSA_X_509 | CKM_RSA_PKCS_PSS => {
+                    return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+                }
+                _ => (),
             }
             self.in_use = true;
 
@@ -1023,7 +1081,7 @@ impl Verify for RsaPKCSOperation {
         if self.finalized {
             return Err(CKR_OPERATION_NOT_INITIALIZED)?;
         }
-        if self.mech == CKM_RSA_PKCS {
+        if self.mech == CKM_RSA_PKCS || self.mech == CKM_RSA_X_509 {
             self.finalized = true;
             if data.len() > self.max_input {
                 return Err(CKR_DATA_LEN_RANGE)?;
@@ -1031,6 +1089,13 @@ impl Verify for RsaPKCSOperation {
             if signature.len() != self.output_len {
                 return Err(CKR_GENERAL_ERROR)?;
             }
+            let padded: Vec<u8>;
+            let data = if self.mech == CKM_RSA_X_509 {
+                padded = self.x509_pad(data)?;
+                padded.as_slice()
+            } else {
+                data
+            };
             let mut ctx = some_or_err!(mut self.public_key).new_ctx()?;
             let res = unsafe { EVP_PKEY_verify_init(ctx.as_mut_ptr()) };
             if res != 1 {
@@ -1067,7 +1132,7 @@ impl Verify for RsaPKCSOperation {
             return Err(CKR_OPERATION_NOT_INITIALIZED)?;
         }
         if !self.in_use {
-            if self.mech == CKM_RSA_PKCS {
+            if self.mech == CKM_RSA_PKCS || self.mech == CKM_RSA_X_509 {
                 return Err(CKR_OPERATION_NOT_INITIALIZED)?;
             }
             self.in_use = true;
@@ -1148,3 +1213,156 @@ impl Verify for RsaPKCSOperation {
         Ok(self.output_len)
     }
 }
+
+impl SignRecover for RsaPKCSOperation {
+    fn sign_recover(
+        &mut self,
+        data: &[u8],
+        signature: &mut [u8],
+    ) -> Result<usize> {
+        match self.mech {
+            CKM_RSA_PKCS | CKM_RSA_X_509 => (),
+            _ => return Err(CKR_MECHANISM_INVALID)?,
+        }
+        if signature.len() < self.output_len {
+            return Err(Error::buf_too_small(self.output_len));
+        }
+        self.sign(data, &mut signature[..self.output_len])?;
+        Ok(self.output_len)
+    }
+
+    fn sign_recover_len(&self) -> Result<usize> {
+        Ok(self.output_len)
+    }
+}
+
+impl VerifyRecover for RsaPKCSOperation {
+    fn verify_recover(
+        &mut self,
+        signature: &[u8],
+        data: &mut [u8],
+    ) -> Result<usize> {
+        if self.in_use {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        if self.finalized {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        match self.mech {
+            CKM_RSA_PKCS | CKM_RSA_X_509 => (),
+            _ => return Err(CKR_MECHANISM_INVALID)?,
+        }
+        if signature.len() != self.output_len {
+            self.finalized = true;
+            return Err(CKR_SIGNATURE_LEN_RANGE)?;
+        }
+        let mut ctx = some_or_err!(mut self.public_key).new_ctx()?;
+        let res = unsafe { EVP_PKEY_verify_recover_init(ctx.as_mut_ptr()) };
+        if res != 1 {
+            self.finalized = true;
+            return Err(CKR_DEVICE_ERROR)?;
+        }
+        let params = self.rsa_sig_params();
+        let res = unsafe {
+            EVP_PKEY_CTX_set_params(ctx.as_mut_ptr(), params.as_ptr())
+        };
+        if res != 1 {
+            self.finalized = true;
+            return Err(CKR_DEVICE_ERROR)?;
+        }
+
+        /* openssl always requires a modulus sized output buffer,
+         * even when the padding makes the recovered data shorter */
+        let mut recovered = vec![0u8; self.output_len];
+        let mut outlen = recovered.len();
+        let outlen_ptr: *mut usize = &mut outlen;
+        let res = unsafe {
+            EVP_PKEY_verify_recover(
+                ctx.as_mut_ptr(),
+                recovered.as_mut_ptr(),
+                outlen_ptr,
+                signature.as_ptr(),
+                signature.len(),
+            )
+        };
+        if res != 1 {
+            self.finalized = true;
+            return Err(CKR_SIGNATURE_INVALID)?;
+        }
+        if data.len() < outlen {
+            return Err(Error::buf_too_small(outlen));
+        }
+
+        self.finalized = true;
+
+        data[..outlen].copy_from_slice(&recovered[..outlen]);
+        Ok(outlen)
+    }
+
+    fn verify_recover_len(&self) -> Result<usize> {
+        Ok(self.max_input)
+    }
+}
+
+impl MessageOperation for RsaPKCSOperation {
+    fn busy(&self) -> bool {
+        self.in_use
+    }
+    fn finalize(&mut self) -> Result<()> {
+        if self.in_use {
+            return Err(CKR_OPERATION_ACTIVE)?;
+        }
+        self.finalized = true;
+        Ok(())
+    }
+
+    /* Only PSS accepts per-message parameters, they are used in place
+     * of the ones given at initialization for the current message only */
+    fn msg_params(
+        &mut self,
+        param: CK_VOID_PTR,
+        paramlen: CK_ULONG,
+    ) -> Result<()> {
+        if param.is_null() {
+            if paramlen != 0 {
+                return Err(CKR_ARGUMENTS_BAD)?;
+            }
+            return Ok(());
+        }
+        let mech = CK_MECHANISM {
+            mechanism: self.mech,
+            pParameter: param,
+            ulParameterLen: paramlen,
+        };
+        let pss_params = parse_pss_params(&mech)?;
+        if pss_params.hash == 0 {
+            /* not a PSS mechanism */
+            return Err(CKR_ARGUMENTS_BAD)?;
+        }
+        let max_input = if self.mech == CKM_RSA_PKCS_PSS {
+            Self::hash_len(pss_params.hash)?
+        } else {
+            self.max_input
+        };
+        self.init_pss = Some((
+            std::mem::replace(&mut self.pss, pss_params),
+            std::mem::replace(&mut self.max_input, max_input),
+        ));
+        Ok(())
+    }
+
+    /* Keys and contexts are reused across messages */
+    fn msg_reset(&mut self) -> Result<()> {
+        self.finalized = false;
+        self.in_use = false;
+        if let Some((pss, max_input)) = self.init_pss.take() {
+            self.pss = pss;
+            self.max_input = max_input;
+        }
+        Ok(())
+    }
+}
+
+impl MsgSign for RsaPKCSOperation {}
+
+impl MsgVerify for RsaPKCSOperation {}
@@ -0,0 +1,455 @@
+// Copyright 2024 Simo Sorce
+// See LICENSE.txt file for terms
+
+use std::ffi::{c_char, c_int};
+
+use crate::attribute::Attribute;
+use crate::error::Result;
+use crate::interface::*;
+use crate::mechanism::*;
+use crate::object::Object;
+use crate::ossl::bindings::*;
+use crate::ossl::common::*;
+use crate::slhdsa::*;
+use crate::{cast_params, some_or_err};
+
+#[cfg(feature = "fips")]
+use crate::ossl::fips::*;
+
+#[cfg(not(feature = "fips"))]
+use crate::ossl::get_libctx;
+
+use zeroize::Zeroize;
+
+/* FIPS 205 limits the context string to 255 bytes */
+const MAX_CONTEXT_LEN: usize = 255;
+
+/* SLH-DSA algorithm names as used in OpenSSL */
+fn slhdsa_param_set_to_name(
+    param_set: CK_SLH_DSA_PARAMETER_SET_TYPE,
+) -> Result<*const c_char> {
+    let name: &[u8] = match param_set {
+        CKP_SLH_DSA_SHA2_128S => b"SLH-DSA-SHA2-128s\0",
+        CKP_SLH_DSA_SHAKE_128S => b"SLH-DSA-SHAKE-128s\0",
+        CKP_SLH_DSA_SHA2_128F => b"SLH-DSA-SHA2-128f\0",
+        CKP_SLH_DSA_SHAKE_128F => b"SLH-DSA-SHAKE-128f\0",
+        CKP_SLH_DSA_SHA2_192S => b"SLH-DSA-SHA2-192s\0",
+        CKP_SLH_DSA_SHAKE_192S => b"SLH-DSA-SHAKE-192s\0",
+        CKP_SLH_DSA_SHA2_192F => b"SLH-DSA-SHA2-192f\0",
+        CKP_SLH_DSA_SHAKE_192F => b"SLH-DSA-SHAKE-192f\0",
+        CKP_SLH_DSA_SHA2_256S => b"SLH-DSA-SHA2-256s\0",
+        CKP_SLH_DSA_SHAKE_256S => b"SLH-DSA-SHAKE-256s\0",
+        CKP_SLH_DSA_SHA2_256F => b"SLH-DSA-SHA2-256f\0",
+        CKP_SLH_DSA_SHAKE_256F => b"SLH-DSA-SHAKE-256f\0",
+        _ => return Err(CKR_KEY_TYPE_INCONSISTENT)?,
+    };
+    Ok(name_as_char(name))
+}
+
+pub fn slhdsa_object_to_params(
+    key: &Object,
+    class: CK_OBJECT_CLASS,
+) -> Result<(*const c_char, OsslParam<'_>)> {
+    let kclass = key.get_attr_as_ulong(CKA_CLASS)?;
+    if kclass != class {
+        Err(CKR_KEY_TYPE_INCONSISTENT)?;
+    }
+    let name =
+        slhdsa_param_set_to_name(key.get_attr_as_ulong(CKA_PARAMETER_SET)?)?;
+
+    let mut params = OsslParam::with_capacity(1);
+    params.zeroize = true;
+
+    match kclass {
+        CKO_PUBLIC_KEY => {
+            params.add_octet_string(
+                name_as_char(OSSL_PKEY_PARAM_PUB_KEY),
+                key.get_attr_as_bytes(CKA_VALUE)?,
+            )?;
+        }
+        CKO_PRIVATE_KEY => {
+            params.add_octet_string(
+                name_as_char(OSSL_PKEY_PARAM_PRIV_KEY),
+                key.get_attr_as_bytes(CKA_VALUE)?,
+            )?;
+        }
+        _ => return Err(CKR_KEY_TYPE_INCONSISTENT)?,
+    }
+
+    params.finalize();
+
+    Ok((name, params))
+}
+
+#[derive(Debug)]
+struct SlhDsaParams {
+    hedge: CK_HEDGE_TYPE,
+    context: Vec<u8>,
+}
+
+fn parse_params(mech: &CK_MECHANISM) -> Result<SlhDsaParams> {
+    if mech.mechanism != CKM_SLH_DSA {
+        Err(CKR_MECHANISM_INVALID)?;
+    }
+    if mech.ulParameterLen == 0 {
+        return Ok(SlhDsaParams {
+            hedge: CKH_HEDGE_PREFERRED,
+            context: Vec::new(),
+        });
+    }
+    let params = cast_params!(mech, CK_SIGN_ADDITIONAL_CONTEXT);
+    match params.hedgeVariant {
+        CKH_HEDGE_PREFERRED
+        | CKH_HEDGE_REQUIRED
+        | CKH_DETERMINISTIC_REQUIRED => (),
+        _ => return Err(CKR_MECHANISM_PARAM_INVALID)?,
+    }
+    let len = usize::try_from(params.ulContextLen)?;
+    if len > MAX_CONTEXT_LEN {
+        Err(CKR_MECHANISM_PARAM_INVALID)?;
+    }
+    let context = if len == 0 {
+        Vec::new()
+    } else {
+        if params.pContext.is_null() {
+            Err(CKR_MECHANISM_PARAM_INVALID)?;
+        }
+        unsafe { std::slice::from_raw_parts(params.pContext, len) }.to_vec()
+    };
+    Ok(SlhDsaParams {
+        hedge: params.hedgeVariant,
+        context,
+    })
+}
+
+fn sig_params<'a>(
+    slhdsa_params: &'a SlhDsaParams,
+    sign: bool,
+) -> Result<OsslParam<'a>> {
+    let mut params = OsslParam::with_capacity(2);
+    if !slhdsa_params.context.is_empty() {
+        params.add_octet_string(
+            name_as_char(OSSL_SIGNATURE_PARAM_CONTEXT_STRING),
+            &slhdsa_params.context,
+        )?;
+    }
+    if sign && slhdsa_params.hedge == CKH_DETERMINISTIC_REQUIRED {
+        params.add_owned_int(
+            name_as_char(OSSL_SIGNATURE_PARAM_DETERMINISTIC),
+            1,
+        )?;
+    }
+    params.finalize();
+    Ok(params)
+}
+
+macro_rules! get_sig_ctx {
+    ($key:ident) => {
+        /* needless match, but otherwise rust complains about experimental attributes on
+         * expressions */
+        match $key {
+            #[cfg(feature = "fips")]
+            _ => Some(ProviderSignatureCtx::new(slhdsa_param_set_to_name(
+                get_param_set($key)?,
+            )?)?),
+            #[cfg(not(feature = "fips"))]
+            _ => Some(EvpMdCtx::new()?),
+        }
+    };
+}
+
+#[derive(Debug)]
+pub struct SlhDsaOperation {
+    mech: CK_MECHANISM_TYPE,
+    output_len: usize,
+    public_key: Option<EvpPkey>,
+    private_key: Option<EvpPkey>,
+    params: SlhDsaParams,
+    data: Vec<u8>,
+    finalized: bool,
+    in_use: bool,
+    #[cfg(not(feature = "fips"))]
+    sigctx: Option<EvpMdCtx>,
+    #[cfg(feature = "fips")]
+    sigctx: Option<ProviderSignatureCtx>,
+}
+
+impl Drop for SlhDsaOperation {
+    fn drop(&mut self) {
+        self.data.zeroize();
+        self.params.context.zeroize();
+    }
+}
+
+impl SlhDsaOperation {
+    pub fn sign_new(
+        mech: &CK_MECHANISM,
+        key: &Object,
+    ) -> Result<SlhDsaOperation> {
+        let (_, _, outlen) = slhdsa_sizes(get_param_set(key)?)?;
+        Ok(SlhDsaOperation {
+            mech: mech.mechanism,
+            output_len: outlen,
+            public_key: None,
+            private_key: Some(EvpPkey::privkey_from_object(key)?),
+            params: parse_params(mech)?,
+            data: Vec::new(),
+            finalized: false,
+            in_use: false,
+            sigctx: get_sig_ctx!(key),
+        })
+    }
+
+    pub fn verify_new(
+        mech: &CK_MECHANISM,
+        key: &Object,
+    ) -> Result<SlhDsaOperation> {
+        let (_, _, outlen) = slhdsa_sizes(get_param_set(key)?)?;
+        Ok(SlhDsaOperation {
+            mech: mech.mechanism,
+            output_len: outlen,
+            public_key: Some(EvpPkey::pubkey_from_object(key)?),
+            private_key: None,
+            params: parse_params(mech)?,
+            data: Vec::new(),
+            finalized: false,
+            in_use: false,
+            sigctx: get_sig_ctx!(key),
+        })
+    }
+
+    pub fn generate_keypair(
+        param_set: CK_SLH_DSA_PARAMETER_SET_TYPE,
+        pubkey: &mut Object,
+        privkey: &mut Object,
+    ) -> Result<()> {
+        let evp_pkey = EvpPkey::generate(
+            slhdsa_param_set_to_name(param_set)?,
+            &OsslParam::empty(),
+        )?;
+
+        let mut params: *mut OSSL_PARAM = std::ptr::null_mut();
+        let res = unsafe {
+            EVP_PKEY_todata(
+                evp_pkey.as_ptr(),
+                c_int::try_from(EVP_PKEY_KEYPAIR)?,
+                &mut params,
+            )
+        };
+        if res != 1 {
+            return Err(CKR_DEVICE_ERROR)?;
+        }
+        let params = OsslParam::from_ptr(params)?;
+
+        /* Public Key */
+        let value = params
+            .get_octet_string(name_as_char(OSSL_PKEY_PARAM_PUB_KEY))?
+            .to_vec();
+        pubkey.set_attr(Attribute::from_bytes(CKA_VALUE, value))?;
+
+        /* Private Key */
+        let value = params
+            .get_octet_string(name_as_char(OSSL_PKEY_PARAM_PRIV_KEY))?
+            .to_vec();
+        privkey.set_attr(Attribute::from_bytes(CKA_VALUE, value))?;
+        Ok(())
+    }
+}
+
+impl MechOperation for SlhDsaOperation {
+    fn mechanism(&self) -> Result<CK_MECHANISM_TYPE> {
+        Ok(self.mech)
+    }
+
+    fn finalized(&self) -> bool {
+        self.finalized
+    }
+}
+
+impl Sign for SlhDsaOperation {
+    fn sign(&mut self, data: &[u8], signature: &mut [u8]) -> Result<()> {
+        if self.in_use {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        if self.finalized {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        self.sign_update(data)?;
+        self.sign_final(signature)
+    }
+
+    fn sign_update(&mut self, data: &[u8]) -> Result<()> {
+        if self.finalized {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        if !self.in_use {
+            self.in_use = true;
+
+            let mut params = sig_params(&self.params, true)?;
+
+            #[cfg(not(feature = "fips"))]
+            if unsafe {
+                EVP_DigestSignInit_ex(
+                    self.sigctx.as_mut().unwrap().as_mut_ptr(),
+                    std::ptr::null_mut(),
+                    std::ptr::null_mut(),
+                    get_libctx(),
+                    std::ptr::null(),
+                    some_or_err!(mut self.private_key).as_mut_ptr(),
+                    params.as_mut_ptr(),
+                )
+            } != 1
+            {
+                return Err(CKR_DEVICE_ERROR)?;
+            }
+            #[cfg(feature = "fips")]
+            self.sigctx.as_mut().unwrap().digest_sign_init(
+                std::ptr::null_mut(),
+                some_or_err!(self.private_key),
+                params.as_mut_ptr(),
+            )?;
+        }
+
+        /* OpenSSL API does not support multi-part operation so we need to emulate it as PKCS#11
+         * supports it with this mechanism */
+        self.data.extend_from_slice(data);
+        Ok(())
+    }
+
+    fn sign_final(&mut self, signature: &mut [u8]) -> Result<()> {
+        if !self.in_use {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        if self.finalized {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        self.finalized = true;
+
+        let siglen;
+
+        #[cfg(not(feature = "fips"))]
+        {
+            let mut slen = signature.len();
+            let slen_ptr = &mut slen;
+            if unsafe {
+                EVP_DigestSign(
+                    self.sigctx.as_mut().unwrap().as_mut_ptr(),
+                    signature.as_mut_ptr(),
+                    slen_ptr,
+                    self.data.as_ptr(),
+                    self.data.len(),
+                )
+            } != 1
+            {
+                return Err(CKR_DEVICE_ERROR)?;
+            }
+            siglen = slen;
+        }
+
+        #[cfg(feature = "fips")]
+        {
+            siglen = self
+                .sigctx
+                .as_mut()
+                .unwrap()
+                .digest_sign(signature, &mut self.data.as_slice())?;
+        }
+        if siglen != signature.len() {
+            return Err(CKR_DEVICE_ERROR)?;
+        }
+
+        Ok(())
+    }
+
+    fn signature_len(&self) -> Result<usize> {
+        Ok(self.output_len)
+    }
+}
+
+impl Verify for SlhDsaOperation {
+    fn verify(&mut self, data: &[u8], signature: &[u8]) -> Result<()> {
+        if self.in_use {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        if self.finalized {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        self.verify_update(data)?;
+        self.verify_final(signature)
+    }
+
+    fn verify_update(&mut self, data: &[u8]) -> Result<()> {
+        if self.finalized {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        if !self.in_use {
+            self.in_use = true;
+
+            let mut params = sig_params(&self.params, false)?;
+
+            #[cfg(not(feature = "fips"))]
+            if unsafe {
+                EVP_DigestVerifyInit_ex(
+                    self.sigctx.as_mut().unwrap().as_mut_ptr(),
+                    std::ptr::null_mut(),
+                    std::ptr::null_mut(),
+                    get_libctx(),
+                    std::ptr::null(),
+                    some_or_err!(mut self.public_key).as_mut_ptr(),
+                    params.as_mut_ptr(),
+                )
+            } != 1
+            {
+                return Err(CKR_DEVICE_ERROR)?;
+            }
+            #[cfg(feature = "fips")]
+            self.sigctx.as_mut().unwrap().digest_verify_init(
+                std::ptr::null_mut(),
+                some_or_err!(self.public_key),
+                params.as_mut_ptr(),
+            )?;
+        }
+
+        /* OpenSSL API does not support multi-part operation so we need to emulate it as PKCS#11
+         * supports it with this mechanism */
+        self.data.extend_from_slice(data);
+        Ok(())
+    }
+
+    fn verify_final(&mut self, signature: &[u8]) -> Result<()> {
+        if !self.in_use {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        if self.finalized {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+
+        self.finalized = true;
+
+        #[cfg(not(feature = "fips"))]
+        if unsafe {
+            EVP_DigestVerify(
+                self.sigctx.as_mut().unwrap().as_mut_ptr(),
+                signature.as_ptr(),
+                signature.len(),
+                self.data.as_ptr(),
+                self.data.len(),
+            )
+        } != 1
+        {
+            return Err(CKR_SIGNATURE_INVALID)?;
+        }
+
+        #[cfg(feature = "fips")]
+        self.sigctx
+            .as_mut()
+            .unwrap()
+            .digest_verify(&signature, &mut self.data.as_slice())?;
+
+        Ok(())
+    }
+
+    fn signature_len(&self) -> Result<usize> {
+        Ok(self.output_len)
+    }
+}
@@ -0,0 +1,934 @@
+// Copyright 2024 Simo Sorce
+// See LICENSE.txt file for terms
+
+use std::ffi::{c_int, c_ulong, CStr};
+
+use crate::cast_params;
+use crate::ec::{get_ec_point_from_obj, get_oid_from_obj};
+use crate::error::Result;
+use crate::get_random_data;
+use crate::hash;
+use crate::interface::*;
+use crate::kasn1::oid;
+use crate::mechanism::*;
+use crate::object::Object;
+use crate::ossl::bindings::*;
+
+use constant_time_eq::constant_time_eq;
+use once_cell::sync::Lazy;
+use zeroize::Zeroize;
+
+/* XEdDSA as specified in https://signal.org/docs/specifications/xeddsa/
+ *
+ * OpenSSL does not expose the Edwards curve arithmetic behind Ed25519
+ * and Ed448, so the computations are performed on the short Weierstrass
+ * curves that are isomorphic to Curve25519 and Curve448, using the
+ * generic prime curve support of OpenSSL. Scalar multiplications of
+ * secret values use the constant time Montgomery ladder OpenSSL selects
+ * for curves with a known order and cofactor, and the modular
+ * arithmetic on secret scalars uses the constant time BIGNUM functions.
+ * Points are converted to the Edwards form only to be encoded, which
+ * happens exclusively for public values. */
+
+#[derive(Debug)]
+struct BigNum {
+    ptr: *mut BIGNUM,
+}
+
+impl BigNum {
+    fn new() -> Result<BigNum> {
+        let ptr = unsafe { BN_new() };
+        if ptr.is_null() {
+            return Err(CKR_DEVICE_ERROR)?;
+        }
+        Ok(BigNum { ptr })
+    }
+
+    /* Secret values are kept in secure memory and flagged so that
+     * OpenSSL picks its constant time code paths for them */
+    fn new_secret() -> Result<BigNum> {
+        let ptr = unsafe { BN_secure_new() };
+        if ptr.is_null() {
+            return Err(CKR_DEVICE_ERROR)?;
+        }
+        unsafe { BN_set_flags(ptr, BN_FLG_CONSTTIME as c_int) };
+        Ok(BigNum { ptr })
+    }
+
+    fn from_word(w: c_ulong) -> Result<BigNum> {
+        let bn = BigNum::new()?;
+        if unsafe { BN_set_word(bn.ptr, w) } != 1 {
+            return Err(CKR_DEVICE_ERROR)?;
+        }
+        Ok(bn)
+    }
+
+    fn from_hex(hex: &CStr) -> Result<BigNum> {
+        let bn = BigNum::new()?;
+        let mut ptr = bn.ptr;
+        if unsafe { BN_hex2bn(&mut ptr, hex.as_ptr()) } == 0 {
+            return Err(CKR_DEVICE_ERROR)?;
+        }
+        Ok(bn)
+    }
+
+    fn from_le(bytes: &[u8], secret: bool) -> Result<BigNum> {
+        let bn = if secret {
+            BigNum::new_secret()?
+        } else {
+            BigNum::new()?
+        };
+        if unsafe {
+            BN_lebin2bn(bytes.as_ptr(), c_int::try_from(bytes.len())?, bn.ptr)
+        }
+        .is_null()
+        {
+            return Err(CKR_DEVICE_ERROR)?;
+        }
+        Ok(bn)
+    }
+
+    fn to_le(&self, len: usize) -> Result<Vec<u8>> {
+        let mut v = vec![0u8; len];
+        if unsafe {
+            BN_bn2lebinpad(self.ptr, v.as_mut_ptr(), c_int::try_from(len)?)
+        } < 0
+        {
+            return Err(CKR_DEVICE_ERROR)?;
+        }
+        Ok(v)
+    }
+
+    fn is_zero(&self) -> bool {
+        unsafe { BN_is_zero(self.ptr) == 1 }
+    }
+
+    fn is_odd(&self) -> bool {
+        unsafe { BN_is_odd(self.ptr) == 1 }
+    }
+}
+
+impl Drop for BigNum {
+    fn drop(&mut self) {
+        unsafe {
+            BN_clear_free(self.ptr);
+        }
+    }
+}
+
+unsafe impl Send for BigNum {}
+unsafe impl Sync for BigNum {}
+
+#[derive(Debug)]
+struct BnCtx {
+    ptr: *mut BN_CTX,
+}
+
+impl BnCtx {
+    fn new() -> Result<BnCtx> {
+        let ptr = unsafe { BN_CTX_secure_new() };
+        if ptr.is_null() {
+            return Err(CKR_DEVICE_ERROR)?;
+        }
+        Ok(BnCtx { ptr })
+    }
+}
+
+impl Drop for BnCtx {
+    fn drop(&mut self) {
+        unsafe {
+            BN_CTX_free(self.ptr);
+        }
+    }
+}
+
+#[derive(Debug)]
+struct MontCtx {
+    ptr: *mut BN_MONT_CTX,
+}
+
+impl MontCtx {
+    fn new(m: &BigNum, ctx: &BnCtx) -> Result<MontCtx> {
+        let ptr = unsafe { BN_MONT_CTX_new() };
+        if ptr.is_null() {
+            return Err(CKR_DEVICE_ERROR)?;
+        }
+        let mont = MontCtx { ptr };
+        if unsafe { BN_MONT_CTX_set(mont.ptr, m.ptr, ctx.ptr) } != 1 {
+            return Err(CKR_DEVICE_ERROR)?;
+        }
+        Ok(mont)
+    }
+}
+
+impl Drop for MontCtx {
+    fn drop(&mut self) {
+        unsafe {
+            BN_MONT_CTX_free(self.ptr);
+        }
+    }
+}
+
+#[derive(Debug)]
+struct EcGroup {
+    ptr: *mut EC_GROUP,
+}
+
+impl Drop for EcGroup {
+    fn drop(&mut self) {
+        unsafe {
+            EC_GROUP_free(self.ptr);
+        }
+    }
+}
+
+unsafe impl Send for EcGroup {}
+unsafe impl Sync for EcGroup {}
+
+#[derive(Debug)]
+struct EcPoint {
+    ptr: *mut EC_POINT,
+}
+
+impl EcPoint {
+    fn new(group: &EcGroup) -> Result<EcPoint> {
+        let ptr = unsafe { EC_POINT_new(group.ptr) };
+        if ptr.is_null() {
+            return Err(CKR_DEVICE_ERROR)?;
+        }
+        Ok(EcPoint { ptr })
+    }
+}
+
+impl Drop for EcPoint {
+    fn drop(&mut self) {
+        unsafe {
+            EC_POINT_clear_free(self.ptr);
+        }
+    }
+}
+
+/* Arithmetic modulo p, used on public values only */
+struct Field<'a> {
+    p: &'a BigNum,
+    ctx: BnCtx,
+}
+
+impl<'a> Field<'a> {
+    fn new(p: &'a BigNum) -> Result<Field<'a>> {
+        Ok(Field {
+            p,
+            ctx: BnCtx::new()?,
+        })
+    }
+
+    fn add(&mut self, a: &BigNum, b: &BigNum) -> Result<BigNum> {
+        let r = BigNum::new()?;
+        if unsafe { BN_mod_add(r.ptr, a.ptr, b.ptr, self.p.ptr, self.ctx.ptr) }
+            != 1
+        {
+            return Err(CKR_DEVICE_ERROR)?;
+        }
+        Ok(r)
+    }
+
+    fn sub(&mut self, a: &BigNum, b: &BigNum) -> Result<BigNum> {
+        let r = BigNum::new()?;
+        if unsafe { BN_mod_sub(r.ptr, a.ptr, b.ptr, self.p.ptr, self.ctx.ptr) }
+            != 1
+        {
+            return Err(CKR_DEVICE_ERROR)?;
+        }
+        Ok(r)
+    }
+
+    fn neg(&mut self, a: &BigNum) -> Result<BigNum> {
+        self.sub(&BigNum::from_word(0)?, a)
+    }
+
+    fn mul(&mut self, a: &BigNum, b: &BigNum) -> Result<BigNum> {
+        let r = BigNum::new()?;
+        if unsafe { BN_mod_mul(r.ptr, a.ptr, b.ptr, self.p.ptr, self.ctx.ptr) }
+            != 1
+        {
+            return Err(CKR_DEVICE_ERROR)?;
+        }
+        Ok(r)
+    }
+
+    fn inv(&mut self, a: &BigNum) -> Result<BigNum> {
+        let r = BigNum::new()?;
+        if unsafe { BN_mod_inverse(r.ptr, a.ptr, self.p.ptr, self.ctx.ptr) }
+            .is_null()
+        {
+            return Err(CKR_DEVICE_ERROR)?;
+        }
+        Ok(r)
+    }
+
+    /* Returns None if a is not a square */
+    fn sqrt(&mut self, a: &BigNum) -> Result<Option<BigNum>> {
+        let r = BigNum::new()?;
+        if unsafe { BN_mod_sqrt(r.ptr, a.ptr, self.p.ptr, self.ctx.ptr) }
+            .is_null()
+        {
+            return Ok(None);
+        }
+        Ok(Some(r))
+    }
+}
+
+fn clamp_x25519(k: &mut [u8]) {
+    k[0] &= 248;
+    k[31] &= 127;
+    k[31] |= 64;
+}
+
+fn clamp_x448(k: &mut [u8]) {
+    k[0] &= 252;
+    k[55] |= 128;
+}
+
+/* The Montgomery curve v^2 = u^3 + A*u^2 + u maps to the short
+ * Weierstrass curve Y^2 = X^3 + a*X + b through X = u + A/3, Y = v,
+ * with a = (3 - A^2) / 3 and b = (2*A^3 - 9*A) / 27.
+ *
+ * The twisted Edwards curve -x^2 + y^2 = 1 + d*x^2*y^2 used by XEdDSA
+ * is reached through y = (u - 1) / (u + 1) and x = c * u / v, where
+ * c^2 = -4 / (1 + d), which for d = -n / (n + 1) is -4 * (n + 1) */
+#[derive(Debug)]
+struct XEdCurve {
+    group: EcGroup,
+    p: BigNum,
+    q: BigNum,
+    a: BigNum,
+    a_3: BigNum,
+    c: BigNum,
+    clamp: fn(&mut [u8]),
+    /* Size of the Montgomery keys in bytes */
+    key_size: usize,
+    /* Size of encoded points and scalars in bytes (b / 8) */
+    enc_size: usize,
+}
+
+/* The constants defining a curve, dd is the denominator of the Edwards
+ * d = -(dd - 1) / dd */
+struct XEdParams {
+    p: &'static CStr,
+    q: &'static CStr,
+    a: c_ulong,
+    dd: c_ulong,
+    base_u: c_ulong,
+    cofactor: c_ulong,
+    clamp: fn(&mut [u8]),
+    key_size: usize,
+    enc_size: usize,
+}
+
+static XED25519: Lazy<Option<XEdCurve>> = Lazy::new(|| {
+    XEdCurve::new(&XEdParams {
+        p: c"7FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFED",
+        q: c"1000000000000000000000000000000014DEF9DEA2F79CD65812631A5CF5D3ED",
+        a: 486662,
+        dd: 121666,
+        base_u: 9,
+        cofactor: 8,
+        clamp: clamp_x25519,
+        key_size: 32,
+        enc_size: 32,
+    })
+    .ok()
+});
+
+static XED448: Lazy<Option<XEdCurve>> = Lazy::new(|| {
+    XEdCurve::new(&XEdParams {
+        p: c"FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFEFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF",
+        q: c"3FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF7CCA23E9C44EDB49AED63690216CC2728DC58F552378C292AB5844F3",
+        a: 156326,
+        dd: 39082,
+        base_u: 5,
+        cofactor: 4,
+        clamp: clamp_x448,
+        key_size: 56,
+        enc_size: 57,
+    })
+    .ok()
+});
+
+impl XEdCurve {
+    fn new(params: &XEdParams) -> Result<XEdCurve> {
+        let p = BigNum::from_hex(params.p)?;
+        let a = BigNum::from_word(params.a)?;
+        let three = BigNum::from_word(3)?;
+        let mut fp = Field::new(&p)?;
+
+        let inv3 = fp.inv(&three)?;
+        let a_3 = fp.mul(&a, &inv3)?;
+        let a2 = fp.mul(&a, &a)?;
+        let t = fp.sub(&three, &a2)?;
+        let wa = fp.mul(&t, &inv3)?;
+        let t = fp.add(&a2, &a2)?;
+        let t = fp.sub(&t, &BigNum::from_word(9)?)?;
+        let t = fp.mul(&t, &a)?;
+        let inv27 = fp.inv(&BigNum::from_word(27)?)?;
+        let wb = fp.mul(&t, &inv27)?;
+
+        /* Either square root works as c, the generator is picked below
+         * to map to the Edwards base point with the chosen one */
+        let t = fp.neg(&BigNum::from_word(4 * params.dd)?)?;
+        let c = match fp.sqrt(&t)? {
+            Some(c) => c,
+            None => return Err(CKR_DEVICE_ERROR)?,
+        };
+        drop(fp);
+
+        let ptr = unsafe {
+            EC_GROUP_new_curve_GFp(p.ptr, wa.ptr, wb.ptr, std::ptr::null_mut())
+        };
+        if ptr.is_null() {
+            return Err(CKR_DEVICE_ERROR)?;
+        }
+        let curve = XEdCurve {
+            group: EcGroup { ptr },
+            p,
+            q: BigNum::from_hex(params.q)?,
+            a,
+            a_3,
+            c,
+            clamp: params.clamp,
+            key_size: params.key_size,
+            enc_size: params.enc_size,
+        };
+
+        /* The base point is the one with u = base_u and a zero sign bit.
+         * Setting the order and cofactor also makes OpenSSL use its
+         * constant time ladder for scalar multiplications */
+        let base =
+            match curve.point_from_u(&BigNum::from_word(params.base_u)?)? {
+                Some(b) => b,
+                None => return Err(CKR_DEVICE_ERROR)?,
+            };
+        let cofactor = BigNum::from_word(params.cofactor)?;
+        if unsafe {
+            EC_GROUP_set_generator(
+                curve.group.ptr,
+                base.ptr,
+                curve.q.ptr,
+                cofactor.ptr,
+            )
+        } != 1
+        {
+            return Err(CKR_DEVICE_ERROR)?;
+        }
+        Ok(curve)
+    }
+
+    /* Returns the point with the given u coordinate whose Edwards form
+     * has a zero sign bit, or None if there is no such point */
+    fn point_from_u(&self, u: &BigNum) -> Result<Option<EcPoint>> {
+        let mut fp = Field::new(&self.p)?;
+        let one = BigNum::from_word(1)?;
+        /* v^2 = ((u + A) * u + 1) * u */
+        let t = fp.add(u, &self.a)?;
+        let t = fp.mul(&t, u)?;
+        let t = fp.add(&t, &one)?;
+        let t = fp.mul(&t, u)?;
+        let mut v = match fp.sqrt(&t)? {
+            Some(v) => v,
+            None => return Ok(None),
+        };
+        if !v.is_zero() {
+            let t = fp.mul(&self.c, u)?;
+            let vinv = fp.inv(&v)?;
+            if fp.mul(&t, &vinv)?.is_odd() {
+                v = fp.neg(&v)?;
+            }
+        }
+        let x = fp.add(u, &self.a_3)?;
+        let point = EcPoint::new(&self.group)?;
+        if unsafe {
+            EC_POINT_set_affine_coordinates(
+                self.group.ptr,
+                point.ptr,
+                x.ptr,
+                v.ptr,
+                fp.ctx.ptr,
+            )
+        } != 1
+        {
+            return Ok(None);
+        }
+        Ok(Some(point))
+    }
+
+    /* Returns the Edwards encoding of the point, or None if the point
+     * has no Edwards form. Only ever called on public points */
+    fn encode_point(&self, point: &EcPoint) -> Result<Option<Vec<u8>>> {
+        let mut fp = Field::new(&self.p)?;
+        let one = BigNum::from_word(1)?;
+        if unsafe { EC_POINT_is_at_infinity(self.group.ptr, point.ptr) } == 1 {
+            /* The identity (0, 1) */
+            return Ok(Some(one.to_le(self.enc_size)?));
+        }
+        let x = BigNum::new()?;
+        let v = BigNum::new()?;
+        if unsafe {
+            EC_POINT_get_affine_coordinates(
+                self.group.ptr,
+                point.ptr,
+                x.ptr,
+                v.ptr,
+                fp.ctx.ptr,
+            )
+        } != 1
+        {
+            return Err(CKR_DEVICE_ERROR)?;
+        }
+        if v.is_zero() {
+            /* u = 0, the point of order 2 (0, -1) */
+            return Ok(Some(fp.neg(&one)?.to_le(self.enc_size)?));
+        }
+        let u = fp.sub(&x, &self.a_3)?;
+        let den = fp.add(&u, &one)?;
+        if den.is_zero() {
+            return Ok(None);
+        }
+        let num = fp.sub(&u, &one)?;
+        let den = fp.inv(&den)?;
+        let y = fp.mul(&num, &den)?;
+        let t = fp.mul(&self.c, &u)?;
+        let vinv = fp.inv(&v)?;
+        let sign = u8::from(fp.mul(&t, &vinv)?.is_odd());
+        let mut enc = y.to_le(self.enc_size)?;
+        enc[self.enc_size - 1] |= sign << 7;
+        Ok(Some(enc))
+    }
+
+    /* Hashes the parts into a scalar modulo q */
+    fn hash(&self, mech: CK_MECHANISM_TYPE, parts: &[&[u8]]) -> Result<BigNum> {
+        let mut op = hash::internal_hash_op(mech)?;
+        for part in parts {
+            op.digest_update(part)?;
+        }
+        let mut digest = vec![0u8; op.digest_len()?];
+        op.digest_final(&mut digest)?;
+        let d = BigNum::from_le(&digest, true);
+        digest.zeroize();
+        let d = d?;
+        let ctx = BnCtx::new()?;
+        let r = BigNum::new_secret()?;
+        if unsafe { BN_nnmod(r.ptr, d.ptr, self.q.ptr, ctx.ptr) } != 1 {
+            return Err(CKR_DEVICE_ERROR)?;
+        }
+        Ok(r)
+    }
+
+    /* Multiplies the generator by a secret scalar */
+    fn mul_base(&self, k: &BigNum, ctx: &BnCtx) -> Result<EcPoint> {
+        let point = EcPoint::new(&self.group)?;
+        if unsafe {
+            EC_POINT_mul(
+                self.group.ptr,
+                point.ptr,
+                k.ptr,
+                std::ptr::null(),
+                std::ptr::null(),
+                ctx.ptr,
+            )
+        } != 1
+        {
+            return Err(CKR_DEVICE_ERROR)?;
+        }
+        Ok(point)
+    }
+
+    fn sign(
+        &self,
+        mech: CK_MECHANISM_TYPE,
+        key: &[u8],
+        msg: &[u8],
+        z: &[u8],
+    ) -> Result<Vec<u8>> {
+        if key.len() != self.key_size {
+            return Err(CKR_KEY_SIZE_RANGE)?;
+        }
+        let ctx = BnCtx::new()?;
+
+        /* k is the clamped private key, reduced modulo q as kB only
+         * depends on k mod q */
+        let mut kbytes = key.to_vec();
+        (self.clamp)(&mut kbytes);
+        let k = BigNum::from_le(&kbytes, true);
+        kbytes.zeroize();
+        let k = k?;
+        let kq = BigNum::new_secret()?;
+        if unsafe { BN_nnmod(kq.ptr, k.ptr, self.q.ptr, ctx.ptr) } != 1 {
+            return Err(CKR_DEVICE_ERROR)?;
+        }
+
+        /* E = kB, and the public key A is E with a zero sign bit. The
+         * u coordinate is the (public) Montgomery public key, so only
+         * the v coordinates of E and A differ when the sign bit of E
+         * is set, in which case the private scalar is negated */
+        let e = self.mul_base(&kq, &ctx)?;
+        let ex = BigNum::new_secret()?;
+        let ey = BigNum::new_secret()?;
+        if unsafe {
+            EC_POINT_get_affine_coordinates(
+                self.group.ptr,
+                e.ptr,
+                ex.ptr,
+                ey.ptr,
+                ctx.ptr,
+            )
+        } != 1
+        {
+            return Err(CKR_DEVICE_ERROR)?;
+        }
+        let mut fp = Field::new(&self.p)?;
+        let u = fp.sub(&ex, &self.a_3)?;
+        drop(fp);
+        let pubkey = match self.point_from_u(&u)? {
+            Some(p) => p,
+            None => return Err(CKR_GENERAL_ERROR)?,
+        };
+        let ay = BigNum::new()?;
+        if unsafe {
+            EC_POINT_get_affine_coordinates(
+                self.group.ptr,
+                pubkey.ptr,
+                std::ptr::null_mut(),
+                ay.ptr,
+                ctx.ptr,
+            )
+        } != 1
+        {
+            return Err(CKR_DEVICE_ERROR)?;
+        }
+        let pubenc = match self.encode_point(&pubkey)? {
+            Some(enc) => enc,
+            None => return Err(CKR_GENERAL_ERROR)?,
+        };
+
+        let mut eybytes = ey.to_le(self.enc_size)?;
+        let mask = 0u8.wrapping_sub(u8::from(!constant_time_eq(
+            &eybytes,
+            &ay.to_le(self.enc_size)?,
+        )));
+        eybytes.zeroize();
+        let kneg = BigNum::new_secret()?;
+        if unsafe { BN_sub(kneg.ptr, self.q.ptr, kq.ptr) } != 1 {
+            return Err(CKR_DEVICE_ERROR)?;
+        }
+        let mut abytes = kq.to_le(self.enc_size)?;
+        let mut negbytes = kneg.to_le(self.enc_size)?;
+        for (a, n) in abytes.iter_mut().zip(negbytes.iter()) {
+            *a = (*a & !mask) | (*n & mask);
+        }
+        negbytes.zeroize();
+
+        /* r = hash1(a || M || Z) mod q, where hash1 prefixes the data
+         * with the encoding of 2^b - 2 */
+        let mut prefix = vec![0xffu8; self.enc_size];
+        prefix[0] = 0xfe;
+        let r = self.hash(mech, &[&prefix, &abytes, msg, z]);
+        let a = BigNum::from_le(&abytes, true);
+        abytes.zeroize();
+        let r = r?;
+        let a = a?;
+
+        let rpoint = self.mul_base(&r, &ctx)?;
+        let renc = match self.encode_point(&rpoint)? {
+            Some(enc) => enc,
+            None => return Err(CKR_GENERAL_ERROR)?,
+        };
+        let h = self.hash(mech, &[&renc, &pubenc, msg])?;
+
+        /* s = r + h * a mod q, the product is computed with Montgomery
+         * multiplication as it is constant time */
+        let mont = MontCtx::new(&self.q, &ctx)?;
+        let hm = BigNum::new_secret()?;
+        let ha = BigNum::new_secret()?;
+        let s = BigNum::new_secret()?;
+        if unsafe { BN_to_montgomery(hm.ptr, h.ptr, mont.ptr, ctx.ptr) } != 1 {
+            return Err(CKR_DEVICE_ERROR)?;
+        }
+        if unsafe {
+            BN_mod_mul_montgomery(ha.ptr, hm.ptr, a.ptr, mont.ptr, ctx.ptr)
+        } != 1
+        {
+            return Err(CKR_DEVICE_ERROR)?;
+        }
+        if unsafe { BN_mod_add_quick(s.ptr, r.ptr, ha.ptr, self.q.ptr) } != 1 {
+            return Err(CKR_DEVICE_ERROR)?;
+        }
+
+        let mut signature = renc;
+        signature.extend_from_slice(&s.to_le(self.enc_size)?);
+        Ok(signature)
+    }
+
+    fn verify(
+        &self,
+        mech: CK_MECHANISM_TYPE,
+        u: &[u8],
+        msg: &[u8],
+        sig: &[u8],
+    ) -> Result<bool> {
+        if sig.len() != 2 * self.enc_size {
+            return Ok(false);
+        }
+        let u = BigNum::from_le(u, false)?;
+        if unsafe { BN_cmp(u.ptr, self.p.ptr) } >= 0 {
+            return Ok(false);
+        }
+        let mut fp = Field::new(&self.p)?;
+        if fp.add(&u, &BigNum::from_word(1)?)?.is_zero() {
+            return Ok(false);
+        }
+        let pubkey = match self.point_from_u(&u)? {
+            Some(p) => p,
+            None => return Ok(false),
+        };
+        let pubenc = match self.encode_point(&pubkey)? {
+            Some(enc) => enc,
+            None => return Ok(false),
+        };
+        let (rbytes, sbytes) = sig.split_at(self.enc_size);
+        let s = BigNum::from_le(sbytes, false)?;
+        if unsafe { BN_num_bits(s.ptr) > BN_num_bits(self.q.ptr) } {
+            return Ok(false);
+        }
+        let h = self.hash(mech, &[rbytes, &pubenc, msg])?;
+
+        /* check = sB - hA */
+        if unsafe { EC_POINT_invert(self.group.ptr, pubkey.ptr, fp.ctx.ptr) }
+            != 1
+        {
+            return Err(CKR_DEVICE_ERROR)?;
+        }
+        let check = EcPoint::new(&self.group)?;
+        if unsafe {
+            EC_POINT_mul(
+                self.group.ptr,
+                check.ptr,
+                s.ptr,
+                pubkey.ptr,
+                h.ptr,
+                fp.ctx.ptr,
+            )
+        } != 1
+        {
+            return Err(CKR_DEVICE_ERROR)?;
+        }
+        match self.encode_point(&check)? {
+            Some(enc) => Ok(enc.as_slice() == rbytes),
+            None => Ok(false),
+        }
+    }
+}
+
+fn parse_params(mech: &CK_MECHANISM) -> Result<CK_MECHANISM_TYPE> {
+    if mech.mechanism != CKM_XEDDSA {
+        Err(CKR_MECHANISM_INVALID)?;
+    }
+    if mech.ulParameterLen == 0 {
+        return Ok(CKM_SHA512);
+    }
+    let params = cast_params!(mech, CK_XEDDSA_PARAMS);
+    match params.hash {
+        CKM_SHA512 | CKM_SHA3_512 => Ok(params.hash),
+        _ => Err(CKR_MECHANISM_PARAM_INVALID)?,
+    }
+}
+
+fn curve_from_object(key: &Object) -> Result<&'static XEdCurve> {
+    let curve = match get_oid_from_obj(key)? {
+        oid::X25519_OID => XED25519.as_ref(),
+        oid::X448_OID => XED448.as_ref(),
+        _ => return Err(CKR_KEY_TYPE_INCONSISTENT)?,
+    };
+    match curve {
+        Some(c) => Ok(c),
+        None => Err(CKR_DEVICE_ERROR)?,
+    }
+}
+
+/* Signs with a caller provided Z instead of a random one, this makes
+ * signatures deterministic for known answer tests */
+#[cfg(test)]
+pub fn sign_with_z(
+    mech: CK_MECHANISM_TYPE,
+    key: &[u8],
+    msg: &[u8],
+    z: &[u8],
+) -> Result<Vec<u8>> {
+    let curve = match key.len() {
+        32 => XED25519.as_ref(),
+        56 => XED448.as_ref(),
+        _ => return Err(CKR_KEY_SIZE_RANGE)?,
+    };
+    match curve {
+        Some(c) => c.sign(mech, key, msg, z),
+        None => Err(CKR_DEVICE_ERROR)?,
+    }
+}
+
+#[derive(Debug)]
+pub struct XEddsaOperation {
+    mech: CK_MECHANISM_TYPE,
+    hash: CK_MECHANISM_TYPE,
+    curve: &'static XEdCurve,
+    /* The private value when signing, the public u coordinate when
+     * verifying */
+    key: Vec<u8>,
+    data: Vec<u8>,
+    finalized: bool,
+    in_use: bool,
+}
+
+impl Drop for XEddsaOperation {
+    fn drop(&mut self) {
+        self.key.zeroize();
+        self.data.zeroize();
+    }
+}
+
+impl XEddsaOperation {
+    pub fn sign_new(
+        mech: &CK_MECHANISM,
+        key: &Object,
+    ) -> Result<XEddsaOperation> {
+        let curve = curve_from_object(key)?;
+        let value = key.get_attr_as_bytes(CKA_VALUE)?;
+        if value.len() != curve.key_size {
+            return Err(CKR_KEY_SIZE_RANGE)?;
+        }
+        Ok(XEddsaOperation {
+            mech: mech.mechanism,
+            hash: parse_params(mech)?,
+            curve,
+            key: value.clone(),
+            data: Vec::new(),
+            finalized: false,
+            in_use: false,
+        })
+    }
+
+    pub fn verify_new(
+        mech: &CK_MECHANISM,
+        key: &Object,
+    ) -> Result<XEddsaOperation> {
+        let curve = curve_from_object(key)?;
+        let point = get_ec_point_from_obj(key)?;
+        if point.len() != curve.key_size {
+            return Err(CKR_KEY_SIZE_RANGE)?;
+        }
+        Ok(XEddsaOperation {
+            mech: mech.mechanism,
+            hash: parse_params(mech)?,
+            curve,
+            key: point,
+            data: Vec::new(),
+            finalized: false,
+            in_use: false,
+        })
+    }
+}
+
+impl MechOperation for XEddsaOperation {
+    fn mechanism(&self) -> Result<CK_MECHANISM_TYPE> {
+        Ok(self.mech)
+    }
+    fn finalized(&self) -> bool {
+        self.finalized
+    }
+}
+
+impl Sign for XEddsaOperation {
+    fn sign(&mut self, data: &[u8], signature: &mut [u8]) -> Result<()> {
+        if self.in_use {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        if self.finalized {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        self.sign_update(data)?;
+        self.sign_final(signature)
+    }
+
+    fn sign_update(&mut self, data: &[u8]) -> Result<()> {
+        if self.finalized {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        self.in_use = true;
+        self.data.extend_from_slice(data);
+        Ok(())
+    }
+
+    fn sign_final(&mut self, signature: &mut [u8]) -> Result<()> {
+        if !self.in_use {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        if self.finalized {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        self.finalized = true;
+        if signature.len() != 2 * self.curve.enc_size {
+            return Err(CKR_GENERAL_ERROR)?;
+        }
+        let mut z = [0u8; 64];
+        get_random_data(&mut z)?;
+        let sig = self.curve.sign(self.hash, &self.key, &self.data, &z);
+        z.zeroize();
+        signature.copy_from_slice(&sig?);
+        Ok(())
+    }
+
+    fn signature_len(&self) -> Result<usize> {
+        Ok(2 * self.curve.enc_size)
+    }
+}
+
+impl Verify for XEddsaOperation {
+    fn verify(&mut self, data: &[u8], signature: &[u8]) -> Result<()> {
+        if self.in_use {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        if self.finalized {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        self.verify_update(data)?;
+        self.verify_final(signature)
+    }
+
+    fn verify_update(&mut self, data: &[u8]) -> Result<()> {
+        if self.finalized {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        self.in_use = true;
+        self.data.extend_from_slice(data);
+        Ok(())
+    }
+
+    fn verify_final(&mut self, signature: &[u8]) -> Result<()> {
+        if !self.in_use {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        if self.finalized {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        self.finalized = true;
+        if !self
+            .curve
+            .verify(self.hash, &self.key, &self.data, signature)?
+        {
+            return Err(CKR_SIGNATURE_INVALID)?;
+        }
+        Ok(())
+    }
+
+    fn signature_len(&self) -> Result<usize> {
+        Ok(2 * self.curve.enc_size)
+    }
+}
@@ -59,6 +59,338 @@ pub struct KR_SSHKDF_PARAMS {
     pub ulSessionIdLen: CK_ULONG,
 }
 
+/* ======================================================================= *
+ * ========================= v3.2 INTERFACE ============================== *
+ * ======================================================================= */
+
+/* The bundled headers are from PKCS#11 3.1, the following are the
+ * additions from PKCS#11 3.2 that are implemented by this token */
+
+/* Attributes */
+pub const CKA_PARAMETER_SET: CK_ATTRIBUTE_TYPE = 0x0000061D;
+pub const CKA_ENCAPSULATE_TEMPLATE: CK_ATTRIBUTE_TYPE = 0x0000062A;
+pub const CKA_DECAPSULATE_TEMPLATE: CK_ATTRIBUTE_TYPE = 0x0000062B;
+pub const CKA_ENCAPSULATE: CK_ATTRIBUTE_TYPE = 0x00000633;
+pub const CKA_DECAPSULATE: CK_ATTRIBUTE_TYPE = 0x00000634;
+pub const CKA_SEED: CK_ATTRIBUTE_TYPE = 0x00000637;
+
+/* Mechanism info flags */
+pub const CKF_ENCAPSULATE: CK_FLAGS = 0x10000000;
+pub const CKF_DECAPSULATE: CK_FLAGS = 0x20000000;
+
+/* ML-KEM */
+pub const CKK_ML_KEM: CK_KEY_TYPE = 0x00000049;
+
+pub const CKM_ML_KEM_KEY_PAIR_GEN: CK_MECHANISM_TYPE = 0x0000000F;
+pub const CKM_ML_KEM: CK_MECHANISM_TYPE = 0x00000017;
+
+pub type CK_ML_KEM_PARAMETER_SET_TYPE = CK_ULONG;
+
+pub const CKP_ML_KEM_512: CK_ML_KEM_PARAMETER_SET_TYPE = 0x00000001;
+pub const CKP_ML_KEM_768: CK_ML_KEM_PARAMETER_SET_TYPE = 0x00000002;
+pub const CKP_ML_KEM_1024: CK_ML_KEM_PARAMETER_SET_TYPE = 0x00000003;
+
+/* ML-DSA */
+pub const CKK_ML_DSA: CK_KEY_TYPE = 0x0000004A;
+
+pub const CKM_ML_DSA_KEY_PAIR_GEN: CK_MECHANISM_TYPE = 0x0000001C;
+pub const CKM_ML_DSA: CK_MECHANISM_TYPE = 0x0000001D;
+pub const CKM_HASH_ML_DSA: CK_MECHANISM_TYPE = 0x0000001F;
+pub const CKM_HASH_ML_DSA_SHA224: CK_MECHANISM_TYPE = 0x00000023;
+pub const CKM_HASH_ML_DSA_SHA256: CK_MECHANISM_TYPE = 0x00000024;
+pub const CKM_HASH_ML_DSA_SHA384: CK_MECHANISM_TYPE = 0x00000025;
+pub const CKM_HASH_ML_DSA_SHA512: CK_MECHANISM_TYPE = 0x00000026;
+pub const CKM_HASH_ML_DSA_SHA3_224: CK_MECHANISM_TYPE = 0x00000027;
+pub const CKM_HASH_ML_DSA_SHA3_256: CK_MECHANISM_TYPE = 0x00000028;
+pub const CKM_HASH_ML_DSA_SHA3_384: CK_MECHANISM_TYPE = 0x00000029;
+pub const CKM_HASH_ML_DSA_SHA3_512: CK_MECHANISM_TYPE = 0x0000002A;
+pub const CKM_HASH_ML_DSA_SHAKE128: CK_MECHANISM_TYPE = 0x0000002B;
+pub const CKM_HASH_ML_DSA_SHAKE256: CK_MECHANISM_TYPE = 0x0000002C;
+
+pub type CK_ML_DSA_PARAMETER_SET_TYPE = CK_ULONG;
+
+pub const CKP_ML_DSA_44: CK_ML_DSA_PARAMETER_SET_TYPE = 0x00000001;
+pub const CKP_ML_DSA_65: CK_ML_DSA_PARAMETER_SET_TYPE = 0x00000002;
+pub const CKP_ML_DSA_87: CK_ML_DSA_PARAMETER_SET_TYPE = 0x00000003;
+
+/* SLH-DSA */
+pub const CKK_SLH_DSA: CK_KEY_TYPE = 0x0000004B;
+
+pub const CKM_SLH_DSA_KEY_PAIR_GEN: CK_MECHANISM_TYPE = 0x0000002D;
+pub const CKM_SLH_DSA: CK_MECHANISM_TYPE = 0x0000002E;
+
+pub type CK_SLH_DSA_PARAMETER_SET_TYPE = CK_ULONG;
+
+pub const CKP_SLH_DSA_SHA2_128S: CK_SLH_DSA_PARAMETER_SET_TYPE = 0x00000001;
+pub const CKP_SLH_DSA_SHAKE_128S: CK_SLH_DSA_PARAMETER_SET_TYPE = 0x00000002;
+pub const CKP_SLH_DSA_SHA2_128F: CK_SLH_DSA_PARAMETER_SET_TYPE = 0x00000003;
+pub const CKP_SLH_DSA_SHAKE_128F: CK_SLH_DSA_PARAMETER_SET_TYPE = 0x00000004;
+pub const CKP_SLH_DSA_SHA2_192S: CK_SLH_DSA_PARAMETER_SET_TYPE = 0x00000005;
+pub const CKP_SLH_DSA_SHAKE_192S: CK_SLH_DSA_PARAMETER_SET_TYPE = 0x00000006;
+pub const CKP_SLH_DSA_SHA2_192F: CK_SLH_DSA_PARAMETER_SET_TYPE = 0x00000007;
+pub const CKP_SLH_DSA_SHAKE_192F: CK_SLH_DSA_PARAMETER_SET_TYPE = 0x00000008;
+pub const CKP_SLH_DSA_SHA2_256S: CK_SLH_DSA_PARAMETER_SET_TYPE = 0x00000009;
+pub const CKP_SLH_DSA_SHAKE_256S: CK_SLH_DSA_PARAMETER_SET_TYPE = 0x0000000A;
+pub const CKP_SLH_DSA_SHA2_256F: CK_SLH_DSA_PARAMETER_SET_TYPE = 0x0000000B;
+pub const CKP_SLH_DSA_SHAKE_256F: CK_SLH_DSA_PARAMETER_SET_TYPE = 0x0000000C;
+
+/* Signature parameters */
+pub type CK_HEDGE_TYPE = CK_ULONG;
+
+pub const CKH_HEDGE_PREFERRED: CK_HEDGE_TYPE = 0x00000000;
+pub const CKH_HEDGE_REQUIRED: CK_HEDGE_TYPE = 0x00000001;
+pub const CKH_DETERMINISTIC_REQUIRED: CK_HEDGE_TYPE = 0x00000002;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct CK_SIGN_ADDITIONAL_CONTEXT {
+    pub hedgeVariant: CK_HEDGE_TYPE,
+    pub pContext: *mut CK_BYTE,
+    pub ulContextLen: CK_ULONG,
+}
+pub type CK_SIGN_ADDITIONAL_CONTEXT_PTR = *mut CK_SIGN_ADDITIONAL_CONTEXT;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct CK_HASH_SIGN_ADDITIONAL_CONTEXT {
+    pub hedgeVariant: CK_HEDGE_TYPE,
+    pub pContext: *mut CK_BYTE,
+    pub ulContextLen: CK_ULONG,
+    pub hash: CK_MECHANISM_TYPE,
+}
+pub type CK_HASH_SIGN_ADDITIONAL_CONTEXT_PTR =
+    *mut CK_HASH_SIGN_ADDITIONAL_CONTEXT;
+
+/* Functions */
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct CK_ASYNC_DATA {
+    pub ulVersion: CK_ULONG,
+    pub pValue: *mut CK_BYTE,
+    pub ulValue: CK_ULONG,
+    pub hObject: CK_OBJECT_HANDLE,
+    pub hAdditionalObject: CK_OBJECT_HANDLE,
+}
+pub type CK_ASYNC_DATA_PTR = *mut CK_ASYNC_DATA;
+
+pub type CK_C_EncapsulateKey = ::std::option::Option<
+    unsafe extern "C" fn(
+        arg1: CK_SESSION_HANDLE,
+        arg2: *mut CK_MECHANISM,
+        arg3: CK_OBJECT_HANDLE,
+        arg4: *mut CK_ATTRIBUTE,
+        arg5: CK_ULONG,
+        arg6: *mut CK_BYTE,
+        arg7: *mut CK_ULONG,
+        arg8: *mut CK_OBJECT_HANDLE,
+    ) -> CK_RV,
+>;
+pub type CK_C_DecapsulateKey = ::std::option::Option<
+    unsafe extern "C" fn(
+        arg1: CK_SESSION_HANDLE,
+        arg2: *mut CK_MECHANISM,
+        arg3: CK_OBJECT_HANDLE,
+        arg4: *mut CK_ATTRIBUTE,
+        arg5: CK_ULONG,
+        arg6: *mut CK_BYTE,
+        arg7: CK_ULONG,
+        arg8: *mut CK_OBJECT_HANDLE,
+    ) -> CK_RV,
+>;
+pub type CK_C_VerifySignatureInit = ::std::option::Option<
+    unsafe extern "C" fn(
+        arg1: CK_SESSION_HANDLE,
+        arg2: *mut CK_MECHANISM,
+        arg3: CK_OBJECT_HANDLE,
+        arg4: *mut CK_BYTE,
+        arg5: CK_ULONG,
+    ) -> CK_RV,
+>;
+pub type CK_C_VerifySignature = ::std::option::Option<
+    unsafe extern "C" fn(
+        arg1: CK_SESSION_HANDLE,
+        arg2: *mut CK_BYTE,
+        arg3: CK_ULONG,
+    ) -> CK_RV,
+>;
+pub type CK_C_VerifySignatureUpdate = ::std::option::Option<
+    unsafe extern "C" fn(
+        arg1: CK_SESSION_HANDLE,
+        arg2: *mut CK_BYTE,
+        arg3: CK_ULONG,
+    ) -> CK_RV,
+>;
+pub type CK_C_VerifySignatureFinal = ::std::option::Option<
+    unsafe extern "C" fn(arg1: CK_SESSION_HANDLE) -> CK_RV,
+>;
+pub type CK_C_GetSessionValidationFlags = ::std::option::Option<
+    unsafe extern "C" fn(
+        arg1: CK_SESSION_HANDLE,
+        arg2: CK_SESSION_VALIDATION_FLAGS_TYPE,
+        arg3: CK_FLAGS_PTR,
+    ) -> CK_RV,
+>;
+pub type CK_C_AsyncComplete = ::std::option::Option<
+    unsafe extern "C" fn(
+        arg1: CK_SESSION_HANDLE,
+        arg2: *mut CK_UTF8CHAR,
+        arg3: CK_ASYNC_DATA_PTR,
+    ) -> CK_RV,
+>;
+pub type CK_C_AsyncGetID = ::std::option::Option<
+    unsafe extern "C" fn(
+        arg1: CK_SESSION_HANDLE,
+        arg2: *mut CK_UTF8CHAR,
+        arg3: *mut CK_ULONG,
+    ) -> CK_RV,
+>;
+pub type CK_C_AsyncJoin = ::std::option::Option<
+    unsafe extern "C" fn(
+        arg1: CK_SESSION_HANDLE,
+        arg2: *mut CK_UTF8CHAR,
+        arg3: CK_ULONG,
+        arg4: *mut CK_BYTE,
+        arg5: CK_ULONG,
+    ) -> CK_RV,
+>;
+pub type CK_C_WrapKeyAuthenticated = ::std::option::Option<
+    unsafe extern "C" fn(
+        arg1: CK_SESSION_HANDLE,
+        arg2: *mut CK_MECHANISM,
+        arg3: CK_OBJECT_HANDLE,
+        arg4: CK_OBJECT_HANDLE,
+        arg5: *mut CK_BYTE,
+        arg6: CK_ULONG,
+        arg7: *mut CK_BYTE,
+        arg8: *mut CK_ULONG,
+    ) -> CK_RV,
+>;
+pub type CK_C_UnwrapKeyAuthenticated = ::std::option::Option<
+    unsafe extern "C" fn(
+        arg1: CK_SESSION_HANDLE,
+        arg2: *mut CK_MECHANISM,
+        arg3: CK_OBJECT_HANDLE,
+        arg4: *mut CK_BYTE,
+        arg5: CK_ULONG,
+        arg6: *mut CK_ATTRIBUTE,
+        arg7: CK_ULONG,
+        arg8: *mut CK_BYTE,
+        arg9: CK_ULONG,
+        arg10: *mut CK_OBJECT_HANDLE,
+    ) -> CK_RV,
+>;
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct CK_FUNCTION_LIST_3_2 {
+    pub version: CK_VERSION,
+    pub C_Initialize: CK_C_Initialize,
+    pub C_Finalize: CK_C_Finalize,
+    pub C_GetInfo: CK_C_GetInfo,
+    pub C_GetFunctionList: CK_C_GetFunctionList,
+    pub C_GetSlotList: CK_C_GetSlotList,
+    pub C_GetSlotInfo: CK_C_GetSlotInfo,
+    pub C_GetTokenInfo: CK_C_GetTokenInfo,
+    pub C_GetMechanismList: CK_C_GetMechanismList,
+    pub C_GetMechanismInfo: CK_C_GetMechanismInfo,
+    pub C_InitToken: CK_C_InitToken,
+    pub C_InitPIN: CK_C_InitPIN,
+    pub C_SetPIN: CK_C_SetPIN,
+    pub C_OpenSession: CK_C_OpenSession,
+    pub C_CloseSession: CK_C_CloseSession,
+    pub C_CloseAllSessions: CK_C_CloseAllSessions,
+    pub C_GetSessionInfo: CK_C_GetSessionInfo,
+    pub C_GetOperationState: CK_C_GetOperationState,
+    pub C_SetOperationState: CK_C_SetOperationState,
+    pub C_Login: CK_C_Login,
+    pub C_Logout: CK_C_Logout,
+    pub C_CreateObject: CK_C_CreateObject,
+    pub C_CopyObject: CK_C_CopyObject,
+    pub C_DestroyObject: CK_C_DestroyObject,
+    pub C_GetObjectSize: CK_C_GetObjectSize,
+    pub C_GetAttributeValue: CK_C_GetAttributeValue,
+    pub C_SetAttributeValue: CK_C_SetAttributeValue,
+    pub C_FindObjectsInit: CK_C_FindObjectsInit,
+    pub C_FindObjects: CK_C_FindObjects,
+    pub C_FindObjectsFinal: CK_C_FindObjectsFinal,
+    pub C_EncryptInit: CK_C_EncryptInit,
+    pub C_Encrypt: CK_C_Encrypt,
+    pub C_EncryptUpdate: CK_C_EncryptUpdate,
+    pub C_EncryptFinal: CK_C_EncryptFinal,
+    pub C_DecryptInit: CK_C_DecryptInit,
+    pub C_Decrypt: CK_C_Decrypt,
+    pub C_DecryptUpdate: CK_C_DecryptUpdate,
+    pub C_DecryptFinal: CK_C_DecryptFinal,
+    pub C_DigestInit: CK_C_DigestInit,
+    pub C_Digest: CK_C_Digest,
+    pub C_DigestUpdate: CK_C_DigestUpdate,
+    pub C_DigestKey: CK_C_DigestKey,
+    pub C_DigestFinal: CK_C_DigestFinal,
+    pub C_SignInit: CK_C_SignInit,
+    pub C_Sign: CK_C_Sign,
+    pub C_SignUpdate: CK_C_SignUpdate,
+    pub C_SignFinal: CK_C_SignFinal,
+    pub C_SignRecoverInit: CK_C_SignRecoverInit,
+    pub C_SignRecover: CK_C_SignRecover,
+    pub C_VerifyInit: CK_C_VerifyInit,
+    pub C_Verify: CK_C_Verify,
+    pub C_VerifyUpdate: CK_C_VerifyUpdate,
+    pub C_VerifyFinal: CK_C_VerifyFinal,
+    pub C_VerifyRecoverInit: CK_C_VerifyRecoverInit,
+    pub C_VerifyRecover: CK_C_VerifyRecover,
+    pub C_DigestEncryptUpdate: CK_C_DigestEncryptUpdate,
+    pub C_DecryptDigestUpdate: CK_C_DecryptDigestUpdate,
+    pub C_SignEncryptUpdate: CK_C_SignEncryptUpdate,
+    pub C_DecryptVerifyUpdate: CK_C_DecryptVerifyUpdate,
+    pub C_GenerateKey: CK_C_GenerateKey,
+    pub C_GenerateKeyPair: CK_C_GenerateKeyPair,
+    pub C_WrapKey: CK_C_WrapKey,
+    pub C_UnwrapKey: CK_C_UnwrapKey,
+    pub C_DeriveKey: CK_C_DeriveKey,
+    pub C_SeedRandom: CK_C_SeedRandom,
+    pub C_GenerateRandom: CK_C_GenerateRandom,
+    pub C_GetFunctionStatus: CK_C_GetFunctionStatus,
+    pub C_CancelFunction: CK_C_CancelFunction,
+    pub C_WaitForSlotEvent: CK_C_WaitForSlotEvent,
+    pub C_GetInterfaceList: CK_C_GetInterfaceList,
+    pub C_GetInterface: CK_C_GetInterface,
+    pub C_LoginUser: CK_C_LoginUser,
+    pub C_SessionCancel: CK_C_SessionCancel,
+    pub C_MessageEncryptInit: CK_C_MessageEncryptInit,
+    pub C_EncryptMessage: CK_C_EncryptMessage,
+    pub C_EncryptMessageBegin: CK_C_EncryptMessageBegin,
+    pub C_EncryptMessageNext: CK_C_EncryptMessageNext,
+    pub C_MessageEncryptFinal: CK_C_MessageEncryptFinal,
+    pub C_MessageDecryptInit: CK_C_MessageDecryptInit,
+    pub C_DecryptMessage: CK_C_DecryptMessage,
+    pub C_DecryptMessageBegin: CK_C_DecryptMessageBegin,
+    pub C_DecryptMessageNext: CK_C_DecryptMessageNext,
+    pub C_MessageDecryptFinal: CK_C_MessageDecryptFinal,
+    pub C_MessageSignInit: CK_C_MessageSignInit,
+    pub C_SignMessage: CK_C_SignMessage,
+    pub C_SignMessageBegin: CK_C_SignMessageBegin,
+    pub C_SignMessageNext: CK_C_SignMessageNext,
+    pub C_MessageSignFinal: CK_C_MessageSignFinal,
+    pub C_MessageVerifyInit: CK_C_MessageVerifyInit,
+    pub C_VerifyMessage: CK_C_VerifyMessage,
+    pub C_VerifyMessageBegin: CK_C_VerifyMessageBegin,
+    pub C_VerifyMessageNext: CK_C_VerifyMessageNext,
+    pub C_MessageVerifyFinal: CK_C_MessageVerifyFinal,
+    pub C_EncapsulateKey: CK_C_EncapsulateKey,
+    pub C_DecapsulateKey: CK_C_DecapsulateKey,
+    pub C_VerifySignatureInit: CK_C_VerifySignatureInit,
+    pub C_VerifySignature: CK_C_VerifySignature,
+    pub C_VerifySignatureUpdate: CK_C_VerifySignatureUpdate,
+    pub C_VerifySignatureFinal: CK_C_VerifySignatureFinal,
+    pub C_GetSessionValidationFlags: CK_C_GetSessionValidationFlags,
+    pub C_AsyncComplete: CK_C_AsyncComplete,
+    pub C_AsyncGetID: CK_C_AsyncGetID,
+    pub C_AsyncJoin: CK_C_AsyncJoin,
+    pub C_WrapKeyAuthenticated: CK_C_WrapKeyAuthenticated,
+    pub C_UnwrapKeyAuthenticated: CK_C_UnwrapKeyAuthenticated,
+}
+pub type CK_FUNCTION_LIST_3_2_PTR = *const CK_FUNCTION_LIST_3_2;
+
 /* ======================================================================= *
  * ====================== v3.2 VALIDATION DRAFT ========================== *
  * ======================================================================= */
@@ -381,11 +381,27 @@ impl RsaPKCSMechanism {
                     | CKF_DECRYPT
                     | CKF_SIGN
                     | CKF_VERIFY
+                    | CKF_SIGN_RECOVER
+                    | CKF_VERIFY_RECOVER
+                    | CKF_MESSAGE_SIGN
+                    | CKF_MESSAGE_VERIFY
                     | CKF_WRAP
                     | CKF_UNWRAP,
             ),
         );
 
+        mechs.add_mechanism(
+            CKM_RSA_X_509,
+            Self::new_mechanism(
+                CKF_ENCRYPT
+                    | CKF_DECRYPT
+                    | CKF_SIGN
+                    | CKF_VERIFY
+                    | CKF_SIGN_RECOVER
+                    | CKF_VERIFY_RECOVER,
+            ),
+        );
+
         for ckm in &[
             CKM_SHA1_RSA_PKCS,
             CKM_SHA224_RSA_PKCS,
@@ -409,7 +425,12 @@ impl RsaPKCSMechanism {
         ] {
             mechs.add_mechanism(
                 *ckm,
-                Self::new_mechanism(CKF_SIGN | CKF_VERIFY),
+                Self::new_mechanism(
+                    CKF_SIGN
+                        | CKF_VERIFY
+                        | CKF_MESSAGE_SIGN
+                        | CKF_MESSAGE_VERIFY,
+                ),
             );
         }
 
@@ -495,6 +516,54 @@ impl Mechanism for RsaPKCSMechanism {
             mech, key, &self.info,
         )?))
     }
+    fn sign_recover_new(
+        &self,
+        mech: &CK_MECHANISM,
+        key: &Object,
+    ) -> Result<Box<dyn SignRecover>> {
+        if self.info.flags & CKF_SIGN_RECOVER != CKF_SIGN_RECOVER {
+            return Err(CKR_MECHANISM_INVALID)?;
+        }
+        key.check_key_ops(CKO_PRIVATE_KEY, CKK_RSA, CKA_SIGN_RECOVER)?;
+        Ok(Box::new(RsaPKCSOperation::sign_new(mech, key, &self.info)?))
+    }
+    fn verify_recover_new(
+        &self,
+        mech: &CK_MECHANISM,
+        key: &Object,
+    ) -> Result<Box<dyn VerifyRecover>> {
+        if self.info.flags & CKF_VERIFY_RECOVER != CKF_VERIFY_RECOVER {
+            return Err(CKR_MECHANISM_INVALID)?;
+        }
+        key.check_key_ops(CKO_PUBLIC_KEY, CKK_RSA, CKA_VERIFY_RECOVER)?;
+        Ok(Box::new(RsaPKCSOperation::verify_new(
+            mech, key, &self.info,
+        )?))
+    }
+    fn msg_sign_op(
+        &self,
+        mech: &CK_MECHANISM,
+        key: &Object,
+    ) -> Result<Box<dyn MsgSign>> {
+        if self.info.flags & CKF_MESSAGE_SIGN != CKF_MESSAGE_SIGN {
+            return Err(CKR_MECHANISM_INVALID)?;
+        }
+        key.check_key_ops(CKO_PRIVATE_KEY, CKK_RSA, CKA_SIGN)?;
+        Ok(Box::new(RsaPKCSOperation::sign_new(mech, key, &self.info)?))
+    }
+    fn msg_verify_op(
+        &self,
+        mech: &CK_MECHANISM,
+        key: &Object,
+    ) -> Result<Box<dyn MsgVerify>> {
+        if self.info.flags & CKF_MESSAGE_VERIFY != CKF_MESSAGE_VERIFY {
+            return Err(CKR_MECHANISM_INVALID)?;
+        }
+        key.check_key_ops(CKO_PUBLIC_KEY, CKK_RSA, CKA_VERIFY)?;
+        Ok(Box::new(RsaPKCSOperation::verify_new(
+            mech, key, &self.info,
+        )?))
+    }
 
     fn generate_keypair(
         &self,
@@ -1,13 +1,20 @@
 // Copyright 2023 Simo Sorce
 // See LICENSE.txt file for terms
 
+use std::collections::VecDeque;
+use std::sync::Mutex;
 use std::vec::Vec;
 
 use crate::error::Result;
+use crate::get_random_data;
 use crate::interface::*;
 use crate::mechanism::{Operation, SearchOperation};
+use crate::misc::CK_ULONG_SIZE;
 use crate::token::Token;
 
+use constant_time_eq::constant_time_eq;
+use once_cell::sync::Lazy;
+
 #[cfg(feature = "fips")]
 use crate::fips;
 
@@ -34,6 +41,43 @@ impl SearchOperation for SessionSearch {
     }
 }
 
+/* OpenSSL does not allow to export the internal state of digest, mac
+ * or cipher contexts, therefore the state returned to applications is
+ * only a reference to a copy of the operation that is kept in the
+ * memory of this process. Saved states can't be restored by another
+ * process, but they are independent of the session that saved them
+ * and they are retained across C_Finalize.
+ * The whole blob is stored along with the copy, and it is compared in
+ * full when the state is restored, so any alteration is detected. The
+ * state is also bound to the slot and to the unique id of the key used
+ * by the operation, the key handle may change after C_Finalize.
+ * Only the MAX_SAVED_STATES most recently saved states are retained,
+ * older states are discarded and can't be restored anymore */
+const SAVED_STATE_MAGIC: &[u8; 4] = b"KRSS";
+const SAVED_STATE_VERSION: u32 = 1;
+const SAVED_STATE_ID_LEN: usize = 16;
+const SAVED_STATE_LEN: usize = 4 + 4 + 4 + CK_ULONG_SIZE + SAVED_STATE_ID_LEN;
+/* Caps the memory used for states that are never restored */
+const MAX_SAVED_STATES: usize = 1024;
+
+const SAVED_OP_ENCRYPTION: u32 = 1;
+const SAVED_OP_DECRYPTION: u32 = 2;
+const SAVED_OP_DIGEST: u32 = 3;
+const SAVED_OP_SIGN: u32 = 4;
+const SAVED_OP_VERIFY: u32 = 5;
+
+#[derive(Debug)]
+struct SavedState {
+    blob: Vec<u8>,
+    slot_id: CK_SLOT_ID,
+    key_uid: Option<String>,
+    operation: Operation,
+    fips_indicator: Option<bool>,
+}
+
+static SAVED_STATES: Lazy<Mutex<VecDeque<SavedState>>> =
+    Lazy::new(|| Mutex::new(VecDeque::new()));
+
 #[derive(Debug)]
 pub enum OpLoginStatus {
     NotInitialized,
@@ -48,8 +92,61 @@ pub struct Session {
     //application: CK_VOID_PTR,
     //notify: CK_NOTIFY,
     operation: Operation,
+    operation_key: CK_OBJECT_HANDLE,
     login_status: OpLoginStatus,
+    /* second operation, only used when the application starts two
+     * operations that are meant to be used with the dual-function
+     * calls, like a digest and an encryption */
+    dual_operation: Operation,
+    dual_operation_key: CK_OBJECT_HANDLE,
+    dual_login_status: OpLoginStatus,
     fips_indicator: Option<bool>,
+    dual_fips_indicator: Option<bool>,
+}
+
+/* The pairs of operations that can be active at the same time */
+fn dual_operations_allowed(a: &Operation, b: &Operation) -> bool {
+    matches!(
+        (a, b),
+        (Operation::Digest(_), Operation::Encryption(_))
+            | (Operation::Encryption(_), Operation::Digest(_))
+            | (Operation::Decryption(_), Operation::Digest(_))
+            | (Operation::Digest(_), Operation::Decryption(_))
+            | (Operation::Sign(_), Operation::Encryption(_))
+            | (Operation::Encryption(_), Operation::Sign(_))
+            | (Operation::Decryption(_), Operation::Verify(_))
+            | (Operation::Verify(_), Operation::Decryption(_))
+    )
+}
+
+/* Returns the flag C_SessionCancel uses to select the operation, the
+ * same flag used to advertise it in the mechanism info */
+fn cancel_flag(op: &Operation) -> CK_FLAGS {
+    match op {
+        Operation::Encryption(_) => CKF_ENCRYPT,
+        Operation::Decryption(_) => CKF_DECRYPT,
+        Operation::Digest(_) => CKF_DIGEST,
+        Operation::Sign(_) => CKF_SIGN,
+        Operation::SignRecover(_) => CKF_SIGN_RECOVER,
+        Operation::Verify(_) => CKF_VERIFY,
+        Operation::VerifyRecover(_) => CKF_VERIFY_RECOVER,
+        Operation::Search(_) => CKF_FIND_OBJECTS,
+        Operation::MsgEncryption(_) => CKF_MESSAGE_ENCRYPT,
+        Operation::MsgDecryption(_) => CKF_MESSAGE_DECRYPT,
+        Operation::MsgSign(_) => CKF_MESSAGE_SIGN,
+        Operation::MsgVerify(_) => CKF_MESSAGE_VERIFY,
+        Operation::VerifySignature(_) => CKF_VERIFY,
+        _ => 0,
+    }
+}
+
+fn check_login_status(status: &OpLoginStatus) -> Result<()> {
+    match status {
+        OpLoginStatus::NotInitialized => Err(CKR_GENERAL_ERROR)?,
+        OpLoginStatus::NotRequired => Ok(()),
+        OpLoginStatus::Required => Err(CKR_USER_NOT_LOGGED_IN)?,
+        OpLoginStatus::LoginOk => Ok(()),
+    }
 }
 
 impl Session {
@@ -96,8 +193,13 @@ impl Session {
             //application: std::ptr::null_mut(),
             //notify: unsafe { std::ptr::null_mut() },
             operation: Operation::Empty,
+            operation_key: CK_INVALID_HANDLE,
             login_status: OpLoginStatus::NotInitialized,
+            dual_operation: Operation::Empty,
+            dual_operation_key: CK_INVALID_HANDLE,
+            dual_login_status: OpLoginStatus::NotInitialized,
             fips_indicator: None,
+            dual_fips_indicator: None,
         })
     }
 
@@ -211,20 +313,19 @@ impl Session {
         token: &mut Token,
         template: &[CK_ATTRIBUTE],
     ) -> Result<()> {
-        if !self.operation.finalized() {
+        if self.has_active_operation() {
             return Err(CKR_OPERATION_ACTIVE)?;
         }
-        self.operation = Operation::Search(Box::new(SessionSearch {
+        let search = Operation::Search(Box::new(SessionSearch {
             handles: token.search_objects(template)?,
             in_use: true,
         }));
-        self.login_status = OpLoginStatus::NotRequired;
-        self.fips_indicator = None;
+        self.set_operation(search, false);
         Ok(())
     }
 
-    pub fn get_operation_nocheck(&self) -> &Operation {
-        &self.operation
+    pub fn has_active_operation(&self) -> bool {
+        !self.operation.finalized() || !self.dual_operation.finalized()
     }
 
     pub fn get_operation(&self) -> Result<&Operation> {
@@ -245,17 +346,322 @@ impl Session {
         }
     }
 
+    /* Swaps the two operations along with their key, login status and
+     * fips indicator */
+    fn swap_operations(&mut self) {
+        std::mem::swap(&mut self.operation, &mut self.dual_operation);
+        std::mem::swap(&mut self.operation_key, &mut self.dual_operation_key);
+        std::mem::swap(&mut self.login_status, &mut self.dual_login_status);
+        std::mem::swap(&mut self.fips_indicator, &mut self.dual_fips_indicator);
+    }
+
+    /* Returns the operation selected by the is_op check, looking also
+     * at the second operation when two operations are active. The
+     * selected operation becomes the primary one, so that the fips
+     * indicator reflects the last operation used */
+    pub fn get_operation_mut_by(
+        &mut self,
+        is_op: fn(&Operation) -> bool,
+    ) -> Result<&mut Operation> {
+        if is_op(&self.operation) {
+            check_login_status(&self.login_status)?;
+            Ok(&mut self.operation)
+        } else if is_op(&self.dual_operation) {
+            check_login_status(&self.dual_login_status)?;
+            self.swap_operations();
+            Ok(&mut self.operation)
+        } else {
+            Err(CKR_OPERATION_NOT_INITIALIZED)?
+        }
+    }
+
+    /* Returns both operations used by a dual-function call, in the
+     * order selected by the is_first and is_second checks */
+    pub fn get_dual_operations_mut(
+        &mut self,
+        is_first: fn(&Operation) -> bool,
+        is_second: fn(&Operation) -> bool,
+    ) -> Result<(&mut Operation, &mut Operation)> {
+        let swapped = if is_first(&self.operation)
+            && is_second(&self.dual_operation)
+        {
+            false
+        } else if is_first(&self.dual_operation) && is_second(&self.operation) {
+            true
+        } else {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        };
+        check_login_status(&self.login_status)?;
+        check_login_status(&self.dual_login_status)?;
+        if swapped {
+            Ok((&mut self.dual_operation, &mut self.operation))
+        } else {
+            Ok((&mut self.operation, &mut self.dual_operation))
+        }
+    }
+
+    fn set_dual_empty(&mut self) {
+        self.dual_operation = Operation::Empty;
+        self.dual_operation_key = CK_INVALID_HANDLE;
+        self.dual_login_status = OpLoginStatus::NotInitialized;
+        self.dual_fips_indicator = None;
+    }
+
     pub fn set_operation(&mut self, op: Operation, needs_login: bool) {
         self.fips_indicator = None;
         self.operation = op;
+        self.operation_key = CK_INVALID_HANDLE;
         self.login_status = if needs_login {
             OpLoginStatus::Required
         } else {
             OpLoginStatus::NotRequired
         };
+        self.set_dual_empty();
+    }
+
+    /* Starts a new operation, if another operation is still active
+     * the two are kept side by side, which is allowed only for the
+     * combinations that can be used with the dual-function calls.
+     * The new operation becomes the primary one, and the one already
+     * active is moved aside with its own key and fips indicator.
+     * The key is recorded so that a saved state can only be restored
+     * with the same key */
+    pub fn start_operation(
+        &mut self,
+        op: Operation,
+        key: CK_OBJECT_HANDLE,
+        needs_login: bool,
+    ) -> Result<()> {
+        if !self.has_active_operation() {
+            self.set_operation(op, needs_login);
+            self.operation_key = key;
+            return Ok(());
+        }
+        if !self.operation.finalized() {
+            if !self.dual_operation.finalized() {
+                return Err(CKR_OPERATION_ACTIVE)?;
+            }
+            self.swap_operations();
+        }
+        if !dual_operations_allowed(&self.dual_operation, &op) {
+            return Err(CKR_OPERATION_ACTIVE)?;
+        }
+        self.operation = op;
+        self.operation_key = key;
+        self.login_status = if needs_login {
+            OpLoginStatus::Required
+        } else {
+            OpLoginStatus::NotRequired
+        };
+        self.fips_indicator = None;
+        Ok(())
+    }
+
+    /* Checks whether a new operation of the type selected by is_op may
+     * be started, the final check on whether the new operation can be
+     * paired with an active one is done in start_operation() */
+    pub fn can_start_operation(&self, is_op: fn(&Operation) -> bool) -> bool {
+        let active = !self.operation.finalized();
+        let dual_active = !self.dual_operation.finalized();
+        if active && dual_active {
+            return false;
+        }
+        if active && is_op(&self.operation) {
+            return false;
+        }
+        if dual_active && is_op(&self.dual_operation) {
+            return false;
+        }
+        true
+    }
+
+    /* Terminates the active operation selected by the is_op check,
+     * returns false if there is no such operation */
+    pub fn cancel_operation(&mut self, is_op: fn(&Operation) -> bool) -> bool {
+        if is_op(&self.operation) && !self.operation.finalized() {
+            if self.dual_operation.finalized() {
+                self.set_operation(Operation::Empty, false);
+            } else {
+                self.swap_operations();
+                self.set_dual_empty();
+            }
+            true
+        } else if is_op(&self.dual_operation)
+            && !self.dual_operation.finalized()
+        {
+            self.set_dual_empty();
+            true
+        } else {
+            false
+        }
+    }
+
+    /* Terminates all the operations selected by the flags, including
+     * those that have no work left but were not finalized by the
+     * application yet, like a search that returned all its results.
+     * The operations are dropped, which also clears any buffered data */
+    pub fn cancel_operations(&mut self, flags: CK_FLAGS) {
+        let flag = cancel_flag(&self.dual_operation);
+        if flag != 0 && flags & flag == flag {
+            self.set_dual_empty();
+        }
+        let flag = cancel_flag(&self.operation);
+        if flag != 0 && flags & flag == flag {
+            if self.dual_operation.finalized() {
+                self.set_operation(Operation::Empty, false);
+            } else {
+                self.swap_operations();
+                self.set_dual_empty();
+            }
+        }
+    }
+
+    pub fn login_required(&self) -> bool {
+        matches!(self.login_status, OpLoginStatus::Required)
+            || matches!(self.dual_login_status, OpLoginStatus::Required)
     }
 
     pub fn set_login_ok(&mut self) {
-        self.login_status = OpLoginStatus::LoginOk;
+        if let OpLoginStatus::Required = self.login_status {
+            self.login_status = OpLoginStatus::LoginOk;
+        }
+        if let OpLoginStatus::Required = self.dual_login_status {
+            self.dual_login_status = OpLoginStatus::LoginOk;
+        }
+    }
+
+    fn saved_operation_type(&self) -> Result<u32> {
+        if !self.has_active_operation() {
+            return Err(CKR_OPERATION_NOT_INITIALIZED)?;
+        }
+        /* saving the state of dual-function operations is not supported */
+        match self.dual_operation {
+            Operation::Empty => (),
+            _ => return Err(CKR_STATE_UNSAVEABLE)?,
+        }
+        match self.get_operation()? {
+            Operation::Encryption(_) => Ok(SAVED_OP_ENCRYPTION),
+            Operation::Decryption(_) => Ok(SAVED_OP_DECRYPTION),
+            Operation::Digest(_) => Ok(SAVED_OP_DIGEST),
+            Operation::Sign(_) => Ok(SAVED_OP_SIGN),
+            Operation::Verify(_) => Ok(SAVED_OP_VERIFY),
+            _ => Err(CKR_STATE_UNSAVEABLE)?,
+        }
+    }
+
+    /* Returns the length of the state get_operation_state() would
+     * return, without saving it */
+    pub fn get_operation_state_len(&self) -> Result<usize> {
+        self.saved_operation_type()?;
+        /* not all operations can be copied */
+        let _ = self.get_operation()?.try_clone()?;
+        Ok(SAVED_STATE_LEN)
+    }
+
+    pub fn get_operation_state(&self, token: &mut Token) -> Result<Vec<u8>> {
+        let optype = self.saved_operation_type()?;
+        let key_uid = if self.operation_key != CK_INVALID_HANDLE {
+            let key = token.get_object_by_handle(self.operation_key)?;
+            Some(key.get_attr_as_string(CKA_UNIQUE_ID)?)
+        } else {
+            None
+        };
+        let operation = self.get_operation()?.try_clone()?;
+        let mut id = vec![0u8; SAVED_STATE_ID_LEN];
+        get_random_data(&mut id)?;
+
+        let mut blob = Vec::<u8>::with_capacity(SAVED_STATE_LEN);
+        blob.extend_from_slice(SAVED_STATE_MAGIC);
+        blob.extend_from_slice(&SAVED_STATE_VERSION.to_be_bytes());
+        blob.extend_from_slice(&optype.to_be_bytes());
+        blob.extend_from_slice(&self.info.slotID.to_be_bytes());
+        blob.extend_from_slice(&id);
+
+        let mut saved = match SAVED_STATES.lock() {
+            Ok(s) => s,
+            Err(_) => return Err(CKR_GENERAL_ERROR)?,
+        };
+        if saved.len() >= MAX_SAVED_STATES {
+            saved.pop_front();
+        }
+        saved.push_back(SavedState {
+            blob: blob.clone(),
+            slot_id: self.info.slotID,
+            key_uid,
+            operation,
+            fips_indicator: self.fips_indicator,
+        });
+        Ok(blob)
+    }
+
+    pub fn set_operation_state(
+        &mut self,
+        token: &mut Token,
+        state: &[u8],
+        encryption_key: CK_OBJECT_HANDLE,
+        authentication_key: CK_OBJECT_HANDLE,
+    ) -> Result<()> {
+        if state.len() != SAVED_STATE_LEN
+            || !state.starts_with(SAVED_STATE_MAGIC)
+        {
+            return Err(CKR_SAVED_STATE_INVALID)?;
+        }
+        let saved = match SAVED_STATES.lock() {
+            Ok(s) => s,
+            Err(_) => return Err(CKR_GENERAL_ERROR)?,
+        };
+        let entry =
+            match saved.iter().find(|e| constant_time_eq(&e.blob, state)) {
+                Some(e) => e,
+                None => return Err(CKR_SAVED_STATE_INVALID)?,
+            };
+        if entry.slot_id != self.info.slotID {
+            return Err(CKR_SAVED_STATE_INVALID)?;
+        }
+
+        let (needed, not_needed) = match entry.operation {
+            Operation::Encryption(_) | Operation::Decryption(_) => {
+                (encryption_key, authentication_key)
+            }
+            Operation::Sign(_) | Operation::Verify(_) => {
+                (authentication_key, encryption_key)
+            }
+            _ => {
+                if encryption_key != CK_INVALID_HANDLE {
+                    return Err(CKR_KEY_NOT_NEEDED)?;
+                }
+                (CK_INVALID_HANDLE, authentication_key)
+            }
+        };
+        if entry.key_uid.is_some() && needed == CK_INVALID_HANDLE {
+            return Err(CKR_KEY_NEEDED)?;
+        }
+        if not_needed != CK_INVALID_HANDLE {
+            return Err(CKR_KEY_NOT_NEEDED)?;
+        }
+        let mut needs_login = false;
+        let mut operation_key = CK_INVALID_HANDLE;
+        if let Some(uid) = &entry.key_uid {
+            let key = match token.get_object_by_handle(needed) {
+                Ok(k) => k,
+                Err(_) => return Err(CKR_KEY_HANDLE_INVALID)?,
+            };
+            if key.get_attr_as_string(CKA_UNIQUE_ID)? != *uid {
+                return Err(CKR_KEY_CHANGED)?;
+            }
+            needs_login = match entry.operation {
+                Operation::Decryption(_) | Operation::Sign(_) => {
+                    key.always_auth()
+                }
+                _ => false,
+            };
+            operation_key = needed;
+        }
+
+        let operation = entry.operation.try_clone()?;
+        self.set_operation(operation, needs_login);
+        self.operation_key = operation_key;
+        self.fips_indicator = entry.fips_indicator;
+        Ok(())
     }
 }
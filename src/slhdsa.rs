@@ -0,0 +1,320 @@
+// Copyright 2024 Simo Sorce
+// See LICENSE.txt file for terms
+
+use std::fmt::Debug;
+
+use crate::attr_element;
+use crate::attribute::Attribute;
+use crate::error::Result;
+use crate::interface::*;
+use crate::mechanism::*;
+use crate::object::*;
+use crate::ossl::slhdsa::SlhDsaOperation;
+
+use once_cell::sync::Lazy;
+
+pub const MIN_SLH_DSA_SIZE_BITS: usize = 128;
+pub const MAX_SLH_DSA_SIZE_BITS: usize = 256;
+
+/* FIPS 205, Table 2: sizes of the public key, private key and signature
+ * for each parameter set */
+pub fn slhdsa_sizes(
+    param_set: CK_SLH_DSA_PARAMETER_SET_TYPE,
+) -> Result<(usize, usize, usize)> {
+    match param_set {
+        CKP_SLH_DSA_SHA2_128S | CKP_SLH_DSA_SHAKE_128S => Ok((32, 64, 7856)),
+        CKP_SLH_DSA_SHA2_128F | CKP_SLH_DSA_SHAKE_128F => Ok((32, 64, 17088)),
+        CKP_SLH_DSA_SHA2_192S | CKP_SLH_DSA_SHAKE_192S => Ok((48, 96, 16224)),
+        CKP_SLH_DSA_SHA2_192F | CKP_SLH_DSA_SHAKE_192F => Ok((48, 96, 35664)),
+        CKP_SLH_DSA_SHA2_256S | CKP_SLH_DSA_SHAKE_256S => Ok((64, 128, 29792)),
+        CKP_SLH_DSA_SHA2_256F | CKP_SLH_DSA_SHAKE_256F => Ok((64, 128, 49856)),
+        _ => Err(CKR_ATTRIBUTE_VALUE_INVALID)?,
+    }
+}
+
+pub fn get_param_set(key: &Object) -> Result<CK_SLH_DSA_PARAMETER_SET_TYPE> {
+    let param_set = match key.get_attr_as_ulong(CKA_PARAMETER_SET) {
+        Ok(p) => p,
+        Err(e) => {
+            if e.attr_not_found() {
+                Err(CKR_TEMPLATE_INCOMPLETE)?;
+            }
+            return Err(e);
+        }
+    };
+    /* validates the parameter set */
+    slhdsa_sizes(param_set)?;
+    Ok(param_set)
+}
+
+#[derive(Debug)]
+pub struct SlhDsaPubFactory {
+    attributes: Vec<ObjectAttr>,
+}
+
+impl SlhDsaPubFactory {
+    pub fn new() -> SlhDsaPubFactory {
+        let mut data: SlhDsaPubFactory = SlhDsaPubFactory {
+            attributes: Vec::new(),
+        };
+        data.attributes.append(&mut data.init_common_object_attrs());
+        data.attributes
+            .append(&mut data.init_common_storage_attrs());
+        data.attributes.append(&mut data.init_common_key_attrs());
+        data.attributes
+            .append(&mut data.init_common_public_key_attrs());
+        data.attributes.push(attr_element!(
+            CKA_PARAMETER_SET; OAFlags::AlwaysRequired
+            | OAFlags::Unchangeable; Attribute::from_ulong; val 0));
+        data.attributes.push(attr_element!(
+            CKA_VALUE; OAFlags::RequiredOnCreate
+            | OAFlags::SettableOnlyOnCreate | OAFlags::Unchangeable;
+            Attribute::from_bytes; val Vec::new()));
+        data
+    }
+}
+
+impl ObjectFactory for SlhDsaPubFactory {
+    fn create(&self, template: &[CK_ATTRIBUTE]) -> Result<Object> {
+        let obj = self.default_object_create(template)?;
+
+        /* PKCS#11 3.2 6.x SLH-DSA public key objects:
+         * CKA_VALUE, Byte array,
+         * Public key bytes as defined by FIPS 205 */
+        let (pklen, _, _) = slhdsa_sizes(get_param_set(&obj)?)?;
+        match obj.get_attr_as_bytes(CKA_VALUE) {
+            Ok(v) => {
+                if v.len() != pklen {
+                    return Err(CKR_ATTRIBUTE_VALUE_INVALID)?;
+                }
+            }
+            Err(e) => {
+                if e.attr_not_found() {
+                    return Err(CKR_TEMPLATE_INCOMPLETE)?;
+                } else {
+                    return Err(e);
+                }
+            }
+        }
+
+        Ok(obj)
+    }
+
+    fn get_attributes(&self) -> &Vec<ObjectAttr> {
+        &self.attributes
+    }
+}
+
+impl CommonKeyFactory for SlhDsaPubFactory {}
+
+impl PubKeyFactory for SlhDsaPubFactory {}
+
+#[derive(Debug)]
+pub struct SlhDsaPrivFactory {
+    attributes: Vec<ObjectAttr>,
+}
+
+impl SlhDsaPrivFactory {
+    pub fn new() -> SlhDsaPrivFactory {
+        let mut data: SlhDsaPrivFactory = SlhDsaPrivFactory {
+            attributes: Vec::new(),
+        };
+        data.attributes.append(&mut data.init_common_object_attrs());
+        data.attributes
+            .append(&mut data.init_common_storage_attrs());
+        data.attributes.append(&mut data.init_common_key_attrs());
+        data.attributes
+            .append(&mut data.init_common_private_key_attrs());
+        data.attributes.push(attr_element!(
+            CKA_PARAMETER_SET; OAFlags::RequiredOnCreate
+            | OAFlags::Unchangeable; Attribute::from_ulong; val 0));
+        data.attributes.push(attr_element!(
+            CKA_VALUE; OAFlags::Sensitive | OAFlags::RequiredOnCreate
+            | OAFlags::SettableOnlyOnCreate | OAFlags::Unchangeable;
+            Attribute::from_bytes; val Vec::new()));
+
+        /* default to private */
+        let private = attr_element!(
+            CKA_PRIVATE; OAFlags::Defval | OAFlags::ChangeOnCopy;
+            Attribute::from_bool; val true);
+        match data
+            .attributes
+            .iter()
+            .position(|x| x.get_type() == CKA_PRIVATE)
+        {
+            Some(idx) => data.attributes[idx] = private,
+            None => data.attributes.push(private),
+        }
+
+        data
+    }
+}
+
+impl ObjectFactory for SlhDsaPrivFactory {
+    fn create(&self, template: &[CK_ATTRIBUTE]) -> Result<Object> {
+        let obj = self.default_object_create(template)?;
+
+        /* PKCS#11 3.2 6.x SLH-DSA private key objects:
+         * CKA_VALUE, Byte array,
+         * Private key bytes as defined by FIPS 205 */
+        let (_, sklen, _) = slhdsa_sizes(get_param_set(&obj)?)?;
+        match obj.get_attr_as_bytes(CKA_VALUE) {
+            Ok(v) => {
+                if v.len() != sklen {
+                    return Err(CKR_ATTRIBUTE_VALUE_INVALID)?;
+                }
+            }
+            Err(e) => {
+                if e.attr_not_found() {
+                    return Err(CKR_TEMPLATE_INCOMPLETE)?;
+                } else {
+                    return Err(e);
+                }
+            }
+        }
+
+        Ok(obj)
+    }
+
+    fn get_attributes(&self) -> &Vec<ObjectAttr> {
+        &self.attributes
+    }
+}
+
+impl CommonKeyFactory for SlhDsaPrivFactory {}
+
+impl PrivKeyFactory for SlhDsaPrivFactory {}
+
+static PUBLIC_KEY_FACTORY: Lazy<Box<dyn ObjectFactory>> =
+    Lazy::new(|| Box::new(SlhDsaPubFactory::new()));
+
+static PRIVATE_KEY_FACTORY: Lazy<Box<dyn ObjectFactory>> =
+    Lazy::new(|| Box::new(SlhDsaPrivFactory::new()));
+
+#[derive(Debug)]
+struct SlhDsaMechanism {
+    info: CK_MECHANISM_INFO,
+}
+
+impl SlhDsaMechanism {
+    fn new_mechanism(flags: CK_FLAGS) -> Box<dyn Mechanism> {
+        Box::new(SlhDsaMechanism {
+            info: CK_MECHANISM_INFO {
+                ulMinKeySize: CK_ULONG::try_from(MIN_SLH_DSA_SIZE_BITS)
+                    .unwrap(),
+                ulMaxKeySize: CK_ULONG::try_from(MAX_SLH_DSA_SIZE_BITS)
+                    .unwrap(),
+                flags,
+            },
+        })
+    }
+
+    fn register_mechanisms(mechs: &mut Mechanisms) {
+        mechs.add_mechanism(
+            CKM_SLH_DSA_KEY_PAIR_GEN,
+            Self::new_mechanism(CKF_GENERATE_KEY_PAIR),
+        );
+        mechs.add_mechanism(
+            CKM_SLH_DSA,
+            Self::new_mechanism(CKF_SIGN | CKF_VERIFY),
+        );
+    }
+}
+
+impl Mechanism for SlhDsaMechanism {
+    fn info(&self) -> &CK_MECHANISM_INFO {
+        &self.info
+    }
+
+    fn sign_new(
+        &self,
+        mech: &CK_MECHANISM,
+        key: &Object,
+    ) -> Result<Box<dyn Sign>> {
+        if self.info.flags & CKF_SIGN != CKF_SIGN {
+            return Err(CKR_MECHANISM_INVALID)?;
+        }
+        key.check_key_ops(CKO_PRIVATE_KEY, CKK_SLH_DSA, CKA_SIGN)?;
+        Ok(Box::new(SlhDsaOperation::sign_new(mech, key)?))
+    }
+
+    fn verify_new(
+        &self,
+        mech: &CK_MECHANISM,
+        key: &Object,
+    ) -> Result<Box<dyn Verify>> {
+        if self.info.flags & CKF_VERIFY != CKF_VERIFY {
+            return Err(CKR_MECHANISM_INVALID)?;
+        }
+        key.check_key_ops(CKO_PUBLIC_KEY, CKK_SLH_DSA, CKA_VERIFY)?;
+        Ok(Box::new(SlhDsaOperation::verify_new(mech, key)?))
+    }
+
+    fn generate_keypair(
+        &self,
+        mech: &CK_MECHANISM,
+        pubkey_template: &[CK_ATTRIBUTE],
+        prikey_template: &[CK_ATTRIBUTE],
+    ) -> Result<(Object, Object)> {
+        let mut pubkey =
+            PUBLIC_KEY_FACTORY.default_object_generate(pubkey_template)?;
+        if !pubkey.check_or_set_attr(Attribute::from_ulong(
+            CKA_CLASS,
+            CKO_PUBLIC_KEY,
+        ))? {
+            return Err(CKR_TEMPLATE_INCONSISTENT)?;
+        }
+        if !pubkey.check_or_set_attr(Attribute::from_ulong(
+            CKA_KEY_TYPE,
+            CKK_SLH_DSA,
+        ))? {
+            return Err(CKR_TEMPLATE_INCONSISTENT)?;
+        }
+
+        let mut privkey =
+            PRIVATE_KEY_FACTORY.default_object_generate(prikey_template)?;
+        if !privkey.check_or_set_attr(Attribute::from_ulong(
+            CKA_CLASS,
+            CKO_PRIVATE_KEY,
+        ))? {
+            return Err(CKR_TEMPLATE_INCONSISTENT)?;
+        }
+        if !privkey.check_or_set_attr(Attribute::from_ulong(
+            CKA_KEY_TYPE,
+            CKK_SLH_DSA,
+        ))? {
+            return Err(CKR_TEMPLATE_INCONSISTENT)?;
+        }
+
+        let param_set = get_param_set(&pubkey)?;
+        if !privkey.check_or_set_attr(Attribute::from_ulong(
+            CKA_PARAMETER_SET,
+            param_set,
+        ))? {
+            return Err(CKR_TEMPLATE_INCONSISTENT)?;
+        }
+
+        SlhDsaOperation::generate_keypair(
+            param_set,
+            &mut pubkey,
+            &mut privkey,
+        )?;
+        default_key_attributes(&mut privkey, mech.mechanism)?;
+        default_key_attributes(&mut pubkey, mech.mechanism)?;
+
+        Ok((pubkey, privkey))
+    }
+}
+
+pub fn register(mechs: &mut Mechanisms, ot: &mut ObjectFactories) {
+    SlhDsaMechanism::register_mechanisms(mechs);
+
+    ot.add_factory(
+        ObjectType::new(CKO_PUBLIC_KEY, CKK_SLH_DSA),
+        &PUBLIC_KEY_FACTORY,
+    );
+    ot.add_factory(
+        ObjectType::new(CKO_PRIVATE_KEY, CKK_SLH_DSA),
+        &PRIVATE_KEY_FACTORY,
+    );
+}
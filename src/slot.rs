@@ -71,12 +71,19 @@ impl Slot {
         &self.slot_info
     }
 
+    pub fn is_token_present(&self) -> bool {
+        self.slot_info.flags & CKF_TOKEN_PRESENT == CKF_TOKEN_PRESENT
+    }
+
     pub fn get_token_info(&self) -> CK_TOKEN_INFO {
         let tok = self.token.read().unwrap();
         *tok.get_token_info()
     }
 
     pub fn get_token(&self) -> Result<RwLockReadGuard<'_, Token>> {
+        if !self.is_token_present() {
+            return Err(CKR_TOKEN_NOT_PRESENT)?;
+        }
         match self.token.read() {
             Ok(token) => {
                 if token.is_initialized() {
@@ -95,6 +102,9 @@ impl Slot {
         &self,
         nochecks: bool,
     ) -> Result<RwLockWriteGuard<'_, Token>> {
+        if !self.is_token_present() {
+            return Err(CKR_TOKEN_NOT_PRESENT)?;
+        }
         match self.token.write() {
             Ok(token) => {
                 if nochecks {
@@ -188,8 +198,21 @@ impl Slot {
         handles
     }
 
+    /// Detaches the token from the slot after saving it, the slot
+    /// itself stays available until a token is inserted again
+    pub fn remove_token(&mut self) -> Result<()> {
+        self.drop_all_sessions();
+        let ret = self.token.write().unwrap().save();
+        self.slot_info.flags &= !CKF_TOKEN_PRESENT;
+        ret
+    }
+
     pub fn finalize(&mut self) -> Result<()> {
         self.drop_all_sessions();
+        if !self.is_token_present() {
+            /* already saved when the token was removed */
+            return Ok(());
+        }
         self.token.write().unwrap().save()
     }
 }
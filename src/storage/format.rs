@@ -43,6 +43,19 @@ pub fn token_info_uid() -> String {
     TOKEN_INFO_UID.to_string()
 }
 
+/* Named users get their own auth object, the uid is derived from the
+ * user object uid so that it can be told apart from regular objects */
+pub fn named_user_obj_uid(username: &str) -> String {
+    format!("{}:{}", USER_OBJ_UID, username)
+}
+
+fn is_named_user_obj_uid(uid: &str) -> bool {
+    match uid.split_once(':') {
+        Some((prefix, _)) => prefix == USER_OBJ_UID,
+        None => false,
+    }
+}
+
 pub fn user_flags(
     user_type: CK_USER_TYPE,
     info: &StorageAuthInfo,
@@ -77,11 +90,21 @@ fn checked_pin(pin: &[u8]) -> &[u8] {
     pin
 }
 
-fn get_pin_uid(user_type: CK_USER_TYPE) -> Result<String> {
-    match user_type {
-        CKU_SO => Ok(so_obj_uid()),
-        CKU_USER => Ok(user_obj_uid()),
-        _ => return Err(CKR_GENERAL_ERROR)?,
+fn get_pin_uid(
+    user_type: CK_USER_TYPE,
+    username: Option<&str>,
+) -> Result<String> {
+    match (user_type, username) {
+        (CKU_SO, None) => Ok(so_obj_uid()),
+        (CKU_USER, None) => Ok(user_obj_uid()),
+        (CKU_USER, Some(name)) => {
+            if name.is_empty() {
+                return Err(CKR_ARGUMENTS_BAD)?;
+            }
+            Ok(named_user_obj_uid(name))
+        }
+        (CKU_SO, Some(_)) => Err(CKR_USER_TYPE_INVALID)?,
+        _ => Err(CKR_GENERAL_ERROR)?,
     }
 }
 
@@ -189,7 +212,7 @@ impl StdStorageFormat {
 
     fn init_pin_flags(&mut self) -> Result<CK_FLAGS> {
         let mut so_flags: CK_FLAGS = 0;
-        let uid = get_pin_uid(CKU_SO)?;
+        let uid = get_pin_uid(CKU_SO, None)?;
         let obj = match self.store.fetch_by_uid(&uid, &[]) {
             Ok(o) => o,
             Err(e) => {
@@ -207,7 +230,7 @@ impl StdStorageFormat {
         }
 
         let mut usr_flags: CK_FLAGS = 0;
-        let uid = get_pin_uid(CKU_USER)?;
+        let uid = get_pin_uid(CKU_USER, None)?;
         match self.store.fetch_by_uid(&uid, &[]) {
             Ok(obj) => {
                 let info = self.aci.user_attempts(&obj)?;
@@ -233,7 +256,7 @@ impl StdStorageFormat {
     fn default_so_pin(&mut self, facilities: &TokenFacilities) -> Result<()> {
         let auth_obj = self.aci.make_auth_object(
             facilities,
-            &get_pin_uid(CKU_SO)?,
+            &get_pin_uid(CKU_SO, None)?,
             checked_pin(&[]),
         )?;
         self.store.store_obj(auth_obj)
@@ -426,6 +449,9 @@ impl Storage for StdStorageFormat {
                         continue;
                     }
                 }
+                if is_named_user_obj_uid(&uid) {
+                    continue;
+                }
                 let handle = match facilities.handles.get_by_uid(&uid) {
                     Some(h) => *h,
                     None => {
@@ -468,11 +494,12 @@ impl Storage for StdStorageFormat {
         &mut self,
         facilities: &TokenFacilities,
         user_type: CK_USER_TYPE,
+        username: Option<&str>,
         pin: &[u8],
         flag: &mut CK_FLAGS,
         check_only: bool,
     ) -> Result<()> {
-        let uid = get_pin_uid(user_type)?;
+        let uid = get_pin_uid(user_type, username)?;
         let mut auth_obj = match self.store.fetch_by_uid(&uid, &[]) {
             Ok(o) => o,
             Err(e) => {
@@ -506,8 +533,12 @@ impl Storage for StdStorageFormat {
         }
     }
 
-    fn unauth_user(&mut self, user_type: CK_USER_TYPE) -> Result<()> {
-        let uid = get_pin_uid(user_type)?;
+    fn unauth_user(
+        &mut self,
+        user_type: CK_USER_TYPE,
+        username: Option<&str>,
+    ) -> Result<()> {
+        let uid = get_pin_uid(user_type, username)?;
         let _ = match self.store.fetch_by_uid(&uid, &[]) {
             Ok(o) => o,
             Err(e) => {
@@ -526,13 +557,29 @@ impl Storage for StdStorageFormat {
         &mut self,
         facilities: &TokenFacilities,
         user_type: CK_USER_TYPE,
+        username: Option<&str>,
         pin: &[u8],
     ) -> Result<()> {
         let obj = self.aci.make_auth_object(
             facilities,
-            &get_pin_uid(user_type)?,
+            &get_pin_uid(user_type, username)?,
             pin,
         )?;
         self.store.store_obj(obj)
     }
+
+    fn remove_user(&mut self, username: &str) -> Result<()> {
+        let uid = get_pin_uid(CKU_USER, Some(username))?;
+        match self.store.fetch_by_uid(&uid, &[]) {
+            Ok(_) => (),
+            Err(e) => {
+                if e.attr_not_found() {
+                    return Err(CKR_ARGUMENTS_BAD)?;
+                } else {
+                    return Err(e);
+                }
+            }
+        }
+        self.store.remove_by_uid(&uid)
+    }
 }
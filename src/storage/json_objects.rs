@@ -8,6 +8,7 @@ use crate::storage::format::StorageRaw;
 use data_encoding::BASE64;
 use serde::{Deserialize, Serialize};
 use serde_json::{from_reader, to_string_pretty, Map, Number, Value};
+use std::io::Write;
 
 fn uninit(e: std::io::Error) -> Error {
     if e.kind() == std::io::ErrorKind::NotFound {
@@ -17,6 +18,29 @@ fn uninit(e: std::io::Error) -> Error {
     }
 }
 
+/* Writes the new content to a temporary file that is then renamed over
+ * the original, so that a crash or a failure at any point leaves either
+ * the old or the new database in place, never a partial one */
+fn write_atomic(filename: &str, data: &[u8]) -> std::io::Result<()> {
+    let tmpname = format!("{}.tmp", filename);
+    let ret = std::fs::File::create(&tmpname)
+        .and_then(|mut f| {
+            f.write_all(data)?;
+            f.sync_all()
+        })
+        .and_then(|_| std::fs::rename(&tmpname, filename));
+    if ret.is_err() {
+        let _ = std::fs::remove_file(&tmpname);
+        return ret;
+    }
+    /* make the rename itself durable */
+    let dir = match std::path::Path::new(filename).parent() {
+        Some(p) if !p.as_os_str().is_empty() => p,
+        _ => std::path::Path::new("."),
+    };
+    std::fs::File::open(dir)?.sync_all()
+}
+
 fn to_json_value(a: &Attribute) -> Value {
     match a.get_attrtype() {
         AttrType::BoolType => match a.to_bool() {
@@ -36,11 +60,82 @@ fn to_json_value(a: &Attribute) -> Value {
             Ok(d) => Value::String(d),
             Err(_) => Value::String(String::new()),
         },
+        AttrType::ArrayType => match a.to_attr_array() {
+            Ok(v) => {
+                let mut map = Map::new();
+                for a in &v {
+                    map.insert(a.name(), to_json_value(a));
+                }
+                Value::Object(map)
+            }
+            Err(_) => Value::Null,
+        },
         AttrType::IgnoreType => Value::Null,
         AttrType::DenyType => Value::Null,
     }
 }
 
+fn from_json_value(key: &String, val: &Value) -> Result<Option<Attribute>> {
+    let (id, atype) = AttrType::attr_name_to_id_type(key)?;
+    let attr = match atype {
+        AttrType::BoolType => match val.as_bool() {
+            Some(b) => Attribute::from_bool(id, b),
+            None => return Err(CKR_ATTRIBUTE_VALUE_INVALID)?,
+        },
+        AttrType::NumType => match val.as_u64() {
+            Some(n) => Attribute::from_u64(id, n),
+            None => return Err(CKR_ATTRIBUTE_VALUE_INVALID)?,
+        },
+        AttrType::StringType => match val.as_str() {
+            Some(s) => Attribute::from_string(id, s.to_string()),
+            None => return Err(CKR_ATTRIBUTE_VALUE_INVALID)?,
+        },
+        AttrType::BytesType => match val.as_str() {
+            Some(s) => {
+                let len = match BASE64.decode_len(s.len()) {
+                    Ok(l) => l,
+                    Err(_) => return Err(CKR_GENERAL_ERROR)?,
+                };
+                let mut v = vec![0; len];
+                match BASE64.decode_mut(s.as_bytes(), &mut v) {
+                    Ok(l) => Attribute::from_bytes(id, v[0..l].to_vec()),
+                    Err(_) => return Err(CKR_GENERAL_ERROR)?,
+                }
+            }
+            None => return Err(CKR_ATTRIBUTE_VALUE_INVALID)?,
+        },
+        AttrType::DateType => match val.as_str() {
+            Some(s) => {
+                if s.is_empty() {
+                    /* special case for default empty value */
+                    Attribute::from_date_bytes(id, Vec::new())
+                } else {
+                    Attribute::from_date(id, string_to_ck_date(&s)?)
+                }
+            }
+            None => return Err(CKR_ATTRIBUTE_VALUE_INVALID)?,
+        },
+        AttrType::ArrayType => match val.as_object() {
+            Some(m) => {
+                let mut v = Vec::with_capacity(m.len());
+                for (k, e) in m {
+                    if let Some(a) = from_json_value(k, e)? {
+                        if a.get_attrtype() == AttrType::ArrayType {
+                            Err(CKR_ATTRIBUTE_VALUE_INVALID)?;
+                        }
+                        v.push(a);
+                    }
+                }
+                Attribute::from_attr_array(id, v)
+            }
+            None => return Err(CKR_ATTRIBUTE_VALUE_INVALID)?,
+        },
+        AttrType::DenyType => return Ok(None),
+        AttrType::IgnoreType => return Ok(None),
+    };
+    Ok(Some(attr))
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct JsonObject {
     attributes: Map<String, Value>,
@@ -75,52 +170,9 @@ impl JsonObjects {
         for jo in &self.objects {
             let mut obj = Object::new();
             for (key, val) in &jo.attributes {
-                let (id, atype) = AttrType::attr_name_to_id_type(key)?;
-                let attr = match atype {
-                    AttrType::BoolType => match val.as_bool() {
-                        Some(b) => Attribute::from_bool(id, b),
-                        None => return Err(CKR_ATTRIBUTE_VALUE_INVALID)?,
-                    },
-                    AttrType::NumType => match val.as_u64() {
-                        Some(n) => Attribute::from_u64(id, n),
-                        None => return Err(CKR_ATTRIBUTE_VALUE_INVALID)?,
-                    },
-                    AttrType::StringType => match val.as_str() {
-                        Some(s) => Attribute::from_string(id, s.to_string()),
-                        None => return Err(CKR_ATTRIBUTE_VALUE_INVALID)?,
-                    },
-                    AttrType::BytesType => match val.as_str() {
-                        Some(s) => {
-                            let len = match BASE64.decode_len(s.len()) {
-                                Ok(l) => l,
-                                Err(_) => return Err(CKR_GENERAL_ERROR)?,
-                            };
-                            let mut v = vec![0; len];
-                            match BASE64.decode_mut(s.as_bytes(), &mut v) {
-                                Ok(l) => {
-                                    Attribute::from_bytes(id, v[0..l].to_vec())
-                                }
-                                Err(_) => return Err(CKR_GENERAL_ERROR)?,
-                            }
-                        }
-                        None => return Err(CKR_ATTRIBUTE_VALUE_INVALID)?,
-                    },
-                    AttrType::DateType => match val.as_str() {
-                        Some(s) => {
-                            if s.len() == 0 {
-                                /* special case for default empty value */
-                                Attribute::from_date_bytes(id, Vec::new())
-                            } else {
-                                Attribute::from_date(id, string_to_ck_date(&s)?)
-                            }
-                        }
-                        None => return Err(CKR_ATTRIBUTE_VALUE_INVALID)?,
-                    },
-                    AttrType::DenyType => continue,
-                    AttrType::IgnoreType => continue,
-                };
-
-                obj.set_attr(attr)?;
+                if let Some(attr) = from_json_value(key, val)? {
+                    obj.set_attr(attr)?;
+                }
             }
             store.store_obj(obj)?;
         }
@@ -147,7 +199,7 @@ impl JsonObjects {
             Ok(j) => j,
             Err(e) => return Err(Error::other_error(e)),
         };
-        match std::fs::write(filename, jstr) {
+        match write_atomic(filename, jstr.as_bytes()) {
             Ok(_) => Ok(()),
             Err(e) => Err(Error::other_error(e)),
         }
@@ -76,17 +76,24 @@ pub trait Storage: Debug + Send + Sync {
         &mut self,
         facilities: &TokenFacilities,
         user_type: CK_USER_TYPE,
+        username: Option<&str>,
         pin: &[u8],
         flag: &mut CK_FLAGS,
         check_only: bool,
     ) -> Result<()>;
-    fn unauth_user(&mut self, user_type: CK_USER_TYPE) -> Result<()>;
+    fn unauth_user(
+        &mut self,
+        user_type: CK_USER_TYPE,
+        username: Option<&str>,
+    ) -> Result<()>;
     fn set_user_pin(
         &mut self,
         facilities: &TokenFacilities,
         user_type: CK_USER_TYPE,
+        username: Option<&str>,
         pin: &[u8],
     ) -> Result<()>;
+    fn remove_user(&mut self, username: &str) -> Result<()>;
 }
 
 mod aci;
@@ -337,7 +337,8 @@ impl NSSStorage {
                     AttrType::BoolType
                     | AttrType::StringType
                     | AttrType::BytesType
-                    | AttrType::DateType => {
+                    | AttrType::DateType
+                    | AttrType::ArrayType => {
                         if blob == &NSS_SPECIAL_NULL_VALUE {
                             Attribute::from_attr_slice(cols[i], atype, &[])
                         } else {
@@ -1084,10 +1085,15 @@ impl Storage for NSSStorage {
         &mut self,
         facilities: &TokenFacilities,
         user_type: CK_USER_TYPE,
+        username: Option<&str>,
         pin: &[u8],
         flag: &mut CK_FLAGS,
         check_only: bool,
     ) -> Result<()> {
+        /* NSS has a single password entry, no named users */
+        if username.is_some() {
+            return Err(CKR_USER_TYPE_INVALID)?;
+        }
         /* NSS supports only a CK_USER password,
          * CKU_SO is allowed only when no pin is set yet */
         match user_type {
@@ -1131,7 +1137,11 @@ impl Storage for NSSStorage {
         Ok(())
     }
 
-    fn unauth_user(&mut self, _user_type: CK_USER_TYPE) -> Result<()> {
+    fn unauth_user(
+        &mut self,
+        _user_type: CK_USER_TYPE,
+        _username: Option<&str>,
+    ) -> Result<()> {
         match self.enckey {
             Some(ref mut key) => {
                 key.zeroize();
@@ -1146,9 +1156,10 @@ impl Storage for NSSStorage {
         &mut self,
         facilities: &TokenFacilities,
         user_type: CK_USER_TYPE,
+        username: Option<&str>,
         pin: &[u8],
     ) -> Result<()> {
-        if user_type != CKU_USER {
+        if user_type != CKU_USER || username.is_some() {
             return Err(CKR_USER_TYPE_INVALID)?;
         }
 
@@ -1180,6 +1191,10 @@ impl Storage for NSSStorage {
         encdata.zeroize();
         result
     }
+
+    fn remove_user(&mut self, _username: &str) -> Result<()> {
+        Err(CKR_USER_TYPE_INVALID)?
+    }
 }
 
 #[derive(Debug)]
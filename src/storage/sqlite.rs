@@ -140,6 +140,16 @@ impl SqliteStorage {
                             None => Attribute::from_bytes(atype, Vec::new()),
                         }
                     }
+                    AttrType::ArrayType => {
+                        match val.as_blob_or_null().map_err(bad_storage)? {
+                            Some(v) => {
+                                Attribute::from_attr_slice(atype, attrtype, v)
+                            }
+                            None => {
+                                Attribute::from_attr_array(atype, Vec::new())
+                            }
+                        }
+                    }
                     AttrType::DateType => {
                         match val.as_str_or_null().map_err(bad_storage)? {
                             Some(s) => Attribute::from_date(
@@ -189,6 +199,7 @@ impl SqliteStorage {
                 AttrType::StringType => Value::from(a.to_string()?),
                 AttrType::BytesType => Value::from(a.to_bytes()?.clone()),
                 AttrType::DateType => Value::from(a.to_date_string()?),
+                AttrType::ArrayType => Value::from(a.get_value().clone()),
                 AttrType::DenyType | AttrType::IgnoreType => continue,
             };
             let _ = stmt
@@ -332,6 +343,9 @@ impl StorageRaw for SqliteStorage {
                 AttrType::DateType => {
                     Value::from(a.to_attribute()?.to_date_string()?)
                 }
+                AttrType::ArrayType => {
+                    Value::from(a.to_attribute()?.get_value().clone())
+                }
                 AttrType::DenyType | AttrType::IgnoreType => {
                     return Err(CKR_ATTRIBUTE_TYPE_INVALID)?
                 }
@@ -238,49 +238,172 @@ fn test_aes_operations() {
 
         /* Counterbits edge cases */
 
-        /* 9 bit counter, counter value should allow a single block before
-         * wrap around */
+        /* the keystream is checked against ECB encryptions of the
+         * expected counter blocks */
+        let ctr_keystream = |counters: &[[u8; 16]]| -> Vec<u8> {
+            let blocks = counters.concat();
+            ret_or_panic!(encrypt(
+                session,
+                handle,
+                &blocks,
+                &CK_MECHANISM {
+                    mechanism: CKM_AES_ECB,
+                    pParameter: std::ptr::null_mut(),
+                    ulParameterLen: 0,
+                },
+            ))
+        };
+
+        /* 9 bit counter two blocks before wrap around, the bits above
+         * the counter must not be touched when it wraps */
+        let mut cb = [0xA5u8; 16];
+        cb[15] = 0xFE;
         let param = CK_AES_CTR_PARAMS {
             ulCounterBits: 9,
-            cb: [
-                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-                0x00, 0x00, 0x00, 0x00, 0x01, 0xFE,
-            ],
+            cb,
         };
         let mut mechanism: CK_MECHANISM = CK_MECHANISM {
             mechanism: CKM_AES_CTR,
             pParameter: void_ptr!(&param),
             ulParameterLen: sizeof!(CK_AES_CTR_PARAMS),
         };
+        let mut counters = [cb; 3];
+        counters[1][15] = 0xFF;
+        counters[2][14] = 0xA4;
+        counters[2][15] = 0x00;
+        let keystream = ctr_keystream(&counters);
+
+        let data = [0x3Cu8; 40];
+        let expected: Vec<u8> = data
+            .iter()
+            .zip(keystream.iter())
+            .map(|(d, k)| d ^ k)
+            .collect();
+
+        /* updates ending both in the middle of a block across the wrap
+         * around and exactly at the wrap around */
+        for parts in [[10usize, 25, 5], [16, 16, 8]] {
+            let ret = fn_encrypt_init(session, &mut mechanism, handle);
+            assert_eq!(ret, CKR_OK);
+
+            let mut enc = [0u8; 40];
+            let mut offset = 0;
+            for len in parts {
+                let mut enc_len = (enc.len() - offset) as CK_ULONG;
+                let ret = fn_encrypt_update(
+                    session,
+                    data[offset..].as_ptr() as *mut _,
+                    len as CK_ULONG,
+                    enc[offset..].as_mut_ptr(),
+                    &mut enc_len,
+                );
+                assert_eq!(ret, CKR_OK);
+                assert_eq!(enc_len as usize, len);
+                offset += len;
+            }
+            let mut fin = [0u8; AES_BLOCK_SIZE];
+            let mut enc_len = fin.len() as CK_ULONG;
+            let ret = fn_encrypt_final(session, fin.as_mut_ptr(), &mut enc_len);
+            assert_eq!(ret, CKR_OK);
+            assert_eq!(enc_len, 0);
+            assert_eq!(enc.as_slice(), expected.as_slice());
+        }
 
-        let ret = fn_encrypt_init(session, &mut mechanism, handle);
-        assert_eq!(ret, CKR_OK);
+        let dec =
+            ret_or_panic!(decrypt(session, handle, &expected, &mechanism));
+        assert_eq!(dec.as_slice(), &data);
 
-        /* Stream mode, so arbitrary data size and matching output */
-        let mut data: [u8; 16] = [255u8; 16];
-        let enc: [u8; 16] = [0; 16];
-        let mut enc_len: CK_ULONG = 16;
+        /* 2 bit counter, the whole counter space can be used once */
+        let mut cb = [0u8; 16];
+        cb[15] = 0x03;
+        let param = CK_AES_CTR_PARAMS {
+            ulCounterBits: 2,
+            cb,
+        };
+        let mut mechanism: CK_MECHANISM = CK_MECHANISM {
+            mechanism: CKM_AES_CTR,
+            pParameter: void_ptr!(&param),
+            ulParameterLen: sizeof!(CK_AES_CTR_PARAMS),
+        };
 
-        /* First block should succeed */
-        let ret = fn_encrypt_update(
+        let mut counters = [cb; 4];
+        for (i, c) in counters.iter_mut().enumerate() {
+            c[15] = ((3 + i) % 4) as u8;
+        }
+        let keystream = ctr_keystream(&counters);
+
+        let ret = fn_decrypt_init(session, &mut mechanism, handle);
+        assert_eq!(ret, CKR_OK);
+
+        let data = [0u8; 64];
+        let mut dec = [0u8; 64];
+        let mut dec_len: CK_ULONG = 60;
+        let ret = fn_decrypt_update(
             session,
-            data.as_mut_ptr(),
-            data.len() as CK_ULONG,
-            enc.as_ptr() as *mut _,
-            &mut enc_len,
+            data.as_ptr() as *mut _,
+            60,
+            dec.as_mut_ptr(),
+            &mut dec_len,
+        );
+        assert_eq!(ret, CKR_OK);
+        let mut dec_len: CK_ULONG = 4;
+        let ret = fn_decrypt_update(
+            session,
+            data[60..].as_ptr() as *mut _,
+            4,
+            dec[60..].as_mut_ptr(),
+            &mut dec_len,
         );
         assert_eq!(ret, CKR_OK);
-        assert_eq!(enc_len as usize, data.len());
 
-        /* Second should fail */
-        let ret = fn_encrypt_update(
+        assert_eq!(dec.as_slice(), keystream.as_slice());
+
+        /* One more byte would reuse the initial counter */
+        let mut dec_len: CK_ULONG = 1;
+        let ret = fn_decrypt_update(
             session,
-            data.as_mut_ptr(),
-            data.len() as CK_ULONG,
-            enc.as_ptr() as *mut _,
-            &mut enc_len,
+            data.as_ptr() as *mut _,
+            1,
+            dec.as_mut_ptr(),
+            &mut dec_len,
         );
         assert_eq!(ret, CKR_DATA_LEN_RANGE);
+
+        /* the same limit applies to a single shot operation */
+        let data = [0u8; 65];
+        err_or_panic!(
+            encrypt(session, handle, &data, &mechanism),
+            CKR_DATA_LEN_RANGE
+        );
+
+        /* a full 128 bit counter wraps around to zero */
+        let param = CK_AES_CTR_PARAMS {
+            ulCounterBits: 128,
+            cb: [0xFFu8; 16],
+        };
+        let mechanism: CK_MECHANISM = CK_MECHANISM {
+            mechanism: CKM_AES_CTR,
+            pParameter: void_ptr!(&param),
+            ulParameterLen: sizeof!(CK_AES_CTR_PARAMS),
+        };
+        let enc =
+            ret_or_panic!(encrypt(session, handle, &[0u8; 32], &mechanism));
+        assert_eq!(enc, ctr_keystream(&[[0xFFu8; 16], [0u8; 16]]));
+
+        /* counter bits must be between 1 and 128 */
+        for bits in [0, 129] {
+            let param = CK_AES_CTR_PARAMS {
+                ulCounterBits: bits,
+                cb: [0u8; 16],
+            };
+            let mut mechanism: CK_MECHANISM = CK_MECHANISM {
+                mechanism: CKM_AES_CTR,
+                pParameter: void_ptr!(&param),
+                ulParameterLen: sizeof!(CK_AES_CTR_PARAMS),
+            };
+            let ret = fn_encrypt_init(session, &mut mechanism, handle);
+            assert_eq!(ret, CKR_MECHANISM_PARAM_INVALID);
+        }
     }
 
     {
@@ -1242,5 +1365,509 @@ fn test_aes_macs() {
         );
     }
 
+    {
+        /* AES GMAC, NIST GCM test vector with AAD only */
+
+        let key = hex::decode("77be63708971c4e240d1cb79e8d77feb").unwrap();
+        let iv = hex::decode("e0e00f19fed7ba0136a797f3").unwrap();
+        let data = hex::decode("7a43ec1d9c0a5a78a0b16533a6213cab").unwrap();
+        let tag = hex::decode("209fcc8d3675ed938e9c7166709dd946").unwrap();
+
+        let handle = ret_or_panic!(import_object(
+            session,
+            CKO_SECRET_KEY,
+            &[(CKA_KEY_TYPE, CKK_AES)],
+            &[(CKA_VALUE, &key)],
+            &[(CKA_SIGN, true), (CKA_VERIFY, true)],
+        ));
+        let mechanism = CK_MECHANISM {
+            mechanism: CKM_AES_GMAC,
+            pParameter: void_ptr!(iv.as_ptr()),
+            ulParameterLen: iv.len() as CK_ULONG,
+        };
+
+        let mac = ret_or_panic!(sig_gen(session, handle, &data, &mechanism));
+        assert_eq!(mac, tag);
+
+        assert_eq!(
+            CKR_OK,
+            sig_verify(session, handle, &data, &tag, &mechanism)
+        );
+
+        /* multi-part */
+        let ret = fn_sign_init(session, &mechanism as *const _ as _, handle);
+        assert_eq!(ret, CKR_OK);
+        let ret =
+            fn_sign_update(session, data.as_ptr() as *mut u8, 5 as CK_ULONG);
+        assert_eq!(ret, CKR_OK);
+        let ret = fn_sign_update(
+            session,
+            data[5..].as_ptr() as *mut u8,
+            (data.len() - 5) as CK_ULONG,
+        );
+        assert_eq!(ret, CKR_OK);
+        let mut mac = vec![0u8; AES_BLOCK_SIZE];
+        let mut mac_len = mac.len() as CK_ULONG;
+        let ret = fn_sign_final(session, mac.as_mut_ptr(), &mut mac_len);
+        assert_eq!(ret, CKR_OK);
+        assert_eq!(mac, tag);
+
+        /* tampered data */
+        let mut bad = data.clone();
+        bad[0] ^= 1;
+        assert_eq!(
+            CKR_SIGNATURE_INVALID,
+            sig_verify(session, handle, &bad, &tag, &mechanism)
+        );
+
+        /* the IV is required */
+        err_or_panic!(
+            sig_gen(
+                session,
+                handle,
+                &data,
+                &CK_MECHANISM {
+                    mechanism: CKM_AES_GMAC,
+                    pParameter: std::ptr::null_mut(),
+                    ulParameterLen: 0,
+                }
+            ),
+            CKR_MECHANISM_PARAM_INVALID
+        );
+    }
+
+    testtokn.finalize();
+}
+
+#[test]
+#[parallel]
+fn test_aes_xts() {
+    let mut testtokn = TestToken::initialized("test_aes_xts.sql", None);
+    let session = testtokn.get_session(true);
+
+    /* login */
+    testtokn.login();
+
+    /* IEEE 1619 test vectors 2 and 15 (ciphertext stealing) */
+    let vectors = [
+        (
+            "1111111111111111111111111111111122222222222222222222222222222222",
+            "33333333330000000000000000000000",
+            "4444444444444444444444444444444444444444444444444444444444444444",
+            "c454185e6a16936e39334038acef838bfb186fff7480adc4289382ecd6d394f0",
+        ),
+        (
+            "fffefdfcfbfaf9f8f7f6f5f4f3f2f1f0bfbebdbcbbbab9b8b7b6b5b4b3b2b1b0",
+            "9a785634120000000000000000000000",
+            "000102030405060708090a0b0c0d0e0f10",
+            "6c1625db4671522d3d7599601de7ca09ed",
+        ),
+    ];
+
+    for (key, tweak, pt, ct) in vectors {
+        let handle = ret_or_panic!(import_object(
+            session,
+            CKO_SECRET_KEY,
+            &[(CKA_KEY_TYPE, CKK_AES_XTS)],
+            &[(CKA_VALUE, &hex::decode(key).unwrap())],
+            &[(CKA_ENCRYPT, true), (CKA_DECRYPT, true)],
+        ));
+        let tweak = hex::decode(tweak).unwrap();
+        let plaintext = hex::decode(pt).unwrap();
+        let mechanism = CK_MECHANISM {
+            mechanism: CKM_AES_XTS,
+            pParameter: void_ptr!(tweak.as_ptr()),
+            ulParameterLen: tweak.len() as CK_ULONG,
+        };
+
+        let enc =
+            ret_or_panic!(encrypt(session, handle, &plaintext, &mechanism));
+        assert_eq!(hex::encode(&enc), ct);
+
+        let dec = ret_or_panic!(decrypt(session, handle, &enc, &mechanism));
+        assert_eq!(dec, plaintext);
+
+        /* multi-part, the whole data unit is returned on final */
+        let ret = fn_decrypt_init(session, &mechanism as *const _ as _, handle);
+        assert_eq!(ret, CKR_OK);
+        let mut dec = vec![0u8; enc.len()];
+        let mut dec_len = dec.len() as CK_ULONG;
+        let ret = fn_decrypt_update(
+            session,
+            byte_ptr!(enc.as_ptr()),
+            10,
+            dec.as_mut_ptr(),
+            &mut dec_len,
+        );
+        assert_eq!(ret, CKR_OK);
+        assert_eq!(dec_len, 0);
+        dec_len = dec.len() as CK_ULONG;
+        let ret = fn_decrypt_update(
+            session,
+            byte_ptr!(enc[10..].as_ptr()),
+            (enc.len() - 10) as CK_ULONG,
+            dec.as_mut_ptr(),
+            &mut dec_len,
+        );
+        assert_eq!(ret, CKR_OK);
+        assert_eq!(dec_len, 0);
+        dec_len = dec.len() as CK_ULONG;
+        let ret = fn_decrypt_final(session, dec.as_mut_ptr(), &mut dec_len);
+        assert_eq!(ret, CKR_OK);
+        assert_eq!(dec_len as usize, plaintext.len());
+        assert_eq!(dec, plaintext);
+    }
+
+    /* Generate a 256 bit XTS key (two AES-256 keys) */
+    let handle = ret_or_panic!(generate_key(
+        session,
+        CKM_AES_XTS_KEY_GEN,
+        std::ptr::null_mut(),
+        0,
+        &[(CKA_VALUE_LEN, 64)],
+        &[],
+        &[(CKA_ENCRYPT, true), (CKA_DECRYPT, true)],
+    ));
+
+    let tweak = [0x5au8; AES_BLOCK_SIZE];
+    let mechanism = CK_MECHANISM {
+        mechanism: CKM_AES_XTS,
+        pParameter: void_ptr!(tweak.as_ptr()),
+        ulParameterLen: tweak.len() as CK_ULONG,
+    };
+    let data = [0xa5u8; 100];
+    let enc = ret_or_panic!(encrypt(session, handle, &data, &mechanism));
+    assert_eq!(enc.len(), data.len());
+    let dec = ret_or_panic!(decrypt(session, handle, &enc, &mechanism));
+    assert_eq!(dec, data);
+
+    /* data units shorter than a block can't be encrypted */
+    err_or_panic!(
+        encrypt(session, handle, &data[..AES_BLOCK_SIZE - 1], &mechanism),
+        CKR_DATA_LEN_RANGE
+    );
+
+    /* the tweak is exactly one block */
+    err_or_panic!(
+        encrypt(
+            session,
+            handle,
+            &data,
+            &CK_MECHANISM {
+                mechanism: CKM_AES_XTS,
+                pParameter: void_ptr!(tweak.as_ptr()),
+                ulParameterLen: 8,
+            }
+        ),
+        CKR_ARGUMENTS_BAD
+    );
+
+    /* only double AES-128 and AES-256 keys */
+    err_or_panic!(
+        generate_key(
+            session,
+            CKM_AES_XTS_KEY_GEN,
+            std::ptr::null_mut(),
+            0,
+            &[(CKA_VALUE_LEN, 48)],
+            &[],
+            &[(CKA_ENCRYPT, true)],
+        ),
+        CKR_TEMPLATE_INCONSISTENT
+    );
+
+    /* identical halves are rejected */
+    let value = [0x42u8; 32];
+    err_or_panic!(
+        import_object(
+            session,
+            CKO_SECRET_KEY,
+            &[(CKA_KEY_TYPE, CKK_AES_XTS)],
+            &[(CKA_VALUE, &value)],
+            &[(CKA_ENCRYPT, true)],
+        ),
+        CKR_ATTRIBUTE_VALUE_INVALID
+    );
+
+    /* plain AES keys can't be used with XTS */
+    let handle = ret_or_panic!(import_object(
+        session,
+        CKO_SECRET_KEY,
+        &[(CKA_KEY_TYPE, CKK_AES)],
+        &[(CKA_VALUE, &value)],
+        &[(CKA_ENCRYPT, true)],
+    ));
+    let ret = fn_encrypt_init(session, &mechanism as *const _ as _, handle);
+    assert_eq!(ret, CKR_KEY_TYPE_INCONSISTENT);
+
+    testtokn.finalize();
+}
+
+#[cfg(feature = "ecdsa")]
+#[test]
+#[parallel]
+fn test_aes_gcm_wrap() {
+    let mut testtokn = TestToken::initialized("test_aes_gcm_wrap.sql", None);
+    let session = testtokn.get_session(true);
+
+    /* login */
+    testtokn.login();
+
+    let handle = ret_or_panic!(generate_key(
+        session,
+        CKM_AES_KEY_GEN,
+        std::ptr::null_mut(),
+        0,
+        &[(CKA_VALUE_LEN, 32)],
+        &[],
+        &[(CKA_WRAP, true), (CKA_UNWRAP, true)],
+    ));
+
+    let label = "wrapped ec key";
+    let ec_params = hex::decode(
+        "06082A8648CE3D030107", // secp256r1
+    )
+    .expect("Failed to decode hex ec_params");
+    let (pubkey, prikey) = ret_or_panic!(generate_key_pair(
+        session,
+        CKM_EC_KEY_PAIR_GEN,
+        &[(CKA_CLASS, CKO_PUBLIC_KEY), (CKA_KEY_TYPE, CKK_EC)],
+        &[(CKA_EC_PARAMS, ec_params.as_slice())],
+        &[(CKA_VERIFY, true)],
+        &[(CKA_CLASS, CKO_PRIVATE_KEY), (CKA_KEY_TYPE, CKK_EC)],
+        &[(CKA_LABEL, label.as_bytes())],
+        &[
+            (CKA_PRIVATE, true),
+            (CKA_SENSITIVE, true),
+            (CKA_SIGN, true),
+            (CKA_EXTRACTABLE, true),
+        ],
+    ));
+
+    let mut template = make_attr_template(
+        &[(CKA_CLASS, CKO_PRIVATE_KEY), (CKA_KEY_TYPE, CKK_EC)],
+        &[(CKA_LABEL, label.as_bytes())],
+        &[(CKA_PRIVATE, true), (CKA_SENSITIVE, true), (CKA_SIGN, true)],
+    );
+
+    let mut wrapped = vec![0u8; 1024];
+
+    {
+        /* C_WrapKey with caller supplied IV and the label as AAD */
+        let iv = [0x5Au8; 12];
+        let param = CK_GCM_PARAMS {
+            pIv: iv.as_ptr() as *mut CK_BYTE,
+            ulIvLen: iv.len() as CK_ULONG,
+            ulIvBits: (iv.len() * 8) as CK_ULONG,
+            pAAD: label.as_ptr() as *mut CK_BYTE,
+            ulAADLen: label.len() as CK_ULONG,
+            ulTagBits: 128,
+        };
+        let mut mechanism = CK_MECHANISM {
+            mechanism: CKM_AES_GCM,
+            pParameter: void_ptr!(&param),
+            ulParameterLen: sizeof!(CK_GCM_PARAMS),
+        };
+
+        let mut wrapped_len = wrapped.len() as CK_ULONG;
+        let ret = fn_wrap_key(
+            session,
+            &mut mechanism,
+            handle,
+            prikey,
+            wrapped.as_mut_ptr(),
+            &mut wrapped_len,
+        );
+        assert_eq!(ret, CKR_OK);
+
+        let mut prikey2 = CK_INVALID_HANDLE;
+        let ret = fn_unwrap_key(
+            session,
+            &mut mechanism,
+            handle,
+            wrapped.as_mut_ptr(),
+            wrapped_len,
+            template.as_mut_ptr(),
+            template.len() as CK_ULONG,
+            &mut prikey2,
+        );
+        assert_eq!(ret, CKR_OK);
+
+        let data = "plaintext";
+        let sigmech = CK_MECHANISM {
+            mechanism: CKM_ECDSA_SHA256,
+            pParameter: std::ptr::null_mut(),
+            ulParameterLen: 0,
+        };
+        let sig =
+            ret_or_panic!(sig_gen(session, prikey2, data.as_bytes(), &sigmech));
+        assert_eq!(
+            CKR_OK,
+            sig_verify(session, pubkey, data.as_bytes(), &sig, &sigmech)
+        );
+
+        /* different AAD */
+        let param = CK_GCM_PARAMS {
+            pAAD: "other label".as_ptr() as *mut CK_BYTE,
+            ulAADLen: 11,
+            ..param
+        };
+        let mut mechanism = CK_MECHANISM {
+            mechanism: CKM_AES_GCM,
+            pParameter: void_ptr!(&param),
+            ulParameterLen: sizeof!(CK_GCM_PARAMS),
+        };
+        let ret = fn_unwrap_key(
+            session,
+            &mut mechanism,
+            handle,
+            wrapped.as_mut_ptr(),
+            wrapped_len,
+            template.as_mut_ptr(),
+            template.len() as CK_ULONG,
+            &mut prikey2,
+        );
+        assert_ne!(ret, CKR_OK);
+    }
+
+    {
+        /* C_WrapKeyAuthenticated with a token generated IV */
+        let mut iv = [0u8; 12];
+        let mut tag = [0u8; 16];
+        let param = CK_GCM_MESSAGE_PARAMS {
+            pIv: iv.as_mut_ptr(),
+            ulIvLen: iv.len() as CK_ULONG,
+            ulIvFixedBits: 0,
+            ivGenerator: CKG_GENERATE_RANDOM,
+            pTag: tag.as_mut_ptr(),
+            ulTagBits: 128,
+        };
+        let mut mechanism = CK_MECHANISM {
+            mechanism: CKM_AES_GCM,
+            pParameter: void_ptr!(&param),
+            ulParameterLen: sizeof!(CK_GCM_MESSAGE_PARAMS),
+        };
+
+        /* length query */
+        let mut wrapped_len: CK_ULONG = 0;
+        let ret = fn_wrap_key_authenticated(
+            session,
+            &mut mechanism,
+            handle,
+            prikey,
+            label.as_ptr() as *mut CK_BYTE,
+            label.len() as CK_ULONG,
+            std::ptr::null_mut(),
+            &mut wrapped_len,
+        );
+        assert_eq!(ret, CKR_OK);
+        assert_ne!(wrapped_len, 0);
+        assert_eq!(iv, [0u8; 12]);
+
+        let ret = fn_wrap_key_authenticated(
+            session,
+            &mut mechanism,
+            handle,
+            prikey,
+            label.as_ptr() as *mut CK_BYTE,
+            label.len() as CK_ULONG,
+            wrapped.as_mut_ptr(),
+            &mut wrapped_len,
+        );
+        assert_eq!(ret, CKR_OK);
+        assert_ne!(iv, [0u8; 12]);
+        assert_ne!(tag, [0u8; 16]);
+
+        /* the generated IV and the tag are returned in the parameters,
+         * and are used as they are to unwrap */
+        let mut prikey2 = CK_INVALID_HANDLE;
+        let ret = fn_unwrap_key_authenticated(
+            session,
+            &mut mechanism,
+            handle,
+            wrapped.as_mut_ptr(),
+            wrapped_len,
+            template.as_mut_ptr(),
+            template.len() as CK_ULONG,
+            label.as_ptr() as *mut CK_BYTE,
+            label.len() as CK_ULONG,
+            &mut prikey2,
+        );
+        assert_eq!(ret, CKR_OK);
+
+        let data = "plaintext";
+        let sigmech = CK_MECHANISM {
+            mechanism: CKM_ECDSA_SHA256,
+            pParameter: std::ptr::null_mut(),
+            ulParameterLen: 0,
+        };
+        let sig =
+            ret_or_panic!(sig_gen(session, prikey2, data.as_bytes(), &sigmech));
+        assert_eq!(
+            CKR_OK,
+            sig_verify(session, pubkey, data.as_bytes(), &sig, &sigmech)
+        );
+
+        /* different AAD */
+        let ret = fn_unwrap_key_authenticated(
+            session,
+            &mut mechanism,
+            handle,
+            wrapped.as_mut_ptr(),
+            wrapped_len,
+            template.as_mut_ptr(),
+            template.len() as CK_ULONG,
+            "other label".as_ptr() as *mut CK_BYTE,
+            11,
+            &mut prikey2,
+        );
+        assert_ne!(ret, CKR_OK);
+
+        /* tampered tag */
+        let mut bad_tag = tag;
+        bad_tag[0] ^= 1;
+        let param = CK_GCM_MESSAGE_PARAMS {
+            pTag: bad_tag.as_mut_ptr(),
+            ..param
+        };
+        let mut mechanism = CK_MECHANISM {
+            mechanism: CKM_AES_GCM,
+            pParameter: void_ptr!(&param),
+            ulParameterLen: sizeof!(CK_GCM_MESSAGE_PARAMS),
+        };
+        let ret = fn_unwrap_key_authenticated(
+            session,
+            &mut mechanism,
+            handle,
+            wrapped.as_mut_ptr(),
+            wrapped_len,
+            template.as_mut_ptr(),
+            template.len() as CK_ULONG,
+            label.as_ptr() as *mut CK_BYTE,
+            label.len() as CK_ULONG,
+            &mut prikey2,
+        );
+        assert_ne!(ret, CKR_OK);
+
+        /* only AES GCM supports authenticated wrapping */
+        let mut mechanism = CK_MECHANISM {
+            mechanism: CKM_AES_KEY_WRAP_KWP,
+            pParameter: std::ptr::null_mut(),
+            ulParameterLen: 0,
+        };
+        let mut wrapped_len = wrapped.len() as CK_ULONG;
+        let ret = fn_wrap_key_authenticated(
+            session,
+            &mut mechanism,
+            handle,
+            prikey,
+            label.as_ptr() as *mut CK_BYTE,
+            label.len() as CK_ULONG,
+            wrapped.as_mut_ptr(),
+            &mut wrapped_len,
+        );
+        assert_eq!(ret, CKR_MECHANISM_INVALID);
+    }
+
     testtokn.finalize();
 }
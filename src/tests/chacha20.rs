@@ -0,0 +1,487 @@
+// Copyright 2024 Simo Sorce
+// See LICENSE.txt file for terms
+
+use crate::tests::*;
+
+use serial_test::parallel;
+
+/* Test vectors from RFC 8439 and draft-irtf-cfrg-xchacha */
+const SUNSCREEN: &[u8] = b"Ladies and Gentlemen of the class of '99: \
+    If I could offer you only one tip for the future, sunscreen would be it.";
+
+const RFC8439_STREAM_CT: &str = "\
+    6e2e359a2568f98041ba0728dd0d6981e97e7aec1d4360c20a27afccfd9fae0b\
+    f91b65c5524733ab8f593dabcd62b3571639d624e65152ab8f530c359f0861d8\
+    07ca0dbf500d6a6156a38e088a22b65e52bc514d16ccf806818ce91ab7793736\
+    5af90bbf74a35be6b40b8eedf2785e42874d";
+
+const ORIGINAL_STREAM_CT: &str = "\
+    bbf9c5e094e6c608ec747fbc0165c3191a3af8cd366d9ab39869c9769b36a2b2\
+    47843b1d531c7f780a6280222000370a6aa6d23dca7335973c643525449d0475\
+    566cf2ba49d250b46a4d34371ae5ac46fdb6f0062ff3ad2dfb9e4cc8355ca7ce\
+    f928ae79af38c9394bd5d784c617d8477d89";
+
+const RFC8439_AEAD_NONCE: &str = "070000004041424344454647";
+const XCHACHA_NONCE: &str = "404142434445464748494a4b4c4d4e4f5051525354555657";
+const AEAD_AAD: &str = "50515253c0c1c2c3c4c5c6c7";
+
+const RFC8439_AEAD_CT: &str = "\
+    d31a8d34648e60db7b86afbc53ef7ec2a4aded51296e08fea9e2b5a736ee62d6\
+    3dbea45e8ca9671282fafb69da92728b1a71de0a9e060b2905d6a5b67ecd3b36\
+    92ddbd7f2d778b8c9803aee328091b58fab324e4fad675945585808b4831d7bc\
+    3ff4def08e4b7a9de576d26586cec64b6116\
+    1ae10b594f09e26a7e902ecbd0600691";
+
+const XCHACHA_AEAD_CT: &str = "\
+    bd6d179d3e83d43b9576579493c0e939572a1700252bfaccbed2902c21396cbb\
+    731c7f1b0b4aa6440bf3a82f4eda7e39ae64c6708c54c216cb96b72e1213b452\
+    2f8c9ba40db5d945b11b69b982c1bb9e3f3fac2bc369488f76b2383565d3fff9\
+    21f9664c97637da9768812f615c68b13b52e\
+    c0875924c1c7987947deafd8780acf49";
+
+const POLY1305_KEY: &str = "\
+    85d6be7857556d337f4452fe42d506a80103808afb0db2fd4abff6af4149f51b";
+const POLY1305_MSG: &[u8] = b"Cryptographic Forum Research Group";
+const POLY1305_TAG: &str = "a8061dc1305136c6c22b8baf0c0127a9";
+
+fn import_chacha20_key(
+    session: CK_SESSION_HANDLE,
+    first: u8,
+) -> Result<CK_OBJECT_HANDLE> {
+    let value: Vec<u8> = (first..first + 32).collect();
+    import_object(
+        session,
+        CKO_SECRET_KEY,
+        &[(CKA_KEY_TYPE, CKK_CHACHA20)],
+        &[(CKA_VALUE, value.as_slice())],
+        &[(CKA_ENCRYPT, true), (CKA_DECRYPT, true)],
+    )
+}
+
+fn chacha20_roundtrip(
+    session: CK_SESSION_HANDLE,
+    key: CK_OBJECT_HANDLE,
+    counter: &[u8],
+    nonce: &[u8],
+    expected: &str,
+) {
+    let mut params = CK_CHACHA20_PARAMS {
+        pBlockCounter: byte_ptr!(counter.as_ptr()),
+        blockCounterBits: (counter.len() * 8) as CK_ULONG,
+        pNonce: byte_ptr!(nonce.as_ptr()),
+        ulNonceBits: (nonce.len() * 8) as CK_ULONG,
+    };
+    let mechanism = CK_MECHANISM {
+        mechanism: CKM_CHACHA20,
+        pParameter: void_ptr!(&mut params),
+        ulParameterLen: sizeof!(CK_CHACHA20_PARAMS),
+    };
+
+    let enc = ret_or_panic!(encrypt(session, key, SUNSCREEN, &mechanism));
+    assert_eq!(hex::encode(&enc), expected);
+
+    let dec = ret_or_panic!(decrypt(session, key, &enc, &mechanism));
+    assert_eq!(dec.as_slice(), SUNSCREEN);
+}
+
+#[test]
+#[parallel]
+fn test_chacha20() {
+    let mut testtokn = TestToken::initialized("test_chacha20.sql", None);
+    let session = testtokn.get_session(true);
+
+    /* login */
+    testtokn.login();
+
+    /* the key size is fixed, so CKA_VALUE_LEN is not needed */
+    let handle = ret_or_panic!(generate_key(
+        session,
+        CKM_CHACHA20_KEY_GEN,
+        std::ptr::null_mut(),
+        0,
+        &[],
+        &[],
+        &[(CKA_ENCRYPT, true), (CKA_DECRYPT, true)],
+    ));
+    if let Some(e) = check_attributes(
+        session,
+        handle,
+        &[(CKA_KEY_TYPE, CKK_CHACHA20), (CKA_VALUE_LEN, 32)],
+        &[],
+        &[(CKA_SENSITIVE, true)],
+    ) {
+        panic!("{}", e);
+    }
+
+    /* any other size is rejected */
+    let err = generate_key(
+        session,
+        CKM_CHACHA20_KEY_GEN,
+        std::ptr::null_mut(),
+        0,
+        &[(CKA_VALUE_LEN, 16)],
+        &[],
+        &[(CKA_ENCRYPT, true)],
+    )
+    .unwrap_err();
+    assert_eq!(err.rv(), CKR_TEMPLATE_INCONSISTENT);
+
+    let handle = ret_or_panic!(import_chacha20_key(session, 0));
+
+    /* RFC 8439, 2.4.2: 96 bit nonce and 32 bit counter */
+    chacha20_roundtrip(
+        session,
+        handle,
+        &hex::decode("01000000").unwrap(),
+        &hex::decode("000000000000004a00000000").unwrap(),
+        RFC8439_STREAM_CT,
+    );
+
+    /* Original ChaCha20: 64 bit nonce and 64 bit counter */
+    chacha20_roundtrip(
+        session,
+        handle,
+        &[0u8; 8],
+        &hex::decode("0001020304050607").unwrap(),
+        ORIGINAL_STREAM_CT,
+    );
+
+    /* XChaCha20: 192 bit nonce, must produce the same key stream as
+     * the AEAD construction, minus the tag */
+    let handle = ret_or_panic!(import_chacha20_key(session, 0x80));
+    chacha20_roundtrip(
+        session,
+        handle,
+        &hex::decode("0100000000000000").unwrap(),
+        &hex::decode(XCHACHA_NONCE).unwrap(),
+        &XCHACHA_AEAD_CT[..SUNSCREEN.len() * 2],
+    );
+
+    /* unsupported nonce/counter combination */
+    let counter = [0u8; 4];
+    let nonce = [0u8; 8];
+    let mut params = CK_CHACHA20_PARAMS {
+        pBlockCounter: byte_ptr!(counter.as_ptr()),
+        blockCounterBits: 32,
+        pNonce: byte_ptr!(nonce.as_ptr()),
+        ulNonceBits: 64,
+    };
+    let mechanism = CK_MECHANISM {
+        mechanism: CKM_CHACHA20,
+        pParameter: void_ptr!(&mut params),
+        ulParameterLen: sizeof!(CK_CHACHA20_PARAMS),
+    };
+    let ret = fn_encrypt_init(session, &mechanism as *const _ as _, handle);
+    assert_eq!(ret, CKR_MECHANISM_PARAM_INVALID);
+
+    /* a counter that would wrap is refused */
+    let counter = [0xffu8; 4];
+    let nonce = [0u8; 12];
+    let mut params = CK_CHACHA20_PARAMS {
+        pBlockCounter: byte_ptr!(counter.as_ptr()),
+        blockCounterBits: 32,
+        pNonce: byte_ptr!(nonce.as_ptr()),
+        ulNonceBits: 96,
+    };
+    let mechanism = CK_MECHANISM {
+        mechanism: CKM_CHACHA20,
+        pParameter: void_ptr!(&mut params),
+        ulParameterLen: sizeof!(CK_CHACHA20_PARAMS),
+    };
+    let err = encrypt(session, handle, &[0u8; 65], &mechanism).unwrap_err();
+    assert_eq!(err.rv(), CKR_DATA_LEN_RANGE);
+
+    testtokn.finalize();
+}
+
+#[test]
+#[parallel]
+fn test_chacha20_poly1305() {
+    let mut testtokn =
+        TestToken::initialized("test_chacha20_poly1305.sql", None);
+    let session = testtokn.get_session(true);
+
+    /* login */
+    testtokn.login();
+
+    let handle = ret_or_panic!(import_chacha20_key(session, 0x80));
+    let aad = hex::decode(AEAD_AAD).unwrap();
+
+    for (nonce, expected) in [
+        (RFC8439_AEAD_NONCE, RFC8439_AEAD_CT),
+        (XCHACHA_NONCE, XCHACHA_AEAD_CT),
+    ] {
+        let nonce = hex::decode(nonce).unwrap();
+        let mut params = CK_SALSA20_CHACHA20_POLY1305_PARAMS {
+            pNonce: byte_ptr!(nonce.as_ptr()),
+            ulNonceLen: nonce.len() as CK_ULONG,
+            pAAD: byte_ptr!(aad.as_ptr()),
+            ulAADLen: aad.len() as CK_ULONG,
+        };
+        let mechanism = CK_MECHANISM {
+            mechanism: CKM_CHACHA20_POLY1305,
+            pParameter: void_ptr!(&mut params),
+            ulParameterLen: sizeof!(CK_SALSA20_CHACHA20_POLY1305_PARAMS),
+        };
+
+        let enc =
+            ret_or_panic!(encrypt(session, handle, SUNSCREEN, &mechanism));
+        assert_eq!(hex::encode(&enc), expected);
+
+        let dec = ret_or_panic!(decrypt(session, handle, &enc, &mechanism));
+        assert_eq!(dec.as_slice(), SUNSCREEN);
+
+        /* multi-part decryption must hold back the tag */
+        let ret = fn_decrypt_init(session, &mechanism as *const _ as _, handle);
+        assert_eq!(ret, CKR_OK);
+        let mut dec = vec![0u8; enc.len()];
+        let mut dec_len = dec.len() as CK_ULONG;
+        let ret = fn_decrypt_update(
+            session,
+            byte_ptr!(enc.as_ptr()),
+            enc.len() as CK_ULONG,
+            dec.as_mut_ptr(),
+            &mut dec_len,
+        );
+        assert_eq!(ret, CKR_OK);
+        assert_eq!(dec_len as usize, SUNSCREEN.len());
+        let mut final_len = (dec.len() - dec_len as usize) as CK_ULONG;
+        let ret = fn_decrypt_final(
+            session,
+            unsafe { dec.as_mut_ptr().add(dec_len as usize) },
+            &mut final_len,
+        );
+        assert_eq!(ret, CKR_OK);
+        assert_eq!(final_len, 0);
+        assert_eq!(&dec[..SUNSCREEN.len()], SUNSCREEN);
+
+        /* tampering with the tag must be detected */
+        let mut bad = enc.clone();
+        let last = bad.len() - 1;
+        bad[last] ^= 0x01;
+        let err = decrypt(session, handle, &bad, &mechanism).unwrap_err();
+        assert_eq!(err.rv(), CKR_ENCRYPTED_DATA_INVALID);
+    }
+
+    /* only 96 and 192 bit nonces are allowed */
+    let nonce = [0u8; 8];
+    let mut params = CK_SALSA20_CHACHA20_POLY1305_PARAMS {
+        pNonce: byte_ptr!(nonce.as_ptr()),
+        ulNonceLen: nonce.len() as CK_ULONG,
+        pAAD: std::ptr::null_mut(),
+        ulAADLen: 0,
+    };
+    let mechanism = CK_MECHANISM {
+        mechanism: CKM_CHACHA20_POLY1305,
+        pParameter: void_ptr!(&mut params),
+        ulParameterLen: sizeof!(CK_SALSA20_CHACHA20_POLY1305_PARAMS),
+    };
+    let ret = fn_encrypt_init(session, &mechanism as *const _ as _, handle);
+    assert_eq!(ret, CKR_MECHANISM_PARAM_INVALID);
+
+    testtokn.finalize();
+}
+
+#[test]
+#[parallel]
+fn test_chacha20_poly1305_message() {
+    let mut testtokn =
+        TestToken::initialized("test_chacha20_poly1305_message.sql", None);
+    let session = testtokn.get_session(true);
+
+    /* login */
+    testtokn.login();
+
+    let handle = ret_or_panic!(import_chacha20_key(session, 0x80));
+    let aad = hex::decode(AEAD_AAD).unwrap();
+    let expected = hex::decode(RFC8439_AEAD_CT).unwrap();
+    let (expected_ct, expected_tag) = expected.split_at(SUNSCREEN.len());
+
+    let mut mechanism = CK_MECHANISM {
+        mechanism: CKM_CHACHA20_POLY1305,
+        pParameter: std::ptr::null_mut(),
+        ulParameterLen: 0,
+    };
+
+    let ret = fn_message_encrypt_init(session, &mut mechanism, handle);
+    assert_eq!(ret, CKR_OK);
+
+    let nonce = hex::decode(RFC8439_AEAD_NONCE).unwrap();
+    let mut tag = [0u8; 16];
+    let mut param = CK_SALSA20_CHACHA20_POLY1305_MSG_PARAMS {
+        pNonce: byte_ptr!(nonce.as_ptr()),
+        ulNonceLen: nonce.len() as CK_ULONG,
+        pTag: tag.as_mut_ptr(),
+    };
+
+    /* single shot */
+    let mut enc = vec![0u8; SUNSCREEN.len()];
+    let mut enc_len = enc.len() as CK_ULONG;
+    let ret = fn_encrypt_message(
+        session,
+        void_ptr!(&mut param),
+        sizeof!(CK_SALSA20_CHACHA20_POLY1305_MSG_PARAMS),
+        byte_ptr!(aad.as_ptr()),
+        aad.len() as CK_ULONG,
+        byte_ptr!(SUNSCREEN.as_ptr()),
+        SUNSCREEN.len() as CK_ULONG,
+        enc.as_mut_ptr(),
+        &mut enc_len,
+    );
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(enc_len as usize, SUNSCREEN.len());
+    assert_eq!(enc.as_slice(), expected_ct);
+    assert_eq!(tag.as_slice(), expected_tag);
+
+    /* multi-part, on a fresh message with a 192 bit nonce */
+    let xnonce = hex::decode(XCHACHA_NONCE).unwrap();
+    let xexpected = hex::decode(XCHACHA_AEAD_CT).unwrap();
+    let (xexpected_ct, xexpected_tag) = xexpected.split_at(SUNSCREEN.len());
+    let mut xtag = [0u8; 16];
+    let mut xparam = CK_SALSA20_CHACHA20_POLY1305_MSG_PARAMS {
+        pNonce: byte_ptr!(xnonce.as_ptr()),
+        ulNonceLen: xnonce.len() as CK_ULONG,
+        pTag: xtag.as_mut_ptr(),
+    };
+    let ret = fn_encrypt_message_begin(
+        session,
+        void_ptr!(&mut xparam),
+        sizeof!(CK_SALSA20_CHACHA20_POLY1305_MSG_PARAMS),
+        byte_ptr!(aad.as_ptr()),
+        aad.len() as CK_ULONG,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let mut xenc = vec![0u8; SUNSCREEN.len()];
+    let split = 50;
+    let mut part_len = split as CK_ULONG;
+    let ret = fn_encrypt_message_next(
+        session,
+        void_ptr!(&mut xparam),
+        sizeof!(CK_SALSA20_CHACHA20_POLY1305_MSG_PARAMS),
+        byte_ptr!(SUNSCREEN.as_ptr()),
+        split as CK_ULONG,
+        xenc.as_mut_ptr(),
+        &mut part_len,
+        0,
+    );
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(part_len as usize, split);
+    let mut part_len = (SUNSCREEN.len() - split) as CK_ULONG;
+    let ret = fn_encrypt_message_next(
+        session,
+        void_ptr!(&mut xparam),
+        sizeof!(CK_SALSA20_CHACHA20_POLY1305_MSG_PARAMS),
+        byte_ptr!(SUNSCREEN[split..].as_ptr()),
+        (SUNSCREEN.len() - split) as CK_ULONG,
+        unsafe { xenc.as_mut_ptr().add(split) },
+        &mut part_len,
+        CKF_END_OF_MESSAGE,
+    );
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(part_len as usize, SUNSCREEN.len() - split);
+    assert_eq!(xenc.as_slice(), xexpected_ct);
+    assert_eq!(xtag.as_slice(), xexpected_tag);
+
+    let ret = fn_message_encrypt_final(session);
+    assert_eq!(ret, CKR_OK);
+
+    /* decryption */
+    let ret = fn_message_decrypt_init(session, &mut mechanism, handle);
+    assert_eq!(ret, CKR_OK);
+
+    let mut dec = vec![0u8; SUNSCREEN.len()];
+    let mut dec_len = dec.len() as CK_ULONG;
+    let ret = fn_decrypt_message(
+        session,
+        void_ptr!(&mut param),
+        sizeof!(CK_SALSA20_CHACHA20_POLY1305_MSG_PARAMS),
+        byte_ptr!(aad.as_ptr()),
+        aad.len() as CK_ULONG,
+        byte_ptr!(enc.as_ptr()),
+        enc.len() as CK_ULONG,
+        dec.as_mut_ptr(),
+        &mut dec_len,
+    );
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(dec_len as usize, SUNSCREEN.len());
+    assert_eq!(dec.as_slice(), SUNSCREEN);
+
+    /* a bad tag fails the message, returns no data and terminates
+     * the operation */
+    tag[0] ^= 0x01;
+    param.pTag = tag.as_mut_ptr();
+    let mut dec = vec![0u8; SUNSCREEN.len()];
+    let mut dec_len = dec.len() as CK_ULONG;
+    let ret = fn_decrypt_message(
+        session,
+        void_ptr!(&mut param),
+        sizeof!(CK_SALSA20_CHACHA20_POLY1305_MSG_PARAMS),
+        byte_ptr!(aad.as_ptr()),
+        aad.len() as CK_ULONG,
+        byte_ptr!(enc.as_ptr()),
+        enc.len() as CK_ULONG,
+        dec.as_mut_ptr(),
+        &mut dec_len,
+    );
+    assert_eq!(ret, CKR_ENCRYPTED_DATA_INVALID);
+    assert_eq!(dec, vec![0u8; SUNSCREEN.len()]);
+
+    let ret = fn_message_decrypt_final(session);
+    assert_eq!(ret, CKR_OPERATION_NOT_INITIALIZED);
+
+    testtokn.finalize();
+}
+
+#[test]
+#[parallel]
+fn test_poly1305() {
+    let mut testtokn = TestToken::initialized("test_poly1305.sql", None);
+    let session = testtokn.get_session(true);
+
+    /* login */
+    testtokn.login();
+
+    /* RFC 8439, 2.5.2 */
+    let handle = ret_or_panic!(import_object(
+        session,
+        CKO_SECRET_KEY,
+        &[(CKA_KEY_TYPE, CKK_POLY1305)],
+        &[(CKA_VALUE, &hex::decode(POLY1305_KEY).unwrap())],
+        &[(CKA_SIGN, true), (CKA_VERIFY, true)],
+    ));
+
+    let mechanism = CK_MECHANISM {
+        mechanism: CKM_POLY1305,
+        pParameter: std::ptr::null_mut(),
+        ulParameterLen: 0,
+    };
+
+    let mut tag =
+        ret_or_panic!(sig_gen(session, handle, POLY1305_MSG, &mechanism));
+    assert_eq!(hex::encode(&tag), POLY1305_TAG);
+
+    let ret = sig_verify(session, handle, POLY1305_MSG, &tag, &mechanism);
+    assert_eq!(ret, CKR_OK);
+
+    tag[0] ^= 0x01;
+    let ret = sig_verify(session, handle, POLY1305_MSG, &tag, &mechanism);
+    assert_eq!(ret, CKR_SIGNATURE_INVALID);
+
+    /* Poly1305 keys can be generated too */
+    let handle = ret_or_panic!(generate_key(
+        session,
+        CKM_POLY1305_KEY_GEN,
+        std::ptr::null_mut(),
+        0,
+        &[],
+        &[],
+        &[(CKA_SIGN, true), (CKA_VERIFY, true)],
+    ));
+    let tag = ret_or_panic!(sig_gen(session, handle, POLY1305_MSG, &mechanism));
+    assert_eq!(tag.len(), 16);
+    let ret = sig_verify(session, handle, POLY1305_MSG, &tag, &mechanism);
+    assert_eq!(ret, CKR_OK);
+
+    testtokn.finalize();
+}
@@ -0,0 +1,286 @@
+// Copyright 2024 Simo Sorce
+// See LICENSE.txt file for terms
+
+use crate::tests::*;
+
+use serial_test::parallel;
+
+fn generate_params(
+    session: CK_SESSION_HANDLE,
+    mech: CK_MECHANISM_TYPE,
+    ulongs: &[(CK_ATTRIBUTE_TYPE, CK_ULONG)],
+) -> Result<CK_OBJECT_HANDLE> {
+    let template = make_attr_template(ulongs, &[], &[]);
+    let mut mechanism = CK_MECHANISM {
+        mechanism: mech,
+        pParameter: std::ptr::null_mut(),
+        ulParameterLen: 0,
+    };
+    let mut handle = CK_INVALID_HANDLE;
+    let ret = fn_generate_key(
+        session,
+        &mut mechanism,
+        template.as_ptr() as CK_ATTRIBUTE_PTR,
+        template.len() as CK_ULONG,
+        &mut handle,
+    );
+    if ret != CKR_OK {
+        Err(ret)?;
+    }
+    Ok(handle)
+}
+
+#[test]
+#[parallel]
+fn test_dh_domain_params() {
+    let mut testtokn =
+        TestToken::initialized("test_dh_domain_params.sql", None);
+    let session = testtokn.get_session(true);
+
+    /* login */
+    testtokn.login();
+
+    /* PKCS#3 parameters */
+    let params = ret_or_panic!(generate_params(
+        session,
+        CKM_DH_PKCS_PARAMETER_GEN,
+        &[(CKA_PRIME_BITS, 1024)],
+    ));
+    if let Some(err) = check_attributes(
+        session,
+        params,
+        &[
+            (CKA_CLASS, CKO_DOMAIN_PARAMETERS),
+            (CKA_KEY_TYPE, CKK_DH),
+            (CKA_PRIME_BITS, 1024),
+        ],
+        &[],
+        &[(CKA_LOCAL, true)],
+    ) {
+        panic!("{}", err);
+    }
+    let prime = get_attr_bytes(session, params, CKA_PRIME);
+    let base = get_attr_bytes(session, params, CKA_BASE);
+    assert_eq!(prime.len(), 128);
+
+    /* key pairs can be generated from the stored parameters */
+    let (_, privkey) = ret_or_panic!(generate_key_pair(
+        session,
+        CKM_DH_PKCS_KEY_PAIR_GEN,
+        &[],
+        &[(CKA_PRIME, &prime), (CKA_BASE, &base)],
+        &[],
+        &[],
+        &[],
+        &[(CKA_DERIVE, true)],
+    ));
+    if let Some(err) = check_attributes(
+        session,
+        privkey,
+        &[],
+        &[(CKA_PRIME, &prime), (CKA_BASE, &base)],
+        &[],
+    ) {
+        panic!("{}", err);
+    }
+
+    /* X9.42 parameters, the subprime size defaults from the prime size */
+    let params = ret_or_panic!(generate_params(
+        session,
+        CKM_X9_42_DH_PARAMETER_GEN,
+        &[(CKA_PRIME_BITS, 2048)],
+    ));
+    if let Some(err) = check_attributes(
+        session,
+        params,
+        &[
+            (CKA_KEY_TYPE, CKK_X9_42_DH),
+            (CKA_PRIME_BITS, 2048),
+            (CKA_SUBPRIME_BITS, 224),
+        ],
+        &[],
+        &[],
+    ) {
+        panic!("{}", err);
+    }
+    let prime = get_attr_bytes(session, params, CKA_PRIME);
+    let base = get_attr_bytes(session, params, CKA_BASE);
+    let subprime = get_attr_bytes(session, params, CKA_SUBPRIME);
+    ret_or_panic!(generate_key_pair(
+        session,
+        CKM_X9_42_DH_KEY_PAIR_GEN,
+        &[],
+        &[
+            (CKA_PRIME, &prime),
+            (CKA_BASE, &base),
+            (CKA_SUBPRIME, &subprime)
+        ],
+        &[],
+        &[],
+        &[],
+        &[(CKA_DERIVE, true)],
+    ));
+
+    /* Imported parameters get the sizes computed */
+    let handle = ret_or_panic!(import_object(
+        session,
+        CKO_DOMAIN_PARAMETERS,
+        &[(CKA_KEY_TYPE, CKK_X9_42_DH)],
+        &[
+            (CKA_PRIME, &prime),
+            (CKA_BASE, &base),
+            (CKA_SUBPRIME, &subprime)
+        ],
+        &[],
+    ));
+    if let Some(err) = check_attributes(
+        session,
+        handle,
+        &[(CKA_PRIME_BITS, 2048), (CKA_SUBPRIME_BITS, 224)],
+        &[],
+        &[(CKA_LOCAL, false)],
+    ) {
+        panic!("{}", err);
+    }
+
+    /* but can't be specified */
+    let err = import_object(
+        session,
+        CKO_DOMAIN_PARAMETERS,
+        &[(CKA_KEY_TYPE, CKK_DH), (CKA_PRIME_BITS, 2048)],
+        &[(CKA_PRIME, &prime), (CKA_BASE, &base)],
+        &[],
+    )
+    .unwrap_err();
+    assert_eq!(err.rv(), CKR_ATTRIBUTE_VALUE_INVALID);
+
+    /* Unsupported sizes */
+    let err = generate_params(
+        session,
+        CKM_DH_PKCS_PARAMETER_GEN,
+        &[(CKA_PRIME_BITS, 512)],
+    )
+    .unwrap_err();
+    assert_eq!(err.rv(), CKR_KEY_SIZE_RANGE);
+
+    /* Missing size */
+    let err =
+        generate_params(session, CKM_X9_42_DH_PARAMETER_GEN, &[]).unwrap_err();
+    assert_eq!(err.rv(), CKR_TEMPLATE_INCOMPLETE);
+
+    testtokn.finalize();
+}
+
+#[cfg(all(feature = "dsa", not(feature = "fips")))]
+#[test]
+#[parallel]
+fn test_dsa_domain_params() {
+    let mut testtokn =
+        TestToken::initialized("test_dsa_domain_params.sql", None);
+    let session = testtokn.get_session(true);
+
+    /* login */
+    testtokn.login();
+
+    let params = ret_or_panic!(generate_params(
+        session,
+        CKM_DSA_PARAMETER_GEN,
+        &[(CKA_PRIME_BITS, 2048), (CKA_SUBPRIME_BITS, 256)],
+    ));
+    if let Some(err) = check_attributes(
+        session,
+        params,
+        &[
+            (CKA_CLASS, CKO_DOMAIN_PARAMETERS),
+            (CKA_KEY_TYPE, CKK_DSA),
+            (CKA_PRIME_BITS, 2048),
+            (CKA_SUBPRIME_BITS, 256),
+        ],
+        &[],
+        &[(CKA_LOCAL, true)],
+    ) {
+        panic!("{}", err);
+    }
+    assert_eq!(get_attr_bytes(session, params, CKA_SUBPRIME).len(), 32);
+
+    /* The key type must match the mechanism */
+    let err = generate_params(
+        session,
+        CKM_DSA_PARAMETER_GEN,
+        &[(CKA_PRIME_BITS, 2048), (CKA_KEY_TYPE, CKK_DH)],
+    )
+    .unwrap_err();
+    assert_eq!(err.rv(), CKR_TEMPLATE_INCONSISTENT);
+
+    testtokn.finalize();
+}
+
+#[cfg(feature = "ecdsa")]
+#[test]
+#[parallel]
+fn test_ec_domain_params() {
+    let mut testtokn =
+        TestToken::initialized("test_ec_domain_params.sql", None);
+    let session = testtokn.get_session(true);
+
+    /* login */
+    testtokn.login();
+
+    /* secp256r1 */
+    let ec_params = hex::decode("06082a8648ce3d030107").unwrap();
+    let handle = ret_or_panic!(import_object(
+        session,
+        CKO_DOMAIN_PARAMETERS,
+        &[(CKA_KEY_TYPE, CKK_EC)],
+        &[(CKA_EC_PARAMS, &ec_params)],
+        &[(CKA_TOKEN, true)],
+    ));
+
+    let stored = get_attr_bytes(session, handle, CKA_EC_PARAMS);
+    assert_eq!(stored, ec_params);
+    ret_or_panic!(generate_key_pair(
+        session,
+        CKM_EC_KEY_PAIR_GEN,
+        &[],
+        &[(CKA_EC_PARAMS, &stored)],
+        &[(CKA_VERIFY, true)],
+        &[],
+        &[],
+        &[(CKA_SIGN, true)],
+    ));
+
+    /* Parameters are not modifiable */
+    let mut template = make_attr_template(&[], &[(CKA_EC_PARAMS, &[])], &[]);
+    let ret = fn_set_attribute_value(
+        session,
+        handle,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+    );
+    assert_eq!(ret, CKR_ATTRIBUTE_READ_ONLY);
+
+    /* Unsupported curve (secp224r1) */
+    let ec_params = hex::decode("06052b81040021").unwrap();
+    let err = import_object(
+        session,
+        CKO_DOMAIN_PARAMETERS,
+        &[(CKA_KEY_TYPE, CKK_EC)],
+        &[(CKA_EC_PARAMS, &ec_params)],
+        &[],
+    )
+    .unwrap_err();
+    assert_eq!(err.rv(), CKR_ATTRIBUTE_VALUE_INVALID);
+
+    /* Malformed parameters */
+    let err = import_object(
+        session,
+        CKO_DOMAIN_PARAMETERS,
+        &[(CKA_KEY_TYPE, CKK_EC)],
+        &[(CKA_EC_PARAMS, &[0x06, 0x05, 0x2b])],
+        &[],
+    )
+    .unwrap_err();
+    assert_eq!(err.rv(), CKR_ATTRIBUTE_VALUE_INVALID);
+
+    testtokn.finalize();
+}
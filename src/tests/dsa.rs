@@ -0,0 +1,204 @@
+// Copyright 2024 Simo Sorce
+// See LICENSE.txt file for terms
+
+use crate::tests::*;
+
+use serial_test::parallel;
+
+/* 2048-bit domain with a 256-bit subprime, the public key and the
+ * signature of "legacy message" with SHA-256 were generated with
+ * the openssl command line tool */
+const DSA_P: &str = "\
+    de0f6da8830124023dee5ea824028af0c238b2ee80621686ee71a3fa5a5d\
+    3a618c33c7b7f9ea7d6dc863575147532039e2a9c90356188faad051d122\
+    34bee62791adf13b49f562b18cbacc0090a848512781fae685615b0a4589\
+    75b99847f0c72facfbd5c85936eed092822cf52f43e913b8e9367fda6ca1\
+    902e8de6c5fe1c6ed300713590fa1261699ab3b4be4c6275194b3966231e\
+    ba37142a77d6426d7ee70803b031734a462a9fad566532b54e29889747be\
+    ca7586d892e96641b4a76845235880fa490307f3de317707ef631f87535f\
+    2155a01fdaf38d9de821768e187b497264e4bd8c8c07854472451888ccb1\
+    5c34cbc899cff4c70566c6187fc0f48b";
+
+const DSA_Q: &str = "\
+    f5d72fe6596c03c9c4c07be96cd016fd3459d281e0606f0579f2d333e9b8\
+    e797";
+
+const DSA_G: &str = "\
+    529f3627e4bb7eda964c750bcb934e9eec5ceb26c97e7ef8d1aa0b893482\
+    b773e24dce33c0a133960a04dcdd47c8f444bdb73431386f8951cae2de57\
+    e40c2c90a79536161eab91a6967d0118b5a20d23692dced277814d727690\
+    8139cd77f4d462d1c8aac06d2154b7d7b1ca493c94eb1b883933ebd4a0d6\
+    8fe1adb6dffcd4a210375c6d14627114a578746689a8d30734c1df9ee738\
+    4a03b641885caf079c6ca1e505e3057a1c0ae23205ad9fb25a794455b6fa\
+    82f19357cfb9636f8b8ff0013952c11c75b3e53b4d22a1783b3ec8335dfc\
+    7607cdc3429c01f0ab877ad57d4bd1ece168b8726b64a5d1296d2a0028b2\
+    fd88f5e1335983bc616f114882402c42";
+
+const DSA_Y: &str = "\
+    c171ca845ede206ebae0634ccbb9d820d6f530d728c00fd04c378474602d\
+    61d4c1c193ca7d9e4af3453a6b8b67f580daf06fefa1b535d4e1bc6eb176\
+    1ddf81aff05b9ef500b9d79d53d31b04f5e78e69b68bfce803998c78e2bb\
+    ba1a3e756a8e16b941eb1f51053e6430e37d8afc804ff472fecc8edd19d8\
+    b04da7d5d43b650305b168451a9cc55200fe93ae758158e07735e10c5f47\
+    b7b72399feef73349f504407f9027316ecada4729053eebb34c4ff0d5b49\
+    180bb73c9a9d09777474757b166abd147267f089ffe804bc2c9132d778f7\
+    bfc3f9857089f3334e5b51b19f162bb1ca30dd613bcdcb34d86be1d708ee\
+    5970babfbccf61c56a04a2d2231eeeb8";
+
+const DSA_SHA256_SIG: &str = "\
+    0d49fff8aa5efffef3217fb1cc23f12a44a47990c9aebd8dd2612d58b333\
+    68a946017ee0a6d33e1e64afa3d3b77e5a32401c0340afac3c10b42bfab1\
+    898cb38e";
+
+const DSA_MESSAGE: &[u8] = b"legacy message";
+const DSA_MESSAGE_SHA256: &str = "\
+    96441f52a2f2f9d37c183dc00a06a517eb6d7e7824c49d29e1e9f8cae0a9f8d1";
+
+fn import_public_key(
+    session: CK_SESSION_HANDLE,
+    value: &[u8],
+) -> Result<CK_OBJECT_HANDLE> {
+    import_object(
+        session,
+        CKO_PUBLIC_KEY,
+        &[(CKA_KEY_TYPE, CKK_DSA)],
+        &[
+            (CKA_PRIME, &hex::decode(DSA_P).unwrap()),
+            (CKA_SUBPRIME, &hex::decode(DSA_Q).unwrap()),
+            (CKA_BASE, &hex::decode(DSA_G).unwrap()),
+            (CKA_VALUE, value),
+        ],
+        &[(CKA_VERIFY, true)],
+    )
+}
+
+#[test]
+#[parallel]
+fn test_dsa_verify() {
+    let mut testtokn = TestToken::initialized("test_dsa_verify.sql", None);
+    let session = testtokn.get_session(false);
+
+    /* login */
+    testtokn.login();
+
+    let handle =
+        ret_or_panic!(import_public_key(session, &hex::decode(DSA_Y).unwrap()));
+
+    let mechanism = CK_MECHANISM {
+        mechanism: CKM_DSA_SHA256,
+        pParameter: std::ptr::null_mut(),
+        ulParameterLen: 0,
+    };
+    let mut signature = hex::decode(DSA_SHA256_SIG).unwrap();
+    let ret = sig_verify(session, handle, DSA_MESSAGE, &signature, &mechanism);
+    assert_eq!(ret, CKR_OK);
+
+    /* the raw mechanism takes the digest as input */
+    let mechanism = CK_MECHANISM {
+        mechanism: CKM_DSA,
+        pParameter: std::ptr::null_mut(),
+        ulParameterLen: 0,
+    };
+    let digest = hex::decode(DSA_MESSAGE_SHA256).unwrap();
+    let ret = sig_verify(session, handle, &digest, &signature, &mechanism);
+    assert_eq!(ret, CKR_OK);
+
+    signature[10] ^= 0x01;
+    let ret = sig_verify(session, handle, &digest, &signature, &mechanism);
+    assert_eq!(ret, CKR_SIGNATURE_INVALID);
+
+    /* The public value is mandatory */
+    let err = import_public_key(session, &[]).unwrap_err();
+    assert_eq!(err.rv(), CKR_ATTRIBUTE_VALUE_INVALID);
+
+    testtokn.finalize();
+}
+
+#[cfg(not(feature = "fips"))]
+#[test]
+#[parallel]
+fn test_dsa_operations() {
+    let mut testtokn = TestToken::initialized("test_dsa_operations.sql", None);
+    let session = testtokn.get_session(true);
+
+    /* login */
+    testtokn.login();
+
+    let prime = hex::decode(DSA_P).unwrap();
+    let subprime = hex::decode(DSA_Q).unwrap();
+    let base = hex::decode(DSA_G).unwrap();
+    let (pubkey, privkey) = ret_or_panic!(generate_key_pair(
+        session,
+        CKM_DSA_KEY_PAIR_GEN,
+        &[],
+        &[
+            (CKA_PRIME, &prime),
+            (CKA_SUBPRIME, &subprime),
+            (CKA_BASE, &base),
+        ],
+        &[(CKA_VERIFY, true)],
+        &[],
+        &[],
+        &[(CKA_SIGN, true)],
+    ));
+    if let Some(err) = check_attributes(
+        session,
+        privkey,
+        &[(CKA_KEY_TYPE, CKK_DSA)],
+        &[(CKA_PRIME, &prime), (CKA_SUBPRIME, &subprime)],
+        &[(CKA_PRIVATE, true), (CKA_LOCAL, true)],
+    ) {
+        panic!("{}", err);
+    }
+
+    let data = b"plaintext that will be signed";
+    for mech in [
+        CKM_DSA_SHA1,
+        CKM_DSA_SHA224,
+        CKM_DSA_SHA256,
+        CKM_DSA_SHA384,
+        CKM_DSA_SHA512,
+        CKM_DSA_SHA3_224,
+        CKM_DSA_SHA3_256,
+        CKM_DSA_SHA3_384,
+        CKM_DSA_SHA3_512,
+    ] {
+        let mechanism = CK_MECHANISM {
+            mechanism: mech,
+            pParameter: std::ptr::null_mut(),
+            ulParameterLen: 0,
+        };
+        let signature =
+            ret_or_panic!(sig_gen(session, privkey, data, &mechanism));
+        /* r || s, each as long as the subprime */
+        assert_eq!(signature.len(), 2 * subprime.len());
+        let ret = sig_verify(session, pubkey, data, &signature, &mechanism);
+        assert_eq!(ret, CKR_OK);
+
+        let signature = ret_or_panic!(sig_gen_multipart(
+            session,
+            privkey,
+            &data.to_vec(),
+            &mechanism
+        ));
+        let ret = sig_verify(session, pubkey, data, &signature, &mechanism);
+        assert_eq!(ret, CKR_OK);
+    }
+
+    /* Raw signature over a digest */
+    let mechanism = CK_MECHANISM {
+        mechanism: CKM_DSA,
+        pParameter: std::ptr::null_mut(),
+        ulParameterLen: 0,
+    };
+    let digest = [0x5a; 32];
+    let signature =
+        ret_or_panic!(sig_gen(session, privkey, &digest, &mechanism));
+    assert_eq!(signature.len(), 2 * subprime.len());
+    let ret = sig_verify(session, pubkey, &digest, &signature, &mechanism);
+    assert_eq!(ret, CKR_OK);
+    let ret = sig_verify(session, pubkey, data, &signature, &mechanism);
+    assert_eq!(ret, CKR_SIGNATURE_INVALID);
+
+    testtokn.finalize();
+}
@@ -0,0 +1,328 @@
+// Copyright 2024 Simo Sorce
+// See LICENSE.txt file for terms
+
+use crate::tests::*;
+
+use serial_test::parallel;
+
+#[test]
+#[parallel]
+fn test_dual_functions() {
+    let mut testtokn = TestToken::initialized("test_dual_functions.sql", None);
+    let session = testtokn.get_session(true);
+
+    /* login */
+    testtokn.login();
+
+    let data = b"Some data to be processed by dual-function calls";
+    let split = 20;
+
+    let aes_key = ret_or_panic!(generate_key(
+        session,
+        CKM_AES_KEY_GEN,
+        std::ptr::null_mut(),
+        0,
+        &[(CKA_VALUE_LEN, 16)],
+        &[],
+        &[(CKA_TOKEN, false), (CKA_ENCRYPT, true), (CKA_DECRYPT, true)],
+    ));
+    let hmac_key = ret_or_panic!(generate_key(
+        session,
+        CKM_GENERIC_SECRET_KEY_GEN,
+        std::ptr::null_mut(),
+        0,
+        &[(CKA_KEY_TYPE, CKK_GENERIC_SECRET), (CKA_VALUE_LEN, 32)],
+        &[],
+        &[(CKA_TOKEN, false), (CKA_SIGN, true), (CKA_VERIFY, true)],
+    ));
+
+    let param = CK_AES_CTR_PARAMS {
+        ulCounterBits: 128,
+        cb: [
+            0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A,
+            0x0B, 0x0C, 0x0D, 0x0E, 0x0F,
+        ],
+    };
+    let mut enc_mech: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_AES_CTR,
+        pParameter: void_ptr!(&param),
+        ulParameterLen: sizeof!(CK_AES_CTR_PARAMS),
+    };
+    let mut dgst_mech: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_SHA256,
+        pParameter: std::ptr::null_mut(),
+        ulParameterLen: 0,
+    };
+    let mut mac_mech: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_SHA256_HMAC,
+        pParameter: std::ptr::null_mut(),
+        ulParameterLen: 0,
+    };
+
+    /* reference values */
+    let enc = ret_or_panic!(encrypt(session, aes_key, data, &enc_mech));
+    let mac = ret_or_panic!(sig_gen(session, hmac_key, data, &mac_mech));
+    let ret = fn_digest_init(session, &mut dgst_mech);
+    assert_eq!(ret, CKR_OK);
+    let mut digest = [0u8; 32];
+    let mut digest_len: CK_ULONG = digest.len() as CK_ULONG;
+    let ret = fn_digest(
+        session,
+        data.as_ptr() as *mut u8,
+        data.len() as CK_ULONG,
+        digest.as_mut_ptr(),
+        &mut digest_len,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    /* Digest + Encrypt */
+    let ret = fn_digest_init(session, &mut dgst_mech);
+    assert_eq!(ret, CKR_OK);
+    /* the dual-function call needs both operations */
+    let mut out = vec![0u8; data.len()];
+    let mut out_len: CK_ULONG = out.len() as CK_ULONG;
+    let ret = fn_digest_encrypt_update(
+        session,
+        data.as_ptr() as *mut u8,
+        data.len() as CK_ULONG,
+        out.as_mut_ptr(),
+        &mut out_len,
+    );
+    assert_eq!(ret, CKR_OPERATION_NOT_INITIALIZED);
+    let ret = fn_encrypt_init(session, &mut enc_mech, aes_key);
+    assert_eq!(ret, CKR_OK);
+
+    /* no more operations can be started */
+    let ret = fn_digest_init(session, &mut dgst_mech);
+    assert_eq!(ret, CKR_OPERATION_ACTIVE);
+    let ret = fn_sign_init(session, &mut mac_mech, hmac_key);
+    assert_eq!(ret, CKR_OPERATION_ACTIVE);
+    let template = make_attr_template(&[(CKA_CLASS, CKO_SECRET_KEY)], &[], &[]);
+    let ret = fn_find_objects_init(session, template.as_ptr() as *mut _, 1);
+    assert_eq!(ret, CKR_OPERATION_ACTIVE);
+
+    /* the state of dual operations can't be saved */
+    let mut state_len: CK_ULONG = 0;
+    let ret =
+        fn_get_operation_state(session, std::ptr::null_mut(), &mut state_len);
+    assert_eq!(ret, CKR_STATE_UNSAVEABLE);
+
+    /* length query */
+    out_len = 0;
+    let ret = fn_digest_encrypt_update(
+        session,
+        data.as_ptr() as *mut u8,
+        split,
+        std::ptr::null_mut(),
+        &mut out_len,
+    );
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(out_len, split);
+
+    /* a short buffer does not consume any data */
+    out_len = 1;
+    let ret = fn_digest_encrypt_update(
+        session,
+        data.as_ptr() as *mut u8,
+        split,
+        out.as_mut_ptr(),
+        &mut out_len,
+    );
+    assert_eq!(ret, CKR_BUFFER_TOO_SMALL);
+    assert_eq!(out_len, split);
+
+    let ret = fn_digest_encrypt_update(
+        session,
+        data.as_ptr() as *mut u8,
+        split,
+        out.as_mut_ptr(),
+        &mut out_len,
+    );
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(out_len, split);
+    let mut rest_len: CK_ULONG = data.len() as CK_ULONG - split;
+    let ret = fn_digest_encrypt_update(
+        session,
+        data[split as usize..].as_ptr() as *mut u8,
+        rest_len,
+        out[split as usize..].as_mut_ptr(),
+        &mut rest_len,
+    );
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(out, enc);
+
+    /* the operations are then terminated independently */
+    let mut final_len: CK_ULONG = out.len() as CK_ULONG;
+    let ret = fn_encrypt_final(session, out.as_mut_ptr(), &mut final_len);
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(final_len, 0);
+    let mut dgst = [0u8; 32];
+    let mut dgst_len: CK_ULONG = dgst.len() as CK_ULONG;
+    let ret = fn_digest_final(session, dgst.as_mut_ptr(), &mut dgst_len);
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(dgst, digest);
+
+    /* Decrypt + Digest */
+    let ret = fn_decrypt_init(session, &mut enc_mech, aes_key);
+    assert_eq!(ret, CKR_OK);
+    let ret = fn_digest_init(session, &mut dgst_mech);
+    assert_eq!(ret, CKR_OK);
+    let mut out = vec![0u8; enc.len()];
+    let mut out_len: CK_ULONG = out.len() as CK_ULONG;
+    let ret = fn_decrypt_digest_update(
+        session,
+        enc.as_ptr() as *mut u8,
+        enc.len() as CK_ULONG,
+        out.as_mut_ptr(),
+        &mut out_len,
+    );
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(out.as_slice(), data);
+    let mut dgst_len: CK_ULONG = dgst.len() as CK_ULONG;
+    let ret = fn_digest_final(session, dgst.as_mut_ptr(), &mut dgst_len);
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(dgst, digest);
+    let mut final_len: CK_ULONG = out.len() as CK_ULONG;
+    let ret = fn_decrypt_final(session, out.as_mut_ptr(), &mut final_len);
+    assert_eq!(ret, CKR_OK);
+
+    /* Sign + Encrypt */
+    let ret = fn_sign_init(session, &mut mac_mech, hmac_key);
+    assert_eq!(ret, CKR_OK);
+    /* not a valid pair */
+    let ret = fn_verify_init(session, &mut mac_mech, hmac_key);
+    assert_eq!(ret, CKR_OPERATION_ACTIVE);
+    let ret = fn_encrypt_init(session, &mut enc_mech, aes_key);
+    assert_eq!(ret, CKR_OK);
+    let mut out = vec![0u8; data.len()];
+    let mut out_len: CK_ULONG = out.len() as CK_ULONG;
+    let ret = fn_sign_encrypt_update(
+        session,
+        data.as_ptr() as *mut u8,
+        data.len() as CK_ULONG,
+        out.as_mut_ptr(),
+        &mut out_len,
+    );
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(out, enc);
+    let mut sig = [0u8; 32];
+    let mut sig_len: CK_ULONG = sig.len() as CK_ULONG;
+    let ret = fn_sign_final(session, sig.as_mut_ptr(), &mut sig_len);
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(sig.as_slice(), mac.as_slice());
+    let mut final_len: CK_ULONG = out.len() as CK_ULONG;
+    let ret = fn_encrypt_final(session, out.as_mut_ptr(), &mut final_len);
+    assert_eq!(ret, CKR_OK);
+
+    /* Decrypt + Verify */
+    let ret = fn_decrypt_init(session, &mut enc_mech, aes_key);
+    assert_eq!(ret, CKR_OK);
+    let ret = fn_verify_init(session, &mut mac_mech, hmac_key);
+    assert_eq!(ret, CKR_OK);
+    let mut out = vec![0u8; enc.len()];
+    let mut out_len: CK_ULONG = out.len() as CK_ULONG;
+    let ret = fn_decrypt_verify_update(
+        session,
+        enc.as_ptr() as *mut u8,
+        enc.len() as CK_ULONG,
+        out.as_mut_ptr(),
+        &mut out_len,
+    );
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(out.as_slice(), data);
+    let mut final_len: CK_ULONG = out.len() as CK_ULONG;
+    let ret = fn_decrypt_final(session, out.as_mut_ptr(), &mut final_len);
+    assert_eq!(ret, CKR_OK);
+    let ret = fn_verify_final(session, mac.as_ptr() as *mut u8, 32);
+    assert_eq!(ret, CKR_OK);
+
+    /* cancelling one operation leaves the other one active */
+    let ret = fn_digest_init(session, &mut dgst_mech);
+    assert_eq!(ret, CKR_OK);
+    let ret = fn_encrypt_init(session, &mut enc_mech, aes_key);
+    assert_eq!(ret, CKR_OK);
+    let ret = fn_encrypt_init(session, std::ptr::null_mut(), aes_key);
+    assert_eq!(ret, CKR_OK);
+    let ret = fn_digest_encrypt_update(
+        session,
+        data.as_ptr() as *mut u8,
+        data.len() as CK_ULONG,
+        out.as_mut_ptr(),
+        &mut out_len,
+    );
+    assert_eq!(ret, CKR_OPERATION_NOT_INITIALIZED);
+    let ret = fn_digest_update(
+        session,
+        data.as_ptr() as *mut u8,
+        data.len() as CK_ULONG,
+    );
+    assert_eq!(ret, CKR_OK);
+    let mut dgst_len: CK_ULONG = dgst.len() as CK_ULONG;
+    let ret = fn_digest_final(session, dgst.as_mut_ptr(), &mut dgst_len);
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(dgst, digest);
+
+    /* the remaining operation keeps its own key */
+    let ret = fn_digest_init(session, &mut dgst_mech);
+    assert_eq!(ret, CKR_OK);
+    let ret = fn_encrypt_init(session, &mut enc_mech, aes_key);
+    assert_eq!(ret, CKR_OK);
+    let ret = fn_digest_init(session, std::ptr::null_mut());
+    assert_eq!(ret, CKR_OK);
+    let mut state_len: CK_ULONG = 0;
+    let ret =
+        fn_get_operation_state(session, std::ptr::null_mut(), &mut state_len);
+    assert_eq!(ret, CKR_OK);
+    let mut state = vec![0u8; state_len as usize];
+    let ret =
+        fn_get_operation_state(session, state.as_mut_ptr(), &mut state_len);
+    assert_eq!(ret, CKR_OK);
+    let ret = fn_set_operation_state(
+        session,
+        state.as_mut_ptr(),
+        state_len,
+        hmac_key,
+        CK_INVALID_HANDLE,
+    );
+    assert_eq!(ret, CKR_KEY_CHANGED);
+    let ret = fn_set_operation_state(
+        session,
+        state.as_mut_ptr(),
+        state_len,
+        aes_key,
+        CK_INVALID_HANDLE,
+    );
+    assert_eq!(ret, CKR_OK);
+    let ret = fn_encrypt_init(session, std::ptr::null_mut(), aes_key);
+    assert_eq!(ret, CKR_OK);
+
+    /* a failure on one side terminates both operations */
+    let iv = [0u8; 16];
+    let mut cts_mech: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_AES_CTS,
+        pParameter: void_ptr!(iv.as_ptr()),
+        ulParameterLen: iv.len() as CK_ULONG,
+    };
+    let ret = fn_digest_init(session, &mut dgst_mech);
+    assert_eq!(ret, CKR_OK);
+    let ret = fn_encrypt_init(session, &mut cts_mech, aes_key);
+    assert_eq!(ret, CKR_OK);
+    /* CTS can not process less than a block of data */
+    let mut out_len: CK_ULONG = out.len() as CK_ULONG;
+    let ret = fn_digest_encrypt_update(
+        session,
+        data.as_ptr() as *mut u8,
+        5,
+        out.as_mut_ptr(),
+        &mut out_len,
+    );
+    assert_eq!(ret, CKR_DATA_LEN_RANGE);
+    let mut dgst_len: CK_ULONG = dgst.len() as CK_ULONG;
+    let ret = fn_digest_final(session, dgst.as_mut_ptr(), &mut dgst_len);
+    assert_eq!(ret, CKR_OPERATION_NOT_INITIALIZED);
+    let mut final_len: CK_ULONG = out.len() as CK_ULONG;
+    let ret = fn_encrypt_final(session, out.as_mut_ptr(), &mut final_len);
+    assert_eq!(ret, CKR_OPERATION_NOT_INITIALIZED);
+
+    testtokn.finalize();
+}
@@ -384,3 +384,491 @@ fn test_ec_montgomery_derive(t: TestUnit) {
 
     testtokn.finalize();
 }
+
+#[cfg(not(feature = "fips"))]
+const XEDDSA_MESSAGE: &[u8] = b"XEdDSA signature test";
+
+#[cfg(not(feature = "fips"))]
+#[derive(Debug)]
+struct XEddsaUnit<'a> {
+    public: &'a str,
+    message: &'a str,
+    signature: &'a str,
+}
+
+/* XEd25519 signatures are Ed25519 signatures made with the Edwards key
+ * that has a zero sign bit. The vectors are therefore the RFC 8032
+ * section 7.1 Ed25519 tests 1 to 3 (all have public keys with a zero
+ * sign bit), with the public keys converted to the Montgomery u
+ * coordinate as u = (1 + y) / (1 - y) */
+#[cfg(not(feature = "fips"))]
+#[test]
+#[parallel]
+fn test_xeddsa_x25519() {
+    let mut testtokn = TestToken::initialized("test_xeddsa_x25519.sql", None);
+    let session = testtokn.get_session(true);
+
+    /* login */
+    testtokn.login();
+
+    let units = [
+        XEddsaUnit {
+            public: "d85e07ec22b0ad881537c2f44d662d1a143cf830c57aca4305d85c7a90f6b62e",
+            message: "",
+            signature: concat!(
+                "e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e0652249",
+                "01555fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe2465514143",
+                "8e7a100b"
+            ),
+        },
+        XEddsaUnit {
+            public: "25c704c594b88afc00a76b69d1ed2b984d7e22550f3ed0802d04fbcd07d38d47",
+            message: "72",
+            signature: concat!(
+                "92a009a9f0d4cab8720e820b5f642540a2b27b5416503f8fb3762223ebdb",
+                "69da085ac1e43e15996e458f3613d0f11d8c387b2eaeb4302aeeb00d2916",
+                "12bb0c00"
+            ),
+        },
+        XEddsaUnit {
+            public: "cbb22fc9f790bd3eba9b84680c157ca4950a9894362601701f89c3c4d9fda23a",
+            message: "af82",
+            signature: concat!(
+                "6291d657deec24024827e69c3abe01a30ce548a284743a445e3680d7db5a",
+                "c3ac18ff9b538d16f290ae67f760984dc6594a7c15e9716ed28dc027bece",
+                "ea1ec40a"
+            ),
+        },
+    ];
+
+    let params = hex::decode("130a63757276653235353139").unwrap();
+    let mechanism: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_XEDDSA,
+        pParameter: std::ptr::null_mut(),
+        ulParameterLen: 0,
+    };
+    for unit in units {
+        let point = hex::decode(unit.public).unwrap();
+        let pubkey = ret_or_panic!(import_object(
+            session,
+            CKO_PUBLIC_KEY,
+            &[(CKA_KEY_TYPE, CKK_EC_MONTGOMERY)],
+            &[(CKA_EC_POINT, &point), (CKA_EC_PARAMS, &params)],
+            &[(CKA_VERIFY, true)]
+        ));
+        let message = hex::decode(unit.message).unwrap();
+        let mut signature = hex::decode(unit.signature).unwrap();
+        assert_eq!(
+            sig_verify(session, pubkey, &message, &signature, &mechanism),
+            CKR_OK
+        );
+        assert_eq!(
+            sig_verify(
+                session,
+                pubkey,
+                b"other message",
+                &signature,
+                &mechanism
+            ),
+            CKR_SIGNATURE_INVALID
+        );
+        signature[0] ^= 1;
+        assert_eq!(
+            sig_verify(session, pubkey, &message, &signature, &mechanism),
+            CKR_SIGNATURE_INVALID
+        );
+    }
+
+    /* RFC 7748 Alice keys */
+    let private =
+        "77076d0a7318a57d3c16c17251b26645df4c2f87ebc0992ab177fba51db92c2a";
+    let public =
+        "8520f0098930a754748b7ddcb43ef75a0dbf3a0d26381af4eba4a98eaa9b4e6a";
+    let random: Vec<u8> = (0u8..64).collect();
+    let kats = [
+        /* The XEd25519 test of the reference implementation, a private
+         * key with only byte 8 set to 189, signing 200 zero bytes with
+         * an all zero random value */
+        XEddsaKat {
+            private: "0000000000000000bd0000000000000000000000000000000000000000000000",
+            public: "5995f464e9d34d5ca56b9905b9a3cc37c456b2d8d313edbcf584b705b5c04955",
+            hash: CKM_SHA512,
+            message: &[0u8; 200],
+            random: &[0u8; 64],
+            signature: concat!(
+                "11c7f3e6c4df9e8a5150e1db3b30f92de3a3b3aa438656545fa7390f4bcc",
+                "7bb26c431d9e90643e4f0eaa0e9c557766fa69ada576d63dcaf2ac326c11",
+                "d0b97702"
+            ),
+        },
+        XEddsaKat {
+            private,
+            public,
+            hash: CKM_SHA512,
+            message: XEDDSA_MESSAGE,
+            random: &random,
+            signature: concat!(
+                "7ac970bc1a9f0e44f7499329d8f8af84c55129112a88f4675fdf6b41b08b",
+                "edd9113f21accdd5851162ef3daa45b6e38b1716ba2ec42b9119e563eb2a",
+                "5dc0700f"
+            ),
+        },
+        XEddsaKat {
+            private,
+            public,
+            hash: CKM_SHA3_512,
+            message: XEDDSA_MESSAGE,
+            random: &random,
+            signature: concat!(
+                "f0a3f4e3ec926cddfa28b73af5470569a3a3b00b568139293f86c716250a",
+                "d1c4828bd1e17145a82837e4942d4d86cb31462dcd597da9316e2869b7d9",
+                "40194403"
+            ),
+        },
+    ];
+    for unit in &kats {
+        test_xeddsa_kat(session, "130a63757276653235353139", unit);
+    }
+
+    test_xeddsa(session, "130a63757276653235353139", private, public, 64);
+
+    testtokn.finalize();
+}
+
+/* There are no published XEd448 vectors, these were generated with an
+ * independent implementation of the specification in Python, which also
+ * reproduces the XEd25519 vectors above */
+#[cfg(not(feature = "fips"))]
+#[test]
+#[parallel]
+fn test_xeddsa_x448() {
+    let mut testtokn = TestToken::initialized("test_xeddsa_x448.sql", None);
+    let session = testtokn.get_session(true);
+
+    /* login */
+    testtokn.login();
+
+    /* RFC 7748 Alice keys */
+    let private = concat!(
+        "9a8f4925d1519f5775cf46b04b5800d4ee9ee8bae8bc5565d498c28dd9c9ba",
+        "f574a9419744897391006382a6f127ab1d9ac2d8c0a598726b"
+    );
+    let public = concat!(
+        "9b08f7cc31b7e3e67d22d5aea121074a273bd2b83de09c63faa73d2c22c5d9",
+        "bbc836647241d953d40c5b12da88120d53177f80e532c41fa0"
+    );
+    let random: Vec<u8> = (0u8..64).collect();
+    let kats = [
+        XEddsaKat {
+            private,
+            public,
+            hash: CKM_SHA512,
+            message: XEDDSA_MESSAGE,
+            random: &random,
+            signature: concat!(
+                "293d6289704ad02fffe34f2ccf0eb3e95af5ee8c5713ed274ed5d23d1ea8",
+                "d20f8e33ef6121235aace73f97e2a3aa202688cdc9310a941a6e003ad93d",
+                "d4195c9ecced3c7b9a4fa05df56e94af8aa64cbb95fac3ba7238214c951b",
+                "ea11086af161e42253f8032721b97e0a45a4905d6f360200"
+            ),
+        },
+        XEddsaKat {
+            private,
+            public,
+            hash: CKM_SHA3_512,
+            message: XEDDSA_MESSAGE,
+            random: &random,
+            signature: concat!(
+                "bc1cd28f561810e02052fb7d2f9af888b6c35be3f1b3b47b61ebdba930b8",
+                "0b7656f0c43f5f95be61f89f04e591247c910c1751c1b5bd93fb00e5172d",
+                "b8be21806343e3a7edfe7d02dcc2b3c94ddb23c90d15802e98d1c410e0db",
+                "57f6e89c7d8ff8535cea9ed799dcff64c6b35ad982ed0700"
+            ),
+        },
+    ];
+    for unit in &kats {
+        test_xeddsa_kat(session, "13086375727665343438", unit);
+    }
+
+    test_xeddsa(session, "13086375727665343438", private, public, 114);
+
+    testtokn.finalize();
+}
+
+#[cfg(not(feature = "fips"))]
+#[derive(Debug)]
+struct XEddsaKat<'a> {
+    private: &'a str,
+    public: &'a str,
+    hash: CK_MECHANISM_TYPE,
+    message: &'a [u8],
+    random: &'a [u8],
+    signature: &'a str,
+}
+
+/* Signs with the fixed random value and checks the result, then
+ * verifies the signature through the token */
+#[cfg(not(feature = "fips"))]
+fn test_xeddsa_kat(
+    session: CK_SESSION_HANDLE,
+    ec_params: &str,
+    unit: &XEddsaKat,
+) {
+    let private = hex::decode(unit.private).unwrap();
+    let signature = hex::decode(unit.signature).unwrap();
+    let result = ret_or_panic!(crate::ossl::xeddsa::sign_with_z(
+        unit.hash,
+        &private,
+        unit.message,
+        unit.random
+    ));
+    assert_eq!(hex::encode(result), unit.signature);
+
+    let params = hex::decode(ec_params).unwrap();
+    let point = hex::decode(unit.public).unwrap();
+    let pubkey = ret_or_panic!(import_object(
+        session,
+        CKO_PUBLIC_KEY,
+        &[(CKA_KEY_TYPE, CKK_EC_MONTGOMERY)],
+        &[(CKA_EC_POINT, &point), (CKA_EC_PARAMS, &params)],
+        &[(CKA_VERIFY, true)]
+    ));
+    let mut xparams = CK_XEDDSA_PARAMS { hash: unit.hash };
+    let mechanism: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_XEDDSA,
+        pParameter: &mut xparams as *mut _ as CK_VOID_PTR,
+        ulParameterLen: sizeof!(CK_XEDDSA_PARAMS),
+    };
+    assert_eq!(
+        sig_verify(session, pubkey, unit.message, &signature, &mechanism),
+        CKR_OK
+    );
+    assert_eq!(
+        sig_verify(session, pubkey, b"other message", &signature, &mechanism),
+        CKR_SIGNATURE_INVALID
+    );
+}
+
+#[cfg(not(feature = "fips"))]
+fn test_xeddsa(
+    session: CK_SESSION_HANDLE,
+    ec_params: &str,
+    private: &str,
+    public: &str,
+    sig_len: usize,
+) {
+    let params = hex::decode(ec_params).unwrap();
+    let point = hex::decode(public).unwrap();
+    let pubkey = ret_or_panic!(import_object(
+        session,
+        CKO_PUBLIC_KEY,
+        &[(CKA_KEY_TYPE, CKK_EC_MONTGOMERY)],
+        &[(CKA_EC_POINT, &point), (CKA_EC_PARAMS, &params)],
+        &[(CKA_VERIFY, true)]
+    ));
+    let value = hex::decode(private).unwrap();
+    let privkey = ret_or_panic!(import_object(
+        session,
+        CKO_PRIVATE_KEY,
+        &[(CKA_KEY_TYPE, CKK_EC_MONTGOMERY)],
+        &[(CKA_VALUE, &value), (CKA_EC_PARAMS, &params)],
+        &[(CKA_SIGN, true)]
+    ));
+
+    let mechanism: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_XEDDSA,
+        pParameter: std::ptr::null_mut(),
+        ulParameterLen: 0,
+    };
+
+    /* Signatures are randomized, but always verify */
+    let signature =
+        ret_or_panic!(sig_gen(session, privkey, XEDDSA_MESSAGE, &mechanism));
+    assert_eq!(signature.len(), sig_len);
+    let other =
+        ret_or_panic!(sig_gen(session, privkey, XEDDSA_MESSAGE, &mechanism));
+    assert_ne!(signature, other);
+    for sig in [&signature, &other] {
+        assert_eq!(
+            sig_verify(session, pubkey, XEDDSA_MESSAGE, sig, &mechanism),
+            CKR_OK
+        );
+    }
+    let data = XEDDSA_MESSAGE.to_vec();
+    let multipart =
+        ret_or_panic!(sig_gen_multipart(session, privkey, &data, &mechanism));
+    assert_eq!(
+        sig_verify(session, pubkey, XEDDSA_MESSAGE, &multipart, &mechanism),
+        CKR_OK
+    );
+
+    /* Bogus signatures are rejected */
+    let bogus = vec![0u8; sig_len];
+    assert_eq!(
+        sig_verify(session, pubkey, XEDDSA_MESSAGE, &bogus, &mechanism),
+        CKR_SIGNATURE_INVALID
+    );
+
+    /* The hash is part of the signature */
+    let mut params = CK_XEDDSA_PARAMS { hash: CKM_SHA3_512 };
+    let sha3_mechanism: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_XEDDSA,
+        pParameter: &mut params as *mut _ as CK_VOID_PTR,
+        ulParameterLen: sizeof!(CK_XEDDSA_PARAMS),
+    };
+    assert_eq!(
+        sig_verify(
+            session,
+            pubkey,
+            XEDDSA_MESSAGE,
+            &signature,
+            &sha3_mechanism
+        ),
+        CKR_SIGNATURE_INVALID
+    );
+    let signature = ret_or_panic!(sig_gen(
+        session,
+        privkey,
+        XEDDSA_MESSAGE,
+        &sha3_mechanism
+    ));
+    assert_eq!(
+        sig_verify(
+            session,
+            pubkey,
+            XEDDSA_MESSAGE,
+            &signature,
+            &sha3_mechanism
+        ),
+        CKR_OK
+    );
+    assert_eq!(
+        sig_verify(session, pubkey, XEDDSA_MESSAGE, &signature, &mechanism),
+        CKR_SIGNATURE_INVALID
+    );
+
+    /* Only 64 byte hashes can be selected */
+    let mut params = CK_XEDDSA_PARAMS { hash: CKM_SHA256 };
+    let sha256_mechanism: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_XEDDSA,
+        pParameter: &mut params as *mut _ as CK_VOID_PTR,
+        ulParameterLen: sizeof!(CK_XEDDSA_PARAMS),
+    };
+    let ret = fn_verify_init(
+        session,
+        &sha256_mechanism as *const _ as CK_MECHANISM_PTR,
+        pubkey,
+    );
+    assert_eq!(ret, CKR_MECHANISM_PARAM_INVALID);
+    let ret = fn_sign_init(
+        session,
+        &sha256_mechanism as *const _ as CK_MECHANISM_PTR,
+        privkey,
+    );
+    assert_eq!(ret, CKR_MECHANISM_PARAM_INVALID);
+}
+
+#[test]
+#[parallel]
+fn test_ec_montgomery_key_wrap() {
+    let mut testtokn =
+        TestToken::initialized("test_ec_montgomery_key_wrap.sql", None);
+    let session = testtokn.get_session(true);
+
+    /* login */
+    testtokn.login();
+
+    let wrapkey = ret_or_panic!(generate_key(
+        session,
+        CKM_AES_KEY_GEN,
+        std::ptr::null_mut(),
+        0,
+        &[(CKA_VALUE_LEN, 32)],
+        &[],
+        &[
+            (CKA_WRAP, true),
+            (CKA_UNWRAP, true),
+            (CKA_ENCRYPT, true),
+            (CKA_DECRYPT, true)
+        ],
+    ));
+    let mut mechanism: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_AES_KEY_WRAP_KWP,
+        pParameter: std::ptr::null_mut(),
+        ulParameterLen: 0,
+    };
+
+    /* The Alice keys from RFC 7748 */
+    for (ec_params, value, prefix) in [
+        (
+            "130a63757276653235353139",
+            "77076d0a7318a57d3c16c17251b26645df4c2f87ebc0992ab177fba51db92c2a",
+            "302e020100300506032b656e04220420",
+        ),
+        (
+            "13086375727665343438",
+            concat!(
+                "9a8f4925d1519f5775cf46b04b5800d4ee9ee8bae8bc5565d498c28dd9c9ba",
+                "f574a9419744897391006382a6f127ab1d9ac2d8c0a598726b"
+            ),
+            "3046020100300506032b656f043a0438",
+        ),
+    ] {
+        let params = hex::decode(ec_params).unwrap();
+        let value = hex::decode(value).unwrap();
+        let privkey = ret_or_panic!(import_object(
+            session,
+            CKO_PRIVATE_KEY,
+            &[(CKA_KEY_TYPE, CKK_EC_MONTGOMERY)],
+            &[(CKA_VALUE, &value), (CKA_EC_PARAMS, &params)],
+            &[(CKA_DERIVE, true), (CKA_EXTRACTABLE, true)],
+        ));
+
+        let mut wrapped = vec![0u8; 1024];
+        let mut wrapped_len = wrapped.len() as CK_ULONG;
+        let ret = fn_wrap_key(
+            session,
+            &mut mechanism,
+            wrapkey,
+            privkey,
+            wrapped.as_mut_ptr(),
+            &mut wrapped_len,
+        );
+        assert_eq!(ret, CKR_OK);
+
+        /* RFC 8410 OneAsymmetricKey encoding */
+        let dec = ret_or_panic!(decrypt(
+            session,
+            wrapkey,
+            &wrapped[..(wrapped_len as usize)],
+            &mechanism,
+        ));
+        let mut pkcs8 = hex::decode(prefix).unwrap();
+        pkcs8.extend_from_slice(&value);
+        assert_eq!(dec, pkcs8);
+
+        let mut pri_template = make_attr_template(
+            &[
+                (CKA_CLASS, CKO_PRIVATE_KEY),
+                (CKA_KEY_TYPE, CKK_EC_MONTGOMERY),
+            ],
+            &[],
+            &[(CKA_DERIVE, true), (CKA_EXTRACTABLE, true)],
+        );
+        let mut privkey2 = CK_INVALID_HANDLE;
+        let ret = fn_unwrap_key(
+            session,
+            &mut mechanism,
+            wrapkey,
+            wrapped.as_mut_ptr(),
+            wrapped_len,
+            pri_template.as_mut_ptr(),
+            pri_template.len() as CK_ULONG,
+            &mut privkey2,
+        );
+        assert_eq!(ret, CKR_OK);
+        assert_eq!(get_attr_bytes(session, privkey2, CKA_VALUE), value);
+    }
+
+    testtokn.finalize();
+}
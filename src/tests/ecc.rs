@@ -226,3 +226,171 @@ fn test_ecc_operations() {
 
     testtokn.finalize();
 }
+
+#[cfg(not(feature = "fips"))]
+#[test]
+#[parallel]
+fn test_ecc_additional_curves() {
+    let mut testtokn =
+        TestToken::initialized("test_ecc_additional_curves.sql", None);
+    let session = testtokn.get_session(true);
+
+    /* login */
+    testtokn.login();
+
+    for (name, keysize) in [
+        (ec::SECP256K1, 32),
+        (ec::BRAINPOOLP256R1, 32),
+        (ec::BRAINPOOLP384R1, 48),
+        (ec::BRAINPOOLP512R1, 64),
+    ] {
+        let params = ec::curvename_to_ec_params(name).unwrap();
+        let (pubkey, privkey) = ret_or_panic!(generate_key_pair(
+            session,
+            CKM_EC_KEY_PAIR_GEN,
+            &[],
+            &[(CKA_EC_PARAMS, &params)],
+            &[(CKA_VERIFY, true)],
+            &[],
+            &[],
+            &[(CKA_SIGN, true)],
+        ));
+
+        let mechanism = CK_MECHANISM {
+            mechanism: CKM_ECDSA_SHA256,
+            pParameter: std::ptr::null_mut(),
+            ulParameterLen: 0,
+        };
+        let data = b"plaintext";
+        let signature =
+            ret_or_panic!(sig_gen(session, privkey, data, &mechanism));
+        assert_eq!(signature.len(), 2 * keysize);
+        let ret = sig_verify(session, pubkey, data, &signature, &mechanism);
+        assert_eq!(ret, CKR_OK);
+    }
+
+    /* Import of a secp256k1 private key */
+    let params = ec::curvename_to_ec_params(ec::SECP256K1).unwrap();
+    let value = hex::decode(
+        "c9afa9d845ba75166b5c215767b1d6934e50c3db36e89b127b8a622b120f67\
+         21",
+    )
+    .unwrap();
+    ret_or_panic!(import_object(
+        session,
+        CKO_PRIVATE_KEY,
+        &[(CKA_KEY_TYPE, CKK_EC)],
+        &[(CKA_EC_PARAMS, &params), (CKA_VALUE, &value)],
+        &[(CKA_SIGN, true)],
+    ));
+
+    /* The key size must match the curve */
+    let err = import_object(
+        session,
+        CKO_PRIVATE_KEY,
+        &[(CKA_KEY_TYPE, CKK_EC)],
+        &[(CKA_EC_PARAMS, &params), (CKA_VALUE, &value[1..])],
+        &[(CKA_SIGN, true)],
+    )
+    .unwrap_err();
+    assert_eq!(err.rv(), CKR_ATTRIBUTE_VALUE_INVALID);
+
+    testtokn.finalize();
+}
+
+#[test]
+#[parallel]
+fn test_ecc_explicit_params() {
+    let mut testtokn =
+        TestToken::initialized("test_ecc_explicit_params.sql", None);
+    let session = testtokn.get_session(true);
+
+    /* login */
+    testtokn.login();
+
+    /* prime256v1 with explicit parameters */
+    let mut params = hex::decode(
+        "3081f7020101302c06072a8648ce3d0101022100ffffffff0000000100000000\
+         0000000000000000ffffffffffffffffffffffff305b0420ffffffff00000001\
+         000000000000000000000000fffffffffffffffffffffffc04205ac635d8aa3a\
+         93e7b3ebbd55769886bc651d06b0cc53b0f63bce3c3e27d2604b031500c49d36\
+         0886e704936a6678e1139d26b7819f7e900441046b17d1f2e12c4247f8bce6e5\
+         63a440f277037d812deb33a0f4a13945d898c2964fe342e2fe1a7f9b8ee7eb4a\
+         7c0f9e162bce33576b315ececbb6406837bf51f5022100ffffffff00000000ff\
+         ffffffffffffffbce6faada7179e84f3b9cac2fc632551020101",
+    )
+    .unwrap();
+    let (pubkey, privkey) = ret_or_panic!(generate_key_pair(
+        session,
+        CKM_EC_KEY_PAIR_GEN,
+        &[],
+        &[(CKA_EC_PARAMS, &params)],
+        &[(CKA_VERIFY, true)],
+        &[],
+        &[],
+        &[(CKA_SIGN, true)],
+    ));
+
+    /* The named curve is stored in place of the explicit parameters */
+    let named = hex::decode("06082a8648ce3d030107").unwrap();
+    assert_eq!(get_attr_bytes(session, pubkey, CKA_EC_PARAMS), named);
+    assert_eq!(get_attr_bytes(session, privkey, CKA_EC_PARAMS), named);
+
+    let mechanism = CK_MECHANISM {
+        mechanism: CKM_ECDSA_SHA256,
+        pParameter: std::ptr::null_mut(),
+        ulParameterLen: 0,
+    };
+    let data = b"plaintext";
+    let signature = ret_or_panic!(sig_gen(session, privkey, data, &mechanism));
+    assert_eq!(signature.len(), 64);
+
+    /* Import the public key with the same parameters */
+    let point = get_attr_bytes(session, pubkey, CKA_EC_POINT);
+    let handle = ret_or_panic!(import_object(
+        session,
+        CKO_PUBLIC_KEY,
+        &[(CKA_KEY_TYPE, CKK_EC)],
+        &[(CKA_EC_PARAMS, &params), (CKA_EC_POINT, &point)],
+        &[(CKA_VERIFY, true)],
+    ));
+    let ret = sig_verify(session, handle, data, &signature, &mechanism);
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(get_attr_bytes(session, handle, CKA_EC_PARAMS), named);
+
+    /* Parameters that do not describe a known curve */
+    params[80] ^= 0x01;
+    let err = import_object(
+        session,
+        CKO_PUBLIC_KEY,
+        &[(CKA_KEY_TYPE, CKK_EC)],
+        &[(CKA_EC_PARAMS, &params), (CKA_EC_POINT, &point)],
+        &[(CKA_VERIFY, true)],
+    )
+    .unwrap_err();
+    assert_eq!(err.rv(), CKR_CURVE_NOT_SUPPORTED);
+
+    /* A known curve that the token does not support (secp224r1) */
+    let params = hex::decode(
+        "3081df020101302806072a8648ce3d0101021d00ffffffffffffffffffffffff\
+         ffffffff0000000000000000000000013053041cffffffffffffffffffffffff\
+         fffffffefffffffffffffffffffffffe041cb4050a850c04b3abf54132565044\
+         b0b7d7bfd8ba270b39432355ffb4031500bd71344799d5c7fcdc45b59fa3b9ab\
+         8f6a948bc5043904b70e0cbd6bb4bf7f321390b94a03c1d356c21122343280d6\
+         115c1d21bd376388b5f723fb4c22dfe6cd4375a05a07476444d5819985007e34\
+         021d00ffffffffffffffffffffffffffff16a2e0b8f03e13dd29455c5c2a3d02\
+         0101",
+    )
+    .unwrap();
+    let err = import_object(
+        session,
+        CKO_DOMAIN_PARAMETERS,
+        &[(CKA_KEY_TYPE, CKK_EC)],
+        &[(CKA_EC_PARAMS, &params)],
+        &[],
+    )
+    .unwrap_err();
+    assert_eq!(err.rv(), CKR_CURVE_NOT_SUPPORTED);
+
+    testtokn.finalize();
+}
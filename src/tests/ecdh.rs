@@ -433,3 +433,90 @@ fn test_ecc_derive_nist() {
 
     testtokn.finalize();
 }
+
+#[cfg(not(feature = "fips"))]
+fn get_raw_ec_point(
+    session: CK_SESSION_HANDLE,
+    handle: CK_OBJECT_HANDLE,
+) -> Vec<u8> {
+    let point = get_attr_bytes(session, handle, CKA_EC_POINT);
+    asn1::parse_single::<&[u8]>(&point).unwrap().to_vec()
+}
+
+#[cfg(not(feature = "fips"))]
+#[test]
+#[parallel]
+fn test_ecc_derive_additional_curves() {
+    let mut testtokn =
+        TestToken::initialized("test_ecc_derive_additional_curves.sql", None);
+    let session = testtokn.get_session(true);
+
+    /* login */
+    testtokn.login();
+
+    for (name, keysize) in [
+        (ec::SECP256K1, 32),
+        (ec::BRAINPOOLP256R1, 32),
+        (ec::BRAINPOOLP384R1, 48),
+        (ec::BRAINPOOLP512R1, 64),
+    ] {
+        let params = ec::curvename_to_ec_params(name).unwrap();
+        let mut keys = Vec::new();
+        for _ in 0..2 {
+            keys.push(ret_or_panic!(generate_key_pair(
+                session,
+                CKM_EC_KEY_PAIR_GEN,
+                &[],
+                &[(CKA_EC_PARAMS, &params)],
+                &[],
+                &[],
+                &[],
+                &[(CKA_DERIVE, true)],
+            )));
+        }
+
+        /* both parties must compute the same shared secret */
+        let mut secrets = Vec::new();
+        for (privkey, peer) in [(keys[0].1, keys[1].0), (keys[1].1, keys[0].0)]
+        {
+            let mut peer_point = get_raw_ec_point(session, peer);
+            let mut params = CK_ECDH1_DERIVE_PARAMS {
+                kdf: CKD_NULL,
+                ulSharedDataLen: 0,
+                pSharedData: std::ptr::null_mut(),
+                ulPublicDataLen: peer_point.len() as CK_ULONG,
+                pPublicData: peer_point.as_mut_ptr(),
+            };
+            let mut mechanism: CK_MECHANISM = CK_MECHANISM {
+                mechanism: CKM_ECDH1_DERIVE,
+                pParameter: &mut params as *mut _ as CK_VOID_PTR,
+                ulParameterLen: sizeof!(CK_ECDH1_DERIVE_PARAMS),
+            };
+            let derive_template = make_attr_template(
+                &[
+                    (CKA_CLASS, CKO_SECRET_KEY),
+                    (CKA_KEY_TYPE, CKK_GENERIC_SECRET),
+                    (CKA_VALUE_LEN, keysize as CK_ULONG),
+                ],
+                &[],
+                &[(CKA_EXTRACTABLE, true)],
+            );
+            let mut s_handle = CK_INVALID_HANDLE;
+            let ret = fn_derive_key(
+                session,
+                &mut mechanism,
+                privkey,
+                derive_template.as_ptr() as *mut _,
+                derive_template.len() as CK_ULONG,
+                &mut s_handle,
+            );
+            assert_eq!(ret, CKR_OK);
+            secrets.push(ret_or_panic!(extract_key_value(
+                session, s_handle, keysize
+            )));
+        }
+        assert_eq!(secrets[0], secrets[1]);
+    }
+
+    testtokn.finalize();
+}
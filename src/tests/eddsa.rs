@@ -671,3 +671,169 @@ fn test_create_eddsa_compat() {
 
     testtokn.finalize();
 }
+
+#[test]
+#[parallel]
+fn test_eddsa_key_wrap() {
+    let mut testtokn = TestToken::initialized("test_eddsa_key_wrap.sql", None);
+    let session = testtokn.get_session(true);
+
+    /* login */
+    testtokn.login();
+
+    let wrapkey = ret_or_panic!(generate_key(
+        session,
+        CKM_AES_KEY_GEN,
+        std::ptr::null_mut(),
+        0,
+        &[(CKA_VALUE_LEN, 32)],
+        &[],
+        &[
+            (CKA_WRAP, true),
+            (CKA_UNWRAP, true),
+            (CKA_ENCRYPT, true),
+            (CKA_DECRYPT, true)
+        ],
+    ));
+    let mut mechanism: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_AES_KEY_WRAP_KWP,
+        pParameter: std::ptr::null_mut(),
+        ulParameterLen: 0,
+    };
+
+    /* Example private key from RFC 8410, 10.3 */
+    let value = hex::decode(
+        "d4ee72dbf913584ad5b6d8f1f769f8ad3afe7c28cbf1d4fbe097a88f44755842",
+    )
+    .unwrap();
+    let pkcs8 = hex::decode(concat!(
+        "302e020100300506032b657004220420",
+        "d4ee72dbf913584ad5b6d8f1f769f8ad3afe7c28cbf1d4fbe097a88f44755842"
+    ))
+    .unwrap();
+    let params = hex::decode("130c656477617264733235353139").unwrap();
+    let privkey = ret_or_panic!(import_object(
+        session,
+        CKO_PRIVATE_KEY,
+        &[(CKA_KEY_TYPE, CKK_EC_EDWARDS)],
+        &[(CKA_VALUE, &value), (CKA_EC_PARAMS, &params)],
+        &[(CKA_EXTRACTABLE, true)],
+    ));
+
+    let mut wrapped = vec![0u8; 1024];
+    let mut wrapped_len = wrapped.len() as CK_ULONG;
+    let ret = fn_wrap_key(
+        session,
+        &mut mechanism,
+        wrapkey,
+        privkey,
+        wrapped.as_mut_ptr(),
+        &mut wrapped_len,
+    );
+    assert_eq!(ret, CKR_OK);
+    let dec = ret_or_panic!(decrypt(
+        session,
+        wrapkey,
+        &wrapped[..(wrapped_len as usize)],
+        &mechanism,
+    ));
+    assert_eq!(dec, pkcs8);
+
+    let mut pri_template = make_attr_template(
+        &[(CKA_CLASS, CKO_PRIVATE_KEY), (CKA_KEY_TYPE, CKK_EC_EDWARDS)],
+        &[],
+        &[(CKA_SIGN, true), (CKA_EXTRACTABLE, true)],
+    );
+    let mut privkey2 = CK_INVALID_HANDLE;
+    let ret = fn_unwrap_key(
+        session,
+        &mut mechanism,
+        wrapkey,
+        wrapped.as_mut_ptr(),
+        wrapped_len,
+        pri_template.as_mut_ptr(),
+        pri_template.len() as CK_ULONG,
+        &mut privkey2,
+    );
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(get_attr_bytes(session, privkey2, CKA_VALUE), value);
+
+    /* The curve must match the key type */
+    let mut pri_template = make_attr_template(
+        &[
+            (CKA_CLASS, CKO_PRIVATE_KEY),
+            (CKA_KEY_TYPE, CKK_EC_MONTGOMERY),
+        ],
+        &[],
+        &[(CKA_DERIVE, true)],
+    );
+    let mut handle = CK_INVALID_HANDLE;
+    let ret = fn_unwrap_key(
+        session,
+        &mut mechanism,
+        wrapkey,
+        wrapped.as_mut_ptr(),
+        wrapped_len,
+        pri_template.as_mut_ptr(),
+        pri_template.len() as CK_ULONG,
+        &mut handle,
+    );
+    assert_eq!(ret, CKR_WRAPPED_KEY_INVALID);
+
+    /* Ed448 round trip */
+    let params = hex::decode("130a65647761726473343438").unwrap();
+    let (pubkey, privkey) = ret_or_panic!(generate_key_pair(
+        session,
+        CKM_EC_EDWARDS_KEY_PAIR_GEN,
+        &[],
+        &[(CKA_EC_PARAMS, &params)],
+        &[(CKA_VERIFY, true)],
+        &[],
+        &[],
+        &[(CKA_SIGN, true), (CKA_EXTRACTABLE, true)],
+    ));
+    let mut wrapped_len = wrapped.len() as CK_ULONG;
+    let ret = fn_wrap_key(
+        session,
+        &mut mechanism,
+        wrapkey,
+        privkey,
+        wrapped.as_mut_ptr(),
+        &mut wrapped_len,
+    );
+    assert_eq!(ret, CKR_OK);
+    let mut pri_template = make_attr_template(
+        &[(CKA_CLASS, CKO_PRIVATE_KEY), (CKA_KEY_TYPE, CKK_EC_EDWARDS)],
+        &[],
+        &[(CKA_SIGN, true)],
+    );
+    let mut privkey2 = CK_INVALID_HANDLE;
+    let ret = fn_unwrap_key(
+        session,
+        &mut mechanism,
+        wrapkey,
+        wrapped.as_mut_ptr(),
+        wrapped_len,
+        pri_template.as_mut_ptr(),
+        pri_template.len() as CK_ULONG,
+        &mut privkey2,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    /* The unwrapped key signs for the original public key */
+    let data = b"plaintext";
+    let mut params = CK_EDDSA_PARAMS {
+        phFlag: CK_FALSE,
+        pContextData: std::ptr::null_mut(),
+        ulContextDataLen: 0,
+    };
+    let mechanism = CK_MECHANISM {
+        mechanism: CKM_EDDSA,
+        pParameter: &mut params as *mut _ as CK_VOID_PTR,
+        ulParameterLen: sizeof!(CK_EDDSA_PARAMS),
+    };
+    let sig = ret_or_panic!(sig_gen(session, privkey2, data, &mechanism));
+    assert_eq!(CKR_OK, sig_verify(session, pubkey, data, &sig, &mechanism));
+
+    testtokn.finalize();
+}
@@ -0,0 +1,408 @@
+// Copyright 2024 Simo Sorce
+// See LICENSE.txt file for terms
+
+use crate::kasn1::pkcs::{KeySpecificInfo, X942OtherInfo};
+use crate::tests::*;
+
+use serial_test::parallel;
+
+/* RFC 7919 ffdhe2048 */
+const FFDHE2048_P: &str = "\
+    ffffffffffffffffadf85458a2bb4a9aafdc5620273d3cf1d8b9c583ce2d\
+    3695a9e13641146433fbcc939dce249b3ef97d2fe363630c75d8f681b202\
+    aec4617ad3df1ed5d5fd65612433f51f5f066ed0856365553ded1af3b557\
+    135e7f57c935984f0c70e0e68b77e2a689daf3efe8721df158a136ade735\
+    30acca4f483a797abc0ab182b324fb61d108a94bb2c8e3fbb96adab760d7\
+    f4681d4f42a3de394df4ae56ede76372bb190b07a7c8ee0a6d709e02fce1\
+    cdf7e2ecc03404cd28342f619172fe9ce98583ff8e4f1232eef28183c3fe\
+    3b1b4c6fad733bb5fcbc2ec22005c58ef1837d1683b2c6f34a26c1b2effa\
+    886b423861285c97ffffffffffffffff";
+
+/* RFC 5114 2048-bit MODP Group with 224-bit Prime Order Subgroup */
+const RFC5114_2048_224_P: &str = "\
+    ad107e1e9123a9d0d660faa79559c51fa20d64e5683b9fd1b54b1597b61d\
+    0a75e6fa141df95a56dbaf9a3c407ba1df15eb3d688a309c180e1de6b85a\
+    1274a0a66d3f8152ad6ac2129037c9edefda4df8d91e8fef55b7394b7ad5\
+    b7d0b6c12207c9f98d11ed34dbf6c6ba0b2c8bbc27be6a00e0a0b9c49708\
+    b3bf8a317091883681286130bc8985db1602e714415d9330278273c7de31\
+    efdc7310f7121fd5a07415987d9adc0a486dcdf93acc44328387315d75e1\
+    98c641a480cd86a1b9e587e8be60e69cc928b2b9c52172e413042e9b23f1\
+    0b0e16e79763c9b53dcf4ba80a29e3fb73c16b8e75b97ef363e2ffa31f71\
+    cf9de5384e71b81c0ac4dffe0c10e64f";
+const RFC5114_2048_224_G: &str = "\
+    ac4032ef4f2d9ae39df30b5c8ffdac506cdebe7b89998caf74866a08cfe4\
+    ffe3a6824a4e10b9a6f0dd921f01a70c4afaab739d7700c29f52c57db17c\
+    620a8652be5e9001a8d66ad7c17669101999024af4d027275ac1348bb8a7\
+    62d0521bc98ae247150422ea1ed409939d54da7460cdb5f6c6b250717cbe\
+    f180eb34118e98d119529a45d6f834566e3025e316a330efbb77a86f0c1a\
+    b15b051ae3d428c8f8acb70a8137150b8eeb10e183edd19963ddd9e263e4\
+    770589ef6aa21e7f5f2ff381b539cce3409d13cd566afbb48d6c019181e1\
+    bcfe94b30269edfe72fe9b6aa4bd7b5a0f1c71cfff4c19c418e1f6ec0179\
+    81bc087f2a7065b384b890d3191f2bfa";
+const RFC5114_2048_224_Q: &str =
+    "801c0d34c58d93fe997177101f80535a4738cebcbf389a99b36371eb";
+
+fn get_public_value(
+    session: CK_SESSION_HANDLE,
+    handle: CK_OBJECT_HANDLE,
+) -> Vec<u8> {
+    let mut template =
+        make_ptrs_template(&[(CKA_VALUE, std::ptr::null_mut(), 0)]);
+    let ret = fn_get_attribute_value(
+        session,
+        handle,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+    );
+    assert_eq!(ret, CKR_OK);
+    let len = template[0].ulValueLen as usize;
+    ret_or_panic!(extract_key_value(session, handle, len))
+}
+
+fn digest(
+    session: CK_SESSION_HANDLE,
+    mech: CK_MECHANISM_TYPE,
+    data: &[u8],
+) -> Vec<u8> {
+    let mut mechanism = CK_MECHANISM {
+        mechanism: mech,
+        pParameter: std::ptr::null_mut(),
+        ulParameterLen: 0,
+    };
+    let ret = fn_digest_init(session, &mut mechanism);
+    assert_eq!(ret, CKR_OK);
+    let mut value = vec![0u8; 64];
+    let mut value_len = value.len() as CK_ULONG;
+    let ret = fn_digest(
+        session,
+        data.as_ptr() as *mut u8,
+        data.len() as CK_ULONG,
+        value.as_mut_ptr(),
+        &mut value_len,
+    );
+    assert_eq!(ret, CKR_OK);
+    value.truncate(value_len as usize);
+    value
+}
+
+fn derive_secret(
+    session: CK_SESSION_HANDLE,
+    mechanism: &mut CK_MECHANISM,
+    key: CK_OBJECT_HANDLE,
+    len: CK_ULONG,
+) -> Result<Vec<u8>> {
+    let mut ulongs = vec![
+        (CKA_CLASS, CKO_SECRET_KEY),
+        (CKA_KEY_TYPE, CKK_GENERIC_SECRET),
+    ];
+    if len != 0 {
+        ulongs.push((CKA_VALUE_LEN, len));
+    }
+    let template = make_attr_template(
+        &ulongs,
+        &[],
+        &[(CKA_EXTRACTABLE, true), (CKA_SENSITIVE, false)],
+    );
+    let mut handle = CK_INVALID_HANDLE;
+    let ret = fn_derive_key(
+        session,
+        mechanism,
+        key,
+        template.as_ptr() as *mut _,
+        template.len() as CK_ULONG,
+        &mut handle,
+    );
+    if ret != CKR_OK {
+        Err(ret)?;
+    }
+    Ok(get_public_value(session, handle))
+}
+
+#[test]
+#[parallel]
+fn test_ffdh_pkcs3_derive() {
+    let mut testtokn =
+        TestToken::initialized("test_ffdh_pkcs3_derive.sql", None);
+    let session = testtokn.get_session(true);
+
+    /* login */
+    testtokn.login();
+
+    let prime = hex::decode(FFDHE2048_P).unwrap();
+    let base = vec![2u8];
+
+    let mut keys = Vec::new();
+    for _ in 0..2 {
+        let (pubkey, privkey) = ret_or_panic!(generate_key_pair(
+            session,
+            CKM_DH_PKCS_KEY_PAIR_GEN,
+            &[],
+            &[(CKA_PRIME, &prime), (CKA_BASE, &base)],
+            &[],
+            &[],
+            &[],
+            &[(CKA_DERIVE, true)],
+        ));
+        keys.push((pubkey, privkey));
+    }
+
+    /* the private value size follows RFC 7919 for named groups */
+    let mut bits: CK_ULONG = 0;
+    let mut template = make_ptrs_template(&[(
+        CKA_VALUE_BITS,
+        void_ptr!(&mut bits),
+        CK_ULONG_SIZE,
+    )]);
+    let ret = fn_get_attribute_value(
+        session,
+        keys[0].1,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+    );
+    assert_eq!(ret, CKR_OK);
+    assert!(bits > 0 && bits <= 225);
+    if let Some(err) = check_attributes(
+        session,
+        keys[0].1,
+        &[],
+        &[(CKA_PRIME, &prime), (CKA_BASE, &base)],
+        &[(CKA_LOCAL, true), (CKA_PRIVATE, true)],
+    ) {
+        panic!("{}", err);
+    }
+
+    let mut secrets = Vec::new();
+    for (local, peer) in [(0, 1), (1, 0)] {
+        let mut peer_value = get_public_value(session, keys[peer].0);
+        let mut mechanism = CK_MECHANISM {
+            mechanism: CKM_DH_PKCS_DERIVE,
+            pParameter: void_ptr!(peer_value.as_mut_ptr()),
+            ulParameterLen: peer_value.len() as CK_ULONG,
+        };
+        secrets.push(ret_or_panic!(derive_secret(
+            session,
+            &mut mechanism,
+            keys[local].1,
+            0,
+        )));
+    }
+    assert_eq!(secrets[0].len(), prime.len());
+    assert_eq!(secrets[0], secrets[1]);
+
+    /* a shorter key is taken from the end of the shared secret */
+    let mut peer_value = get_public_value(session, keys[1].0);
+    let mut mechanism = CK_MECHANISM {
+        mechanism: CKM_DH_PKCS_DERIVE,
+        pParameter: void_ptr!(peer_value.as_mut_ptr()),
+        ulParameterLen: peer_value.len() as CK_ULONG,
+    };
+    let short =
+        ret_or_panic!(derive_secret(session, &mut mechanism, keys[0].1, 32));
+    assert_eq!(short, secrets[0][(prime.len() - 32)..]);
+
+    /* Invalid peer public value */
+    let mut bad_value = vec![1u8];
+    let mut mechanism = CK_MECHANISM {
+        mechanism: CKM_DH_PKCS_DERIVE,
+        pParameter: void_ptr!(bad_value.as_mut_ptr()),
+        ulParameterLen: bad_value.len() as CK_ULONG,
+    };
+    let err = derive_secret(session, &mut mechanism, keys[0].1, 0)
+        .expect_err("derive with an invalid peer value should fail");
+    assert_eq!(err.rv(), CKR_MECHANISM_PARAM_INVALID);
+
+    /* PKCS#3 keys can't be used with the X9.42 mechanism */
+    let mut params = CK_X9_42_DH1_DERIVE_PARAMS {
+        kdf: CKD_NULL,
+        ulOtherInfoLen: 0,
+        pOtherInfo: std::ptr::null_mut(),
+        ulPublicDataLen: peer_value.len() as CK_ULONG,
+        pPublicData: peer_value.as_mut_ptr(),
+    };
+    let mut mechanism = CK_MECHANISM {
+        mechanism: CKM_X9_42_DH_DERIVE,
+        pParameter: void_ptr!(&mut params),
+        ulParameterLen: sizeof!(CK_X9_42_DH1_DERIVE_PARAMS),
+    };
+    let err = derive_secret(session, &mut mechanism, keys[0].1, 0)
+        .expect_err("derive with the wrong key type should fail");
+    assert_eq!(err.rv(), CKR_KEY_TYPE_INCONSISTENT);
+
+    /* Domain parameters that are too small are refused */
+    let ret = generate_key_pair(
+        session,
+        CKM_DH_PKCS_KEY_PAIR_GEN,
+        &[],
+        &[(CKA_PRIME, &prime[..64]), (CKA_BASE, &base)],
+        &[],
+        &[],
+        &[],
+        &[(CKA_DERIVE, true)],
+    );
+    assert_eq!(ret.unwrap_err().rv(), CKR_ATTRIBUTE_VALUE_INVALID);
+
+    testtokn.finalize();
+}
+
+#[test]
+#[parallel]
+fn test_ffdh_x942_derive() {
+    let mut testtokn =
+        TestToken::initialized("test_ffdh_x942_derive.sql", None);
+    let session = testtokn.get_session(true);
+
+    /* login */
+    testtokn.login();
+
+    let prime = hex::decode(RFC5114_2048_224_P).unwrap();
+    let base = hex::decode(RFC5114_2048_224_G).unwrap();
+    let subprime = hex::decode(RFC5114_2048_224_Q).unwrap();
+
+    let mut keys = Vec::new();
+    for _ in 0..2 {
+        let (pubkey, privkey) = ret_or_panic!(generate_key_pair(
+            session,
+            CKM_X9_42_DH_KEY_PAIR_GEN,
+            &[],
+            &[
+                (CKA_PRIME, &prime),
+                (CKA_BASE, &base),
+                (CKA_SUBPRIME, &subprime)
+            ],
+            &[],
+            &[],
+            &[],
+            &[(CKA_DERIVE, true)],
+        ));
+        if let Some(err) = check_attributes(
+            session,
+            privkey,
+            &[(CKA_KEY_TYPE, CKK_X9_42_DH)],
+            &[(CKA_SUBPRIME, &subprime)],
+            &[],
+        ) {
+            panic!("{}", err);
+        }
+        keys.push((pubkey, privkey));
+    }
+    let mut peer_value = get_public_value(session, keys[1].0);
+
+    /* raw shared secret */
+    let mut params = CK_X9_42_DH1_DERIVE_PARAMS {
+        kdf: CKD_NULL,
+        ulOtherInfoLen: 0,
+        pOtherInfo: std::ptr::null_mut(),
+        ulPublicDataLen: peer_value.len() as CK_ULONG,
+        pPublicData: peer_value.as_mut_ptr(),
+    };
+    let mut mechanism = CK_MECHANISM {
+        mechanism: CKM_X9_42_DH_DERIVE,
+        pParameter: void_ptr!(&mut params),
+        ulParameterLen: sizeof!(CK_X9_42_DH1_DERIVE_PARAMS),
+    };
+    let secret =
+        ret_or_panic!(derive_secret(session, &mut mechanism, keys[0].1, 0));
+    assert_eq!(secret.len(), prime.len());
+
+    /* check the other side agrees */
+    let mut peer_value0 = get_public_value(session, keys[0].0);
+    params.ulPublicDataLen = peer_value0.len() as CK_ULONG;
+    params.pPublicData = peer_value0.as_mut_ptr();
+    let mut mechanism = CK_MECHANISM {
+        mechanism: CKM_X9_42_DH_DERIVE,
+        pParameter: void_ptr!(&mut params),
+        ulParameterLen: sizeof!(CK_X9_42_DH1_DERIVE_PARAMS),
+    };
+    let secret1 =
+        ret_or_panic!(derive_secret(session, &mut mechanism, keys[1].1, 0));
+    assert_eq!(secret, secret1);
+
+    /* ANSI X9.42 ASN.1 KDF, the counter is filled in by the token */
+    let supp_pub_info = 128u32.to_be_bytes();
+    let mut other_info = asn1::write_single(&X942OtherInfo {
+        key_info: KeySpecificInfo {
+            algorithm: asn1::ObjectIdentifier::from_string(
+                "2.16.840.1.101.3.4.1.5",
+            )
+            .unwrap(),
+            counter: &[0, 0, 0, 1],
+        },
+        party_a_info: None,
+        supp_pub_info: asn1::Explicit::new(&supp_pub_info),
+    })
+    .unwrap();
+    let mut data = secret.clone();
+    data.extend_from_slice(&other_info);
+    let asn1_expect = digest(session, CKM_SHA_1, &data);
+
+    /* X9.63 style concatenation KDF */
+    let mut data = secret.clone();
+    data.extend_from_slice(&[0, 0, 0, 1]);
+    data.extend_from_slice(&other_info);
+    let concat_expect = digest(session, CKM_SHA_1, &data);
+
+    /* SP 800-56C One-Step KDF */
+    let mut data = vec![0, 0, 0, 1];
+    data.extend_from_slice(&secret);
+    data.extend_from_slice(&other_info);
+    let sp800_expect = digest(session, CKM_SHA256, &data);
+
+    for (kdf, expect) in [
+        (CKD_SHA1_KDF_ASN1, asn1_expect),
+        (CKD_SHA1_KDF_CONCATENATE, concat_expect),
+        (CKD_SHA256_KDF_SP800, sp800_expect),
+    ] {
+        let mut params = CK_X9_42_DH1_DERIVE_PARAMS {
+            kdf,
+            ulOtherInfoLen: other_info.len() as CK_ULONG,
+            pOtherInfo: other_info.as_mut_ptr(),
+            ulPublicDataLen: peer_value.len() as CK_ULONG,
+            pPublicData: peer_value.as_mut_ptr(),
+        };
+        let mut mechanism = CK_MECHANISM {
+            mechanism: CKM_X9_42_DH_DERIVE,
+            pParameter: void_ptr!(&mut params),
+            ulParameterLen: sizeof!(CK_X9_42_DH1_DERIVE_PARAMS),
+        };
+        let value = ret_or_panic!(derive_secret(
+            session,
+            &mut mechanism,
+            keys[0].1,
+            16
+        ));
+        assert_eq!(value, expect[..16]);
+    }
+
+    /* Other info is not allowed without a KDF */
+    let mut params = CK_X9_42_DH1_DERIVE_PARAMS {
+        kdf: CKD_NULL,
+        ulOtherInfoLen: other_info.len() as CK_ULONG,
+        pOtherInfo: other_info.as_mut_ptr(),
+        ulPublicDataLen: peer_value.len() as CK_ULONG,
+        pPublicData: peer_value.as_mut_ptr(),
+    };
+    let mut mechanism = CK_MECHANISM {
+        mechanism: CKM_X9_42_DH_DERIVE,
+        pParameter: void_ptr!(&mut params),
+        ulParameterLen: sizeof!(CK_X9_42_DH1_DERIVE_PARAMS),
+    };
+    let err = derive_secret(session, &mut mechanism, keys[0].1, 16)
+        .expect_err("other info without a KDF should fail");
+    assert_eq!(err.rv(), CKR_MECHANISM_PARAM_INVALID);
+
+    /* Malformed OtherInfo for the ASN.1 KDF */
+    let mut bad_info = vec![0u8; 8];
+    params.kdf = CKD_SHA1_KDF_ASN1;
+    params.ulOtherInfoLen = bad_info.len() as CK_ULONG;
+    params.pOtherInfo = bad_info.as_mut_ptr();
+    let mut mechanism = CK_MECHANISM {
+        mechanism: CKM_X9_42_DH_DERIVE,
+        pParameter: void_ptr!(&mut params),
+        ulParameterLen: sizeof!(CK_X9_42_DH1_DERIVE_PARAMS),
+    };
+    let err = derive_secret(session, &mut mechanism, keys[0].1, 16)
+        .expect_err("malformed other info should fail");
+    assert_eq!(err.rv(), CKR_MECHANISM_PARAM_INVALID);
+
+    testtokn.finalize();
+}
@@ -0,0 +1,577 @@
+// Copyright 2024 Simo Sorce
+// See LICENSE.txt file for terms
+
+use std::io;
+use std::io::BufRead;
+
+use crate::attribute::CkAttrs;
+use crate::hss::*;
+use crate::mechanism::Sign;
+use crate::tests::*;
+
+use serial_test::parallel;
+
+fn ulongs_to_bytes(v: &[CK_ULONG]) -> Vec<u8> {
+    v.iter().flat_map(|u| u.to_ne_bytes()).collect()
+}
+
+/* index of the signature for a 2 level H5/W4 key, the top level LMS
+ * signature starts at offset 4 and the bottom one at offset 2408 */
+fn hss_index(sig: &[u8]) -> u32 {
+    let top = u32::from_be_bytes(sig[4..8].try_into().unwrap());
+    let bottom = u32::from_be_bytes(sig[2408..2412].try_into().unwrap());
+    (top << 5) + bottom
+}
+
+#[test]
+#[parallel]
+fn test_hss_operations() {
+    let mut testtokn = TestToken::initialized("test_hss_operations.sql", None);
+    let session = testtokn.get_session(true);
+
+    /* login */
+    testtokn.login();
+
+    let data = b"firmware image";
+    let mechanism = CK_MECHANISM {
+        mechanism: CKM_HSS,
+        pParameter: std::ptr::null_mut(),
+        ulParameterLen: 0,
+    };
+
+    let lms_types = ulongs_to_bytes(&[LMS_SHA256_M32_H5, LMS_SHA256_M32_H5]);
+    let lmots_types =
+        ulongs_to_bytes(&[LMOTS_SHA256_N32_W4, LMOTS_SHA256_N32_W4]);
+    let (pubkey, privkey) = ret_or_panic!(generate_key_pair(
+        session,
+        CKM_HSS_KEY_PAIR_GEN,
+        &[],
+        &[],
+        &[(CKA_VERIFY, true)],
+        &[(CKA_HSS_LEVELS, 2)],
+        &[
+            (CKA_HSS_LMS_TYPES, &lms_types),
+            (CKA_HSS_LMOTS_TYPES, &lmots_types)
+        ],
+        &[(CKA_SIGN, true)],
+    ));
+    if let Some(err) = check_attributes(
+        session,
+        pubkey,
+        &[
+            (CKA_HSS_LEVELS, 2),
+            (CKA_HSS_LMS_TYPE, LMS_SHA256_M32_H5),
+            (CKA_HSS_LMOTS_TYPE, LMOTS_SHA256_N32_W4),
+        ],
+        &[],
+        &[(CKA_LOCAL, true)],
+    ) {
+        panic!("{}", err);
+    }
+    if let Some(err) = check_attributes(
+        session,
+        privkey,
+        &[(CKA_HSS_KEYS_REMAINING, 1024)],
+        &[],
+        &[(CKA_PRIVATE, true), (CKA_COPYABLE, false)],
+    ) {
+        panic!("{}", err);
+    }
+
+    /* each signature uses the next one time key */
+    let sig = ret_or_panic!(sig_gen(session, privkey, data, &mechanism));
+    assert_eq!(sig.len(), 4 + 2348 + 56 + 2348);
+    assert_eq!(hss_index(&sig), 0);
+    assert_eq!(CKR_OK, sig_verify(session, pubkey, data, &sig, &mechanism));
+    assert_eq!(
+        CKR_SIGNATURE_INVALID,
+        sig_verify(session, pubkey, b"tampered image", &sig, &mechanism)
+    );
+    assert_eq!(
+        CKR_SIGNATURE_INVALID,
+        sig_verify(session, pubkey, data, &sig[..sig.len() - 1], &mechanism)
+    );
+
+    let sig = ret_or_panic!(sig_gen_multipart(
+        session,
+        privkey,
+        &data.to_vec(),
+        &mechanism
+    ));
+    assert_eq!(hss_index(&sig), 1);
+    assert_eq!(CKR_OK, sig_verify(session, pubkey, data, &sig, &mechanism));
+
+    if let Some(err) = check_attributes(
+        session,
+        privkey,
+        &[(CKA_HSS_KEYS_REMAINING, 1022)],
+        &[],
+        &[],
+    ) {
+        panic!("{}", err);
+    }
+
+    /* the state can not be changed or duplicated */
+    let remaining: CK_ULONG = 1024;
+    let mut template =
+        make_attr_template(&[(CKA_HSS_KEYS_REMAINING, remaining)], &[], &[]);
+    let ret = fn_set_attribute_value(
+        session,
+        privkey,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+    );
+    assert_eq!(ret, CKR_ATTRIBUTE_READ_ONLY);
+
+    let mut copy: CK_OBJECT_HANDLE = CK_INVALID_HANDLE;
+    let mut template = make_attr_template(&[], &[], &[(CKA_PRIVATE, true)]);
+    let ret = fn_copy_object(
+        session,
+        privkey,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut copy,
+    );
+    assert_eq!(ret, CKR_ACTION_PROHIBITED);
+
+    /* a key with a single signature left */
+    let lms_types = ulongs_to_bytes(&[LMS_SHA256_M32_H5]);
+    let lmots_types = ulongs_to_bytes(&[LMOTS_SHA256_N32_W8]);
+    let mut value = 31u64.to_be_bytes().to_vec();
+    value.extend_from_slice(&[0x5a; HSS_I_LEN + HSS_N]);
+    let lastkey = ret_or_panic!(import_object(
+        session,
+        CKO_PRIVATE_KEY,
+        &[(CKA_KEY_TYPE, CKK_HSS), (CKA_HSS_LEVELS, 1)],
+        &[
+            (CKA_HSS_LMS_TYPES, &lms_types),
+            (CKA_HSS_LMOTS_TYPES, &lmots_types),
+            (CKA_VALUE, &value)
+        ],
+        &[(CKA_SIGN, true)],
+    ));
+    if let Some(err) = check_attributes(
+        session,
+        lastkey,
+        &[(CKA_HSS_KEYS_REMAINING, 1)],
+        &[],
+        &[],
+    ) {
+        panic!("{}", err);
+    }
+    let sig = ret_or_panic!(sig_gen(session, lastkey, data, &mechanism));
+    assert_eq!(u32::from_be_bytes(sig[4..8].try_into().unwrap()), 31);
+    match sig_gen(session, lastkey, data, &mechanism) {
+        Ok(_) => panic!("the key should be exhausted"),
+        Err(e) => assert_eq!(e.rv(), CKR_KEY_EXHAUSTED),
+    }
+
+    testtokn.finalize();
+}
+
+#[derive(Debug)]
+struct HssTestUnit {
+    line: usize,
+    label: String,
+    lms_types: Vec<CK_ULONG>,
+    lmots_types: Vec<CK_ULONG>,
+    private: Vec<u8>,
+    public: Vec<u8>,
+    message: Vec<u8>,
+    signature: Vec<u8>,
+}
+
+enum HssParserState {
+    None,
+    Private,
+    Public,
+    Message,
+    Signature,
+}
+
+fn parse_types(line: &str, ln: usize) -> Vec<CK_ULONG> {
+    line.split_whitespace()
+        .map(|t| parse_or_panic!(t.parse::<CK_ULONG>(); line; ln))
+        .collect()
+}
+
+fn parse_hss_vector(filename: &str) -> Vec<HssTestUnit> {
+    let file = ret_or_panic!(std::fs::File::open(filename));
+
+    let mut data = Vec::<HssTestUnit>::new();
+    let mut state = HssParserState::None;
+    for (l, line) in io::BufReader::new(file)
+        .lines()
+        .map_while(std::result::Result::ok)
+        .enumerate()
+    {
+        let ln = l + 1;
+
+        if line.is_empty() {
+            continue;
+        }
+        if line.starts_with("-----") {
+            println!("  : Testcase: {}", line);
+            data.push(HssTestUnit {
+                line: ln,
+                label: line.clone(),
+                lms_types: Vec::new(),
+                lmots_types: Vec::new(),
+                private: Vec::new(),
+                public: Vec::new(),
+                message: Vec::new(),
+                signature: Vec::new(),
+            });
+            state = HssParserState::None;
+            continue;
+        }
+        let unit = match data.last_mut() {
+            Some(u) => u,
+            None => continue,
+        };
+        if let Some(types) = line.strip_prefix("LMS TYPES:") {
+            unit.lms_types = parse_types(types, ln);
+            continue;
+        }
+        if let Some(types) = line.strip_prefix("LMOTS TYPES:") {
+            unit.lmots_types = parse_types(types, ln);
+            continue;
+        }
+        match line.as_str() {
+            "PRIVATE KEY:" => state = HssParserState::Private,
+            "PUBLIC KEY:" => state = HssParserState::Public,
+            "MESSAGE:" => state = HssParserState::Message,
+            "SIGNATURE:" => state = HssParserState::Signature,
+            _ => {
+                let val = parse_or_panic!(hex::decode(&line); line; ln);
+                match state {
+                    HssParserState::Private => unit.private.extend(val),
+                    HssParserState::Public => unit.public.extend(val),
+                    HssParserState::Message => unit.message.extend(val),
+                    HssParserState::Signature => unit.signature.extend(val),
+                    HssParserState::None => {
+                        panic!("Unexpected data on line {}", ln)
+                    }
+                }
+            }
+        }
+    }
+    data
+}
+
+fn test_hss_units(session: CK_SESSION_HANDLE, test_data: Vec<HssTestUnit>) {
+    let mechanism = CK_MECHANISM {
+        mechanism: CKM_HSS,
+        pParameter: std::ptr::null_mut(),
+        ulParameterLen: 0,
+    };
+
+    for unit in test_data {
+        println!("Executing test at line {}", unit.line);
+
+        let levels = unit.lms_types.len() as CK_ULONG;
+        let lms_types = ulongs_to_bytes(&unit.lms_types);
+        let lmots_types = ulongs_to_bytes(&unit.lmots_types);
+        let priv_handle = ret_or_panic!(import_object(
+            session,
+            CKO_PRIVATE_KEY,
+            &[(CKA_KEY_TYPE, CKK_HSS), (CKA_HSS_LEVELS, levels)],
+            &[
+                (CKA_HSS_LMS_TYPES, &lms_types),
+                (CKA_HSS_LMOTS_TYPES, &lmots_types),
+                (CKA_VALUE, &unit.private),
+                (
+                    CKA_LABEL,
+                    format!(
+                        "HSS private key, label={}, line {}",
+                        unit.label, unit.line
+                    )
+                    .as_bytes()
+                )
+            ],
+            &[(CKA_SIGN, true)],
+        ));
+
+        let pub_handle = ret_or_panic!(import_object(
+            session,
+            CKO_PUBLIC_KEY,
+            &[(CKA_KEY_TYPE, CKK_HSS)],
+            &[
+                (CKA_VALUE, &unit.public),
+                (
+                    CKA_LABEL,
+                    format!(
+                        "HSS public key, label={}, line {}",
+                        unit.label, unit.line
+                    )
+                    .as_bytes()
+                )
+            ],
+            &[(CKA_VERIFY, true)],
+        ));
+        if let Some(err) = check_attributes(
+            session,
+            pub_handle,
+            &[
+                (CKA_HSS_LEVELS, levels),
+                (CKA_HSS_LMS_TYPE, unit.lms_types[0]),
+                (CKA_HSS_LMOTS_TYPE, unit.lmots_types[0]),
+            ],
+            &[],
+            &[],
+        ) {
+            panic!("{}", err);
+        }
+
+        assert_eq!(
+            CKR_OK,
+            sig_verify(
+                session,
+                pub_handle,
+                &unit.message,
+                &unit.signature,
+                &mechanism
+            )
+        );
+
+        /* signing with the same state must produce the same signatures
+         * of the lower level public keys, only the message signature
+         * at the bottom uses a random value */
+        let sig = ret_or_panic!(sig_gen(
+            session,
+            priv_handle,
+            &unit.message,
+            &mechanism
+        ));
+        assert_eq!(sig.len(), unit.signature.len());
+        let last = unit.lms_types.len() - 1;
+        let bottom = sig.len()
+            - ret_or_panic!(lms_signature_len(
+                unit.lms_types[last],
+                unit.lmots_types[last]
+            ));
+        assert_eq!(sig[..bottom], unit.signature[..bottom]);
+        assert_eq!(
+            CKR_OK,
+            sig_verify(session, pub_handle, &unit.message, &sig, &mechanism)
+        );
+    }
+}
+
+#[test]
+#[parallel]
+fn test_hss_vector() {
+    /* Signatures computed with an independent implementation of RFC 8554
+     * using the same derivation of the lower level trees */
+    let test_data = parse_hss_vector("testdata/hss_kat.txt");
+
+    let mut testtokn = TestToken::initialized("test_hss_vector.sql", None);
+    let session = testtokn.get_session(false);
+
+    /* login */
+    testtokn.login();
+
+    test_hss_units(session, test_data);
+
+    testtokn.finalize();
+}
+
+fn find_key(token: &mut Token, class: CK_OBJECT_CLASS) -> CK_OBJECT_HANDLE {
+    let mut template = CkAttrs::with_capacity(2);
+    ret_or_panic!(template.add_owned_ulong(CKA_CLASS, class));
+    ret_or_panic!(template.add_owned_ulong(CKA_KEY_TYPE, CKK_HSS));
+    let handles = ret_or_panic!(token.search_objects(template.as_slice()));
+    assert_eq!(handles.len(), 1);
+    handles[0]
+}
+
+fn keys_remaining(token: &mut Token) -> CK_ULONG {
+    let handle = find_key(token, CKO_PRIVATE_KEY);
+    let key = ret_or_panic!(token.get_object_by_handle(handle));
+    ret_or_panic!(key.get_attr_as_ulong(CKA_HSS_KEYS_REMAINING))
+}
+
+fn new_signature(token: &mut Token) -> Box<dyn Sign> {
+    let mechanism = CK_MECHANISM {
+        mechanism: CKM_HSS,
+        pParameter: std::ptr::null_mut(),
+        ulParameterLen: 0,
+    };
+    let handle = find_key(token, CKO_PRIVATE_KEY);
+    let key = ret_or_panic!(token.get_object_by_handle(handle));
+    let mech = ret_or_panic!(token.get_mechanisms().get(CKM_HSS));
+    ret_or_panic!(mech.sign_new(&mechanism, &key))
+}
+
+/* Goes through the same steps as C_Sign, the state is committed to the
+ * storage before the signature is computed */
+fn start_signature(token: &mut Token) -> Box<dyn Sign> {
+    let mut op = new_signature(token);
+    ret_or_panic!(token.reserve_sign_state(op.as_mut()));
+    op
+}
+
+fn sign(token: &mut Token, data: &[u8]) -> Vec<u8> {
+    let mut op = start_signature(token);
+    let mut sig = vec![0u8; ret_or_panic!(op.signature_len())];
+    ret_or_panic!(op.sign(data, &mut sig));
+    sig
+}
+
+fn verify(token: &mut Token, data: &[u8], sig: &[u8]) {
+    let mechanism = CK_MECHANISM {
+        mechanism: CKM_HSS,
+        pParameter: std::ptr::null_mut(),
+        ulParameterLen: 0,
+    };
+    let handle = find_key(token, CKO_PUBLIC_KEY);
+    let key = ret_or_panic!(token.get_object_by_handle(handle));
+    let mech = ret_or_panic!(token.get_mechanisms().get(CKM_HSS));
+    let mut op = ret_or_panic!(mech.verify_new(&mechanism, &key));
+    ret_or_panic!(op.verify(data, sig));
+}
+
+fn open_token(dbpath: &str) -> Token {
+    let dbtype = storage::suffix_to_type(dbpath).unwrap();
+    let mut token = ret_or_panic!(Token::new(dbtype, Some(dbpath.to_string())));
+    assert_eq!(token.login(CKU_USER, USER_PIN.as_bytes()), CKR_OK);
+    token
+}
+
+fn generate_keys(token: &mut Token) {
+    let mechanism = CK_MECHANISM {
+        mechanism: CKM_HSS_KEY_PAIR_GEN,
+        pParameter: std::ptr::null_mut(),
+        ulParameterLen: 0,
+    };
+    let mut pub_template = CkAttrs::with_capacity(2);
+    ret_or_panic!(pub_template.add_owned_bool(CKA_TOKEN, CK_TRUE));
+    ret_or_panic!(pub_template.add_owned_bool(CKA_VERIFY, CK_TRUE));
+    let mut pri_template = CkAttrs::with_capacity(5);
+    ret_or_panic!(pri_template.add_owned_bool(CKA_TOKEN, CK_TRUE));
+    ret_or_panic!(pri_template.add_owned_bool(CKA_SIGN, CK_TRUE));
+    ret_or_panic!(pri_template.add_owned_ulong(CKA_HSS_LEVELS, 2));
+    ret_or_panic!(pri_template.add_vec(
+        CKA_HSS_LMS_TYPES,
+        ulongs_to_bytes(&[LMS_SHA256_M32_H5, LMS_SHA256_M32_H5])
+    ));
+    ret_or_panic!(pri_template.add_vec(
+        CKA_HSS_LMOTS_TYPES,
+        ulongs_to_bytes(&[LMOTS_SHA256_N32_W4, LMOTS_SHA256_N32_W4])
+    ));
+    let mech = ret_or_panic!(token.get_mechanisms().get(CKM_HSS_KEY_PAIR_GEN));
+    let (pubkey, privkey) = ret_or_panic!(mech.generate_keypair(
+        &mechanism,
+        pub_template.as_slice(),
+        pri_template.as_slice()
+    ));
+    ret_or_panic!(token.insert_object(CK_INVALID_HANDLE, pubkey));
+    ret_or_panic!(token.insert_object(CK_INVALID_HANDLE, privkey));
+}
+
+fn test_hss_state_common(dbpath: String) {
+    let mut testtokn = TestToken::new(dbpath.clone());
+    testtokn.setup_db(None);
+
+    let data = b"firmware image";
+
+    /* generate a pair of token keys */
+    let mut token = open_token(&dbpath);
+    generate_keys(&mut token);
+
+    let sig = sign(&mut token, data);
+    assert_eq!(hss_index(&sig), 0);
+    verify(&mut token, data, &sig);
+    assert_eq!(keys_remaining(&mut token), 1023);
+
+    /* crash after the state is committed, before any signature is
+     * computed, the in memory token is simply thrown away */
+    let op = start_signature(&mut token);
+    drop(op);
+    drop(token);
+
+    let mut token = open_token(&dbpath);
+    assert_eq!(keys_remaining(&mut token), 1022);
+
+    /* crash after the signature is computed, but before it is returned */
+    let mut op = start_signature(&mut token);
+    let mut sig = vec![0u8; ret_or_panic!(op.signature_len())];
+    ret_or_panic!(op.sign(data, &mut sig));
+    drop(op);
+    drop(token);
+
+    /* the one time keys reserved before the crashes are never reused */
+    let mut token = open_token(&dbpath);
+    assert_eq!(keys_remaining(&mut token), 1021);
+    let sig = sign(&mut token, data);
+    assert_eq!(hss_index(&sig), 3);
+    verify(&mut token, data, &sig);
+    assert_eq!(keys_remaining(&mut token), 1020);
+    drop(token);
+
+    testtokn.finalize();
+}
+
+#[cfg(feature = "jsondb")]
+#[test]
+#[parallel]
+fn test_hss_state_json() {
+    let dbpath = format!("{}/{}", TESTDIR, "test_hss_state.json");
+    test_hss_state_common(dbpath)
+}
+
+#[test]
+#[parallel]
+fn test_hss_state_sql() {
+    let dbpath = format!("{}/{}", TESTDIR, "test_hss_state.sql");
+    test_hss_state_common(dbpath)
+}
+
+#[cfg(feature = "jsondb")]
+#[test]
+#[parallel]
+fn test_hss_state_json_save_failures() {
+    let dbpath = format!("{}/{}", TESTDIR, "test_hss_state_save.json");
+    let tmppath = format!("{}.tmp", dbpath);
+    /* in case a previous run was interrupted */
+    let _ = std::fs::remove_dir(&tmppath);
+    let mut testtokn = TestToken::new(dbpath.clone());
+    testtokn.setup_db(None);
+
+    let data = b"firmware image";
+
+    let mut token = open_token(&dbpath);
+    generate_keys(&mut token);
+    let sig = sign(&mut token, data);
+    assert_eq!(hss_index(&sig), 0);
+    drop(token);
+
+    /* a crash while the database is being saved leaves a partially
+     * written temporary file behind, the database itself is intact */
+    let content = ret_or_panic!(std::fs::read(&dbpath));
+    ret_or_panic!(std::fs::write(&tmppath, &content[..content.len() / 2]));
+    let mut token = open_token(&dbpath);
+    assert_eq!(keys_remaining(&mut token), 1023);
+    let sig = sign(&mut token, data);
+    assert_eq!(hss_index(&sig), 1);
+    drop(token);
+
+    /* the new state can not be saved, so the signature is refused */
+    ret_or_panic!(std::fs::create_dir(&tmppath));
+    let mut token = open_token(&dbpath);
+    let mut op = new_signature(&mut token);
+    assert!(token.reserve_sign_state(op.as_mut()).is_err());
+    drop(op);
+    drop(token);
+    ret_or_panic!(std::fs::remove_dir(&tmppath));
+
+    /* the saved state never goes back */
+    let mut token = open_token(&dbpath);
+    assert_eq!(keys_remaining(&mut token), 1022);
+    let sig = sign(&mut token, data);
+    assert_eq!(hss_index(&sig), 2);
+    verify(&mut token, data, &sig);
+    drop(token);
+
+    testtokn.finalize();
+}
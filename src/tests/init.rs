@@ -5,6 +5,8 @@
 use crate::fips::indicators::KRF_FIPS;
 use crate::tests::*;
 
+use std::sync::atomic::{AtomicUsize, Ordering};
+
 use serial_test::{parallel, serial};
 
 #[test]
@@ -289,3 +291,138 @@ fn test_re_init_token_sql() {
     let dbpath = format!("{}/{}", TESTDIR, "test_reinit_token.sql");
     test_re_init_token_common(dbpath)
 }
+
+struct TestMutex {
+    locked: std::sync::Mutex<bool>,
+    cond: std::sync::Condvar,
+}
+
+static MUTEX_CREATED: AtomicUsize = AtomicUsize::new(0);
+static MUTEX_DESTROYED: AtomicUsize = AtomicUsize::new(0);
+static MUTEX_LOCKED: AtomicUsize = AtomicUsize::new(0);
+static MUTEX_UNLOCKED: AtomicUsize = AtomicUsize::new(0);
+
+extern "C" fn test_create_mutex(mutex: *mut CK_VOID_PTR) -> CK_RV {
+    let m = Box::new(TestMutex {
+        locked: std::sync::Mutex::new(false),
+        cond: std::sync::Condvar::new(),
+    });
+    unsafe { *mutex = Box::into_raw(m) as CK_VOID_PTR };
+    MUTEX_CREATED.fetch_add(1, Ordering::SeqCst);
+    CKR_OK
+}
+
+extern "C" fn test_create_mutex_fail(_mutex: *mut CK_VOID_PTR) -> CK_RV {
+    CKR_HOST_MEMORY
+}
+
+extern "C" fn test_destroy_mutex(mutex: CK_VOID_PTR) -> CK_RV {
+    drop(unsafe { Box::from_raw(mutex as *mut TestMutex) });
+    MUTEX_DESTROYED.fetch_add(1, Ordering::SeqCst);
+    CKR_OK
+}
+
+extern "C" fn test_lock_mutex(mutex: CK_VOID_PTR) -> CK_RV {
+    let m = unsafe { &*(mutex as *const TestMutex) };
+    let mut locked = m.locked.lock().unwrap();
+    while *locked {
+        locked = m.cond.wait(locked).unwrap();
+    }
+    *locked = true;
+    MUTEX_LOCKED.fetch_add(1, Ordering::SeqCst);
+    CKR_OK
+}
+
+extern "C" fn test_unlock_mutex(mutex: CK_VOID_PTR) -> CK_RV {
+    let m = unsafe { &*(mutex as *const TestMutex) };
+    let mut locked = m.locked.lock().unwrap();
+    if !*locked {
+        return CKR_MUTEX_NOT_LOCKED;
+    }
+    *locked = false;
+    m.cond.notify_one();
+    MUTEX_UNLOCKED.fetch_add(1, Ordering::SeqCst);
+    CKR_OK
+}
+
+#[test]
+#[serial]
+fn test_init_locking() {
+    let mut testtokn = TestToken::initialized("test_init_locking.sql", None);
+
+    let mut args = CK_C_INITIALIZE_ARGS {
+        CreateMutex: Some(test_create_mutex),
+        DestroyMutex: None,
+        LockMutex: None,
+        UnlockMutex: None,
+        flags: 0,
+        pReserved: std::ptr::null_mut(),
+    };
+    /* a partial set of callbacks is invalid */
+    let ret = fn_initialize(void_ptr!(&mut args));
+    assert_eq!(ret, CKR_ARGUMENTS_BAD);
+
+    /* the locking model is set by the first initialization only */
+    let ret = fn_finalize(std::ptr::null_mut());
+    assert_eq!(ret, CKR_OK);
+
+    /* native locking is used when allowed */
+    args.DestroyMutex = Some(test_destroy_mutex);
+    args.LockMutex = Some(test_lock_mutex);
+    args.UnlockMutex = Some(test_unlock_mutex);
+    args.flags = CKF_OS_LOCKING_OK;
+    let ret = fn_initialize(void_ptr!(&mut args));
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(MUTEX_CREATED.load(Ordering::SeqCst), 0);
+    assert!(lock::os_threads_allowed());
+    let ret = fn_finalize(std::ptr::null_mut());
+    assert_eq!(ret, CKR_OK);
+
+    /* failure to create the application mutex */
+    args.CreateMutex = Some(test_create_mutex_fail);
+    args.flags = 0;
+    let ret = fn_initialize(void_ptr!(&mut args));
+    assert_eq!(ret, CKR_CANT_LOCK);
+
+    /* application locking */
+    args.CreateMutex = Some(test_create_mutex);
+    args.flags = CKF_LIBRARY_CANT_CREATE_OS_THREADS;
+    let ret = fn_initialize(void_ptr!(&mut args));
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(MUTEX_CREATED.load(Ordering::SeqCst), 1);
+    assert!(!lock::os_threads_allowed());
+
+    /* initializing again does not change the locking */
+    args.CreateMutex = Some(test_create_mutex_fail);
+    let ret = fn_initialize(void_ptr!(&mut args));
+    assert_eq!(ret, CKR_OK);
+    args.CreateMutex = Some(test_create_mutex);
+    let ret = fn_initialize(void_ptr!(&mut args));
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(MUTEX_CREATED.load(Ordering::SeqCst), 1);
+    assert!(!lock::os_threads_allowed());
+
+    let locked = MUTEX_LOCKED.load(Ordering::SeqCst);
+    let session = testtokn.get_session(false);
+    let mut info = CK_SESSION_INFO::default();
+    let ret = fn_get_session_info(session, &mut info);
+    assert_eq!(ret, CKR_OK);
+    assert!(MUTEX_LOCKED.load(Ordering::SeqCst) > locked);
+    assert_eq!(
+        MUTEX_LOCKED.load(Ordering::SeqCst),
+        MUTEX_UNLOCKED.load(Ordering::SeqCst)
+    );
+    testtokn.close_session();
+
+    /* the mutex is destroyed on finalization */
+    let ret = fn_finalize(std::ptr::null_mut());
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(MUTEX_DESTROYED.load(Ordering::SeqCst), 1);
+    assert!(lock::os_threads_allowed());
+
+    let mut args = TestToken::make_init_args(Some(testtokn.make_init_string()));
+    let ret = fn_initialize(void_ptr!(&mut args));
+    assert_eq!(ret, CKR_OK);
+
+    testtokn.finalize();
+}
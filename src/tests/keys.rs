@@ -79,6 +79,116 @@ fn test_secret_key() {
     testtokn.finalize();
 }
 
+#[test]
+#[parallel]
+fn test_key_check_value() {
+    let mut testtokn = TestToken::initialized("test_key_check_value.sql", None);
+    let session = testtokn.get_session(true);
+
+    /* login */
+    testtokn.login();
+
+    let zeros = [0u8; 16];
+
+    /* AES: first 3 bytes of the encryption of a zero block */
+    let handle = ret_or_panic!(import_object(
+        session,
+        CKO_SECRET_KEY,
+        &[(CKA_KEY_TYPE, CKK_AES)],
+        &[(CKA_VALUE, &zeros)],
+        &[(CKA_ENCRYPT, true)],
+    ));
+    if let Some(err) = check_attributes(
+        session,
+        handle,
+        &[],
+        &[(CKA_CHECK_VALUE, &[0x66, 0xe9, 0x4b])],
+        &[],
+    ) {
+        panic!("{}", err);
+    }
+
+    /* AES-XTS: same as AES, using the data key */
+    let mut xts = [0u8; 32];
+    xts[16..].fill(0x01);
+    let handle = ret_or_panic!(import_object(
+        session,
+        CKO_SECRET_KEY,
+        &[(CKA_KEY_TYPE, CKK_AES_XTS)],
+        &[(CKA_VALUE, &xts)],
+        &[(CKA_ENCRYPT, true)],
+    ));
+    if let Some(err) = check_attributes(
+        session,
+        handle,
+        &[],
+        &[(CKA_CHECK_VALUE, &[0x66, 0xe9, 0x4b])],
+        &[],
+    ) {
+        panic!("{}", err);
+    }
+
+    /* Generic Secret: first 3 bytes of the SHA-1 of the value */
+    let handle = ret_or_panic!(import_object(
+        session,
+        CKO_SECRET_KEY,
+        &[(CKA_KEY_TYPE, CKK_GENERIC_SECRET)],
+        &[(CKA_VALUE, &zeros)],
+        &[(CKA_DERIVE, true)],
+    ));
+    if let Some(err) = check_attributes(
+        session,
+        handle,
+        &[],
+        &[(CKA_CHECK_VALUE, &[0xe1, 0x29, 0xf2])],
+        &[],
+    ) {
+        panic!("{}", err);
+    }
+
+    /* a matching check value is accepted */
+    let _ = ret_or_panic!(import_object(
+        session,
+        CKO_SECRET_KEY,
+        &[(CKA_KEY_TYPE, CKK_AES)],
+        &[(CKA_VALUE, &zeros), (CKA_CHECK_VALUE, &[0x66, 0xe9, 0x4b])],
+        &[(CKA_ENCRYPT, true)],
+    ));
+
+    /* a mismatching one is not */
+    let err = import_object(
+        session,
+        CKO_SECRET_KEY,
+        &[(CKA_KEY_TYPE, CKK_AES)],
+        &[(CKA_VALUE, &zeros), (CKA_CHECK_VALUE, &[0x00, 0x01, 0x02])],
+        &[(CKA_ENCRYPT, true)],
+    )
+    .unwrap_err();
+    assert_eq!(err.rv(), CKR_ATTRIBUTE_VALUE_INVALID);
+
+    /* generated keys get a check value too */
+    let handle = ret_or_panic!(generate_key(
+        session,
+        CKM_AES_KEY_GEN,
+        std::ptr::null_mut(),
+        0,
+        &[(CKA_KEY_TYPE, CKK_AES), (CKA_VALUE_LEN, 16)],
+        &[],
+        &[(CKA_ENCRYPT, true)],
+    ));
+    let mut value = [0u8; 3];
+    let mut template = make_ptrs_template(&[(
+        CKA_CHECK_VALUE,
+        void_ptr!(value.as_mut_ptr()),
+        3,
+    )]);
+    let ret = fn_get_attribute_value(session, handle, template.as_mut_ptr(), 1);
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(template[0].ulValueLen, 3);
+
+    testtokn.finalize();
+}
+
 #[cfg(feature = "rsa")]
 #[test]
 #[parallel]
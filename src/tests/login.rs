@@ -146,3 +146,228 @@ fn test_login_json() {
 fn test_login_sql() {
     test_login("test_login.sql");
 }
+
+fn login_user(session: CK_SESSION_HANDLE, name: &str, pin: &str) -> CK_RV {
+    fn_login_user(
+        session,
+        CKU_USER,
+        pin.as_ptr() as *mut _,
+        pin.len() as CK_ULONG,
+        name.as_ptr() as *mut _,
+        name.len() as CK_ULONG,
+    )
+}
+
+fn create_user(session: CK_SESSION_HANDLE, name: &str, pin: &str) -> CK_RV {
+    KR_CreateUser(
+        session,
+        name.as_ptr() as *mut _,
+        name.len() as CK_ULONG,
+        pin.as_ptr() as *mut _,
+        pin.len() as CK_ULONG,
+    )
+}
+
+fn count_objects(session: CK_SESSION_HANDLE) -> CK_ULONG {
+    let mut template: [CK_ATTRIBUTE; 0] = [];
+    let ret = fn_find_objects_init(session, template.as_mut_ptr(), 0);
+    assert_eq!(ret, CKR_OK);
+    let mut handles = [CK_INVALID_HANDLE; 100];
+    let mut count: CK_ULONG = 0;
+    let ret = fn_find_objects(
+        session,
+        handles.as_mut_ptr(),
+        handles.len() as CK_ULONG,
+        &mut count,
+    );
+    assert_eq!(ret, CKR_OK);
+    let ret = fn_find_objects_final(session);
+    assert_eq!(ret, CKR_OK);
+    count
+}
+
+fn test_login_user(name: &str) {
+    let mut testtokn = TestToken::initialized(name, None);
+    let session = testtokn.get_session(true);
+
+    /* only the SO can manage users */
+    let ret = create_user(session, "alice", "alice pin");
+    assert_eq!(ret, CKR_USER_NOT_LOGGED_IN);
+
+    let ret = fn_login(
+        session,
+        CKU_SO,
+        SO_PIN.as_ptr() as *mut _,
+        SO_PIN.len() as CK_ULONG,
+    );
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(create_user(session, "alice", "alice pin"), CKR_OK);
+    assert_eq!(create_user(session, "bob", "bob pin"), CKR_OK);
+    assert_eq!(fn_logout(session), CKR_OK);
+
+    testtokn.login();
+    let objects = count_objects(session);
+    assert_eq!(fn_logout(session), CKR_OK);
+
+    let ret = login_user(session, "alice", "bad pin");
+    assert_eq!(ret, CKR_PIN_INCORRECT);
+    let ret = login_user(session, "carol", "alice pin");
+    assert_eq!(ret, CKR_USER_PIN_NOT_INITIALIZED);
+
+    let ret = login_user(session, "alice", "alice pin");
+    assert_eq!(ret, CKR_OK);
+    let mut info = CK_SESSION_INFO {
+        slotID: CK_UNAVAILABLE_INFORMATION,
+        state: CK_UNAVAILABLE_INFORMATION,
+        flags: 0,
+        ulDeviceError: 0,
+    };
+    let ret = fn_get_session_info(session, &mut info);
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(info.state, CKS_RW_USER_FUNCTIONS);
+
+    /* users' auth objects are never returned */
+    assert_eq!(count_objects(session), objects);
+
+    let ret = login_user(session, "alice", "alice pin");
+    assert_eq!(ret, CKR_USER_ALREADY_LOGGED_IN);
+    let ret = login_user(session, "bob", "bob pin");
+    assert_eq!(ret, CKR_USER_ANOTHER_ALREADY_LOGGED_IN);
+
+    /* a named user changes its own pin */
+    let old = "alice pin";
+    let new = "new alice pin";
+    let ret = fn_set_pin(
+        session,
+        old.as_ptr() as *mut _,
+        old.len() as CK_ULONG,
+        new.as_ptr() as *mut _,
+        new.len() as CK_ULONG,
+    );
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(fn_logout(session), CKR_OK);
+    let ret = login_user(session, "alice", old);
+    assert_eq!(ret, CKR_PIN_INCORRECT);
+    let ret = login_user(session, "alice", new);
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(fn_logout(session), CKR_OK);
+
+    /* each user has its own lockout counter */
+    for _ in 0..10 {
+        let ret = login_user(session, "bob", "bad pin");
+        assert_eq!(ret, CKR_PIN_INCORRECT);
+    }
+    let ret = login_user(session, "bob", "bob pin");
+    assert_eq!(ret, CKR_PIN_LOCKED);
+
+    let mut token_info = CK_TOKEN_INFO::default();
+    let ret = fn_get_token_info(testtokn.get_slot(), &mut token_info);
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(token_info.flags & CKF_USER_PIN_LOCKED, 0);
+
+    let ret = login_user(session, "alice", new);
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(fn_logout(session), CKR_OK);
+
+    /* the SO resets or removes users */
+    let ret = fn_login(
+        session,
+        CKU_SO,
+        SO_PIN.as_ptr() as *mut _,
+        SO_PIN.len() as CK_ULONG,
+    );
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(create_user(session, "bob", "bob pin 2"), CKR_OK);
+    let ret = KR_RemoveUser(session, "alice".as_ptr() as *mut _, 5);
+    assert_eq!(ret, CKR_OK);
+    let ret = KR_RemoveUser(session, "alice".as_ptr() as *mut _, 5);
+    assert_eq!(ret, CKR_ARGUMENTS_BAD);
+    assert_eq!(fn_logout(session), CKR_OK);
+
+    let ret = login_user(session, "alice", new);
+    assert_eq!(ret, CKR_USER_PIN_NOT_INITIALIZED);
+
+    /* users are persisted in the database */
+    let mut token = Token::new(
+        storage::suffix_to_type(&testtokn.filename).unwrap(),
+        Some(testtokn.filename.clone()),
+    )
+    .unwrap();
+    assert_eq!(
+        token.login_user(CKU_USER, Some("bob"), b"bob pin 2"),
+        CKR_OK
+    );
+
+    testtokn.finalize();
+}
+
+#[cfg(feature = "jsondb")]
+#[test]
+#[parallel]
+fn test_login_user_json() {
+    test_login_user("test_login_user.json");
+}
+
+#[test]
+#[parallel]
+fn test_login_user_sql() {
+    test_login_user("test_login_user.sql");
+}
+
+/* Named users must be able to log in and out of tokens that have
+ * no default user PIN set */
+fn test_login_user_no_default(name: &str, nodefault: &str) {
+    let mut testtokn = TestToken::initialized(name, None);
+
+    let so_pin = SO_PIN.as_bytes();
+    let mut label = TOKEN_LABEL.as_bytes().to_vec();
+    label.resize(32, 0x20);
+    let dbpath = format!("{}/{}", TESTDIR, nodefault);
+    let mut token = Token::new(
+        storage::suffix_to_type(&dbpath).unwrap(),
+        Some(dbpath.clone()),
+    )
+    .unwrap();
+    token.initialize(so_pin, &label).unwrap();
+    assert_eq!(token.login(CKU_SO, so_pin), CKR_OK);
+    token.add_user("alice", b"alice pin").unwrap();
+    assert_eq!(token.logout(), CKR_OK);
+
+    assert_eq!(
+        token.login_user(CKU_USER, Some("alice"), b"alice pin"),
+        CKR_OK
+    );
+    /* a logged in user can't be removed */
+    assert_eq!(
+        token.remove_user("alice").unwrap_err().rv(),
+        CKR_USER_ALREADY_LOGGED_IN
+    );
+    assert_eq!(token.logout(), CKR_OK);
+
+    assert_eq!(
+        token.login_user(CKU_USER, Some("alice"), b"alice pin"),
+        CKR_OK
+    );
+    assert_eq!(token.logout(), CKR_OK);
+
+    testtokn.finalize();
+}
+
+#[cfg(feature = "jsondb")]
+#[test]
+#[parallel]
+fn test_login_user_no_default_json() {
+    test_login_user_no_default(
+        "test_login_user_no_default.json",
+        "test_login_user_nodefault.json",
+    );
+}
+
+#[test]
+#[parallel]
+fn test_login_user_no_default_sql() {
+    test_login_user_no_default(
+        "test_login_user_no_default.sql",
+        "test_login_user_nodefault.sql",
+    );
+}
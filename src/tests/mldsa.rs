@@ -0,0 +1,588 @@
+// Copyright 2024 Simo Sorce
+// See LICENSE.txt file for terms
+
+use std::io;
+use std::io::BufRead;
+
+use crate::tests::*;
+
+use serial_test::parallel;
+
+fn digest(
+    session: CK_SESSION_HANDLE,
+    mech: CK_MECHANISM_TYPE,
+    data: &[u8],
+) -> Vec<u8> {
+    let mut mechanism = CK_MECHANISM {
+        mechanism: mech,
+        pParameter: std::ptr::null_mut(),
+        ulParameterLen: 0,
+    };
+    let ret = fn_digest_init(session, &mut mechanism);
+    assert_eq!(ret, CKR_OK);
+    let mut digest = vec![0u8; 64];
+    let mut digest_len = digest.len() as CK_ULONG;
+    let ret = fn_digest(
+        session,
+        byte_ptr!(data),
+        data.len() as CK_ULONG,
+        digest.as_mut_ptr(),
+        &mut digest_len,
+    );
+    assert_eq!(ret, CKR_OK);
+    digest.truncate(digest_len as usize);
+    digest
+}
+
+#[test]
+#[parallel]
+fn test_mldsa_operations() {
+    let mut testtokn =
+        TestToken::initialized("test_mldsa_operations.sql", None);
+    let session = testtokn.get_session(true);
+
+    /* login */
+    testtokn.login();
+
+    let data = b"plaintext";
+    let mechanism = CK_MECHANISM {
+        mechanism: CKM_ML_DSA,
+        pParameter: std::ptr::null_mut(),
+        ulParameterLen: 0,
+    };
+
+    for (param_set, siglen) in [
+        (CKP_ML_DSA_44, 2420),
+        (CKP_ML_DSA_65, 3309),
+        (CKP_ML_DSA_87, 4627),
+    ] {
+        let (pubkey, privkey) = ret_or_panic!(generate_key_pair(
+            session,
+            CKM_ML_DSA_KEY_PAIR_GEN,
+            &[(CKA_PARAMETER_SET, param_set)],
+            &[],
+            &[(CKA_TOKEN, true), (CKA_VERIFY, true)],
+            &[],
+            &[],
+            &[
+                (CKA_TOKEN, true),
+                (CKA_PRIVATE, true),
+                (CKA_SENSITIVE, true),
+                (CKA_SIGN, true),
+            ],
+        ));
+        if let Some(err) = check_attributes(
+            session,
+            privkey,
+            &[(CKA_PARAMETER_SET, param_set)],
+            &[],
+            &[(CKA_LOCAL, true)],
+        ) {
+            panic!("{}", err);
+        }
+
+        let sig = ret_or_panic!(sig_gen(session, privkey, data, &mechanism));
+        assert_eq!(sig.len(), siglen);
+        assert_eq!(CKR_OK, sig_verify(session, pubkey, data, &sig, &mechanism));
+        assert_eq!(
+            CKR_SIGNATURE_INVALID,
+            sig_verify(session, pubkey, b"different", &sig, &mechanism)
+        );
+    }
+
+    let (pubkey, privkey) = ret_or_panic!(generate_key_pair(
+        session,
+        CKM_ML_DSA_KEY_PAIR_GEN,
+        &[(CKA_PARAMETER_SET, CKP_ML_DSA_65)],
+        &[],
+        &[(CKA_VERIFY, true)],
+        &[],
+        &[],
+        &[(CKA_SIGN, true)],
+    ));
+
+    /* multi-part */
+    let sig = ret_or_panic!(sig_gen_multipart(
+        session,
+        privkey,
+        &data.to_vec(),
+        &mechanism
+    ));
+    assert_eq!(CKR_OK, sig_verify(session, pubkey, data, &sig, &mechanism));
+
+    /* context string */
+    let mut context = b"context".to_vec();
+    let mut params = CK_SIGN_ADDITIONAL_CONTEXT {
+        hedgeVariant: CKH_HEDGE_PREFERRED,
+        pContext: context.as_mut_ptr(),
+        ulContextLen: context.len() as CK_ULONG,
+    };
+    let ctx_mechanism = CK_MECHANISM {
+        mechanism: CKM_ML_DSA,
+        pParameter: &mut params as *mut _ as CK_VOID_PTR,
+        ulParameterLen: sizeof!(CK_SIGN_ADDITIONAL_CONTEXT),
+    };
+    let sig = ret_or_panic!(sig_gen(session, privkey, data, &ctx_mechanism));
+    assert_eq!(
+        CKR_OK,
+        sig_verify(session, pubkey, data, &sig, &ctx_mechanism)
+    );
+    /* a different context must not verify */
+    assert_eq!(
+        CKR_SIGNATURE_INVALID,
+        sig_verify(session, pubkey, data, &sig, &mechanism)
+    );
+
+    /* deterministic signatures */
+    let mut det_params = CK_SIGN_ADDITIONAL_CONTEXT {
+        hedgeVariant: CKH_DETERMINISTIC_REQUIRED,
+        pContext: context.as_mut_ptr(),
+        ulContextLen: context.len() as CK_ULONG,
+    };
+    let det_mechanism = CK_MECHANISM {
+        mechanism: CKM_ML_DSA,
+        pParameter: &mut det_params as *mut _ as CK_VOID_PTR,
+        ulParameterLen: sizeof!(CK_SIGN_ADDITIONAL_CONTEXT),
+    };
+    let sig1 = ret_or_panic!(sig_gen(session, privkey, data, &det_mechanism));
+    let sig2 = ret_or_panic!(sig_gen(session, privkey, data, &det_mechanism));
+    assert_eq!(sig1, sig2);
+    assert_eq!(
+        CKR_OK,
+        sig_verify(session, pubkey, data, &sig1, &ctx_mechanism)
+    );
+
+    /* HashML-DSA, the generic mechanism signs a precomputed hash and is
+     * interoperable with the hashing variant */
+    let hash_mechanism = CK_MECHANISM {
+        mechanism: CKM_HASH_ML_DSA_SHA256,
+        pParameter: std::ptr::null_mut(),
+        ulParameterLen: 0,
+    };
+    let sig = ret_or_panic!(sig_gen(session, privkey, data, &hash_mechanism));
+    assert_eq!(
+        CKR_OK,
+        sig_verify(session, pubkey, data, &sig, &hash_mechanism)
+    );
+    /* pure and pre-hash signatures are domain separated */
+    assert_eq!(
+        CKR_SIGNATURE_INVALID,
+        sig_verify(session, pubkey, data, &sig, &mechanism)
+    );
+
+    let digest = digest(session, CKM_SHA256, data);
+    let mut hash_params = CK_HASH_SIGN_ADDITIONAL_CONTEXT {
+        hedgeVariant: CKH_HEDGE_PREFERRED,
+        pContext: std::ptr::null_mut(),
+        ulContextLen: 0,
+        hash: CKM_SHA256,
+    };
+    let prehash_mechanism = CK_MECHANISM {
+        mechanism: CKM_HASH_ML_DSA,
+        pParameter: &mut hash_params as *mut _ as CK_VOID_PTR,
+        ulParameterLen: sizeof!(CK_HASH_SIGN_ADDITIONAL_CONTEXT),
+    };
+    assert_eq!(
+        CKR_OK,
+        sig_verify(session, pubkey, &digest, &sig, &prehash_mechanism)
+    );
+    let sig =
+        ret_or_panic!(sig_gen(session, privkey, &digest, &prehash_mechanism));
+    assert_eq!(
+        CKR_OK,
+        sig_verify(session, pubkey, data, &sig, &hash_mechanism)
+    );
+
+    /* the hash must have the right length */
+    let err = sig_gen(session, privkey, data, &prehash_mechanism);
+    assert_eq!(err.unwrap_err().rv(), CKR_DATA_LEN_RANGE);
+
+    /* the generic mechanism requires parameters */
+    let ret = fn_sign_init(
+        session,
+        &CK_MECHANISM {
+            mechanism: CKM_HASH_ML_DSA,
+            pParameter: std::ptr::null_mut(),
+            ulParameterLen: 0,
+        } as *const _ as CK_MECHANISM_PTR,
+        privkey,
+    );
+    assert_eq!(ret, CKR_ARGUMENTS_BAD);
+
+    testtokn.finalize();
+}
+
+#[test]
+#[parallel]
+fn test_mldsa_verify_signature() {
+    let mut testtokn =
+        TestToken::initialized("test_mldsa_verify_signature.sql", None);
+    let session = testtokn.get_session(false);
+
+    /* login */
+    testtokn.login();
+
+    let (pubkey, privkey) = ret_or_panic!(generate_key_pair(
+        session,
+        CKM_ML_DSA_KEY_PAIR_GEN,
+        &[(CKA_PARAMETER_SET, CKP_ML_DSA_44)],
+        &[],
+        &[(CKA_VERIFY, true)],
+        &[],
+        &[],
+        &[(CKA_PRIVATE, true), (CKA_SENSITIVE, true), (CKA_SIGN, true)],
+    ));
+
+    let data = b"plaintext";
+    let mut mechanism = CK_MECHANISM {
+        mechanism: CKM_ML_DSA,
+        pParameter: std::ptr::null_mut(),
+        ulParameterLen: 0,
+    };
+    let mut sig = ret_or_panic!(sig_gen(session, privkey, data, &mechanism));
+
+    /* single part */
+    let ret = fn_verify_signature_init(
+        session,
+        &mut mechanism,
+        pubkey,
+        sig.as_mut_ptr(),
+        sig.len() as CK_ULONG,
+    );
+    assert_eq!(ret, CKR_OK);
+    let ret = fn_verify_signature(
+        session,
+        byte_ptr!(data.as_ptr()),
+        data.len() as CK_ULONG,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    /* multi part */
+    let ret = fn_verify_signature_init(
+        session,
+        &mut mechanism,
+        pubkey,
+        sig.as_mut_ptr(),
+        sig.len() as CK_ULONG,
+    );
+    assert_eq!(ret, CKR_OK);
+    for part in [&data[..5], &data[5..]] {
+        let ret = fn_verify_signature_update(
+            session,
+            byte_ptr!(part.as_ptr()),
+            part.len() as CK_ULONG,
+        );
+        assert_eq!(ret, CKR_OK);
+    }
+    let ret = fn_verify_signature_final(session);
+    assert_eq!(ret, CKR_OK);
+
+    /* the signature length is checked on initialization */
+    let ret = fn_verify_signature_init(
+        session,
+        &mut mechanism,
+        pubkey,
+        sig.as_mut_ptr(),
+        (sig.len() - 1) as CK_ULONG,
+    );
+    assert_eq!(ret, CKR_SIGNATURE_LEN_RANGE);
+
+    /* the operation can not be used with C_Verify */
+    let ret = fn_verify_signature_init(
+        session,
+        &mut mechanism,
+        pubkey,
+        sig.as_mut_ptr(),
+        sig.len() as CK_ULONG,
+    );
+    assert_eq!(ret, CKR_OK);
+    let ret = fn_verify(
+        session,
+        byte_ptr!(data.as_ptr()),
+        data.len() as CK_ULONG,
+        sig.as_mut_ptr(),
+        sig.len() as CK_ULONG,
+    );
+    assert_eq!(ret, CKR_OPERATION_NOT_INITIALIZED);
+
+    /* a bad signature */
+    sig[0] ^= 0x01;
+    let ret = fn_verify_signature_init(
+        session,
+        std::ptr::null_mut(),
+        CK_INVALID_HANDLE,
+        std::ptr::null_mut(),
+        0,
+    );
+    assert_eq!(ret, CKR_OK);
+    let ret = fn_verify_signature_init(
+        session,
+        &mut mechanism,
+        pubkey,
+        sig.as_mut_ptr(),
+        sig.len() as CK_ULONG,
+    );
+    assert_eq!(ret, CKR_OK);
+    let ret = fn_verify_signature(
+        session,
+        byte_ptr!(data.as_ptr()),
+        data.len() as CK_ULONG,
+    );
+    assert_eq!(ret, CKR_SIGNATURE_INVALID);
+
+    testtokn.finalize();
+}
+
+#[test]
+#[parallel]
+fn test_mldsa_key_wrap() {
+    let mut testtokn = TestToken::initialized("test_mldsa_key_wrap.sql", None);
+    let session = testtokn.get_session(true);
+
+    /* login */
+    testtokn.login();
+
+    let (pubkey, privkey) = ret_or_panic!(generate_key_pair(
+        session,
+        CKM_ML_DSA_KEY_PAIR_GEN,
+        &[(CKA_PARAMETER_SET, CKP_ML_DSA_44)],
+        &[],
+        &[(CKA_VERIFY, true)],
+        &[],
+        &[],
+        &[
+            (CKA_PRIVATE, true),
+            (CKA_SENSITIVE, true),
+            (CKA_SIGN, true),
+            (CKA_EXTRACTABLE, true),
+        ],
+    ));
+
+    /* Wrap ML-DSA key in AES */
+    let handle = ret_or_panic!(generate_key(
+        session,
+        CKM_AES_KEY_GEN,
+        std::ptr::null_mut(),
+        0,
+        &[(CKA_VALUE_LEN, 32)],
+        &[],
+        &[(CKA_WRAP, true), (CKA_UNWRAP, true)],
+    ));
+
+    let mut mechanism: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_AES_KEY_WRAP_KWP,
+        pParameter: std::ptr::null_mut(),
+        ulParameterLen: 0,
+    };
+
+    let mut wrapped = vec![0u8; 65536];
+    let mut wrapped_len = wrapped.len() as CK_ULONG;
+
+    let mut ret = fn_wrap_key(
+        session,
+        &mut mechanism,
+        handle,
+        privkey,
+        wrapped.as_mut_ptr(),
+        &mut wrapped_len,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    /* the parameter set is recovered from the PKCS#8 encoding */
+    let mut pri_template = make_attr_template(
+        &[(CKA_CLASS, CKO_PRIVATE_KEY), (CKA_KEY_TYPE, CKK_ML_DSA)],
+        &[],
+        &[(CKA_PRIVATE, true), (CKA_SENSITIVE, true), (CKA_SIGN, true)],
+    );
+
+    let mut privkey2 = CK_INVALID_HANDLE;
+    ret = fn_unwrap_key(
+        session,
+        &mut mechanism,
+        handle,
+        wrapped.as_mut_ptr(),
+        wrapped_len,
+        pri_template.as_mut_ptr(),
+        pri_template.len() as CK_ULONG,
+        &mut privkey2,
+    );
+    assert_eq!(ret, CKR_OK);
+    if let Some(err) = check_attributes(
+        session,
+        privkey2,
+        &[(CKA_PARAMETER_SET, CKP_ML_DSA_44)],
+        &[],
+        &[],
+    ) {
+        panic!("{}", err);
+    }
+
+    /* Test the unwrapped key can be used */
+    let data = b"plaintext";
+    let mechanism = CK_MECHANISM {
+        mechanism: CKM_ML_DSA,
+        pParameter: std::ptr::null_mut(),
+        ulParameterLen: 0,
+    };
+    let sig = ret_or_panic!(sig_gen(session, privkey2, data, &mechanism));
+
+    /* And signature verified by the original public key */
+    assert_eq!(CKR_OK, sig_verify(session, pubkey, data, &sig, &mechanism));
+
+    testtokn.finalize();
+}
+
+#[derive(Debug)]
+struct SigVerTestUnit {
+    line: usize,
+    count: usize,
+    param_set: CK_ML_DSA_PARAMETER_SET_TYPE,
+    hash: CK_MECHANISM_TYPE,
+    mech: CK_MECHANISM_TYPE,
+    pk: Vec<u8>,
+    msg: Vec<u8>,
+    ctx: Vec<u8>,
+    sig: Vec<u8>,
+    result: bool,
+}
+
+fn parse_value<'a>(line: &'a str, ln: usize, p: &str) -> Option<&'a str> {
+    match line.strip_prefix(p) {
+        Some(v) => match v.strip_prefix(" = ") {
+            Some(v) => Some(v),
+            None => panic!("Malformed line: {} (line {})", line, ln),
+        },
+        None => None,
+    }
+}
+
+fn parse_sigver_vector(filename: &str) -> Vec<SigVerTestUnit> {
+    let file = ret_or_panic!(std::fs::File::open(filename));
+
+    let mut data = Vec::<SigVerTestUnit>::new();
+
+    for (l, line) in io::BufReader::new(file)
+        .lines()
+        .map_while(std::result::Result::ok)
+        .enumerate()
+    {
+        let ln = l + 1;
+        if line.starts_with("#") || line.is_empty() {
+            continue;
+        }
+
+        if let Some(v) = parse_value(&line, ln, "Count") {
+            data.push(SigVerTestUnit {
+                line: ln,
+                count: parse_or_panic!(v.parse(); line; ln),
+                param_set: CK_UNAVAILABLE_INFORMATION,
+                hash: CK_UNAVAILABLE_INFORMATION,
+                mech: CK_UNAVAILABLE_INFORMATION,
+                pk: Vec::new(),
+                msg: Vec::new(),
+                ctx: Vec::new(),
+                sig: Vec::new(),
+                result: false,
+            });
+            continue;
+        }
+
+        let unit = match data.last_mut() {
+            Some(u) => u,
+            None => panic!("No unit defined (line {})", ln),
+        };
+
+        if let Some(v) = parse_value(&line, ln, "ParameterSet") {
+            unit.param_set = match v {
+                "ML-DSA-44" => CKP_ML_DSA_44,
+                "ML-DSA-65" => CKP_ML_DSA_65,
+                "ML-DSA-87" => CKP_ML_DSA_87,
+                _ => panic!("Unknown parameter set: {} (line {})", v, ln),
+            };
+        } else if let Some(v) = parse_value(&line, ln, "HashAlg") {
+            (unit.hash, unit.mech) = match v {
+                "SHA2-256" => (CKM_SHA256, CKM_HASH_ML_DSA_SHA256),
+                "SHA2-512" => (CKM_SHA512, CKM_HASH_ML_DSA_SHA512),
+                "SHA3-256" => (CKM_SHA3_256, CKM_HASH_ML_DSA_SHA3_256),
+                _ => panic!("Unknown hash: {} (line {})", v, ln),
+            };
+        } else if let Some(v) = parse_value(&line, ln, "pk") {
+            unit.pk = parse_or_panic!(hex::decode(v); line; ln);
+        } else if let Some(v) = parse_value(&line, ln, "msg") {
+            unit.msg = parse_or_panic!(hex::decode(v); line; ln);
+        } else if let Some(v) = parse_value(&line, ln, "ctx") {
+            unit.ctx = parse_or_panic!(hex::decode(v); line; ln);
+        } else if let Some(v) = parse_value(&line, ln, "sig") {
+            unit.sig = parse_or_panic!(hex::decode(v); line; ln);
+        } else if let Some(v) = parse_value(&line, ln, "Result") {
+            unit.result = v == "P";
+        }
+    }
+
+    data
+}
+
+#[test]
+#[parallel]
+fn test_mldsa_sigver_vector() {
+    let test_data = parse_sigver_vector("testdata/ml_dsa_sigver.rsp");
+
+    let mut testtokn = TestToken::initialized("test_mldsa_sigver.sql", None);
+    let session = testtokn.get_session(false);
+
+    /* login */
+    testtokn.login();
+
+    for unit in test_data {
+        println!("Executing test at line {}", unit.line);
+
+        let handle = ret_or_panic!(import_object(
+            session,
+            CKO_PUBLIC_KEY,
+            &[
+                (CKA_KEY_TYPE, CKK_ML_DSA),
+                (CKA_PARAMETER_SET, unit.param_set)
+            ],
+            &[(CKA_VALUE, &unit.pk)],
+            &[(CKA_VERIFY, true)],
+        ));
+
+        let expected = if unit.result {
+            CKR_OK
+        } else {
+            CKR_SIGNATURE_INVALID
+        };
+
+        /* the hashing variant */
+        let mut params = CK_SIGN_ADDITIONAL_CONTEXT {
+            hedgeVariant: CKH_HEDGE_PREFERRED,
+            pContext: unit.ctx.as_ptr() as *mut CK_BYTE,
+            ulContextLen: unit.ctx.len() as CK_ULONG,
+        };
+        let mechanism = CK_MECHANISM {
+            mechanism: unit.mech,
+            pParameter: &mut params as *mut _ as CK_VOID_PTR,
+            ulParameterLen: sizeof!(CK_SIGN_ADDITIONAL_CONTEXT),
+        };
+        let ret = sig_verify(session, handle, &unit.msg, &unit.sig, &mechanism);
+        assert_eq!(ret, expected, "Count = {}", unit.count);
+
+        /* and the generic mechanism on a precomputed hash */
+        let hash = digest(session, unit.hash, &unit.msg);
+        let mut hash_params = CK_HASH_SIGN_ADDITIONAL_CONTEXT {
+            hedgeVariant: CKH_HEDGE_PREFERRED,
+            pContext: unit.ctx.as_ptr() as *mut CK_BYTE,
+            ulContextLen: unit.ctx.len() as CK_ULONG,
+            hash: unit.hash,
+        };
+        let mechanism = CK_MECHANISM {
+            mechanism: CKM_HASH_ML_DSA,
+            pParameter: &mut hash_params as *mut _ as CK_VOID_PTR,
+            ulParameterLen: sizeof!(CK_HASH_SIGN_ADDITIONAL_CONTEXT),
+        };
+        let ret = sig_verify(session, handle, &hash, &unit.sig, &mechanism);
+        assert_eq!(ret, expected, "Count = {}", unit.count);
+    }
+
+    testtokn.finalize();
+}
@@ -0,0 +1,174 @@
+// Copyright 2024 Simo Sorce
+// See LICENSE.txt file for terms
+
+use crate::tests::*;
+
+use serial_test::parallel;
+
+fn get_secret_value(
+    session: CK_SESSION_HANDLE,
+    handle: CK_OBJECT_HANDLE,
+) -> Vec<u8> {
+    let mut value = vec![0u8; 64];
+    let mut attr = make_attribute!(CKA_VALUE, value.as_mut_ptr(), value.len());
+    let ret = fn_get_attribute_value(session, handle, &mut attr, 1);
+    assert_eq!(ret, CKR_OK);
+    value.resize(attr.ulValueLen as usize, 0);
+    value
+}
+
+#[test]
+#[parallel]
+fn test_mlkem_operations() {
+    let mut testtokn =
+        TestToken::initialized("test_mlkem_operations.sql", None);
+    let session = testtokn.get_session(true);
+
+    /* login */
+    testtokn.login();
+
+    let mut mechanism = CK_MECHANISM {
+        mechanism: CKM_ML_KEM,
+        pParameter: std::ptr::null_mut(),
+        ulParameterLen: 0,
+    };
+    let secret_template = make_attr_template(
+        &[
+            (CKA_CLASS, CKO_SECRET_KEY),
+            (CKA_KEY_TYPE, CKK_GENERIC_SECRET),
+        ],
+        &[],
+        &[(CKA_EXTRACTABLE, true), (CKA_SENSITIVE, false)],
+    );
+
+    for (param_set, ctlen) in [
+        (CKP_ML_KEM_512, 768),
+        (CKP_ML_KEM_768, 1088),
+        (CKP_ML_KEM_1024, 1568),
+    ] {
+        let (pubkey, privkey) = ret_or_panic!(generate_key_pair(
+            session,
+            CKM_ML_KEM_KEY_PAIR_GEN,
+            &[(CKA_PARAMETER_SET, param_set)],
+            &[],
+            &[(CKA_TOKEN, true), (CKA_ENCAPSULATE, true)],
+            &[],
+            &[],
+            &[
+                (CKA_TOKEN, true),
+                (CKA_PRIVATE, true),
+                (CKA_SENSITIVE, true),
+                (CKA_DECAPSULATE, true),
+            ],
+        ));
+        if let Some(err) = check_attributes(
+            session,
+            privkey,
+            &[(CKA_PARAMETER_SET, param_set)],
+            &[],
+            &[(CKA_LOCAL, true)],
+        ) {
+            panic!("{}", err);
+        }
+
+        /* query the ciphertext length */
+        let mut ciphertext_len: CK_ULONG = 0;
+        let mut secret1 = CK_INVALID_HANDLE;
+        let ret = fn_encapsulate_key(
+            session,
+            &mut mechanism,
+            pubkey,
+            secret_template.as_ptr() as *mut _,
+            secret_template.len() as CK_ULONG,
+            std::ptr::null_mut(),
+            &mut ciphertext_len,
+            &mut secret1,
+        );
+        assert_eq!(ret, CKR_OK);
+        assert_eq!(ciphertext_len, ctlen);
+
+        let mut ciphertext = vec![0u8; ciphertext_len as usize];
+        let ret = fn_encapsulate_key(
+            session,
+            &mut mechanism,
+            pubkey,
+            secret_template.as_ptr() as *mut _,
+            secret_template.len() as CK_ULONG,
+            ciphertext.as_mut_ptr(),
+            &mut ciphertext_len,
+            &mut secret1,
+        );
+        assert_eq!(ret, CKR_OK);
+        assert_eq!(ciphertext_len, ctlen);
+
+        let mut secret2 = CK_INVALID_HANDLE;
+        let ret = fn_decapsulate_key(
+            session,
+            &mut mechanism,
+            privkey,
+            secret_template.as_ptr() as *mut _,
+            secret_template.len() as CK_ULONG,
+            ciphertext.as_mut_ptr(),
+            ciphertext_len,
+            &mut secret2,
+        );
+        assert_eq!(ret, CKR_OK);
+
+        let value1 = get_secret_value(session, secret1);
+        let value2 = get_secret_value(session, secret2);
+        assert_eq!(value1.len(), 32);
+        assert_eq!(value1, value2);
+
+        /* a truncated ciphertext is rejected */
+        let ret = fn_decapsulate_key(
+            session,
+            &mut mechanism,
+            privkey,
+            secret_template.as_ptr() as *mut _,
+            secret_template.len() as CK_ULONG,
+            ciphertext.as_mut_ptr(),
+            ciphertext_len - 1,
+            &mut secret2,
+        );
+        assert_eq!(ret, CKR_ENCRYPTED_DATA_LEN_RANGE);
+    }
+
+    /* keys must allow the operation */
+    let (pubkey, privkey) = ret_or_panic!(generate_key_pair(
+        session,
+        CKM_ML_KEM_KEY_PAIR_GEN,
+        &[(CKA_PARAMETER_SET, CKP_ML_KEM_768)],
+        &[],
+        &[],
+        &[],
+        &[],
+        &[],
+    ));
+    let mut ciphertext = vec![0u8; 1088];
+    let mut ciphertext_len = ciphertext.len() as CK_ULONG;
+    let mut secret = CK_INVALID_HANDLE;
+    let ret = fn_encapsulate_key(
+        session,
+        &mut mechanism,
+        pubkey,
+        secret_template.as_ptr() as *mut _,
+        secret_template.len() as CK_ULONG,
+        ciphertext.as_mut_ptr(),
+        &mut ciphertext_len,
+        &mut secret,
+    );
+    assert_eq!(ret, CKR_KEY_FUNCTION_NOT_PERMITTED);
+    let ret = fn_decapsulate_key(
+        session,
+        &mut mechanism,
+        privkey,
+        secret_template.as_ptr() as *mut _,
+        secret_template.len() as CK_ULONG,
+        ciphertext.as_mut_ptr(),
+        ciphertext_len,
+        &mut secret,
+    );
+    assert_eq!(ret, CKR_KEY_FUNCTION_NOT_PERMITTED);
+
+    testtokn.finalize();
+}
@@ -248,8 +248,23 @@ mod aes;
 #[cfg(feature = "rsa")]
 mod rsa;
 
+#[cfg(feature = "slhdsa")]
+mod slhdsa;
+
+#[cfg(feature = "hss")]
+mod hss;
+
 mod session;
 
+#[cfg(feature = "ffdh")]
+mod domain_params;
+
+#[cfg(feature = "chacha20")]
+mod chacha20;
+
+#[cfg(feature = "dsa")]
+mod dsa;
+
 #[cfg(feature = "ecdsa")]
 mod ecc;
 
@@ -265,6 +280,9 @@ mod ecdh_vectors;
 #[cfg(feature = "eddsa")]
 mod eddsa;
 
+#[cfg(feature = "ffdh")]
+mod ffdh;
+
 #[cfg(feature = "hash")]
 mod hashes;
 
@@ -277,14 +295,39 @@ mod kdf_vectors;
 
 mod kdfs;
 
+#[cfg(feature = "mldsa")]
+mod mldsa;
+
+#[cfg(feature = "mlkem")]
+mod mlkem;
+
 #[cfg(feature = "hmac")]
 mod mac_vectors;
 
 #[cfg(feature = "aes")]
 mod aes_kw_vectors;
 
+#[cfg(feature = "aes")]
+mod templates;
+
 #[cfg(feature = "tlskdf")]
 mod tls;
 
 #[cfg(feature = "nssdb")]
 mod nssdb;
+
+#[cfg(all(feature = "hash", feature = "hmac", feature = "aes"))]
+mod opstate;
+
+#[cfg(all(feature = "hash", feature = "hmac", feature = "aes"))]
+mod dual;
+
+#[cfg(all(
+    feature = "hmac",
+    feature = "ecdsa",
+    feature = "eddsa",
+    feature = "rsa"
+))]
+mod msgsig;
+
+mod slotevents;
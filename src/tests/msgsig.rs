@@ -0,0 +1,612 @@
+// Copyright 2024 Simo Sorce
+// See LICENSE.txt file for terms
+
+use crate::tests::*;
+
+use serial_test::parallel;
+
+fn msg_sign(
+    session: CK_SESSION_HANDLE,
+    param: CK_VOID_PTR,
+    paramlen: CK_ULONG,
+    data: &[u8],
+) -> Vec<u8> {
+    let mut siglen: CK_ULONG = 0;
+    let ret = fn_sign_message(
+        session,
+        param,
+        paramlen,
+        byte_ptr!(data),
+        data.len() as CK_ULONG,
+        std::ptr::null_mut(),
+        &mut siglen,
+    );
+    assert_eq!(ret, CKR_OK);
+    let mut signature = vec![0u8; siglen as usize];
+    let ret = fn_sign_message(
+        session,
+        param,
+        paramlen,
+        byte_ptr!(data),
+        data.len() as CK_ULONG,
+        signature.as_mut_ptr(),
+        &mut siglen,
+    );
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(siglen as usize, signature.len());
+    signature
+}
+
+fn msg_verify(
+    session: CK_SESSION_HANDLE,
+    param: CK_VOID_PTR,
+    paramlen: CK_ULONG,
+    data: &[u8],
+    signature: &[u8],
+) -> CK_RV {
+    fn_verify_message(
+        session,
+        param,
+        paramlen,
+        byte_ptr!(data),
+        data.len() as CK_ULONG,
+        byte_ptr!(signature),
+        signature.len() as CK_ULONG,
+    )
+}
+
+#[test]
+#[parallel]
+fn test_msg_sign_hmac() {
+    let mut testtokn = TestToken::initialized("test_msg_sign_hmac.sql", None);
+    let session = testtokn.get_session(false);
+
+    /* login */
+    testtokn.login();
+
+    let key = ret_or_panic!(generate_key(
+        session,
+        CKM_GENERIC_SECRET_KEY_GEN,
+        std::ptr::null_mut(),
+        0,
+        &[(CKA_KEY_TYPE, CKK_GENERIC_SECRET), (CKA_VALUE_LEN, 32)],
+        &[],
+        &[(CKA_TOKEN, false), (CKA_SIGN, true), (CKA_VERIFY, true)],
+    ));
+
+    let mut mechanism: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_SHA256_HMAC,
+        pParameter: std::ptr::null_mut(),
+        ulParameterLen: 0,
+    };
+    let records: [&[u8]; 3] = [b"first record", b"second record", b""];
+
+    let mut expected = Vec::<Vec<u8>>::new();
+    for rec in records {
+        expected.push(ret_or_panic!(sig_gen(session, key, rec, &mechanism)));
+    }
+
+    let ret = fn_message_sign_init(session, &mut mechanism, key);
+    assert_eq!(ret, CKR_OK);
+
+    /* a second message operation can't be started */
+    let ret = fn_message_sign_init(session, &mut mechanism, key);
+    assert_eq!(ret, CKR_OPERATION_ACTIVE);
+
+    for (rec, exp) in records.iter().zip(expected.iter()) {
+        let sig = msg_sign(session, std::ptr::null_mut(), 0, rec);
+        assert_eq!(&sig, exp);
+    }
+
+    /* HMAC takes no per-message parameters */
+    let param: CK_ULONG = 16;
+    let mut siglen: CK_ULONG = 32;
+    let mut sig = [0u8; 32];
+    let ret = fn_sign_message(
+        session,
+        void_ptr!(&param),
+        sizeof!(CK_ULONG),
+        byte_ptr!(records[0]),
+        records[0].len() as CK_ULONG,
+        sig.as_mut_ptr(),
+        &mut siglen,
+    );
+    assert_eq!(ret, CKR_ARGUMENTS_BAD);
+
+    /* multipart message */
+    let ret = fn_sign_message_begin(session, std::ptr::null_mut(), 0);
+    assert_eq!(ret, CKR_OK);
+
+    /* no single part messages while a multipart one is in progress */
+    let ret = fn_sign_message(
+        session,
+        std::ptr::null_mut(),
+        0,
+        byte_ptr!(records[0]),
+        records[0].len() as CK_ULONG,
+        sig.as_mut_ptr(),
+        &mut siglen,
+    );
+    assert_eq!(ret, CKR_OPERATION_ACTIVE);
+    let ret = fn_message_sign_final(session);
+    assert_eq!(ret, CKR_OPERATION_ACTIVE);
+
+    let ret = fn_sign_message_next(
+        session,
+        std::ptr::null_mut(),
+        0,
+        byte_ptr!(&records[1][..6]),
+        6,
+        std::ptr::null_mut(),
+        std::ptr::null_mut(),
+    );
+    assert_eq!(ret, CKR_OK);
+
+    /* parameters can only be given when the message begins */
+    let ret = fn_sign_message_next(
+        session,
+        void_ptr!(&param),
+        sizeof!(CK_ULONG),
+        byte_ptr!(&records[1][6..]),
+        (records[1].len() - 6) as CK_ULONG,
+        std::ptr::null_mut(),
+        std::ptr::null_mut(),
+    );
+    assert_eq!(ret, CKR_ARGUMENTS_BAD);
+
+    /* length query does not terminate the message */
+    siglen = 0;
+    let ret = fn_sign_message_next(
+        session,
+        std::ptr::null_mut(),
+        0,
+        byte_ptr!(&records[1][6..]),
+        (records[1].len() - 6) as CK_ULONG,
+        std::ptr::null_mut(),
+        &mut siglen,
+    );
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(siglen, 32);
+
+    siglen = 16;
+    let ret = fn_sign_message_next(
+        session,
+        std::ptr::null_mut(),
+        0,
+        byte_ptr!(&records[1][6..]),
+        (records[1].len() - 6) as CK_ULONG,
+        sig.as_mut_ptr(),
+        &mut siglen,
+    );
+    assert_eq!(ret, CKR_BUFFER_TOO_SMALL);
+
+    siglen = 32;
+    let ret = fn_sign_message_next(
+        session,
+        std::ptr::null_mut(),
+        0,
+        byte_ptr!(&records[1][6..]),
+        (records[1].len() - 6) as CK_ULONG,
+        sig.as_mut_ptr(),
+        &mut siglen,
+    );
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(sig.as_slice(), expected[1].as_slice());
+
+    /* next requires a message in progress */
+    let ret = fn_sign_message_next(
+        session,
+        std::ptr::null_mut(),
+        0,
+        byte_ptr!(records[0]),
+        records[0].len() as CK_ULONG,
+        std::ptr::null_mut(),
+        std::ptr::null_mut(),
+    );
+    assert_eq!(ret, CKR_OPERATION_NOT_INITIALIZED);
+
+    let ret = fn_message_sign_final(session);
+    assert_eq!(ret, CKR_OK);
+    let ret = fn_sign_message(
+        session,
+        std::ptr::null_mut(),
+        0,
+        byte_ptr!(records[0]),
+        records[0].len() as CK_ULONG,
+        sig.as_mut_ptr(),
+        &mut siglen,
+    );
+    assert_eq!(ret, CKR_OPERATION_NOT_INITIALIZED);
+
+    /* verification */
+    let ret = fn_message_verify_init(session, &mut mechanism, key);
+    assert_eq!(ret, CKR_OK);
+    for (rec, exp) in records.iter().zip(expected.iter()) {
+        let ret = msg_verify(session, std::ptr::null_mut(), 0, rec, exp);
+        assert_eq!(ret, CKR_OK);
+    }
+    let ret =
+        msg_verify(session, std::ptr::null_mut(), 0, records[0], &expected[1]);
+    assert_eq!(ret, CKR_SIGNATURE_INVALID);
+    let ret = msg_verify(
+        session,
+        std::ptr::null_mut(),
+        0,
+        records[0],
+        &expected[0][..16],
+    );
+    assert_eq!(ret, CKR_SIGNATURE_LEN_RANGE);
+
+    /* a failed verification does not terminate the operation */
+    let ret = fn_verify_message_begin(session, std::ptr::null_mut(), 0);
+    assert_eq!(ret, CKR_OK);
+    let ret = fn_verify_message_next(
+        session,
+        std::ptr::null_mut(),
+        0,
+        byte_ptr!(records[0]),
+        records[0].len() as CK_ULONG,
+        std::ptr::null_mut(),
+        0,
+    );
+    assert_eq!(ret, CKR_OK);
+    let ret = fn_verify_message_next(
+        session,
+        std::ptr::null_mut(),
+        0,
+        std::ptr::null_mut(),
+        0,
+        expected[0].as_ptr() as *mut u8,
+        expected[0].len() as CK_ULONG,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let ret = fn_message_verify_final(session);
+    assert_eq!(ret, CKR_OK);
+    let ret = fn_message_verify_final(session);
+    assert_eq!(ret, CKR_OPERATION_NOT_INITIALIZED);
+
+    testtokn.finalize();
+}
+
+#[test]
+#[parallel]
+fn test_msg_sign_ecdsa() {
+    let mut testtokn = TestToken::initialized("test_msg_sign_ecdsa.sql", None);
+    let session = testtokn.get_session(true);
+
+    /* login */
+    testtokn.login();
+
+    let ec_params = hex::decode(
+        "06082A8648CE3D030107", // secp256r1
+    )
+    .expect("Failed to decode hex ec_params");
+    let (pubkey, prikey) = ret_or_panic!(generate_key_pair(
+        session,
+        CKM_EC_KEY_PAIR_GEN,
+        &[(CKA_CLASS, CKO_PUBLIC_KEY), (CKA_KEY_TYPE, CKK_EC),],
+        &[(CKA_EC_PARAMS, ec_params.as_slice())],
+        &[(CKA_VERIFY, true)],
+        &[(CKA_CLASS, CKO_PRIVATE_KEY), (CKA_KEY_TYPE, CKK_EC),],
+        &[],
+        &[(CKA_TOKEN, false), (CKA_SIGN, true)],
+    ));
+
+    let mut mechanism: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_ECDSA_SHA256,
+        pParameter: std::ptr::null_mut(),
+        ulParameterLen: 0,
+    };
+    let records: [&[u8]; 3] = [b"record one", b"record two", b"record three"];
+
+    let ret = fn_message_sign_init(session, &mut mechanism, prikey);
+    assert_eq!(ret, CKR_OK);
+    let mut signatures = Vec::<Vec<u8>>::new();
+    for rec in records {
+        let sig = msg_sign(session, std::ptr::null_mut(), 0, rec);
+        assert_eq!(sig.len(), 64);
+        signatures.push(sig);
+    }
+    let ret = fn_message_sign_final(session);
+    assert_eq!(ret, CKR_OK);
+
+    for (rec, sig) in records.iter().zip(signatures.iter()) {
+        let ret = sig_verify(session, pubkey, rec, sig, &mechanism);
+        assert_eq!(ret, CKR_OK);
+    }
+
+    let ret = fn_message_verify_init(session, &mut mechanism, pubkey);
+    assert_eq!(ret, CKR_OK);
+    for (rec, sig) in records.iter().zip(signatures.iter()) {
+        let ret = msg_verify(session, std::ptr::null_mut(), 0, rec, sig);
+        assert_eq!(ret, CKR_OK);
+    }
+    let ret = msg_verify(
+        session,
+        std::ptr::null_mut(),
+        0,
+        records[1],
+        &signatures[0],
+    );
+    assert_eq!(ret, CKR_SIGNATURE_INVALID);
+    let ret = fn_message_verify_final(session);
+    assert_eq!(ret, CKR_OK);
+
+    /* raw ECDSA can't process multipart messages */
+    mechanism.mechanism = CKM_ECDSA;
+    let ret = fn_message_sign_init(session, &mut mechanism, prikey);
+    assert_eq!(ret, CKR_OK);
+    let ret = fn_sign_message_begin(session, std::ptr::null_mut(), 0);
+    assert_eq!(ret, CKR_OPERATION_NOT_INITIALIZED);
+    let hash = [0x5au8; 32];
+    let sig = msg_sign(session, std::ptr::null_mut(), 0, &hash);
+    let ret = fn_message_sign_final(session);
+    assert_eq!(ret, CKR_OK);
+    let ret = sig_verify(session, pubkey, &hash, &sig, &mechanism);
+    assert_eq!(ret, CKR_OK);
+
+    testtokn.finalize();
+}
+
+#[test]
+#[parallel]
+fn test_msg_sign_eddsa() {
+    let mut testtokn = TestToken::initialized("test_msg_sign_eddsa.sql", None);
+    let session = testtokn.get_session(true);
+
+    /* login */
+    testtokn.login();
+
+    /* Test Vectors for Ed25519ctx */
+    let point = hex::decode(
+        "dfc9425e4f968f7f0c29f0259cf5f9aed6851c2bb4ad8bfb860cfee0ab248292",
+    )
+    .expect("Failed to decode hex point");
+    let params = hex::decode("130c656477617264733235353139")
+        .expect("Failed to decode hex params");
+    let pubkey = ret_or_panic!(import_object(
+        session,
+        CKO_PUBLIC_KEY,
+        &[(CKA_KEY_TYPE, CKK_EC_EDWARDS)],
+        &[
+            (CKA_EC_POINT, point.as_slice()),
+            (CKA_EC_PARAMS, params.as_slice()),
+        ],
+        &[(CKA_VERIFY, true)]
+    ));
+    let value = hex::decode(
+        "0305334e381af78f141cb666f6199f57bc3495335a256a95bd2a55bf546663f6",
+    )
+    .expect("Failed to decode value");
+    let prikey = ret_or_panic!(import_object(
+        session,
+        CKO_PRIVATE_KEY,
+        &[(CKA_KEY_TYPE, CKK_EC_EDWARDS)],
+        &[
+            (CKA_VALUE, value.as_slice()),
+            (CKA_EC_PARAMS, params.as_slice()),
+        ],
+        &[(CKA_SIGN, true)]
+    ));
+
+    let data = hex::decode("f726936d19c800494e3fdaff20b276a8")
+        .expect("Failed to decode data");
+    let expected = hex::decode(
+        "55a4cc2f70a54e04288c5f4cd1e45a7bb520b36292911876cada7323198dd87a\
+         8b36950b95130022907a7fb7c4e9b2d5f6cca685a587b4b21f4b888e4e7edb0d",
+    )
+    .expect("failed to decode expected signature");
+
+    /* plain Ed25519 at init, the context is given per message */
+    let mut mechanism: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_EDDSA,
+        pParameter: std::ptr::null_mut(),
+        ulParameterLen: 0,
+    };
+    let ctx = hex::decode("666f6f").expect("Failed to decode context");
+    let eddsa_params = CK_EDDSA_PARAMS {
+        phFlag: CK_FALSE,
+        pContextData: ctx.as_ptr() as *mut CK_BYTE,
+        ulContextDataLen: ctx.len() as CK_ULONG,
+    };
+
+    let ret = fn_message_sign_init(session, &mut mechanism, prikey);
+    assert_eq!(ret, CKR_OK);
+    let plain = msg_sign(session, std::ptr::null_mut(), 0, &data);
+    assert_ne!(plain, expected);
+    let sig = msg_sign(
+        session,
+        void_ptr!(&eddsa_params),
+        sizeof!(CK_EDDSA_PARAMS),
+        &data,
+    );
+    assert_eq!(sig, expected);
+
+    /* the context only applies to the message it was given with */
+    for (param, paramlen, result) in [
+        (std::ptr::null_mut(), 0, &plain),
+        (
+            void_ptr!(&eddsa_params),
+            sizeof!(CK_EDDSA_PARAMS),
+            &expected,
+        ),
+        (std::ptr::null_mut(), 0, &plain),
+    ] {
+        let ret = fn_sign_message_begin(session, param, paramlen);
+        assert_eq!(ret, CKR_OK);
+        let ret = fn_sign_message_next(
+            session,
+            std::ptr::null_mut(),
+            0,
+            data.as_ptr() as *mut u8,
+            4,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+        );
+        assert_eq!(ret, CKR_OK);
+        let mut sig = [0u8; 64];
+        let mut siglen: CK_ULONG = 64;
+        let ret = fn_sign_message_next(
+            session,
+            std::ptr::null_mut(),
+            0,
+            data[4..].as_ptr() as *mut u8,
+            (data.len() - 4) as CK_ULONG,
+            sig.as_mut_ptr(),
+            &mut siglen,
+        );
+        assert_eq!(ret, CKR_OK);
+        assert_eq!(sig.as_slice(), result.as_slice());
+    }
+    let ret = fn_message_sign_final(session);
+    assert_eq!(ret, CKR_OK);
+
+    let ret = fn_message_verify_init(session, &mut mechanism, pubkey);
+    assert_eq!(ret, CKR_OK);
+    let ret = msg_verify(session, std::ptr::null_mut(), 0, &data, &plain);
+    assert_eq!(ret, CKR_OK);
+    let ret = msg_verify(session, std::ptr::null_mut(), 0, &data, &expected);
+    assert_eq!(ret, CKR_SIGNATURE_INVALID);
+    let ret = msg_verify(
+        session,
+        void_ptr!(&eddsa_params),
+        sizeof!(CK_EDDSA_PARAMS),
+        &data,
+        &expected,
+    );
+    assert_eq!(ret, CKR_OK);
+    let ret = fn_message_verify_final(session);
+    assert_eq!(ret, CKR_OK);
+
+    testtokn.finalize();
+}
+
+#[test]
+#[parallel]
+fn test_msg_sign_rsa() {
+    let mut testtokn = TestToken::initialized("test_msg_sign_rsa.sql", None);
+    let session = testtokn.get_session(true);
+
+    /* login */
+    testtokn.login();
+
+    let (pubkey, prikey) = ret_or_panic!(generate_key_pair(
+        session,
+        CKM_RSA_PKCS_KEY_PAIR_GEN,
+        &[(CKA_MODULUS_BITS, 2048)],
+        &[],
+        &[(CKA_VERIFY, true)],
+        &[(CKA_CLASS, CKO_PRIVATE_KEY), (CKA_KEY_TYPE, CKK_RSA),],
+        &[],
+        &[(CKA_TOKEN, false), (CKA_SIGN, true)],
+    ));
+
+    let data = b"a record to be signed";
+
+    /* PKCS#1 v1.5 signatures are deterministic */
+    let mut mechanism: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_SHA256_RSA_PKCS,
+        pParameter: std::ptr::null_mut(),
+        ulParameterLen: 0,
+    };
+    let expected = ret_or_panic!(sig_gen(session, prikey, data, &mechanism));
+    let ret = fn_message_sign_init(session, &mut mechanism, prikey);
+    assert_eq!(ret, CKR_OK);
+    for _ in 0..3 {
+        let sig = msg_sign(session, std::ptr::null_mut(), 0, data);
+        assert_eq!(sig, expected);
+    }
+    let ret = fn_message_sign_final(session);
+    assert_eq!(ret, CKR_OK);
+
+    /* PSS with a salt length selected per message */
+    let salted = CK_RSA_PKCS_PSS_PARAMS {
+        hashAlg: CKM_SHA256,
+        mgf: CKG_MGF1_SHA256,
+        sLen: 32,
+    };
+    let unsalted = CK_RSA_PKCS_PSS_PARAMS {
+        hashAlg: CKM_SHA256,
+        mgf: CKG_MGF1_SHA256,
+        sLen: 0,
+    };
+    let mut mechanism: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_SHA256_RSA_PKCS_PSS,
+        pParameter: void_ptr!(&salted),
+        ulParameterLen: sizeof!(CK_RSA_PKCS_PSS_PARAMS),
+    };
+    let ret = fn_message_sign_init(session, &mut mechanism, prikey);
+    assert_eq!(ret, CKR_OK);
+    let sig1 = msg_sign(session, std::ptr::null_mut(), 0, data);
+    let sig2 = msg_sign(session, std::ptr::null_mut(), 0, data);
+    assert_ne!(sig1, sig2);
+    let sig3 = msg_sign(
+        session,
+        void_ptr!(&unsalted),
+        sizeof!(CK_RSA_PKCS_PSS_PARAMS),
+        data,
+    );
+    /* the init parameters apply again to the following message */
+    let sig4 = msg_sign(session, std::ptr::null_mut(), 0, data);
+    assert_ne!(sig3, sig4);
+    let sig5 = msg_sign(
+        session,
+        void_ptr!(&unsalted),
+        sizeof!(CK_RSA_PKCS_PSS_PARAMS),
+        data,
+    );
+    assert_eq!(sig3, sig5);
+
+    /* the hash must match the mechanism */
+    let wrong = CK_RSA_PKCS_PSS_PARAMS {
+        hashAlg: CKM_SHA384,
+        mgf: CKG_MGF1_SHA384,
+        sLen: 0,
+    };
+    let mut siglen: CK_ULONG = 256;
+    let mut sig = [0u8; 256];
+    let ret = fn_sign_message(
+        session,
+        void_ptr!(&wrong),
+        sizeof!(CK_RSA_PKCS_PSS_PARAMS),
+        byte_ptr!(data),
+        data.len() as CK_ULONG,
+        sig.as_mut_ptr(),
+        &mut siglen,
+    );
+    assert_eq!(ret, CKR_ARGUMENTS_BAD);
+    let ret = fn_message_sign_final(session);
+    assert_eq!(ret, CKR_OK);
+
+    for sig in [&sig1, &sig2, &sig4] {
+        let ret = sig_verify(session, pubkey, data, sig, &mechanism);
+        assert_eq!(ret, CKR_OK);
+    }
+    mechanism.pParameter = void_ptr!(&unsalted);
+    let ret = sig_verify(session, pubkey, data, &sig3, &mechanism);
+    assert_eq!(ret, CKR_OK);
+    let ret = sig_verify(session, pubkey, data, &sig4, &mechanism);
+    assert_eq!(ret, CKR_SIGNATURE_INVALID);
+
+    let ret = fn_message_verify_init(session, &mut mechanism, pubkey);
+    assert_eq!(ret, CKR_OK);
+    let ret = msg_verify(session, std::ptr::null_mut(), 0, data, &sig3);
+    assert_eq!(ret, CKR_OK);
+    let ret = msg_verify(
+        session,
+        void_ptr!(&salted),
+        sizeof!(CK_RSA_PKCS_PSS_PARAMS),
+        data,
+        &sig1,
+    );
+    assert_eq!(ret, CKR_OK);
+    let ret = msg_verify(session, std::ptr::null_mut(), 0, data, &sig3);
+    assert_eq!(ret, CKR_OK);
+    let ret = msg_verify(session, std::ptr::null_mut(), 0, data, &sig1);
+    assert_eq!(ret, CKR_SIGNATURE_INVALID);
+    let ret = fn_message_verify_final(session);
+    assert_eq!(ret, CKR_OK);
+
+    testtokn.finalize();
+}
@@ -0,0 +1,363 @@
+// Copyright 2024 Simo Sorce
+// See LICENSE.txt file for terms
+
+use crate::tests::*;
+
+use serial_test::parallel;
+
+fn get_op_state(session: CK_SESSION_HANDLE) -> Vec<u8> {
+    let mut state_len: CK_ULONG = 0;
+    let ret =
+        fn_get_operation_state(session, std::ptr::null_mut(), &mut state_len);
+    assert_eq!(ret, CKR_OK);
+    let mut state = vec![0u8; state_len as usize];
+    let ret =
+        fn_get_operation_state(session, state.as_mut_ptr(), &mut state_len);
+    assert_eq!(ret, CKR_OK);
+    state.resize(state_len as usize, 0);
+    state
+}
+
+fn set_op_state(
+    session: CK_SESSION_HANDLE,
+    state: &[u8],
+    enc_key: CK_OBJECT_HANDLE,
+    auth_key: CK_OBJECT_HANDLE,
+) -> CK_RV {
+    fn_set_operation_state(
+        session,
+        state.as_ptr() as *mut u8,
+        state.len() as CK_ULONG,
+        enc_key,
+        auth_key,
+    )
+}
+
+#[test]
+#[parallel]
+fn test_operation_state() {
+    let mut testtokn = TestToken::initialized("test_operation_state.sql", None);
+    let session = testtokn.get_session(true);
+
+    /* login */
+    testtokn.login();
+
+    let mut session2: CK_SESSION_HANDLE = CK_INVALID_HANDLE;
+    let ret = fn_open_session(
+        testtokn.get_slot(),
+        CKF_SERIAL_SESSION,
+        std::ptr::null_mut(),
+        None,
+        &mut session2,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let data = b"Some data to be processed in multiple parts";
+    let split = 20;
+
+    /* nothing to save */
+    let mut state_len: CK_ULONG = 0;
+    let ret =
+        fn_get_operation_state(session, std::ptr::null_mut(), &mut state_len);
+    assert_eq!(ret, CKR_OPERATION_NOT_INITIALIZED);
+
+    /* Digest */
+    let mut mechanism: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_SHA256,
+        pParameter: std::ptr::null_mut(),
+        ulParameterLen: 0,
+    };
+    let ret = fn_digest_init(session, &mut mechanism);
+    assert_eq!(ret, CKR_OK);
+    let mut digest = [0u8; 32];
+    let mut digest_len: CK_ULONG = digest.len() as CK_ULONG;
+    let ret = fn_digest(
+        session,
+        data.as_ptr() as *mut u8,
+        data.len() as CK_ULONG,
+        digest.as_mut_ptr(),
+        &mut digest_len,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let ret = fn_digest_init(session, &mut mechanism);
+    assert_eq!(ret, CKR_OK);
+    let ret = fn_digest_update(session, data.as_ptr() as *mut u8, split);
+    assert_eq!(ret, CKR_OK);
+
+    /* too small a buffer */
+    let mut short = [0u8; 4];
+    let mut short_len: CK_ULONG = short.len() as CK_ULONG;
+    let ret =
+        fn_get_operation_state(session, short.as_mut_ptr(), &mut short_len);
+    assert_eq!(ret, CKR_BUFFER_TOO_SMALL);
+    assert!(short_len > short.len() as CK_ULONG);
+
+    let state = get_op_state(session);
+
+    /* a digest does not use keys */
+    let ret = set_op_state(session2, &state, 1, CK_INVALID_HANDLE);
+    assert_eq!(ret, CKR_KEY_NOT_NEEDED);
+
+    /* tampered states are rejected */
+    let mut tampered = state.clone();
+    let last = tampered.len() - 1;
+    tampered[last] ^= 0x01;
+    let ret =
+        set_op_state(session2, &tampered, CK_INVALID_HANDLE, CK_INVALID_HANDLE);
+    assert_eq!(ret, CKR_SAVED_STATE_INVALID);
+    let mut tampered = state.clone();
+    tampered[8] ^= 0x01;
+    let ret =
+        set_op_state(session2, &tampered, CK_INVALID_HANDLE, CK_INVALID_HANDLE);
+    assert_eq!(ret, CKR_SAVED_STATE_INVALID);
+    let ret = set_op_state(
+        session2,
+        &state[..(state.len() - 1)],
+        CK_INVALID_HANDLE,
+        CK_INVALID_HANDLE,
+    );
+    assert_eq!(ret, CKR_SAVED_STATE_INVALID);
+
+    /* resume in the other session */
+    let ret =
+        set_op_state(session2, &state, CK_INVALID_HANDLE, CK_INVALID_HANDLE);
+    assert_eq!(ret, CKR_OK);
+    let ret = fn_digest_update(
+        session2,
+        data[split as usize..].as_ptr() as *mut u8,
+        (data.len() - split as usize) as CK_ULONG,
+    );
+    assert_eq!(ret, CKR_OK);
+    let mut digest2 = [0u8; 32];
+    let ret = fn_digest_final(session2, digest2.as_mut_ptr(), &mut digest_len);
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(digest, digest2);
+
+    /* the original operation is unaffected */
+    let ret = fn_digest_update(
+        session,
+        data[split as usize..].as_ptr() as *mut u8,
+        (data.len() - split as usize) as CK_ULONG,
+    );
+    assert_eq!(ret, CKR_OK);
+    let mut digest3 = [0u8; 32];
+    let ret = fn_digest_final(session, digest3.as_mut_ptr(), &mut digest_len);
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(digest, digest3);
+
+    /* HMAC */
+    let hmac_key = ret_or_panic!(generate_key(
+        session,
+        CKM_GENERIC_SECRET_KEY_GEN,
+        std::ptr::null_mut(),
+        0,
+        &[(CKA_KEY_TYPE, CKK_GENERIC_SECRET), (CKA_VALUE_LEN, 32)],
+        &[],
+        &[(CKA_TOKEN, false), (CKA_SIGN, true), (CKA_VERIFY, true)],
+    ));
+    let other_key = ret_or_panic!(generate_key(
+        session,
+        CKM_GENERIC_SECRET_KEY_GEN,
+        std::ptr::null_mut(),
+        0,
+        &[(CKA_KEY_TYPE, CKK_GENERIC_SECRET), (CKA_VALUE_LEN, 32)],
+        &[],
+        &[(CKA_TOKEN, false), (CKA_SIGN, true), (CKA_VERIFY, true)],
+    ));
+    let mut mechanism: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_SHA256_HMAC,
+        pParameter: std::ptr::null_mut(),
+        ulParameterLen: 0,
+    };
+    let mac = ret_or_panic!(sig_gen(session, hmac_key, data, &mechanism));
+
+    let ret = fn_sign_init(session, &mut mechanism, hmac_key);
+    assert_eq!(ret, CKR_OK);
+    let ret = fn_sign_update(session, data.as_ptr() as *mut u8, split);
+    assert_eq!(ret, CKR_OK);
+    let state = get_op_state(session);
+
+    let ret =
+        set_op_state(session2, &state, CK_INVALID_HANDLE, CK_INVALID_HANDLE);
+    assert_eq!(ret, CKR_KEY_NEEDED);
+    let ret = set_op_state(session2, &state, hmac_key, hmac_key);
+    assert_eq!(ret, CKR_KEY_NOT_NEEDED);
+    let ret = set_op_state(session2, &state, CK_INVALID_HANDLE, other_key);
+    assert_eq!(ret, CKR_KEY_CHANGED);
+    let ret = set_op_state(session2, &state, CK_INVALID_HANDLE, hmac_key);
+    assert_eq!(ret, CKR_OK);
+
+    let ret = fn_sign_update(
+        session2,
+        data[split as usize..].as_ptr() as *mut u8,
+        (data.len() - split as usize) as CK_ULONG,
+    );
+    assert_eq!(ret, CKR_OK);
+    let mut mac2 = [0u8; 32];
+    let mut mac2_len: CK_ULONG = mac2.len() as CK_ULONG;
+    let ret = fn_sign_final(session2, mac2.as_mut_ptr(), &mut mac2_len);
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(mac.as_slice(), &mac2);
+
+    /* the same state can be restored more than once */
+    let ret = set_op_state(session2, &state, CK_INVALID_HANDLE, hmac_key);
+    assert_eq!(ret, CKR_OK);
+    let ret = fn_sign_update(
+        session2,
+        data[split as usize..].as_ptr() as *mut u8,
+        (data.len() - split as usize) as CK_ULONG,
+    );
+    assert_eq!(ret, CKR_OK);
+    let ret = fn_sign_final(session2, mac2.as_mut_ptr(), &mut mac2_len);
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(mac.as_slice(), &mac2);
+
+    /* clear the original operation */
+    let ret = fn_sign_final(session, mac2.as_mut_ptr(), &mut mac2_len);
+    assert_eq!(ret, CKR_OK);
+
+    /* AES CTR */
+    let aes_key = ret_or_panic!(generate_key(
+        session,
+        CKM_AES_KEY_GEN,
+        std::ptr::null_mut(),
+        0,
+        &[(CKA_VALUE_LEN, 16)],
+        &[],
+        &[(CKA_TOKEN, false), (CKA_ENCRYPT, true), (CKA_DECRYPT, true)],
+    ));
+    let param = CK_AES_CTR_PARAMS {
+        ulCounterBits: 128,
+        cb: [
+            0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A,
+            0x0B, 0x0C, 0x0D, 0x0E, 0x0F,
+        ],
+    };
+    let mut mechanism: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_AES_CTR,
+        pParameter: void_ptr!(&param),
+        ulParameterLen: sizeof!(CK_AES_CTR_PARAMS),
+    };
+    let enc = ret_or_panic!(encrypt(session, aes_key, data, &mechanism));
+
+    let ret = fn_encrypt_init(session, &mut mechanism, aes_key);
+    assert_eq!(ret, CKR_OK);
+    let mut enc2 = vec![0u8; data.len()];
+    let mut enc2_len: CK_ULONG = split;
+    let ret = fn_encrypt_update(
+        session,
+        data.as_ptr() as *mut u8,
+        split,
+        enc2.as_mut_ptr(),
+        &mut enc2_len,
+    );
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(enc2_len, split);
+    let state = get_op_state(session);
+
+    let ret = set_op_state(session2, &state, CK_INVALID_HANDLE, aes_key);
+    assert_eq!(ret, CKR_KEY_NEEDED);
+    let ret = set_op_state(session2, &state, aes_key, CK_INVALID_HANDLE);
+    assert_eq!(ret, CKR_OK);
+
+    let mut enc2_len: CK_ULONG = (data.len() - split as usize) as CK_ULONG;
+    let ret = fn_encrypt_update(
+        session2,
+        data[split as usize..].as_ptr() as *mut u8,
+        enc2_len,
+        enc2[split as usize..].as_mut_ptr(),
+        &mut enc2_len,
+    );
+    assert_eq!(ret, CKR_OK);
+    let mut final_len: CK_ULONG = 0;
+    let ret = fn_encrypt_final(session2, enc2.as_mut_ptr(), &mut final_len);
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(final_len, 0);
+    assert_eq!(enc, enc2);
+
+    let ret = fn_encrypt_final(session, enc2.as_mut_ptr(), &mut final_len);
+    assert_eq!(ret, CKR_OK);
+
+    /* AEAD modes can't be saved */
+    let param = CK_GCM_PARAMS {
+        pIv: param.cb.as_ptr() as *mut u8,
+        ulIvLen: 12,
+        ulIvBits: 96,
+        pAAD: std::ptr::null_mut(),
+        ulAADLen: 0,
+        ulTagBits: 128,
+    };
+    let mut mechanism: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_AES_GCM,
+        pParameter: void_ptr!(&param),
+        ulParameterLen: sizeof!(CK_GCM_PARAMS),
+    };
+    let ret = fn_encrypt_init(session, &mut mechanism, aes_key);
+    assert_eq!(ret, CKR_OK);
+    let ret =
+        fn_get_operation_state(session, std::ptr::null_mut(), &mut state_len);
+    assert_eq!(ret, CKR_STATE_UNSAVEABLE);
+    let ret = fn_encrypt_init(session, std::ptr::null_mut(), aes_key);
+    assert_eq!(ret, CKR_OK);
+
+    let ret = fn_close_session(session2);
+    assert_eq!(ret, CKR_OK);
+
+    /* states outlive the session that saved them */
+    let mut session3: CK_SESSION_HANDLE = CK_INVALID_HANDLE;
+    let ret = fn_open_session(
+        testtokn.get_slot(),
+        CKF_SERIAL_SESSION,
+        std::ptr::null_mut(),
+        None,
+        &mut session3,
+    );
+    assert_eq!(ret, CKR_OK);
+    let mut mechanism: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_SHA256,
+        pParameter: std::ptr::null_mut(),
+        ulParameterLen: 0,
+    };
+    let ret = fn_digest_init(session3, &mut mechanism);
+    assert_eq!(ret, CKR_OK);
+    let ret = fn_digest_update(session3, data.as_ptr() as *mut u8, split);
+    assert_eq!(ret, CKR_OK);
+    let state = get_op_state(session3);
+    let ret = fn_close_session(session3);
+    assert_eq!(ret, CKR_OK);
+
+    let ret =
+        set_op_state(session, &state, CK_INVALID_HANDLE, CK_INVALID_HANDLE);
+    assert_eq!(ret, CKR_OK);
+    let ret = fn_digest_update(
+        session,
+        data[split as usize..].as_ptr() as *mut u8,
+        (data.len() - split as usize) as CK_ULONG,
+    );
+    assert_eq!(ret, CKR_OK);
+    let mut digest4 = [0u8; 32];
+    let ret = fn_digest_final(session, digest4.as_mut_ptr(), &mut digest_len);
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(digest, digest4);
+
+    /* only the most recently saved states are retained */
+    let ret = fn_digest_init(session, &mut mechanism);
+    assert_eq!(ret, CKR_OK);
+    let ret = fn_digest_update(session, data.as_ptr() as *mut u8, split);
+    assert_eq!(ret, CKR_OK);
+    let oldest = get_op_state(session);
+    for _ in 0..1024 {
+        let _ = get_op_state(session);
+    }
+    let ret =
+        set_op_state(session, &oldest, CK_INVALID_HANDLE, CK_INVALID_HANDLE);
+    assert_eq!(ret, CKR_SAVED_STATE_INVALID);
+    let ret =
+        set_op_state(session, &state, CK_INVALID_HANDLE, CK_INVALID_HANDLE);
+    assert_eq!(ret, CKR_SAVED_STATE_INVALID);
+    let ret = fn_digest_final(session, digest4.as_mut_ptr(), &mut digest_len);
+    assert_eq!(ret, CKR_OK);
+
+    testtokn.finalize();
+}
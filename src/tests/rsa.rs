@@ -301,3 +301,180 @@ fn test_rsa_operations() {
 
     testtokn.finalize();
 }
+
+#[test]
+#[parallel]
+fn test_rsa_recover() {
+    let mut testtokn = TestToken::initialized("test_rsa_recover.sql", None);
+    let session = testtokn.get_session(true);
+
+    /* login */
+    testtokn.login();
+
+    let (pubkey, prikey) = ret_or_panic!(generate_key_pair(
+        session,
+        CKM_RSA_PKCS_KEY_PAIR_GEN,
+        &[(CKA_MODULUS_BITS, 2048)],
+        &[],
+        &[
+            (CKA_ENCRYPT, true),
+            (CKA_VERIFY, true),
+            (CKA_VERIFY_RECOVER, true),
+        ],
+        &[(CKA_CLASS, CKO_PRIVATE_KEY), (CKA_KEY_TYPE, CKK_RSA),],
+        &[],
+        &[
+            (CKA_PRIVATE, true),
+            (CKA_SENSITIVE, true),
+            (CKA_TOKEN, true),
+            (CKA_DECRYPT, true),
+            (CKA_SIGN, true),
+            (CKA_SIGN_RECOVER, true),
+        ],
+    ));
+
+    /* mechanisms that can not recover data are refused */
+    let mut mechanism: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_SHA256_RSA_PKCS,
+        pParameter: std::ptr::null_mut(),
+        ulParameterLen: 0,
+    };
+    let ret = fn_sign_recover_init(session, &mut mechanism, prikey);
+    assert_eq!(ret, CKR_MECHANISM_INVALID);
+
+    /* RSA PKCS Sign Recover */
+    mechanism.mechanism = CKM_RSA_PKCS;
+    let data = "recoverable message".as_bytes();
+
+    let ret = fn_sign_recover_init(session, &mut mechanism, prikey);
+    assert_eq!(ret, CKR_OK);
+
+    let mut sig_len: CK_ULONG = 0;
+    let ret = fn_sign_recover(
+        session,
+        data.as_ptr() as *mut u8,
+        data.len() as CK_ULONG,
+        std::ptr::null_mut(),
+        &mut sig_len,
+    );
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(sig_len, 256);
+
+    let mut signature = vec![0u8; sig_len as usize];
+    let ret = fn_sign_recover(
+        session,
+        data.as_ptr() as *mut u8,
+        data.len() as CK_ULONG,
+        signature.as_mut_ptr(),
+        &mut sig_len,
+    );
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(sig_len, 256);
+
+    /* the signature is a regular PKCS#1 v1.5 signature */
+    let ret = sig_verify(session, pubkey, data, &signature, &mechanism);
+    assert_eq!(ret, CKR_OK);
+
+    /* RSA PKCS Verify Recover */
+    let ret = fn_verify_recover_init(session, &mut mechanism, pubkey);
+    assert_eq!(ret, CKR_OK);
+
+    let mut data_len: CK_ULONG = 0;
+    let ret = fn_verify_recover(
+        session,
+        signature.as_mut_ptr(),
+        sig_len,
+        std::ptr::null_mut(),
+        &mut data_len,
+    );
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(data_len, 256 - 11);
+
+    /* a short buffer returns the actual length of the data */
+    let mut recovered = vec![0u8; 4];
+    data_len = 4;
+    let ret = fn_verify_recover(
+        session,
+        signature.as_mut_ptr(),
+        sig_len,
+        recovered.as_mut_ptr(),
+        &mut data_len,
+    );
+    assert_eq!(ret, CKR_BUFFER_TOO_SMALL);
+    assert_eq!(data_len, data.len() as CK_ULONG);
+
+    recovered.resize(data_len as usize, 0);
+    let ret = fn_verify_recover(
+        session,
+        signature.as_mut_ptr(),
+        sig_len,
+        recovered.as_mut_ptr(),
+        &mut data_len,
+    );
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(data, recovered.as_slice());
+
+    /* a corrupted signature can not be recovered */
+    signature[10] ^= 0x01;
+    let ret = fn_verify_recover_init(session, &mut mechanism, pubkey);
+    assert_eq!(ret, CKR_OK);
+    recovered.resize(256, 0);
+    data_len = 256;
+    let ret = fn_verify_recover(
+        session,
+        signature.as_mut_ptr(),
+        sig_len,
+        recovered.as_mut_ptr(),
+        &mut data_len,
+    );
+    assert_eq!(ret, CKR_SIGNATURE_INVALID);
+
+    #[cfg(not(feature = "fips"))]
+    {
+        /* Raw RSA Sign Recover */
+        mechanism.mechanism = CKM_RSA_X_509;
+
+        let ret = fn_sign_recover_init(session, &mut mechanism, prikey);
+        assert_eq!(ret, CKR_OK);
+        let mut signature = vec![0u8; 256];
+        let mut sig_len: CK_ULONG = 256;
+        let ret = fn_sign_recover(
+            session,
+            data.as_ptr() as *mut u8,
+            data.len() as CK_ULONG,
+            signature.as_mut_ptr(),
+            &mut sig_len,
+        );
+        assert_eq!(ret, CKR_OK);
+        assert_eq!(sig_len, 256);
+
+        let ret = sig_verify(session, pubkey, data, &signature, &mechanism);
+        assert_eq!(ret, CKR_OK);
+
+        /* raw recovery returns the full modulus sized integer */
+        let ret = fn_verify_recover_init(session, &mut mechanism, pubkey);
+        assert_eq!(ret, CKR_OK);
+        let mut recovered = vec![0u8; 256];
+        let mut data_len: CK_ULONG = 256;
+        let ret = fn_verify_recover(
+            session,
+            signature.as_mut_ptr(),
+            sig_len,
+            recovered.as_mut_ptr(),
+            &mut data_len,
+        );
+        assert_eq!(ret, CKR_OK);
+        assert_eq!(data_len, 256);
+        let padlen = 256 - data.len();
+        assert!(recovered[..padlen].iter().all(|b| *b == 0));
+        assert_eq!(data, &recovered[padlen..]);
+
+        /* Raw RSA Encryption */
+        let enc = ret_or_panic!(encrypt(session, pubkey, data, &mechanism));
+        assert_eq!(enc.len(), 256);
+        let dec = ret_or_panic!(decrypt(session, prikey, &enc, &mechanism));
+        assert_eq!(dec, recovered);
+    }
+
+    testtokn.finalize();
+}
@@ -121,3 +121,114 @@ fn test_session_objects() {
 
     testtokn.finalize();
 }
+
+#[cfg(all(feature = "aes", feature = "hash"))]
+#[test]
+#[parallel]
+fn test_session_cancel() {
+    let mut testtokn = TestToken::initialized("test_session_cancel.sql", None);
+    let session = testtokn.get_session(true);
+
+    /* login */
+    testtokn.login();
+
+    let aes_key = ret_or_panic!(generate_key(
+        session,
+        CKM_AES_KEY_GEN,
+        std::ptr::null_mut(),
+        0,
+        &[(CKA_VALUE_LEN, 16)],
+        &[],
+        &[(CKA_TOKEN, false), (CKA_ENCRYPT, true), (CKA_DECRYPT, true)],
+    ));
+
+    /* nothing active is not an error */
+    let ret = fn_session_cancel(session, CKF_ENCRYPT | CKF_FIND_OBJECTS);
+    assert_eq!(ret, CKR_OK);
+
+    /* search */
+    let template = make_attr_template(&[(CKA_CLASS, CKO_SECRET_KEY)], &[], &[]);
+    let ret = fn_find_objects_init(session, template.as_ptr() as *mut _, 1);
+    assert_eq!(ret, CKR_OK);
+    let ret = fn_session_cancel(session, CKF_ENCRYPT | CKF_SIGN);
+    assert_eq!(ret, CKR_OK);
+    let mut handle = CK_INVALID_HANDLE;
+    let mut count: CK_ULONG = 0;
+    let ret = fn_find_objects(session, &mut handle, 1, &mut count);
+    assert_eq!(ret, CKR_OK);
+    let ret = fn_session_cancel(session, CKF_FIND_OBJECTS);
+    assert_eq!(ret, CKR_OK);
+    let ret = fn_find_objects(session, &mut handle, 1, &mut count);
+    assert_eq!(ret, CKR_OPERATION_NOT_INITIALIZED);
+
+    /* digest and encryption active at the same time */
+    let data = b"Some data to be encrypted and digested";
+    let param = CK_AES_CTR_PARAMS {
+        ulCounterBits: 128,
+        cb: [0u8; 16],
+    };
+    let mut enc_mech: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_AES_CTR,
+        pParameter: void_ptr!(&param),
+        ulParameterLen: sizeof!(CK_AES_CTR_PARAMS),
+    };
+    let mut dgst_mech: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_SHA256,
+        pParameter: std::ptr::null_mut(),
+        ulParameterLen: 0,
+    };
+    let ret = fn_digest_init(session, &mut dgst_mech);
+    assert_eq!(ret, CKR_OK);
+    let ret = fn_encrypt_init(session, &mut enc_mech, aes_key);
+    assert_eq!(ret, CKR_OK);
+    let mut out = vec![0u8; data.len()];
+    let mut out_len: CK_ULONG = out.len() as CK_ULONG;
+    let ret = fn_digest_encrypt_update(
+        session,
+        data.as_ptr() as *mut u8,
+        data.len() as CK_ULONG,
+        out.as_mut_ptr(),
+        &mut out_len,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    /* only the digest goes away */
+    let ret = fn_session_cancel(session, CKF_DIGEST);
+    assert_eq!(ret, CKR_OK);
+    let ret = fn_digest_update(
+        session,
+        data.as_ptr() as *mut u8,
+        data.len() as CK_ULONG,
+    );
+    assert_eq!(ret, CKR_OPERATION_NOT_INITIALIZED);
+    out_len = out.len() as CK_ULONG;
+    let ret = fn_encrypt_update(
+        session,
+        data.as_ptr() as *mut u8,
+        data.len() as CK_ULONG,
+        out.as_mut_ptr(),
+        &mut out_len,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let ret = fn_session_cancel(session, CKF_ENCRYPT | CKF_DECRYPT);
+    assert_eq!(ret, CKR_OK);
+    out_len = out.len() as CK_ULONG;
+    let ret = fn_encrypt_update(
+        session,
+        data.as_ptr() as *mut u8,
+        data.len() as CK_ULONG,
+        out.as_mut_ptr(),
+        &mut out_len,
+    );
+    assert_eq!(ret, CKR_OPERATION_NOT_INITIALIZED);
+
+    /* the session and its objects are still usable */
+    let enc = ret_or_panic!(encrypt(session, aes_key, data, &enc_mech));
+    assert_eq!(enc.len(), data.len());
+
+    let ret = fn_cancel_function(session);
+    assert_eq!(ret, CKR_FUNCTION_NOT_PARALLEL);
+
+    testtokn.finalize();
+}
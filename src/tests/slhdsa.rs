@@ -0,0 +1,338 @@
+// Copyright 2024 Simo Sorce
+// See LICENSE.txt file for terms
+
+use std::io;
+use std::io::BufRead;
+
+use crate::tests::*;
+
+use serial_test::parallel;
+
+#[test]
+#[parallel]
+fn test_slhdsa_operations() {
+    let mut testtokn =
+        TestToken::initialized("test_slhdsa_operations.sql", None);
+    let session = testtokn.get_session(true);
+
+    /* login */
+    testtokn.login();
+
+    let data = b"firmware image";
+    let mechanism = CK_MECHANISM {
+        mechanism: CKM_SLH_DSA,
+        pParameter: std::ptr::null_mut(),
+        ulParameterLen: 0,
+    };
+
+    for (param_set, siglen) in [
+        (CKP_SLH_DSA_SHA2_128S, 7856),
+        (CKP_SLH_DSA_SHAKE_128S, 7856),
+        (CKP_SLH_DSA_SHA2_128F, 17088),
+        (CKP_SLH_DSA_SHAKE_128F, 17088),
+        (CKP_SLH_DSA_SHA2_192S, 16224),
+        (CKP_SLH_DSA_SHAKE_192S, 16224),
+        (CKP_SLH_DSA_SHA2_192F, 35664),
+        (CKP_SLH_DSA_SHAKE_192F, 35664),
+        (CKP_SLH_DSA_SHA2_256S, 29792),
+        (CKP_SLH_DSA_SHAKE_256S, 29792),
+        (CKP_SLH_DSA_SHA2_256F, 49856),
+        (CKP_SLH_DSA_SHAKE_256F, 49856),
+    ] {
+        let (pubkey, privkey) = ret_or_panic!(generate_key_pair(
+            session,
+            CKM_SLH_DSA_KEY_PAIR_GEN,
+            &[(CKA_PARAMETER_SET, param_set)],
+            &[],
+            &[(CKA_VERIFY, true)],
+            &[],
+            &[],
+            &[(CKA_PRIVATE, true), (CKA_SENSITIVE, true), (CKA_SIGN, true)],
+        ));
+        if let Some(err) = check_attributes(
+            session,
+            privkey,
+            &[(CKA_PARAMETER_SET, param_set)],
+            &[],
+            &[(CKA_LOCAL, true)],
+        ) {
+            panic!("{}", err);
+        }
+
+        let sig = ret_or_panic!(sig_gen(session, privkey, data, &mechanism));
+        assert_eq!(sig.len(), siglen);
+        assert_eq!(CKR_OK, sig_verify(session, pubkey, data, &sig, &mechanism));
+        assert_eq!(
+            CKR_SIGNATURE_INVALID,
+            sig_verify(session, pubkey, b"tampered image", &sig, &mechanism)
+        );
+    }
+
+    /* keys must allow the operation */
+    let (pubkey, privkey) = ret_or_panic!(generate_key_pair(
+        session,
+        CKM_SLH_DSA_KEY_PAIR_GEN,
+        &[(CKA_PARAMETER_SET, CKP_SLH_DSA_SHAKE_128F)],
+        &[],
+        &[],
+        &[],
+        &[],
+        &[],
+    ));
+    let ret = fn_sign_init(
+        session,
+        &mechanism as *const _ as CK_MECHANISM_PTR,
+        privkey,
+    );
+    assert_eq!(ret, CKR_KEY_FUNCTION_NOT_PERMITTED);
+    let ret = fn_verify_init(
+        session,
+        &mechanism as *const _ as CK_MECHANISM_PTR,
+        pubkey,
+    );
+    assert_eq!(ret, CKR_KEY_FUNCTION_NOT_PERMITTED);
+
+    testtokn.finalize();
+}
+
+#[derive(Debug)]
+struct SlhDsaTestUnit {
+    line: usize,
+    label: String,
+    algo: String,
+    secret: Vec<u8>,
+    public: Vec<u8>,
+    message: Vec<u8>,
+    context: Vec<u8>,
+    signature: Vec<u8>,
+}
+
+enum SlhDsaParserState {
+    None,
+    Algorithm,
+    Secret,
+    Public,
+    Message,
+    Context,
+    Signature,
+}
+
+fn parse_slhdsa_vector(filename: &str) -> Vec<SlhDsaTestUnit> {
+    let file = ret_or_panic!(std::fs::File::open(filename));
+
+    let mut data = Vec::<SlhDsaTestUnit>::new();
+    let mut label = None;
+
+    let mut state = SlhDsaParserState::None;
+    for (l, line) in io::BufReader::new(file)
+        .lines()
+        .map_while(std::result::Result::ok)
+        .enumerate()
+    {
+        let ln = l + 1;
+
+        if line.is_empty() {
+            continue;
+        }
+        /* skip these comment lines */
+        if line.starts_with("-----") {
+            label = Some(line.clone());
+            state = SlhDsaParserState::None;
+            continue;
+        }
+
+        match state {
+            SlhDsaParserState::None if line.starts_with("ALGORITHM:") => {
+                state = SlhDsaParserState::Algorithm;
+            }
+            SlhDsaParserState::Algorithm => {
+                if line.starts_with("SECRET KEY:") {
+                    state = SlhDsaParserState::Secret;
+                    continue;
+                }
+
+                let unit = SlhDsaTestUnit {
+                    line: ln - 1,
+                    label: match label {
+                        Some(ref v) => v.clone(),
+                        _ => panic!("Missing label on line {})", ln),
+                    },
+                    algo: line.clone(),
+                    secret: Vec::new(),
+                    public: Vec::new(),
+                    message: Vec::new(),
+                    context: Vec::new(),
+                    signature: Vec::new(),
+                };
+                println!("  : Testcase: {:?}", label);
+                data.push(unit);
+            }
+            _ => (),
+        }
+        let unit = match data.last_mut() {
+            Some(u) => u,
+            None => continue,
+        };
+        match state {
+            SlhDsaParserState::Secret => {
+                if line.starts_with("PUBLIC KEY:") {
+                    state = SlhDsaParserState::Public;
+                    continue;
+                }
+
+                let sec = parse_or_panic!(hex::decode(&line); line; ln);
+                unit.secret.extend(sec);
+            }
+            SlhDsaParserState::Public => {
+                if line.starts_with("MESSAGE ") {
+                    state = SlhDsaParserState::Message;
+                    continue;
+                }
+
+                let public = parse_or_panic!(hex::decode(&line); line; ln);
+                unit.public.extend(public);
+            }
+            SlhDsaParserState::Message => {
+                if line.starts_with("CONTEXT:") {
+                    state = SlhDsaParserState::Context;
+                    continue;
+                } else if line.starts_with("SIGNATURE:") {
+                    state = SlhDsaParserState::Signature;
+                    continue;
+                }
+
+                let msg = parse_or_panic!(hex::decode(&line); line; ln);
+                unit.message.extend(msg);
+            }
+            SlhDsaParserState::Context => {
+                if line.starts_with("SIGNATURE:") {
+                    state = SlhDsaParserState::Signature;
+                    continue;
+                }
+
+                let context = parse_or_panic!(hex::decode(&line); line; ln);
+                unit.context.extend(context);
+            }
+            SlhDsaParserState::Signature => {
+                let sig = parse_or_panic!(hex::decode(&line); line; ln);
+                unit.signature.extend(sig);
+            }
+            _ => (),
+        }
+    }
+    data
+}
+
+fn algo_to_param_set(algo: &String) -> CK_SLH_DSA_PARAMETER_SET_TYPE {
+    match algo.as_str() {
+        "SLH-DSA-SHA2-128s" => CKP_SLH_DSA_SHA2_128S,
+        "SLH-DSA-SHAKE-128s" => CKP_SLH_DSA_SHAKE_128S,
+        "SLH-DSA-SHA2-128f" => CKP_SLH_DSA_SHA2_128F,
+        "SLH-DSA-SHAKE-128f" => CKP_SLH_DSA_SHAKE_128F,
+        "SLH-DSA-SHA2-192s" => CKP_SLH_DSA_SHA2_192S,
+        "SLH-DSA-SHAKE-192s" => CKP_SLH_DSA_SHAKE_192S,
+        "SLH-DSA-SHA2-192f" => CKP_SLH_DSA_SHA2_192F,
+        "SLH-DSA-SHAKE-192f" => CKP_SLH_DSA_SHAKE_192F,
+        "SLH-DSA-SHA2-256s" => CKP_SLH_DSA_SHA2_256S,
+        "SLH-DSA-SHAKE-256s" => CKP_SLH_DSA_SHAKE_256S,
+        "SLH-DSA-SHA2-256f" => CKP_SLH_DSA_SHA2_256F,
+        "SLH-DSA-SHAKE-256f" => CKP_SLH_DSA_SHAKE_256F,
+        _ => panic!("Unknown algorithm {}", algo),
+    }
+}
+
+fn test_slhdsa_units(
+    session: CK_SESSION_HANDLE,
+    test_data: Vec<SlhDsaTestUnit>,
+) {
+    for unit in test_data {
+        println!("Executing test at line {}", unit.line);
+
+        let param_set = algo_to_param_set(&unit.algo);
+        let priv_handle = ret_or_panic!(import_object(
+            session,
+            CKO_PRIVATE_KEY,
+            &[(CKA_KEY_TYPE, CKK_SLH_DSA), (CKA_PARAMETER_SET, param_set)],
+            &[
+                (CKA_VALUE, &unit.secret),
+                (
+                    CKA_LABEL,
+                    format!(
+                        "{} private key, label={}, line {}",
+                        unit.algo, unit.label, unit.line
+                    )
+                    .as_bytes()
+                )
+            ],
+            &[(CKA_SIGN, true)],
+        ));
+
+        let pub_handle = ret_or_panic!(import_object(
+            session,
+            CKO_PUBLIC_KEY,
+            &[(CKA_KEY_TYPE, CKK_SLH_DSA), (CKA_PARAMETER_SET, param_set)],
+            &[
+                (CKA_VALUE, &unit.public),
+                (
+                    CKA_LABEL,
+                    format!(
+                        "{} public key, label={}, line {}",
+                        unit.algo, unit.label, unit.line
+                    )
+                    .as_bytes()
+                )
+            ],
+            &[(CKA_VERIFY, true)],
+        ));
+
+        /* the vectors use the deterministic variant, so the exact
+         * signature can be compared */
+        let params = CK_SIGN_ADDITIONAL_CONTEXT {
+            hedgeVariant: CKH_DETERMINISTIC_REQUIRED,
+            pContext: unit.context.as_ptr() as *mut CK_BYTE,
+            ulContextLen: unit.context.len() as CK_ULONG,
+        };
+        let mechanism: CK_MECHANISM = CK_MECHANISM {
+            mechanism: CKM_SLH_DSA,
+            pParameter: void_ptr!(&params),
+            ulParameterLen: sizeof!(CK_SIGN_ADDITIONAL_CONTEXT),
+        };
+
+        let sig = ret_or_panic!(sig_gen(
+            session,
+            priv_handle,
+            &unit.message,
+            &mechanism
+        ));
+        assert_eq!(sig, unit.signature);
+
+        assert_eq!(
+            CKR_OK,
+            sig_verify(
+                session,
+                pub_handle,
+                &unit.message,
+                &unit.signature,
+                &mechanism
+            )
+        );
+    }
+}
+
+#[test]
+#[parallel]
+fn test_slhdsa_vector() {
+    /* Deterministic signatures with a context string for every parameter
+     * set, see the file header for their origin */
+    let test_data = parse_slhdsa_vector("testdata/slh_dsa_kat.txt");
+
+    let mut testtokn = TestToken::initialized("test_slhdsa_vector.sql", None);
+    let session = testtokn.get_session(false);
+
+    /* login */
+    testtokn.login();
+
+    test_slhdsa_units(session, test_data);
+
+    testtokn.finalize();
+}
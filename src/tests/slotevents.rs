@@ -0,0 +1,135 @@
+// Copyright 2024 Simo Sorce
+// See LICENSE.txt file for terms
+
+use std::fs::remove_file;
+
+use crate::tests::*;
+
+use serial_test::parallel;
+
+fn slot_in_list(slot: CK_SLOT_ID, token_present: CK_BBOOL) -> bool {
+    let mut count: CK_ULONG = 0;
+    let ret = fn_get_slot_list(token_present, std::ptr::null_mut(), &mut count);
+    assert_eq!(ret, CKR_OK);
+    let mut list = vec![0 as CK_SLOT_ID; count as usize];
+    let ret = fn_get_slot_list(token_present, list.as_mut_ptr(), &mut count);
+    assert_eq!(ret, CKR_OK);
+    list.truncate(count as usize);
+    list.contains(&slot)
+}
+
+fn slot_flags(slot: CK_SLOT_ID) -> CK_FLAGS {
+    let mut info = CK_SLOT_INFO {
+        slotDescription: [0; 64],
+        manufacturerID: [0; 32],
+        flags: 0,
+        hardwareVersion: CK_VERSION { major: 0, minor: 0 },
+        firmwareVersion: CK_VERSION { major: 0, minor: 0 },
+    };
+    let ret = fn_get_slot_info(slot, &mut info);
+    assert_eq!(ret, CKR_OK);
+    info.flags
+}
+
+#[test]
+#[parallel]
+fn test_slot_events() {
+    let dbpath = format!("{}/{}", TESTDIR, "slot_events.sql");
+    let confname = format!("{}.conf", dbpath);
+    let _ = remove_file(&confname);
+
+    let mut testtokn = TestToken::new(dbpath);
+    testtokn.setup_db(None);
+    let slot = testtokn.get_slot();
+
+    let ret = fn_initialize(std::ptr::null_mut());
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(set_config_watch(&confname), CKR_OK);
+
+    let mut event: CK_SLOT_ID = CK_UNAVAILABLE_INFORMATION;
+    let ret = fn_wait_for_slot_event(
+        CKF_DONT_BLOCK,
+        &mut event,
+        std::ptr::null_mut(),
+    );
+    assert_eq!(ret, CKR_NO_EVENT);
+    assert!(!slot_in_list(slot, CK_FALSE));
+
+    /* token appears */
+    testtokn.make_config_file(&confname);
+    let ret = fn_wait_for_slot_event(
+        CKF_DONT_BLOCK,
+        &mut event,
+        std::ptr::null_mut(),
+    );
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(event, slot);
+    assert_eq!(slot_flags(slot) & CKF_TOKEN_PRESENT, CKF_TOKEN_PRESENT);
+    assert!(slot_in_list(slot, CK_TRUE));
+
+    let mut session = CK_INVALID_HANDLE;
+    let ret = fn_open_session(
+        slot,
+        CKF_SERIAL_SESSION,
+        std::ptr::null_mut(),
+        None,
+        &mut session,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let ret = fn_wait_for_slot_event(
+        CKF_DONT_BLOCK,
+        &mut event,
+        std::ptr::null_mut(),
+    );
+    assert_eq!(ret, CKR_NO_EVENT);
+
+    /* token disappears, blocking mode */
+    remove_file(&confname).unwrap();
+    event = CK_UNAVAILABLE_INFORMATION;
+    let ret = fn_wait_for_slot_event(0, &mut event, std::ptr::null_mut());
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(event, slot);
+    assert_eq!(slot_flags(slot) & CKF_TOKEN_PRESENT, 0);
+    assert!(slot_in_list(slot, CK_FALSE));
+    assert!(!slot_in_list(slot, CK_TRUE));
+
+    let mut info = CK_TOKEN_INFO::default();
+    let ret = fn_get_token_info(slot, &mut info);
+    assert_eq!(ret, CKR_TOKEN_NOT_PRESENT);
+
+    /* sessions went away with the token */
+    let ret = fn_close_session(session);
+    assert_eq!(ret, CKR_SESSION_HANDLE_INVALID);
+    let mut handle = CK_INVALID_HANDLE;
+    let ret = fn_open_session(
+        slot,
+        CKF_SERIAL_SESSION,
+        std::ptr::null_mut(),
+        None,
+        &mut handle,
+    );
+    assert_eq!(ret, CKR_TOKEN_NOT_PRESENT);
+
+    /* token is inserted again */
+    testtokn.make_config_file(&confname);
+    let ret = fn_wait_for_slot_event(0, &mut event, std::ptr::null_mut());
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(event, slot);
+    assert_eq!(slot_flags(slot) & CKF_TOKEN_PRESENT, CKF_TOKEN_PRESENT);
+
+    let ret = fn_get_token_info(slot, &mut info);
+    assert_eq!(ret, CKR_OK);
+    let ret = fn_open_session(
+        slot,
+        CKF_SERIAL_SESSION,
+        std::ptr::null_mut(),
+        None,
+        &mut handle,
+    );
+    assert_eq!(ret, CKR_OK);
+    let ret = fn_close_session(handle);
+    assert_eq!(ret, CKR_OK);
+
+    testtokn.finalize();
+}
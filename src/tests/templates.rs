@@ -0,0 +1,277 @@
+// Copyright 2024 Simo Sorce
+// See LICENSE.txt file for terms
+
+use crate::attribute::{AttrType, Attribute};
+use crate::tests::*;
+
+use serial_test::parallel;
+
+#[test]
+#[parallel]
+fn test_key_templates() {
+    let mut testtokn = TestToken::initialized("test_key_templates.sql", None);
+    let session = testtokn.get_session(true);
+
+    /* login */
+    testtokn.login();
+
+    let wrap_template = make_attr_template(
+        &[(CKA_KEY_TYPE, CKK_AES)],
+        &[],
+        &[(CKA_ENCRYPT, true)],
+    );
+    let unwrap_template = make_attr_template(&[], &[], &[(CKA_DECRYPT, false)]);
+    let derive_template = make_attr_template(&[], &[], &[(CKA_ENCRYPT, false)]);
+
+    /* token key, so that the templates go through the storage */
+    let mut template = make_attr_template(
+        &[
+            (CKA_CLASS, CKO_SECRET_KEY),
+            (CKA_KEY_TYPE, CKK_AES),
+            (CKA_VALUE_LEN, 16),
+        ],
+        &[],
+        &[
+            (CKA_TOKEN, true),
+            (CKA_WRAP, true),
+            (CKA_UNWRAP, true),
+            (CKA_DERIVE, true),
+            (CKA_ENCRYPT, true),
+            (CKA_DECRYPT, true),
+        ],
+    );
+    template.push(make_attribute!(
+        CKA_WRAP_TEMPLATE,
+        wrap_template.as_ptr(),
+        wrap_template.len() * std::mem::size_of::<CK_ATTRIBUTE>()
+    ));
+    template.push(make_attribute!(
+        CKA_UNWRAP_TEMPLATE,
+        unwrap_template.as_ptr(),
+        unwrap_template.len() * std::mem::size_of::<CK_ATTRIBUTE>()
+    ));
+    template.push(make_attribute!(
+        CKA_DERIVE_TEMPLATE,
+        derive_template.as_ptr(),
+        derive_template.len() * std::mem::size_of::<CK_ATTRIBUTE>()
+    ));
+    let mut mechanism = CK_MECHANISM {
+        mechanism: CKM_AES_KEY_GEN,
+        pParameter: std::ptr::null_mut(),
+        ulParameterLen: 0,
+    };
+    let mut handle = CK_INVALID_HANDLE;
+    let ret = fn_generate_key(
+        session,
+        &mut mechanism,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut handle,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    /* read back the wrap template */
+    let mut attr = make_attribute!(CKA_WRAP_TEMPLATE, std::ptr::null_mut(), 0);
+    let ret = fn_get_attribute_value(session, handle, &mut attr, 1);
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(
+        attr.ulValueLen as usize,
+        2 * std::mem::size_of::<CK_ATTRIBUTE>()
+    );
+    let mut key_type: CK_ULONG = 0;
+    let mut encrypt: CK_BBOOL = CK_FALSE;
+    let mut readback = make_ptrs_template(&[
+        (0, void_ptr!(&mut key_type), CK_ULONG_SIZE),
+        (0, void_ptr!(&mut encrypt), CK_BBOOL_SIZE),
+    ]);
+    attr.pValue = void_ptr!(readback.as_mut_ptr());
+    let ret = fn_get_attribute_value(session, handle, &mut attr, 1);
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(readback[0].type_, CKA_KEY_TYPE);
+    assert_eq!(key_type, CKK_AES);
+    assert_eq!(readback[1].type_, CKA_ENCRYPT);
+    assert_eq!(encrypt, CK_TRUE);
+
+    /* wrapping is allowed only for keys matching the wrap template */
+    let data = [0x55u8; 16];
+    let mut wrapped = [0u8; 32];
+    let mut wraplen = wrapped.len() as CK_ULONG;
+    let mut mechanism = CK_MECHANISM {
+        mechanism: CKM_AES_KEY_WRAP,
+        pParameter: std::ptr::null_mut(),
+        ulParameterLen: 0,
+    };
+    let no_match = ret_or_panic!(import_object(
+        session,
+        CKO_SECRET_KEY,
+        &[(CKA_KEY_TYPE, CKK_AES)],
+        &[(CKA_VALUE, &data)],
+        &[(CKA_EXTRACTABLE, true), (CKA_ENCRYPT, false)],
+    ));
+    let ret = fn_wrap_key(
+        session,
+        &mut mechanism,
+        handle,
+        no_match,
+        wrapped.as_mut_ptr(),
+        &mut wraplen,
+    );
+    assert_eq!(ret, CKR_KEY_NOT_WRAPPABLE);
+
+    let to_wrap = ret_or_panic!(import_object(
+        session,
+        CKO_SECRET_KEY,
+        &[(CKA_KEY_TYPE, CKK_AES)],
+        &[(CKA_VALUE, &data)],
+        &[(CKA_EXTRACTABLE, true), (CKA_ENCRYPT, true)],
+    ));
+    let ret = fn_wrap_key(
+        session,
+        &mut mechanism,
+        handle,
+        to_wrap,
+        wrapped.as_mut_ptr(),
+        &mut wraplen,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    /* the unwrap template is merged in the unwrapped key */
+    let mut template = make_attr_template(
+        &[(CKA_CLASS, CKO_SECRET_KEY), (CKA_KEY_TYPE, CKK_AES)],
+        &[],
+        &[(CKA_EXTRACTABLE, true)],
+    );
+    let mut unwrapped = CK_INVALID_HANDLE;
+    let ret = fn_unwrap_key(
+        session,
+        &mut mechanism,
+        handle,
+        wrapped.as_mut_ptr(),
+        wraplen,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut unwrapped,
+    );
+    assert_eq!(ret, CKR_OK);
+    if let Some(err) =
+        check_attributes(session, unwrapped, &[], &[], &[(CKA_DECRYPT, false)])
+    {
+        panic!("{}", err);
+    }
+
+    /* conflicting attributes are rejected */
+    let mut template = make_attr_template(
+        &[(CKA_CLASS, CKO_SECRET_KEY), (CKA_KEY_TYPE, CKK_AES)],
+        &[],
+        &[(CKA_EXTRACTABLE, true), (CKA_DECRYPT, true)],
+    );
+    let ret = fn_unwrap_key(
+        session,
+        &mut mechanism,
+        handle,
+        wrapped.as_mut_ptr(),
+        wraplen,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut unwrapped,
+    );
+    assert_eq!(ret, CKR_TEMPLATE_INCONSISTENT);
+
+    /* the derive template is merged in derived keys */
+    let derive_data = "derive keys data";
+    let mut derive_params = CK_KEY_DERIVATION_STRING_DATA {
+        pData: derive_data.as_ptr() as CK_BYTE_PTR,
+        ulLen: derive_data.len() as CK_ULONG,
+    };
+    let mut derive_mech = CK_MECHANISM {
+        mechanism: CKM_AES_ECB_ENCRYPT_DATA,
+        pParameter: &mut derive_params as *mut _ as CK_VOID_PTR,
+        ulParameterLen: sizeof!(CK_KEY_DERIVATION_STRING_DATA),
+    };
+    let template = make_attr_template(
+        &[
+            (CKA_CLASS, CKO_SECRET_KEY),
+            (CKA_KEY_TYPE, CKK_AES),
+            (CKA_VALUE_LEN, 16),
+        ],
+        &[],
+        &[],
+    );
+    let mut derived = CK_INVALID_HANDLE;
+    let ret = fn_derive_key(
+        session,
+        &mut derive_mech,
+        handle,
+        template.as_ptr() as *mut _,
+        template.len() as CK_ULONG,
+        &mut derived,
+    );
+    assert_eq!(ret, CKR_OK);
+    if let Some(err) =
+        check_attributes(session, derived, &[], &[], &[(CKA_ENCRYPT, false)])
+    {
+        panic!("{}", err);
+    }
+
+    let template = make_attr_template(
+        &[
+            (CKA_CLASS, CKO_SECRET_KEY),
+            (CKA_KEY_TYPE, CKK_AES),
+            (CKA_VALUE_LEN, 16),
+        ],
+        &[],
+        &[(CKA_ENCRYPT, true)],
+    );
+    let ret = fn_derive_key(
+        session,
+        &mut derive_mech,
+        handle,
+        template.as_ptr() as *mut _,
+        template.len() as CK_ULONG,
+        &mut derived,
+    );
+    assert_eq!(ret, CKR_TEMPLATE_INCONSISTENT);
+
+    testtokn.finalize();
+}
+
+#[test]
+#[parallel]
+fn test_template_encoding() {
+    /* stored templates do not depend on the platform CK_ULONG */
+    let attr = Attribute::from_attr_array(
+        CKA_WRAP_TEMPLATE,
+        vec![
+            Attribute::from_ulong(CKA_KEY_TYPE, CKK_AES),
+            Attribute::from_bool(CKA_ENCRYPT, true),
+        ],
+    );
+    let expected = hex::decode(concat!(
+        "0000000000000100", /* CKA_KEY_TYPE */
+        "0000000000000008",
+        "000000000000001f", /* CKK_AES */
+        "0000000000000104", /* CKA_ENCRYPT */
+        "0000000000000001",
+        "01",
+    ))
+    .unwrap();
+    assert_eq!(attr.get_value(), &expected);
+
+    let attrs = attr.to_attr_array().unwrap();
+    assert_eq!(attrs.len(), 2);
+    assert_eq!(attrs[0].get_type(), CKA_KEY_TYPE);
+    assert_eq!(attrs[0].to_ulong().unwrap(), CKK_AES);
+    assert_eq!(attrs[1].get_type(), CKA_ENCRYPT);
+    assert!(attrs[1].to_bool().unwrap());
+
+    /* numeric values must be u64s */
+    let attr = Attribute::from_attr_slice(
+        CKA_WRAP_TEMPLATE,
+        AttrType::ArrayType,
+        &hex::decode("0000000000000100000000000000000400000001").unwrap(),
+    );
+    assert_eq!(
+        attr.to_attr_array().unwrap_err().rv(),
+        CKR_ATTRIBUTE_VALUE_INVALID
+    );
+}
@@ -240,6 +240,49 @@ fn test_interface_pkcs11_version3() {
     testtokn.finalize();
 }
 
+#[test]
+#[parallel]
+fn test_interface_pkcs11_version32() {
+    let dbpath =
+        format!("{}/{}", TESTDIR, "test_interface_pkcs11_version32.sql");
+    let mut testtokn = TestToken::new(dbpath);
+    testtokn.setup_db(None);
+
+    /* Get the specific version 3.2 */
+    let mut piface: *mut CK_INTERFACE = std::ptr::null_mut();
+    let ppiface = &mut piface;
+    let mut version = { CK_VERSION { major: 3, minor: 2 } };
+    let result = C_GetInterface(
+        c"PKCS 11".as_ptr() as CK_UTF8CHAR_PTR,
+        &mut version,
+        &mut *ppiface,
+        0,
+    );
+    assert_eq!(result, CKR_OK);
+    unsafe {
+        let iface: CK_INTERFACE = *piface;
+        let list: CK_FUNCTION_LIST_3_2 =
+            *(iface.pFunctionList as CK_FUNCTION_LIST_3_2_PTR);
+        assert_eq!(list.version.major, 3);
+        assert_eq!(list.version.minor, 2);
+        assert!(list.C_EncapsulateKey.is_some());
+        assert!(list.C_DecapsulateKey.is_some());
+        match list.C_Initialize {
+            Some(value) => {
+                let mut args = TestToken::make_init_args(Some(
+                    testtokn.make_init_string(),
+                ));
+                let args_ptr = &mut args as *mut CK_C_INITIALIZE_ARGS;
+                let ret = value(args_ptr as *mut std::ffi::c_void);
+                assert_eq!(ret, CKR_OK)
+            }
+            None => todo!(),
+        }
+    }
+
+    testtokn.finalize();
+}
+
 #[test]
 #[parallel]
 fn test_interface_pkcs11_version240() {
@@ -640,6 +640,31 @@ pub fn extract_key_value(
     Ok(value)
 }
 
+pub fn get_attr_bytes(
+    session: CK_SESSION_HANDLE,
+    handle: CK_OBJECT_HANDLE,
+    attr: CK_ATTRIBUTE_TYPE,
+) -> Vec<u8> {
+    let mut template = make_ptrs_template(&[(attr, std::ptr::null_mut(), 0)]);
+    let ret = fn_get_attribute_value(
+        session,
+        handle,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+    );
+    assert_eq!(ret, CKR_OK);
+    let mut value = vec![0u8; template[0].ulValueLen as usize];
+    template[0].pValue = void_ptr!(value.as_mut_ptr());
+    let ret = fn_get_attribute_value(
+        session,
+        handle,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+    );
+    assert_eq!(ret, CKR_OK);
+    value
+}
+
 #[cfg(feature = "fips")]
 pub fn check_validation(session: CK_SESSION_HANDLE, expect: CK_FLAGS) -> bool {
     let mut flags: CK_FLAGS = 0;
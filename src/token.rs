@@ -10,7 +10,7 @@ use crate::error::Result;
 #[cfg(feature = "fips")]
 use crate::fips;
 use crate::interface::*;
-use crate::mechanism::Mechanisms;
+use crate::mechanism::{Mechanisms, Sign};
 use crate::misc::copy_sized_string;
 use crate::object::{Object, ObjectFactories};
 use crate::register_all;
@@ -76,6 +76,7 @@ pub struct Token {
     storage: Box<dyn Storage>,
     session_objects: HashMap<CK_OBJECT_HANDLE, Object>,
     logged: CK_USER_TYPE,
+    username: Option<String>,
 }
 
 impl Token {
@@ -109,6 +110,7 @@ impl Token {
             storage: new_storage(dbtype, &dbpath)?,
             session_objects: HashMap::new(),
             logged: KRY_UNSPEC,
+            username: None,
         };
 
         /* register mechanisms and factories */
@@ -151,12 +153,13 @@ impl Token {
 
     pub fn initialize(&mut self, pin: &[u8], label: &[u8]) -> Result<()> {
         if self.is_initialized() {
-            self.auth_user(CKU_SO, pin, true)?;
+            self.auth_user(CKU_SO, None, pin, true)?;
         };
 
         self.facilities.handles = Handles::new();
         self.session_objects.clear();
         self.logged = KRY_UNSPEC;
+        self.username = None;
 
         /* this inits from scratch or deletes and reinits an existing db */
         let mut info = self.storage.reinit(&self.facilities)?;
@@ -186,7 +189,7 @@ impl Token {
          * to properly store data and set PINs).
          * The caller must ensure authentication after a reset
          * to be able to correctly access the database. */
-        self.storage.unauth_user(CKU_SO)?;
+        self.storage.unauth_user(CKU_SO, None)?;
 
         #[cfg(feature = "fips")]
         if fips::token_init(self).is_err() {
@@ -202,25 +205,58 @@ impl Token {
         pin: &[u8],
         old: &[u8],
     ) -> Result<()> {
-        let utype = match user_type {
-            CK_UNAVAILABLE_INFORMATION => self.logged,
-            CKU_USER => CKU_USER,
-            CKU_SO => CKU_SO,
+        /* a named user can only change its own pin */
+        let (utype, username) = match user_type {
+            CK_UNAVAILABLE_INFORMATION => (self.logged, self.username.clone()),
+            CKU_USER => (CKU_USER, None),
+            CKU_SO => (CKU_SO, None),
             _ => return Err(CKR_GENERAL_ERROR)?,
         };
 
         if old.len() != 0 {
-            self.auth_user(utype, old, true)?;
+            self.auth_user(utype, username.as_deref(), old, true)?;
         }
 
-        self.storage.set_user_pin(&self.facilities, utype, pin)?;
+        self.storage.set_user_pin(
+            &self.facilities,
+            utype,
+            username.as_deref(),
+            pin,
+        )?;
 
-        if utype == CKU_USER {
+        if utype == CKU_USER && username.is_none() {
             self.info.flags |= CKF_USER_PIN_INITIALIZED;
         }
         Ok(())
     }
 
+    /// Creates a named user, or resets its pin if it already exists.
+    /// Requires the SO to be logged in.
+    pub fn add_user(&mut self, username: &str, pin: &[u8]) -> Result<()> {
+        if !self.is_logged_in(CKU_SO) {
+            return Err(CKR_USER_NOT_LOGGED_IN)?;
+        }
+        self.storage.set_user_pin(
+            &self.facilities,
+            CKU_USER,
+            Some(username),
+            pin,
+        )
+    }
+
+    /// Removes a named user. Requires the SO to be logged in, and
+    /// refuses to remove the user currently logged in.
+    pub fn remove_user(&mut self, username: &str) -> Result<()> {
+        if self.logged == CKU_USER && self.username.as_deref() == Some(username)
+        {
+            return Err(CKR_USER_ALREADY_LOGGED_IN)?;
+        }
+        if !self.is_logged_in(CKU_SO) {
+            return Err(CKR_USER_NOT_LOGGED_IN)?;
+        }
+        self.storage.remove_user(username)
+    }
+
     pub fn is_logged_in(&self, user_type: CK_USER_TYPE) -> bool {
         match user_type {
             KRY_UNSPEC => self.logged == CKU_SO || self.logged == CKU_USER,
@@ -251,6 +287,7 @@ impl Token {
     fn auth_user(
         &mut self,
         user_type: CK_USER_TYPE,
+        username: Option<&str>,
         pin: &[u8],
         check_only: bool,
     ) -> Result<()> {
@@ -261,32 +298,52 @@ impl Token {
         let ret = self.storage.auth_user(
             &self.facilities,
             user_type,
+            username,
             pin,
             &mut flags,
             check_only,
         );
-        self.update_auth_flags(user_type, flags);
+        /* the token flags only reflect the default users, named users
+         * keep their own counters in their auth objects */
+        if username.is_none() {
+            self.update_auth_flags(user_type, flags);
+        }
         if ret.is_err() {
             return ret;
         }
         if !check_only {
             self.logged = user_type;
+            self.username = username.map(|u| u.to_string());
         }
         Ok(())
     }
 
     pub fn login(&mut self, user_type: CK_USER_TYPE, pin: &[u8]) -> CK_RV {
+        self.login_user(user_type, None, pin)
+    }
+
+    pub fn login_user(
+        &mut self,
+        user_type: CK_USER_TYPE,
+        username: Option<&str>,
+        pin: &[u8],
+    ) -> CK_RV {
         let result = match user_type {
             CKU_SO | CKU_USER => {
-                if user_type == self.logged {
+                if user_type == self.logged
+                    && username == self.username.as_deref()
+                {
                     return CKR_USER_ALREADY_LOGGED_IN;
                 }
                 if self.logged != KRY_UNSPEC {
                     return CKR_USER_ANOTHER_ALREADY_LOGGED_IN;
                 }
-                self.auth_user(user_type, pin, false)
+                self.auth_user(user_type, username, pin, false)
+            }
+            CKU_CONTEXT_SPECIFIC => {
+                let username = self.username.clone();
+                self.auth_user(self.logged, username.as_deref(), pin, true)
             }
-            CKU_CONTEXT_SPECIFIC => self.auth_user(self.logged, pin, true),
             _ => return CKR_USER_TYPE_INVALID,
         };
         match result {
@@ -301,8 +358,13 @@ impl Token {
             CKU_SO | CKU_USER => {
                 self.clear_private_session_objects();
                 let user_type = self.logged;
+                let username = self.username.take();
                 self.logged = KRY_UNSPEC;
-                if self.storage.unauth_user(user_type).is_err() {
+                if self
+                    .storage
+                    .unauth_user(user_type, username.as_deref())
+                    .is_err()
+                {
                     return CKR_GENERAL_ERROR;
                 }
                 CKR_OK
@@ -401,6 +463,15 @@ impl Token {
         mut obj: Object,
     ) -> Result<CK_OBJECT_HANDLE> {
         let handle: CK_OBJECT_HANDLE;
+        if obj.get_attr_as_ulong(CKA_CLASS)? == CKO_SECRET_KEY {
+            /* some key types (eg CKK_HKDF) can be generated but have no
+             * object factory of their own, they get no check value */
+            if let Ok(factory) =
+                self.facilities.factories.get_object_factory(&obj)
+            {
+                factory.as_secret_key_factory()?.set_check_value(&mut obj)?;
+            }
+        }
         if obj.is_token() {
             if !self.is_logged_in(KRY_UNSPEC) {
                 return Err(CKR_USER_NOT_LOGGED_IN)?;
@@ -528,6 +599,35 @@ impl Token {
         }
     }
 
+    /* Stateful signature schemes (HSS) keep the signing state in the
+     * private key object. The next state is reserved and committed to
+     * storage before the signature is computed, so that one time keys
+     * are never reused even if the process dies before the signature
+     * is returned */
+    pub fn reserve_sign_state(&mut self, op: &mut dyn Sign) -> Result<()> {
+        let handle = match op.state_key() {
+            Some(h) => h,
+            None => return Ok(()),
+        };
+        let key = self.get_object_by_handle(handle)?;
+        let mut update = op.reserve_state(&key)?;
+        let ret = match self.session_objects.get_mut(&handle) {
+            Some(obj) => {
+                update.iter().try_for_each(|a| obj.set_attr(a.clone()))
+            }
+            None => {
+                let mut attrs = CkAttrs::with_capacity(update.len());
+                for a in &update {
+                    attrs.add_slice(a.get_type(), a.get_value())?;
+                }
+                self.storage
+                    .update(&self.facilities, handle, attrs.as_slice())
+            }
+        };
+        update.iter_mut().for_each(|a| a.zeroize());
+        ret
+    }
+
     pub fn get_object_size(&self, o_handle: CK_OBJECT_HANDLE) -> Result<usize> {
         let obj = if let Some(o) = self.session_objects.get(&o_handle) {
             Cow::Borrowed(o)